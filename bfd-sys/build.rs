@@ -37,6 +37,8 @@ fn build_binutils(src_dir: &Path, build_dir: &Path) {
     "--build=x86_64-apple-darwin",
     "--host=x86_64-apple-darwin",
     "--target=x86_64-apple-darwin",
+    // ELF objects are read as inputs too (src/elf.rs in the linker).
+    "--enable-targets=x86_64-elf,aarch64-elf",
   ]);
 
   // let tmp_build_dir = TempDir::new("autotools-build").unwrap();
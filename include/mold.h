@@ -0,0 +1,37 @@
+/* The C API to mold, built as libmold.a and libmold.so (or .dylib). */
+
+#ifndef MOLD_H
+#define MOLD_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MOL_SEVERITY_WARNING 0
+#define MOL_SEVERITY_ERROR 1
+#define MOL_SEVERITY_NOTE 2
+
+/* Everything but message may be NULL. The strings are only valid for the
+   duration of the callback. */
+struct MolDiagnostic {
+  int severity;
+  const char *code;
+  const char *message;
+  const char *file;
+  const char *symbol;
+};
+
+typedef void (*mol_diag_callback)(const struct MolDiagnostic *);
+
+/* Link with argv, which is laid out like a main function's: argv[0] is the
+   program name and is ignored. Returns 0 on success and 1 on failure; either
+   way, every warning and error is passed to diag_callback (if it isn't NULL)
+   before this returns. */
+int mol_link(int argc, const char *const *argv,
+             mol_diag_callback diag_callback);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,399 @@
+// Encoding for LC_DYLD_CHAINED_FIXUPS, the replacement for the dyld info
+// rebase/bind opcodes on newer deployment targets.
+//
+// Instead of a side table of opcodes, every pointer which needs fixing up is
+// overwritten in the segment contents with a bit-packed description of the
+// fixup, plus the distance to the next fixup on the same page. The
+// __LINKEDIT payload then only has to record where each page's chain starts
+// and the table of imported symbols the binds refer to.
+
+use {align_to, put_u16, put_u32, put_u64, set_u32, set_u64, Arch, MachOError,
+     Platform, Result, Version};
+
+pub const DYLD_CHAINED_PTR_ARM64E: u16 = 1;
+pub const DYLD_CHAINED_PTR_64: u16 = 2;
+pub const DYLD_CHAINED_PTR_32: u16 = 3;
+pub const DYLD_CHAINED_PTR_64_OFFSET: u16 = 6;
+
+pub const DYLD_CHAINED_PTR_START_NONE: u16 = 0xffff;
+
+pub const DYLD_CHAINED_IMPORT: u32 = 1;
+
+// Special library ordinals for imports which aren't bound to a specific
+// dylib.
+pub const BIND_SPECIAL_DYLIB_SELF: u8 = 0;
+pub const BIND_SPECIAL_DYLIB_MAIN_EXECUTABLE: u8 = 0xff;
+pub const BIND_SPECIAL_DYLIB_FLAT_LOOKUP: u8 = 0xfe;
+pub const BIND_SPECIAL_DYLIB_WEAK_LOOKUP: u8 = 0xfd;
+
+// sizeof(struct dyld_chained_fixups_header)
+const HEADER_SIZE: usize = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerFormat {
+  Ptr64,
+  Ptr64Offset,
+  Arm64e,
+  // 32-bit pointers, for arm64_32.
+  Ptr32,
+}
+
+impl PointerFormat {
+  pub fn for_arch(arch: Arch) -> Self {
+    match arch {
+      Arch::Arm64e => PointerFormat::Arm64e,
+      Arch::X86_64 | Arch::Arm64 => PointerFormat::Ptr64Offset,
+      Arch::Arm64_32 | Arch::I386 | Arch::Armv7 | Arch::Armv7s => {
+        PointerFormat::Ptr32
+      }
+    }
+  }
+
+  pub fn value(&self) -> u16 {
+    match *self {
+      PointerFormat::Ptr64 => DYLD_CHAINED_PTR_64,
+      PointerFormat::Ptr64Offset => DYLD_CHAINED_PTR_64_OFFSET,
+      PointerFormat::Arm64e => DYLD_CHAINED_PTR_ARM64E,
+      PointerFormat::Ptr32 => DYLD_CHAINED_PTR_32,
+    }
+  }
+
+  // The unit the `next` field of each pointer is measured in.
+  pub fn stride(&self) -> u64 {
+    match *self {
+      PointerFormat::Ptr64 | PointerFormat::Ptr64Offset => 4,
+      PointerFormat::Ptr32 => 4,
+      PointerFormat::Arm64e => 8,
+    }
+  }
+
+  pub fn pointer_size(&self) -> u64 {
+    match *self {
+      PointerFormat::Ptr32 => 4,
+      _ => 8,
+    }
+  }
+
+  fn next_bits(&self) -> u32 {
+    match *self {
+      PointerFormat::Ptr64 | PointerFormat::Ptr64Offset => 12,
+      PointerFormat::Arm64e => 11,
+      PointerFormat::Ptr32 => 5,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixup {
+  // `target` is an unslid vmaddr for DYLD_CHAINED_PTR_64, DYLD_CHAINED_PTR_32
+  // and arm64e, and an offset from the mach header for
+  // DYLD_CHAINED_PTR_64_OFFSET.
+  Rebase { target: u64, high8: u8 },
+  // `ordinal` indexes into the import table.
+  Bind { ordinal: u32, addend: u64 },
+  AuthRebase {
+    target: u64,
+    diversity: u16,
+    addr_div: bool,
+    key: u8,
+  },
+  AuthBind {
+    ordinal: u32,
+    diversity: u16,
+    addr_div: bool,
+    key: u8,
+  },
+}
+
+fn check_field(name: &'static str, val: u64, bits: u32) -> Result<u64> {
+  if val >> bits != 0 {
+    Err(MachOError::FieldOverflow(name, val))
+  } else {
+    Ok(val)
+  }
+}
+
+// Pack a single fixup into the value stored at its location, which is only
+// 32 bits for DYLD_CHAINED_PTR_32.
+pub fn encode_pointer(
+  format: PointerFormat,
+  fixup: &Fixup,
+  next: u64,
+) -> Result<u64> {
+  let next = check_field("next", next, format.next_bits())?;
+  match (format, *fixup) {
+    (PointerFormat::Arm64e, Fixup::Rebase { target, high8 }) => {
+      Ok(check_field("target", target, 43)? | ((high8 as u64) << 43)
+        | (next << 51))
+    }
+    (PointerFormat::Arm64e, Fixup::Bind { ordinal, addend }) => {
+      Ok(check_field("ordinal", ordinal as u64, 16)?
+        | (check_field("addend", addend, 19)? << 32)
+        | (next << 51) | (1 << 62))
+    }
+    (
+      PointerFormat::Arm64e,
+      Fixup::AuthRebase {
+        target,
+        diversity,
+        addr_div,
+        key,
+      },
+    ) => Ok(check_field("target", target, 32)?
+      | ((diversity as u64) << 32) | ((addr_div as u64) << 48)
+      | (check_field("key", key as u64, 2)? << 49) | (next << 51)
+      | (1 << 63)),
+    (
+      PointerFormat::Arm64e,
+      Fixup::AuthBind {
+        ordinal,
+        diversity,
+        addr_div,
+        key,
+      },
+    ) => Ok(check_field("ordinal", ordinal as u64, 16)?
+      | ((diversity as u64) << 32) | ((addr_div as u64) << 48)
+      | (check_field("key", key as u64, 2)? << 49) | (next << 51)
+      | (1 << 62) | (1 << 63)),
+    (PointerFormat::Ptr32, Fixup::Rebase { target, .. }) => {
+      Ok(check_field("target", target, 26)? | (next << 26))
+    }
+    (PointerFormat::Ptr32, Fixup::Bind { ordinal, addend }) => {
+      Ok(check_field("ordinal", ordinal as u64, 20)?
+        | (check_field("addend", addend, 6)? << 20) | (next << 26)
+        | (1 << 31))
+    }
+    (_, Fixup::Rebase { target, high8 }) => {
+      Ok(check_field("target", target, 36)? | ((high8 as u64) << 36)
+        | (next << 51))
+    }
+    (_, Fixup::Bind { ordinal, addend }) => {
+      Ok(check_field("ordinal", ordinal as u64, 24)?
+        | (check_field("addend", addend, 8)? << 24) | (next << 51)
+        | (1 << 63))
+    }
+    (_, Fixup::AuthRebase { .. }) | (_, Fixup::AuthBind { .. }) => {
+      Err(MachOError::FieldOverflow("auth", 1))
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Import {
+  pub lib_ordinal: u8,
+  pub weak_import: bool,
+  pub name: String,
+}
+
+// The fixups within one segment, by offset from the start of the segment.
+#[derive(Debug, Clone)]
+pub struct SegmentFixups {
+  // Offset of the segment's vmaddr from the mach header.
+  pub segment_offset: u64,
+  pub page_size: u64,
+  pub fixups: Vec<(u64, Fixup)>,
+  // For DYLD_CHAINED_PTR_32, the end of the image's address range: dyld
+  // treats rebase targets above it as non-pointers. Zero otherwise.
+  pub max_valid_pointer: u32,
+}
+
+// The contents of a struct dyld_chained_starts_in_segment.
+#[derive(Debug, Clone)]
+pub struct SegmentStarts {
+  pub segment_offset: u64,
+  pub page_size: u16,
+  pub pointer_format: PointerFormat,
+  pub max_valid_pointer: u32,
+  pub page_starts: Vec<u16>,
+}
+
+impl SegmentStarts {
+  fn write(&self, buf: &mut Vec<u8>) {
+    // size, page_size, pointer_format, segment_offset, max_valid_pointer,
+    // page_count, then the page_start array.
+    let size = 22 + 2 * self.page_starts.len();
+    put_u32(buf, size as u32);
+    put_u16(buf, self.page_size);
+    put_u16(buf, self.pointer_format.value());
+    put_u64(buf, self.segment_offset);
+    put_u32(buf, self.max_valid_pointer);
+    put_u16(buf, self.page_starts.len() as u16);
+    for start in self.page_starts.iter() {
+      put_u16(buf, *start);
+    }
+  }
+}
+
+// Thread the fixups for one segment into `contents` (the segment's file
+// contents), and return the page starts dyld needs to walk the chains.
+pub fn apply_chains(
+  format: PointerFormat,
+  seg: &SegmentFixups,
+  contents: &mut [u8],
+) -> Result<SegmentStarts> {
+  let stride = format.stride();
+  let pointer_size = format.pointer_size();
+  let page_size = seg.page_size;
+  let page_count = (contents.len() as u64 + page_size - 1) / page_size;
+  let mut page_starts: Vec<u16> =
+    vec![DYLD_CHAINED_PTR_START_NONE; page_count as usize];
+
+  let mut fixups = seg.fixups.clone();
+  fixups.sort_by_key(|&(off, _)| off);
+
+  for (i, &(off, ref fixup)) in fixups.iter().enumerate() {
+    let page = off / page_size;
+    // Pointers may not straddle a page boundary, or their chain couldn't be
+    // walked one page at a time.
+    if off % stride != 0 || off % page_size + pointer_size > page_size
+      || off + pointer_size > contents.len() as u64
+    {
+      return Err(MachOError::MisalignedFixup(off));
+    }
+    if page_starts[page as usize] == DYLD_CHAINED_PTR_START_NONE {
+      page_starts[page as usize] = (off % page_size) as u16;
+    }
+    let next = match fixups.get(i + 1) {
+      Some(&(next_off, _)) if next_off / page_size == page => {
+        if next_off == off {
+          return Err(MachOError::MisalignedFixup(next_off));
+        }
+        (next_off - off) / stride
+      }
+      _ => 0,
+    };
+    let value = encode_pointer(format, fixup, next)?;
+    if pointer_size == 4 {
+      set_u32(contents, off as usize, value as u32);
+    } else {
+      set_u64(contents, off as usize, value);
+    }
+  }
+
+  Ok(SegmentStarts {
+    segment_offset: seg.segment_offset,
+    page_size: page_size as u16,
+    pointer_format: format,
+    max_valid_pointer: seg.max_valid_pointer,
+    page_starts: page_starts,
+  })
+}
+
+// Builds the __LINKEDIT payload pointed to by LC_DYLD_CHAINED_FIXUPS.
+#[derive(Debug, Default)]
+pub struct ChainedFixupsBuilder {
+  // One entry per segment load command, in order. Segments without any
+  // fixups (__PAGEZERO, __TEXT, __LINKEDIT, ...) are None.
+  pub segments: Vec<Option<SegmentStarts>>,
+  pub imports: Vec<Import>,
+}
+
+impl ChainedFixupsBuilder {
+  pub fn new() -> Self {
+    ChainedFixupsBuilder {
+      ..Default::default()
+    }
+  }
+
+  pub fn add_segment(&mut self, starts: Option<SegmentStarts>) {
+    self.segments.push(starts);
+  }
+
+  // Returns the ordinal for use in Fixup::Bind.
+  pub fn add_import(&mut self, import: Import) -> u32 {
+    self.imports.push(import);
+    (self.imports.len() - 1) as u32
+  }
+
+  pub fn write(&self) -> Result<Vec<u8>> {
+    let mut buf: Vec<u8> = vec![0; HEADER_SIZE];
+    align_to(&mut buf, 8);
+
+    // struct dyld_chained_starts_in_image, whose seg_info_offset entries are
+    // relative to its own start.
+    let starts_offset = buf.len();
+    put_u32(&mut buf, self.segments.len() as u32);
+    let seg_info_offsets = buf.len();
+    for _ in self.segments.iter() {
+      put_u32(&mut buf, 0);
+    }
+    for (i, seg) in self.segments.iter().enumerate() {
+      if let Some(ref starts) = *seg {
+        align_to(&mut buf, 8);
+        let rel = (buf.len() - starts_offset) as u32;
+        set_u32(&mut buf, seg_info_offsets + 4 * i, rel);
+        starts.write(&mut buf);
+      }
+    }
+
+    align_to(&mut buf, 4);
+    let imports_offset = buf.len();
+    let mut symbols: Vec<u8> = Vec::new();
+    for import in self.imports.iter() {
+      let name_offset = check_field("name_offset", symbols.len() as u64, 23)?;
+      put_u32(
+        &mut buf,
+        (import.lib_ordinal as u32) | ((import.weak_import as u32) << 8)
+          | ((name_offset as u32) << 9),
+      );
+      symbols.extend_from_slice(import.name.as_bytes());
+      symbols.push(0);
+    }
+
+    let symbols_offset = buf.len();
+    buf.extend(symbols);
+    align_to(&mut buf, 8);
+
+    // Now that the offsets are known, fill in the header.
+    set_u32(&mut buf, 0, 0);
+    set_u32(&mut buf, 4, starts_offset as u32);
+    set_u32(&mut buf, 8, imports_offset as u32);
+    set_u32(&mut buf, 12, symbols_offset as u32);
+    set_u32(&mut buf, 16, self.imports.len() as u32);
+    set_u32(&mut buf, 20, DYLD_CHAINED_IMPORT);
+    set_u32(&mut buf, 24, 0);
+    Ok(buf)
+  }
+}
+
+// The first OS version whose dyld understands chained fixups, and the first
+// for which ld64 uses them by default.
+fn thresholds(platform: Platform) -> (Version, Version) {
+  match platform {
+    Platform::MacOS => (Version::new(11, 0, 0), Version::new(12, 0, 0)),
+    Platform::IOS
+    | Platform::IOSSimulator
+    | Platform::TvOS
+    | Platform::TvOSSimulator
+    | Platform::MacCatalyst => {
+      (Version::new(13, 4, 0), Version::new(15, 0, 0))
+    }
+    Platform::WatchOS | Platform::WatchOSSimulator => {
+      (Version::new(6, 2, 0), Version::new(8, 0, 0))
+    }
+    Platform::BridgeOS => (Version::new(4, 0, 0), Version::new(6, 0, 0)),
+    Platform::DriverKit => (Version::new(20, 0, 0), Version::new(21, 0, 0)),
+  }
+}
+
+pub fn supported(platform: Platform, min_version: Version) -> bool {
+  min_version >= thresholds(platform).0
+}
+
+// Whether to emit chained fixups when neither -fixup_chains nor
+// -no_fixup_chains was given. This follows ld64: arm64e always uses them
+// where supported, everything else from a later deployment target.
+pub fn default_enabled(
+  arch: Arch,
+  platform: Platform,
+  min_version: Version,
+) -> bool {
+  match arch {
+    Arch::Arm64e => supported(platform, min_version),
+    Arch::X86_64 | Arch::Arm64 | Arch::Arm64_32 => {
+      min_version >= thresholds(platform).1
+    }
+    // Nothing new enough to understand chained fixups still runs these.
+    Arch::I386 | Arch::Armv7 | Arch::Armv7s => false,
+  }
+}
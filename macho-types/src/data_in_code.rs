@@ -0,0 +1,53 @@
+// LC_DATA_IN_CODE: the ranges of code sections which are data (jump
+// tables, literal pools), so disassemblers and debuggers don't decode them
+// as instructions.
+//
+// The data is an array of struct data_in_code_entry. In object files each
+// offset is an address in the object; in linked images it's the distance
+// from the mach header.
+
+use {get_u16, get_u32, put_u16, put_u32, Result};
+
+pub const DICE_KIND_DATA: u16 = 1;
+pub const DICE_KIND_JUMP_TABLE8: u16 = 2;
+pub const DICE_KIND_JUMP_TABLE16: u16 = 3;
+pub const DICE_KIND_JUMP_TABLE32: u16 = 4;
+pub const DICE_KIND_ABS_JUMP_TABLE32: u16 = 5;
+
+// sizeof(struct data_in_code_entry)
+pub const DATA_IN_CODE_ENTRY_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataInCodeEntry {
+  pub offset: u32,
+  pub length: u16,
+  pub kind: u16,
+}
+
+pub fn parse(data: &[u8]) -> Result<Vec<DataInCodeEntry>> {
+  let what = "data_in_code_entry";
+  let mut entries: Vec<DataInCodeEntry> = Vec::new();
+  for i in 0..(data.len() / DATA_IN_CODE_ENTRY_SIZE) {
+    let at = i * DATA_IN_CODE_ENTRY_SIZE;
+    entries.push(DataInCodeEntry {
+      offset: get_u32(data, at, what)?,
+      length: get_u16(data, at + 4, what)?,
+      kind: get_u16(data, at + 6, what)?,
+    });
+  }
+  Ok(entries)
+}
+
+// Encode `entries`, which dyld and the tools reading them expect sorted by
+// offset.
+pub fn write(entries: &[DataInCodeEntry]) -> Vec<u8> {
+  let mut sorted: Vec<DataInCodeEntry> = entries.to_vec();
+  sorted.sort_by_key(|entry| entry.offset);
+  let mut buf: Vec<u8> = Vec::new();
+  for entry in sorted.iter() {
+    put_u32(&mut buf, entry.offset);
+    put_u16(&mut buf, entry.length);
+    put_u16(&mut buf, entry.kind);
+  }
+  buf
+}
@@ -0,0 +1,156 @@
+// Encoding for the opcode streams referenced by LC_DYLD_INFO_ONLY, used when
+// chained fixups aren't.
+
+use {put_sleb128, put_u8, put_uleb128};
+
+pub const REBASE_TYPE_POINTER: u8 = 1;
+
+pub const REBASE_OPCODE_DONE: u8 = 0x00;
+pub const REBASE_OPCODE_SET_TYPE_IMM: u8 = 0x10;
+pub const REBASE_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB: u8 = 0x20;
+pub const REBASE_OPCODE_DO_REBASE_IMM_TIMES: u8 = 0x50;
+
+pub const BIND_TYPE_POINTER: u8 = 1;
+
+// Special dylib ordinals, encoded with BIND_OPCODE_SET_DYLIB_SPECIAL_IMM.
+pub const BIND_SPECIAL_DYLIB_SELF: i64 = 0;
+pub const BIND_SPECIAL_DYLIB_MAIN_EXECUTABLE: i64 = -1;
+pub const BIND_SPECIAL_DYLIB_FLAT_LOOKUP: i64 = -2;
+pub const BIND_SPECIAL_DYLIB_WEAK_LOOKUP: i64 = -3;
+
+pub const BIND_SYMBOL_FLAGS_WEAK_IMPORT: u8 = 0x1;
+pub const BIND_SYMBOL_FLAGS_NON_WEAK_DEFINITION: u8 = 0x8;
+
+pub const BIND_OPCODE_DONE: u8 = 0x00;
+pub const BIND_OPCODE_SET_DYLIB_ORDINAL_IMM: u8 = 0x10;
+pub const BIND_OPCODE_SET_DYLIB_ORDINAL_ULEB: u8 = 0x20;
+pub const BIND_OPCODE_SET_DYLIB_SPECIAL_IMM: u8 = 0x30;
+pub const BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM: u8 = 0x40;
+pub const BIND_OPCODE_SET_TYPE_IMM: u8 = 0x50;
+pub const BIND_OPCODE_SET_ADDEND_SLEB: u8 = 0x60;
+pub const BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB: u8 = 0x70;
+pub const BIND_OPCODE_ADD_ADDR_ULEB: u8 = 0x80;
+pub const BIND_OPCODE_DO_BIND: u8 = 0x90;
+
+pub const EXPORT_SYMBOL_FLAGS_KIND_MASK: u64 = 0x03;
+pub const EXPORT_SYMBOL_FLAGS_KIND_REGULAR: u64 = 0x00;
+pub const EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE: u64 = 0x02;
+pub const EXPORT_SYMBOL_FLAGS_WEAK_DEFINITION: u64 = 0x04;
+pub const EXPORT_SYMBOL_FLAGS_REEXPORT: u64 = 0x08;
+pub const EXPORT_SYMBOL_FLAGS_STUB_AND_RESOLVER: u64 = 0x10;
+
+// A symbol taking part in dyld's cross-image weak coalescing.
+#[derive(Debug, Clone)]
+pub struct WeakBinding {
+  pub name: String,
+  // Strong definitions of a symbol other images define weakly are listed
+  // (with no locations) so dyld knows to prefer them.
+  pub strong_definition: bool,
+  // (segment index, offset within segment) of each pointer to the symbol.
+  pub locations: Vec<(u8, u64)>,
+}
+
+// Encode the weak binding stream. dyld requires the entries to be sorted by
+// symbol name, so they are sorted here rather than trusting the caller.
+pub fn encode_weak_bind(bindings: &[WeakBinding]) -> Vec<u8> {
+  let mut sorted: Vec<&WeakBinding> = bindings.iter().collect();
+  sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+  let mut buf: Vec<u8> = Vec::new();
+  if sorted.is_empty() {
+    return buf;
+  }
+  for binding in sorted {
+    let flags = if binding.strong_definition {
+      BIND_SYMBOL_FLAGS_NON_WEAK_DEFINITION
+    } else {
+      0
+    };
+    put_u8(&mut buf, BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM | flags);
+    buf.extend_from_slice(binding.name.as_bytes());
+    put_u8(&mut buf, 0);
+    if binding.strong_definition {
+      continue;
+    }
+    put_u8(&mut buf, BIND_OPCODE_SET_TYPE_IMM | BIND_TYPE_POINTER);
+    for &(seg, offset) in binding.locations.iter() {
+      put_u8(&mut buf, BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB | (seg & 0xf));
+      put_uleb128(&mut buf, offset);
+      put_u8(&mut buf, BIND_OPCODE_DO_BIND);
+    }
+  }
+  put_u8(&mut buf, BIND_OPCODE_DONE);
+  buf
+}
+
+// Encode the rebase stream for pointers at (segment index, offset within
+// segment). Each is rebased on its own, which is larger than ld64's runs
+// but means the same to dyld.
+pub fn encode_rebase(locations: &[(u8, u64)]) -> Vec<u8> {
+  let mut buf: Vec<u8> = Vec::new();
+  if locations.is_empty() {
+    return buf;
+  }
+  let mut sorted = locations.to_vec();
+  sorted.sort();
+  put_u8(&mut buf, REBASE_OPCODE_SET_TYPE_IMM | REBASE_TYPE_POINTER);
+  for &(seg, offset) in sorted.iter() {
+    put_u8(&mut buf, REBASE_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB | (seg & 0xf));
+    put_uleb128(&mut buf, offset);
+    put_u8(&mut buf, REBASE_OPCODE_DO_REBASE_IMM_TIMES | 1);
+  }
+  put_u8(&mut buf, REBASE_OPCODE_DONE);
+  buf
+}
+
+// A pointer dyld sets to the address of a symbol in another image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+  // The dylib's ordinal, or one of the BIND_SPECIAL_DYLIB_* values.
+  pub ordinal: i64,
+  pub name: String,
+  pub weak_import: bool,
+  pub addend: i64,
+  // (segment index, offset within segment) of the pointer.
+  pub segment: u8,
+  pub offset: u64,
+}
+
+// Encode the (non-lazy) bind stream, setting everything for each binding.
+pub fn encode_bind(bindings: &[Binding]) -> Vec<u8> {
+  let mut buf: Vec<u8> = Vec::new();
+  if bindings.is_empty() {
+    return buf;
+  }
+  for binding in bindings.iter() {
+    if binding.ordinal <= 0 {
+      let special = (binding.ordinal & 0xf) as u8;
+      put_u8(&mut buf, BIND_OPCODE_SET_DYLIB_SPECIAL_IMM | special);
+    } else if binding.ordinal <= 0xf {
+      let ordinal = binding.ordinal as u8;
+      put_u8(&mut buf, BIND_OPCODE_SET_DYLIB_ORDINAL_IMM | ordinal);
+    } else {
+      put_u8(&mut buf, BIND_OPCODE_SET_DYLIB_ORDINAL_ULEB);
+      put_uleb128(&mut buf, binding.ordinal as u64);
+    }
+    let flags = if binding.weak_import {
+      BIND_SYMBOL_FLAGS_WEAK_IMPORT
+    } else {
+      0
+    };
+    put_u8(&mut buf, BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM | flags);
+    buf.extend_from_slice(binding.name.as_bytes());
+    put_u8(&mut buf, 0);
+    put_u8(&mut buf, BIND_OPCODE_SET_TYPE_IMM | BIND_TYPE_POINTER);
+    if binding.addend != 0 {
+      put_u8(&mut buf, BIND_OPCODE_SET_ADDEND_SLEB);
+      put_sleb128(&mut buf, binding.addend);
+    }
+    let seg = binding.segment & 0xf;
+    put_u8(&mut buf, BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB | seg);
+    put_uleb128(&mut buf, binding.offset);
+    put_u8(&mut buf, BIND_OPCODE_DO_BIND);
+  }
+  put_u8(&mut buf, BIND_OPCODE_DONE);
+  buf
+}
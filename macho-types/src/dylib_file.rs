@@ -0,0 +1,164 @@
+// Reading the parts of a dylib a client links against: its identity, the
+// dylibs it re-exports, the umbrella it belongs to and who may link it, and
+// the symbols it exports, and whether app extensions may link it.
+
+use export_trie::{self, Export};
+use header::{header_size, parse_magic};
+use load_command::{parse_platform, LC_DYLD_EXPORTS_TRIE, LC_DYLD_INFO,
+                   LC_DYLD_INFO_ONLY, LC_ID_DYLIB, LC_REEXPORT_DYLIB,
+                   LC_SUB_CLIENT, LC_SUB_FRAMEWORK};
+use {check_limit, get_cstr, get_u32, next_command, MachOError, ParseLimits,
+     Platform, Result, Version, MH_DYLIB, MH_EXECUTE};
+
+#[derive(Debug, Clone, Default)]
+pub struct DylibFile {
+  pub install_name: String,
+  pub current_version: Version,
+  pub compatibility_version: Version,
+  // The mach header flags, such as MH_APP_EXTENSION_SAFE.
+  pub flags: u32,
+  // Install names from LC_REEXPORT_DYLIB, in ordinal order.
+  pub reexports: Vec<String>,
+  // LC_SUB_FRAMEWORK: the umbrella this dylib is part of, if any. Only the
+  // umbrella, its other parts and the allowable clients may link it.
+  pub umbrella: Option<String>,
+  // LC_SUB_CLIENT names.
+  pub allowable_clients: Vec<String>,
+  pub exports: Vec<Export>,
+  // From LC_BUILD_VERSION or LC_VERSION_MIN_*, if present.
+  pub platform: Option<(Platform, Version)>,
+  // The second LC_BUILD_VERSION of a zippered dylib.
+  pub variant: Option<(Platform, Version)>,
+}
+
+// The lc_str at `str_offset` within the command at `cmd_offset`.
+pub fn lc_str(
+  data: &[u8],
+  cmd_offset: usize,
+  cmdsize: usize,
+  str_offset: usize,
+) -> Result<String> {
+  if str_offset >= cmdsize {
+    return Err(MachOError::Malformed("lc_str offset outside its command"));
+  }
+  let end = cmd_offset.saturating_add(cmdsize).min(data.len());
+  let mut cur = cmd_offset.saturating_add(str_offset);
+  get_cstr(&data[..end], &mut cur, "load command string")
+}
+
+fn linkedit_slice(data: &[u8], off: u32, size: u32) -> Result<&[u8]> {
+  let start = off as usize;
+  let end = (start as u64).checked_add(size as u64);
+  end
+    .filter(|&end| end <= data.len() as u64)
+    .map(|end| &data[start..(end as usize)])
+    .ok_or(MachOError::Truncated("__LINKEDIT data"))
+}
+
+impl DylibFile {
+  pub fn parse(data: &[u8]) -> Result<DylibFile> {
+    DylibFile::parse_with_limits(data, &ParseLimits::default())
+  }
+
+  pub fn parse_with_limits(
+    data: &[u8],
+    limits: &ParseLimits,
+  ) -> Result<DylibFile> {
+    let dylib = DylibFile::parse_image(data, MH_DYLIB, limits)?;
+    if dylib.install_name.is_empty() {
+      return Err(MachOError::Malformed("dylib has no LC_ID_DYLIB"));
+    }
+    Ok(dylib)
+  }
+
+  // Read the exports of the executable given to -bundle_loader, which has
+  // no install name.
+  pub fn parse_executable(data: &[u8]) -> Result<DylibFile> {
+    DylibFile::parse_image(data, MH_EXECUTE, &ParseLimits::default())
+  }
+
+  fn parse_image(
+    data: &[u8],
+    expected_filetype: u32,
+    limits: &ParseLimits,
+  ) -> Result<DylibFile> {
+    let is_64_bit = parse_magic(data)?;
+    let filetype = get_u32(data, 12, "mach header")?;
+    if filetype != expected_filetype {
+      return Err(MachOError::WrongFileType(expected_filetype, filetype));
+    }
+    let ncmds = get_u32(data, 16, "mach header")?;
+    check_limit(
+      "load commands",
+      ncmds as u64,
+      limits.max_load_commands as u64,
+    )?;
+
+    let mut dylib = DylibFile {
+      flags: get_u32(data, 24, "mach header")?,
+      ..DylibFile::default()
+    };
+    let mut trie: &[u8] = &[];
+    let mut offset = header_size(is_64_bit) as usize;
+    for _ in 0..ncmds {
+      let cmd = get_u32(data, offset, "load command")?;
+      let cmdsize = get_u32(data, offset + 4, "load command")? as usize;
+      if cmdsize < 8 {
+        return Err(MachOError::Malformed("load command smaller than 8"));
+      }
+      if let Some(platform) = parse_platform(data, offset)? {
+        if dylib.platform.is_none() {
+          dylib.platform = Some(platform);
+        } else {
+          dylib.variant = Some(platform);
+        }
+      }
+      match cmd {
+        LC_ID_DYLIB => {
+          let name_offset = get_u32(data, offset + 8, "dylib_command")?;
+          dylib.install_name =
+            lc_str(data, offset, cmdsize, name_offset as usize)?;
+          dylib.current_version =
+            Version(get_u32(data, offset + 16, "dylib_command")?);
+          dylib.compatibility_version =
+            Version(get_u32(data, offset + 20, "dylib_command")?);
+        }
+        LC_REEXPORT_DYLIB => {
+          let name_offset = get_u32(data, offset + 8, "dylib_command")?;
+          dylib
+            .reexports
+            .push(lc_str(data, offset, cmdsize, name_offset as usize)?);
+        }
+        LC_SUB_FRAMEWORK | LC_SUB_CLIENT => {
+          let name_offset = get_u32(data, offset + 8, "sub_*_command")?;
+          let name = lc_str(data, offset, cmdsize, name_offset as usize)?;
+          if cmd == LC_SUB_FRAMEWORK {
+            dylib.umbrella = Some(name);
+          } else {
+            dylib.allowable_clients.push(name);
+          }
+        }
+        LC_DYLD_INFO | LC_DYLD_INFO_ONLY => {
+          // export_off and export_size are the last two fields.
+          let off = get_u32(data, offset + 40, "dyld_info_command")?;
+          let size = get_u32(data, offset + 44, "dyld_info_command")?;
+          trie = linkedit_slice(data, off, size)?;
+        }
+        LC_DYLD_EXPORTS_TRIE => {
+          let off = get_u32(data, offset + 8, "linkedit_data_command")?;
+          let size = get_u32(data, offset + 12, "linkedit_data_command")?;
+          trie = linkedit_slice(data, off, size)?;
+        }
+        _ => (),
+      }
+      offset = next_command(offset, cmdsize)?;
+    }
+
+    dylib.exports = export_trie::parse_with_limits(trie, limits)?;
+    Ok(dylib)
+  }
+
+  pub fn exports_symbol(&self, name: &str) -> bool {
+    self.exports.iter().any(|export| export.name == name)
+  }
+}
@@ -0,0 +1,267 @@
+// Encoding and decoding for the export trie (LC_DYLD_EXPORTS_TRIE, or the
+// export part of LC_DYLD_INFO_ONLY): a prefix tree of exported symbol names,
+// with the address and flags of each symbol stored at its terminal node.
+
+use std::collections::HashSet;
+
+use dyld_info::{EXPORT_SYMBOL_FLAGS_REEXPORT,
+                EXPORT_SYMBOL_FLAGS_STUB_AND_RESOLVER};
+use {check_limit, get_cstr, get_uleb128, put_u8, put_uleb128, uleb128_size,
+     MachOError, ParseLimits, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportKind {
+  // Offset of the symbol from the mach header.
+  Regular { address: u64 },
+  // Re-exported from the dylib with the given ordinal, possibly under a
+  // different name (empty if the same).
+  Reexport { ordinal: u64, imported_name: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+  pub name: String,
+  pub flags: u64,
+  pub kind: ExportKind,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+  terminal: Option<Vec<u8>>,
+  // (edge label, child node index)
+  children: Vec<(String, usize)>,
+  offset: u64,
+}
+
+fn terminal_info(export: &Export) -> Vec<u8> {
+  let mut buf: Vec<u8> = Vec::new();
+  match export.kind {
+    ExportKind::Regular { address } => {
+      put_uleb128(&mut buf, export.flags);
+      put_uleb128(&mut buf, address);
+    }
+    ExportKind::Reexport {
+      ordinal,
+      ref imported_name,
+    } => {
+      put_uleb128(&mut buf, export.flags | EXPORT_SYMBOL_FLAGS_REEXPORT);
+      put_uleb128(&mut buf, ordinal);
+      buf.extend_from_slice(imported_name.as_bytes());
+      put_u8(&mut buf, 0);
+    }
+  }
+  buf
+}
+
+fn common_prefix_len(a: &str, b: &str) -> usize {
+  a.bytes().zip(b.bytes()).take_while(|&(x, y)| x == y).count()
+}
+
+#[derive(Debug)]
+pub struct ExportTrie {
+  nodes: Vec<Node>,
+}
+
+impl Default for ExportTrie {
+  fn default() -> Self {
+    ExportTrie::new()
+  }
+}
+
+impl ExportTrie {
+  pub fn new() -> Self {
+    ExportTrie {
+      nodes: vec![Node::default()],
+    }
+  }
+
+  pub fn build(exports: &[Export]) -> Self {
+    let mut trie = ExportTrie::new();
+    for export in exports.iter() {
+      trie.insert(export);
+    }
+    trie
+  }
+
+  pub fn insert(&mut self, export: &Export) {
+    let mut node = 0;
+    let mut rest: &str = &export.name;
+    'descend: while !rest.is_empty() {
+      for i in 0..self.nodes[node].children.len() {
+        let (label, child) = self.nodes[node].children[i].clone();
+        let common = common_prefix_len(&label, rest);
+        if common == 0 {
+          continue;
+        }
+        if common < label.len() {
+          // Split the edge at the end of the shared prefix.
+          let mid = self.nodes.len();
+          self.nodes.push(Node {
+            children: vec![(label[common..].to_string(), child)],
+            ..Default::default()
+          });
+          self.nodes[node].children[i] = (label[..common].to_string(), mid);
+          node = mid;
+        } else {
+          node = child;
+        }
+        rest = &rest[common..];
+        continue 'descend;
+      }
+      let leaf = self.nodes.len();
+      self.nodes.push(Node::default());
+      self.nodes[node].children.push((rest.to_string(), leaf));
+      node = leaf;
+      break;
+    }
+    self.nodes[node].terminal = Some(terminal_info(export));
+  }
+
+  fn node_size(&self, idx: usize) -> u64 {
+    let node = &self.nodes[idx];
+    let mut size: u64 = match node.terminal {
+      Some(ref info) => {
+        uleb128_size(info.len() as u64) as u64 + info.len() as u64
+      }
+      None => 1,
+    };
+    // Child count.
+    size += 1;
+    for &(ref label, child) in node.children.iter() {
+      size += label.len() as u64 + 1;
+      size += uleb128_size(self.nodes[child].offset) as u64;
+    }
+    size
+  }
+
+  // Nodes in the order they're written: a preorder walk from the root.
+  fn order(&self) -> Vec<usize> {
+    let mut order: Vec<usize> = Vec::new();
+    let mut stack: Vec<usize> = vec![0];
+    while let Some(idx) = stack.pop() {
+      order.push(idx);
+      for &(_, child) in self.nodes[idx].children.iter().rev() {
+        stack.push(child);
+      }
+    }
+    order
+  }
+
+  pub fn encode(&mut self) -> Vec<u8> {
+    let order = self.order();
+    // Child offsets are ulebs, so a node's size depends on the offsets of
+    // the nodes after it. Iterate until the layout stops changing.
+    loop {
+      let mut changed = false;
+      let mut offset: u64 = 0;
+      for idx in order.iter() {
+        if self.nodes[*idx].offset != offset {
+          self.nodes[*idx].offset = offset;
+          changed = true;
+        }
+        offset += self.node_size(*idx);
+      }
+      if !changed {
+        break;
+      }
+    }
+
+    let mut buf: Vec<u8> = Vec::new();
+    for idx in order.iter() {
+      let node = &self.nodes[*idx];
+      match node.terminal {
+        Some(ref info) => {
+          put_uleb128(&mut buf, info.len() as u64);
+          buf.extend_from_slice(info);
+        }
+        None => put_u8(&mut buf, 0),
+      }
+      put_u8(&mut buf, node.children.len() as u8);
+      for &(ref label, child) in node.children.iter() {
+        buf.extend_from_slice(label.as_bytes());
+        put_u8(&mut buf, 0);
+        put_uleb128(&mut buf, self.nodes[child].offset);
+      }
+    }
+    // The trie is padded to pointer alignment in __LINKEDIT.
+    while buf.len() % 8 != 0 {
+      buf.push(0);
+    }
+    buf
+  }
+}
+
+fn parse_terminal(
+  data: &[u8],
+  offset: usize,
+  name: &str,
+) -> Result<Export> {
+  let mut cur = offset;
+  let flags = get_uleb128(data, &mut cur, "export trie")?;
+  let kind = if flags & EXPORT_SYMBOL_FLAGS_REEXPORT != 0 {
+    let ordinal = get_uleb128(data, &mut cur, "export trie")?;
+    let imported_name = get_cstr(data, &mut cur, "export trie")?;
+    ExportKind::Reexport {
+      ordinal: ordinal,
+      imported_name: imported_name,
+    }
+  } else {
+    // For stub-and-resolver exports this is the stub. The resolver which
+    // follows doesn't matter to clients.
+    let address = get_uleb128(data, &mut cur, "export trie")?;
+    if flags & EXPORT_SYMBOL_FLAGS_STUB_AND_RESOLVER != 0 {
+      get_uleb128(data, &mut cur, "export trie")?;
+    }
+    ExportKind::Regular { address: address }
+  };
+  Ok(Export {
+    name: name.to_string(),
+    flags: flags & !EXPORT_SYMBOL_FLAGS_REEXPORT,
+    kind: kind,
+  })
+}
+
+// Decode an encoded trie back into its exports, in preorder.
+pub fn parse(data: &[u8]) -> Result<Vec<Export>> {
+  parse_with_limits(data, &ParseLimits::default())
+}
+
+pub fn parse_with_limits(
+  data: &[u8],
+  limits: &ParseLimits,
+) -> Result<Vec<Export>> {
+  let mut exports: Vec<Export> = Vec::new();
+  if data.is_empty() {
+    return Ok(exports);
+  }
+  let mut visited: HashSet<usize> = HashSet::new();
+  // (node offset, depth, symbol name up to that node)
+  let mut stack: Vec<(usize, u32, String)> = vec![(0, 0, String::new())];
+  while let Some((offset, depth, prefix)) = stack.pop() {
+    if !visited.insert(offset) {
+      return Err(MachOError::Malformed("export trie node visited twice"));
+    }
+    let max_depth = limits.max_trie_depth as u64;
+    check_limit("export trie depth", depth as u64, max_depth)?;
+    let mut cur = offset;
+    let info_len = get_uleb128(data, &mut cur, "export trie")?;
+    if info_len > 0 {
+      exports.push(parse_terminal(data, cur, &prefix)?);
+    }
+    // The child count follows the terminal info.
+    let nchildren = (cur as u64)
+      .checked_add(info_len)
+      .filter(|&pos| pos < data.len() as u64)
+      .map(|pos| data[pos as usize])
+      .ok_or(MachOError::Truncated("export trie"))?;
+    cur += info_len as usize + 1;
+    let mut children: Vec<(usize, u32, String)> = Vec::new();
+    for _ in 0..nchildren {
+      let label = get_cstr(data, &mut cur, "export trie")?;
+      let child = get_uleb128(data, &mut cur, "export trie")? as usize;
+      children.push((child, depth + 1, format!("{}{}", prefix, label)));
+    }
+    stack.extend(children.into_iter().rev());
+  }
+  Ok(exports)
+}
@@ -0,0 +1,141 @@
+// Universal ("fat") files: a big-endian header listing one thin Mach-O per
+// architecture, each slice aligned to its architecture's page size.
+
+use {round_up, Arch, MachOError, Result, CPU_SUBTYPE_MASK};
+
+pub const FAT_MAGIC: u32 = 0xcafebabe;
+
+// sizeof(struct fat_header), sizeof(struct fat_arch)
+const FAT_HEADER_SIZE: usize = 8;
+const FAT_ARCH_SIZE: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FatArch {
+  pub cputype: u32,
+  pub cpusubtype: u32,
+  pub offset: u32,
+  pub size: u32,
+  // log2 of the slice alignment.
+  pub align: u32,
+}
+
+fn get_be32(data: &[u8], offset: usize) -> Result<u32> {
+  data
+    .get(offset..(offset + 4))
+    .map(|b| {
+      (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8
+        | b[3] as u32
+    })
+    .ok_or(MachOError::Truncated("fat header"))
+}
+
+fn put_be32(buf: &mut Vec<u8>, val: u32) {
+  for i in (0..4).rev() {
+    buf.push((val >> (i * 8)) as u8);
+  }
+}
+
+pub fn is_fat(data: &[u8]) -> bool {
+  get_be32(data, 0).ok() == Some(FAT_MAGIC)
+}
+
+pub fn parse(data: &[u8]) -> Result<Vec<FatArch>> {
+  let magic = get_be32(data, 0)?;
+  if magic != FAT_MAGIC {
+    return Err(MachOError::BadMagic(magic));
+  }
+  let nfat_arch = get_be32(data, 4)? as usize;
+  let mut archs: Vec<FatArch> = Vec::new();
+  for i in 0..nfat_arch {
+    let offset = FAT_HEADER_SIZE + i * FAT_ARCH_SIZE;
+    let arch = FatArch {
+      cputype: get_be32(data, offset)?,
+      cpusubtype: get_be32(data, offset + 4)?,
+      offset: get_be32(data, offset + 8)?,
+      size: get_be32(data, offset + 12)?,
+      align: get_be32(data, offset + 16)?,
+    };
+    if arch.offset as u64 + arch.size as u64 > data.len() as u64 {
+      return Err(MachOError::Truncated("fat slice"));
+    }
+    archs.push(arch);
+  }
+  Ok(archs)
+}
+
+// The slice of a fat file for `arch`, if it has one.
+pub fn slice_for(data: &[u8], arch: Arch) -> Result<Option<&[u8]>> {
+  let found = parse(data)?.into_iter().find(|fat_arch| {
+    fat_arch.cputype == arch.cputype()
+      && fat_arch.cpusubtype & !CPU_SUBTYPE_MASK == arch.cpusubtype()
+  });
+  Ok(found.map(|fat_arch| {
+    let start = fat_arch.offset as usize;
+    &data[start..(start + fat_arch.size as usize)]
+  }))
+}
+
+// The thin file for `arch`: `data` itself, or its slice if it's fat.
+pub fn thin(data: &[u8], arch: Arch) -> Result<&[u8]> {
+  if !is_fat(data) {
+    return Ok(data);
+  }
+  slice_for(data, arch)?.ok_or(MachOError::Malformed("no slice for -arch"))
+}
+
+// Every thin file in `data` with the architecture it's for: its slices if
+// it's fat, or just `data` (with no architecture) if it isn't.
+pub fn slices(data: &[u8]) -> Result<Vec<(Option<Arch>, &[u8])>> {
+  if !is_fat(data) {
+    return Ok(vec![(None, data)]);
+  }
+  Ok(
+    parse(data)?
+      .iter()
+      .map(|slice| {
+        let start = slice.offset as usize;
+        (
+          Arch::from_cputype(slice.cputype, slice.cpusubtype),
+          &data[start..(start + slice.size as usize)],
+        )
+      })
+      .collect(),
+  )
+}
+
+// Assemble thin files into a fat file, in the order given.
+pub fn write(slices: &[(Arch, &[u8])]) -> Result<Vec<u8>> {
+  let mut archs: Vec<FatArch> = Vec::new();
+  let mut offset = (FAT_HEADER_SIZE + slices.len() * FAT_ARCH_SIZE) as u64;
+  for &(arch, data) in slices.iter() {
+    let page_size = arch.page_size();
+    offset = round_up(offset, page_size);
+    if offset + data.len() as u64 > u32::MAX as u64 {
+      return Err(MachOError::Malformed("fat file larger than 4GB"));
+    }
+    archs.push(FatArch {
+      cputype: arch.cputype(),
+      cpusubtype: arch.cpusubtype(),
+      offset: offset as u32,
+      size: data.len() as u32,
+      align: page_size.trailing_zeros(),
+    });
+    offset += data.len() as u64;
+  }
+
+  let mut buf: Vec<u8> = Vec::new();
+  put_be32(&mut buf, FAT_MAGIC);
+  put_be32(&mut buf, archs.len() as u32);
+  for arch in archs.iter() {
+    put_be32(&mut buf, arch.cputype);
+    put_be32(&mut buf, arch.cpusubtype);
+    put_be32(&mut buf, arch.offset);
+    put_be32(&mut buf, arch.size);
+    put_be32(&mut buf, arch.align);
+  }
+  for (arch, &(_, data)) in archs.iter().zip(slices.iter()) {
+    buf.resize(arch.offset as usize, 0);
+    buf.extend_from_slice(data);
+  }
+  Ok(buf)
+}
@@ -0,0 +1,45 @@
+// LC_FUNCTION_STARTS: where each function in the image starts, which
+// debuggers, profilers and crash reporters use to find functions in
+// stripped images.
+//
+// The data is a list of ULEB128s: the distance from the mach header to the
+// first function, then from each function to the next, ending with a zero
+// and padded with zeros to 8 bytes.
+
+use {get_uleb128, put_uleb128, Result};
+
+// The addresses of the functions in an image whose mach header is at
+// `base`.
+pub fn parse(data: &[u8], base: u64) -> Result<Vec<u64>> {
+  let mut starts: Vec<u64> = Vec::new();
+  let mut addr = base;
+  let mut cur: usize = 0;
+  while cur < data.len() {
+    let delta = get_uleb128(data, &mut cur, "function starts")?;
+    if delta == 0 {
+      break;
+    }
+    addr = addr.wrapping_add(delta);
+    starts.push(addr);
+  }
+  Ok(starts)
+}
+
+// Encode `starts`, in any order, for an image whose mach header is at
+// `base`. Functions at the same address are listed once.
+pub fn write(starts: &[u64], base: u64) -> Vec<u8> {
+  let mut sorted: Vec<u64> = starts.to_vec();
+  sorted.sort();
+  sorted.dedup();
+  let mut buf: Vec<u8> = Vec::new();
+  let mut prev = base;
+  for &addr in sorted.iter() {
+    put_uleb128(&mut buf, addr - prev);
+    prev = addr;
+  }
+  buf.push(0);
+  while buf.len() % 8 != 0 {
+    buf.push(0);
+  }
+  buf
+}
@@ -0,0 +1,63 @@
+use {get_u32, put_u32, Arch, MachOError, Result, MH_MAGIC, MH_MAGIC_64};
+
+// sizeof(struct mach_header_64), sizeof(struct mach_header)
+pub const MACH_HEADER_64_SIZE: u64 = 32;
+pub const MACH_HEADER_SIZE: u64 = 28;
+
+pub fn header_size(is_64_bit: bool) -> u64 {
+  if is_64_bit {
+    MACH_HEADER_64_SIZE
+  } else {
+    MACH_HEADER_SIZE
+  }
+}
+
+// Whether the Mach-O file in `data` is 64-bit, from its magic.
+pub fn parse_magic(data: &[u8]) -> Result<bool> {
+  match get_u32(data, 0, "mach header")? {
+    MH_MAGIC_64 => Ok(true),
+    MH_MAGIC => Ok(false),
+    magic => Err(MachOError::BadMagic(magic)),
+  }
+}
+
+// A struct mach_header_64, or for 32-bit architectures a struct mach_header,
+// which is the same without the trailing reserved field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachHeader64 {
+  pub cputype: u32,
+  pub cpusubtype: u32,
+  pub filetype: u32,
+  pub ncmds: u32,
+  pub sizeofcmds: u32,
+  pub flags: u32,
+  pub is_64_bit: bool,
+}
+
+impl MachHeader64 {
+  pub fn new(arch: Arch, filetype: u32) -> Self {
+    MachHeader64 {
+      cputype: arch.cputype(),
+      cpusubtype: arch.cpusubtype(),
+      filetype: filetype,
+      ncmds: 0,
+      sizeofcmds: 0,
+      flags: 0,
+      is_64_bit: arch.is_64_bit(),
+    }
+  }
+
+  pub fn write(&self, buf: &mut Vec<u8>) {
+    put_u32(buf, if self.is_64_bit { MH_MAGIC_64 } else { MH_MAGIC });
+    put_u32(buf, self.cputype);
+    put_u32(buf, self.cpusubtype);
+    put_u32(buf, self.filetype);
+    put_u32(buf, self.ncmds);
+    put_u32(buf, self.sizeofcmds);
+    put_u32(buf, self.flags);
+    if self.is_64_bit {
+      // reserved
+      put_u32(buf, 0);
+    }
+  }
+}
@@ -0,0 +1,96 @@
+// Reading a linked image (an executable, dylib or bundle) for tools which
+// post-process one: its segments and section headers, UUID and symbols.
+
+use header::{header_size, parse_magic};
+use load_command::{parse_platform, Segment64, LC_SEGMENT, LC_SEGMENT_64,
+                   LC_SYMTAB, LC_UUID};
+use symtab::{parse_symtab_with_limits, Nlist};
+use {check_limit, get_u32, next_command, CopyBudget, MachOError, ParseLimits,
+     Platform, Result, Version};
+
+#[derive(Debug, Clone, Default)]
+pub struct ImageFile {
+  pub cputype: u32,
+  pub cpusubtype: u32,
+  pub filetype: u32,
+  pub segments: Vec<Segment64>,
+  pub uuid: Option<[u8; 16]>,
+  pub symbols: Vec<Nlist>,
+  pub platform: Option<(Platform, Version)>,
+}
+
+impl ImageFile {
+  pub fn parse(data: &[u8]) -> Result<ImageFile> {
+    ImageFile::parse_with_limits(data, &ParseLimits::default())
+  }
+
+  pub fn parse_with_limits(
+    data: &[u8],
+    limits: &ParseLimits,
+  ) -> Result<ImageFile> {
+    let mut budget = CopyBudget::new(limits);
+    let is_64_bit = parse_magic(data)?;
+    let mut image = ImageFile {
+      cputype: get_u32(data, 4, "mach header")?,
+      cpusubtype: get_u32(data, 8, "mach header")?,
+      filetype: get_u32(data, 12, "mach header")?,
+      ..Default::default()
+    };
+    let ncmds = get_u32(data, 16, "mach header")?;
+    check_limit(
+      "load commands",
+      ncmds as u64,
+      limits.max_load_commands as u64,
+    )?;
+
+    let mut offset = header_size(is_64_bit) as usize;
+    for _ in 0..ncmds {
+      let cmd = get_u32(data, offset, "load command")?;
+      let cmdsize = get_u32(data, offset + 4, "load command")? as usize;
+      if cmdsize < 8 {
+        return Err(MachOError::Malformed("load command smaller than 8"));
+      }
+      if let Some(platform) = parse_platform(data, offset)? {
+        image.platform = Some(platform);
+      }
+      match cmd {
+        LC_SEGMENT_64 | LC_SEGMENT => {
+          let seg = if cmd == LC_SEGMENT_64 {
+            Segment64::parse(data, offset)?
+          } else {
+            Segment64::parse_32(data, offset)?
+          };
+          let sections: usize =
+            image.segments.iter().map(|seg| seg.sections.len()).sum();
+          let count = (sections + seg.sections.len()) as u64;
+          check_limit("sections", count, limits.max_sections as u64)?;
+          image.segments.push(seg);
+        }
+        LC_SYMTAB => {
+          image.symbols = parse_symtab_with_limits(
+            data,
+            offset,
+            is_64_bit,
+            limits,
+            &mut budget,
+          )?
+        }
+        LC_UUID => {
+          let uuid = data
+            .get((offset + 8)..(offset + 24))
+            .ok_or(MachOError::Truncated("uuid_command"))?;
+          let mut bytes: [u8; 16] = [0; 16];
+          bytes.copy_from_slice(uuid);
+          image.uuid = Some(bytes);
+        }
+        _ => (),
+      }
+      offset = next_command(offset, cmdsize)?;
+    }
+    Ok(image)
+  }
+
+  pub fn segment(&self, segname: &str) -> Option<&Segment64> {
+    self.segments.iter().find(|seg| seg.segname == segname)
+  }
+}
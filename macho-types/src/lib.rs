@@ -0,0 +1,703 @@
+// The Mach-O data model: constants, the load command, section and symbol
+// structures, and readers and writers for them. This is the linker's own
+// (`mold` uses it as its `macho` module), published separately so other
+// tools can read and write Mach-O files without the rest of the linker.
+// Nothing here depends on anything outside the standard library.
+//
+// Everything here is written in little-endian byte order, since every
+// architecture we target (x86_64, arm64, arm64_32, and the legacy 32-bit
+// i386 and armv7) is little-endian.
+
+// Struct fields are initialized as `field: field`, and patterns and statics
+// spelled out, in the style of the rest of the linker.
+#![allow(
+  clippy::needless_borrowed_reference,
+  clippy::redundant_field_names,
+  clippy::redundant_static_lifetimes
+)]
+
+pub mod chained_fixups;
+pub mod data_in_code;
+pub mod dyld_info;
+pub mod dylib_file;
+pub mod export_trie;
+pub mod fat;
+pub mod function_starts;
+pub mod header;
+pub mod image_file;
+pub mod load_command;
+pub mod loh;
+pub mod object_file;
+pub mod reloc;
+pub mod split_seg;
+pub mod symtab;
+pub mod unwind_info;
+
+use std::fmt;
+
+pub const MH_MAGIC: u32 = 0xfeedface;
+pub const MH_MAGIC_64: u32 = 0xfeedfacf;
+
+pub const CPU_ARCH_ABI64: u32 = 0x0100_0000;
+// 64-bit instructions with 32-bit pointers.
+pub const CPU_ARCH_ABI64_32: u32 = 0x0200_0000;
+pub const CPU_TYPE_X86: u32 = 7;
+pub const CPU_TYPE_I386: u32 = CPU_TYPE_X86;
+pub const CPU_TYPE_X86_64: u32 = CPU_TYPE_X86 | CPU_ARCH_ABI64;
+pub const CPU_TYPE_ARM: u32 = 12;
+pub const CPU_TYPE_ARM64: u32 = CPU_TYPE_ARM | CPU_ARCH_ABI64;
+pub const CPU_TYPE_ARM64_32: u32 = CPU_TYPE_ARM | CPU_ARCH_ABI64_32;
+
+pub const CPU_SUBTYPE_X86_64_ALL: u32 = 3;
+pub const CPU_SUBTYPE_I386_ALL: u32 = 3;
+pub const CPU_SUBTYPE_ARM_V7: u32 = 9;
+pub const CPU_SUBTYPE_ARM_V7S: u32 = 11;
+pub const CPU_SUBTYPE_ARM64_ALL: u32 = 0;
+// The capability bits in the high byte of cpusubtype (e.g. the arm64e ABI
+// version), which don't change the architecture.
+pub const CPU_SUBTYPE_MASK: u32 = 0xff00_0000;
+pub const CPU_SUBTYPE_ARM64E: u32 = 2;
+pub const CPU_SUBTYPE_ARM64_32_V8: u32 = 1;
+
+// File types.
+pub const MH_OBJECT: u32 = 0x1;
+pub const MH_EXECUTE: u32 = 0x2;
+pub const MH_PRELOAD: u32 = 0x5;
+pub const MH_DYLIB: u32 = 0x6;
+pub const MH_BUNDLE: u32 = 0x8;
+pub const MH_DSYM: u32 = 0xa;
+pub const MH_KEXT_BUNDLE: u32 = 0xb;
+
+pub const VM_PROT_NONE: u32 = 0x0;
+pub const VM_PROT_READ: u32 = 0x1;
+pub const VM_PROT_WRITE: u32 = 0x2;
+pub const VM_PROT_EXECUTE: u32 = 0x4;
+
+// Segment flags.
+// Read-only once dyld has applied fixups.
+pub const SG_READ_ONLY: u32 = 0x10;
+
+// Mach header flags.
+pub const MH_NOUNDEFS: u32 = 0x1;
+pub const MH_INCRLINK: u32 = 0x2;
+pub const MH_DYLDLINK: u32 = 0x4;
+pub const MH_TWOLEVEL: u32 = 0x80;
+pub const MH_SUBSECTIONS_VIA_SYMBOLS: u32 = 0x2000;
+pub const MH_WEAK_DEFINES: u32 = 0x8000;
+pub const MH_BINDS_TO_WEAK: u32 = 0x10000;
+pub const MH_NO_REEXPORTED_DYLIBS: u32 = 0x10_0000;
+pub const MH_PIE: u32 = 0x20_0000;
+pub const MH_DEAD_STRIPPABLE_DYLIB: u32 = 0x40_0000;
+pub const MH_HAS_TLV_DESCRIPTORS: u32 = 0x80_0000;
+pub const MH_NO_HEAP_EXECUTION: u32 = 0x100_0000;
+pub const MH_APP_EXTENSION_SAFE: u32 = 0x200_0000;
+
+// Section types, the low byte of a section's flags.
+pub const SECTION_TYPE: u32 = 0xff;
+pub const S_REGULAR: u32 = 0x0;
+pub const S_ZEROFILL: u32 = 0x1;
+pub const S_CSTRING_LITERALS: u32 = 0x2;
+pub const S_4BYTE_LITERALS: u32 = 0x3;
+pub const S_8BYTE_LITERALS: u32 = 0x4;
+pub const S_LITERAL_POINTERS: u32 = 0x5;
+pub const S_NON_LAZY_SYMBOL_POINTERS: u32 = 0x6;
+pub const S_LAZY_SYMBOL_POINTERS: u32 = 0x7;
+pub const S_SYMBOL_STUBS: u32 = 0x8;
+pub const S_MOD_INIT_FUNC_POINTERS: u32 = 0x9;
+pub const S_MOD_TERM_FUNC_POINTERS: u32 = 0xa;
+pub const S_COALESCED: u32 = 0xb;
+pub const S_GB_ZEROFILL: u32 = 0xc;
+pub const S_INTERPOSING: u32 = 0xd;
+pub const S_16BYTE_LITERALS: u32 = 0xe;
+pub const S_DTRACE_DOF: u32 = 0xf;
+pub const S_LAZY_DYLIB_SYMBOL_POINTERS: u32 = 0x10;
+pub const S_THREAD_LOCAL_REGULAR: u32 = 0x11;
+pub const S_THREAD_LOCAL_ZEROFILL: u32 = 0x12;
+pub const S_THREAD_LOCAL_VARIABLES: u32 = 0x13;
+pub const S_THREAD_LOCAL_VARIABLE_POINTERS: u32 = 0x14;
+pub const S_THREAD_LOCAL_INIT_FUNCTION_POINTERS: u32 = 0x15;
+pub const S_INIT_FUNC_OFFSETS: u32 = 0x16;
+
+// The type of a section. __DATA,__interpose is S_INTERPOSING even when the
+// compiler left it S_REGULAR, as it does for a section attribute.
+pub fn section_type(segname: &str, sectname: &str, flags: u32) -> u32 {
+  match (segname, sectname, flags & SECTION_TYPE) {
+    ("__DATA", "__interpose", S_REGULAR) => S_INTERPOSING,
+    (_, _, kind) => kind,
+  }
+}
+
+// Section attributes.
+pub const SECTION_ATTRIBUTES: u32 = 0xffff_ff00;
+pub const S_ATTR_PURE_INSTRUCTIONS: u32 = 0x8000_0000;
+pub const S_ATTR_NO_DEAD_STRIP: u32 = 0x1000_0000;
+pub const S_ATTR_LIVE_SUPPORT: u32 = 0x0800_0000;
+pub const S_ATTR_SOME_INSTRUCTIONS: u32 = 0x0000_0400;
+
+pub const LC_REQ_DYLD: u32 = 0x8000_0000;
+
+#[derive(Debug)]
+pub enum MachOError {
+  // A value did not fit in the bit field the format gives it.
+  FieldOverflow(&'static str, u64),
+  // A fixup location was not aligned to the pointer format's stride.
+  MisalignedFixup(u64),
+  InvalidVersion(String),
+  // The input ended while reading the named structure.
+  Truncated(&'static str),
+  BadMagic(u32),
+  // (expected, found)
+  WrongFileType(u32, u32),
+  // Structurally invalid input, e.g. a cycle in an export trie.
+  Malformed(&'static str),
+  // (what, how many) of something over its ParseLimits limit.
+  LimitExceeded(&'static str, u64),
+}
+
+pub type Result<T> = ::std::result::Result<T, MachOError>;
+
+// Limits on what the parsers accept, so a small file can't make them
+// allocate (or work) far beyond its own size, however it's corrupted. The
+// parsers never panic on bad input either way; the default limits accept
+// anything the format can express, and `strict` is for untrusted files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+  pub max_load_commands: u32,
+  pub max_sections: u32,
+  pub max_symbols: u32,
+  pub max_string_table_size: u32,
+  // How deep an export trie may nest, which bounds the names built from it.
+  pub max_trie_depth: u32,
+  // Section contents, relocations and symbol names copied out of the file,
+  // in bytes. Anything the file points at more than once counts each time.
+  pub max_bytes_copied: u64,
+}
+
+impl Default for ParseLimits {
+  fn default() -> Self {
+    ParseLimits {
+      max_load_commands: u32::MAX,
+      max_sections: u32::MAX,
+      max_symbols: u32::MAX,
+      max_string_table_size: u32::MAX,
+      max_trie_depth: u32::MAX,
+      max_bytes_copied: u64::MAX,
+    }
+  }
+}
+
+impl ParseLimits {
+  // Comfortably more than compilers and linkers produce.
+  pub fn strict() -> Self {
+    ParseLimits {
+      max_load_commands: 1 << 12,
+      max_sections: 1 << 12,
+      max_symbols: 1 << 22,
+      max_string_table_size: 1 << 28,
+      max_trie_depth: 1 << 10,
+      max_bytes_copied: 1 << 32,
+    }
+  }
+}
+
+// Check `count` of `what` against its `limit`.
+pub fn check_limit(what: &'static str, count: u64, limit: u64) -> Result<()> {
+  if count > limit {
+    return Err(MachOError::LimitExceeded(what, count));
+  }
+  Ok(())
+}
+
+// What's left of a parse's ParseLimits::max_bytes_copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyBudget {
+  remaining: u64,
+}
+
+impl CopyBudget {
+  pub fn new(limits: &ParseLimits) -> Self {
+    CopyBudget {
+      remaining: limits.max_bytes_copied,
+    }
+  }
+
+  // Account for copying `len` bytes.
+  pub fn spend(&mut self, len: u64) -> Result<()> {
+    if len > self.remaining {
+      return Err(MachOError::LimitExceeded("bytes copied", len));
+    }
+    self.remaining -= len;
+    Ok(())
+  }
+}
+
+// The offset of the load command after the one at `offset`, which is
+// `cmdsize` bytes.
+pub fn next_command(offset: usize, cmdsize: usize) -> Result<usize> {
+  offset
+    .checked_add(cmdsize)
+    .ok_or(MachOError::Truncated("load command"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+  X86_64,
+  Arm64,
+  Arm64e,
+  // watchOS's ILP32 arm64.
+  Arm64_32,
+  I386,
+  Armv7,
+  Armv7s,
+}
+
+impl Arch {
+  pub fn from_name(name: &str) -> Option<Arch> {
+    match name {
+      "x86_64" => Some(Arch::X86_64),
+      "arm64" => Some(Arch::Arm64),
+      "arm64e" => Some(Arch::Arm64e),
+      "arm64_32" => Some(Arch::Arm64_32),
+      "i386" => Some(Arch::I386),
+      "armv7" => Some(Arch::Armv7),
+      "armv7s" => Some(Arch::Armv7s),
+      _ => None,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match *self {
+      Arch::X86_64 => "x86_64",
+      Arch::Arm64 => "arm64",
+      Arch::Arm64e => "arm64e",
+      Arch::Arm64_32 => "arm64_32",
+      Arch::I386 => "i386",
+      Arch::Armv7 => "armv7",
+      Arch::Armv7s => "armv7s",
+    }
+  }
+
+  pub fn cputype(&self) -> u32 {
+    match *self {
+      Arch::X86_64 => CPU_TYPE_X86_64,
+      Arch::Arm64 | Arch::Arm64e => CPU_TYPE_ARM64,
+      Arch::Arm64_32 => CPU_TYPE_ARM64_32,
+      Arch::I386 => CPU_TYPE_I386,
+      Arch::Armv7 | Arch::Armv7s => CPU_TYPE_ARM,
+    }
+  }
+
+  // Whether this is one of the arm64 variants, which share relocation types
+  // and instruction encodings.
+  pub fn is_arm64(&self) -> bool {
+    matches!(*self, Arch::Arm64 | Arch::Arm64e | Arch::Arm64_32)
+  }
+
+  // Whether this is i386 or 32-bit arm, whose relocations may be scattered
+  // and come in pairs.
+  pub fn is_legacy_32_bit(&self) -> bool {
+    matches!(*self, Arch::I386 | Arch::Armv7 | Arch::Armv7s)
+  }
+
+  // Whether the container is 64-bit: mach_header_64, LC_SEGMENT_64 and
+  // nlist_64, rather than their 32-bit forms.
+  pub fn is_64_bit(&self) -> bool {
+    self.pointer_size() == 8
+  }
+
+  pub fn pointer_size(&self) -> u64 {
+    match *self {
+      Arch::X86_64 | Arch::Arm64 | Arch::Arm64e => 8,
+      Arch::Arm64_32 | Arch::I386 | Arch::Armv7 | Arch::Armv7s => 4,
+    }
+  }
+
+  // Whether executables may be linked -no_pie. arm64 requires PIE.
+  pub fn allows_non_pie(&self) -> bool {
+    !self.is_arm64()
+  }
+
+  // Whether executables get MH_NO_HEAP_EXECUTION unless -allow_heap_execute
+  // is given. 64-bit heaps are never executable, so the flag is only
+  // needed for 32-bit targets, and as with ld64 only i386 gets it.
+  pub fn non_executable_heap(&self) -> bool {
+    *self == Arch::I386
+  }
+
+  pub fn page_size(&self) -> u64 {
+    match *self {
+      Arch::X86_64 | Arch::I386 | Arch::Armv7 | Arch::Armv7s => 0x1000,
+      Arch::Arm64 | Arch::Arm64e | Arch::Arm64_32 => 0x4000,
+    }
+  }
+
+  pub fn cpusubtype(&self) -> u32 {
+    match *self {
+      Arch::X86_64 => CPU_SUBTYPE_X86_64_ALL,
+      Arch::Arm64 => CPU_SUBTYPE_ARM64_ALL,
+      Arch::Arm64e => CPU_SUBTYPE_ARM64E,
+      Arch::Arm64_32 => CPU_SUBTYPE_ARM64_32_V8,
+      Arch::I386 => CPU_SUBTYPE_I386_ALL,
+      Arch::Armv7 => CPU_SUBTYPE_ARM_V7,
+      Arch::Armv7s => CPU_SUBTYPE_ARM_V7S,
+    }
+  }
+
+  // The architecture a header or fat slice is for, if we know it.
+  pub fn from_cputype(cputype: u32, cpusubtype: u32) -> Option<Arch> {
+    ARCHS.iter().cloned().find(|arch| {
+      arch.cputype() == cputype
+        && arch.cpusubtype() == cpusubtype & !CPU_SUBTYPE_MASK
+    })
+  }
+}
+
+const ARCHS: &[Arch] = &[
+  Arch::X86_64,
+  Arch::Arm64,
+  Arch::Arm64e,
+  Arch::Arm64_32,
+  Arch::I386,
+  Arch::Armv7,
+  Arch::Armv7s,
+];
+
+// Platforms from LC_BUILD_VERSION.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+  MacOS,
+  IOS,
+  TvOS,
+  WatchOS,
+  BridgeOS,
+  MacCatalyst,
+  IOSSimulator,
+  TvOSSimulator,
+  WatchOSSimulator,
+  DriverKit,
+}
+
+const PLATFORMS: [(Platform, u32, &'static str); 10] = [
+  (Platform::MacOS, 1, "macos"),
+  (Platform::IOS, 2, "ios"),
+  (Platform::TvOS, 3, "tvos"),
+  (Platform::WatchOS, 4, "watchos"),
+  (Platform::BridgeOS, 5, "bridgeos"),
+  (Platform::MacCatalyst, 6, "mac-catalyst"),
+  (Platform::IOSSimulator, 7, "ios-simulator"),
+  (Platform::TvOSSimulator, 8, "tvos-simulator"),
+  (Platform::WatchOSSimulator, 9, "watchos-simulator"),
+  (Platform::DriverKit, 10, "driverkit"),
+];
+
+impl Platform {
+  // Accepts the names ld64 does, or the raw PLATFORM_* number.
+  pub fn from_name(name: &str) -> Option<Platform> {
+    let number = name.parse::<u32>().ok();
+    PLATFORMS
+      .iter()
+      .find(|&&(_, n, s)| s == name || Some(n) == number)
+      .map(|&(platform, _, _)| platform)
+  }
+
+  pub fn from_number(number: u32) -> Option<Platform> {
+    PLATFORMS
+      .iter()
+      .find(|&&(_, n, _)| n == number)
+      .map(|&(platform, _, _)| platform)
+  }
+
+  pub fn number(&self) -> u32 {
+    PLATFORMS.iter().find(|&&(p, _, _)| p == *self).unwrap().1
+  }
+
+  pub fn name(&self) -> &'static str {
+    PLATFORMS.iter().find(|&&(p, _, _)| p == *self).unwrap().2
+  }
+
+  pub fn is_simulator(&self) -> bool {
+    matches!(
+      *self,
+      Platform::IOSSimulator
+        | Platform::TvOSSimulator
+        | Platform::WatchOSSimulator
+    )
+  }
+}
+
+// A version number packed the way load commands store it: xxxx.yy.zz in
+// nibbles, i.e. 16 bits of major, 8 bits of minor and 8 bits of patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Version(pub u32);
+
+impl Version {
+  pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+    Version((major << 16) | ((minor & 0xff) << 8) | (patch & 0xff))
+  }
+
+  pub fn parse(s: &str) -> Result<Version> {
+    let err = || MachOError::InvalidVersion(s.to_string());
+    let parts: Vec<&str> = s.split('.').collect();
+    if parts.is_empty() || parts.len() > 3 {
+      return Err(err());
+    }
+    let mut nums: [u32; 3] = [0, 0, 0];
+    for (i, part) in parts.iter().enumerate() {
+      nums[i] = part.parse::<u32>().map_err(|_| err())?;
+    }
+    if nums[0] > 0xffff || nums[1] > 0xff || nums[2] > 0xff {
+      return Err(err());
+    }
+    Ok(Version::new(nums[0], nums[1], nums[2]))
+  }
+
+  pub fn major(&self) -> u32 {
+    self.0 >> 16
+  }
+
+  pub fn minor(&self) -> u32 {
+    (self.0 >> 8) & 0xff
+  }
+
+  pub fn patch(&self) -> u32 {
+    self.0 & 0xff
+  }
+}
+
+impl fmt::Display for Version {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}.{}.{}", self.major(), self.minor(), self.patch())
+  }
+}
+
+// A source version as LC_SOURCE_VERSION stores it: a.b.c.d.e packed into 24,
+// 10, 10, 10 and 10 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct SourceVersion(pub u64);
+
+impl SourceVersion {
+  pub fn parse(s: &str) -> Result<SourceVersion> {
+    let err = || MachOError::InvalidVersion(s.to_string());
+    let parts: Vec<&str> = s.split('.').collect();
+    if parts.is_empty() || parts.len() > 5 {
+      return Err(err());
+    }
+    let mut packed: u64 = 0;
+    for i in 0..5 {
+      let max: u64 = if i == 0 { 0xff_ffff } else { 0x3ff };
+      let num = match parts.get(i) {
+        Some(part) => part.parse::<u64>().map_err(|_| err())?,
+        None => 0,
+      };
+      if num > max {
+        return Err(err());
+      }
+      packed = if i == 0 { num } else { packed << 10 | num };
+    }
+    Ok(SourceVersion(packed))
+  }
+
+  pub fn components(&self) -> [u64; 5] {
+    [
+      self.0 >> 40,
+      (self.0 >> 30) & 0x3ff,
+      (self.0 >> 20) & 0x3ff,
+      (self.0 >> 10) & 0x3ff,
+      self.0 & 0x3ff,
+    ]
+  }
+}
+
+// Like otool: always a.b, and the rest up to the last which isn't 0.
+impl fmt::Display for SourceVersion {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let parts = self.components();
+    let len = (2..5).rev().find(|&i| parts[i] != 0).map_or(2, |i| i + 1);
+    let strs: Vec<String> =
+      parts[..len].iter().map(|p| p.to_string()).collect();
+    write!(f, "{}", strs.join("."))
+  }
+}
+
+pub fn put_u8(buf: &mut Vec<u8>, val: u8) {
+  buf.push(val);
+}
+
+pub fn put_u16(buf: &mut Vec<u8>, val: u16) {
+  buf.push(val as u8);
+  buf.push((val >> 8) as u8);
+}
+
+pub fn put_u32(buf: &mut Vec<u8>, val: u32) {
+  for i in 0..4 {
+    buf.push((val >> (i * 8)) as u8);
+  }
+}
+
+pub fn put_u64(buf: &mut Vec<u8>, val: u64) {
+  for i in 0..8 {
+    buf.push((val >> (i * 8)) as u8);
+  }
+}
+
+pub fn set_u64(buf: &mut [u8], offset: usize, val: u64) {
+  for i in 0..8 {
+    buf[offset + i] = (val >> (i * 8)) as u8;
+  }
+}
+
+pub fn set_u32(buf: &mut [u8], offset: usize, val: u32) {
+  for i in 0..4 {
+    buf[offset + i] = (val >> (i * 8)) as u8;
+  }
+}
+
+pub fn put_uleb128(buf: &mut Vec<u8>, val: u64) {
+  let mut val = val;
+  loop {
+    let byte = (val & 0x7f) as u8;
+    val >>= 7;
+    if val == 0 {
+      buf.push(byte);
+      break;
+    }
+    buf.push(byte | 0x80);
+  }
+}
+
+pub fn put_sleb128(buf: &mut Vec<u8>, val: i64) {
+  let mut val = val;
+  loop {
+    let byte = (val & 0x7f) as u8;
+    val >>= 7;
+    let sign_bit = byte & 0x40 != 0;
+    if (val == 0 && !sign_bit) || (val == -1 && sign_bit) {
+      buf.push(byte);
+      break;
+    }
+    buf.push(byte | 0x80);
+  }
+}
+
+pub fn uleb128_size(val: u64) -> usize {
+  let mut buf: Vec<u8> = Vec::new();
+  put_uleb128(&mut buf, val);
+  buf.len()
+}
+
+// Pad `buf` with zeros until its length is a multiple of `align`.
+pub fn align_to(buf: &mut Vec<u8>, align: usize) {
+  while buf.len() % align != 0 {
+    buf.push(0);
+  }
+}
+
+// Round `val` up to the next multiple of `align`, which must be a power of 2.
+pub fn round_up(val: u64, align: u64) -> u64 {
+  (val + align - 1) & !(align - 1)
+}
+
+// Read `size` little-endian bytes at `offset`.
+pub fn get_le(
+  data: &[u8],
+  offset: usize,
+  size: usize,
+  what: &'static str,
+) -> Result<u64> {
+  if offset.checked_add(size).map_or(true, |end| end > data.len()) {
+    return Err(MachOError::Truncated(what));
+  }
+  let mut val: u64 = 0;
+  for i in 0..size {
+    val |= (data[offset + i] as u64) << (i * 8);
+  }
+  Ok(val)
+}
+
+pub fn get_u16(data: &[u8], offset: usize, what: &'static str) -> Result<u16> {
+  get_le(data, offset, 2, what).map(|val| val as u16)
+}
+
+pub fn get_u32(data: &[u8], offset: usize, what: &'static str) -> Result<u32> {
+  get_le(data, offset, 4, what).map(|val| val as u32)
+}
+
+pub fn get_u64(data: &[u8], offset: usize, what: &'static str) -> Result<u64> {
+  get_le(data, offset, 8, what)
+}
+
+// A fixed 16-byte, NUL-padded segment or section name.
+pub fn get_name16(
+  data: &[u8],
+  offset: usize,
+  what: &'static str,
+) -> Result<String> {
+  let bytes = offset
+    .checked_add(16)
+    .and_then(|end| data.get(offset..end))
+    .ok_or(MachOError::Truncated(what))?;
+  let len = bytes.iter().position(|b| *b == 0).unwrap_or(16);
+  Ok(String::from_utf8_lossy(&bytes[..len]).into_owned())
+}
+
+// Read a uleb128 at `*offset`, advancing past it.
+pub fn get_uleb128(
+  data: &[u8],
+  offset: &mut usize,
+  what: &'static str,
+) -> Result<u64> {
+  let mut val: u64 = 0;
+  let mut shift = 0;
+  loop {
+    let byte = *data.get(*offset).ok_or(MachOError::Truncated(what))?;
+    *offset += 1;
+    if shift >= 64 {
+      return Err(MachOError::FieldOverflow(what, val));
+    }
+    val |= ((byte & 0x7f) as u64) << shift;
+    shift += 7;
+    if byte & 0x80 == 0 {
+      return Ok(val);
+    }
+  }
+}
+
+// Read a sleb128 at `*offset`, advancing past it.
+pub fn get_sleb128(
+  data: &[u8],
+  offset: &mut usize,
+  what: &'static str,
+) -> Result<i64> {
+  let mut val: i64 = 0;
+  let mut shift = 0;
+  loop {
+    let byte = *data.get(*offset).ok_or(MachOError::Truncated(what))?;
+    *offset += 1;
+    if shift >= 64 {
+      return Err(MachOError::FieldOverflow(what, val as u64));
+    }
+    val |= ((byte & 0x7f) as i64) << shift;
+    shift += 7;
+    if byte & 0x80 == 0 {
+      if shift < 64 && byte & 0x40 != 0 {
+        val |= -1 << shift;
+      }
+      return Ok(val);
+    }
+  }
+}
+
+// Read a NUL-terminated string at `*offset`, advancing past the NUL.
+pub fn get_cstr(
+  data: &[u8],
+  offset: &mut usize,
+  what: &'static str,
+) -> Result<String> {
+  let start = *offset;
+  let len = data
+    .get(start..)
+    .and_then(|rest| rest.iter().position(|b| *b == 0))
+    .ok_or(MachOError::Truncated(what))?;
+  *offset = start + len + 1;
+  Ok(String::from_utf8_lossy(&data[start..(start + len)]).into_owned())
+}
@@ -0,0 +1,605 @@
+use header::{header_size, parse_magic};
+use {get_name16, get_u32, get_u64, next_command, put_u32, put_u64, set_u32,
+     Arch, MachOError, Platform, Result, SourceVersion, Version,
+     CPU_TYPE_I386, CPU_TYPE_X86_64, LC_REQ_DYLD};
+
+pub const LC_SEGMENT: u32 = 0x1;
+pub const LC_SYMTAB: u32 = 0x2;
+pub const LC_UNIXTHREAD: u32 = 0x5;
+pub const LC_DYSYMTAB: u32 = 0xb;
+pub const LC_LOAD_DYLIB: u32 = 0xc;
+pub const LC_ID_DYLIB: u32 = 0xd;
+pub const LC_LOAD_DYLINKER: u32 = 0xe;
+pub const LC_LOAD_WEAK_DYLIB: u32 = 0x18 | LC_REQ_DYLD;
+pub const LC_SUB_FRAMEWORK: u32 = 0x12;
+pub const LC_SUB_UMBRELLA: u32 = 0x13;
+pub const LC_SUB_CLIENT: u32 = 0x14;
+pub const LC_SUB_LIBRARY: u32 = 0x15;
+pub const LC_SEGMENT_64: u32 = 0x19;
+pub const LC_UUID: u32 = 0x1b;
+pub const LC_RPATH: u32 = 0x1c | LC_REQ_DYLD;
+pub const LC_CODE_SIGNATURE: u32 = 0x1d;
+pub const LC_SEGMENT_SPLIT_INFO: u32 = 0x1e;
+pub const LC_REEXPORT_DYLIB: u32 = 0x1f | LC_REQ_DYLD;
+pub const LC_ENCRYPTION_INFO: u32 = 0x21;
+pub const LC_DYLD_INFO: u32 = 0x22;
+pub const LC_DYLD_INFO_ONLY: u32 = 0x22 | LC_REQ_DYLD;
+pub const LC_LOAD_UPWARD_DYLIB: u32 = 0x23 | LC_REQ_DYLD;
+pub const LC_VERSION_MIN_MACOSX: u32 = 0x24;
+pub const LC_VERSION_MIN_IPHONEOS: u32 = 0x25;
+pub const LC_FUNCTION_STARTS: u32 = 0x26;
+pub const LC_DYLD_ENVIRONMENT: u32 = 0x27;
+pub const LC_MAIN: u32 = 0x28 | LC_REQ_DYLD;
+pub const LC_DATA_IN_CODE: u32 = 0x29;
+pub const LC_SOURCE_VERSION: u32 = 0x2a;
+pub const LC_ENCRYPTION_INFO_64: u32 = 0x2c;
+pub const LC_LINKER_OPTION: u32 = 0x2d;
+pub const LC_VERSION_MIN_TVOS: u32 = 0x2f;
+pub const LC_VERSION_MIN_WATCHOS: u32 = 0x30;
+pub const LC_LINKER_OPTIMIZATION_HINT: u32 = 0x2e;
+pub const LC_NOTE: u32 = 0x31;
+pub const LC_BUILD_VERSION: u32 = 0x32;
+pub const LC_DYLD_EXPORTS_TRIE: u32 = 0x33 | LC_REQ_DYLD;
+pub const LC_DYLD_CHAINED_FIXUPS: u32 = 0x34 | LC_REQ_DYLD;
+
+// Load commands are always padded to 8 bytes in 64-bit images. 32-bit
+// images only need 4, which matters for LC_SEGMENT: its section headers
+// are 68 bytes each, and nothing may follow them.
+const LOAD_COMMAND_ALIGN: usize = 8;
+const LOAD_COMMAND_ALIGN_32: usize = 4;
+
+// Write a segment or section name, which are fixed 16-byte fields padded
+// with zeros.
+fn put_name16(buf: &mut Vec<u8>, name: &str) {
+  let bytes = name.as_bytes();
+  for i in 0..16 {
+    buf.push(if i < bytes.len() { bytes[i] } else { 0 });
+  }
+}
+
+// Write the string at the end of a command which has an lc_str offset to
+// it, NUL-terminated. The caller pads the command afterwards.
+fn put_lc_str(buf: &mut Vec<u8>, s: &str) {
+  buf.extend_from_slice(s.as_bytes());
+  buf.push(0);
+}
+
+// struct section_64
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Section64 {
+  pub sectname: String,
+  pub segname: String,
+  pub addr: u64,
+  pub size: u64,
+  pub offset: u32,
+  // log2 of the alignment.
+  pub align: u32,
+  pub reloff: u32,
+  pub nreloc: u32,
+  pub flags: u32,
+  pub reserved1: u32,
+  pub reserved2: u32,
+  pub reserved3: u32,
+}
+
+impl Section64 {
+  fn parse(data: &[u8], offset: usize) -> Result<Section64> {
+    let what = "section_64";
+    Ok(Section64 {
+      sectname: get_name16(data, offset, what)?,
+      segname: get_name16(data, offset + 16, what)?,
+      addr: get_u64(data, offset + 32, what)?,
+      size: get_u64(data, offset + 40, what)?,
+      offset: get_u32(data, offset + 48, what)?,
+      align: get_u32(data, offset + 52, what)?,
+      reloff: get_u32(data, offset + 56, what)?,
+      nreloc: get_u32(data, offset + 60, what)?,
+      flags: get_u32(data, offset + 64, what)?,
+      reserved1: get_u32(data, offset + 68, what)?,
+      reserved2: get_u32(data, offset + 72, what)?,
+      reserved3: get_u32(data, offset + 76, what)?,
+    })
+  }
+
+  // struct section, which has 32-bit addr and size and no reserved3.
+  fn parse_32(data: &[u8], offset: usize) -> Result<Section64> {
+    let what = "section";
+    Ok(Section64 {
+      sectname: get_name16(data, offset, what)?,
+      segname: get_name16(data, offset + 16, what)?,
+      addr: get_u32(data, offset + 32, what)? as u64,
+      size: get_u32(data, offset + 36, what)? as u64,
+      offset: get_u32(data, offset + 40, what)?,
+      align: get_u32(data, offset + 44, what)?,
+      reloff: get_u32(data, offset + 48, what)?,
+      nreloc: get_u32(data, offset + 52, what)?,
+      flags: get_u32(data, offset + 56, what)?,
+      reserved1: get_u32(data, offset + 60, what)?,
+      reserved2: get_u32(data, offset + 64, what)?,
+      reserved3: 0,
+    })
+  }
+
+  fn write(&self, buf: &mut Vec<u8>) {
+    put_name16(buf, &self.sectname);
+    put_name16(buf, &self.segname);
+    put_u64(buf, self.addr);
+    put_u64(buf, self.size);
+    put_u32(buf, self.offset);
+    put_u32(buf, self.align);
+    put_u32(buf, self.reloff);
+    put_u32(buf, self.nreloc);
+    put_u32(buf, self.flags);
+    put_u32(buf, self.reserved1);
+    put_u32(buf, self.reserved2);
+    put_u32(buf, self.reserved3);
+  }
+
+  fn write_32(&self, buf: &mut Vec<u8>) {
+    put_name16(buf, &self.sectname);
+    put_name16(buf, &self.segname);
+    put_u32(buf, self.addr as u32);
+    put_u32(buf, self.size as u32);
+    put_u32(buf, self.offset);
+    put_u32(buf, self.align);
+    put_u32(buf, self.reloff);
+    put_u32(buf, self.nreloc);
+    put_u32(buf, self.flags);
+    put_u32(buf, self.reserved1);
+    put_u32(buf, self.reserved2);
+  }
+}
+
+// struct dysymtab_command. The symbol table is partitioned into locals,
+// then defined externals, then undefined externals. The table of contents
+// and module table are for formats we never write, and are written as zero;
+// the relocations are only for kexts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Dysymtab {
+  pub ilocalsym: u32,
+  pub nlocalsym: u32,
+  pub iextdefsym: u32,
+  pub nextdefsym: u32,
+  pub iundefsym: u32,
+  pub nundefsym: u32,
+  pub indirectsymoff: u32,
+  pub nindirectsyms: u32,
+  pub extreloff: u32,
+  pub nextrel: u32,
+  pub locreloff: u32,
+  pub nlocrel: u32,
+}
+
+// struct dyld_info_command: where the rebase, bind, weak bind, lazy bind
+// and export parts are in __LINKEDIT. Empty parts have offset 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DyldInfo {
+  pub rebase_off: u32,
+  pub rebase_size: u32,
+  pub bind_off: u32,
+  pub bind_size: u32,
+  pub weak_bind_off: u32,
+  pub weak_bind_size: u32,
+  pub lazy_bind_off: u32,
+  pub lazy_bind_size: u32,
+  pub export_off: u32,
+  pub export_size: u32,
+}
+
+// struct segment_command_64, along with its sections.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Segment64 {
+  pub segname: String,
+  pub vmaddr: u64,
+  pub vmsize: u64,
+  pub fileoff: u64,
+  pub filesize: u64,
+  pub maxprot: u32,
+  pub initprot: u32,
+  pub flags: u32,
+  pub sections: Vec<Section64>,
+}
+
+// sizeof(struct segment_command_64), sizeof(struct section_64), and their
+// 32-bit counterparts.
+pub const SEGMENT_COMMAND_64_SIZE: usize = 72;
+pub const SECTION_64_SIZE: usize = 80;
+pub const SEGMENT_COMMAND_SIZE: usize = 56;
+pub const SECTION_SIZE: usize = 68;
+
+impl Segment64 {
+  // The LC_SEGMENT_64 command at `offset`, with its section headers.
+  pub fn parse(data: &[u8], offset: usize) -> Result<Segment64> {
+    let what = "segment_command_64";
+    let mut seg = Segment64 {
+      segname: get_name16(data, offset + 8, what)?,
+      vmaddr: get_u64(data, offset + 24, what)?,
+      vmsize: get_u64(data, offset + 32, what)?,
+      fileoff: get_u64(data, offset + 40, what)?,
+      filesize: get_u64(data, offset + 48, what)?,
+      maxprot: get_u32(data, offset + 56, what)?,
+      initprot: get_u32(data, offset + 60, what)?,
+      flags: get_u32(data, offset + 68, what)?,
+      sections: Vec::new(),
+    };
+    let nsects = get_u32(data, offset + 64, what)? as usize;
+    for i in 0..nsects {
+      let sect_offset = offset + SEGMENT_COMMAND_64_SIZE + i * SECTION_64_SIZE;
+      seg.sections.push(Section64::parse(data, sect_offset)?);
+    }
+    Ok(seg)
+  }
+
+  // The LC_SEGMENT command at `offset`, widened.
+  pub fn parse_32(data: &[u8], offset: usize) -> Result<Segment64> {
+    let what = "segment_command";
+    let mut seg = Segment64 {
+      segname: get_name16(data, offset + 8, what)?,
+      vmaddr: get_u32(data, offset + 24, what)? as u64,
+      vmsize: get_u32(data, offset + 28, what)? as u64,
+      fileoff: get_u32(data, offset + 32, what)? as u64,
+      filesize: get_u32(data, offset + 36, what)? as u64,
+      maxprot: get_u32(data, offset + 40, what)?,
+      initprot: get_u32(data, offset + 44, what)?,
+      flags: get_u32(data, offset + 52, what)?,
+      sections: Vec::new(),
+    };
+    let nsects = get_u32(data, offset + 48, what)? as usize;
+    for i in 0..nsects {
+      let sect_offset = offset + SEGMENT_COMMAND_SIZE + i * SECTION_SIZE;
+      seg.sections.push(Section64::parse_32(data, sect_offset)?);
+    }
+    Ok(seg)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadCommand {
+  Segment64(Segment64),
+  // LC_SEGMENT, for 32-bit architectures. The addresses and sizes have to
+  // fit in 32 bits.
+  Segment32(Segment64),
+  // Any of the commands which just point at a blob of data in __LINKEDIT
+  // (struct linkedit_data_command).
+  LinkeditData {
+    cmd: u32,
+    dataoff: u32,
+    datasize: u32,
+  },
+  // LC_ID_DYLIB, LC_LOAD_DYLIB and the other kinds of dylib reference
+  // (struct dylib_command).
+  Dylib {
+    cmd: u32,
+    name: String,
+    timestamp: u32,
+    current_version: Version,
+    compatibility_version: Version,
+  },
+  Rpath { path: String },
+  // LC_SUB_FRAMEWORK, LC_SUB_CLIENT, LC_SUB_UMBRELLA and LC_SUB_LIBRARY,
+  // which all hold a single name.
+  SubName { cmd: u32, name: String },
+  Symtab {
+    symoff: u32,
+    nsyms: u32,
+    stroff: u32,
+    strsize: u32,
+  },
+  Dysymtab(Dysymtab),
+  // LC_DYLD_INFO_ONLY.
+  DyldInfo(DyldInfo),
+  // LC_LOAD_DYLINKER: the dynamic linker which loads an executable.
+  Dylinker { name: String },
+  Uuid([u8; 16]),
+  // struct build_version_command, with its (tool, version) entries.
+  BuildVersion {
+    platform: u32,
+    minos: Version,
+    sdk: Version,
+    tools: Vec<(u32, Version)>,
+  },
+  // LC_VERSION_MIN_*, which older OS versions need instead.
+  VersionMin {
+    cmd: u32,
+    version: Version,
+    sdk: Version,
+  },
+  // struct entry_point_command. `entryoff` is the file offset of main().
+  Main { entryoff: u64, stacksize: u64 },
+  // struct thread_command with a single thread state, which is `state.len()`
+  // 32-bit words.
+  UnixThread { flavor: u32, state: Vec<u32> },
+  SourceVersion(SourceVersion),
+  // LC_ENCRYPTION_INFO or LC_ENCRYPTION_INFO_64: the range of file offsets
+  // which is encrypted, and with what (0 for not yet).
+  EncryptionInfo {
+    cmd: u32,
+    cryptoff: u32,
+    cryptsize: u32,
+    cryptid: u32,
+  },
+  // struct note_command: `size` bytes of data at file offset `offset`, for
+  // whatever tool `data_owner` names.
+  Note {
+    data_owner: String,
+    offset: u64,
+    size: u64,
+  },
+  // Any other command, as the bytes which follow its cmd and cmdsize.
+  Raw { cmd: u32, body: Vec<u8> },
+}
+
+impl LoadCommand {
+  // The segment command for `seg` in `arch`'s container.
+  pub fn segment(arch: Arch, seg: Segment64) -> LoadCommand {
+    if arch.is_64_bit() {
+      LoadCommand::Segment64(seg)
+    } else {
+      LoadCommand::Segment32(seg)
+    }
+  }
+
+  pub fn cmd(&self) -> u32 {
+    match *self {
+      LoadCommand::Segment64(_) => LC_SEGMENT_64,
+      LoadCommand::Segment32(_) => LC_SEGMENT,
+      LoadCommand::LinkeditData { cmd, .. } => cmd,
+      LoadCommand::Dylib { cmd, .. } => cmd,
+      LoadCommand::Rpath { .. } => LC_RPATH,
+      LoadCommand::SubName { cmd, .. } => cmd,
+      LoadCommand::Main { .. } => LC_MAIN,
+      LoadCommand::UnixThread { .. } => LC_UNIXTHREAD,
+      LoadCommand::SourceVersion(_) => LC_SOURCE_VERSION,
+      LoadCommand::Symtab { .. } => LC_SYMTAB,
+      LoadCommand::Dysymtab(_) => LC_DYSYMTAB,
+      LoadCommand::DyldInfo(_) => LC_DYLD_INFO_ONLY,
+      LoadCommand::Dylinker { .. } => LC_LOAD_DYLINKER,
+      LoadCommand::Uuid(_) => LC_UUID,
+      LoadCommand::BuildVersion { .. } => LC_BUILD_VERSION,
+      LoadCommand::VersionMin { cmd, .. } => cmd,
+      LoadCommand::EncryptionInfo { cmd, .. } => cmd,
+      LoadCommand::Note { .. } => LC_NOTE,
+      LoadCommand::Raw { cmd, .. } => cmd,
+    }
+  }
+
+  pub fn cmdsize(&self) -> u32 {
+    let mut buf: Vec<u8> = Vec::new();
+    self.write(&mut buf);
+    buf.len() as u32
+  }
+
+  pub fn write(&self, buf: &mut Vec<u8>) {
+    let start = buf.len();
+    put_u32(buf, self.cmd());
+    // Placeholder for cmdsize, filled in once the body is written.
+    put_u32(buf, 0);
+    match *self {
+      LoadCommand::Segment64(ref seg) => {
+        put_name16(buf, &seg.segname);
+        put_u64(buf, seg.vmaddr);
+        put_u64(buf, seg.vmsize);
+        put_u64(buf, seg.fileoff);
+        put_u64(buf, seg.filesize);
+        put_u32(buf, seg.maxprot);
+        put_u32(buf, seg.initprot);
+        put_u32(buf, seg.sections.len() as u32);
+        put_u32(buf, seg.flags);
+        for sect in seg.sections.iter() {
+          sect.write(buf);
+        }
+      }
+      LoadCommand::Segment32(ref seg) => {
+        put_name16(buf, &seg.segname);
+        put_u32(buf, seg.vmaddr as u32);
+        put_u32(buf, seg.vmsize as u32);
+        put_u32(buf, seg.fileoff as u32);
+        put_u32(buf, seg.filesize as u32);
+        put_u32(buf, seg.maxprot);
+        put_u32(buf, seg.initprot);
+        put_u32(buf, seg.sections.len() as u32);
+        put_u32(buf, seg.flags);
+        for sect in seg.sections.iter() {
+          sect.write_32(buf);
+        }
+      }
+      LoadCommand::LinkeditData {
+        dataoff, datasize, ..
+      } => {
+        put_u32(buf, dataoff);
+        put_u32(buf, datasize);
+      }
+      LoadCommand::Dylib {
+        ref name,
+        timestamp,
+        current_version,
+        compatibility_version,
+        ..
+      } => {
+        // The name follows the fixed part of the command.
+        put_u32(buf, 24);
+        put_u32(buf, timestamp);
+        put_u32(buf, current_version.0);
+        put_u32(buf, compatibility_version.0);
+        put_lc_str(buf, name);
+      }
+      LoadCommand::Rpath { ref path } => {
+        put_u32(buf, 12);
+        put_lc_str(buf, path);
+      }
+      LoadCommand::SubName { ref name, .. }
+      | LoadCommand::Dylinker { ref name } => {
+        put_u32(buf, 12);
+        put_lc_str(buf, name);
+      }
+      LoadCommand::Main {
+        entryoff,
+        stacksize,
+      } => {
+        put_u64(buf, entryoff);
+        put_u64(buf, stacksize);
+      }
+      LoadCommand::SourceVersion(version) => put_u64(buf, version.0),
+      LoadCommand::UnixThread { flavor, ref state } => {
+        put_u32(buf, flavor);
+        put_u32(buf, state.len() as u32);
+        for &word in state.iter() {
+          put_u32(buf, word);
+        }
+      }
+      LoadCommand::Symtab {
+        symoff,
+        nsyms,
+        stroff,
+        strsize,
+      } => {
+        put_u32(buf, symoff);
+        put_u32(buf, nsyms);
+        put_u32(buf, stroff);
+        put_u32(buf, strsize);
+      }
+      LoadCommand::DyldInfo(ref info) => {
+        put_u32(buf, info.rebase_off);
+        put_u32(buf, info.rebase_size);
+        put_u32(buf, info.bind_off);
+        put_u32(buf, info.bind_size);
+        put_u32(buf, info.weak_bind_off);
+        put_u32(buf, info.weak_bind_size);
+        put_u32(buf, info.lazy_bind_off);
+        put_u32(buf, info.lazy_bind_size);
+        put_u32(buf, info.export_off);
+        put_u32(buf, info.export_size);
+      }
+      LoadCommand::Uuid(ref uuid) => buf.extend_from_slice(uuid),
+      LoadCommand::BuildVersion {
+        platform,
+        minos,
+        sdk,
+        ref tools,
+      } => {
+        put_u32(buf, platform);
+        put_u32(buf, minos.0);
+        put_u32(buf, sdk.0);
+        put_u32(buf, tools.len() as u32);
+        for &(tool, version) in tools.iter() {
+          put_u32(buf, tool);
+          put_u32(buf, version.0);
+        }
+      }
+      LoadCommand::VersionMin { version, sdk, .. } => {
+        put_u32(buf, version.0);
+        put_u32(buf, sdk.0);
+      }
+      LoadCommand::EncryptionInfo {
+        cmd,
+        cryptoff,
+        cryptsize,
+        cryptid,
+      } => {
+        put_u32(buf, cryptoff);
+        put_u32(buf, cryptsize);
+        put_u32(buf, cryptid);
+        if cmd == LC_ENCRYPTION_INFO_64 {
+          // pad
+          put_u32(buf, 0);
+        }
+      }
+      LoadCommand::Note {
+        ref data_owner,
+        offset,
+        size,
+      } => {
+        put_name16(buf, data_owner);
+        put_u64(buf, offset);
+        put_u64(buf, size);
+      }
+      LoadCommand::Raw { ref body, .. } => buf.extend_from_slice(body),
+      LoadCommand::Dysymtab(ref dysymtab) => {
+        put_u32(buf, dysymtab.ilocalsym);
+        put_u32(buf, dysymtab.nlocalsym);
+        put_u32(buf, dysymtab.iextdefsym);
+        put_u32(buf, dysymtab.nextdefsym);
+        put_u32(buf, dysymtab.iundefsym);
+        put_u32(buf, dysymtab.nundefsym);
+        // tocoff, ntoc, modtaboff, nmodtab, extrefsymoff, nextrefsyms
+        for _ in 0..6 {
+          put_u32(buf, 0);
+        }
+        put_u32(buf, dysymtab.indirectsymoff);
+        put_u32(buf, dysymtab.nindirectsyms);
+        put_u32(buf, dysymtab.extreloff);
+        put_u32(buf, dysymtab.nextrel);
+        put_u32(buf, dysymtab.locreloff);
+        put_u32(buf, dysymtab.nlocrel);
+      }
+    }
+    let align = match *self {
+      LoadCommand::Segment32(_) => LOAD_COMMAND_ALIGN_32,
+      _ => LOAD_COMMAND_ALIGN,
+    };
+    while (buf.len() - start) % align != 0 {
+      buf.push(0);
+    }
+    let size = (buf.len() - start) as u32;
+    set_u32(buf, start + 4, size);
+  }
+}
+
+// The platform and minimum OS version an input declares with the load
+// command at `offset`, if it is LC_BUILD_VERSION or LC_VERSION_MIN_*.
+pub fn parse_platform(
+  data: &[u8],
+  offset: usize,
+) -> Result<Option<(Platform, Version)>> {
+  let cmd = get_u32(data, offset, "load command")?;
+  let (number, version_offset) = match cmd {
+    LC_BUILD_VERSION => (get_u32(data, offset + 8, "build_version")?, 12),
+    LC_VERSION_MIN_MACOSX => (1, 8),
+    LC_VERSION_MIN_IPHONEOS => (2, 8),
+    LC_VERSION_MIN_TVOS => (3, 8),
+    LC_VERSION_MIN_WATCHOS => (4, 8),
+    _ => return Ok(None),
+  };
+  let version = Version(get_u32(data, offset + version_offset, "min os")?);
+  // Before LC_BUILD_VERSION, simulator builds used the device's
+  // LC_VERSION_MIN_* command, and were told apart by being for Intel.
+  let cputype = get_u32(data, 4, "mach header")?;
+  let intel = cputype == CPU_TYPE_X86_64 || cputype == CPU_TYPE_I386;
+  let number = match number {
+    2 if intel && cmd == LC_VERSION_MIN_IPHONEOS => 7,
+    3 if intel && cmd == LC_VERSION_MIN_TVOS => 8,
+    4 if intel && cmd == LC_VERSION_MIN_WATCHOS => 9,
+    number => number,
+  };
+  Ok(Platform::from_number(number).map(|platform| (platform, version)))
+}
+
+// Where a load command is, for tools which list them all rather than look
+// for particular ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadCommandHeader {
+  pub cmd: u32,
+  pub cmdsize: u32,
+  pub offset: usize,
+}
+
+// Every load command in a thin Mach-O file, in order.
+pub fn load_commands(data: &[u8]) -> Result<Vec<LoadCommandHeader>> {
+  let is_64_bit = parse_magic(data)?;
+  let ncmds = get_u32(data, 16, "mach header")?;
+  let mut commands: Vec<LoadCommandHeader> = Vec::new();
+  let mut offset = header_size(is_64_bit) as usize;
+  for _ in 0..ncmds {
+    let cmdsize = get_u32(data, offset + 4, "load command")?;
+    if cmdsize < 8 {
+      return Err(MachOError::Malformed("load command smaller than 8"));
+    }
+    commands.push(LoadCommandHeader {
+      cmd: get_u32(data, offset, "load command")?,
+      cmdsize: cmdsize,
+      offset: offset,
+    });
+    offset = next_command(offset, cmdsize as usize)?;
+  }
+  Ok(commands)
+}
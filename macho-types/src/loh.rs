@@ -0,0 +1,213 @@
+// Linker optimization hints (LC_LINKER_OPTIMIZATION_HINT), which arm64
+// compilers emit for sequences of instructions computing an address with
+// `adrp`. Once the layout is known, sequences whose target turns out to be
+// close enough are relaxed: into an `adr`, or a pc-relative literal load,
+// and `nop`s.
+//
+// The data is a list of ULEB128s: a kind, the number of instructions, and
+// then their addresses, for each hint; padded with zeros to 8 bytes.
+
+use reloc::{read_u32, write_u32};
+use {get_uleb128, put_uleb128, Result};
+
+pub const LOH_ARM64_ADRP_ADRP: u64 = 1;
+pub const LOH_ARM64_ADRP_LDR: u64 = 2;
+pub const LOH_ARM64_ADRP_ADD_LDR: u64 = 3;
+pub const LOH_ARM64_ADRP_LDR_GOT_LDR: u64 = 4;
+pub const LOH_ARM64_ADRP_ADD_STR: u64 = 5;
+pub const LOH_ARM64_ADRP_LDR_GOT_STR: u64 = 6;
+pub const LOH_ARM64_ADRP_ADD: u64 = 7;
+pub const LOH_ARM64_ADRP_LDR_GOT: u64 = 8;
+
+const NOP: u32 = 0xd503_201f;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loh {
+  pub kind: u64,
+  pub addresses: Vec<u64>,
+}
+
+pub fn parse(data: &[u8]) -> Result<Vec<Loh>> {
+  let mut hints: Vec<Loh> = Vec::new();
+  let mut cur: usize = 0;
+  while cur < data.len() {
+    let kind = get_uleb128(data, &mut cur, "optimization hint")?;
+    // The padding at the end.
+    if kind == 0 {
+      break;
+    }
+    let count = get_uleb128(data, &mut cur, "optimization hint")?;
+    let mut addresses: Vec<u64> = Vec::new();
+    for _ in 0..count {
+      addresses.push(get_uleb128(data, &mut cur, "optimization hint")?);
+    }
+    hints.push(Loh {
+      kind: kind,
+      addresses: addresses,
+    });
+  }
+  Ok(hints)
+}
+
+pub fn write(hints: &[Loh]) -> Vec<u8> {
+  let mut buf: Vec<u8> = Vec::new();
+  for hint in hints.iter() {
+    put_uleb128(&mut buf, hint.kind);
+    put_uleb128(&mut buf, hint.addresses.len() as u64);
+    for &addr in hint.addresses.iter() {
+      put_uleb128(&mut buf, addr);
+    }
+  }
+  while buf.len() % 8 != 0 {
+    buf.push(0);
+  }
+  buf
+}
+
+fn sign_extend(val: u64, bits: u32) -> i64 {
+  ((val << (64 - bits)) as i64) >> (64 - bits)
+}
+
+fn fits_signed(val: i64, bits: u32) -> bool {
+  val >= -(1 << (bits - 1)) && val < (1 << (bits - 1))
+}
+
+fn rd(insn: u32) -> u32 {
+  insn & 0x1f
+}
+
+fn rn(insn: u32) -> u32 {
+  (insn >> 5) & 0x1f
+}
+
+// The page an `adrp` at `pc` computes, if `insn` is one.
+fn adrp_target(insn: u32, pc: u64) -> Option<u64> {
+  if insn & 0x9f00_0000 != 0x9000_0000 {
+    return None;
+  }
+  let imm = ((insn >> 5) & 0x7ffff) << 2 | (insn >> 29) & 0x3;
+  let delta = sign_extend(imm as u64, 21) << 12;
+  Some((pc & !0xfff).wrapping_add(delta as u64))
+}
+
+// The immediate of an unshifted `add xd, xn, #imm`.
+fn add_imm(insn: u32) -> Option<u64> {
+  if insn & 0xffc0_0000 != 0x9100_0000 {
+    return None;
+  }
+  Some(((insn >> 10) & 0xfff) as u64)
+}
+
+// The scaled offset of an integer load or store with an unsigned
+// immediate, and whether it's a load of a 32- or 64-bit register, which
+// have literal forms.
+fn load_store_offset(insn: u32) -> Option<(u64, bool)> {
+  if insn & 0x3b00_0000 != 0x3900_0000 {
+    return None;
+  }
+  let size = insn >> 30;
+  let offset = (((insn >> 10) & 0xfff) << size) as u64;
+  let has_literal = insn & 0xbfc0_0000 == 0xb940_0000;
+  Some((offset, has_literal))
+}
+
+// `adr xd, target` at `pc`.
+fn encode_adr(rd: u32, pc: u64, target: u64) -> Option<u32> {
+  let delta = target.wrapping_sub(pc) as i64;
+  if !fits_signed(delta, 21) {
+    return None;
+  }
+  let imm = delta as u32 & 0x1f_ffff;
+  Some(0x1000_0000 | (imm & 0x3) << 29 | (imm >> 2) << 5 | rd)
+}
+
+// The literal form of the load `insn` at `pc`, loading from `target`.
+fn encode_ldr_literal(insn: u32, pc: u64, target: u64) -> Option<u32> {
+  let delta = target.wrapping_sub(pc) as i64;
+  if delta & 0x3 != 0 || !fits_signed(delta, 21) {
+    return None;
+  }
+  let is_64 = insn & 0x4000_0000 != 0;
+  let opcode = if is_64 { 0x5800_0000 } else { 0x1800_0000 };
+  Some(opcode | ((delta >> 2) as u32 & 0x7_ffff) << 5 | rd(insn))
+}
+
+// Relax the instructions `hint` describes in `code`, whose first byte is at
+// address `base`, if their target is close enough. Returns whether
+// anything changed. GOT loads are left alone: relaxing them needs to know
+// whether the symbol is defined in this image.
+pub fn apply(code: &mut [u8], base: u64, hint: &Loh) -> bool {
+  let mut offsets: Vec<usize> = Vec::new();
+  for &addr in hint.addresses.iter() {
+    match addr.checked_sub(base) {
+      Some(off) if off % 4 == 0 && off as usize + 4 <= code.len() => {
+        offsets.push(off as usize)
+      }
+      _ => return false,
+    }
+  }
+  let insns: Vec<u32> =
+    offsets.iter().map(|&off| read_u32(code, off)).collect();
+  let pcs = &hint.addresses;
+  let patched: Option<Vec<u32>> = match (hint.kind, insns.as_slice()) {
+    (LOH_ARM64_ADRP_ADRP, &[first, second]) => {
+      let same_page = adrp_target(first, pcs[0]).is_some()
+        && adrp_target(first, pcs[0]) == adrp_target(second, pcs[1]);
+      if same_page && rd(first) == rd(second) {
+        Some(vec![first, NOP])
+      } else {
+        None
+      }
+    }
+    (LOH_ARM64_ADRP_ADD, &[adrp, add]) => {
+      let page = adrp_target(adrp, pcs[0]);
+      match (page, add_imm(add)) {
+        (Some(page), Some(lo)) if rn(add) == rd(adrp) => {
+          encode_adr(rd(add), pcs[0], page + lo).map(|adr| vec![adr, NOP])
+        }
+        _ => None,
+      }
+    }
+    (LOH_ARM64_ADRP_LDR, &[adrp, ldr]) => {
+      let page = adrp_target(adrp, pcs[0]);
+      match (page, load_store_offset(ldr)) {
+        (Some(page), Some((off, true))) if rn(ldr) == rd(adrp) => {
+          encode_ldr_literal(ldr, pcs[1], page + off)
+            .map(|literal| vec![NOP, literal])
+        }
+        _ => None,
+      }
+    }
+    (LOH_ARM64_ADRP_ADD_LDR, &[adrp, add, mem])
+    | (LOH_ARM64_ADRP_ADD_STR, &[adrp, add, mem]) => {
+      let page = adrp_target(adrp, pcs[0]);
+      let chained = rn(add) == rd(adrp) && rn(mem) == rd(add);
+      match (page, add_imm(add), load_store_offset(mem)) {
+        (Some(page), Some(lo), Some((off, has_literal))) if chained => {
+          let literal = if hint.kind == LOH_ARM64_ADRP_ADD_LDR && has_literal
+          {
+            encode_ldr_literal(mem, pcs[2], page + lo + off)
+          } else {
+            None
+          };
+          match literal {
+            Some(literal) => Some(vec![NOP, NOP, literal]),
+            None => encode_adr(rd(add), pcs[0], page + lo)
+              .map(|adr| vec![adr, NOP, mem]),
+          }
+        }
+        _ => None,
+      }
+    }
+    _ => None,
+  };
+  match patched {
+    Some(patched) => {
+      for (&off, &insn) in offsets.iter().zip(patched.iter()) {
+        write_u32(code, off, insn);
+      }
+      true
+    }
+    None => false,
+  }
+}
@@ -0,0 +1,255 @@
+// Reading relocatable objects (MH_OBJECT): their sections with the
+// relocations against them, and their symbol table.
+
+use data_in_code::{self, DataInCodeEntry};
+use header::{header_size, parse_magic};
+use load_command::{parse_platform, LC_DATA_IN_CODE,
+                   LC_LINKER_OPTIMIZATION_HINT, LC_SEGMENT, LC_SEGMENT_64,
+                   LC_SYMTAB, SECTION_64_SIZE, SECTION_SIZE,
+                   SEGMENT_COMMAND_64_SIZE, SEGMENT_COMMAND_SIZE};
+use loh::{self, Loh};
+use reloc::{RelocationInfo, RELOCATION_INFO_SIZE};
+use symtab::{parse_symtab_with_limits, Nlist, N_SECT, N_TYPE};
+use {check_limit, get_name16, get_u32, get_u64, next_command, CopyBudget,
+     MachOError, ParseLimits, Platform, Result, Version, MH_OBJECT,
+     SECTION_TYPE, S_ATTR_PURE_INSTRUCTIONS, S_ATTR_SOME_INSTRUCTIONS,
+     S_GB_ZEROFILL, S_THREAD_LOCAL_ZEROFILL, S_ZEROFILL};
+
+#[derive(Debug, Clone, Default)]
+pub struct ObjectSection {
+  pub segname: String,
+  pub sectname: String,
+  pub addr: u64,
+  pub size: u64,
+  // log2 of the alignment.
+  pub align: u32,
+  pub flags: u32,
+  pub reserved1: u32,
+  pub reserved2: u32,
+  // Empty for zero-fill sections.
+  pub contents: Vec<u8>,
+  pub relocs: Vec<RelocationInfo>,
+}
+
+impl ObjectSection {
+  pub fn is_zerofill(&self) -> bool {
+    matches!(
+      self.flags & SECTION_TYPE,
+      S_ZEROFILL | S_GB_ZEROFILL | S_THREAD_LOCAL_ZEROFILL
+    )
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ObjectFile {
+  pub cputype: u32,
+  pub cpusubtype: u32,
+  pub flags: u32,
+  // In file order, so section ordinal n is sections[n - 1].
+  pub sections: Vec<ObjectSection>,
+  pub symbols: Vec<Nlist>,
+  // From LC_BUILD_VERSION or LC_VERSION_MIN_*, if present.
+  pub platform: Option<(Platform, Version)>,
+  // Linker optimization hints, at input addresses.
+  pub loh: Vec<Loh>,
+  // Data in code sections, at input addresses.
+  pub data_in_code: Vec<DataInCodeEntry>,
+}
+
+fn slice<'a>(
+  data: &'a [u8],
+  offset: u32,
+  size: u64,
+  what: &'static str,
+) -> Result<&'a [u8]> {
+  let start = offset as usize;
+  let end = (start as u64).checked_add(size);
+  end
+    .filter(|&end| end <= data.len() as u64)
+    .and_then(|end| data.get(start..(end as usize)))
+    .ok_or(MachOError::Truncated(what))
+}
+
+// A section_64, or a 32-bit section, whose fields from `offset` onwards are
+// 8 bytes closer to the start.
+fn parse_section(
+  data: &[u8],
+  offset: usize,
+  is_64_bit: bool,
+  budget: &mut CopyBudget,
+) -> Result<ObjectSection> {
+  let (what, rest) = if is_64_bit {
+    ("section_64", offset.checked_add(48))
+  } else {
+    ("section", offset.checked_add(40))
+  };
+  let rest = rest.ok_or(MachOError::Truncated(what))?;
+  let mut sect = ObjectSection {
+    sectname: get_name16(data, offset, what)?,
+    segname: get_name16(data, offset + 16, what)?,
+    align: get_u32(data, rest + 4, what)?,
+    flags: get_u32(data, rest + 16, what)?,
+    reserved1: get_u32(data, rest + 20, what)?,
+    reserved2: get_u32(data, rest + 24, what)?,
+    ..Default::default()
+  };
+  if is_64_bit {
+    sect.addr = get_u64(data, offset + 32, what)?;
+    sect.size = get_u64(data, offset + 40, what)?;
+  } else {
+    sect.addr = get_u32(data, offset + 32, what)? as u64;
+    sect.size = get_u32(data, offset + 36, what)? as u64;
+  }
+  if !sect.is_zerofill() {
+    let fileoff = get_u32(data, rest, what)?;
+    let contents = slice(data, fileoff, sect.size, "section")?;
+    budget.spend(contents.len() as u64)?;
+    sect.contents = contents.to_vec();
+  }
+  let reloff = get_u32(data, rest + 8, what)?;
+  let nreloc = get_u32(data, rest + 12, what)?;
+  let relocs = slice(
+    data,
+    reloff,
+    nreloc as u64 * RELOCATION_INFO_SIZE as u64,
+    "relocations",
+  )?;
+  budget.spend(relocs.len() as u64)?;
+  sect.relocs = (0..(nreloc as usize))
+    .map(|i| RelocationInfo::parse(relocs, i * RELOCATION_INFO_SIZE))
+    .collect();
+  Ok(sect)
+}
+
+// Whether there's room for another section.
+fn check_sections(obj: &ObjectFile, limits: &ParseLimits) -> Result<()> {
+  let count = obj.sections.len() as u64 + 1;
+  check_limit("sections", count, limits.max_sections as u64)
+}
+
+impl ObjectFile {
+  pub fn parse(data: &[u8]) -> Result<ObjectFile> {
+    ObjectFile::parse_with_limits(data, &ParseLimits::default())
+  }
+
+  pub fn parse_with_limits(
+    data: &[u8],
+    limits: &ParseLimits,
+  ) -> Result<ObjectFile> {
+    let mut budget = CopyBudget::new(limits);
+    let is_64_bit = parse_magic(data)?;
+    let filetype = get_u32(data, 12, "mach header")?;
+    if filetype != MH_OBJECT {
+      return Err(MachOError::WrongFileType(MH_OBJECT, filetype));
+    }
+    let mut obj = ObjectFile {
+      cputype: get_u32(data, 4, "mach header")?,
+      cpusubtype: get_u32(data, 8, "mach header")?,
+      flags: get_u32(data, 24, "mach header")?,
+      ..Default::default()
+    };
+    let ncmds = get_u32(data, 16, "mach header")?;
+    check_limit(
+      "load commands",
+      ncmds as u64,
+      limits.max_load_commands as u64,
+    )?;
+
+    let mut offset = header_size(is_64_bit) as usize;
+    for _ in 0..ncmds {
+      let cmd = get_u32(data, offset, "load command")?;
+      let cmdsize = get_u32(data, offset + 4, "load command")? as usize;
+      if cmdsize < 8 {
+        return Err(MachOError::Malformed("load command smaller than 8"));
+      }
+      if let Some(platform) = parse_platform(data, offset)? {
+        obj.platform = Some(platform);
+      }
+      match cmd {
+        LC_SEGMENT_64 => {
+          let nsects = get_u32(data, offset + 64, "segment_command_64")?;
+          for i in 0..(nsects as usize) {
+            check_sections(&obj, limits)?;
+            let sect_offset =
+              offset + SEGMENT_COMMAND_64_SIZE + i * SECTION_64_SIZE;
+            let sect = parse_section(data, sect_offset, true, &mut budget)?;
+            obj.sections.push(sect);
+          }
+        }
+        LC_SEGMENT => {
+          let nsects = get_u32(data, offset + 48, "segment_command")?;
+          for i in 0..(nsects as usize) {
+            check_sections(&obj, limits)?;
+            let sect_offset = offset + SEGMENT_COMMAND_SIZE + i * SECTION_SIZE;
+            let sect = parse_section(data, sect_offset, false, &mut budget)?;
+            obj.sections.push(sect);
+          }
+        }
+        LC_SYMTAB => {
+          obj.symbols = parse_symtab_with_limits(
+            data,
+            offset,
+            is_64_bit,
+            limits,
+            &mut budget,
+          )?
+        }
+        LC_LINKER_OPTIMIZATION_HINT => {
+          let what = "linkedit_data_command";
+          let dataoff = get_u32(data, offset + 8, what)?;
+          let datasize = get_u32(data, offset + 12, what)?;
+          let hints = slice(data, dataoff, datasize as u64, "hints")?;
+          budget.spend(hints.len() as u64)?;
+          obj.loh = loh::parse(hints)?;
+        }
+        LC_DATA_IN_CODE => {
+          let what = "linkedit_data_command";
+          let dataoff = get_u32(data, offset + 8, what)?;
+          let datasize = get_u32(data, offset + 12, what)?;
+          let entries = slice(data, dataoff, datasize as u64, "data in code")?;
+          budget.spend(entries.len() as u64)?;
+          obj.data_in_code = data_in_code::parse(entries)?;
+        }
+        _ => (),
+      }
+      offset = next_command(offset, cmdsize)?;
+    }
+    Ok(obj)
+  }
+
+  // Where the functions in sections[index] start, as offsets into it: at
+  // the section's start and at each symbol defined in it, which is where
+  // each of its atoms starts. Nothing if it isn't code.
+  pub fn function_starts(&self, index: usize) -> Vec<u64> {
+    let sect = match self.sections.get(index) {
+      Some(sect) => sect,
+      None => return Vec::new(),
+    };
+    let code = S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS;
+    if sect.flags & code == 0 || sect.size == 0 {
+      return Vec::new();
+    }
+    let mut starts: Vec<u64> = vec![0];
+    for sym in self.symbols.iter() {
+      let defined = !sym.is_stab() && sym.n_type & N_TYPE == N_SECT;
+      if defined && sym.n_sect as usize == index + 1
+        && sym.n_value >= sect.addr
+        && sym.n_value < sect.addr + sect.size
+      {
+        starts.push(sym.n_value - sect.addr);
+      }
+    }
+    starts.sort();
+    starts.dedup();
+    starts
+  }
+
+  // The section with 1-based ordinal `n_sect`.
+  pub fn section(&self, n_sect: u8) -> Option<&ObjectSection> {
+    if n_sect == 0 {
+      None
+    } else {
+      self.sections.get(n_sect as usize - 1)
+    }
+  }
+}
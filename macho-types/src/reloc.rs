@@ -0,0 +1,297 @@
+// Relocation types and the instruction patching shared by every relocation
+// we apply.
+
+use Arch;
+
+pub const X86_64_RELOC_UNSIGNED: u8 = 0;
+pub const X86_64_RELOC_SIGNED: u8 = 1;
+pub const X86_64_RELOC_BRANCH: u8 = 2;
+pub const X86_64_RELOC_GOT_LOAD: u8 = 3;
+pub const X86_64_RELOC_GOT: u8 = 4;
+pub const X86_64_RELOC_SUBTRACTOR: u8 = 5;
+pub const X86_64_RELOC_SIGNED_1: u8 = 6;
+pub const X86_64_RELOC_SIGNED_2: u8 = 7;
+pub const X86_64_RELOC_SIGNED_4: u8 = 8;
+pub const X86_64_RELOC_TLV: u8 = 9;
+
+pub const ARM64_RELOC_UNSIGNED: u8 = 0;
+pub const ARM64_RELOC_SUBTRACTOR: u8 = 1;
+pub const ARM64_RELOC_BRANCH26: u8 = 2;
+pub const ARM64_RELOC_PAGE21: u8 = 3;
+pub const ARM64_RELOC_PAGEOFF12: u8 = 4;
+pub const ARM64_RELOC_GOT_LOAD_PAGE21: u8 = 5;
+pub const ARM64_RELOC_GOT_LOAD_PAGEOFF12: u8 = 6;
+pub const ARM64_RELOC_POINTER_TO_GOT: u8 = 7;
+pub const ARM64_RELOC_TLVP_LOAD_PAGE21: u8 = 8;
+pub const ARM64_RELOC_TLVP_LOAD_PAGEOFF12: u8 = 9;
+pub const ARM64_RELOC_ADDEND: u8 = 10;
+// arm64e only.
+pub const ARM64_RELOC_AUTHENTICATED_POINTER: u8 = 11;
+
+pub const GENERIC_RELOC_VANILLA: u8 = 0;
+pub const GENERIC_RELOC_PAIR: u8 = 1;
+pub const GENERIC_RELOC_SECTDIFF: u8 = 2;
+pub const GENERIC_RELOC_PB_LA_PTR: u8 = 3;
+pub const GENERIC_RELOC_LOCAL_SECTDIFF: u8 = 4;
+pub const GENERIC_RELOC_TLV: u8 = 5;
+
+pub const ARM_RELOC_VANILLA: u8 = 0;
+pub const ARM_RELOC_PAIR: u8 = 1;
+pub const ARM_RELOC_SECTDIFF: u8 = 2;
+pub const ARM_RELOC_LOCAL_SECTDIFF: u8 = 3;
+pub const ARM_RELOC_PB_LA_PTR: u8 = 4;
+pub const ARM_RELOC_BR24: u8 = 5;
+pub const ARM_THUMB_RELOC_BR22: u8 = 6;
+pub const ARM_THUMB_32BIT_BRANCH: u8 = 7;
+pub const ARM_RELOC_HALF: u8 = 8;
+pub const ARM_RELOC_HALF_SECTDIFF: u8 = 9;
+
+// Set in the first word of a scattered relocation_info.
+pub const R_SCATTERED: u32 = 0x8000_0000;
+
+#[derive(Debug)]
+pub enum RelocError {
+  // (what overflowed, the value which didn't fit)
+  Overflow(&'static str, i64),
+  // The instruction at the fixup location isn't one the relocation can
+  // apply to: (offset, instruction bytes).
+  UnexpectedInstruction(usize, u32),
+  Misaligned(&'static str, u64),
+}
+
+pub type Result<T> = ::std::result::Result<T, RelocError>;
+
+// sizeof(struct relocation_info)
+pub const RELOCATION_INFO_SIZE: usize = 8;
+
+// struct relocation_info, unpacked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelocationInfo {
+  // Offset of the fixup from the start of its section.
+  pub address: u32,
+  // A symbol table index if `external`, otherwise a 1-based section ordinal.
+  // For ARM64_RELOC_ADDEND, the addend itself.
+  pub symbolnum: u32,
+  pub pcrel: bool,
+  // log2 of the fixup size in bytes.
+  pub length: u8,
+  pub external: bool,
+  pub kind: u8,
+  // For a scattered relocation (i386 and 32-bit arm only), the address of
+  // the target, which takes the place of symbolnum. `address` then only has
+  // 24 bits.
+  pub scattered: Option<u32>,
+}
+
+impl RelocationInfo {
+  pub fn parse(data: &[u8], offset: usize) -> RelocationInfo {
+    let address = read_u32(data, offset);
+    let info = read_u32(data, offset + 4);
+    if address & R_SCATTERED != 0 {
+      // struct scattered_relocation_info
+      return RelocationInfo {
+        address: address & 0x00ff_ffff,
+        symbolnum: 0,
+        pcrel: (address >> 30) & 1 != 0,
+        length: ((address >> 28) & 3) as u8,
+        external: false,
+        kind: ((address >> 24) & 0xf) as u8,
+        scattered: Some(info),
+      };
+    }
+    RelocationInfo {
+      address: address,
+      symbolnum: info & 0x00ff_ffff,
+      pcrel: (info >> 24) & 1 != 0,
+      length: ((info >> 25) & 3) as u8,
+      external: (info >> 27) & 1 != 0,
+      kind: (info >> 28) as u8,
+      scattered: None,
+    }
+  }
+
+  pub fn write(&self, buf: &mut Vec<u8>) {
+    let words = match self.scattered {
+      Some(value) => [
+        R_SCATTERED
+          | ((self.pcrel as u32) << 30)
+          | (((self.length & 3) as u32) << 28)
+          | (((self.kind & 0xf) as u32) << 24)
+          | (self.address & 0x00ff_ffff),
+        value,
+      ],
+      None => [
+        self.address,
+        (self.symbolnum & 0x00ff_ffff)
+          | ((self.pcrel as u32) << 24)
+          | (((self.length & 3) as u32) << 25)
+          | ((self.external as u32) << 27)
+          | (((self.kind & 0xf) as u32) << 28),
+      ],
+    };
+    for val in words.iter() {
+      for i in 0..4 {
+        buf.push((*val >> (i * 8)) as u8);
+      }
+    }
+  }
+}
+
+pub fn read_u32(code: &[u8], offset: usize) -> u32 {
+  (0..4).fold(0, |acc, i| acc | ((code[offset + i] as u32) << (i * 8)))
+}
+
+pub fn write_u32(code: &mut [u8], offset: usize, val: u32) {
+  for i in 0..4 {
+    code[offset + i] = (val >> (i * 8)) as u8;
+  }
+}
+
+fn check_signed(what: &'static str, val: i64, bits: u32) -> Result<i64> {
+  let min = -(1i64 << (bits - 1));
+  let max = (1i64 << (bits - 1)) - 1;
+  if val < min || val > max {
+    Err(RelocError::Overflow(what, val))
+  } else {
+    Ok(val)
+  }
+}
+
+// Write a 32-bit pc-relative displacement, where `pc` is the address just
+// past the end of the instruction (where x86_64 displacements are measured
+// from).
+pub fn write_rel32(
+  code: &mut [u8],
+  offset: usize,
+  pc: u64,
+  target: u64,
+) -> Result<()> {
+  let disp = check_signed("rel32", target.wrapping_sub(pc) as i64, 32)?;
+  write_u32(code, offset, disp as u32);
+  Ok(())
+}
+
+// Whether `kind` is the second half of a pair: the PAIR which follows a
+// SECTDIFF (holding the subtracted address) or an ARM_RELOC_HALF.
+pub fn is_pair(arch: Arch, kind: u8) -> bool {
+  match arch {
+    Arch::I386 => kind == GENERIC_RELOC_PAIR,
+    Arch::Armv7 | Arch::Armv7s => kind == ARM_RELOC_PAIR,
+    _ => false,
+  }
+}
+
+// Whether `kind` is a SECTDIFF or LOCAL_SECTDIFF, whose fixup holds the
+// difference of its target and its pair's.
+pub fn is_sectdiff(arch: Arch, kind: u8) -> bool {
+  match arch {
+    Arch::I386 => {
+      kind == GENERIC_RELOC_SECTDIFF || kind == GENERIC_RELOC_LOCAL_SECTDIFF
+    }
+    Arch::Armv7 | Arch::Armv7s => {
+      kind == ARM_RELOC_SECTDIFF || kind == ARM_RELOC_LOCAL_SECTDIFF
+    }
+    _ => false,
+  }
+}
+
+// Move the target of the arm `b`/`bl` at `offset` by `delta` bytes.
+pub fn adjust_br24(code: &mut [u8], offset: usize, delta: i64) -> Result<()> {
+  let insn = read_u32(code, offset);
+  // imm24 counts words, signed.
+  let imm = (((insn & 0x00ff_ffff) << 8) as i32 >> 6) as i64;
+  let imm = check_signed("br24", imm + delta, 26)?;
+  if imm & 3 != 0 {
+    return Err(RelocError::Misaligned("br24", imm as u64));
+  }
+  let imm24 = (imm >> 2) as u32 & 0x00ff_ffff;
+  write_u32(code, offset, (insn & 0xff00_0000) | imm24);
+  Ok(())
+}
+
+// Move the target of the thumb-2 `bl`/`blx` at `offset` by `delta` bytes.
+// The offset is split across both halfwords as S:I1:I2:imm10:imm11, where
+// I1 and I2 are stored inverted and xored with S, as J1 and J2.
+pub fn adjust_thumb_br22(
+  code: &mut [u8],
+  offset: usize,
+  delta: i64,
+) -> Result<()> {
+  let insn = read_u32(code, offset);
+  let (hi, lo) = (insn & 0xffff, insn >> 16);
+  let s = (hi >> 10) & 1;
+  let i1 = !((lo >> 13) ^ s) & 1;
+  let i2 = !((lo >> 11) ^ s) & 1;
+  let raw = (s << 24) | (i1 << 23) | (i2 << 22) | ((hi & 0x3ff) << 12)
+    | ((lo & 0x7ff) << 1);
+  let disp = ((raw << 7) as i32 >> 7) as i64;
+  let disp = check_signed("thumb_br22", disp + delta, 25)? as u32;
+  if disp & 1 != 0 {
+    return Err(RelocError::Misaligned("thumb_br22", disp as u64));
+  }
+  let s = (disp >> 24) & 1;
+  let j1 = (!(disp >> 23) ^ s) & 1;
+  let j2 = (!(disp >> 22) ^ s) & 1;
+  let hi = (hi & 0xf800) | (s << 10) | ((disp >> 12) & 0x3ff);
+  let lo = (lo & 0xd000) | (j1 << 13) | (j2 << 11) | ((disp >> 1) & 0x7ff);
+  write_u32(code, offset, hi | (lo << 16));
+  Ok(())
+}
+
+// Fill in the immediate of an arm64 `adrp` at `pc` so it produces the page
+// containing `target`.
+pub fn encode_adrp(insn: u32, pc: u64, target: u64) -> Result<u32> {
+  let page_delta = ((target >> 12) as i64) - ((pc >> 12) as i64);
+  let delta = check_signed("adrp", page_delta, 21)? as u32;
+  let immlo = (delta & 0x3) << 29;
+  let immhi = ((delta >> 2) & 0x7ffff) << 5;
+  Ok((insn & 0x9f00_001f) | immlo | immhi)
+}
+
+// Fill in the immediate of an arm64 `b` or `bl` at `pc` so it branches to
+// `target`.
+pub fn encode_branch26(insn: u32, pc: u64, target: u64) -> Result<u32> {
+  let disp = check_signed("branch26", target.wrapping_sub(pc) as i64, 28)?;
+  if disp & 3 != 0 {
+    return Err(RelocError::Misaligned("branch26", target));
+  }
+  Ok((insn & 0xfc00_0000) | ((disp >> 2) as u32 & 0x03ff_ffff))
+}
+
+// Fill in the low 12 bits of `target` into an arm64 `add` or load/store with
+// an unsigned immediate offset, scaling by the access size for the latter.
+pub fn encode_pageoff12(insn: u32, target: u64) -> Result<u32> {
+  let off = (target & 0xfff) as u32;
+  let is_load_store = insn & 0x3b00_0000 == 0x3900_0000;
+  let scale = if !is_load_store {
+    0
+  } else if insn & 0x0480_0000 == 0x0480_0000 {
+    // 128-bit SIMD register loads and stores.
+    4
+  } else {
+    insn >> 30
+  };
+  if off & ((1 << scale) - 1) != 0 {
+    return Err(RelocError::Misaligned("pageoff12", target));
+  }
+  Ok((insn & !(0xfff << 10)) | ((off >> scale) << 10))
+}
+
+pub fn is_adrp(insn: u32) -> bool {
+  insn & 0x9f00_0000 == 0x9000_0000
+}
+
+// `ldr xN, [xM, #imm]`
+pub fn is_ldr_x_imm(insn: u32) -> bool {
+  insn & 0xffc0_0000 == 0xf940_0000
+}
+
+// `add xN, xM, #imm`
+pub fn is_add_x_imm(insn: u32) -> bool {
+  insn & 0xffc0_0000 == 0x9100_0000
+}
+
+// Build `add xd, xn, #imm12`.
+pub fn encode_add_x_imm(rd: u32, rn: u32, imm12: u32) -> u32 {
+  0x9100_0000 | ((imm12 & 0xfff) << 10) | ((rn & 0x1f) << 5) | (rd & 0x1f)
+}
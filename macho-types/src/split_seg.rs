@@ -0,0 +1,130 @@
+// LC_SEGMENT_SPLIT_INFO, version 2: every reference from one section of a
+// dylib to another, so that the dyld shared cache builder can move the
+// dylib's segments apart (all of __TEXT together, all of __DATA together)
+// and fix up whatever spans them.
+//
+// The data is a DYLD_CACHE_ADJ_V2_FORMAT byte, then ULEB128s: the number
+// of (from section, to section) pairs; for each pair, the two section
+// indices (0 is the mach header, sections count from 1 in load command
+// order) and the number of distinct target offsets; for each of those, the
+// distance from the last one and the number of kinds of reference to it;
+// for each kind, the kind, the number of references and the distance of
+// each from the last. Padded with zeros to the pointer size.
+
+use std::collections::BTreeMap;
+
+use {get_uleb128, put_uleb128, MachOError, Result};
+
+pub const DYLD_CACHE_ADJ_V2_FORMAT: u8 = 0x7f;
+
+pub const DYLD_CACHE_ADJ_V2_POINTER_32: u64 = 0x01;
+pub const DYLD_CACHE_ADJ_V2_POINTER_64: u64 = 0x02;
+pub const DYLD_CACHE_ADJ_V2_DELTA_32: u64 = 0x03;
+pub const DYLD_CACHE_ADJ_V2_DELTA_64: u64 = 0x04;
+pub const DYLD_CACHE_ADJ_V2_ARM64_ADRP: u64 = 0x05;
+pub const DYLD_CACHE_ADJ_V2_ARM64_OFF12: u64 = 0x06;
+pub const DYLD_CACHE_ADJ_V2_ARM64_BR26: u64 = 0x07;
+pub const DYLD_CACHE_ADJ_V2_ARM_MOVW_MOVT: u64 = 0x08;
+pub const DYLD_CACHE_ADJ_V2_ARM_BR24: u64 = 0x09;
+pub const DYLD_CACHE_ADJ_V2_THUMB_MOVW_MOVT: u64 = 0x0a;
+pub const DYLD_CACHE_ADJ_V2_THUMB_BR22: u64 = 0x0b;
+pub const DYLD_CACHE_ADJ_V2_IMAGE_OFF_32: u64 = 0x0c;
+pub const DYLD_CACHE_ADJ_V2_THREADED_POINTER_64: u64 = 0x0d;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Reference {
+  pub from_section: u64,
+  pub to_section: u64,
+  pub to_offset: u64,
+  pub kind: u64,
+  pub from_offset: u64,
+}
+
+pub fn parse(data: &[u8]) -> Result<Vec<Reference>> {
+  let what = "split seg info";
+  if data.is_empty() {
+    return Ok(Vec::new());
+  }
+  if data[0] != DYLD_CACHE_ADJ_V2_FORMAT {
+    return Err(MachOError::Malformed("split seg info isn't version 2"));
+  }
+  let mut refs: Vec<Reference> = Vec::new();
+  let mut cur: usize = 1;
+  let pairs = get_uleb128(data, &mut cur, what)?;
+  for _ in 0..pairs {
+    let from_section = get_uleb128(data, &mut cur, what)?;
+    let to_section = get_uleb128(data, &mut cur, what)?;
+    let to_count = get_uleb128(data, &mut cur, what)?;
+    let mut to_offset: u64 = 0;
+    for _ in 0..to_count {
+      to_offset += get_uleb128(data, &mut cur, what)?;
+      let kinds = get_uleb128(data, &mut cur, what)?;
+      for _ in 0..kinds {
+        let kind = get_uleb128(data, &mut cur, what)?;
+        let from_count = get_uleb128(data, &mut cur, what)?;
+        let mut from_offset: u64 = 0;
+        for _ in 0..from_count {
+          from_offset += get_uleb128(data, &mut cur, what)?;
+          refs.push(Reference {
+            from_section: from_section,
+            to_section: to_section,
+            to_offset: to_offset,
+            kind: kind,
+            from_offset: from_offset,
+          });
+        }
+      }
+    }
+  }
+  Ok(refs)
+}
+
+// The references from one section to another: to_offset -> kind -> from
+// offsets.
+type ByTarget = BTreeMap<u64, BTreeMap<u64, Vec<u64>>>;
+
+// Encode `refs`, in any order. The same reference twice is listed once.
+pub fn write(refs: &[Reference], pointer_size: usize) -> Vec<u8> {
+  // (from, to) -> to_offset -> kind -> from offsets, all in order.
+  let mut groups: BTreeMap<(u64, u64), ByTarget> = BTreeMap::new();
+  let mut sorted: Vec<Reference> = refs.to_vec();
+  sorted.sort();
+  sorted.dedup();
+  for r in sorted.iter() {
+    groups
+      .entry((r.from_section, r.to_section))
+      .or_default()
+      .entry(r.to_offset)
+      .or_default()
+      .entry(r.kind)
+      .or_default()
+      .push(r.from_offset);
+  }
+
+  let mut buf: Vec<u8> = vec![DYLD_CACHE_ADJ_V2_FORMAT];
+  put_uleb128(&mut buf, groups.len() as u64);
+  for (&(from_section, to_section), targets) in groups.iter() {
+    put_uleb128(&mut buf, from_section);
+    put_uleb128(&mut buf, to_section);
+    put_uleb128(&mut buf, targets.len() as u64);
+    let mut last_to: u64 = 0;
+    for (&to_offset, kinds) in targets.iter() {
+      put_uleb128(&mut buf, to_offset - last_to);
+      last_to = to_offset;
+      put_uleb128(&mut buf, kinds.len() as u64);
+      for (&kind, from_offsets) in kinds.iter() {
+        put_uleb128(&mut buf, kind);
+        put_uleb128(&mut buf, from_offsets.len() as u64);
+        let mut last_from: u64 = 0;
+        for &from_offset in from_offsets.iter() {
+          put_uleb128(&mut buf, from_offset - last_from);
+          last_from = from_offset;
+        }
+      }
+    }
+  }
+  while buf.len() % pointer_size != 0 {
+    buf.push(0);
+  }
+  buf
+}
@@ -0,0 +1,240 @@
+// The symbol table (LC_SYMTAB): nlist_64 entries (or nlist, in 32-bit
+// images) and the string table their names live in.
+
+use std::collections::HashMap;
+
+use {check_limit, get_cstr, get_u16, get_u32, get_u64, put_u16, put_u32,
+     put_u64, put_u8, CopyBudget, MachOError, ParseLimits, Result};
+
+// sizeof(struct nlist_64), sizeof(struct nlist)
+pub const NLIST_64_SIZE: usize = 16;
+pub const NLIST_SIZE: usize = 12;
+
+pub fn nlist_size(is_64_bit: bool) -> usize {
+  if is_64_bit {
+    NLIST_64_SIZE
+  } else {
+    NLIST_SIZE
+  }
+}
+
+// n_type masks and values.
+pub const N_STAB: u8 = 0xe0;
+pub const N_PEXT: u8 = 0x10;
+pub const N_TYPE: u8 = 0x0e;
+pub const N_EXT: u8 = 0x01;
+
+pub const N_UNDF: u8 = 0x0;
+pub const N_ABS: u8 = 0x2;
+pub const N_SECT: u8 = 0xe;
+pub const N_INDR: u8 = 0xa;
+
+pub const NO_SECT: u8 = 0;
+
+// Debugging (stab) symbol types, which make up the debug map: the objects
+// a linked image was built from and where their symbols ended up.
+pub const N_GSYM: u8 = 0x20;
+pub const N_FUN: u8 = 0x24;
+pub const N_STSYM: u8 = 0x26;
+pub const N_SO: u8 = 0x64;
+pub const N_OSO: u8 = 0x66;
+
+// n_desc flags.
+// The symbol must stay in the symbol table even if stripped, as
+// __mh_execute_header must.
+pub const REFERENCED_DYNAMICALLY: u16 = 0x10;
+pub const N_NO_DEAD_STRIP: u16 = 0x20;
+pub const N_WEAK_REF: u16 = 0x40;
+pub const N_WEAK_DEF: u16 = 0x80;
+// The function is rarely run (from __attribute__((cold))).
+pub const N_COLD_FUNC: u16 = 0x400;
+
+// Indirect symbol table entries for pointers to symbols which aren't in the
+// symbol table as externals.
+pub const INDIRECT_SYMBOL_LOCAL: u32 = 0x8000_0000;
+pub const INDIRECT_SYMBOL_ABS: u32 = 0x4000_0000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nlist {
+  pub name: String,
+  pub n_type: u8,
+  // 1-based section ordinal, or NO_SECT.
+  pub n_sect: u8,
+  pub n_desc: u16,
+  pub n_value: u64,
+}
+
+impl Nlist {
+  pub fn is_stab(&self) -> bool {
+    self.n_type & N_STAB != 0
+  }
+
+  pub fn is_external(&self) -> bool {
+    !self.is_stab() && self.n_type & N_EXT != 0
+  }
+
+  pub fn is_private_extern(&self) -> bool {
+    !self.is_stab() && self.n_type & N_PEXT != 0
+  }
+
+  pub fn is_undefined(&self) -> bool {
+    !self.is_stab() && self.n_type & N_TYPE == N_UNDF
+  }
+
+  // An undefined external with a size is a tentative definition.
+  pub fn is_common(&self) -> bool {
+    self.is_undefined() && self.is_external() && self.n_value != 0
+  }
+
+  pub fn is_weak_def(&self) -> bool {
+    !self.is_undefined() && self.n_desc & N_WEAK_DEF != 0
+  }
+
+  pub fn parse(
+    data: &[u8],
+    offset: usize,
+    strtab: &[u8],
+    is_64_bit: bool,
+  ) -> Result<Nlist> {
+    let mut strx = get_u32(data, offset, "nlist_64")? as usize;
+    let name = if strx == 0 {
+      String::new()
+    } else {
+      get_cstr(strtab, &mut strx, "string table")?
+    };
+    Ok(Nlist {
+      name: name,
+      n_type: *data.get(offset + 4).unwrap_or(&0),
+      n_sect: *data.get(offset + 5).unwrap_or(&0),
+      n_desc: get_u16(data, offset + 6, "nlist_64")?,
+      n_value: if is_64_bit {
+        get_u64(data, offset + 8, "nlist_64")?
+      } else {
+        get_u32(data, offset + 8, "nlist")? as u64
+      },
+    })
+  }
+
+  pub fn write(&self, buf: &mut Vec<u8>, strtab: &mut StringTable) {
+    self.write_sized(buf, strtab, true);
+  }
+
+  // Write this as an nlist_64, or a 32-bit nlist.
+  pub fn write_sized(
+    &self,
+    buf: &mut Vec<u8>,
+    strtab: &mut StringTable,
+    is_64_bit: bool,
+  ) {
+    put_u32(buf, strtab.add(&self.name));
+    put_u8(buf, self.n_type);
+    put_u8(buf, self.n_sect);
+    put_u16(buf, self.n_desc);
+    if is_64_bit {
+      put_u64(buf, self.n_value);
+    } else {
+      put_u32(buf, self.n_value as u32);
+    }
+  }
+}
+
+// The symbols of the symtab_command at `cmd_offset`.
+pub fn parse_symtab(
+  data: &[u8],
+  cmd_offset: usize,
+  is_64_bit: bool,
+) -> Result<Vec<Nlist>> {
+  let limits = ParseLimits::default();
+  let mut budget = CopyBudget::new(&limits);
+  parse_symtab_with_limits(data, cmd_offset, is_64_bit, &limits, &mut budget)
+}
+
+// `len` bytes at `offset`, if `data` has them.
+fn table<'a>(
+  data: &'a [u8],
+  offset: usize,
+  len: u64,
+  what: &'static str,
+) -> Result<&'a [u8]> {
+  let end = (offset as u64).checked_add(len);
+  end
+    .filter(|&end| end <= data.len() as u64)
+    .map(|end| &data[offset..(end as usize)])
+    .ok_or(MachOError::Truncated(what))
+}
+
+pub fn parse_symtab_with_limits(
+  data: &[u8],
+  cmd_offset: usize,
+  is_64_bit: bool,
+  limits: &ParseLimits,
+  budget: &mut CopyBudget,
+) -> Result<Vec<Nlist>> {
+  let what = "symtab_command";
+  let symoff = get_u32(data, cmd_offset + 8, what)? as usize;
+  let nsyms = get_u32(data, cmd_offset + 12, what)?;
+  let stroff = get_u32(data, cmd_offset + 16, what)? as usize;
+  let strsize = get_u32(data, cmd_offset + 20, what)?;
+  check_limit("symbols", nsyms as u64, limits.max_symbols as u64)?;
+  check_limit(
+    "string table bytes",
+    strsize as u64,
+    limits.max_string_table_size as u64,
+  )?;
+  let strtab = table(data, stroff, strsize as u64, "string table")?;
+  let size = nlist_size(is_64_bit);
+  let syms_len = nsyms as u64 * size as u64;
+  let syms = table(data, symoff, syms_len, "symbol table")?;
+  (0..(nsyms as usize))
+    .map(|i| {
+      let sym = Nlist::parse(syms, i * size, strtab, is_64_bit)?;
+      budget.spend(sym.name.len() as u64)?;
+      Ok(sym)
+    })
+    .collect()
+}
+
+// Builds the string table, sharing the storage of repeated names.
+#[derive(Debug)]
+pub struct StringTable {
+  data: Vec<u8>,
+  offsets: HashMap<String, u32>,
+}
+
+impl Default for StringTable {
+  fn default() -> Self {
+    StringTable::new()
+  }
+}
+
+impl StringTable {
+  pub fn new() -> Self {
+    // Offset 0 is reserved for the empty name.
+    StringTable {
+      data: vec![0],
+      offsets: HashMap::new(),
+    }
+  }
+
+  pub fn add(&mut self, name: &str) -> u32 {
+    if name.is_empty() {
+      return 0;
+    }
+    if let Some(offset) = self.offsets.get(name) {
+      return *offset;
+    }
+    let offset = self.data.len() as u32;
+    self.data.extend_from_slice(name.as_bytes());
+    self.data.push(0);
+    self.offsets.insert(name.to_string(), offset);
+    offset
+  }
+
+  // The table padded to pointer alignment, as it appears in the file.
+  pub fn finish(mut self) -> Vec<u8> {
+    while self.data.len() % 8 != 0 {
+      self.data.push(0);
+    }
+    self.data
+  }
+}
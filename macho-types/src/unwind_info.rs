@@ -0,0 +1,356 @@
+// Compact unwind info: __LD,__compact_unwind in objects, and the
+// __TEXT,__unwind_info section the linker builds from it.
+//
+// Each function has a 32-bit encoding describing how to unwind its frame.
+// The output section is a two-level lookup table: a first-level index of
+// function offsets, each pointing at a 4K second-level page of entries. A
+// page is either regular (offset and encoding per entry) or compressed (a
+// 24-bit offset from the page's first function and an 8-bit index into the
+// common or page-local encodings per entry). Functions with a personality
+// routine carry its index in the encoding, and functions with an LSDA get an
+// entry in a separate LSDA index.
+
+use std::collections::HashMap;
+
+use {get_u32, get_u64, put_u16, put_u32, Arch, MachOError, Result};
+
+// sizeof(struct compact_unwind_entry) in 64-bit objects.
+pub const COMPACT_UNWIND_ENTRY_SIZE: usize = 32;
+
+pub const UNWIND_IS_NOT_FUNCTION_START: u32 = 0x8000_0000;
+pub const UNWIND_HAS_LSDA: u32 = 0x4000_0000;
+pub const UNWIND_PERSONALITY_MASK: u32 = 0x3000_0000;
+pub const UNWIND_MODE_MASK: u32 = 0x0f00_0000;
+pub const UNWIND_X86_64_MODE_DWARF: u32 = 0x0400_0000;
+pub const UNWIND_ARM64_MODE_DWARF: u32 = 0x0300_0000;
+pub const UNWIND_X86_MODE_DWARF: u32 = 0x0400_0000;
+pub const UNWIND_ARM_MODE_DWARF: u32 = 0x0400_0000;
+// In DWARF mode, the offset of the function's FDE in __eh_frame.
+pub const UNWIND_DWARF_SECTION_OFFSET: u32 = 0x00ff_ffff;
+
+const UNWIND_SECTION_VERSION: u32 = 1;
+const UNWIND_SECOND_LEVEL_REGULAR: u32 = 2;
+const UNWIND_SECOND_LEVEL_COMPRESSED: u32 = 3;
+
+const UNWIND_INFO_HEADER_SIZE: usize = 28;
+const FIRST_LEVEL_ENTRY_SIZE: usize = 12;
+const LSDA_ENTRY_SIZE: usize = 8;
+const SECOND_LEVEL_PAGE_SIZE: usize = 0x1000;
+const REGULAR_PAGE_HEADER_SIZE: usize = 8;
+const REGULAR_ENTRY_SIZE: usize = 8;
+const COMPRESSED_PAGE_HEADER_SIZE: usize = 12;
+
+// Encoding indices in compressed entries are 8 bits, shared between the
+// common encodings and each page's own.
+const MAX_COMMON_ENCODINGS: usize = 127;
+const MAX_ENCODINGS: usize = 256;
+const MAX_COMPRESSED_OFFSET: u64 = 1 << 24;
+// The personality index is 2 bits, with 0 meaning none.
+pub const MAX_PERSONALITIES: usize = 3;
+
+// One function's unwind info, with every address resolved. Addresses are
+// absolute; the output stores them as offsets from the mach header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactUnwindEntry {
+  pub function: u64,
+  pub length: u32,
+  pub encoding: u32,
+  // The GOT slot holding the personality routine's address.
+  pub personality: Option<u64>,
+  pub lsda: Option<u64>,
+}
+
+pub fn dwarf_mode(arch: Arch) -> u32 {
+  match arch {
+    Arch::X86_64 => UNWIND_X86_64_MODE_DWARF,
+    Arch::Arm64 | Arch::Arm64e | Arch::Arm64_32 => UNWIND_ARM64_MODE_DWARF,
+    Arch::I386 => UNWIND_X86_MODE_DWARF,
+    Arch::Armv7 | Arch::Armv7s => UNWIND_ARM_MODE_DWARF,
+  }
+}
+
+pub fn is_dwarf(arch: Arch, encoding: u32) -> bool {
+  encoding & UNWIND_MODE_MASK == dwarf_mode(arch)
+}
+
+// The entries of a __compact_unwind section whose relocations have already
+// been applied.
+pub fn parse_compact_unwind(data: &[u8]) -> Result<Vec<CompactUnwindEntry>> {
+  if data.len() % COMPACT_UNWIND_ENTRY_SIZE != 0 {
+    return Err(MachOError::Truncated("compact unwind entry"));
+  }
+  let optional = |addr: u64| if addr == 0 { None } else { Some(addr) };
+  let mut entries: Vec<CompactUnwindEntry> = Vec::new();
+  for offset in (0..data.len()).step_by(COMPACT_UNWIND_ENTRY_SIZE) {
+    let what = "compact unwind entry";
+    entries.push(CompactUnwindEntry {
+      function: get_u64(data, offset, what)?,
+      length: get_u32(data, offset + 8, what)?,
+      encoding: get_u32(data, offset + 12, what)?,
+      personality: optional(get_u64(data, offset + 16, what)?),
+      lsda: optional(get_u64(data, offset + 24, what)?),
+    });
+  }
+  Ok(entries)
+}
+
+// An entry as stored in the output: offsets from the mach header, and the
+// personality folded into the encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FinalEntry {
+  function: u32,
+  encoding: u32,
+  lsda: Option<u32>,
+}
+
+fn image_offset(addr: u64, image_base: u64, what: &'static str) -> Result<u32> {
+  let offset = addr
+    .checked_sub(image_base)
+    .ok_or(MachOError::FieldOverflow(what, addr))?;
+  if offset > u32::MAX as u64 {
+    return Err(MachOError::FieldOverflow(what, addr));
+  }
+  Ok(offset as u32)
+}
+
+// Sort the entries, fill gaps between functions with entries which have no
+// unwind info, and fold runs of identical encodings into one entry.
+fn final_entries(
+  entries: &[CompactUnwindEntry],
+  image_base: u64,
+  personalities: &[u64],
+) -> Result<(Vec<FinalEntry>, u32)> {
+  let mut sorted: Vec<CompactUnwindEntry> = entries.to_vec();
+  sorted.sort_by_key(|entry| entry.function);
+  let mut result: Vec<FinalEntry> = Vec::new();
+  let mut end: u32 = 0;
+  for entry in sorted.iter() {
+    let function = image_offset(entry.function, image_base, "unwind function")?;
+    if !result.is_empty() && function > end {
+      result.push(FinalEntry {
+        function: end,
+        encoding: 0,
+        lsda: None,
+      });
+    }
+    let mut encoding = entry.encoding & !UNWIND_PERSONALITY_MASK;
+    if let Some(personality) = entry.personality {
+      let idx = personalities
+        .iter()
+        .position(|p| *p == personality)
+        .ok_or(MachOError::Malformed("unknown personality"))?;
+      encoding |= ((idx as u32 + 1) << 28) & UNWIND_PERSONALITY_MASK;
+    }
+    let lsda = match entry.lsda {
+      Some(lsda) => {
+        encoding |= UNWIND_HAS_LSDA;
+        Some(image_offset(lsda, image_base, "LSDA")?)
+      }
+      None => {
+        encoding &= !UNWIND_HAS_LSDA;
+        None
+      }
+    };
+    let folds = match result.last() {
+      Some(prev) => prev.encoding == encoding && lsda.is_none(),
+      None => false,
+    };
+    if !folds {
+      result.push(FinalEntry {
+        function: function,
+        encoding: encoding,
+        lsda: lsda,
+      });
+    }
+    end = end.max(function.saturating_add(entry.length));
+  }
+  Ok((result, end))
+}
+
+// The most used encodings, most used first, which compressed pages can refer
+// to without repeating them. Encodings used once gain nothing from this.
+fn common_encodings(entries: &[FinalEntry]) -> Vec<u32> {
+  let mut counts: HashMap<u32, usize> = HashMap::new();
+  for entry in entries.iter() {
+    *counts.entry(entry.encoding).or_insert(0) += 1;
+  }
+  let mut common: Vec<(u32, usize)> =
+    counts.into_iter().filter(|&(_, n)| n > 1).collect();
+  // Ties are broken by encoding so the output doesn't depend on hash order.
+  common.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+  common.truncate(MAX_COMMON_ENCODINGS);
+  common.into_iter().map(|(encoding, _)| encoding).collect()
+}
+
+// How many of `entries` fit in a compressed page, and the page-local
+// encodings they need.
+fn compressed_page_fit(
+  entries: &[FinalEntry],
+  common: &[u32],
+) -> (usize, Vec<u32>) {
+  let mut local: Vec<u32> = Vec::new();
+  let mut count = 0;
+  for entry in entries.iter() {
+    let new_encoding =
+      !common.contains(&entry.encoding) && !local.contains(&entry.encoding);
+    let nlocal = local.len() + new_encoding as usize;
+    let size = COMPRESSED_PAGE_HEADER_SIZE + 4 * (count + 1) + 4 * nlocal;
+    let too_far =
+      (entry.function - entries[0].function) as u64 >= MAX_COMPRESSED_OFFSET;
+    if size > SECOND_LEVEL_PAGE_SIZE
+      || common.len() + nlocal > MAX_ENCODINGS
+      || too_far
+    {
+      break;
+    }
+    if new_encoding {
+      local.push(entry.encoding);
+    }
+    count += 1;
+  }
+  (count, local)
+}
+
+fn regular_page(entries: &[FinalEntry]) -> Vec<u8> {
+  let mut page: Vec<u8> = Vec::new();
+  put_u32(&mut page, UNWIND_SECOND_LEVEL_REGULAR);
+  put_u16(&mut page, REGULAR_PAGE_HEADER_SIZE as u16);
+  put_u16(&mut page, entries.len() as u16);
+  for entry in entries.iter() {
+    put_u32(&mut page, entry.function);
+    put_u32(&mut page, entry.encoding);
+  }
+  page
+}
+
+fn compressed_page(
+  entries: &[FinalEntry],
+  common: &[u32],
+  local: &[u32],
+) -> Vec<u8> {
+  let mut page: Vec<u8> = Vec::new();
+  let encodings_offset = COMPRESSED_PAGE_HEADER_SIZE + 4 * entries.len();
+  put_u32(&mut page, UNWIND_SECOND_LEVEL_COMPRESSED);
+  put_u16(&mut page, COMPRESSED_PAGE_HEADER_SIZE as u16);
+  put_u16(&mut page, entries.len() as u16);
+  put_u16(&mut page, encodings_offset as u16);
+  put_u16(&mut page, local.len() as u16);
+  for entry in entries.iter() {
+    let idx = match common.iter().position(|e| *e == entry.encoding) {
+      Some(idx) => idx,
+      None => {
+        common.len()
+          + local.iter().position(|e| *e == entry.encoding).unwrap()
+      }
+    };
+    let offset = entry.function - entries[0].function;
+    put_u32(&mut page, ((idx as u32) << 24) | offset);
+  }
+  for encoding in local.iter() {
+    put_u32(&mut page, *encoding);
+  }
+  page
+}
+
+// The contents of __unwind_info for an image mapped at `image_base`, or None
+// if there are no entries. `personalities` are the GOT slots of every
+// personality routine the entries use, at most MAX_PERSONALITIES.
+pub fn build(
+  entries: &[CompactUnwindEntry],
+  image_base: u64,
+  personalities: &[u64],
+) -> Result<Option<Vec<u8>>> {
+  if entries.is_empty() {
+    return Ok(None);
+  }
+  if personalities.len() > MAX_PERSONALITIES {
+    return Err(MachOError::FieldOverflow(
+      "personality count",
+      personalities.len() as u64,
+    ));
+  }
+  let (entries, end) = final_entries(entries, image_base, personalities)?;
+  let common = common_encodings(&entries);
+
+  // Fill each page with as many entries as it takes, compressed unless a
+  // regular page would hold more.
+  let max_regular =
+    (SECOND_LEVEL_PAGE_SIZE - REGULAR_PAGE_HEADER_SIZE) / REGULAR_ENTRY_SIZE;
+  let mut pages: Vec<(usize, Vec<u8>)> = Vec::new();
+  let mut start = 0;
+  while start < entries.len() {
+    let rest = &entries[start..];
+    let (count, local) = compressed_page_fit(rest, &common);
+    let regular_count = rest.len().min(max_regular);
+    if regular_count > count {
+      pages.push((start, regular_page(&rest[..regular_count])));
+      start += regular_count;
+    } else {
+      pages.push((start, compressed_page(&rest[..count], &common, &local)));
+      start += count;
+    }
+  }
+
+  let common_offset = UNWIND_INFO_HEADER_SIZE;
+  let personalities_offset = common_offset + 4 * common.len();
+  let index_offset = personalities_offset + 4 * personalities.len();
+  let index_count = pages.len() + 1;
+  let lsda_offset = index_offset + FIRST_LEVEL_ENTRY_SIZE * index_count;
+  let lsda_count = entries.iter().filter(|e| e.lsda.is_some()).count();
+  let pages_offset = lsda_offset + LSDA_ENTRY_SIZE * lsda_count;
+
+  let mut buf: Vec<u8> = Vec::new();
+  put_u32(&mut buf, UNWIND_SECTION_VERSION);
+  put_u32(&mut buf, common_offset as u32);
+  put_u32(&mut buf, common.len() as u32);
+  put_u32(&mut buf, personalities_offset as u32);
+  put_u32(&mut buf, personalities.len() as u32);
+  put_u32(&mut buf, index_offset as u32);
+  put_u32(&mut buf, index_count as u32);
+  for encoding in common.iter() {
+    put_u32(&mut buf, *encoding);
+  }
+  for personality in personalities.iter() {
+    put_u32(&mut buf, image_offset(*personality, image_base, "personality")?);
+  }
+
+  // First-level index: each page's first function, where the page is, and
+  // where its LSDAs start. A sentinel entry marks the end of the last
+  // function.
+  let mut page_offset = pages_offset;
+  let mut lsdas_before = 0;
+  for (i, &(first, ref page)) in pages.iter().enumerate() {
+    let next = pages.get(i + 1).map_or(entries.len(), |p| p.0);
+    put_u32(&mut buf, entries[first].function);
+    put_u32(&mut buf, page_offset as u32);
+    put_u32(&mut buf, (lsda_offset + LSDA_ENTRY_SIZE * lsdas_before) as u32);
+    page_offset += page.len();
+    lsdas_before += entries[first..next]
+      .iter()
+      .filter(|e| e.lsda.is_some())
+      .count();
+  }
+  put_u32(&mut buf, end);
+  put_u32(&mut buf, 0);
+  put_u32(&mut buf, (lsda_offset + LSDA_ENTRY_SIZE * lsda_count) as u32);
+
+  for entry in entries.iter() {
+    if let Some(lsda) = entry.lsda {
+      put_u32(&mut buf, entry.function);
+      put_u32(&mut buf, lsda);
+    }
+  }
+  for &(_, ref page) in pages.iter() {
+    buf.extend_from_slice(page);
+  }
+  Ok(Some(buf))
+}
+
+// Point the DWARF-mode encoding in `encoding` at the FDE now at `fde_offset`
+// in __eh_frame.
+pub fn set_dwarf_offset(encoding: u32, fde_offset: u32) -> Result<u32> {
+  if fde_offset > UNWIND_DWARF_SECTION_OFFSET {
+    return Err(MachOError::FieldOverflow("FDE offset", fde_offset as u64));
+  }
+  Ok((encoding & !UNWIND_DWARF_SECTION_OFFSET) | fde_offset)
+}
@@ -0,0 +1,161 @@
+// Each writer's output read back by its parser, and the parsers run on the
+// linker's checked-in hello-world object (../test.o, built from ../test.c
+// for x86_64 macOS).
+
+extern crate macho_types as macho;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use macho::data_in_code::{self, DataInCodeEntry, DICE_KIND_DATA,
+                          DICE_KIND_JUMP_TABLE16};
+use macho::export_trie::{self, Export, ExportKind, ExportTrie};
+use macho::fat;
+use macho::function_starts;
+use macho::image_file::ImageFile;
+use macho::object_file::ObjectFile;
+use macho::split_seg::{self, Reference, DYLD_CACHE_ADJ_V2_DELTA_32,
+                       DYLD_CACHE_ADJ_V2_POINTER_64};
+use macho::{Arch, MachOError, ParseLimits};
+
+fn fixture(name: &str) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(name)
+}
+
+#[test]
+fn round_trips_function_starts() {
+  let base = 0x1_0000_0000;
+  let starts = vec![base + 0x4000, base + 0x3f00, base + 0x4000];
+  let data = function_starts::write(&starts, base);
+  assert_eq!(data.len() % 8, 0);
+  assert_eq!(
+    function_starts::parse(&data, base).unwrap(),
+    vec![base + 0x3f00, base + 0x4000]
+  );
+}
+
+#[test]
+fn round_trips_data_in_code() {
+  let table = DataInCodeEntry {
+    offset: 0x40,
+    length: 8,
+    kind: DICE_KIND_JUMP_TABLE16,
+  };
+  let data = DataInCodeEntry {
+    offset: 0x10,
+    length: 4,
+    kind: DICE_KIND_DATA,
+  };
+  let written = data_in_code::write(&[table, data]);
+  assert_eq!(data_in_code::parse(&written).unwrap(), vec![data, table]);
+}
+
+#[test]
+fn round_trips_export_tries() {
+  let exports = vec![
+    Export {
+      name: "_main".to_string(),
+      flags: 0,
+      kind: ExportKind::Regular { address: 0x3f00 },
+    },
+    Export {
+      name: "_malloc".to_string(),
+      flags: 0,
+      kind: ExportKind::Reexport {
+        ordinal: 1,
+        imported_name: String::new(),
+      },
+    },
+    Export {
+      name: "_m".to_string(),
+      flags: 0,
+      kind: ExportKind::Regular { address: 0x3f10 },
+    },
+  ];
+  let data = ExportTrie::build(&exports).encode();
+  let mut parsed = export_trie::parse(&data).unwrap();
+  parsed.sort_by(|a, b| a.name.cmp(&b.name));
+  let mut sorted = exports.clone();
+  sorted.sort_by(|a, b| a.name.cmp(&b.name));
+  assert_eq!(parsed, sorted);
+}
+
+#[test]
+fn round_trips_segment_split_info() {
+  let refs = vec![
+    Reference {
+      from_section: 2,
+      to_section: 1,
+      to_offset: 12,
+      kind: DYLD_CACHE_ADJ_V2_POINTER_64,
+      from_offset: 8,
+    },
+    Reference {
+      from_section: 1,
+      to_section: 2,
+      to_offset: 0,
+      kind: DYLD_CACHE_ADJ_V2_DELTA_32,
+      from_offset: 4,
+    },
+  ];
+  let data = split_seg::write(&refs, 8);
+  assert_eq!(data.len() % 8, 0);
+  let mut sorted = refs.clone();
+  sorted.sort();
+  assert_eq!(split_seg::parse(&data).unwrap(), sorted);
+}
+
+#[test]
+fn round_trips_fat_files() {
+  let object = fs::read(fixture("test.o")).unwrap();
+  let fake_arm64 = vec![0xcf, 0xfa, 0xed, 0xfe, 0x0c, 0, 0, 0x01];
+  let data =
+    fat::write(&[(Arch::X86_64, &object), (Arch::Arm64, &fake_arm64)])
+      .unwrap();
+  assert!(fat::is_fat(&data));
+  assert_eq!(fat::thin(&data, Arch::X86_64).unwrap(), object.as_slice());
+  assert_eq!(fat::thin(&data, Arch::Arm64).unwrap(), fake_arm64.as_slice());
+  let archs: Vec<Option<Arch>> = fat::slices(&data)
+    .unwrap()
+    .into_iter()
+    .map(|(arch, _)| arch)
+    .collect();
+  assert_eq!(archs, vec![Some(Arch::X86_64), Some(Arch::Arm64)]);
+  // A thin file is its own only slice.
+  assert_eq!(fat::thin(&object, Arch::X86_64).unwrap(), object.as_slice());
+}
+
+#[test]
+fn parses_damaged_files_within_limits() {
+  let data = fs::read(fixture("test.o")).unwrap();
+  let strict = ParseLimits::strict();
+  assert!(ObjectFile::parse_with_limits(&data, &strict).is_ok());
+  // Every prefix, and every byte set to 0xff, is an error or a parse, never
+  // a panic.
+  for len in 0..data.len() {
+    assert!(ObjectFile::parse_with_limits(&data[..len], &strict).is_err());
+  }
+  for i in 0..data.len() {
+    let mut damaged = data.clone();
+    damaged[i] = 0xff;
+    let _ = ObjectFile::parse_with_limits(&damaged, &strict);
+    let _ = ImageFile::parse_with_limits(&damaged, &strict);
+  }
+
+  let limits = ParseLimits {
+    max_symbols: 1,
+    ..ParseLimits::default()
+  };
+  match ObjectFile::parse_with_limits(&data, &limits) {
+    Err(MachOError::LimitExceeded("symbols", 4)) => (),
+    other => panic!("{:?}", other.map(|_| ())),
+  }
+  let limits = ParseLimits {
+    max_bytes_copied: 16,
+    ..ParseLimits::default()
+  };
+  match ObjectFile::parse_with_limits(&data, &limits) {
+    Err(MachOError::LimitExceeded("bytes copied", _)) => (),
+    other => panic!("{:?}", other.map(|_| ())),
+  }
+}
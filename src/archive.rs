@@ -0,0 +1,97 @@
+// Static archives (.a): BSD-format ar files of objects. Members are only
+// loaded when they define a symbol something else left undefined.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+const ARCHIVE_MAGIC: &[u8] = b"!<arch>\n";
+// sizeof(struct ar_hdr)
+const AR_HEADER_SIZE: usize = 60;
+// Names longer than 16 bytes, or containing spaces, are stored as "#1/<len>"
+// with the name at the start of the member's data.
+const LONG_NAME_PREFIX: &str = "#1/";
+
+#[derive(Debug)]
+pub enum ArchiveError {
+  // (offset of the member header) for a header which doesn't parse.
+  BadHeader(usize),
+  Truncated(usize),
+}
+
+pub type Result<T> = ::std::result::Result<T, ArchiveError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Member<'a> {
+  pub name: &'a str,
+  pub data: &'a [u8],
+}
+
+impl<'a> Member<'a> {
+  // The ranlib table of contents, which we don't need: loaded members are
+  // found by parsing every member's symbol table instead.
+  pub fn is_symbol_table(&self) -> bool {
+    self.name.starts_with("__.SYMDEF") || self.name == "/"
+  }
+}
+
+pub fn is_archive(data: &[u8]) -> bool {
+  data.starts_with(ARCHIVE_MAGIC)
+}
+
+// Whether the file at `path` is a (thin) archive, from its first bytes.
+pub fn is_archive_file(path: &Path) -> bool {
+  let mut magic = [0u8; 8];
+  File::open(path)
+    .and_then(|mut f| f.read_exact(&mut magic))
+    .map(|_| is_archive(&magic))
+    .unwrap_or(false)
+}
+
+fn header_field(header: &[u8], start: usize, len: usize) -> &str {
+  ::std::str::from_utf8(&header[start..(start + len)])
+    .unwrap_or("")
+    .trim_end()
+}
+
+// Every member, in order.
+pub fn members<'a>(data: &'a [u8]) -> Result<Vec<Member<'a>>> {
+  let mut members: Vec<Member> = Vec::new();
+  let mut offset = ARCHIVE_MAGIC.len();
+  while offset < data.len() {
+    let header = data
+      .get(offset..(offset + AR_HEADER_SIZE))
+      .ok_or(ArchiveError::Truncated(offset))?;
+    if &header[58..60] != b"`\n" {
+      return Err(ArchiveError::BadHeader(offset));
+    }
+    let size: usize = header_field(header, 48, 10)
+      .parse()
+      .map_err(|_| ArchiveError::BadHeader(offset))?;
+    let start = offset + AR_HEADER_SIZE;
+    let mut contents = start
+      .checked_add(size)
+      .and_then(|end| data.get(start..end))
+      .ok_or(ArchiveError::Truncated(offset))?;
+    let mut name = header_field(header, 0, 16);
+    if name.starts_with(LONG_NAME_PREFIX) {
+      let len: usize = name[LONG_NAME_PREFIX.len()..]
+        .parse()
+        .map_err(|_| ArchiveError::BadHeader(offset))?;
+      if len > contents.len() {
+        return Err(ArchiveError::BadHeader(offset));
+      }
+      name = ::std::str::from_utf8(&contents[..len])
+        .map_err(|_| ArchiveError::BadHeader(offset))?
+        .trim_end_matches('\0');
+      contents = &contents[len..];
+    }
+    members.push(Member {
+      name: name,
+      data: contents,
+    });
+    // Members start on even offsets.
+    offset = start + size + (size & 1);
+  }
+  Ok(members)
+}
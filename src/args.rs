@@ -0,0 +1,1754 @@
+// Parsing for the subset of OSX `ld` command line options we understand.
+//
+// `ld` options are single-dash long options (`-arch x86_64`, `-lSystem`), so
+// this is done by hand instead of with a getopt-style library.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use diagnostics::{self, Format};
+use gnu_args::{self, GnuArgsError};
+use image::{self, SectionAlignment, SegmentProtection};
+use ld_env;
+use macho::chained_fixups;
+use macho::load_command::LoadCommand;
+use macho::{Arch, ParseLimits, Platform, SourceVersion, Version, MH_BUNDLE,
+            MH_DYLIB, MH_EXECUTE, MH_KEXT_BUNDLE, MH_NO_HEAP_EXECUTION,
+            MH_OBJECT, MH_PIE, MH_PRELOAD,
+            VM_PROT_EXECUTE, VM_PROT_NONE, VM_PROT_READ, VM_PROT_WRITE};
+use response_file::{self, ResponseFileError};
+use search;
+use strip::StripOptions;
+use symbol_list::{self, ExportControl, SymbolList};
+use target::{Target, TargetVariant};
+use undefined::UndefinedTreatment;
+use uuid::UuidMode;
+
+// ld64 always leaves at least this much after the load commands.
+const DEFAULT_HEADERPAD: u64 = 32;
+const MAXPATHLEN: u64 = 1024;
+
+// The top of the 47-bit user address space, which is as far as a stack can
+// go.
+const MAX_STACK_ADDR: u64 = 0x7fff_ffff_f000;
+
+#[derive(Debug)]
+pub enum ArgsError {
+  MissingValue(String),
+  InvalidValue(String, String),
+  UnknownOption(String),
+  // Options which parsed fine on their own but can't be used together or
+  // with the rest of the configuration.
+  Incompatible(String),
+  // Options which are read but which can't be acted on for the output
+  // being made.
+  Unsupported(String),
+  NoInputFiles,
+  ResponseFile(ResponseFileError),
+  GnuArgs(GnuArgsError),
+  // The -filelist which couldn't be read.
+  FileList(PathBuf, io::Error),
+}
+
+impl From<ResponseFileError> for ArgsError {
+  fn from(err: ResponseFileError) -> Self {
+    ArgsError::ResponseFile(err)
+  }
+}
+
+impl From<GnuArgsError> for ArgsError {
+  fn from(err: GnuArgsError) -> Self {
+    ArgsError::GnuArgs(err)
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, ArgsError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+  Executable,
+  Dylib,
+  Bundle,
+  // -kext: loaded by the kernel rather than dyld.
+  KextBundle,
+  // -preload: loaded by firmware or a bootloader, not by the kernel.
+  Preload,
+  // -r: a single object to be linked again later.
+  Relocatable,
+}
+
+impl OutputKind {
+  pub fn filetype(&self) -> u32 {
+    match *self {
+      OutputKind::Executable => MH_EXECUTE,
+      OutputKind::Dylib => MH_DYLIB,
+      OutputKind::Bundle => MH_BUNDLE,
+      OutputKind::KextBundle => MH_KEXT_BUNDLE,
+      OutputKind::Preload => MH_PRELOAD,
+      OutputKind::Relocatable => MH_OBJECT,
+    }
+  }
+}
+
+// What compiles bitcode inputs: libLTO, loaded at run time, or the LLVM
+// this linker was built against (the `llvm` feature).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LtoBackend {
+  LibLto,
+  #[cfg(feature = "llvm")]
+  Llvm,
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkOptions {
+  pub output_path: PathBuf,
+  pub output_kind: OutputKind,
+  pub input_paths: Vec<PathBuf>,
+  // Contents of inputs which are in memory (given through LinkJob) rather
+  // than on disk, by their names in `input_paths`.
+  pub input_buffers: Vec<(PathBuf, Arc<Vec<u8>>)>,
+  pub libraries: Vec<String>,
+  pub library_paths: Vec<PathBuf>,
+  // -syslibroot, which may be given more than once: SDK roots to find
+  // absolute -L and -F directories (and re-exported dylibs) in.
+  pub syslibroots: Vec<PathBuf>,
+  pub arch: Arch,
+  // Every -arch given, in order. With more than one, each is linked
+  // separately (with `arch` set to it) and the results are combined into a
+  // universal file.
+  pub archs: Vec<Arch>,
+  // -platform_version, or the older -<platform>_version_min options.
+  pub platform: Platform,
+  pub min_os_version: Version,
+  // Defaults to the minimum OS version.
+  pub sdk_version: Option<Version>,
+  // A second -platform_version, for a zippered output: (platform, minimum
+  // OS version, SDK version).
+  pub platform_variant: Option<(Platform, Version, Version)>,
+  // None if neither -fixup_chains nor -no_fixup_chains was given.
+  pub fixup_chains: Option<bool>,
+  // None if neither -adhoc_codesign nor -no_adhoc_codesign was given.
+  pub adhoc_codesign: Option<bool>,
+  // None if neither -data_const nor -no_data_const was given.
+  pub data_const: Option<bool>,
+  // None if neither -encryptable nor -no_encryption was given.
+  pub encryptable: Option<bool>,
+  // Entitlements plist to embed in the ad-hoc signature.
+  pub entitlements: Option<PathBuf>,
+  // CodeDirectory flags for the ad-hoc signature.
+  pub hardened_runtime: bool,
+  pub library_validation: bool,
+  pub no_weak_exports: bool,
+  // -init: a symbol to run before all the other initializers.
+  pub init_symbol: Option<String>,
+  pub order_file: Option<PathBuf>,
+  // --profile-order: a sampled profile or symbol list to order functions
+  // by, after -order_file's.
+  pub profile_order: Option<PathBuf>,
+  // --split-cold: move cold functions to the end of __TEXT (see
+  // cold_split.rs).
+  pub split_cold: bool,
+  // --split-cold-report: report what --split-cold moved, as a note.
+  pub split_cold_report: bool,
+  // libLTO to optimize bitcode inputs with, and where to keep the object
+  // it produces.
+  pub lto_library: Option<PathBuf>,
+  // --lto-backend; None picks one from what was built and -lto_library.
+  pub lto_backend: Option<LtoBackend>,
+  // Code generation for the llvm backend: --lto-O<n>, --lto-cpu= and
+  // --lto-features=. --thinlto (or --thinlto-jobs=<n>, 0 for every CPU)
+  // compiles each module on its own, in parallel.
+  pub lto_opt_level: u32,
+  pub lto_cpu: Option<String>,
+  pub lto_features: Option<String>,
+  pub thinlto: bool,
+  pub thinlto_jobs: usize,
+  // Where to write the files the link depended on, for Xcode.
+  pub dependency_info: Option<PathBuf>,
+  // -oso_prefix: removed from the start of the input paths the debug map
+  // names. "." is the current directory, which -reproducible defaults to.
+  pub oso_prefix: Option<PathBuf>,
+  // -t: print each file as it's loaded. -why_load: print why each archive
+  // member was loaded.
+  pub trace_files: bool,
+  // -v: print the version and search paths (see verbose.rs).
+  pub verbose: bool,
+  // RC_TRACE_DYLIBS, RC_TRACE_ARCHIVES and LD_TRACE_FILE (see ld_env.rs).
+  pub trace_dylibs: bool,
+  pub trace_archives: bool,
+  pub trace_file: Option<PathBuf>,
+  pub dead_strip: bool,
+  // -ObjC: load every archive member with Objective-C classes or categories.
+  pub objc: bool,
+  // -all_load: load every member of every archive. -force_load: every
+  // member of these archives, which are also in `input_paths`.
+  pub all_load: bool,
+  pub force_load: Vec<PathBuf>,
+  // None if neither -objc_category_merging nor -no_objc_category_merging
+  // was given.
+  pub objc_category_merging: Option<bool>,
+  // -dsym: write <output>.dSYM from the debug map after linking.
+  pub dsym: bool,
+  // -segprot and -sectalign.
+  pub segment_protections: Vec<SegmentProtection>,
+  pub section_alignments: Vec<SectionAlignment>,
+  // -x, -S and -exported_only: which symbols to leave out of the output.
+  pub strip: StripOptions,
+  // -reproducible (or ZERO_AR_DATE in the environment, as for ld64): the
+  // output must depend only on the inputs and options.
+  pub reproducible: bool,
+  // Symbols to explain the liveness of when dead stripping.
+  pub why_live: Vec<String>,
+  pub why_load: bool,
+  // Keep the layout between links and rewrite only what changed.
+  pub incremental: bool,
+  pub object_path_lto: Option<PathBuf>,
+  pub exported_symbols_lists: Vec<PathBuf>,
+  pub unexported_symbols_lists: Vec<PathBuf>,
+  // -exported_symbol and -unexported_symbol, which add to the lists.
+  pub exported_symbols: Vec<String>,
+  pub unexported_symbols: Vec<String>,
+  // -interposable and -interposable_list: exported symbols which the output
+  // reaches through dyld, so other images can interpose on them.
+  pub interposable: bool,
+  pub interposable_lists: Vec<PathBuf>,
+  // -u: symbols which must be defined, loading archive members for them
+  // and keeping them alive when dead stripping.
+  pub forced_undefined: Vec<String>,
+  // -undefined: what to do about symbols nothing defines. None if it wasn't
+  // given.
+  pub undefined: Option<UndefinedTreatment>,
+  // -U and -dynamic_lookup_list: undefined symbols which are expected, and
+  // left for dyld to find at runtime, whatever -undefined says about the
+  // rest.
+  pub dynamic_lookup_symbols: Vec<String>,
+  pub dynamic_lookup_lists: Vec<PathBuf>,
+  // (existing symbol, alias) from -alias, in command-line order.
+  pub aliases: Vec<(String, String)>,
+  pub alias_lists: Vec<PathBuf>,
+  pub created_sections: Vec<CreatedSection>,
+  // -add_note <owner> <file>: LC_NOTE payloads, by owner.
+  pub notes: Vec<(String, PathBuf)>,
+  // -add_load_command <cmd> <file>: commands to add as they are, with the
+  // file holding what follows cmd and cmdsize.
+  pub extra_load_commands: Vec<(u32, PathBuf)>,
+  pub install_name: Option<String>,
+  pub rpaths: Vec<String>,
+  pub current_version: Option<Version>,
+  pub compatibility_version: Option<Version>,
+  // -source_version, and None if neither -add_source_version nor
+  // -no_source_version was given.
+  pub source_version: Option<SourceVersion>,
+  pub add_source_version: Option<bool>,
+  // Dylibs to link by path, whatever they're named (LinkJob::add_dylib).
+  // On the command line they're inputs ending in .dylib.
+  pub dylibs: Vec<PathBuf>,
+  // -F, and -framework names.
+  pub framework_paths: Vec<PathBuf>,
+  pub frameworks: Vec<String>,
+  // -weak_library and -upward_library paths, -weak-l and -upward-l names,
+  // and -weak_framework and -upward_framework names.
+  pub weak_libraries: Vec<PathBuf>,
+  pub upward_libraries: Vec<PathBuf>,
+  pub weak_library_names: Vec<String>,
+  pub upward_library_names: Vec<String>,
+  pub weak_frameworks: Vec<String>,
+  pub upward_frameworks: Vec<String>,
+  // -reexport_library paths and -reexport-l names.
+  pub reexport_libraries: Vec<PathBuf>,
+  pub reexported_library_names: Vec<String>,
+  // -dylib_file install_name:path: read the dependent dylib with that
+  // install name from `path`.
+  pub dylib_files: Vec<(String, PathBuf)>,
+  // -umbrella: the umbrella framework this dylib is part of.
+  pub umbrella: Option<String>,
+  pub sub_libraries: Vec<String>,
+  // -allowable_client: clients besides the umbrella's parts which may link
+  // this dylib directly (LC_SUB_CLIENT).
+  pub allowable_clients: Vec<String>,
+  // -client_name: the name checked against the allowable clients of the
+  // umbrella parts the output links, instead of its install name's.
+  pub client_name: Option<String>,
+  // -application_extension: only link against extension-safe APIs.
+  pub application_extension: bool,
+  // -mark_dead_strippable_dylib: clients may drop the dylib if they use
+  // none of its symbols.
+  pub mark_dead_strippable_dylib: bool,
+  // -bundle_loader: the executable a -bundle output will be loaded into.
+  pub bundle_loader: Option<PathBuf>,
+  // -keep_private_externs: leave hidden symbols global in -r output.
+  pub keep_private_externs: bool,
+  // None if neither -pie nor -no_pie was given.
+  pub pie: Option<bool>,
+  pub allow_heap_execute: bool,
+  // -static: an executable which doesn't use dyld.
+  pub static_link: bool,
+  // -image_base (or -seg1addr), and -segaddr: where to put the first
+  // segment, and any others which need fixed addresses.
+  pub image_base: Option<u64>,
+  pub segment_addresses: Vec<(String, u64)>,
+  // -headerpad: the minimum space to leave after the load commands.
+  // -headerpad_max_install_names: leave enough for install_name_tool to
+  // change every dylib's install name to the longest path.
+  pub headerpad: Option<u64>,
+  pub headerpad_max_install_names: bool,
+  // -stack_size and -stack_addr, for the main thread.
+  pub stack_size: Option<u64>,
+  pub stack_addr: Option<u64>,
+  pub uuid: UuidMode,
+  // --diagnostics-format: how warnings and errors are printed.
+  pub diagnostics_format: Format,
+  // --size-report: print which inputs the output's bytes came from.
+  pub size_report: bool,
+  // --time-passes[=json]: print how long each phase of the link took.
+  pub time_passes: Option<Format>,
+  // -print_statistics: print counts of what the link did, pass timings and
+  // peak memory use.
+  pub print_statistics: bool,
+  // --untrusted-inputs: parse inputs with ParseLimits::strict, for links of
+  // files from untrusted sources.
+  pub untrusted_inputs: bool,
+  // --daemon-socket: have the `mold --daemon` listening there do the link.
+  pub daemon_socket: Option<PathBuf>,
+  // --skip-unchanged: don't relink if the inputs, options and output are
+  // what they were after the last link.
+  pub skip_unchanged: bool,
+  // --verify-output: check the output as dyld would before loading it, and
+  // fail the link if dyld would refuse to.
+  pub verify_output: bool,
+  // --shared-cache-eligible: list the dylib's references between sections
+  // in LC_SEGMENT_SPLIT_INFO, so the shared cache builder can insert it.
+  pub shared_cache_eligible: bool,
+  // --error-limit=<n>: how many undefined and duplicate symbols to report
+  // before leaving the rest out. 0 reports all of them.
+  pub error_limit: usize,
+  // --threads=<n>: how many threads the parallel passes use, 0 for one per
+  // CPU. The output is the same whatever the count.
+  pub threads: usize,
+}
+
+// A section given by -sectcreate or -add_empty_section.
+#[derive(Debug, Clone)]
+pub struct CreatedSection {
+  pub segname: String,
+  pub sectname: String,
+  // None for -add_empty_section.
+  pub path: Option<PathBuf>,
+}
+
+impl Default for LinkOptions {
+  fn default() -> Self {
+    LinkOptions {
+      output_path: PathBuf::from("a.out"),
+      output_kind: OutputKind::Executable,
+      input_paths: Vec::new(),
+      input_buffers: Vec::new(),
+      libraries: Vec::new(),
+      library_paths: Vec::new(),
+      syslibroots: Vec::new(),
+      arch: Arch::X86_64,
+      archs: Vec::new(),
+      platform: Platform::MacOS,
+      min_os_version: Version::new(10, 13, 0),
+      sdk_version: None,
+      platform_variant: None,
+      fixup_chains: None,
+      adhoc_codesign: None,
+      data_const: None,
+      encryptable: None,
+      entitlements: None,
+      hardened_runtime: false,
+      library_validation: false,
+      no_weak_exports: false,
+      init_symbol: None,
+      order_file: None,
+      profile_order: None,
+      split_cold: false,
+      split_cold_report: false,
+      lto_library: None,
+      lto_backend: None,
+      lto_opt_level: 2,
+      lto_cpu: None,
+      lto_features: None,
+      thinlto: false,
+      thinlto_jobs: 0,
+      dependency_info: None,
+      oso_prefix: None,
+      trace_files: false,
+      verbose: false,
+      trace_dylibs: false,
+      trace_archives: false,
+      trace_file: None,
+      dead_strip: false,
+      objc: false,
+      all_load: false,
+      force_load: Vec::new(),
+      objc_category_merging: None,
+      dsym: false,
+      segment_protections: Vec::new(),
+      section_alignments: Vec::new(),
+      strip: StripOptions::default(),
+      reproducible: false,
+      why_live: Vec::new(),
+      why_load: false,
+      incremental: false,
+      object_path_lto: None,
+      exported_symbols_lists: Vec::new(),
+      unexported_symbols_lists: Vec::new(),
+      exported_symbols: Vec::new(),
+      unexported_symbols: Vec::new(),
+      interposable: false,
+      interposable_lists: Vec::new(),
+      forced_undefined: Vec::new(),
+      dynamic_lookup_symbols: Vec::new(),
+      dynamic_lookup_lists: Vec::new(),
+      undefined: None,
+      aliases: Vec::new(),
+      alias_lists: Vec::new(),
+      created_sections: Vec::new(),
+      notes: Vec::new(),
+      extra_load_commands: Vec::new(),
+      install_name: None,
+      rpaths: Vec::new(),
+      current_version: None,
+      compatibility_version: None,
+      source_version: None,
+      add_source_version: None,
+      dylibs: Vec::new(),
+      framework_paths: Vec::new(),
+      frameworks: Vec::new(),
+      weak_libraries: Vec::new(),
+      upward_libraries: Vec::new(),
+      weak_library_names: Vec::new(),
+      upward_library_names: Vec::new(),
+      weak_frameworks: Vec::new(),
+      upward_frameworks: Vec::new(),
+      reexport_libraries: Vec::new(),
+      dylib_files: Vec::new(),
+      reexported_library_names: Vec::new(),
+      umbrella: None,
+      sub_libraries: Vec::new(),
+      allowable_clients: Vec::new(),
+      client_name: None,
+      application_extension: false,
+      mark_dead_strippable_dylib: false,
+      bundle_loader: None,
+      keep_private_externs: false,
+      pie: None,
+      allow_heap_execute: false,
+      static_link: false,
+      image_base: None,
+      segment_addresses: Vec::new(),
+      headerpad: None,
+      headerpad_max_install_names: false,
+      stack_size: None,
+      stack_addr: None,
+      uuid: UuidMode::Content,
+      diagnostics_format: Format::Text,
+      size_report: false,
+      time_passes: None,
+      print_statistics: false,
+      untrusted_inputs: false,
+      daemon_socket: None,
+      skip_unchanged: false,
+      verify_output: false,
+      shared_cache_eligible: false,
+      error_limit: 20,
+      threads: 0,
+    }
+  }
+}
+
+impl LinkOptions {
+  // Whether dyld loads the output. Static executables, kexts and -preload
+  // outputs are loaded as they are, at their link address unless they have
+  // relocations, so they have no dyld info or dylibs.
+  pub fn uses_dyld(&self) -> bool {
+    match self.output_kind {
+      OutputKind::Executable => !self.static_link,
+      OutputKind::Dylib | OutputKind::Bundle => true,
+      OutputKind::KextBundle
+      | OutputKind::Preload
+      | OutputKind::Relocatable => false,
+    }
+  }
+
+  // Whether to emit LC_DYLD_CHAINED_FIXUPS instead of dyld info opcodes.
+  pub fn use_chained_fixups(&self) -> bool {
+    if !self.uses_dyld() {
+      return false;
+    }
+    match self.fixup_chains {
+      Some(explicit) => explicit,
+      None => {
+        chained_fixups::default_enabled(
+          self.arch,
+          self.platform,
+          self.min_os_version,
+        )
+      }
+    }
+  }
+
+  // The -syslibroot roots in effect. As with ld64, a last root of / turns
+  // them all off.
+  pub fn effective_syslibroots(&self) -> &[PathBuf] {
+    match self.syslibroots.last() {
+      Some(root) if root == Path::new("/") => &[],
+      _ => &self.syslibroots,
+    }
+  }
+
+  // The -L directories, inside the -syslibroot roots.
+  pub fn library_search_paths(&self) -> Vec<PathBuf> {
+    search::apply_syslibroots(
+      &self.library_paths,
+      self.effective_syslibroots(),
+    )
+  }
+
+  // The -F directories, inside the -syslibroot roots.
+  pub fn framework_search_paths(&self) -> Vec<PathBuf> {
+    search::apply_syslibroots(
+      &self.framework_paths,
+      self.effective_syslibroots(),
+    )
+  }
+
+  // The space to leave after the load commands of an output with
+  // `dylib_commands` LC_ID_DYLIB and LC_LOAD_DYLIB-like commands.
+  pub fn headerpad_size(&self, dylib_commands: usize) -> u64 {
+    let explicit = self.headerpad.unwrap_or(DEFAULT_HEADERPAD);
+    if self.headerpad_max_install_names {
+      explicit.max(dylib_commands as u64 * MAXPATHLEN)
+    } else {
+      explicit
+    }
+  }
+
+  // LC_SOURCE_VERSION, which like ld64 is in every final output (with
+  // version 0 if none was given) unless turned off.
+  pub fn source_version_command(&self) -> Option<LoadCommand> {
+    if self.output_kind == OutputKind::Relocatable
+      || self.add_source_version == Some(false)
+    {
+      return None;
+    }
+    let version = self.source_version.unwrap_or_default();
+    Some(LoadCommand::SourceVersion(version))
+  }
+
+  // Whether to put const data in __DATA_CONST.
+  pub fn use_data_const(&self) -> bool {
+    if self.output_kind == OutputKind::Relocatable {
+      return false;
+    }
+    match self.data_const {
+      Some(explicit) => explicit,
+      None => image::data_const_default(self.platform, self.min_os_version),
+    }
+  }
+
+  // Whether to leave an LC_ENCRYPTION_INFO for the App Store to encrypt
+  // the output under. As with ld64, that's the default for executables on
+  // devices.
+  pub fn use_encryption_info(&self) -> bool {
+    if self.output_kind == OutputKind::Relocatable {
+      return false;
+    }
+    match self.encryptable {
+      Some(explicit) => explicit,
+      None => {
+        self.output_kind == OutputKind::Executable
+          && image::encryption_default(self.platform)
+      }
+    }
+  }
+
+  // Whether to sign the output ad-hoc. arm64 macOS won't run anything
+  // unsigned, so that's the default there, as with ld64.
+  pub fn use_adhoc_codesign(&self) -> bool {
+    if self.output_kind == OutputKind::Relocatable {
+      return false;
+    }
+    match self.adhoc_codesign {
+      Some(explicit) => explicit,
+      None => self.arch.is_arm64(),
+    }
+  }
+
+  // Whether to merge Objective-C categories into their classes. Like ld64,
+  // only final images do by default: a later link could add more. The
+  // merging only knows the 64-bit runtime's layouts.
+  pub fn merge_objc_categories(&self) -> bool {
+    if !self.arch.is_64_bit() {
+      return false;
+    }
+    match self.objc_category_merging {
+      Some(explicit) => explicit,
+      None => self.output_kind != OutputKind::Relocatable,
+    }
+  }
+
+  // Whether a main executable is position independent. Only executables
+  // have a choice: everything else is always slid.
+  pub fn use_pie(&self) -> bool {
+    if self.static_link {
+      return false;
+    }
+    match self.pie {
+      Some(explicit) => explicit || !self.arch.allows_non_pie(),
+      None => true,
+    }
+  }
+
+  // Non-PIE executables load at their link address, so they need no rebase
+  // information.
+  pub fn needs_rebases(&self) -> bool {
+    match self.output_kind {
+      OutputKind::Executable => self.use_pie(),
+      OutputKind::Preload => false,
+      _ => true,
+    }
+  }
+
+  // MH_PIE and MH_NO_HEAP_EXECUTION, as appropriate for an executable.
+  pub fn executable_header_flags(&self) -> u32 {
+    if self.output_kind != OutputKind::Executable {
+      return 0;
+    }
+    let mut flags = 0;
+    if self.use_pie() {
+      flags |= MH_PIE;
+    }
+    if self.arch.non_executable_heap() && !self.allow_heap_execute {
+      flags |= MH_NO_HEAP_EXECUTION;
+    }
+    flags
+  }
+
+  // Undefined symbols are errors in final links, unless -undefined says
+  // otherwise. -r output can refer to anything, so it's only checked if
+  // asked, and kexts are bound by the kext loader against the kernel, as
+  // ld64 assumes.
+  pub fn undefined_treatment(&self) -> UndefinedTreatment {
+    match self.undefined {
+      Some(treatment) => treatment,
+      None if self.output_kind == OutputKind::Relocatable => {
+        UndefinedTreatment::Suppress
+      }
+      None if self.output_kind == OutputKind::KextBundle => {
+        UndefinedTreatment::DynamicLookup
+      }
+      None => UndefinedTreatment::Error,
+    }
+  }
+
+  // How much inputs may ask of the parsers.
+  pub fn parse_limits(&self) -> ParseLimits {
+    if self.untrusted_inputs {
+      ParseLimits::strict()
+    } else {
+      ParseLimits::default()
+    }
+  }
+
+  // The contents of an input which is in memory, or None if it's on disk.
+  pub fn input_buffer(&self, path: &Path) -> Option<&[u8]> {
+    self
+      .input_buffers
+      .iter()
+      .find(|&&(ref name, _)| name == path)
+      .map(|&(_, ref data)| data.as_slice())
+  }
+
+  pub fn is_universal(&self) -> bool {
+    self.archs.len() > 1
+  }
+
+  // The options for linking one slice of a universal output.
+  pub fn for_arch(&self, arch: Arch) -> LinkOptions {
+    LinkOptions {
+      arch: arch,
+      archs: vec![arch],
+      ..self.clone()
+    }
+  }
+
+  // Turn off whatever would make the output depend on more than the inputs
+  // and options.
+  fn make_reproducible(&mut self) {
+    if self.uuid == UuidMode::Random {
+      diagnostics::warning(
+        "-random_uuid is ignored for reproducible output; the UUID is a hash \
+         of the output instead",
+      );
+      self.uuid = UuidMode::Content;
+    }
+    if self.incremental {
+      // The layout would depend on the links before this one.
+      diagnostics::warning("-incremental is ignored for reproducible output");
+      self.incremental = false;
+    }
+    if self.oso_prefix.is_none() {
+      // The debug map names inputs from where the link ran.
+      self.oso_prefix = Some(PathBuf::from("."));
+    }
+    let default_install_name = self.output_kind == OutputKind::Dylib
+      && self.install_name.is_none()
+      && self.output_path.is_absolute();
+    if default_install_name {
+      diagnostics::warning(
+        "the install name defaults to the absolute output path; pass \
+         -install_name to keep host paths out of reproducible output",
+      );
+    }
+  }
+
+  // Fill in what depends on other options, and validate, once they have
+  // all been given.
+  pub fn finish(&mut self) -> Result<()> {
+    match self.archs.first() {
+      Some(arch) => self.arch = *arch,
+      None => self.archs.push(self.arch),
+    }
+    if self.reproducible {
+      self.make_reproducible();
+    }
+    self.validate()
+  }
+
+  // An explicit --lto-backend, or the built-in LLVM unless -lto_library
+  // asks for a particular libLTO.
+  pub fn lto_backend(&self) -> LtoBackend {
+    match self.lto_backend {
+      Some(backend) => backend,
+      #[cfg(feature = "llvm")]
+      None if self.lto_library.is_none() => LtoBackend::Llvm,
+      None => LtoBackend::LibLto,
+    }
+  }
+
+  // Check constraints between options once they have all been read.
+  pub fn validate(&self) -> Result<()> {
+    // `ld -v` only prints the version.
+    if self.input_paths.is_empty() && !self.verbose {
+      return Err(ArgsError::NoInputFiles);
+    }
+    if self.incremental
+      && (self.output_kind != OutputKind::Relocatable || self.is_universal())
+    {
+      diagnostics::warning(
+        "-incremental is only supported for single-architecture -r links",
+      );
+    }
+    if self.dsym && self.output_kind == OutputKind::Relocatable {
+      diagnostics::warning(
+        "-dsym is ignored with -r; the output keeps its inputs' debug info",
+      );
+    }
+    if (self.entitlements.is_some()
+      || self.hardened_runtime
+      || self.library_validation)
+      && !self.use_adhoc_codesign()
+    {
+      diagnostics::warning(
+        "-entitlements, -hardened_runtime and -library_validation are \
+         ignored without -adhoc_codesign",
+      );
+    }
+    if self.output_kind == OutputKind::Relocatable
+      && !(self.segment_protections.is_empty()
+        && self.section_alignments.is_empty())
+    {
+      diagnostics::warning("-segprot and -sectalign are ignored with -r");
+    }
+    if self.size_report && self.output_kind != OutputKind::Relocatable {
+      diagnostics::warning("--size-report is only supported with -r");
+    }
+    if self.time_passes.is_some()
+      && self.output_kind != OutputKind::Relocatable
+    {
+      diagnostics::warning("--time-passes only times the phases of -r links");
+    }
+    if self.print_statistics && self.output_kind != OutputKind::Relocatable {
+      diagnostics::warning("-print_statistics only measures -r links");
+    }
+    let allows_dynamic_lookup = !(self.dynamic_lookup_symbols.is_empty()
+      && self.dynamic_lookup_lists.is_empty());
+    let all_dynamic = match self.undefined_treatment() {
+      UndefinedTreatment::Suppress | UndefinedTreatment::DynamicLookup => true,
+      _ => false,
+    };
+    if allows_dynamic_lookup && all_dynamic {
+      diagnostics::warning(
+        "-U and -dynamic_lookup_list have no effect when undefined symbols \
+         aren't errors or warnings",
+      );
+    }
+    if self.verify_output && !self.uses_dyld() {
+      diagnostics::warning(
+        "--verify-output is ignored for outputs which dyld doesn't load",
+      );
+    }
+    let interposable = self.interposable || !self.interposable_lists.is_empty();
+    if interposable && !self.uses_dyld() {
+      diagnostics::warning(
+        "-interposable and -interposable_list are ignored for outputs which \
+         dyld doesn't load",
+      );
+    }
+    if self.shared_cache_eligible && self.output_kind != OutputKind::Dylib {
+      diagnostics::warning(
+        "--shared-cache-eligible is ignored for outputs other than dylibs",
+      );
+    }
+    if self.skip_unchanged && !self.input_buffers.is_empty() {
+      diagnostics::warning("--skip-unchanged is ignored for in-memory inputs");
+    }
+    if !self.why_live.is_empty() && !self.dead_strip {
+      diagnostics::warning("-why_live is ignored without -dead_strip");
+    }
+    if self.add_source_version == Some(false) && self.source_version.is_some()
+    {
+      diagnostics::warning(
+        "-source_version is ignored with -no_source_version",
+      );
+    }
+    if self.output_kind == OutputKind::Relocatable
+      && (self.headerpad.is_some() || self.headerpad_max_install_names)
+    {
+      diagnostics::warning(
+        "-headerpad and -headerpad_max_install_names are ignored with -r",
+      );
+    }
+    if self.pie.is_some() && self.output_kind != OutputKind::Executable {
+      diagnostics::warning(
+        "-pie and -no_pie are ignored when not linking a main executable",
+      );
+    }
+    self.validate_static()?;
+    for root in self.syslibroots.iter().filter(|root| !root.is_dir()) {
+      diagnostics::warning(&format!(
+        "-syslibroot {} is not a directory",
+        root.display()
+      ));
+    }
+    if let Some((variant, _, _)) = self.platform_variant {
+      let mut platforms = [self.platform.name(), variant.name()];
+      platforms.sort();
+      if platforms != ["mac-catalyst", "macos"] {
+        return Err(ArgsError::Incompatible(format!(
+          "can't link for both {} and {}; only macos and mac-catalyst can \
+           be zippered",
+          self.platform.name(),
+          variant.name()
+        )));
+      }
+    }
+    if self.pie == Some(false)
+      && !self.arch.allows_non_pie()
+      && !self.static_link
+    {
+      return Err(ArgsError::Incompatible(format!(
+        "-no_pie is not supported for {}",
+        self.arch.name()
+      )));
+    }
+    if self.pie == Some(false)
+      && self.platform == Platform::MacOS
+      && self.min_os_version >= Version::new(10, 15, 0)
+    {
+      diagnostics::warning(&format!(
+        "-no_pie is deprecated when targeting macOS {}",
+        self.min_os_version
+      ));
+    }
+    if self.output_kind == OutputKind::KextBundle
+      && (self.fixup_chains == Some(true) || self.bundle_loader.is_some())
+    {
+      return Err(ArgsError::Incompatible(
+        "-fixup_chains and -bundle_loader can't be used with -kext".to_string(),
+      ));
+    }
+    if self.fixup_chains == Some(true)
+      && !chained_fixups::supported(self.platform, self.min_os_version)
+    {
+      return Err(ArgsError::Incompatible(format!(
+        "-fixup_chains is not supported when targeting {} {}",
+        self.platform.name(),
+        self.min_os_version
+      )));
+    }
+    if self.has_exported_symbols() && self.has_unexported_symbols() {
+      return Err(ArgsError::Incompatible(
+        "can't use both -exported_symbol(s_list) and \
+         -unexported_symbol(s_list)"
+          .to_string(),
+      ));
+    }
+    self.validate_stack()?;
+    self.validate_supported()?;
+    if self.lto_backend() == LtoBackend::LibLto
+      && (self.lto_cpu.is_some() || self.lto_features.is_some() || self.thinlto)
+    {
+      diagnostics::warning(
+        "--lto-cpu, --lto-features and --thinlto need --lto-backend=llvm; \
+         libLTO ignores them",
+      );
+    }
+    if self.bundle_loader.is_some() && self.output_kind != OutputKind::Bundle {
+      return Err(ArgsError::Incompatible(
+        "-bundle_loader can only be used with -bundle".to_string(),
+      ));
+    }
+    if self.output_kind != OutputKind::Dylib {
+      let dylib_only = [
+        ("-install_name", self.install_name.is_some()),
+        ("-current_version", self.current_version.is_some()),
+        ("-compatibility_version", self.compatibility_version.is_some()),
+        ("-umbrella", self.umbrella.is_some()),
+        ("-allowable_client", !self.allowable_clients.is_empty()),
+        ("-mark_dead_strippable_dylib", self.mark_dead_strippable_dylib),
+      ];
+      for &(opt, given) in dylib_only.iter() {
+        if given {
+          return Err(ArgsError::Incompatible(format!(
+            "{} can only be used with -dylib",
+            opt
+          )));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  // Options which only final links act on.
+  fn validate_supported(&self) -> Result<()> {
+    if self.output_kind != OutputKind::Relocatable {
+      return Ok(());
+    }
+    let final_only = [
+      ("-exported_symbol(s_list)", self.has_exported_symbols()),
+      ("-unexported_symbol(s_list)", self.has_unexported_symbols()),
+      (
+        "-alias(_list)",
+        !(self.aliases.is_empty() && self.alias_lists.is_empty()),
+      ),
+    ];
+    for &(opt, given) in final_only.iter() {
+      if given {
+        return Err(ArgsError::Unsupported(format!("{} with -r", opt)));
+      }
+    }
+    Ok(())
+  }
+
+  fn validate_static(&self) -> Result<()> {
+    if self.static_link && self.output_kind != OutputKind::Executable {
+      return Err(ArgsError::Incompatible(
+        "-static can only be used with -execute".to_string(),
+      ));
+    }
+    let is_static =
+      self.static_link || self.output_kind == OutputKind::Preload;
+    if is_static && self.pie == Some(true) {
+      return Err(ArgsError::Incompatible(
+        "-pie can't be used with -static or -preload".to_string(),
+      ));
+    }
+    let links_dylibs = self.input_paths.iter().any(|p| search::is_dylib_path(p))
+      || !(self.dylibs.is_empty()
+        && self.frameworks.is_empty()
+        && self.weak_libraries.is_empty()
+        && self.upward_libraries.is_empty()
+        && self.weak_library_names.is_empty()
+        && self.upward_library_names.is_empty()
+        && self.weak_frameworks.is_empty()
+        && self.upward_frameworks.is_empty()
+        && self.reexport_libraries.is_empty()
+        && self.reexported_library_names.is_empty());
+    if is_static && links_dylibs {
+      return Err(ArgsError::Incompatible(
+        "dylibs can't be linked with -static or -preload".to_string(),
+      ));
+    }
+    let page_size = self.arch.page_size();
+    let addresses = self
+      .image_base
+      .iter()
+      .chain(self.segment_addresses.iter().map(|&(_, ref addr)| addr));
+    for &addr in addresses {
+      if addr % page_size != 0 {
+        return Err(ArgsError::Incompatible(format!(
+          "segment address {:#x} is not a multiple of the page size ({:#x})",
+          addr, page_size
+        )));
+      }
+    }
+    if self.output_kind == OutputKind::Relocatable
+      && !(self.image_base.is_none() && self.segment_addresses.is_empty())
+    {
+      diagnostics::warning("-image_base and -segaddr are ignored with -r");
+    }
+    Ok(())
+  }
+
+  fn validate_stack(&self) -> Result<()> {
+    if self.stack_size.is_none() && self.stack_addr.is_none() {
+      return Ok(());
+    }
+    if self.output_kind != OutputKind::Executable {
+      return Err(ArgsError::Incompatible(
+        "-stack_size and -stack_addr can only be used with -execute"
+          .to_string(),
+      ));
+    }
+    let page_size = self.arch.page_size();
+    let values = [
+      ("-stack_size", self.stack_size),
+      ("-stack_addr", self.stack_addr),
+    ];
+    for &(opt, val) in values.iter() {
+      match val {
+        Some(0) => {
+          return Err(ArgsError::Incompatible(format!("{} must not be 0", opt)))
+        }
+        Some(val) if val % page_size != 0 => {
+          return Err(ArgsError::Incompatible(format!(
+            "{} {:#x} is not a multiple of the page size ({:#x})",
+            opt, val, page_size
+          )))
+        }
+        _ => (),
+      }
+    }
+    match (self.stack_size, self.stack_addr) {
+      (None, Some(_)) => Err(ArgsError::Incompatible(
+        "-stack_addr needs -stack_size too".to_string(),
+      )),
+      (Some(size), Some(addr)) if size > addr => {
+        Err(ArgsError::Incompatible(format!(
+          "-stack_size {:#x} is larger than -stack_addr {:#x}",
+          size, addr
+        )))
+      }
+      (_, Some(addr)) if addr > MAX_STACK_ADDR => {
+        Err(ArgsError::Incompatible(format!(
+          "-stack_addr {:#x} is above the user address space",
+          addr
+        )))
+      }
+      (Some(size), None) if size > MAX_STACK_ADDR => {
+        Err(ArgsError::Incompatible(format!(
+          "-stack_size {:#x} is larger than the user address space",
+          size
+        )))
+      }
+      _ => Ok(()),
+    }
+  }
+
+  // For zippered outputs the macOS side comes first, whichever order the
+  // -platform_version options were in.
+  pub fn target(&self) -> Target {
+    let primary = (
+      self.platform,
+      self.min_os_version,
+      self.sdk_version.unwrap_or(self.min_os_version),
+    );
+    let (primary, variant) = match self.platform_variant {
+      Some(variant) if variant.0 == Platform::MacOS => (variant, Some(primary)),
+      variant => (primary, variant),
+    };
+    Target {
+      platform: primary.0,
+      min_version: primary.1,
+      sdk_version: primary.2,
+      variant: variant.map(|(platform, min_version, sdk_version)| {
+        TargetVariant {
+          platform: platform,
+          min_version: min_version,
+          sdk_version: sdk_version,
+        }
+      }),
+    }
+  }
+
+  fn has_exported_symbols(&self) -> bool {
+    !(self.exported_symbols_lists.is_empty()
+      && self.exported_symbols.is_empty())
+  }
+
+  fn has_unexported_symbols(&self) -> bool {
+    !(self.unexported_symbols_lists.is_empty()
+      && self.unexported_symbols.is_empty())
+  }
+
+  // Read the export control files, and add the single symbols, into the
+  // set of exported symbols.
+  pub fn export_control(&self) -> io::Result<ExportControl> {
+    let mut list = SymbolList::new();
+    if self.has_exported_symbols() {
+      for path in self.exported_symbols_lists.iter() {
+        list.read(path)?;
+      }
+      for name in self.exported_symbols.iter() {
+        list.add(name);
+      }
+      Ok(ExportControl::Only(list))
+    } else if self.has_unexported_symbols() {
+      for path in self.unexported_symbols_lists.iter() {
+        list.read(path)?;
+      }
+      for name in self.unexported_symbols.iter() {
+        list.add(name);
+      }
+      Ok(ExportControl::AllExcept(list))
+    } else {
+      Ok(ExportControl::All)
+    }
+  }
+
+  // The symbols -interposable (all of them) and -interposable_list make
+  // interposable.
+  pub fn interposable_symbols(&self) -> io::Result<SymbolList> {
+    let mut list = SymbolList::new();
+    if self.interposable {
+      list.add("*");
+    }
+    for path in self.interposable_lists.iter() {
+      list.read(path)?;
+    }
+    Ok(list)
+  }
+
+  // All the aliases to define: those from -alias_list files, then -alias.
+  pub fn all_aliases(&self) -> io::Result<Vec<(String, String)>> {
+    let mut aliases: Vec<(String, String)> = Vec::new();
+    for path in self.alias_lists.iter() {
+      aliases.extend(symbol_list::read_alias_list(path)?);
+    }
+    aliases.extend(self.aliases.iter().cloned());
+    Ok(aliases)
+  }
+}
+
+// The options which take values, and how many, so that response files
+// aren't expanded in their place (`-rpath @loader_path/../lib`). GNU
+// spellings are included, since they're translated after expansion.
+const OPTION_VALUES: &[(&str, usize)] = &[
+  ("-o", 1),
+  ("-arch", 1),
+  ("-platform_version", 3),
+  ("-macosx_version_min", 1),
+  ("-ios_version_min", 1),
+  ("-iphoneos_version_min", 1),
+  ("-ios_simulator_version_min", 1),
+  ("-tvos_version_min", 1),
+  ("-watchos_version_min", 1),
+  ("-maccatalyst_version_min", 1),
+  ("-sdk_version", 1),
+  ("-image_base", 1),
+  ("-seg1addr", 1),
+  ("-segaddr", 2),
+  ("-headerpad", 1),
+  ("-stack_size", 1),
+  ("-stack_addr", 1),
+  ("-bundle_loader", 1),
+  ("-entitlements", 1),
+  ("-init", 1),
+  ("-order_file", 1),
+  ("-force_load", 1),
+  ("-segprot", 3),
+  ("-sectalign", 3),
+  ("-filelist", 1),
+  ("-why_live", 1),
+  ("--profile-order", 1),
+  ("--daemon-socket", 1),
+  ("-dependency_info", 1),
+  ("-oso_prefix", 1),
+  ("-lto_library", 1),
+  ("-object_path_lto", 1),
+  ("-exported_symbols_list", 1),
+  ("-unexported_symbols_list", 1),
+  ("-interposable_list", 1),
+  ("-exported_symbol", 1),
+  ("-unexported_symbol", 1),
+  ("-u", 1),
+  ("-undefined", 1),
+  ("-U", 1),
+  ("-dynamic_lookup_list", 1),
+  ("-alias", 2),
+  ("-alias_list", 1),
+  ("-sectcreate", 3),
+  ("-segcreate", 3),
+  ("-add_note", 2),
+  ("-add_load_command", 2),
+  ("-install_name", 1),
+  ("-dylib_install_name", 1),
+  ("-rpath", 1),
+  ("-source_version", 1),
+  ("-current_version", 1),
+  ("-dylib_current_version", 1),
+  ("-compatibility_version", 1),
+  ("-dylib_compatibility_version", 1),
+  ("-reexport_library", 1),
+  ("-weak_library", 1),
+  ("-upward_library", 1),
+  ("-framework", 1),
+  ("-weak_framework", 1),
+  ("-upward_framework", 1),
+  ("-syslibroot", 1),
+  ("-dylib_file", 1),
+  ("-umbrella", 1),
+  ("-sub_library", 1),
+  ("-allowable_client", 1),
+  ("-client_name", 1),
+  ("-add_empty_section", 2),
+  ("--rpath", 1),
+  ("-soname", 1),
+  ("--soname", 1),
+  ("--version-script", 1),
+  ("--dynamic-list", 1),
+  ("--export-dynamic-symbol", 1),
+];
+
+// How many values follow `opt` on the command line.
+fn value_count(opt: &str) -> usize {
+  OPTION_VALUES
+    .iter()
+    .find(|&&(name, _)| name == opt)
+    .map_or(0, |&(_, count)| count)
+}
+
+fn next_value<I: Iterator<Item = String>>(
+  opt: &str,
+  args: &mut I,
+) -> Result<String> {
+  args
+    .next()
+    .ok_or_else(|| ArgsError::MissingValue(opt.to_string()))
+}
+
+fn parse_version(opt: &str, val: &str) -> Result<Version> {
+  Version::parse(val)
+    .map_err(|_| ArgsError::InvalidValue(opt.to_string(), val.to_string()))
+}
+
+// A hexadecimal value, with or without a leading 0x, as ld64 takes them.
+fn parse_hex(opt: &str, val: &str) -> Result<u64> {
+  let digits = val.trim_start_matches("0x").trim_start_matches("0X");
+  u64::from_str_radix(digits, 16)
+    .map_err(|_| ArgsError::InvalidValue(opt.to_string(), val.to_string()))
+}
+
+// -segprot values: some of "rwx", "-" for none, or a number.
+fn parse_protection(opt: &str, val: &str) -> Result<u32> {
+  let invalid = || ArgsError::InvalidValue(opt.to_string(), val.to_string());
+  if val.starts_with(|c: char| c.is_digit(10)) {
+    return parse_hex(opt, val)
+      .ok()
+      .filter(|&prot| prot <= 0x7)
+      .map(|prot| prot as u32)
+      .ok_or_else(invalid);
+  }
+  val.chars().try_fold(VM_PROT_NONE, |prot, c| match c {
+    'r' => Ok(prot | VM_PROT_READ),
+    'w' => Ok(prot | VM_PROT_WRITE),
+    'x' => Ok(prot | VM_PROT_EXECUTE),
+    '-' => Ok(prot),
+    _ => Err(invalid()),
+  })
+}
+
+fn parse_segprot<I: Iterator<Item = String>>(
+  opt: &str,
+  args: &mut I,
+) -> Result<SegmentProtection> {
+  let segname = next_value(opt, args)?;
+  let maxprot = parse_protection(opt, &next_value(opt, args)?)?;
+  let initprot = parse_protection(opt, &next_value(opt, args)?)?;
+  if initprot & !maxprot != 0 {
+    return Err(ArgsError::Incompatible(format!(
+      "-segprot {}: the initial protection exceeds the maximum",
+      segname
+    )));
+  }
+  Ok(SegmentProtection {
+    segname: segname,
+    maxprot: maxprot,
+    initprot: initprot,
+  })
+}
+
+// -sectalign <segname> <sectname> <align>: a power of 2 up to 0x8000.
+fn parse_sectalign<I: Iterator<Item = String>>(
+  opt: &str,
+  args: &mut I,
+) -> Result<SectionAlignment> {
+  let segname = next_value(opt, args)?;
+  let sectname = next_value(opt, args)?;
+  let val = next_value(opt, args)?;
+  let align = parse_hex(opt, &val)?;
+  if !align.is_power_of_two() || align > 0x8000 {
+    return Err(ArgsError::InvalidValue(opt.to_string(), val));
+  }
+  Ok(SectionAlignment {
+    segname: segname,
+    sectname: sectname,
+    align: align.trailing_zeros(),
+  })
+}
+
+// -filelist <path>[,<dirname>]: input files one per line, relative to
+// `dirname` if it's given.
+fn read_file_list(val: &str) -> Result<Vec<PathBuf>> {
+  let (path, dir) = match val.find(',') {
+    Some(i) => (PathBuf::from(&val[..i]), Some(PathBuf::from(&val[(i + 1)..]))),
+    None => (PathBuf::from(val), None),
+  };
+  let mut text = String::new();
+  File::open(&path)
+    .and_then(|mut f| f.read_to_string(&mut text))
+    .map_err(|e| ArgsError::FileList(path.clone(), e))?;
+  Ok(
+    text
+      .lines()
+      .map(|line| line.trim_end_matches('\r'))
+      .filter(|line| !line.is_empty())
+      .map(|line| match dir {
+        Some(ref dir) => dir.join(line),
+        None => PathBuf::from(line),
+      })
+      .collect(),
+  )
+}
+
+// Segment and section names are fixed 16-byte fields.
+fn parse_name16(opt: &str, name: String) -> Result<String> {
+  if name.is_empty() || name.len() > 16 {
+    Err(ArgsError::InvalidValue(opt.to_string(), name))
+  } else {
+    Ok(name)
+  }
+}
+
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<LinkOptions> {
+  let args = response_file::expand(args, value_count)?;
+  ld_env::print_options(&args);
+  let mut args = gnu_args::translate(args)?.into_iter();
+  let mut opts = LinkOptions::default();
+  ld_env::apply(&mut opts);
+  let mut platform_given = false;
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-o" => {
+        opts.output_path = PathBuf::from(next_value(&arg, &mut args)?);
+      }
+      "-arch" => {
+        let name = next_value(&arg, &mut args)?;
+        let arch = Arch::from_name(&name)
+          .ok_or_else(|| ArgsError::InvalidValue(arg.clone(), name.clone()))?;
+        if !opts.archs.contains(&arch) {
+          opts.archs.push(arch);
+        }
+      }
+      "-platform_version" => {
+        let name = next_value(&arg, &mut args)?;
+        let platform = Platform::from_name(&name)
+          .ok_or_else(|| ArgsError::InvalidValue(arg.clone(), name.clone()))?;
+        let min = next_value(&arg, &mut args)?;
+        let min = parse_version(&arg, &min)?;
+        let sdk = next_value(&arg, &mut args)?;
+        let sdk = parse_version(&arg, &sdk)?;
+        // A second platform makes the output zippered.
+        if platform_given && platform != opts.platform {
+          opts.platform_variant = Some((platform, min, sdk));
+        } else {
+          opts.platform = platform;
+          opts.min_os_version = min;
+          opts.sdk_version = Some(sdk);
+        }
+        platform_given = true;
+      }
+      "-macosx_version_min"
+      | "-ios_version_min"
+      | "-iphoneos_version_min"
+      | "-ios_simulator_version_min"
+      | "-tvos_version_min"
+      | "-watchos_version_min"
+      | "-maccatalyst_version_min" => {
+        let val = next_value(&arg, &mut args)?;
+        opts.platform = match arg.as_str() {
+          "-macosx_version_min" => Platform::MacOS,
+          "-maccatalyst_version_min" => Platform::MacCatalyst,
+          "-ios_simulator_version_min" => Platform::IOSSimulator,
+          "-tvos_version_min" => Platform::TvOS,
+          "-watchos_version_min" => Platform::WatchOS,
+          _ => Platform::IOS,
+        };
+        opts.min_os_version = parse_version(&arg, &val)?;
+      }
+      "-sdk_version" => {
+        let val = next_value(&arg, &mut args)?;
+        opts.sdk_version = Some(parse_version(&arg, &val)?);
+      }
+      "-execute" => opts.output_kind = OutputKind::Executable,
+      "-dylib" => opts.output_kind = OutputKind::Dylib,
+      "-bundle" => opts.output_kind = OutputKind::Bundle,
+      "-kext" => opts.output_kind = OutputKind::KextBundle,
+      "-preload" => opts.output_kind = OutputKind::Preload,
+      "-static" => opts.static_link = true,
+      "-dynamic" => opts.static_link = false,
+      "-image_base" | "-seg1addr" => {
+        let val = next_value(&arg, &mut args)?;
+        opts.image_base = Some(parse_hex(&arg, &val)?);
+      }
+      "-segaddr" => {
+        let segname = parse_name16(&arg, next_value(&arg, &mut args)?)?;
+        let val = next_value(&arg, &mut args)?;
+        let addr = parse_hex(&arg, &val)?;
+        opts.segment_addresses.push((segname, addr));
+      }
+      "-r" => opts.output_kind = OutputKind::Relocatable,
+      "-keep_private_externs" => opts.keep_private_externs = true,
+      "-pie" => opts.pie = Some(true),
+      "-no_pie" => opts.pie = Some(false),
+      "-allow_heap_execute" => opts.allow_heap_execute = true,
+      "-headerpad" => {
+        let val = next_value(&arg, &mut args)?;
+        opts.headerpad = Some(parse_hex(&arg, &val)?);
+      }
+      "-headerpad_max_install_names" => opts.headerpad_max_install_names = true,
+      "-stack_size" => {
+        let val = next_value(&arg, &mut args)?;
+        opts.stack_size = Some(parse_hex(&arg, &val)?);
+      }
+      "-stack_addr" => {
+        let val = next_value(&arg, &mut args)?;
+        opts.stack_addr = Some(parse_hex(&arg, &val)?);
+      }
+      "-no_uuid" => opts.uuid = UuidMode::None,
+      "-random_uuid" => opts.uuid = UuidMode::Random,
+      "-bundle_loader" => {
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.bundle_loader = Some(path);
+      }
+      "-application_extension" => opts.application_extension = true,
+      "-no_application_extension" => opts.application_extension = false,
+      "-mark_dead_strippable_dylib" => opts.mark_dead_strippable_dylib = true,
+      "-fixup_chains" => opts.fixup_chains = Some(true),
+      "-no_fixup_chains" => opts.fixup_chains = Some(false),
+      "-data_const" => opts.data_const = Some(true),
+      "-no_data_const" => opts.data_const = Some(false),
+      "-encryptable" => opts.encryptable = Some(true),
+      "-no_encryption" => opts.encryptable = Some(false),
+      "-adhoc_codesign" => opts.adhoc_codesign = Some(true),
+      "-no_adhoc_codesign" => opts.adhoc_codesign = Some(false),
+      "-entitlements" => {
+        opts.entitlements = Some(PathBuf::from(next_value(&arg, &mut args)?));
+      }
+      "-hardened_runtime" => opts.hardened_runtime = true,
+      "-library_validation" => opts.library_validation = true,
+      "-no_weak_exports" => opts.no_weak_exports = true,
+      "-init" => opts.init_symbol = Some(next_value(&arg, &mut args)?),
+      "-order_file" => {
+        opts.order_file = Some(PathBuf::from(next_value(&arg, &mut args)?));
+      }
+      "-incremental" => opts.incremental = true,
+      "-t" => opts.trace_files = true,
+      "-v" => opts.verbose = true,
+      "-dead_strip" => opts.dead_strip = true,
+      "-ObjC" => opts.objc = true,
+      "-all_load" => opts.all_load = true,
+      "-noall_load" => opts.all_load = false,
+      "-force_load" => {
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.input_paths.push(path.clone());
+        opts.force_load.push(path);
+      }
+      "-objc_category_merging" => opts.objc_category_merging = Some(true),
+      "-no_objc_category_merging" => {
+        opts.objc_category_merging = Some(false);
+      }
+      "-reproducible" => opts.reproducible = true,
+      "-dsym" => opts.dsym = true,
+      "-segprot" => {
+        let prot = parse_segprot(&arg, &mut args)?;
+        opts.segment_protections.push(prot);
+      }
+      "-sectalign" => {
+        let align = parse_sectalign(&arg, &mut args)?;
+        opts.section_alignments.push(align);
+      }
+      "-x" => opts.strip.locals = true,
+      "-S" => opts.strip.debug = true,
+      "-exported_only" => opts.strip.non_exported = true,
+      "-filelist" => {
+        let val = next_value(&arg, &mut args)?;
+        opts.input_paths.extend(read_file_list(&val)?);
+      }
+      "-why_live" => opts.why_live.push(next_value(&arg, &mut args)?),
+      "-why_load" | "-whyload" => opts.why_load = true,
+      "--size-report" => opts.size_report = true,
+      "--time-passes" | "--time-passes=text" => {
+        opts.time_passes = Some(Format::Text)
+      }
+      "--time-passes=json" => opts.time_passes = Some(Format::Json),
+      "-print_statistics" => opts.print_statistics = true,
+      "--untrusted-inputs" => opts.untrusted_inputs = true,
+      "--skip-unchanged" => opts.skip_unchanged = true,
+      "--verify-output" => opts.verify_output = true,
+      "--shared-cache-eligible" => opts.shared_cache_eligible = true,
+      "-not_for_dyld_shared_cache" => opts.shared_cache_eligible = false,
+      "--profile-order" => {
+        let path = next_value(&arg, &mut args)?;
+        opts.profile_order = Some(PathBuf::from(path));
+      }
+      "--split-cold" => opts.split_cold = true,
+      "--split-cold-report" => opts.split_cold_report = true,
+      "--daemon-socket" => {
+        opts.daemon_socket = Some(PathBuf::from(next_value(&arg, &mut args)?));
+      }
+      "-dependency_info" => {
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.dependency_info = Some(path);
+      }
+      "-oso_prefix" => {
+        opts.oso_prefix = Some(PathBuf::from(next_value(&arg, &mut args)?));
+      }
+      "-lto_library" => {
+        opts.lto_library = Some(PathBuf::from(next_value(&arg, &mut args)?));
+      }
+      "-object_path_lto" => {
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.object_path_lto = Some(path);
+      }
+      "-exported_symbols_list" => {
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.exported_symbols_lists.push(path);
+      }
+      "-unexported_symbols_list" => {
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.unexported_symbols_lists.push(path);
+      }
+      "-interposable" => opts.interposable = true,
+      "-interposable_list" => {
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.interposable_lists.push(path);
+      }
+      "-exported_symbol" => {
+        opts.exported_symbols.push(next_value(&arg, &mut args)?);
+      }
+      "-unexported_symbol" => {
+        opts.unexported_symbols.push(next_value(&arg, &mut args)?);
+      }
+      "-u" => opts.forced_undefined.push(next_value(&arg, &mut args)?),
+      "-undefined" => {
+        let val = next_value(&arg, &mut args)?;
+        opts.undefined = match UndefinedTreatment::parse(&val) {
+          Some(treatment) => Some(treatment),
+          None => return Err(ArgsError::InvalidValue(arg, val)),
+        };
+      }
+      "-U" => opts.dynamic_lookup_symbols.push(next_value(&arg, &mut args)?),
+      "-dynamic_lookup_list" => {
+        let path = next_value(&arg, &mut args)?;
+        opts.dynamic_lookup_lists.push(PathBuf::from(path));
+      }
+      "-alias" => {
+        let target = next_value(&arg, &mut args)?;
+        let alias = next_value(&arg, &mut args)?;
+        opts.aliases.push((target, alias));
+      }
+      "-alias_list" => {
+        opts.alias_lists.push(PathBuf::from(next_value(&arg, &mut args)?));
+      }
+      "-sectcreate" | "-segcreate" => {
+        let segname = parse_name16(&arg, next_value(&arg, &mut args)?)?;
+        let sectname = parse_name16(&arg, next_value(&arg, &mut args)?)?;
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.created_sections.push(CreatedSection {
+          segname: segname,
+          sectname: sectname,
+          path: Some(path),
+        });
+      }
+      "-add_note" => {
+        let owner = parse_name16(&arg, next_value(&arg, &mut args)?)?;
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.notes.push((owner, path));
+      }
+      "-add_load_command" => {
+        let val = next_value(&arg, &mut args)?;
+        let cmd = parse_hex(&arg, &val)?;
+        if cmd > u32::max_value() as u64 {
+          return Err(ArgsError::InvalidValue(arg.clone(), val));
+        }
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.extra_load_commands.push((cmd as u32, path));
+      }
+      "-install_name" | "-dylib_install_name" => {
+        opts.install_name = Some(next_value(&arg, &mut args)?);
+      }
+      "-rpath" => opts.rpaths.push(next_value(&arg, &mut args)?),
+      "-source_version" => {
+        let val = next_value(&arg, &mut args)?;
+        let version = SourceVersion::parse(&val)
+          .map_err(|_| ArgsError::InvalidValue(arg.clone(), val.clone()))?;
+        opts.source_version = Some(version);
+      }
+      "-add_source_version" => opts.add_source_version = Some(true),
+      "-no_source_version" => opts.add_source_version = Some(false),
+      "-current_version" | "-dylib_current_version" => {
+        let val = next_value(&arg, &mut args)?;
+        opts.current_version = Some(parse_version(&arg, &val)?);
+      }
+      "-compatibility_version" | "-dylib_compatibility_version" => {
+        let val = next_value(&arg, &mut args)?;
+        opts.compatibility_version = Some(parse_version(&arg, &val)?);
+      }
+      "-reexport_library" => {
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.reexport_libraries.push(path);
+      }
+      "-weak_library" => {
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.weak_libraries.push(path);
+      }
+      "-upward_library" => {
+        let path = PathBuf::from(next_value(&arg, &mut args)?);
+        opts.upward_libraries.push(path);
+      }
+      "-framework" => opts.frameworks.push(next_value(&arg, &mut args)?),
+      "-weak_framework" => {
+        opts.weak_frameworks.push(next_value(&arg, &mut args)?);
+      }
+      "-upward_framework" => {
+        opts.upward_frameworks.push(next_value(&arg, &mut args)?);
+      }
+      "-syslibroot" => {
+        opts
+          .syslibroots
+          .push(PathBuf::from(next_value(&arg, &mut args)?));
+      }
+      "-dylib_file" => {
+        let val = next_value(&arg, &mut args)?;
+        let (install_name, path) = match val.find(':') {
+          Some(idx) if idx > 0 && idx + 1 < val.len() => {
+            (val[..idx].to_string(), PathBuf::from(&val[(idx + 1)..]))
+          }
+          _ => return Err(ArgsError::InvalidValue(arg.clone(), val.clone())),
+        };
+        opts.dylib_files.push((install_name, path));
+      }
+      "-umbrella" => opts.umbrella = Some(next_value(&arg, &mut args)?),
+      "-sub_library" => {
+        opts.sub_libraries.push(next_value(&arg, &mut args)?);
+      }
+      "-allowable_client" => {
+        opts.allowable_clients.push(next_value(&arg, &mut args)?);
+      }
+      "-client_name" => {
+        opts.client_name = Some(next_value(&arg, &mut args)?)
+      }
+      "-add_empty_section" => {
+        let segname = parse_name16(&arg, next_value(&arg, &mut args)?)?;
+        let sectname = parse_name16(&arg, next_value(&arg, &mut args)?)?;
+        opts.created_sections.push(CreatedSection {
+          segname: segname,
+          sectname: sectname,
+          path: None,
+        });
+      }
+      _ if arg.starts_with("-l") && arg.len() > 2 => {
+        opts.libraries.push(arg[2..].to_string());
+      }
+      _ if arg.starts_with("-reexport-l") && arg.len() > 11 => {
+        opts.reexported_library_names.push(arg[11..].to_string());
+      }
+      _ if arg.starts_with("-weak-l") && arg.len() > 7 => {
+        opts.weak_library_names.push(arg[7..].to_string());
+      }
+      _ if arg.starts_with("-upward-l") && arg.len() > 9 => {
+        opts.upward_library_names.push(arg[9..].to_string());
+      }
+      _ if arg.starts_with("-F") && arg.len() > 2 => {
+        opts.framework_paths.push(PathBuf::from(&arg[2..]));
+      }
+      _ if arg.starts_with("-L") && arg.len() > 2 => {
+        opts.library_paths.push(PathBuf::from(&arg[2..]));
+      }
+      _ if arg.starts_with("--diagnostics-format=") => {
+        opts.diagnostics_format = match &arg[21..] {
+          "text" => Format::Text,
+          "json" => Format::Json,
+          val => {
+            return Err(ArgsError::InvalidValue(
+              "--diagnostics-format".to_string(),
+              val.to_string(),
+            ))
+          }
+        };
+      }
+      _ if arg.starts_with("--lto-backend=") => {
+        opts.lto_backend = Some(match &arg[14..] {
+          "liblto" => LtoBackend::LibLto,
+          #[cfg(feature = "llvm")]
+          "llvm" => LtoBackend::Llvm,
+          val => {
+            return Err(ArgsError::InvalidValue(
+              "--lto-backend".to_string(),
+              val.to_string(),
+            ))
+          }
+        });
+      }
+      _ if arg.starts_with("--lto-O") => {
+        let level = arg[7..].parse().ok().filter(|&level: &u32| level <= 3);
+        opts.lto_opt_level = level.ok_or_else(|| {
+          ArgsError::InvalidValue("--lto-O".to_string(), arg[7..].into())
+        })?;
+      }
+      _ if arg.starts_with("--lto-cpu=") => {
+        opts.lto_cpu = Some(arg[10..].to_string());
+      }
+      _ if arg.starts_with("--lto-features=") => {
+        opts.lto_features = Some(arg[15..].to_string());
+      }
+      "--thinlto" => opts.thinlto = true,
+      _ if arg.starts_with("--thinlto-jobs=") => {
+        let jobs = &arg[15..];
+        opts.thinlto = true;
+        opts.thinlto_jobs = jobs.parse().map_err(|_| {
+          ArgsError::InvalidValue("--thinlto-jobs".to_string(), jobs.into())
+        })?;
+      }
+      _ if arg.starts_with("--profile-order=") => {
+        let path = &arg["--profile-order=".len()..];
+        opts.profile_order = Some(PathBuf::from(path));
+      }
+      _ if arg.starts_with("--error-limit=") => {
+        opts.error_limit = arg[14..].parse().map_err(|_| {
+          ArgsError::InvalidValue("--error-limit".to_string(), arg[14..].into())
+        })?;
+      }
+      _ if arg.starts_with("--threads=") => {
+        opts.threads = arg[10..].parse().map_err(|_| {
+          ArgsError::InvalidValue("--threads".to_string(), arg[10..].into())
+        })?;
+      }
+      _ if arg.starts_with('-') => {
+        return Err(ArgsError::UnknownOption(arg));
+      }
+      _ => opts.input_paths.push(PathBuf::from(arg)),
+    }
+  }
+
+  opts.finish()?;
+  Ok(opts)
+}
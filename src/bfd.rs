@@ -2,8 +2,15 @@ extern crate bfd_sys;
 extern crate libc;
 
 use self::bfd_sys::{bfd, bfd_hash_table, bfd_link_info, bfd_target};
+// The section and symbol flags of foreign objects.
+pub use self::bfd_sys::{BSF_DEBUGGING, BSF_FILE, BSF_GLOBAL, BSF_LOCAL,
+                        BSF_SECTION_SYM, BSF_WEAK, SEC_ALLOC, SEC_CODE,
+                        SEC_HAS_CONTENTS, SEC_MERGE, SEC_READONLY,
+                        SEC_STRINGS, SEC_THREAD_LOCAL};
 
-use std::ffi::CStr;
+use resolve::{InputSymbol, SymbolKind};
+
+use std::ffi::{CStr, CString};
 use std::fmt;
 use std::io;
 use std::mem::size_of;
@@ -19,6 +26,10 @@ pub enum BFDError {
   NullPtrError,
   IoError(io::Error),
   LinkError,
+  SymbolTableError,
+  // (section name) whose relocations or contents couldn't be read.
+  RelocationError(String),
+  ContentsError(String),
 }
 
 impl From<io::Error> for BFDError {
@@ -59,7 +70,9 @@ fn ptr_mut_opt<'a, T>(ptr: *mut T) -> Option<&'a mut T> {
   }
 }
 
-pub fn openr<'a>(path: &'a Path, target: &'a str) -> Result<&'a mut bfd> {
+// The handles belong to libbfd, not to the path and target they're opened
+// with.
+pub fn openr<'a>(path: &Path, target: &str) -> Result<&'a mut bfd> {
   let in_obj_path_c_char_vec: Vec<c_char> = path_to_c_char_vec(path);
   let in_obj_path_c_str: *const c_char =
     in_obj_path_c_char_vec.as_slice().as_ptr();
@@ -76,7 +89,7 @@ pub fn openr<'a>(path: &'a Path, target: &'a str) -> Result<&'a mut bfd> {
   }
 }
 
-pub fn openw<'a>(path: &'a Path, target: &'a str) -> Result<&'a mut bfd> {
+pub fn openw<'a>(path: &Path, target: &str) -> Result<&'a mut bfd> {
   let out_obj_path_c_char_vec: Vec<c_char> = path_to_c_char_vec(path);
   let out_obj_path_c_str: *const c_char =
     out_obj_path_c_char_vec.as_slice().as_ptr();
@@ -147,15 +160,266 @@ impl<'a> BFDHandle<'a> {
   }
 
   unsafe fn final_link(&mut self, link_info: &mut bfd_link_info) -> bool {
-    eprintln!("final_link: 1");
     let fun = (*self.bfd.xvec)._bfd_final_link.unwrap();
-    eprintln!("final_link: 2");
     fun(self.bfd, link_info) != 0
   }
 
   fn target(&self) -> Option<&bfd_target> {
     ptr_opt(self.bfd.xvec)
   }
+
+  // Read the external symbols of an input file into the form the resolver
+  // uses. Local symbols don't take part in resolution and are skipped.
+  pub fn symbols(&mut self) -> Result<Vec<InputSymbol>> {
+    let mut syms: Vec<InputSymbol> = Vec::new();
+    unsafe {
+      let upper_bound_fun =
+        (*self.bfd.xvec)._bfd_get_symtab_upper_bound.unwrap();
+      let upper_bound = upper_bound_fun(self.bfd);
+      if upper_bound < 0 {
+        return Err(BFDError::SymbolTableError);
+      }
+      let mut sym_ptrs: Vec<*mut bfd_sys::asymbol> = vec![
+        ptr::null_mut();
+        upper_bound as usize / size_of::<*mut bfd_sys::asymbol>()
+      ];
+      let canonicalize_fun = (*self.bfd.xvec)._bfd_canonicalize_symtab.unwrap();
+      let count = canonicalize_fun(self.bfd, sym_ptrs.as_mut_ptr());
+      if count < 0 {
+        return Err(BFDError::SymbolTableError);
+      }
+
+      for sym_ptr in sym_ptrs[..count as usize].iter() {
+        let sym: &bfd_sys::asymbol = &**sym_ptr;
+        let section: &bfd_sys::asection = &*sym.section;
+        let section_name = CStr::from_ptr(section.name).to_string_lossy();
+        let kind = if section_name == "*UND*" {
+          SymbolKind::Undefined
+        } else if sym.flags & bfd_sys::BSF_WEAK != 0 {
+          SymbolKind::WeakDefined
+        } else if sym.flags & bfd_sys::BSF_GLOBAL != 0 {
+          SymbolKind::Defined
+        } else {
+          continue;
+        };
+        syms.push(InputSymbol {
+          name: CStr::from_ptr(sym.name).to_string_lossy().into_owned(),
+          kind: kind,
+          // TODO: read N_PEXT from the mach-o specific symbol, which needs
+          // mach-o.h in our bindings.
+          private_extern: false,
+          value: sym.value + section.vma,
+        });
+      }
+    }
+    Ok(syms)
+  }
+}
+
+// What a relocation in a foreign object refers to: an entry in its symbol
+// table, or (for BFD's section symbols, which aren't in the table) the
+// start of one of its sections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocTarget {
+  Symbol(usize),
+  Section(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct ForeignReloc {
+  pub address: u64,
+  pub target: RelocTarget,
+  pub addend: i64,
+  // The format's own relocation type (r_type, for ELF).
+  pub kind: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForeignSection {
+  pub name: String,
+  // BFD's SEC_* flags.
+  pub flags: u32,
+  pub size: u64,
+  pub alignment_power: u32,
+  // Empty for sections without contents (.bss).
+  pub contents: Vec<u8>,
+  pub relocs: Vec<ForeignReloc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForeignSymbolKind {
+  // Defined in sections[n], at an offset from its start.
+  Defined(usize),
+  Absolute,
+  Undefined,
+  // A common symbol, whose value is its size.
+  Common,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForeignSymbol {
+  pub name: String,
+  // BFD's BSF_* flags.
+  pub flags: u32,
+  pub kind: ForeignSymbolKind,
+  pub value: u64,
+}
+
+// An object file in a format other than Mach-O, as BFD sees it.
+#[derive(Debug, Clone)]
+pub struct ForeignObject {
+  pub sections: Vec<ForeignSection>,
+  pub symbols: Vec<ForeignSymbol>,
+}
+
+// Read an object file in any format BFD was built for (ELF, with
+// --enable-targets), letting BFD work out which.
+pub fn read_foreign_object(path: &Path) -> Result<ForeignObject> {
+  let path_c_str = path
+    .to_str()
+    .and_then(|path| CString::new(path).ok())
+    .ok_or(BFDError::NullPtrError)?;
+  let abfd: *mut bfd;
+  unsafe {
+    abfd = bfd_sys::bfd_openr(path_c_str.as_ptr(), ptr::null());
+  }
+  let handle = BFDHandle {
+    bfd: ptr_mut_opt(abfd).ok_or(BFDError::NullPtrError)?,
+  };
+  unsafe {
+    let format_check_result =
+      bfd_sys::bfd_check_format(handle.bfd, bfd_sys::bfd_format_bfd_object);
+    if format_check_result == 0 {
+      return Err(BFDError::FormatCheckError);
+    }
+  }
+  let mut section_ptrs: Vec<*mut bfd_sys::asection> = Vec::new();
+  let mut sect_ptr = handle.bfd.sections;
+  while let Some(sect) = ptr_mut_opt(sect_ptr) {
+    sect_ptr = sect.next;
+    section_ptrs.push(sect);
+  }
+  let section_index = |sect: *const bfd_sys::asection| {
+    section_ptrs.iter().position(|&s| s as *const _ == sect)
+  };
+
+  let mut sym_ptrs: Vec<*mut bfd_sys::asymbol>;
+  let mut symbols: Vec<ForeignSymbol> = Vec::new();
+  unsafe {
+    // These are macros over the target vector in bfd.h.
+    let upper_bound_fun =
+      (*handle.bfd.xvec)._bfd_get_symtab_upper_bound.unwrap();
+    let upper_bound = upper_bound_fun(handle.bfd);
+    if upper_bound < 0 {
+      return Err(BFDError::SymbolTableError);
+    }
+    sym_ptrs = vec![
+      ptr::null_mut();
+      upper_bound as usize / size_of::<*mut bfd_sys::asymbol>()
+    ];
+    let canonicalize_fun =
+      (*handle.bfd.xvec)._bfd_canonicalize_symtab.unwrap();
+    let count = canonicalize_fun(handle.bfd, sym_ptrs.as_mut_ptr());
+    if count < 0 {
+      return Err(BFDError::SymbolTableError);
+    }
+    sym_ptrs.truncate(count as usize);
+    for sym_ptr in sym_ptrs.iter() {
+      let sym: &bfd_sys::asymbol = &**sym_ptr;
+      let section: &bfd_sys::asection = &*sym.section;
+      let section_name = CStr::from_ptr(section.name).to_string_lossy();
+      let kind = match section_name.as_ref() {
+        "*UND*" => ForeignSymbolKind::Undefined,
+        "*COM*" => ForeignSymbolKind::Common,
+        "*ABS*" => ForeignSymbolKind::Absolute,
+        _ => match section_index(section) {
+          Some(idx) => ForeignSymbolKind::Defined(idx),
+          None => return Err(BFDError::SymbolTableError),
+        },
+      };
+      symbols.push(ForeignSymbol {
+        name: CStr::from_ptr(sym.name).to_string_lossy().into_owned(),
+        flags: sym.flags,
+        kind: kind,
+        // Section-relative, as BFD reads it.
+        value: sym.value,
+      });
+    }
+  }
+
+  let mut sections: Vec<ForeignSection> = Vec::new();
+  for &sect_ptr in section_ptrs.iter() {
+    let sect: &bfd_sys::asection = unsafe { &*sect_ptr };
+    let name = unsafe { CStr::from_ptr(sect.name) };
+    let name = name.to_string_lossy().into_owned();
+    let mut contents: Vec<u8> = Vec::new();
+    if sect.flags & bfd_sys::SEC_HAS_CONTENTS != 0 {
+      contents = vec![0; sect.size as usize];
+      let ok = unsafe {
+        bfd_sys::bfd_get_section_contents(
+          handle.bfd,
+          sect_ptr,
+          contents.as_mut_ptr() as *mut libc::c_void,
+          0,
+          sect.size,
+        )
+      };
+      if ok == 0 {
+        return Err(BFDError::ContentsError(name));
+      }
+    }
+    let mut relocs: Vec<ForeignReloc> = Vec::new();
+    unsafe {
+      let upper_bound =
+        bfd_sys::bfd_get_reloc_upper_bound(handle.bfd, sect_ptr);
+      if upper_bound < 0 {
+        return Err(BFDError::RelocationError(name));
+      }
+      let mut reloc_ptrs: Vec<*mut bfd_sys::arelent> = vec![
+        ptr::null_mut();
+        upper_bound as usize / size_of::<*mut bfd_sys::arelent>()
+      ];
+      let count = bfd_sys::bfd_canonicalize_reloc(
+        handle.bfd,
+        sect_ptr,
+        reloc_ptrs.as_mut_ptr(),
+        sym_ptrs.as_mut_ptr(),
+      );
+      if count < 0 {
+        return Err(BFDError::RelocationError(name));
+      }
+      for reloc_ptr in reloc_ptrs[..count as usize].iter() {
+        let reloc: &bfd_sys::arelent = &**reloc_ptr;
+        let sym = *reloc.sym_ptr_ptr;
+        let target = match sym_ptrs.iter().position(|&s| s == sym) {
+          Some(idx) => RelocTarget::Symbol(idx),
+          None => section_index((*sym).section)
+            .map(RelocTarget::Section)
+            .ok_or_else(|| BFDError::RelocationError(name.clone()))?,
+        };
+        let howto = ptr_opt(reloc.howto)
+          .ok_or_else(|| BFDError::RelocationError(name.clone()))?;
+        relocs.push(ForeignReloc {
+          address: reloc.address,
+          target: target,
+          addend: reloc.addend as i64,
+          kind: howto.type_,
+        });
+      }
+    }
+    sections.push(ForeignSection {
+      name: name,
+      flags: sect.flags,
+      size: sect.size,
+      alignment_power: sect.alignment_power,
+      contents: contents,
+      relocs: relocs,
+    });
+  }
+  Ok(ForeignObject {
+    sections: sections,
+    symbols: symbols,
+  })
 }
 
 pub struct LinkProcess {
@@ -193,7 +457,6 @@ pub fn make_executable<'a>(
   // clang_rt: &Path,
   out_path: &'a Path,
 ) -> Result<&'a Path> {
-  eprintln!("1");
   let mut tbl = bfd_hash_table {
     ..Default::default()
   };
@@ -204,13 +467,10 @@ pub fn make_executable<'a>(
       size_of::<bfd_sys::bfd_hash_entry>() as u32,
     );
   }
-  eprintln!("2");
 
   // Create the output object file.
   let mut obj_out = BFDHandle::for_output_obj_file(out_path)?;
 
-  eprintln!("3");
-
   let mut link_info: bfd_link_info;
   unsafe {
     link_info = bfd_link_info {
@@ -220,22 +480,16 @@ pub fn make_executable<'a>(
     };
     link_info.input_bfds_tail = &mut link_info.input_bfds;
   };
-  eprintln!("4");
   // Read in the input object file.
   let obj_in = BFDHandle::for_input_file(object_path)?;
-  eprintln!("5");
   // Add symbols from the input object file.
   unsafe {
-    eprintln!("5.1");
     (*link_info.input_bfds_tail) = obj_in.bfd;
-    eprintln!("5.2");
     link_info.input_bfds_tail = &mut obj_in.bfd.link.next;
-    eprintln!("5.3");
     if !obj_out.final_link(&mut link_info) {
       return Err(BFDError::LinkError);
     }
   }
-  eprintln!("6");
 
   // Add symbols from the -lSystem library.
   // Add symbols from the clang runtime archive libclang_rt.osx.a.
@@ -0,0 +1,69 @@
+// `cargo mach-link [<command>] --target <apple triple> [<args>...]`: run a
+// cargo command (`build` by default) with mach-o-linker as the linker for
+// each Apple target given, to cross-link for macOS and iOS from any host.
+
+use std::env;
+use std::ffi::OsString;
+use std::process::{self, Command};
+
+// The Apple triples among cargo's `--target` options.
+fn apple_targets(args: &[String]) -> Vec<String> {
+  let mut targets: Vec<String> = Vec::new();
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    let target = if arg == "--target" {
+      args.next().cloned()
+    } else if arg.starts_with("--target=") {
+      Some(arg[9..].to_string())
+    } else {
+      None
+    };
+    targets.extend(target.filter(|target| target.contains("-apple-")));
+  }
+  targets
+}
+
+// Cargo's variable for the linker of `target`.
+fn linker_variable(target: &str) -> String {
+  let target: String = target
+    .chars()
+    .map(|c| if c.is_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+    .collect();
+  format!("CARGO_TARGET_{}_LINKER", target)
+}
+
+fn main() {
+  // Cargo runs `cargo-mach-link mach-link <args>...`.
+  let mut args: Vec<String> = env::args().skip(2).collect();
+  if args.first().map_or(true, |arg| arg.starts_with('-')) {
+    args.insert(0, "build".to_string());
+  }
+  let targets = apple_targets(&args);
+  if targets.is_empty() {
+    eprintln!("error: cargo mach-link needs --target <apple triple>");
+    process::exit(1);
+  }
+  let linker = env::current_exe().map(|exe| {
+    exe.with_file_name(format!("mach-o-linker{}", env::consts::EXE_SUFFIX))
+  });
+  let linker = match linker {
+    Ok(linker) => linker,
+    Err(e) => {
+      eprintln!("error: unable to find mach-o-linker: {}", e);
+      process::exit(1);
+    }
+  };
+  let cargo = env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo"));
+  let mut command = Command::new(cargo);
+  command.args(&args);
+  for target in targets.iter() {
+    command.env(linker_variable(target), &linker);
+  }
+  match command.status() {
+    Ok(status) => process::exit(status.code().unwrap_or(1)),
+    Err(e) => {
+      eprintln!("error: unable to run cargo: {}", e);
+      process::exit(1);
+    }
+  }
+}
@@ -0,0 +1,24 @@
+// The linker as rustc and build systems expect to run a C compiler to
+// link: `linker = "mach-o-linker"` in .cargo/config runs this with `cc`'s
+// arguments, which it hands to `mold --flavor=cc` (installed alongside it).
+
+use std::env;
+use std::process::{self, Command};
+
+fn main() {
+  let exe = env::current_exe()
+    .map(|exe| exe.with_file_name(format!("mold{}", env::consts::EXE_SUFFIX)));
+  let status = exe.and_then(|mold| {
+    Command::new(mold)
+      .arg("--flavor=cc")
+      .args(env::args_os().skip(1))
+      .status()
+  });
+  match status {
+    Ok(status) => process::exit(status.code().unwrap_or(1)),
+    Err(e) => {
+      eprintln!("error: unable to run mold: {}", e);
+      process::exit(1);
+    }
+  }
+}
@@ -0,0 +1,279 @@
+// `mold build-version`: vtool's view of the platform an image was built
+// for, and changing it, e.g. to turn a device build into a simulator one.
+//
+//   mold build-version [-arch <name>] <file>...
+//   mold build-version <change>... [-o <output>] <file>
+//
+// where a change is one of
+//
+//   -set-build-version <platform> <minos> <sdk> [-tool <tool> <version>]...
+//   -set-version-min <platform> <version> <sdk>
+//   -remove-build-version <platform>
+//
+// The -set options replace all of the image's LC_BUILD_VERSION and
+// LC_VERSION_MIN_* commands; give two of them for a zippered image. The
+// file is changed in place without -o.
+
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use edit::{self, Edit, EditError};
+use inspect::command_name;
+use macho::fat;
+use macho::load_command::{load_commands, LoadCommand, LC_BUILD_VERSION,
+                          LC_VERSION_MIN_IPHONEOS, LC_VERSION_MIN_MACOSX,
+                          LC_VERSION_MIN_TVOS, LC_VERSION_MIN_WATCHOS};
+use macho::{get_u32, Arch, MachOError, Platform, Version};
+use target::{TOOL_CLANG, TOOL_LD, TOOL_SWIFT};
+
+#[derive(Debug)]
+pub enum BuildVersionError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  Usage(String),
+  Edit(EditError),
+}
+
+pub type Result<T> = ::std::result::Result<T, BuildVersionError>;
+
+const TOOLS: &[(u32, &str)] =
+  &[(TOOL_CLANG, "clang"), (TOOL_SWIFT, "swift"), (TOOL_LD, "ld")];
+
+fn tool_name(tool: u32) -> String {
+  match TOOLS.iter().find(|&&(number, _)| number == tool) {
+    Some(&(_, name)) => name.to_string(),
+    None => tool.to_string(),
+  }
+}
+
+// A tool by name, or by number for ones without a name here.
+fn parse_tool(name: &str) -> Option<u32> {
+  TOOLS
+    .iter()
+    .find(|&&(_, other)| other == name)
+    .map(|&(number, _)| number)
+    .or_else(|| name.parse::<u32>().ok())
+}
+
+// The LC_VERSION_MIN_* command for `platform`, which only the four original
+// platforms have.
+fn version_min_command(platform: Platform) -> Option<u32> {
+  match platform {
+    Platform::MacOS => Some(LC_VERSION_MIN_MACOSX),
+    Platform::IOS => Some(LC_VERSION_MIN_IPHONEOS),
+    Platform::TvOS => Some(LC_VERSION_MIN_TVOS),
+    Platform::WatchOS => Some(LC_VERSION_MIN_WATCHOS),
+    _ => None,
+  }
+}
+
+fn version_min_platform(cmd: u32) -> Option<Platform> {
+  match cmd {
+    LC_VERSION_MIN_MACOSX => Some(Platform::MacOS),
+    LC_VERSION_MIN_IPHONEOS => Some(Platform::IOS),
+    LC_VERSION_MIN_TVOS => Some(Platform::TvOS),
+    LC_VERSION_MIN_WATCHOS => Some(Platform::WatchOS),
+    _ => None,
+  }
+}
+
+// The LC_BUILD_VERSION and LC_VERSION_MIN_* commands of a thin file.
+pub fn build_versions(data: &[u8]) -> ::macho::Result<Vec<LoadCommand>> {
+  let mut versions: Vec<LoadCommand> = Vec::new();
+  for header in load_commands(data)?.iter() {
+    let offset = header.offset;
+    match header.cmd {
+      LC_BUILD_VERSION => {
+        let what = "build_version_command";
+        let ntools = get_u32(data, offset + 20, what)? as usize;
+        let mut tools: Vec<(u32, Version)> = Vec::new();
+        for i in 0..ntools {
+          let entry = offset + 24 + i * 8;
+          if entry + 8 > offset + header.cmdsize as usize {
+            return Err(MachOError::Truncated("build_tool_version"));
+          }
+          let tool = get_u32(data, entry, "build_tool_version")?;
+          let version = get_u32(data, entry + 4, "build_tool_version")?;
+          tools.push((tool, Version(version)));
+        }
+        versions.push(LoadCommand::BuildVersion {
+          platform: get_u32(data, offset + 8, what)?,
+          minos: Version(get_u32(data, offset + 12, what)?),
+          sdk: Version(get_u32(data, offset + 16, what)?),
+          tools: tools,
+        });
+      }
+      cmd if version_min_platform(cmd).is_some() => {
+        let what = "version_min_command";
+        versions.push(LoadCommand::VersionMin {
+          cmd: cmd,
+          version: Version(get_u32(data, offset + 8, what)?),
+          sdk: Version(get_u32(data, offset + 12, what)?),
+        });
+      }
+      _ => (),
+    }
+  }
+  Ok(versions)
+}
+
+// The versions of a thin file, a command at a time.
+pub fn show(data: &[u8]) -> ::macho::Result<String> {
+  let mut out = String::new();
+  for command in build_versions(data)?.iter() {
+    match *command {
+      LoadCommand::BuildVersion {
+        platform,
+        minos,
+        sdk,
+        ref tools,
+      } => {
+        let platform = Platform::from_number(platform)
+          .map_or_else(|| platform.to_string(), |p| p.name().to_string());
+        let _ = writeln!(
+          out,
+          "LC_BUILD_VERSION\n  platform {}  minos {}  sdk {}",
+          platform, minos, sdk
+        );
+        for &(tool, version) in tools.iter() {
+          let _ = writeln!(out, "  tool {} {}", tool_name(tool), version);
+        }
+      }
+      LoadCommand::VersionMin { cmd, version, sdk } => {
+        let _ = writeln!(
+          out,
+          "{}\n  platform {}  version {}  sdk {}",
+          command_name(cmd).unwrap_or("LC_VERSION_MIN"),
+          version_min_platform(cmd).map_or("unknown", |p| p.name()),
+          version,
+          sdk
+        );
+      }
+      _ => (),
+    }
+  }
+  if out.is_empty() {
+    out.push_str("no build version\n");
+  }
+  Ok(out)
+}
+
+fn usage(message: &str) -> BuildVersionError {
+  BuildVersionError::Usage(message.to_string())
+}
+
+// `mold build-version`, printing to stdout if nothing is to be changed.
+pub fn run_command(args: &[String]) -> Result<()> {
+  let mut edits: Vec<Edit> = Vec::new();
+  let mut arch: Option<Arch> = None;
+  let mut output: Option<PathBuf> = None;
+  let mut inputs: Vec<PathBuf> = Vec::new();
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    let mut value = |what: &str| {
+      args
+        .next()
+        .cloned()
+        .ok_or_else(|| usage(&format!("{} needs {}", arg, what)))
+    };
+    let version = |value: String| {
+      Version::parse(&value)
+        .map_err(|_| usage(&format!("{} isn't a version", value)))
+    };
+    let platform = |name: String| {
+      Platform::from_name(&name)
+        .ok_or_else(|| usage(&format!("unknown platform {}", name)))
+    };
+    match arg.as_str() {
+      "-set-build-version" => {
+        let platform = platform(value("a platform")?)?;
+        let minos = version(value("a minimum OS version")?)?;
+        let sdk = version(value("an SDK version")?)?;
+        edits.push(Edit::SetVersion(LoadCommand::BuildVersion {
+          platform: platform.number(),
+          minos: minos,
+          sdk: sdk,
+          tools: Vec::new(),
+        }));
+      }
+      "-tool" => {
+        let name = value("a tool")?;
+        let tool = parse_tool(&name)
+          .ok_or_else(|| usage(&format!("unknown tool {}", name)))?;
+        let tool_version = version(value("a version")?)?;
+        match edits.last_mut() {
+          Some(&mut Edit::SetVersion(LoadCommand::BuildVersion {
+            ref mut tools,
+            ..
+          })) => tools.push((tool, tool_version)),
+          _ => return Err(usage("-tool must follow -set-build-version")),
+        }
+      }
+      "-set-version-min" => {
+        let name = value("a platform")?;
+        let platform = platform(name.clone())?;
+        let cmd = version_min_command(platform).ok_or_else(|| {
+          usage(&format!("{} has no LC_VERSION_MIN command", name))
+        })?;
+        let minos = version(value("a minimum OS version")?)?;
+        let sdk = version(value("an SDK version")?)?;
+        edits.push(Edit::SetVersion(LoadCommand::VersionMin {
+          cmd: cmd,
+          version: minos,
+          sdk: sdk,
+        }));
+      }
+      "-remove-build-version" => {
+        let platform = platform(value("a platform")?)?;
+        edits.push(Edit::RemoveVersion(platform));
+      }
+      "-arch" => {
+        let name = value("an architecture")?;
+        arch = Some(Arch::from_name(&name).ok_or_else(|| {
+          usage(&format!("unknown architecture {}", name))
+        })?);
+      }
+      "-o" => output = Some(PathBuf::from(value("a path")?)),
+      _ if arg.starts_with('-') => {
+        return Err(usage(&format!("unknown option {}", arg)));
+      }
+      _ => inputs.push(PathBuf::from(arg)),
+    }
+  }
+  if inputs.is_empty() {
+    return Err(usage("no input files"));
+  }
+
+  if !edits.is_empty() {
+    if inputs.len() > 1 {
+      return Err(usage("only one input can be changed at a time"));
+    }
+    let input = &inputs[0];
+    let output = output.unwrap_or_else(|| input.clone());
+    return edit::edit_file(input, &output, &edits)
+      .map_err(BuildVersionError::Edit);
+  }
+
+  let headings = inputs.len() > 1;
+  for input in inputs.iter() {
+    let data = fs::read(input)
+      .map_err(|e| BuildVersionError::IoError(input.clone(), e))?;
+    let malformed = |e| BuildVersionError::Malformed(input.clone(), e);
+    let is_fat = fat::is_fat(&data);
+    for (slice_arch, thin) in fat::slices(&data).map_err(malformed)? {
+      if is_fat && arch.is_some() && slice_arch != arch {
+        continue;
+      }
+      if is_fat {
+        let slice_arch = slice_arch.map_or("unknown", |a| a.name());
+        println!("{} (architecture {}):", input.display(), slice_arch);
+      } else if headings {
+        println!("{}:", input.display());
+      }
+      print!("{}", show(thin).map_err(malformed)?);
+    }
+  }
+  Ok(())
+}
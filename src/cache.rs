@@ -0,0 +1,103 @@
+// Parsed inputs kept between links, for the daemon (see daemon.rs). Each
+// entry is keyed by canonical path, since the daemon's clients give paths
+// relative to their own directories, and is only used while the file's
+// size and modification time are what they were when it was parsed.
+// Outside the daemon nothing is kept, and every load goes straight to the
+// file.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use dylib::ReadDylib;
+use macho::object_file::ObjectFile;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Thin archives' members, by name.
+pub static ARCHIVES: FileCache<Vec<(String, ObjectFile)>> = FileCache::new();
+// Dylibs linked against, and their export sets.
+pub static DYLIBS: FileCache<ReadDylib> = FileCache::new();
+
+pub fn enable() {
+  ENABLED.store(true, Ordering::Relaxed);
+}
+
+// What a file looked like when it was parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stamp {
+  len: u64,
+  modified: SystemTime,
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+  let metadata = fs::metadata(path).ok()?;
+  Some(Stamp {
+    len: metadata.len(),
+    modified: metadata.modified().ok()?,
+  })
+}
+
+type Entries<T> = HashMap<PathBuf, (Stamp, Arc<T>)>;
+
+pub struct FileCache<T> {
+  entries: Mutex<Option<Entries<T>>>,
+}
+
+impl<T> FileCache<T> {
+  const fn new() -> Self {
+    FileCache {
+      entries: Mutex::new(None),
+    }
+  }
+
+  // What `load` parses from `path`, from the cache if the file hasn't
+  // changed since it was last parsed. The lock isn't held while loading, so
+  // threads reading different inputs don't wait for each other.
+  pub fn get<E, F>(&self, path: &Path, load: F) -> Result<Arc<T>, E>
+  where
+    F: FnOnce() -> Result<T, E>,
+  {
+    if !ENABLED.load(Ordering::Relaxed) {
+      return load().map(Arc::new);
+    }
+    let (key, stamp) = match (fs::canonicalize(path), stamp(path)) {
+      (Ok(key), Some(stamp)) => (key, stamp),
+      _ => return load().map(Arc::new),
+    };
+    {
+      let entries = self.lock();
+      let cached = entries.as_ref().and_then(|entries| entries.get(&key));
+      if let Some(&(ref cached_stamp, ref value)) = cached {
+        if *cached_stamp == stamp {
+          return Ok(value.clone());
+        }
+      }
+    }
+    let value = Arc::new(load()?);
+    self
+      .lock()
+      .get_or_insert_with(HashMap::new)
+      .insert(key, (stamp, value.clone()));
+    Ok(value)
+  }
+
+  // How many files are cached.
+  pub fn len(&self) -> usize {
+    self.lock().as_ref().map_or(0, |entries| entries.len())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  fn lock(&self) -> MutexGuard<'_, Option<Entries<T>>> {
+    match self.entries.lock() {
+      Ok(entries) => entries,
+      Err(poisoned) => poisoned.into_inner(),
+    }
+  }
+}
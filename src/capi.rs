@@ -0,0 +1,124 @@
+// The C API (see include/mold.h): the command-line linker as a function, for
+// build systems which aren't written in Rust, or a compiler driver which
+// wants to link in-process. Diagnostics go to a callback instead of stderr.
+
+extern crate libc;
+
+use self::libc::{c_char, c_int};
+
+use std::ffi::{CStr, CString};
+use std::panic;
+use std::ptr;
+
+use args;
+use diagnostics::{self, Diagnostic, Severity};
+use link_job::{self, LinkError};
+
+pub const MOL_SEVERITY_WARNING: c_int = 0;
+pub const MOL_SEVERITY_ERROR: c_int = 1;
+pub const MOL_SEVERITY_NOTE: c_int = 2;
+
+// Everything but the message may be null. The strings are only valid for
+// the duration of the callback.
+#[repr(C)]
+pub struct MolDiagnostic {
+  pub severity: c_int,
+  pub code: *const c_char,
+  pub message: *const c_char,
+  pub file: *const c_char,
+  pub symbol: *const c_char,
+}
+
+pub type MolDiagCallback = Option<extern "C" fn(*const MolDiagnostic)>;
+
+fn c_string(s: &str) -> CString {
+  // Interior NULs can only come from odd file names; drop them rather than
+  // the whole string.
+  CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+fn deliver(callback: MolDiagCallback, diagnostic: &Diagnostic) {
+  let callback = match callback {
+    Some(callback) => callback,
+    None => return,
+  };
+  let code = diagnostic.code.map(c_string);
+  let message = c_string(&diagnostic.message);
+  let file = diagnostic
+    .file
+    .as_ref()
+    .map(|file| c_string(&file.display().to_string()));
+  let symbol = diagnostic.symbol.as_ref().map(|symbol| c_string(symbol));
+  let as_ptr =
+    |s: &Option<CString>| s.as_ref().map_or(ptr::null(), |s| s.as_ptr());
+  let record = MolDiagnostic {
+    severity: match diagnostic.severity {
+      Severity::Note => MOL_SEVERITY_NOTE,
+      Severity::Warning => MOL_SEVERITY_WARNING,
+      Severity::Error => MOL_SEVERITY_ERROR,
+    },
+    code: as_ptr(&code),
+    message: message.as_ptr(),
+    file: as_ptr(&file),
+    symbol: as_ptr(&symbol),
+  };
+  callback(&record);
+}
+
+// Parse the arguments and link, as the command line would, collecting every
+// diagnostic. True on success.
+fn link(args: Vec<String>) -> (bool, Vec<Diagnostic>) {
+  diagnostics::capture(|| {
+    let opts = match args::parse_args(args.into_iter()) {
+      Ok(opts) => opts,
+      Err(e) => {
+        diagnostics::report(LinkError::Args(e).diagnostic());
+        return false;
+      }
+    };
+    match link_job::run(&opts) {
+      Ok(()) => true,
+      Err(e) => {
+        diagnostics::report(e.diagnostic());
+        false
+      }
+    }
+  })
+}
+
+// Link with `argv`, which is laid out like a main function's: argv[0] is
+// the program name and is ignored. Returns 0 on success and 1 on failure;
+// either way, every warning and error is passed to `diag_callback` (if it
+// isn't null) before this returns. argv must hold argc valid C strings
+// (a null entry ends it early).
+#[no_mangle]
+pub unsafe extern "C" fn mol_link(
+  argc: c_int,
+  argv: *const *const c_char,
+  diag_callback: MolDiagCallback,
+) -> c_int {
+  let mut args: Vec<String> = Vec::new();
+  for i in 1..(argc.max(0) as isize) {
+    let arg = *argv.offset(i);
+    if arg.is_null() {
+      break;
+    }
+    args.push(CStr::from_ptr(arg).to_string_lossy().into_owned());
+  }
+  // Panics can't unwind into C.
+  let (ok, reported) = match panic::catch_unwind(|| link(args)) {
+    Ok(result) => result,
+    Err(_) => {
+      let message = "the linker panicked".to_string();
+      (false, vec![Diagnostic::new(Severity::Error, message)])
+    }
+  };
+  for diagnostic in reported.iter() {
+    deliver(diag_callback, diagnostic);
+  }
+  if ok {
+    0
+  } else {
+    1
+  }
+}
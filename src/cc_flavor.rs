@@ -0,0 +1,170 @@
+// `mold --flavor=cc`: the arguments rustc (or a Makefile) would pass to `cc`
+// to link for an Apple target, translated into `ld` ones, so that the
+// linker can stand in for a cross-compiling C compiler driver. Linker
+// options come through `-Wl,` and `-Xlinker`; compiler-only options are
+// dropped.
+
+use std::vec;
+
+use args::{ArgsError, Result};
+use macho::Arch;
+
+// Options which only mean something to a compiler, or which `ld` implies.
+fn is_compiler_only(arg: &str) -> bool {
+  let prefixes = ["-f", "-g", "-O", "-W", "-m", "-std=", "-D", "-U", "-I"];
+  match arg {
+    "-ObjC" => false,
+    "-nostartfiles" | "-pthread" | "-pipe" | "-v" | "-c" => true,
+    _ => prefixes.iter().any(|prefix| arg.starts_with(prefix)),
+  }
+}
+
+// `cc`'s spelling of an architecture in a target triple.
+fn arch_name(name: &str) -> &str {
+  match name {
+    "aarch64" => "arm64",
+    "i686" => "i386",
+    _ => name,
+  }
+}
+
+// The `ld` options for `-target <triple>`, as in arm64-apple-macosx11.0 or
+// aarch64-apple-ios14.0-simulator: the architecture and, when the triple
+// has an OS version, the platform and minimum version.
+fn target_options(triple: &str) -> Result<Vec<String>> {
+  let invalid = || ArgsError::InvalidValue("-target".into(), triple.into());
+  let parts: Vec<&str> = triple.split('-').collect();
+  if parts.len() < 3 || Arch::from_name(arch_name(parts[0])).is_none() {
+    return Err(invalid());
+  }
+  let mut options = vec!["-arch".to_string(), arch_name(parts[0]).into()];
+  let os = parts[2];
+  let version_at = os.find(|c: char| c.is_ascii_digit());
+  let (os, version) = match version_at {
+    Some(at) => (&os[..at], &os[at..]),
+    None => return Ok(options),
+  };
+  let environment = parts.get(3).cloned().unwrap_or("");
+  let option = match (os, environment) {
+    ("macosx", _) | ("macos", _) => "-macosx_version_min",
+    ("ios", "simulator") => "-ios_simulator_version_min",
+    ("ios", "macabi") => "-maccatalyst_version_min",
+    ("ios", _) => "-ios_version_min",
+    ("tvos", _) => "-tvos_version_min",
+    ("watchos", _) => "-watchos_version_min",
+    _ => return Err(invalid()),
+  };
+  options.push(option.to_string());
+  options.push(version.to_string());
+  Ok(options)
+}
+
+fn next_value(opt: &str, args: &mut vec::IntoIter<String>) -> Result<String> {
+  args
+    .next()
+    .ok_or_else(|| ArgsError::MissingValue(opt.to_string()))
+}
+
+// The `ld` arguments meaning what `args` would to `cc`.
+pub fn translate(args: Vec<String>) -> Result<Vec<String>> {
+  let mut translated: Vec<String> = Vec::new();
+  let mut default_libraries = true;
+  let mut args = args.into_iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      _ if arg.starts_with("-Wl,") => {
+        translated.extend(arg[4..].split(',').map(|s| s.to_string()));
+      }
+      "-Xlinker" => translated.push(next_value(&arg, &mut args)?),
+      "-o" | "-arch" | "-framework" | "-weak_framework" | "-u" => {
+        translated.push(arg.clone());
+        translated.push(next_value(&arg, &mut args)?);
+      }
+      "-L" | "-F" => {
+        let dir = next_value(&arg, &mut args)?;
+        translated.push(format!("{}{}", arg, dir));
+      }
+      "-target" => {
+        translated.extend(target_options(&next_value(&arg, &mut args)?)?);
+      }
+      _ if arg.starts_with("--target=") => {
+        translated.extend(target_options(&arg[9..])?);
+      }
+      "-isysroot" => {
+        translated.push("-syslibroot".to_string());
+        translated.push(next_value(&arg, &mut args)?);
+      }
+      _ if arg.starts_with("-mmacosx-version-min=") => {
+        translated.push("-macosx_version_min".to_string());
+        translated.push(arg[21..].to_string());
+      }
+      _ if arg.starts_with("-mios-version-min=") => {
+        translated.push("-ios_version_min".to_string());
+        translated.push(arg[18..].to_string());
+      }
+      _ if arg.starts_with("-miphoneos-version-min=") => {
+        translated.push("-ios_version_min".to_string());
+        translated.push(arg[23..].to_string());
+      }
+      "-dynamiclib" | "-shared" => translated.push("-dylib".to_string()),
+      "-nodefaultlibs" | "-nostdlib" => default_libraries = false,
+      // Split forms of compiler options, whose values go too.
+      "-include" | "-x" | "-D" | "-U" | "-I" => {
+        next_value(&arg, &mut args)?;
+      }
+      _ if is_compiler_only(&arg) => (),
+      _ => translated.push(arg),
+    }
+  }
+  if default_libraries && !translated.iter().any(|arg| arg == "-r") {
+    translated.push("-lSystem".to_string());
+  }
+  Ok(translated)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+  }
+
+  #[test]
+  fn drops_the_values_of_split_compiler_options() {
+    let args = strings(&[
+      "-DFOO", "-D", "BAR", "-U", "BAZ", "-UQUX", "-I", "include", "-O2",
+      "a.o",
+    ]);
+    assert_eq!(translate(args).unwrap(), strings(&["a.o", "-lSystem"]));
+    match translate(strings(&["a.o", "-U"])) {
+      Err(ArgsError::MissingValue(ref opt)) => assert_eq!(opt, "-U"),
+      other => panic!("expected a missing value, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn translates_targets_and_linker_options() {
+    let args = strings(&[
+      "-target",
+      "arm64-apple-ios14.0-simulator",
+      "-Wl,-rpath,@loader_path",
+      "-shared",
+      "-nostdlib",
+      "a.o",
+    ]);
+    assert_eq!(
+      translate(args).unwrap(),
+      strings(&[
+        "-arch",
+        "arm64",
+        "-ios_simulator_version_min",
+        "14.0",
+        "-rpath",
+        "@loader_path",
+        "-dylib",
+        "a.o",
+      ])
+    );
+  }
+}
@@ -0,0 +1,729 @@
+// Ad-hoc code signatures. arm64 macOS won't run code without a valid
+// signature, so like ld64 we sign outputs ourselves: LC_CODE_SIGNATURE
+// points at a SuperBlob, at the very end of __LINKEDIT, holding a single
+// CodeDirectory with the SHA-256 of each 4KB page of the file before it.
+// Entitlements, when given, go in their own blob which the CodeDirectory
+// hashes in a special slot. The signature is big-endian, unlike the rest of
+// the file.
+
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use args::LinkOptions;
+use dependency_info;
+use image::{self, Image};
+use macho::fat;
+use macho::header::MACH_HEADER_64_SIZE;
+use macho::load_command::LC_CODE_SIGNATURE;
+use macho::{get_u32, next_command, round_up, Arch, MachOError, Result};
+use parallel;
+
+const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade_0cc0;
+const CSMAGIC_CODEDIRECTORY: u32 = 0xfade_0c02;
+const CSMAGIC_REQUIREMENTS: u32 = 0xfade_0c01;
+const CSMAGIC_EMBEDDED_ENTITLEMENTS: u32 = 0xfade_7171;
+const CSSLOT_CODEDIRECTORY: u32 = 0;
+const CSSLOT_REQUIREMENTS: u32 = 2;
+const CSSLOT_ENTITLEMENTS: u32 = 5;
+const CSSLOT_DER_ENTITLEMENTS: u32 = 7;
+// The first version with codeLimit64.
+const CS_SUPPORTSCODELIMIT64: u32 = 0x2_0300;
+// The first version with the exec segment fields.
+const CS_SUPPORTSEXECSEG: u32 = 0x2_0400;
+const CS_ADHOC: u32 = 0x2;
+const CS_REQUIRE_LV: u32 = 0x2000;
+const CS_RUNTIME: u32 = 0x1_0000;
+const CS_LINKER_SIGNED: u32 = 0x2_0000;
+const CS_HASHTYPE_SHA256: u8 = 2;
+const CS_HASHTYPE_SHA256_TRUNCATED: u8 = 3;
+// The flags the kernel accepts in a Mach-O file's CodeDirectory.
+const CS_ALLOWED_MACHO: u32 = 0x3_3f02;
+const CS_EXECSEG_MAIN_BINARY: u64 = 0x1;
+
+const PAGE_SIZE_LOG2: u8 = 12;
+const PAGE_SIZE: usize = 1 << PAGE_SIZE_LOG2;
+const HASH_SIZE: usize = 32;
+const SUPER_BLOB_HEADER_SIZE: usize = 12;
+const BLOB_INDEX_SIZE: usize = 8;
+const CODE_DIRECTORY_SIZE: usize = 88;
+// An empty requirement set, which codesign also adds alongside
+// entitlements.
+const REQUIREMENTS_SIZE: usize = 12;
+
+fn put_be32(buf: &mut Vec<u8>, val: u32) {
+  buf.extend_from_slice(&[
+    (val >> 24) as u8,
+    (val >> 16) as u8,
+    (val >> 8) as u8,
+    val as u8,
+  ]);
+}
+
+fn put_be64(buf: &mut Vec<u8>, val: u64) {
+  put_be32(buf, (val >> 32) as u32);
+  put_be32(buf, val as u32);
+}
+
+fn get_be32(data: &[u8], offset: usize) -> Result<u32> {
+  get_u32(data, offset, "code signature").map(|val| val.swap_bytes())
+}
+
+fn get_be64(data: &[u8], offset: usize) -> Result<u64> {
+  let high = get_be32(data, offset)? as u64;
+  Ok(high << 32 | get_be32(data, offset + 4)? as u64)
+}
+
+// The identifier ld64 gives linker-signed outputs: the output's file name.
+pub fn identifier(output_path: &Path) -> String {
+  output_path
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_else(|| "a.out".to_string())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Signer {
+  pub identifier: String,
+  // -hardened_runtime
+  pub runtime: bool,
+  // -library_validation: only load libraries signed by the same team (or
+  // Apple).
+  pub library_validation: bool,
+  // The entitlements plist, as is.
+  pub entitlements: Option<Vec<u8>>,
+}
+
+impl Signer {
+  fn flags(&self) -> u32 {
+    let mut flags = CS_ADHOC | CS_LINKER_SIGNED;
+    if self.runtime {
+      flags |= CS_RUNTIME;
+    }
+    if self.library_validation {
+      flags |= CS_REQUIRE_LV;
+    }
+    flags
+  }
+
+  // The blobs other than the CodeDirectory, by slot.
+  fn extra_blobs(&self) -> Vec<(u32, Vec<u8>)> {
+    let entitlements = match self.entitlements {
+      Some(ref entitlements) => entitlements,
+      None => return Vec::new(),
+    };
+    let mut requirements: Vec<u8> = Vec::new();
+    put_be32(&mut requirements, CSMAGIC_REQUIREMENTS);
+    put_be32(&mut requirements, REQUIREMENTS_SIZE as u32);
+    put_be32(&mut requirements, 0);
+    let mut blob: Vec<u8> = Vec::new();
+    put_be32(&mut blob, CSMAGIC_EMBEDDED_ENTITLEMENTS);
+    put_be32(&mut blob, (8 + entitlements.len()) as u32);
+    blob.extend_from_slice(entitlements);
+    vec![
+      (CSSLOT_REQUIREMENTS, requirements),
+      (CSSLOT_ENTITLEMENTS, blob),
+    ]
+  }
+
+  fn special_slots(&self) -> usize {
+    if self.entitlements.is_some() {
+      CSSLOT_ENTITLEMENTS as usize
+    } else {
+      0
+    }
+  }
+
+  // How much space to reserve for the signature of the first `code_limit`
+  // bytes of a file.
+  pub fn signature_size(&self, code_limit: u64) -> u64 {
+    let pages = (code_limit as usize + PAGE_SIZE - 1) / PAGE_SIZE;
+    let extra: usize = self
+      .extra_blobs()
+      .iter()
+      .map(|&(_, ref blob)| BLOB_INDEX_SIZE + blob.len())
+      .sum();
+    let size = SUPER_BLOB_HEADER_SIZE + BLOB_INDEX_SIZE + CODE_DIRECTORY_SIZE
+      + self.identifier.len() + 1
+      + (self.special_slots() + pages) * HASH_SIZE + extra;
+    round_up(size as u64, 16)
+  }
+}
+
+pub fn add_to_image(
+  image: &mut Image,
+  opts: &LinkOptions,
+) -> image::Result<()> {
+  if !opts.use_adhoc_codesign() {
+    return Ok(());
+  }
+  let entitlements = match opts.entitlements {
+    Some(ref path) => {
+      dependency_info::record_input(path);
+      let mut data: Vec<u8> = Vec::new();
+      File::open(path)?.read_to_end(&mut data)?;
+      // The simulator checks entitlements in a section rather than the
+      // signature.
+      if opts.platform.is_simulator() {
+        image.create_section("__TEXT", "__entitlements", Some(path))?;
+      }
+      Some(data)
+    }
+    None => None,
+  };
+  image.code_signature = Some(Signer {
+    identifier: identifier(&opts.output_path),
+    runtime: opts.hardened_runtime,
+    library_validation: opts.library_validation,
+    entitlements: entitlements,
+  });
+  Ok(())
+}
+
+// The __TEXT segment, which the CodeDirectory records so the kernel knows
+// which pages are executable.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecSegment {
+  pub fileoff: u64,
+  pub filesize: u64,
+  pub main_binary: bool,
+}
+
+// Sign `output`, whose signature goes at `offset` and covers everything
+// before it.
+pub fn sign(
+  output: &mut [u8],
+  offset: usize,
+  signer: &Signer,
+  exec_seg: ExecSegment,
+) {
+  let pages: Vec<&[u8]> = output[..offset].chunks(PAGE_SIZE).collect();
+  let hashes = parallel::map(&pages, |page| sha256(page));
+  let extra_blobs = signer.extra_blobs();
+  // Special slots count down from the code hashes, starting at 1.
+  let nspecial = signer.special_slots();
+  let mut special: Vec<[u8; 32]> = vec![[0; 32]; nspecial];
+  for &(slot, ref blob) in extra_blobs.iter() {
+    special[nspecial - slot as usize] = sha256(blob);
+  }
+
+  let identifier = &signer.identifier;
+  let hash_offset =
+    CODE_DIRECTORY_SIZE + identifier.len() + 1 + special.len() * HASH_SIZE;
+  let cd_size = hash_offset + hashes.len() * HASH_SIZE;
+  let header_size =
+    SUPER_BLOB_HEADER_SIZE + (1 + extra_blobs.len()) * BLOB_INDEX_SIZE;
+  let total_size = header_size + cd_size
+    + extra_blobs.iter().map(|&(_, ref blob)| blob.len()).sum::<usize>();
+  let mut sig: Vec<u8> = Vec::new();
+  put_be32(&mut sig, CSMAGIC_EMBEDDED_SIGNATURE);
+  put_be32(&mut sig, total_size as u32);
+  put_be32(&mut sig, (1 + extra_blobs.len()) as u32);
+  put_be32(&mut sig, CSSLOT_CODEDIRECTORY);
+  put_be32(&mut sig, header_size as u32);
+  let mut blob_offset = header_size + cd_size;
+  for &(slot, ref blob) in extra_blobs.iter() {
+    put_be32(&mut sig, slot);
+    put_be32(&mut sig, blob_offset as u32);
+    blob_offset += blob.len();
+  }
+
+  put_be32(&mut sig, CSMAGIC_CODEDIRECTORY);
+  put_be32(&mut sig, cd_size as u32);
+  put_be32(&mut sig, CS_SUPPORTSEXECSEG);
+  put_be32(&mut sig, signer.flags());
+  put_be32(&mut sig, hash_offset as u32);
+  put_be32(&mut sig, CODE_DIRECTORY_SIZE as u32);
+  put_be32(&mut sig, special.len() as u32);
+  put_be32(&mut sig, hashes.len() as u32);
+  put_be32(&mut sig, offset as u32);
+  sig.extend_from_slice(&[HASH_SIZE as u8, CS_HASHTYPE_SHA256, 0]);
+  sig.push(PAGE_SIZE_LOG2);
+  // spare2, scatterOffset, teamOffset, spare3
+  sig.extend_from_slice(&[0; 16]);
+  // codeLimit64, only for files over 4GB.
+  put_be64(&mut sig, 0);
+  put_be64(&mut sig, exec_seg.fileoff);
+  put_be64(&mut sig, exec_seg.filesize);
+  put_be64(
+    &mut sig,
+    if exec_seg.main_binary {
+      CS_EXECSEG_MAIN_BINARY
+    } else {
+      0
+    },
+  );
+  sig.extend_from_slice(identifier.as_bytes());
+  sig.push(0);
+  for hash in special.iter().chain(hashes.iter()) {
+    sig.extend_from_slice(hash);
+  }
+  for &(_, ref blob) in extra_blobs.iter() {
+    sig.extend_from_slice(blob);
+  }
+  output[offset..(offset + sig.len())].copy_from_slice(&sig);
+}
+
+// Where LC_CODE_SIGNATURE says the signature is, as (dataoff, datasize).
+fn find_signature(data: &[u8]) -> Result<Option<(usize, usize)>> {
+  let ncmds = get_u32(data, 16, "mach header")?;
+  let mut offset = MACH_HEADER_64_SIZE as usize;
+  for _ in 0..ncmds {
+    let cmd = get_u32(data, offset, "load command")?;
+    let cmdsize = get_u32(data, offset + 4, "load command")? as usize;
+    if cmdsize < 8 {
+      return Err(MachOError::Malformed("load command smaller than 8"));
+    }
+    if cmd == LC_CODE_SIGNATURE {
+      let what = "linkedit_data_command";
+      let dataoff = get_u32(data, offset + 8, what)? as usize;
+      let datasize = get_u32(data, offset + 12, what)? as usize;
+      return Ok(Some((dataoff, datasize)));
+    }
+    offset = next_command(offset, cmdsize)?;
+  }
+  Ok(None)
+}
+
+// The (slot, file offset) of each blob in the SuperBlob at `dataoff`.
+fn blobs(data: &[u8], dataoff: usize) -> Result<Vec<(u32, usize)>> {
+  if get_be32(data, dataoff)? != CSMAGIC_EMBEDDED_SIGNATURE {
+    return Err(MachOError::Malformed("bad code signature magic"));
+  }
+  let count = get_be32(data, dataoff + 8)? as usize;
+  let mut blobs: Vec<(u32, usize)> = Vec::new();
+  for i in 0..count {
+    let index = dataoff + SUPER_BLOB_HEADER_SIZE + i * BLOB_INDEX_SIZE;
+    let slot = get_be32(data, index)?;
+    blobs.push((slot, dataoff + get_be32(data, index + 4)? as usize));
+  }
+  Ok(blobs)
+}
+
+// A whole blob, header included, as its length field says.
+fn blob_contents(data: &[u8], blob: usize) -> Result<&[u8]> {
+  let len = get_be32(data, blob + 4)? as usize;
+  data
+    .get(blob..(blob + len.max(8)))
+    .ok_or(MachOError::Truncated("code signature blob"))
+}
+
+fn code_directory(data: &[u8], blobs: &[(u32, usize)]) -> Result<usize> {
+  let cd = match blobs.iter().find(|&&(slot, _)| slot == CSSLOT_CODEDIRECTORY)
+  {
+    Some(&(_, cd)) => cd,
+    None => return Err(MachOError::Malformed("no code directory")),
+  };
+  if get_be32(data, cd)? != CSMAGIC_CODEDIRECTORY {
+    return Err(MachOError::Malformed("bad code directory magic"));
+  }
+  Ok(cd)
+}
+
+fn cd_identifier(data: &[u8], cd: usize) -> Result<String> {
+  let ident_start = cd + get_be32(data, cd + 20)? as usize;
+  data
+    .get(ident_start..)
+    .and_then(|rest| rest.split(|&b| b == 0).next())
+    .map(|name| String::from_utf8_lossy(name).into_owned())
+    .ok_or(MachOError::Truncated("code directory identifier"))
+}
+
+// Sign a linked image again after it was modified, keeping its identifier,
+// flags and entitlements.
+// Only ad-hoc signatures can be redone; returns false for anything else,
+// which has to go back through `codesign`.
+pub fn resign(data: &mut [u8]) -> Result<bool> {
+  let (dataoff, datasize) = match find_signature(data)? {
+    Some(sig) => sig,
+    None => return Ok(false),
+  };
+  let blobs = blobs(data, dataoff)?;
+  let mut entitlements: Option<Vec<u8>> = None;
+  for &(slot, blob) in blobs.iter() {
+    if slot == CSSLOT_ENTITLEMENTS {
+      let contents = blob_contents(data, blob)?;
+      entitlements = Some(contents[8..].to_vec());
+    }
+  }
+  let cd = code_directory(data, &blobs)?;
+  let flags = get_be32(data, cd + 12)?;
+  if flags & CS_ADHOC == 0 {
+    return Ok(false);
+  }
+  let identifier = cd_identifier(data, cd)?;
+  let exec_seg = if get_be32(data, cd + 8)? >= CS_SUPPORTSEXECSEG {
+    ExecSegment {
+      fileoff: get_be64(data, cd + 64)?,
+      filesize: get_be64(data, cd + 72)?,
+      main_binary: get_be64(data, cd + 80)? & CS_EXECSEG_MAIN_BINARY != 0,
+    }
+  } else {
+    ExecSegment::default()
+  };
+  let signer = Signer {
+    identifier: identifier,
+    runtime: flags & CS_RUNTIME != 0,
+    library_validation: flags & CS_REQUIRE_LV != 0,
+    entitlements: entitlements,
+  };
+  if signer.signature_size(dataoff as u64) > datasize as u64 {
+    return Ok(false);
+  }
+  for b in data[dataoff..(dataoff + datasize)].iter_mut() {
+    *b = 0;
+  }
+  sign(data, dataoff, &signer, exec_seg);
+  Ok(true)
+}
+
+// Something wrong with a signature, found by `verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+  // (page number, its file offset)
+  Page(usize, usize),
+  SpecialSlot(u32),
+  // A special slot with a hash of a blob that isn't in the signature.
+  MissingBlob(u32),
+  // A blob in the signature which no special slot hashes.
+  UnhashedBlob(u32),
+  // (code limit, where the signature starts)
+  CodeLimit(u64, usize),
+  // (hash slots, pages up to the code limit)
+  PageCount(usize, usize),
+  Flags(u32, &'static str),
+  HashType(u8),
+}
+
+impl Mismatch {
+  pub fn describe(&self) -> String {
+    match *self {
+      Mismatch::Page(page, offset) => format!(
+        "page {} (offset {:#x}) doesn't match its hash",
+        page, offset
+      ),
+      Mismatch::SpecialSlot(slot) => {
+        format!("special slot {} doesn't match its blob", slot)
+      }
+      Mismatch::MissingBlob(slot) => {
+        format!("special slot {} hashes a blob that isn't there", slot)
+      }
+      Mismatch::UnhashedBlob(slot) => {
+        format!("the blob in slot {} isn't hashed", slot)
+      }
+      Mismatch::CodeLimit(limit, dataoff) => format!(
+        "the code limit is {:#x} but the signature starts at {:#x}",
+        limit, dataoff
+      ),
+      Mismatch::PageCount(slots, pages) => {
+        format!("{} page hashes for {} pages", slots, pages)
+      }
+      Mismatch::Flags(flags, why) => format!("flags {:#x}: {}", flags, why),
+      Mismatch::HashType(hash_type) => {
+        format!("hash type {} isn't supported", hash_type)
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+  pub identifier: String,
+  pub flags: u32,
+  pub page_size: usize,
+  pub pages: usize,
+  pub special_slots: usize,
+  pub mismatches: Vec<Mismatch>,
+}
+
+impl Verification {
+  pub fn is_valid(&self) -> bool {
+    self.mismatches.is_empty()
+  }
+
+  pub fn to_text(&self) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+      out,
+      "identifier {}  flags {:#x}  {} pages of {} bytes  {} special slots",
+      self.identifier,
+      self.flags,
+      self.pages,
+      self.page_size,
+      self.special_slots
+    );
+    for mismatch in self.mismatches.iter() {
+      let _ = writeln!(out, "  {}", mismatch.describe());
+    }
+    let verdict = if self.is_valid() { "valid" } else { "invalid" };
+    let _ = writeln!(out, "{}", verdict);
+    out
+  }
+}
+
+// Check the signature of a thin image against its contents: each page's
+// hash, the hash of each blob with a special slot, and the flags. None if
+// it isn't signed.
+pub fn verify(data: &[u8]) -> Result<Option<Verification>> {
+  let (dataoff, _) = match find_signature(data)? {
+    Some(sig) => sig,
+    None => return Ok(None),
+  };
+  let blobs = blobs(data, dataoff)?;
+  let cd = code_directory(data, &blobs)?;
+  let version = get_be32(data, cd + 8)?;
+  let flags = get_be32(data, cd + 12)?;
+  let hash_offset = cd + get_be32(data, cd + 16)? as usize;
+  let nspecial = get_be32(data, cd + 24)? as usize;
+  let ncode = get_be32(data, cd + 28)? as usize;
+  let mut code_limit = get_be32(data, cd + 32)? as u64;
+  if version >= CS_SUPPORTSCODELIMIT64 && get_be64(data, cd + 56)? != 0 {
+    code_limit = get_be64(data, cd + 56)?;
+  }
+  let header = data
+    .get((cd + 36)..(cd + 40))
+    .ok_or(MachOError::Truncated("code directory"))?;
+  let (hash_size, hash_type, page_size_log2) =
+    (header[0] as usize, header[1], header[3]);
+
+  let mut verification = Verification {
+    identifier: cd_identifier(data, cd)?,
+    flags: flags,
+    // A page size of 0 means the code is one page.
+    page_size: if page_size_log2 == 0 {
+      code_limit as usize
+    } else {
+      1 << page_size_log2
+    },
+    pages: ncode,
+    special_slots: nspecial,
+    mismatches: Vec::new(),
+  };
+  let mismatches = &mut verification.mismatches;
+  if flags & !CS_ALLOWED_MACHO != 0 {
+    let why = "not all of these are allowed in a Mach-O signature";
+    mismatches.push(Mismatch::Flags(flags, why));
+  }
+  if flags & CS_LINKER_SIGNED != 0 && flags & CS_ADHOC == 0 {
+    let why = "linker-signed but not ad-hoc";
+    mismatches.push(Mismatch::Flags(flags, why));
+  }
+  if code_limit != dataoff as u64 {
+    mismatches.push(Mismatch::CodeLimit(code_limit, dataoff));
+  }
+  let sha256_type = hash_type == CS_HASHTYPE_SHA256
+    || hash_type == CS_HASHTYPE_SHA256_TRUNCATED;
+  if !sha256_type || hash_size > HASH_SIZE {
+    mismatches.push(Mismatch::HashType(hash_type));
+    return Ok(Some(verification));
+  }
+  let hash_at = |index: usize| {
+    data
+      .get(index..(index + hash_size))
+      .ok_or(MachOError::Truncated("code directory hashes"))
+  };
+
+  let code = data
+    .get(..(code_limit as usize))
+    .ok_or(MachOError::Truncated("signed code"))?;
+  let pages: Vec<&[u8]> = code.chunks(verification.page_size.max(1)).collect();
+  if pages.len() != ncode {
+    mismatches.push(Mismatch::PageCount(ncode, pages.len()));
+  }
+  let hashes = parallel::map(&pages, |page| sha256(page));
+  for (i, hash) in hashes.iter().enumerate().take(ncode) {
+    if hash[..hash_size] != *hash_at(hash_offset + i * hash_size)? {
+      mismatches.push(Mismatch::Page(i, i * verification.page_size));
+    }
+  }
+
+  // Special slots count down from the code hashes, starting at 1.
+  for slot in 1..=(nspecial as u32) {
+    let expected = hash_offset
+      .checked_sub(slot as usize * hash_size)
+      .ok_or(MachOError::Malformed("special slots before code directory"))
+      .and_then(&hash_at)?;
+    match blobs.iter().find(|&&(other, _)| other == slot) {
+      Some(&(_, blob)) => {
+        if sha256(blob_contents(data, blob)?)[..hash_size] != *expected {
+          mismatches.push(Mismatch::SpecialSlot(slot));
+        }
+      }
+      // The other slots hash files outside the image, like Info.plist.
+      None => match slot {
+        CSSLOT_REQUIREMENTS | CSSLOT_ENTITLEMENTS | CSSLOT_DER_ENTITLEMENTS
+          if expected.iter().any(|&b| b != 0) =>
+        {
+          mismatches.push(Mismatch::MissingBlob(slot));
+        }
+        _ => (),
+      },
+    }
+  }
+  for &(slot, _) in blobs.iter() {
+    let special = (1..=CSSLOT_DER_ENTITLEMENTS).contains(&slot);
+    if special && slot as usize > nspecial {
+      mismatches.push(Mismatch::UnhashedBlob(slot));
+    }
+  }
+  Ok(Some(verification))
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  Usage(String),
+  Unsigned(PathBuf),
+  // Something didn't match; what is printed.
+  Invalid(PathBuf),
+}
+
+// `mold codesign verify [-arch <name>] <file>...`, printing what it finds
+// to stdout.
+pub fn run_command(args: &[String]) -> ::std::result::Result<(), VerifyError> {
+  match args.first().map(|arg| arg.as_str()) {
+    Some("verify") => (),
+    Some(operation) => {
+      let message = format!("unknown operation {}", operation);
+      return Err(VerifyError::Usage(message));
+    }
+    None => return Err(VerifyError::Usage("no operation".to_string())),
+  }
+  let mut arch: Option<Arch> = None;
+  let mut inputs: Vec<PathBuf> = Vec::new();
+  let mut args = args[1..].iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-arch" => {
+        let name = args.next().ok_or_else(|| {
+          VerifyError::Usage("-arch needs an architecture".to_string())
+        })?;
+        arch = Some(Arch::from_name(name).ok_or_else(|| {
+          VerifyError::Usage(format!("unknown architecture {}", name))
+        })?);
+      }
+      _ if arg.starts_with('-') => {
+        return Err(VerifyError::Usage(format!("unknown option {}", arg)));
+      }
+      _ => inputs.push(PathBuf::from(arg)),
+    }
+  }
+  if inputs.is_empty() {
+    return Err(VerifyError::Usage("no input files".to_string()));
+  }
+
+  // Check everything before giving up on the first bad file.
+  let mut invalid: Option<PathBuf> = None;
+  for input in inputs.iter() {
+    let data =
+      fs::read(input).map_err(|e| VerifyError::IoError(input.clone(), e))?;
+    let malformed = |e| VerifyError::Malformed(input.clone(), e);
+    let is_fat = fat::is_fat(&data);
+    for (slice_arch, thin) in fat::slices(&data).map_err(malformed)? {
+      if is_fat && arch.is_some() && slice_arch != arch {
+        continue;
+      }
+      let verification = verify(thin)
+        .map_err(malformed)?
+        .ok_or_else(|| VerifyError::Unsigned(input.clone()))?;
+      if is_fat {
+        let slice_arch = slice_arch.map_or("unknown", |a| a.name());
+        print!("{} (architecture {}): ", input.display(), slice_arch);
+      } else {
+        print!("{}: ", input.display());
+      }
+      print!("{}", verification.to_text());
+      if !verification.is_valid() && invalid.is_none() {
+        invalid = Some(input.clone());
+      }
+    }
+  }
+  match invalid {
+    Some(input) => Err(VerifyError::Invalid(input)),
+    None => Ok(()),
+  }
+}
+
+const SHA256_K: [u32; 64] = [
+  0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1,
+  0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3,
+  0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+  0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+  0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147,
+  0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+  0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+  0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+  0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+  0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+  0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// FIPS 180-4.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+  let mut state: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c,
+    0x1f83d9ab, 0x5be0cd19,
+  ];
+
+  let mut message = data.to_vec();
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  put_be64(&mut message, (data.len() as u64).wrapping_mul(8));
+
+  for chunk in message.chunks(64) {
+    let mut w: [u32; 64] = [0; 64];
+    for i in 0..16 {
+      w[i] = (0..4).fold(0, |acc, j| acc << 8 | chunk[i * 4 + j] as u32);
+    }
+    for i in 16..64 {
+      let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18)
+        ^ (w[i - 15] >> 3);
+      let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19)
+        ^ (w[i - 2] >> 10);
+      w[i] = w[i - 16]
+        .wrapping_add(s0)
+        .wrapping_add(w[i - 7])
+        .wrapping_add(s1);
+    }
+    let mut v = state;
+    for i in 0..64 {
+      let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11)
+        ^ v[4].rotate_right(25);
+      let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+      let t1 = v[7]
+        .wrapping_add(s1)
+        .wrapping_add(ch)
+        .wrapping_add(SHA256_K[i])
+        .wrapping_add(w[i]);
+      let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13)
+        ^ v[0].rotate_right(22);
+      let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+      let t2 = s0.wrapping_add(maj);
+      v = [
+        t1.wrapping_add(t2),
+        v[0],
+        v[1],
+        v[2],
+        v[3].wrapping_add(t1),
+        v[4],
+        v[5],
+        v[6],
+      ];
+    }
+    for (s, x) in state.iter_mut().zip(v.iter()) {
+      *s = s.wrapping_add(*x);
+    }
+  }
+
+  let mut digest: [u8; 32] = [0; 32];
+  for (i, word) in state.iter().enumerate() {
+    for j in 0..4 {
+      digest[i * 4 + j] = (word >> (24 - j * 8)) as u8;
+    }
+  }
+  digest
+}
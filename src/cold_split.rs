@@ -0,0 +1,110 @@
+// Hot/cold splitting (--split-cold): code which rarely runs goes in its own
+// section at the end of __TEXT, so the code which does is packed onto fewer
+// pages.
+//
+// A function is cold if the compiler says so: the parts of functions it
+// split off (`_f.cold` or `_f.cold.1`, as clang's machine function
+// splitter and GCC name them), and those marked N_COLD_FUNC. With a
+// --profile-order profile, it's also cold if the profile doesn't name it.
+// Like dead stripping this works on input sections, so an input __text
+// moves only when every function in it is cold; objects built with
+// -ffunction-sections have a section per function.
+
+use std::collections::HashSet;
+
+use macho::symtab::{Nlist, N_COLD_FUNC, N_SECT, N_TYPE};
+use macho::S_ATTR_PURE_INSTRUCTIONS;
+use profile_order::Profile;
+use relocatable::RelocatableInput;
+
+pub const COLD_SECTION: &str = "__text_cold";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ColdSplit {
+  // The functions moved, in input order.
+  pub functions: Vec<String>,
+  pub sections: usize,
+  pub bytes: u64,
+}
+
+impl ColdSplit {
+  pub fn report(&self) -> String {
+    format!(
+      "split cold: moved {} functions ({} bytes in {} sections) to \
+       __TEXT,{}",
+      self.functions.len(),
+      self.bytes,
+      self.sections,
+      COLD_SECTION
+    )
+  }
+}
+
+// A part of a function the compiler split off as cold.
+pub fn is_cold_part(name: &str) -> bool {
+  match name.rfind(".cold") {
+    Some(idx) => {
+      let suffix = &name[idx + ".cold".len()..];
+      suffix.is_empty()
+        || (suffix.starts_with('.')
+          && suffix.len() > 1
+          && suffix[1..].bytes().all(|b| b.is_ascii_digit()))
+    }
+    None => false,
+  }
+}
+
+// A function's symbol, rather than a local label or debug information.
+fn is_function(sym: &Nlist) -> bool {
+  !sym.is_stab()
+    && sym.n_type & N_TYPE == N_SECT
+    && sym.n_sect != 0
+    && !sym.name.starts_with('l')
+    && !sym.name.starts_with('L')
+}
+
+fn is_cold(sym: &Nlist, hot: Option<&HashSet<&str>>) -> bool {
+  if sym.n_desc & N_COLD_FUNC != 0 || is_cold_part(&sym.name) {
+    return true;
+  }
+  hot.map_or(false, |hot| !hot.contains(sym.name.as_str()))
+}
+
+// Moves each input __TEXT,__text whose functions are all cold to
+// COLD_SECTION, which merging places after the rest of __TEXT.
+pub fn split(
+  inputs: &mut [RelocatableInput],
+  profile: Option<&Profile>,
+) -> ColdSplit {
+  let hot = profile
+    .map(|profile| profile.functions())
+    .filter(|hot| !hot.is_empty());
+  let mut split = ColdSplit::default();
+  for input in inputs.iter_mut() {
+    let object = &mut input.object;
+    for (i, sect) in object.sections.iter_mut().enumerate() {
+      let is_code = sect.flags & S_ATTR_PURE_INSTRUCTIONS != 0;
+      if sect.segname != "__TEXT" || sect.sectname != "__text" || !is_code {
+        continue;
+      }
+      let ordinal = i as u8 + 1;
+      let functions: Vec<&Nlist> = object
+        .symbols
+        .iter()
+        .filter(|sym| is_function(sym) && sym.n_sect == ordinal)
+        .collect();
+      if functions.is_empty()
+        || !functions.iter().all(|sym| is_cold(sym, hot.as_ref()))
+      {
+        continue;
+      }
+      sect.sectname = COLD_SECTION.to_string();
+      split.sections += 1;
+      split.bytes += sect.size;
+      split
+        .functions
+        .extend(functions.iter().map(|sym| sym.name.clone()));
+    }
+  }
+  split
+}
@@ -0,0 +1,252 @@
+// `mold --daemon <socket>`: a linker which stays running between links,
+// keeping what it parsed (see cache.rs) so the next link with the same
+// archives and dylibs doesn't parse them again. Links are sent to it with
+// `--daemon-socket <socket>`, and run one at a time.
+//
+// Only the user running the daemon may use it: the socket is created
+// readable and writable by them alone, and requests from other users are
+// refused.
+//
+// The protocol, over a unix socket: the client sends its working directory,
+// its environment as NAME=value, an empty field, then the link's arguments,
+// each followed by a NUL, and shuts down its side. The daemon links as if
+// it had been run in that directory with that environment, and replies with
+// a status byte (0 for success) followed by the diagnostics, printed as the
+// client would have. Anything else the link prints (-t, -why_load,
+// --time-passes and so on) goes to the daemon's own stdout and stderr.
+
+extern crate libc;
+
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::Shutdown;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use args;
+use cache;
+use diagnostics::{self, Diagnostic, Format};
+use link_job::{self, LinkError};
+
+#[derive(Debug)]
+pub enum DaemonError {
+  IoError(PathBuf, io::Error),
+  Usage(String),
+}
+
+pub type Result<T> = ::std::result::Result<T, DaemonError>;
+
+// How long a client has to send its whole request. Requests are handled one
+// at a time, so one which never finishes would hold up every other client.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Link with `args` (without the program name), returning whether it
+// succeeded and what it reported, printed as the link asked.
+fn link(args: Vec<String>) -> (bool, String) {
+  let json = args.iter().any(|arg| arg == "--diagnostics-format=json");
+  let (ok, reported) = diagnostics::capture(|| {
+    let result = args::parse_args(args.into_iter())
+      .map_err(LinkError::Args)
+      .and_then(|opts| link_job::run(&opts));
+    match result {
+      Ok(()) => true,
+      Err(e) => {
+        diagnostics::report(e.diagnostic());
+        false
+      }
+    }
+  });
+  let format = if json { Format::Json } else { Format::Text };
+  let mut text = String::new();
+  for diagnostic in reported.iter() {
+    text.push_str(&render(diagnostic, format));
+    text.push('\n');
+  }
+  (ok, text)
+}
+
+fn render(diagnostic: &Diagnostic, format: Format) -> String {
+  match format {
+    Format::Text => diagnostic.to_text(),
+    Format::Json => diagnostic.to_json(),
+  }
+}
+
+fn invalid(msg: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// The user on the other end of `stream`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+  let mut cred = libc::ucred {
+    pid: 0,
+    uid: 0,
+    gid: 0,
+  };
+  let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+  let ret = unsafe {
+    libc::getsockopt(
+      stream.as_raw_fd(),
+      libc::SOL_SOCKET,
+      libc::SO_PEERCRED,
+      &mut cred as *mut libc::ucred as *mut libc::c_void,
+      &mut len,
+    )
+  };
+  if ret != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(cred.uid)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+  let mut uid: libc::uid_t = 0;
+  let mut gid: libc::gid_t = 0;
+  if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0
+  {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(uid)
+}
+
+// Switch to the client's directory and environment. They're the whole
+// process's, which is why requests are handled one at a time.
+fn enter(dir: &OsStr, vars: &[&[u8]]) -> io::Result<()> {
+  env::set_current_dir(dir)?;
+  for (name, _) in env::vars_os() {
+    env::remove_var(name);
+  }
+  for var in vars.iter() {
+    let eq = var.iter().position(|&b| b == b'=');
+    let eq = eq.ok_or_else(|| invalid("environment variable without '='"))?;
+    let (name, value) = (&var[..eq], &var[(eq + 1)..]);
+    env::set_var(OsStr::from_bytes(name), OsStr::from_bytes(value));
+  }
+  Ok(())
+}
+
+fn handle(mut stream: UnixStream) -> io::Result<()> {
+  let uid = peer_uid(&stream)?;
+  if uid != unsafe { libc::geteuid() } {
+    return Err(io::Error::new(
+      io::ErrorKind::PermissionDenied,
+      format!("refused a request from uid {}", uid),
+    ));
+  }
+  stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+  let mut request: Vec<u8> = Vec::new();
+  stream.read_to_end(&mut request)?;
+  if request.is_empty() {
+    // Another daemon checking whether this one is still listening.
+    return Ok(());
+  }
+  if request.last() != Some(&0) {
+    return Err(invalid("request doesn't end with a NUL"));
+  }
+  request.pop();
+  let fields: Vec<&[u8]> = request.split(|&b| b == 0).collect();
+  let end_of_env = fields
+    .iter()
+    .skip(1)
+    .position(|field| field.is_empty())
+    .ok_or_else(|| invalid("request has no end to its environment"))?;
+  enter(OsStr::from_bytes(fields[0]), &fields[1..(end_of_env + 1)])?;
+  let args: Vec<String> = fields[(end_of_env + 2)..]
+    .iter()
+    .map(|arg| String::from_utf8_lossy(arg).into_owned())
+    .collect();
+  let (ok, text) = link(args);
+  let mut response: Vec<u8> = vec![if ok { 0 } else { 1 }];
+  response.extend_from_slice(text.as_bytes());
+  stream.write_all(&response)
+}
+
+// Listen on `socket`, linking whatever is sent, until killed.
+pub fn serve(socket: &Path) -> io::Result<()> {
+  // A socket left by a daemon which didn't exit cleanly is replaced. One a
+  // daemon still answers on is in use, and anything else isn't ours.
+  match fs::symlink_metadata(socket) {
+    Ok(metadata) => {
+      if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+          io::ErrorKind::AlreadyExists,
+          "the path exists, and isn't a socket",
+        ));
+      }
+      if UnixStream::connect(socket).is_ok() {
+        return Err(io::Error::new(
+          io::ErrorKind::AddrInUse,
+          "another daemon is listening on it",
+        ));
+      }
+      fs::remove_file(socket)?;
+    }
+    Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+    Err(e) => return Err(e),
+  }
+  // Created without permissions for anyone else (0600), rather than changed
+  // after, so there's no moment where others can connect.
+  let umask = unsafe { libc::umask(0o177) };
+  let listener = UnixListener::bind(socket);
+  unsafe { libc::umask(umask) };
+  let listener = listener?;
+  cache::enable();
+  // Each link runs to completion before the next is accepted.
+  for stream in listener.incoming() {
+    let result = stream.and_then(handle);
+    if let Err(e) = result {
+      diagnostics::warning(&format!("daemon request failed: {}", e));
+    }
+  }
+  Ok(())
+}
+
+// Have the daemon listening on `socket` run the link with `args`, printing
+// what it reports. Returns whether the link succeeded, or an error if
+// there's no daemon to ask.
+pub fn forward(socket: &Path, args: &[String]) -> io::Result<bool> {
+  let mut stream = UnixStream::connect(socket)?;
+  let mut request: Vec<u8> = Vec::new();
+  let mut field = |bytes: &[u8]| {
+    request.extend_from_slice(bytes);
+    request.push(0);
+  };
+  field(env::current_dir()?.as_os_str().as_bytes());
+  for (name, value) in env::vars_os() {
+    let mut var: OsString = name;
+    var.push("=");
+    var.push(value);
+    field(&var.into_vec());
+  }
+  field(b"");
+  for arg in args.iter() {
+    field(arg.as_bytes());
+  }
+  stream.write_all(&request)?;
+  stream.shutdown(Shutdown::Write)?;
+  let mut response: Vec<u8> = Vec::new();
+  stream.read_to_end(&mut response)?;
+  let (status, text) = response
+    .split_first()
+    .ok_or_else(|| invalid("the daemon closed the connection"))?;
+  io::stderr().write_all(text)?;
+  Ok(*status == 0)
+}
+
+// mold --daemon <socket>
+pub fn run_command(args: &[String]) -> Result<()> {
+  let socket = match args {
+    [socket] => PathBuf::from(socket),
+    _ => return Err(DaemonError::Usage("mold --daemon <socket>".to_string())),
+  };
+  serve(&socket).map_err(|e| DaemonError::IoError(socket.clone(), e))
+}
@@ -0,0 +1,350 @@
+// Dead stripping (-dead_strip): drop input sections nothing live refers to.
+//
+// Each input section is an atom. The roots are the sections defining
+// symbols visible outside the output (or the entry point of an executable),
+// sections marked S_ATTR_NO_DEAD_STRIP, symbols marked N_NO_DEAD_STRIP or
+// named by -u, initializers, interposing tables and DTrace DOF. Anything
+// reachable from a root through relocations is live. S_ATTR_LIVE_SUPPORT
+// sections (unwind info and the like) are live only if they refer to
+// something else which is.
+
+use std::collections::{HashMap, VecDeque};
+
+use args::{LinkOptions, OutputKind};
+use final_link;
+use macho::object_file::{ObjectFile, ObjectSection};
+use macho::reloc::{self, ARM64_RELOC_ADDEND};
+use macho::symtab::{Nlist, N_NO_DEAD_STRIP, N_SECT, N_TYPE};
+use macho::{section_type, Arch, S_ATTR_LIVE_SUPPORT, S_ATTR_NO_DEAD_STRIP,
+            S_DTRACE_DOF, S_INTERPOSING, S_MOD_INIT_FUNC_POINTERS,
+            S_MOD_TERM_FUNC_POINTERS};
+use relocatable::RelocatableInput;
+
+// (input index, section index)
+pub type Atom = (usize, usize);
+
+pub struct Liveness {
+  // Indexed like the inputs and their sections.
+  pub live: Vec<Vec<bool>>,
+  // Why each live atom is live: the atom which first referred to it, and
+  // the symbol it was referred to by. Roots usually have no entry.
+  reasons: HashMap<Atom, (Atom, String)>,
+}
+
+fn defines_in_section(sym: &Nlist) -> bool {
+  !sym.is_stab() && sym.n_type & N_TYPE == N_SECT && sym.n_sect != 0
+}
+
+fn is_root_symbol(sym: &Nlist, opts: &LinkOptions) -> bool {
+  if sym.n_desc & N_NO_DEAD_STRIP != 0 {
+    return true;
+  }
+  let hidden = sym.is_private_extern() && !opts.keep_private_externs;
+  if !sym.is_external() || hidden {
+    return false;
+  }
+  if opts.forced_undefined.contains(&sym.name) {
+    return true;
+  }
+  match opts.output_kind {
+    OutputKind::Executable | OutputKind::Preload => {
+      sym.name == final_link::entry_symbol(opts)
+    }
+    _ => true,
+  }
+}
+
+fn is_root_section(sect: &ObjectSection) -> bool {
+  match section_type(&sect.segname, &sect.sectname, sect.flags) {
+    S_MOD_INIT_FUNC_POINTERS
+    | S_MOD_TERM_FUNC_POINTERS
+    | S_INTERPOSING
+    | S_DTRACE_DOF => true,
+    _ => sect.flags & S_ATTR_NO_DEAD_STRIP != 0,
+  }
+}
+
+// The atoms each atom refers to, with the names used to refer to them.
+fn references(
+  arch: Arch,
+  inputs: &[RelocatableInput],
+) -> HashMap<Atom, Vec<(Atom, String)>> {
+  // Where each global is defined. The first strong definition wins, as in
+  // resolution; otherwise the first weak one.
+  let mut globals: HashMap<&str, (Atom, bool)> = HashMap::new();
+  for (i, input) in inputs.iter().enumerate() {
+    for sym in input.object.symbols.iter() {
+      if !sym.is_external() || !defines_in_section(sym) {
+        continue;
+      }
+      let atom = (i, sym.n_sect as usize - 1);
+      let weak = sym.is_weak_def();
+      let replace = match globals.get(sym.name.as_str()) {
+        None => true,
+        Some(&(_, existing_weak)) => existing_weak && !weak,
+      };
+      if replace {
+        globals.insert(&sym.name, (atom, weak));
+      }
+    }
+  }
+
+  let mut refs: HashMap<Atom, Vec<(Atom, String)>> = HashMap::new();
+  for (i, input) in inputs.iter().enumerate() {
+    let object = &input.object;
+    for (j, sect) in object.sections.iter().enumerate() {
+      let mut targets: Vec<(Atom, String)> = Vec::new();
+      for reloc in sect.relocs.iter() {
+        let is_addend = arch.is_arm64() && reloc.kind == ARM64_RELOC_ADDEND;
+        if is_addend || reloc::is_pair(arch, reloc.kind) {
+          continue;
+        }
+        if let Some(addr) = reloc.scattered {
+          let addr = addr as u64;
+          let target = object
+            .sections
+            .iter()
+            .position(|sect| addr >= sect.addr && addr < sect.addr + sect.size);
+          if let Some(target) = target {
+            targets.push(((i, target), atom_name(object, target)));
+          }
+        } else if reloc.external {
+          let sym = match object.symbols.get(reloc.symbolnum as usize) {
+            Some(sym) => sym,
+            None => continue,
+          };
+          let target = if sym.is_external() {
+            globals.get(sym.name.as_str()).map(|&(atom, _)| atom)
+          } else if defines_in_section(sym) {
+            Some((i, sym.n_sect as usize - 1))
+          } else {
+            None
+          };
+          if let Some(atom) = target {
+            targets.push((atom, sym.name.clone()));
+          }
+        } else if reloc.symbolnum >= 1 {
+          let target = reloc.symbolnum as usize - 1;
+          if target < object.sections.len() {
+            targets.push(((i, target), atom_name(object, target)));
+          }
+        }
+      }
+      refs.insert((i, j), targets);
+    }
+  }
+  refs
+}
+
+// A name for an atom in diagnostics: the first symbol it defines, or its
+// section name.
+fn atom_name(object: &ObjectFile, section: usize) -> String {
+  object
+    .symbols
+    .iter()
+    .find(|sym| defines_in_section(sym) && sym.n_sect as usize == section + 1)
+    .map(|sym| sym.name.clone())
+    .unwrap_or_else(|| {
+      let sect = &object.sections[section];
+      format!("{},{}", sect.segname, sect.sectname)
+    })
+}
+
+impl Liveness {
+  pub fn compute(opts: &LinkOptions, inputs: &[RelocatableInput]) -> Self {
+    let refs = references(opts.arch, inputs);
+    let mut liveness = Liveness {
+      live: inputs
+        .iter()
+        .map(|input| vec![false; input.object.sections.len()])
+        .collect(),
+      reasons: HashMap::new(),
+    };
+    let mut queue: VecDeque<Atom> = VecDeque::new();
+    for (i, input) in inputs.iter().enumerate() {
+      for (j, sect) in input.object.sections.iter().enumerate() {
+        if is_root_section(sect) {
+          queue.push_back((i, j));
+        }
+      }
+      for sym in input.object.symbols.iter() {
+        if defines_in_section(sym) && is_root_symbol(sym, opts) {
+          queue.push_back((i, sym.n_sect as usize - 1));
+        }
+      }
+    }
+
+    loop {
+      while let Some((i, j)) = queue.pop_front() {
+        if liveness.live[i][j] {
+          continue;
+        }
+        liveness.live[i][j] = true;
+        for &(target, ref name) in refs[&(i, j)].iter() {
+          if !liveness.live[target.0][target.1] {
+            liveness
+              .reasons
+              .entry(target)
+              .or_insert(((i, j), name.clone()));
+            queue.push_back(target);
+          }
+        }
+      }
+      // Live-support sections which refer to anything live are live too,
+      // and may make more live in turn.
+      for (i, input) in inputs.iter().enumerate() {
+        for (j, sect) in input.object.sections.iter().enumerate() {
+          let supports_live = sect.flags & S_ATTR_LIVE_SUPPORT != 0
+            && !liveness.live[i][j]
+            && refs[&(i, j)]
+              .iter()
+              .any(|&((ti, tj), _)| liveness.live[ti][tj]);
+          if supports_live {
+            queue.push_back((i, j));
+          }
+        }
+      }
+      if queue.is_empty() {
+        return liveness;
+      }
+    }
+  }
+
+  // -why_live: the chain of references from `name` back to a root, one
+  // line per atom, each indented under the one it keeps alive. None if no
+  // live atom defines `name`.
+  pub fn why_live(
+    &self,
+    inputs: &[RelocatableInput],
+    name: &str,
+  ) -> Option<Vec<String>> {
+    let mut atom = inputs.iter().enumerate().filter_map(|(i, input)| {
+      input
+        .object
+        .symbols
+        .iter()
+        .find(|sym| sym.name == name && defines_in_section(sym))
+        .map(|sym| (i, sym.n_sect as usize - 1))
+    }).find(|&(i, j)| self.live[i][j])?;
+    let mut lines: Vec<String> = Vec::new();
+    let mut label = name.to_string();
+    loop {
+      lines.push(format!(
+        "{}{} from {}",
+        "  ".repeat(lines.len()),
+        label,
+        inputs[atom.0].path.display()
+      ));
+      // Parents went live before their children, so this terminates.
+      match self.reasons.get(&atom) {
+        Some(&(parent, _)) => {
+          // The name the parent was itself referred to by, if it isn't a
+          // root.
+          label = match self.reasons.get(&parent) {
+            Some(&(_, ref name)) => name.clone(),
+            None => atom_name(&inputs[parent.0].object, parent.1),
+          };
+          atom = parent;
+        }
+        _ => return Some(lines),
+      }
+    }
+  }
+}
+
+// `input` without its dead sections, with section ordinals and symbol
+// indices renumbered to match.
+pub fn strip(
+  arch: Arch,
+  input: &RelocatableInput,
+  live: &[bool],
+) -> RelocatableInput {
+  let object = &input.object;
+  // New 1-based ordinal for each old section, or 0 if it's dead.
+  let mut ordinals: Vec<u8> = Vec::new();
+  let mut next: u8 = 1;
+  for &is_live in live.iter() {
+    ordinals.push(if is_live { next } else { 0 });
+    if is_live {
+      next += 1;
+    }
+  }
+  let mut symbol_map: Vec<Option<u32>> = Vec::new();
+  let mut symbols: Vec<Nlist> = Vec::new();
+  for sym in object.symbols.iter() {
+    let in_dead_section =
+      defines_in_section(sym) && ordinals[sym.n_sect as usize - 1] == 0;
+    if in_dead_section {
+      symbol_map.push(None);
+      continue;
+    }
+    let mut sym = sym.clone();
+    if defines_in_section(&sym) {
+      sym.n_sect = ordinals[sym.n_sect as usize - 1];
+    }
+    symbol_map.push(Some(symbols.len() as u32));
+    symbols.push(sym);
+  }
+
+  let mut sections = Vec::new();
+  for (sect, &is_live) in object.sections.iter().zip(live.iter()) {
+    if !is_live {
+      continue;
+    }
+    let mut sect = sect.clone();
+    for reloc in sect.relocs.iter_mut() {
+      let is_addend = arch.is_arm64() && reloc.kind == ARM64_RELOC_ADDEND;
+      if is_addend || reloc::is_pair(arch, reloc.kind) {
+        // symbolnum is the addend, or unused.
+      } else if reloc.scattered.is_some() {
+        // Section addresses don't change.
+      } else if reloc.external {
+        // Live sections only refer to live symbols.
+        if let Some(&Some(idx)) = symbol_map.get(reloc.symbolnum as usize) {
+          reloc.symbolnum = idx;
+        }
+      } else if reloc.symbolnum >= 1 {
+        if let Some(&ordinal) = ordinals.get(reloc.symbolnum as usize - 1) {
+          reloc.symbolnum = ordinal as u32;
+        }
+      }
+    }
+    sections.push(sect);
+  }
+  RelocatableInput {
+    path: input.path.clone(),
+    object: ObjectFile {
+      cputype: object.cputype,
+      cpusubtype: object.cpusubtype,
+      flags: object.flags,
+      sections: sections,
+      symbols: symbols,
+      platform: object.platform,
+      // Hints for instructions in dead sections go with them.
+      loh: object
+        .loh
+        .iter()
+        .filter(|hint| {
+          hint.addresses.iter().all(|&addr| {
+            object.sections.iter().zip(live.iter()).any(|(sect, &live)| {
+              live && addr >= sect.addr && addr < sect.addr + sect.size
+            })
+          })
+        })
+        .cloned()
+        .collect(),
+      // And so does their data.
+      data_in_code: object
+        .data_in_code
+        .iter()
+        .filter(|entry| {
+          let addr = entry.offset as u64;
+          object.sections.iter().zip(live.iter()).any(|(sect, &live)| {
+            live && addr >= sect.addr && addr < sect.addr + sect.size
+          })
+        })
+        .cloned()
+        .collect(),
+    },
+  }
+}
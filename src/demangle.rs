@@ -0,0 +1,439 @@
+// Demangling of Itanium C++ ABI names, which is what clang emits for Apple
+// targets, for diagnostics. Only the common forms are understood: functions
+// and variables in namespaces and classes, taking builtin, class, pointer
+// and reference types, with templates and substitutions. Anything else is
+// left mangled.
+
+const BUILTINS: &[(u8, &str)] = &[
+  (b'v', "void"),
+  (b'w', "wchar_t"),
+  (b'b', "bool"),
+  (b'c', "char"),
+  (b'a', "signed char"),
+  (b'h', "unsigned char"),
+  (b's', "short"),
+  (b't', "unsigned short"),
+  (b'i', "int"),
+  (b'j', "unsigned int"),
+  (b'l', "long"),
+  (b'm', "unsigned long"),
+  (b'x', "long long"),
+  (b'y', "unsigned long long"),
+  (b'n', "__int128"),
+  (b'o', "unsigned __int128"),
+  (b'f', "float"),
+  (b'd', "double"),
+  (b'e', "long double"),
+  (b'z', "..."),
+];
+
+// The abbreviations which don't have to be substituted first.
+const STD_SUBSTITUTIONS: &[(u8, &str)] = &[
+  (b'a', "std::allocator"),
+  (b'b', "std::basic_string"),
+  (b's', "std::string"),
+  (b'i', "std::istream"),
+  (b'o', "std::ostream"),
+  (b'd', "std::iostream"),
+];
+
+const OPERATORS: &[(&str, &str)] = &[
+  ("nw", "new"),
+  ("na", "new[]"),
+  ("dl", "delete"),
+  ("da", "delete[]"),
+  ("ps", "+"),
+  ("ng", "-"),
+  ("ad", "&"),
+  ("de", "*"),
+  ("co", "~"),
+  ("pl", "+"),
+  ("mi", "-"),
+  ("ml", "*"),
+  ("dv", "/"),
+  ("rm", "%"),
+  ("an", "&"),
+  ("or", "|"),
+  ("eo", "^"),
+  ("aS", "="),
+  ("pL", "+="),
+  ("mI", "-="),
+  ("mL", "*="),
+  ("dV", "/="),
+  ("eq", "=="),
+  ("ne", "!="),
+  ("lt", "<"),
+  ("gt", ">"),
+  ("le", "<="),
+  ("ge", ">="),
+  ("nt", "!"),
+  ("aa", "&&"),
+  ("oo", "||"),
+  ("pp", "++"),
+  ("mm", "--"),
+  ("cm", ","),
+  ("pt", "->"),
+  ("cl", "()"),
+  ("ix", "[]"),
+  ("ls", "<<"),
+  ("rs", ">>"),
+];
+
+// `symbol` (a Mach-O symbol name, with its extra leading underscore)
+// demangled, or None if it isn't a C++ name or uses something we don't
+// understand.
+pub fn demangle(symbol: &str) -> Option<String> {
+  if !symbol.starts_with("__Z") {
+    return None;
+  }
+  let mut parser = Parser {
+    input: &symbol.as_bytes()[3..],
+    pos: 0,
+    subs: Vec::new(),
+    template_args: Vec::new(),
+  };
+  let demangled = parser.encoding()?;
+  if parser.at_end() {
+    Some(demangled)
+  } else {
+    None
+  }
+}
+
+// Whether `symbol` is a mangled C++ name.
+pub fn is_mangled(symbol: &str) -> bool {
+  symbol.starts_with("__Z")
+}
+
+struct Parser<'a> {
+  input: &'a [u8],
+  pos: usize,
+  // Everything which can be referred back to with S_, S0_ and so on.
+  subs: Vec<String>,
+  // The arguments of the last template, for T_, T0_ and so on.
+  template_args: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+  // At the end of the name, or of the mangling with a clone suffix like
+  // ".cold.1" left.
+  fn at_end(&self) -> bool {
+    self.pos == self.input.len() || self.peek() == Some(b'.')
+  }
+
+  fn peek(&self) -> Option<u8> {
+    self.input.get(self.pos).cloned()
+  }
+
+  fn peek_at(&self, offset: usize) -> Option<u8> {
+    self.input.get(self.pos + offset).cloned()
+  }
+
+  fn eat(&mut self, c: u8) -> bool {
+    if self.peek() == Some(c) {
+      self.pos += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn next(&mut self) -> Option<u8> {
+    let c = self.peek()?;
+    self.pos += 1;
+    Some(c)
+  }
+
+  fn number(&mut self) -> Option<usize> {
+    let start = self.pos;
+    while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+      self.pos += 1;
+    }
+    ::std::str::from_utf8(&self.input[start..self.pos])
+      .ok()?
+      .parse()
+      .ok()
+  }
+
+  fn source_name(&mut self) -> Option<String> {
+    let len = self.number()?;
+    let name = self.input.get(self.pos..(self.pos + len))?;
+    self.pos += len;
+    String::from_utf8(name.to_vec()).ok()
+  }
+
+  // A function (name and parameter types) or a variable (just its name).
+  fn encoding(&mut self) -> Option<String> {
+    let (name, is_template, qualifiers) = self.name()?;
+    if self.at_end() {
+      return Some(name);
+    }
+    // Template functions have their return type first.
+    if is_template {
+      self.type_()?;
+    }
+    let mut params: Vec<String> = Vec::new();
+    while !self.at_end() {
+      params.push(self.type_()?);
+    }
+    if params.len() == 1 && params[0] == "void" {
+      params.clear();
+    }
+    Some(format!("{}({}){}", name, params.join(", "), qualifiers))
+  }
+
+  // The name, whether it ends in template arguments, and any qualifiers of
+  // a member function.
+  fn name(&mut self) -> Option<(String, bool, String)> {
+    match self.peek()? {
+      b'N' => self.nested_name(false),
+      b'Z' => None,
+      b'S' => {
+        let name = if self.peek_at(1) == Some(b't') {
+          self.pos += 2;
+          let name = format!("std::{}", self.unqualified_name("")?);
+          self.subs.push(name.clone());
+          name
+        } else {
+          self.substitution()?
+        };
+        self.template_name(name)
+      }
+      _ => {
+        let name = self.unqualified_name("")?;
+        if self.peek() == Some(b'I') {
+          self.subs.push(name.clone());
+        }
+        self.template_name(name)
+      }
+    }
+  }
+
+  // `name`, and its template arguments if it has any.
+  fn template_name(&mut self, name: String) -> Option<(String, bool, String)> {
+    if self.peek() == Some(b'I') {
+      let args = self.template_args()?;
+      Some((format!("{}{}", name, args), true, String::new()))
+    } else {
+      Some((name, false, String::new()))
+    }
+  }
+
+  // N [qualifiers] prefix... E. The prefixes can be substituted later, and
+  // so can the whole name when it's a type.
+  fn nested_name(&mut self, is_type: bool) -> Option<(String, bool, String)> {
+    self.eat(b'N');
+    let mut qualifiers = String::new();
+    if self.eat(b'r') {
+      qualifiers.push_str(" restrict");
+    }
+    if self.eat(b'V') {
+      qualifiers.push_str(" volatile");
+    }
+    if self.eat(b'K') {
+      qualifiers.push_str(" const");
+    }
+    if self.eat(b'R') {
+      qualifiers.push_str(" &");
+    } else if self.eat(b'O') {
+      qualifiers.push_str(" &&");
+    }
+
+    let mut name = String::new();
+    // The last unqualified name, for constructors and destructors.
+    let mut last = String::new();
+    let mut is_template = false;
+    let mut pushed = false;
+    while !self.eat(b'E') {
+      is_template = false;
+      match self.peek()? {
+        b'S' if self.peek_at(1) == Some(b't') => {
+          self.pos += 2;
+          name = "std".to_string();
+          continue;
+        }
+        b'S' => {
+          name = self.substitution()?;
+          last = name.rsplit("::").next().unwrap_or("").to_string();
+          continue;
+        }
+        b'I' => {
+          name.push_str(&self.template_args()?);
+          is_template = true;
+        }
+        _ => {
+          let component = self.unqualified_name(&last)?;
+          last = component.clone();
+          if !name.is_empty() {
+            name.push_str("::");
+          }
+          name.push_str(&component);
+        }
+      }
+      self.subs.push(name.clone());
+      pushed = true;
+    }
+    // A function's own name can't be substituted, only its prefixes.
+    if !is_type && pushed {
+      self.subs.pop();
+    }
+    Some((name, is_template, qualifiers))
+  }
+
+  // A source name, operator, or constructor or destructor of `class`.
+  fn unqualified_name(&mut self, class: &str) -> Option<String> {
+    let c = self.peek()?;
+    if c.is_ascii_digit() {
+      return self.source_name();
+    }
+    let class = match class.find('<') {
+      Some(end) => &class[..end],
+      None => class,
+    };
+    match (c, self.peek_at(1)?) {
+      (b'C', b'1'..=b'3') => {
+        self.pos += 2;
+        Some(class.to_string())
+      }
+      (b'D', b'0'..=b'2') => {
+        self.pos += 2;
+        Some(format!("~{}", class))
+      }
+      (a, b) => {
+        let code = [a, b];
+        let op = OPERATORS
+          .iter()
+          .find(|&&(code_str, _)| code_str.as_bytes() == &code[..])?;
+        self.pos += 2;
+        Some(format!("operator{}", op.1))
+      }
+    }
+  }
+
+  fn substitution(&mut self) -> Option<String> {
+    self.eat(b'S');
+    let c = self.next()?;
+    if let Some(&(_, name)) = STD_SUBSTITUTIONS.iter().find(|s| s.0 == c) {
+      return Some(name.to_string());
+    }
+    let mut index: usize = 0;
+    let mut c = c;
+    if c != b'_' {
+      index += 1;
+      let mut seq: usize = 0;
+      while c != b'_' {
+        let digit = match c {
+          b'0'..=b'9' => c - b'0',
+          b'A'..=b'Z' => c - b'A' + 10,
+          _ => return None,
+        };
+        seq = seq * 36 + digit as usize;
+        c = self.next()?;
+      }
+      index += seq;
+    }
+    self.subs.get(index).cloned()
+  }
+
+  fn template_args(&mut self) -> Option<String> {
+    self.eat(b'I');
+    let mut args: Vec<String> = Vec::new();
+    while !self.eat(b'E') {
+      if self.eat(b'L') {
+        // An integer literal: its type, then its value.
+        self.type_()?;
+        let negative = self.eat(b'n');
+        let value = self.number()?;
+        if !self.eat(b'E') {
+          return None;
+        }
+        args.push(format!("{}{}", if negative { "-" } else { "" }, value));
+      } else {
+        args.push(self.type_()?);
+      }
+    }
+    self.template_args = args.clone();
+    let args = args.join(", ");
+    // Avoid ">>", as c++filt does.
+    if args.ends_with('>') {
+      Some(format!("<{} >", args))
+    } else {
+      Some(format!("<{}>", args))
+    }
+  }
+
+  fn type_(&mut self) -> Option<String> {
+    let c = self.peek()?;
+    if let Some(&(_, name)) = BUILTINS.iter().find(|b| b.0 == c) {
+      self.pos += 1;
+      return Some(name.to_string());
+    }
+    let ty = match c {
+      b'P' | b'R' | b'O' | b'K' | b'V' => {
+        self.pos += 1;
+        let inner = self.type_()?;
+        match c {
+          b'P' => format!("{}*", inner),
+          b'R' => format!("{}&", inner),
+          b'O' => format!("{}&&", inner),
+          b'K' => format!("{} const", inner),
+          _ => format!("{} volatile", inner),
+        }
+      }
+      b'D' => {
+        self.pos += 1;
+        return match self.next()? {
+          b'n' => Some("std::nullptr_t".to_string()),
+          b's' => Some("char16_t".to_string()),
+          b'i' => Some("char32_t".to_string()),
+          _ => None,
+        };
+      }
+      b'N' => return self.nested_name(true).map(|(name, _, _)| name),
+      b'S' if self.peek_at(1) == Some(b't') => {
+        self.pos += 2;
+        let name = format!("std::{}", self.source_name()?);
+        self.class_type(name)?
+      }
+      b'S' => {
+        let name = self.substitution()?;
+        if self.peek() != Some(b'I') {
+          return Some(name);
+        }
+        let args = self.template_args()?;
+        format!("{}{}", name, args)
+      }
+      b'T' => {
+        self.pos += 1;
+        let index = if self.eat(b'_') {
+          0
+        } else {
+          let index = self.number()? + 1;
+          if !self.eat(b'_') {
+            return None;
+          }
+          index
+        };
+        self.template_args.get(index)?.clone()
+      }
+      b'0'..=b'9' => {
+        let name = self.source_name()?;
+        self.class_type(name)?
+      }
+      _ => return None,
+    };
+    self.subs.push(ty.clone());
+    Some(ty)
+  }
+
+  // A class named `name`, with template arguments if it has any. Both the
+  // template and its specialization can be substituted.
+  fn class_type(&mut self, name: String) -> Option<String> {
+    if self.peek() != Some(b'I') {
+      return Some(name);
+    }
+    self.subs.push(name.clone());
+    let args = self.template_args()?;
+    Some(format!("{}{}", name, args))
+  }
+}
@@ -0,0 +1,126 @@
+// -dependency_info: the files a link read, looked for without finding, and
+// wrote, in the binary format Xcode's build system reads to decide when to
+// relink. Each record is an opcode byte followed by a NUL-terminated string.
+//
+// What's listed is recorded as the link goes, where each file is read, so
+// it includes what the options only lead to: dylibs re-exported by the ones
+// linked, say. Like the diagnostics sink, the recorder is per thread, and
+// threads doing work for a link carry it over with `with_recorder`.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const DEPINFO_VERSION: u8 = 0x00;
+const DEPINFO_INPUT_FOUND: u8 = 0x10;
+const DEPINFO_INPUT_MISSING: u8 = 0x11;
+const DEPINFO_OUTPUT: u8 = 0x40;
+
+#[derive(Debug, Default)]
+pub struct DependencyInfo {
+  pub inputs: BTreeSet<PathBuf>,
+  pub missing: BTreeSet<PathBuf>,
+  pub output: PathBuf,
+  // --skip-unchanged found the output up to date, so it wasn't relinked.
+  pub skipped: bool,
+}
+
+impl DependencyInfo {
+  pub fn write(&self, path: &Path) -> io::Result<()> {
+    let mut buf: Vec<u8> = Vec::new();
+    // The version record is free text, so whether the link was skipped goes
+    // there, where anything reading the record can see it.
+    let mut version = format!("mold-{}", env!("CARGO_PKG_VERSION"));
+    if self.skipped {
+      version.push_str(" (skipped: output up to date)");
+    }
+    put_record(&mut buf, DEPINFO_VERSION, &version);
+    for input in self.inputs.iter() {
+      put_record(&mut buf, DEPINFO_INPUT_FOUND, &input.to_string_lossy());
+    }
+    for missing in self.missing.iter() {
+      put_record(&mut buf, DEPINFO_INPUT_MISSING, &missing.to_string_lossy());
+    }
+    put_record(&mut buf, DEPINFO_OUTPUT, &self.output.to_string_lossy());
+    File::create(path)?.write_all(&buf)
+  }
+}
+
+fn put_record(buf: &mut Vec<u8>, opcode: u8, value: &str) {
+  buf.push(opcode);
+  buf.extend_from_slice(value.as_bytes());
+  buf.push(0);
+}
+
+pub type Recorder = Arc<Mutex<DependencyInfo>>;
+
+thread_local! {
+  static RECORDER: RefCell<Option<Recorder>> = const { RefCell::new(None) };
+}
+
+// Puts the previous recorder back, even if the work panics.
+struct Restore(Option<Recorder>);
+
+impl Drop for Restore {
+  fn drop(&mut self) {
+    let previous = self.0.take();
+    RECORDER.with(|recorder| *recorder.borrow_mut() = previous);
+  }
+}
+
+pub fn current_recorder() -> Option<Recorder> {
+  RECORDER.with(|recorder| recorder.borrow().clone())
+}
+
+// Run `f` with the files it reads going to `recorder`, or nowhere for None.
+pub fn with_recorder<T, F: FnOnce() -> T>(
+  recorder: Option<Recorder>,
+  f: F,
+) -> T {
+  let _restore = Restore(RECORDER.with(|current| current.replace(recorder)));
+  f()
+}
+
+// Run `f`, the link writing `output`, recording the files it reads.
+pub fn recording<T, F: FnOnce() -> T>(
+  output: &Path,
+  f: F,
+) -> (T, DependencyInfo) {
+  let recorder: Recorder = Arc::new(Mutex::new(DependencyInfo {
+    output: output.to_path_buf(),
+    ..Default::default()
+  }));
+  let result = with_recorder(Some(recorder.clone()), f);
+  let info = match recorder.lock() {
+    Ok(mut info) => mem::take(&mut *info),
+    Err(poisoned) => mem::take(&mut *poisoned.into_inner()),
+  };
+  (result, info)
+}
+
+fn update<F: FnOnce(&mut DependencyInfo)>(f: F) {
+  if let Some(recorder) = current_recorder() {
+    match recorder.lock() {
+      Ok(mut info) => f(&mut info),
+      Err(poisoned) => f(&mut poisoned.into_inner()),
+    }
+  }
+}
+
+// The link is reading `path`.
+pub fn record_input(path: &Path) {
+  update(|info| {
+    info.inputs.insert(path.to_path_buf());
+  });
+}
+
+// The link looked for `path`, and would have used it if it were there.
+pub fn record_missing(path: &Path) {
+  update(|info| {
+    info.missing.insert(path.to_path_buf());
+  });
+}
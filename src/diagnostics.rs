@@ -0,0 +1,204 @@
+// Reporting of warnings and errors to the user.
+//
+// They go to stderr, as text or (with --diagnostics-format=json) one JSON
+// object per line, unless the link is running in-process (see LinkJob),
+// in which case they are collected in a sink for the caller. The sink is
+// per thread, so threads doing work for a link have to carry it over with
+// `with_sink`.
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+  // Information the user asked for, such as --split-cold-report.
+  Note,
+  Warning,
+  Error,
+}
+
+impl Severity {
+  pub fn name(&self) -> &'static str {
+    match *self {
+      Severity::Note => "note",
+      Severity::Warning => "warning",
+      Severity::Error => "error",
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  // What kind of problem this is, for tools to match on: "duplicate-symbol"
+  // and so on. Only errors have codes so far.
+  pub code: Option<&'static str>,
+  pub message: String,
+  // The input and symbol it's about, if any, and the sections referring to
+  // the symbol, as "path(segname,sectname)".
+  pub file: Option<PathBuf>,
+  pub symbol: Option<String>,
+  pub sections: Vec<String>,
+}
+
+impl Diagnostic {
+  pub fn new(severity: Severity, message: String) -> Diagnostic {
+    Diagnostic {
+      severity: severity,
+      code: None,
+      message: message,
+      file: None,
+      symbol: None,
+      sections: Vec::new(),
+    }
+  }
+
+  // As printed to stderr without --diagnostics-format=json.
+  pub fn to_text(&self) -> String {
+    format!("mold: {}: {}", self.severity.name(), self.message)
+  }
+
+  pub fn to_json(&self) -> String {
+    let optional = |value: Option<String>| match value {
+      Some(value) => json_string(&value),
+      None => "null".to_string(),
+    };
+    let sections: Vec<String> =
+      self.sections.iter().map(|s| json_string(s)).collect();
+    format!(
+      "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},\
+       \"symbol\":{},\"sections\":[{}]}}",
+      json_string(self.severity.name()),
+      optional(self.code.map(|code| code.to_string())),
+      json_string(&self.message),
+      optional(self.file.as_ref().map(|f| f.display().to_string())),
+      optional(self.symbol.clone()),
+      sections.join(",")
+    )
+  }
+}
+
+// `s` as a JSON string literal.
+pub fn json_string(s: &str) -> String {
+  let mut out = String::from("\"");
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => {
+        out.push_str(&format!("\\u{:04x}", c as u32));
+      }
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+  out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Text,
+  Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+// How diagnostics which aren't collected in a sink are printed.
+pub fn set_format(format: Format) {
+  JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+pub type Sink = Arc<Mutex<Vec<Diagnostic>>>;
+
+thread_local! {
+  static SINK: RefCell<Option<Sink>> = const { RefCell::new(None) };
+}
+
+// Puts the previous sink back, even if the work panics.
+struct Restore(Option<Sink>);
+
+impl Drop for Restore {
+  fn drop(&mut self) {
+    let previous = self.0.take();
+    SINK.with(|sink| *sink.borrow_mut() = previous);
+  }
+}
+
+pub fn current_sink() -> Option<Sink> {
+  SINK.with(|sink| sink.borrow().clone())
+}
+
+// Run `f` with diagnostics going to `sink`, or to stderr for None.
+pub fn with_sink<T, F: FnOnce() -> T>(sink: Option<Sink>, f: F) -> T {
+  let _restore = Restore(SINK.with(|current| current.replace(sink)));
+  f()
+}
+
+// Run `f`, collecting what it reports instead of printing it.
+pub fn capture<T, F: FnOnce() -> T>(f: F) -> (T, Vec<Diagnostic>) {
+  let sink: Sink = Arc::new(Mutex::new(Vec::new()));
+  let result = with_sink(Some(sink.clone()), f);
+  let diagnostics = match sink.lock() {
+    Ok(mut diagnostics) => diagnostics.drain(..).collect(),
+    Err(poisoned) => poisoned.into_inner().drain(..).collect(),
+  };
+  (result, diagnostics)
+}
+
+pub fn report(diagnostic: Diagnostic) {
+  if let Some(sink) = current_sink() {
+    match sink.lock() {
+      Ok(mut diagnostics) => diagnostics.push(diagnostic),
+      Err(poisoned) => poisoned.into_inner().push(diagnostic),
+    }
+  } else if JSON.load(Ordering::Relaxed) {
+    eprintln!("{}", diagnostic.to_json());
+  } else {
+    eprintln!("{}", diagnostic.to_text());
+  }
+}
+
+// --error-limit, and how many errors have been reported against it.
+static ERROR_LIMIT: AtomicUsize = AtomicUsize::new(0);
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+// Start counting errors against `limit` (0 for no limit), for a new link.
+pub fn set_error_limit(limit: usize) {
+  ERROR_LIMIT.store(limit, Ordering::Relaxed);
+  ERRORS.store(0, Ordering::Relaxed);
+}
+
+// Report one of possibly many errors of the same kind (undefined symbols,
+// say), unless --error-limit of them have been reported already. The first
+// one over the limit is replaced by a note saying the rest aren't shown.
+pub fn report_error(diagnostic: Diagnostic) {
+  let limit = ERROR_LIMIT.load(Ordering::Relaxed);
+  let count = ERRORS.fetch_add(1, Ordering::Relaxed);
+  if limit == 0 || count < limit {
+    report(diagnostic);
+  } else if count == limit {
+    report(Diagnostic::new(
+      Severity::Error,
+      "too many errors; the rest aren't shown (use --error-limit=0 to see \
+       all of them)"
+        .to_string(),
+    ));
+  }
+}
+
+pub fn note(msg: &str) {
+  report(Diagnostic::new(Severity::Note, msg.to_string()));
+}
+
+pub fn warning(msg: &str) {
+  report(Diagnostic::new(Severity::Warning, msg.to_string()));
+}
+
+pub fn error(msg: &str) {
+  report(Diagnostic::new(Severity::Error, msg.to_string()));
+}
@@ -0,0 +1,516 @@
+// dSYM bundles (-dsym): what dsymutil does after a link, without needing
+// Apple's tools.
+//
+// The linked image's debug map (its N_OSO and N_FUN/N_STSYM/N_GSYM stabs)
+// names the objects it was built from and where their symbols ended up.
+// Each object's __DWARF sections are relocated so that addresses refer to
+// the image rather than the object, combined, and written to
+// <output>.dSYM/Contents/Resources/DWARF/<output name>: an MH_DSYM file with
+// the image's UUID, segment layout and symbols, and the debug info.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use archive::{self, ArchiveError};
+use args::LinkOptions;
+use diagnostics;
+use dwarf::{self, DebugSections, DwarfError};
+use macho::fat;
+use macho::header::{self, MachHeader64};
+use macho::image_file::ImageFile;
+use macho::load_command::{LoadCommand, Section64, Segment64};
+use macho::object_file::ObjectFile;
+use macho::reloc::{ARM64_RELOC_SUBTRACTOR, ARM64_RELOC_UNSIGNED,
+                   ARM_RELOC_SECTDIFF, ARM_RELOC_VANILLA,
+                   GENERIC_RELOC_SECTDIFF, GENERIC_RELOC_VANILLA,
+                   X86_64_RELOC_SUBTRACTOR, X86_64_RELOC_UNSIGNED};
+use macho::symtab::{nlist_size, Nlist, StringTable, N_FUN, N_GSYM, N_OSO,
+                    N_SO, N_STSYM};
+use macho::{self, get_u32, get_u64, round_up, set_u32, set_u64, Arch,
+            MachOError, MH_DSYM, VM_PROT_READ, VM_PROT_WRITE};
+
+const DWARF_SEGMENT: &str = "__DWARF";
+
+#[derive(Debug)]
+pub enum DsymError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  BadArchive(PathBuf, ArchiveError),
+  Dwarf(PathBuf, DwarfError),
+  // The output has no N_OSO stabs to say where its debug info is.
+  NoDebugMap(PathBuf),
+}
+
+pub type Result<T> = ::std::result::Result<T, DsymError>;
+
+// One object named by the debug map, with the final address of each of its
+// symbols.
+#[derive(Debug, Clone, Default)]
+pub struct DebugMapObject {
+  pub path: String,
+  // The object's modification time when it was linked, or 0.
+  pub mtime: u64,
+  pub symbols: HashMap<String, u64>,
+}
+
+pub fn debug_map(image: &ImageFile) -> Vec<DebugMapObject> {
+  // N_GSYM stabs have no address; the global symbol has it.
+  let globals: HashMap<&str, u64> = image
+    .symbols
+    .iter()
+    .filter(|sym| sym.is_external() && !sym.is_undefined())
+    .map(|sym| (sym.name.as_str(), sym.n_value))
+    .collect();
+  let mut objects: Vec<DebugMapObject> = Vec::new();
+  let mut current: Option<DebugMapObject> = None;
+  for sym in image.symbols.iter().filter(|sym| sym.is_stab()) {
+    match sym.n_type {
+      N_OSO => {
+        objects.extend(current.take());
+        current = Some(DebugMapObject {
+          path: sym.name.clone(),
+          mtime: sym.n_value,
+          symbols: HashMap::new(),
+        });
+      }
+      // An empty N_SO ends the object's stabs.
+      N_SO if sym.name.is_empty() => objects.extend(current.take()),
+      // N_FUN stabs come in pairs; the second, unnamed, has the size.
+      N_FUN | N_STSYM if !sym.name.is_empty() => {
+        if let Some(ref mut object) = current {
+          object.symbols.insert(sym.name.clone(), sym.n_value);
+        }
+      }
+      N_GSYM => {
+        let addr = globals.get(sym.name.as_str());
+        if let (Some(object), Some(addr)) = (current.as_mut(), addr) {
+          object.symbols.insert(sym.name.clone(), *addr);
+        }
+      }
+      _ => (),
+    }
+  }
+  objects.extend(current);
+  objects
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+  let mut data: Vec<u8> = Vec::new();
+  File::open(path)
+    .and_then(|mut f| f.read_to_end(&mut data))
+    .map_err(|e| DsymError::IoError(path.to_path_buf(), e))?;
+  Ok(data)
+}
+
+// The contents of the object at a debug map path, which for archive
+// members is written libfoo.a(bar.o).
+fn read_object(path: &str, arch: Arch) -> Result<ObjectFile> {
+  let member = if path.ends_with(')') { path.rfind('(') } else { None };
+  let data = match member {
+    Some(paren) => {
+      let archive_path = PathBuf::from(&path[..paren]);
+      let name = &path[(paren + 1)..(path.len() - 1)];
+      let data = read_file(&archive_path)?;
+      let thin = fat::thin(&data, arch)
+        .map_err(|e| DsymError::Malformed(archive_path.clone(), e))?;
+      let members = archive::members(thin)
+        .map_err(|e| DsymError::BadArchive(archive_path.clone(), e))?;
+      match members.iter().find(|m| m.name == name) {
+        Some(member) => member.data.to_vec(),
+        None => {
+          let err = io::Error::new(io::ErrorKind::NotFound, "no such member");
+          return Err(DsymError::IoError(PathBuf::from(path), err));
+        }
+      }
+    }
+    None => read_file(Path::new(path))?,
+  };
+  let thin = fat::thin(&data, arch)
+    .map_err(|e| DsymError::Malformed(PathBuf::from(path), e))?;
+  ObjectFile::parse(thin)
+    .map_err(|e| DsymError::Malformed(PathBuf::from(path), e))
+}
+
+fn check_mtime(object: &DebugMapObject) {
+  let modified = fs::metadata(&object.path)
+    .and_then(|meta| meta.modified())
+    .ok()
+    .and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+  if let Some(modified) = modified {
+    if object.mtime != 0 && modified.as_secs() != object.mtime {
+      diagnostics::warning(&format!(
+        "{} has changed since it was linked; its debug info may not match",
+        object.path
+      ));
+    }
+  }
+}
+
+// Where each of the object's sections' atoms ended up: (object address,
+// final address) for every symbol the debug map places, by section.
+fn placements(
+  object: &ObjectFile,
+  symbols: &HashMap<String, u64>,
+) -> Vec<Vec<(u64, u64)>> {
+  let mut placed: Vec<Vec<(u64, u64)>> =
+    vec![Vec::new(); object.sections.len()];
+  for sym in object.symbols.iter() {
+    let in_section = !sym.is_stab() && !sym.is_undefined() && sym.n_sect != 0;
+    let addr = symbols.get(&sym.name);
+    if let (true, Some(addr)) = (in_section, addr) {
+      if let Some(list) = placed.get_mut(sym.n_sect as usize - 1) {
+        list.push((sym.n_value, *addr));
+      }
+    }
+  }
+  for list in placed.iter_mut() {
+    list.sort();
+  }
+  placed
+}
+
+// The final address of object address `addr` in section `n_sect`, from the
+// symbol at or before it. Code which was dead-stripped has none, and gets 0.
+fn translate(placed: &[Vec<(u64, u64)>], n_sect: u8, addr: u64) -> u64 {
+  let list = match placed.get((n_sect as usize).wrapping_sub(1)) {
+    Some(list) => list,
+    None => return 0,
+  };
+  match list.iter().rev().find(|&&(start, _)| start <= addr) {
+    Some(&(start, final_addr)) => final_addr + (addr - start),
+    None => 0,
+  }
+}
+
+fn final_symbol_address(
+  sym: &Nlist,
+  placed: &[Vec<(u64, u64)>],
+  symbols: &HashMap<String, u64>,
+) -> u64 {
+  if !sym.is_undefined() && sym.n_sect != 0 {
+    translate(placed, sym.n_sect, sym.n_value)
+  } else {
+    symbols.get(&sym.name).cloned().unwrap_or(0)
+  }
+}
+
+// The object's debug sections, with addresses relocated to the image.
+fn relocated_debug_sections(
+  arch: Arch,
+  object: &ObjectFile,
+  symbols: &HashMap<String, u64>,
+) -> macho::Result<DebugSections> {
+  let placed = placements(object, symbols);
+  let (unsigned, subtractor) = match arch {
+    Arch::X86_64 => (X86_64_RELOC_UNSIGNED, X86_64_RELOC_SUBTRACTOR),
+    Arch::Arm64 | Arch::Arm64e | Arch::Arm64_32 => {
+      (ARM64_RELOC_UNSIGNED, ARM64_RELOC_SUBTRACTOR)
+    }
+    // SECTDIFF comes before its PAIR, just as SUBTRACTOR does before the
+    // relocation it pairs with.
+    Arch::I386 => (GENERIC_RELOC_VANILLA, GENERIC_RELOC_SECTDIFF),
+    Arch::Armv7 | Arch::Armv7s => (ARM_RELOC_VANILLA, ARM_RELOC_SECTDIFF),
+  };
+  let mut debug = DebugSections::default();
+  for sect in object.sections.iter() {
+    let wanted = sect.segname == DWARF_SEGMENT
+      && dwarf::DEBUG_SECTIONS.contains(&sect.sectname.as_str());
+    if !wanted {
+      continue;
+    }
+    let mut contents = sect.contents.clone();
+    // A subtractor pair holds a difference, which doesn't change.
+    let mut after_subtractor = false;
+    for reloc in sect.relocs.iter() {
+      let is_pair = after_subtractor;
+      after_subtractor = reloc.kind == subtractor;
+      if is_pair || after_subtractor || reloc.kind != unsigned {
+        continue;
+      }
+      let offset = reloc.address as usize;
+      let what = "debug info relocation";
+      let value = match reloc.length {
+        2 => get_u32(&contents, offset, what).map(|v| v as u64),
+        3 => get_u64(&contents, offset, what),
+        _ => continue,
+      }?;
+      let relocated = if let Some(addr) = reloc.scattered {
+        // Scattered relocations name the section by an address in it.
+        let n_sect = object
+          .sections
+          .iter()
+          .position(|sect| {
+            addr as u64 >= sect.addr && (addr as u64) < sect.addr + sect.size
+          })
+          .map_or(0, |idx| idx + 1);
+        translate(&placed, n_sect as u8, value)
+      } else if reloc.external {
+        match object.symbols.get(reloc.symbolnum as usize) {
+          Some(sym) => {
+            final_symbol_address(sym, &placed, symbols).wrapping_add(value)
+          }
+          None => continue,
+        }
+      } else {
+        translate(&placed, reloc.symbolnum as u8, value)
+      };
+      if reloc.length == 2 {
+        set_u32(&mut contents, offset, relocated as u32);
+      } else {
+        set_u64(&mut contents, offset, relocated);
+      }
+    }
+    debug.sections.insert(sect.sectname.clone(), contents);
+  }
+  Ok(debug)
+}
+
+// The MH_DSYM file: the image's load commands which describe its layout,
+// with no section contents, then its symbols, then the debug info.
+fn dwarf_file(
+  arch: Arch,
+  image: &ImageFile,
+  debug: &DebugSections,
+) -> Vec<u8> {
+  let page_size = arch.page_size();
+  let symbols: Vec<&Nlist> =
+    image.symbols.iter().filter(|sym| !sym.is_stab()).collect();
+  let mut strtab = StringTable::new();
+  let mut sym_data: Vec<u8> = Vec::new();
+  let is_64_bit = arch.is_64_bit();
+  for sym in symbols.iter() {
+    sym.write_sized(&mut sym_data, &mut strtab, is_64_bit);
+  }
+  let strings = strtab.finish();
+
+  let mut segments: Vec<Segment64> = image
+    .segments
+    .iter()
+    .filter(|seg| seg.segname != "__LINKEDIT" && seg.segname != DWARF_SEGMENT)
+    .map(|seg| Segment64 {
+      fileoff: 0,
+      filesize: 0,
+      sections: seg
+        .sections
+        .iter()
+        .map(|sect| Section64 {
+          offset: 0,
+          reloff: 0,
+          nreloc: 0,
+          ..sect.clone()
+        })
+        .collect(),
+      ..seg.clone()
+    })
+    .collect();
+  let linkedit_vmaddr = match image.segment("__LINKEDIT") {
+    Some(seg) => seg.vmaddr,
+    None => segments
+      .iter()
+      .map(|seg| seg.vmaddr + seg.vmsize)
+      .max()
+      .unwrap_or(0),
+  };
+  let linkedit_size = (sym_data.len() + strings.len()) as u64;
+  let mut linkedit = Segment64 {
+    segname: "__LINKEDIT".to_string(),
+    vmaddr: linkedit_vmaddr,
+    vmsize: round_up(linkedit_size, page_size),
+    filesize: linkedit_size,
+    maxprot: VM_PROT_READ,
+    initprot: VM_PROT_READ,
+    ..Default::default()
+  };
+  let mut dwarf_segment = Segment64 {
+    segname: DWARF_SEGMENT.to_string(),
+    vmaddr: linkedit.vmaddr + linkedit.vmsize,
+    maxprot: VM_PROT_READ | VM_PROT_WRITE,
+    initprot: VM_PROT_READ | VM_PROT_WRITE,
+    ..Default::default()
+  };
+  for name in dwarf::DEBUG_SECTIONS.iter() {
+    let size = debug.get(name).len() as u64;
+    if size > 0 {
+      dwarf_segment.sections.push(Section64 {
+        sectname: name.to_string(),
+        segname: DWARF_SEGMENT.to_string(),
+        addr: dwarf_segment.vmaddr + dwarf_segment.filesize,
+        size: size,
+        ..Default::default()
+      });
+      dwarf_segment.filesize += size;
+    }
+  }
+  dwarf_segment.vmsize = round_up(dwarf_segment.filesize, page_size);
+
+  let mut cmds = vec![
+    LoadCommand::Uuid(image.uuid.unwrap_or([0; 16])),
+    LoadCommand::Symtab {
+      symoff: 0,
+      nsyms: 0,
+      stroff: 0,
+      strsize: 0,
+    },
+  ];
+  let sizeofcmds: u32 = cmds.iter().map(|cmd| cmd.cmdsize()).sum::<u32>()
+    + segments
+      .iter()
+      .chain(Some(&linkedit))
+      .chain(Some(&dwarf_segment))
+      .map(|seg| LoadCommand::segment(arch, seg.clone()).cmdsize())
+      .sum::<u32>();
+
+  let header_size = header::header_size(is_64_bit) + sizeofcmds as u64;
+  linkedit.fileoff = round_up(header_size, page_size);
+  let symoff = linkedit.fileoff;
+  let stroff = symoff + sym_data.len() as u64;
+  cmds[1] = LoadCommand::Symtab {
+    symoff: symoff as u32,
+    nsyms: (sym_data.len() / nlist_size(is_64_bit)) as u32,
+    stroff: stroff as u32,
+    strsize: strings.len() as u32,
+  };
+  dwarf_segment.fileoff =
+    round_up(linkedit.fileoff + linkedit.filesize, page_size);
+  let mut sect_offset = dwarf_segment.fileoff;
+  for sect in dwarf_segment.sections.iter_mut() {
+    sect.offset = sect_offset as u32;
+    sect_offset += sect.size;
+  }
+  segments.push(linkedit);
+  segments.push(dwarf_segment.clone());
+  cmds.extend(segments.into_iter().map(|seg| LoadCommand::segment(arch, seg)));
+
+  let mut buf: Vec<u8> = Vec::new();
+  let mut header = MachHeader64::new(arch, MH_DSYM);
+  header.ncmds = cmds.len() as u32;
+  header.sizeofcmds = sizeofcmds;
+  header.write(&mut buf);
+  for cmd in cmds.iter() {
+    cmd.write(&mut buf);
+  }
+  buf.resize(symoff as usize, 0);
+  buf.extend_from_slice(&sym_data);
+  buf.extend_from_slice(&strings);
+  buf.resize(dwarf_segment.fileoff as usize, 0);
+  for sect in dwarf_segment.sections.iter() {
+    buf.extend_from_slice(debug.get(&sect.sectname));
+  }
+  buf
+}
+
+fn info_plist(name: &str) -> String {
+  format!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+     <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+     \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+     <plist version=\"1.0\">\n\
+     <dict>\n\
+     \t<key>CFBundleDevelopmentRegion</key>\n\
+     \t<string>English</string>\n\
+     \t<key>CFBundleIdentifier</key>\n\
+     \t<string>com.apple.xcode.dsym.{}</string>\n\
+     \t<key>CFBundleInfoDictionaryVersion</key>\n\
+     \t<string>6.0</string>\n\
+     \t<key>CFBundlePackageType</key>\n\
+     \t<string>dSYM</string>\n\
+     \t<key>CFBundleSignature</key>\n\
+     \t<string>????</string>\n\
+     \t<key>CFBundleShortVersionString</key>\n\
+     \t<string>1.0</string>\n\
+     \t<key>CFBundleVersion</key>\n\
+     \t<string>1</string>\n\
+     </dict>\n\
+     </plist>\n",
+    name
+  )
+}
+
+pub fn bundle_path(output: &Path) -> PathBuf {
+  let mut name = output.as_os_str().to_os_string();
+  name.push(".dSYM");
+  PathBuf::from(name)
+}
+
+// Where the object at a debug map path is, given the -oso_prefix taken off
+// it.
+fn object_path(opts: &LinkOptions, path: &str) -> String {
+  match opts.oso_prefix {
+    Some(ref prefix) if Path::new(path).is_relative() => {
+      prefix.join(path).to_string_lossy().into_owned()
+    }
+    _ => path.to_string(),
+  }
+}
+
+// The DWARF file for the `arch` slice of the output, whose contents are
+// `data`.
+fn dwarf_for_arch(
+  opts: &LinkOptions,
+  data: &[u8],
+  arch: Arch,
+) -> Result<Vec<u8>> {
+  let output = &opts.output_path;
+  let malformed = |e| DsymError::Malformed(output.to_path_buf(), e);
+  let thin = fat::thin(data, arch).map_err(malformed)?;
+  let image = ImageFile::parse(thin).map_err(malformed)?;
+  let mut objects = debug_map(&image);
+  if objects.is_empty() {
+    return Err(DsymError::NoDebugMap(output.to_path_buf()));
+  }
+
+  let mut debug = DebugSections::default();
+  for object in objects.iter_mut() {
+    object.path = object_path(opts, &object.path);
+    check_mtime(object);
+    let parsed = read_object(&object.path, arch)?;
+    let sections = relocated_debug_sections(arch, &parsed, &object.symbols)
+      .map_err(|e| DsymError::Malformed(PathBuf::from(&object.path), e))?;
+    debug
+      .append(&sections)
+      .map_err(|e| DsymError::Dwarf(PathBuf::from(&object.path), e))?;
+  }
+  Ok(dwarf_file(arch, &image, &debug))
+}
+
+// Write the dSYM bundle for the output of the link `opts` describes. A
+// universal output's DWARF file is universal too.
+pub fn write_bundle(opts: &LinkOptions) -> Result<PathBuf> {
+  let output = &opts.output_path;
+  let data = read_file(output)?;
+  let dwarf = if opts.is_universal() {
+    let mut slices: Vec<(Arch, Vec<u8>)> = Vec::new();
+    for &arch in opts.archs.iter() {
+      slices.push((arch, dwarf_for_arch(opts, &data, arch)?));
+    }
+    let borrowed: Vec<(Arch, &[u8])> = slices
+      .iter()
+      .map(|&(arch, ref data)| (arch, data.as_slice()))
+      .collect();
+    fat::write(&borrowed)
+      .map_err(|e| DsymError::Malformed(output.clone(), e))?
+  } else {
+    dwarf_for_arch(opts, &data, opts.arch)?
+  };
+
+  let name = output
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  let bundle = bundle_path(output);
+  let contents = bundle.join("Contents");
+  let dwarf_dir = contents.join("Resources").join("DWARF");
+  let io_err = |path: &Path| {
+    let path = path.to_path_buf();
+    move |e| DsymError::IoError(path, e)
+  };
+  fs::create_dir_all(&dwarf_dir).map_err(io_err(&dwarf_dir))?;
+  let plist = contents.join("Info.plist");
+  fs::write(&plist, info_plist(&name)).map_err(io_err(&plist))?;
+  let dwarf_path = dwarf_dir.join(&name);
+  fs::write(&dwarf_path, dwarf).map_err(io_err(&dwarf_path))?;
+  Ok(bundle)
+}
@@ -0,0 +1,458 @@
+// DTrace static probes (USDT). Code fires a probe by calling
+// ___dtrace_probe$<provider>$<probe>$v1$<argument types>, and asks whether
+// one is enabled by calling ___dtrace_isenabled$<provider>$<probe>$v1,
+// neither of which anything defines. As ld64 does, the linker turns those
+// calls into NOPs (or a zeroing of the result), and describes where they
+// were in DOF: one __TEXT,__dof_<provider> section per provider, which dyld
+// hands to the kernel so dtrace can patch the sites back when tracing.
+
+use std::collections::BTreeMap;
+
+use image::{self, Image, OutputSection};
+use macho::object_file::ObjectFile;
+use macho::symtab::{N_SECT, N_TYPE};
+use macho::{put_u16, put_u32, put_u64, put_u8, round_up, Arch,
+            S_ATTR_PURE_INSTRUCTIONS, S_ATTR_SOME_INSTRUCTIONS,
+            S_DTRACE_DOF};
+
+const PROBE_PREFIX: &str = "___dtrace_probe$";
+const IS_ENABLED_PREFIX: &str = "___dtrace_isenabled$";
+const STABILITY_PREFIX: &str = "___dtrace_stability$";
+
+// DOF, as in <sys/dtrace.h>.
+const DOF_VERSION_1: u8 = 1;
+const DOF_VERSION_2: u8 = 2;
+const DOF_MODEL_ILP32: u8 = 1;
+const DOF_MODEL_LP64: u8 = 2;
+const DOF_ENCODE_LSB: u8 = 1;
+const DIF_VERSION_2: u8 = 2;
+const DIF_DIR_NREGS: u8 = 8;
+const DIF_DTR_NREGS: u8 = 8;
+const DOF_HDR_SIZE: u64 = 64;
+const DOF_SEC_SIZE: u64 = 32;
+const DOF_SECF_LOAD: u32 = 1;
+const DOF_SECT_STRTAB: u32 = 8;
+const DOF_SECT_RELTAB: u32 = 10;
+const DOF_SECT_URELHDR: u32 = 12;
+const DOF_SECT_PROVIDER: u32 = 15;
+const DOF_SECT_PROBES: u32 = 16;
+const DOF_SECT_PRARGS: u32 = 17;
+const DOF_SECT_PROFFS: u32 = 18;
+const DOF_SECT_PRENOFFS: u32 = 26;
+const DOF_PROBE_SIZE: u32 = 48;
+const DOF_RELODESC_SIZE: u32 = 24;
+const DOF_RELO_SETX: u32 = 1;
+
+// Private names, private data, unknown class: what a provider without a
+// ___dtrace_stability reference gets.
+const DEFAULT_ATTR: u32 = (2 << 24) | (2 << 16);
+
+#[derive(Debug)]
+pub enum DtraceError {
+  // No probe site encoding for the architecture.
+  UnsupportedArch(Arch),
+  // The call at this address isn't a call the linker knows how to patch.
+  BadSite(u64),
+}
+
+pub type Result<T> = ::std::result::Result<T, DtraceError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SiteKind {
+  Probe,
+  IsEnabled,
+}
+
+// What a ___dtrace_probe or ___dtrace_isenabled name says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeSymbol {
+  pub provider: String,
+  pub probe: String,
+  pub kind: SiteKind,
+  // The C types of the probe's arguments.
+  pub arg_types: Vec<String>,
+}
+
+// A call to a probe in the code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeSite {
+  pub symbol: ProbeSymbol,
+  // The function making the call, and where it starts.
+  pub function: String,
+  pub function_address: u64,
+  // Where the call instruction is.
+  pub address: u64,
+}
+
+// Whether `name` is one of the symbols `dtrace -h` headers refer to, which
+// the linker takes care of instead of anything defining them.
+pub fn is_dtrace_symbol(name: &str) -> bool {
+  name.starts_with("___dtrace_")
+}
+
+// `dtrace -h` spells '-' in probe names as "__", and hex-encodes types.
+fn decode_type(hex: &str) -> String {
+  let bytes: Option<Vec<u8>> = if hex.len() % 2 == 0 {
+    (0..hex.len() / 2)
+      .map(|i| hex.get(i * 2..i * 2 + 2))
+      .map(|byte| byte.and_then(|b| u8::from_str_radix(b, 16).ok()))
+      .collect()
+  } else {
+    None
+  };
+  bytes
+    .and_then(|b| String::from_utf8(b).ok())
+    .unwrap_or_else(|| hex.to_string())
+}
+
+pub fn parse_symbol(name: &str) -> Option<ProbeSymbol> {
+  let (kind, rest) = if name.starts_with(PROBE_PREFIX) {
+    (SiteKind::Probe, &name[PROBE_PREFIX.len()..])
+  } else if name.starts_with(IS_ENABLED_PREFIX) {
+    (SiteKind::IsEnabled, &name[IS_ENABLED_PREFIX.len()..])
+  } else {
+    return None;
+  };
+  let fields: Vec<&str> = rest.split('$').collect();
+  if fields.len() < 3 || fields[0].is_empty() || fields[1].is_empty() {
+    return None;
+  }
+  Some(ProbeSymbol {
+    provider: fields[0].to_string(),
+    probe: fields[1].replace("__", "-"),
+    kind: kind,
+    arg_types: fields[3..].iter().map(|hex| decode_type(hex)).collect(),
+  })
+}
+
+// The provider's five attributes (provider, module, function, name and
+// arguments) from a ___dtrace_stability$<provider>$v1$<n>_<n>_... name, as
+// (name stability, data stability, class) triples.
+pub fn parse_stability(name: &str) -> Option<(String, [u32; 5])> {
+  if !name.starts_with(STABILITY_PREFIX) {
+    return None;
+  }
+  let fields: Vec<&str> = name[STABILITY_PREFIX.len()..].split('$').collect();
+  if fields.len() != 3 {
+    return None;
+  }
+  let levels: Option<Vec<u32>> =
+    fields[2].split('_').map(|n| n.parse().ok()).collect();
+  let levels = levels.filter(|levels| levels.len() == 15)?;
+  let mut attrs = [0; 5];
+  for (i, attr) in attrs.iter_mut().enumerate() {
+    let triple = &levels[i * 3..i * 3 + 3];
+    *attr = (triple[0] << 24) | (triple[1] << 16) | (triple[2] << 8);
+  }
+  Some((fields[0].to_string(), attrs))
+}
+
+// The defined symbol in section `n_sect` at or before `addr`.
+fn function_at(object: &ObjectFile, n_sect: u8, addr: u64) -> (String, u64) {
+  object
+    .symbols
+    .iter()
+    .filter(|sym| {
+      !sym.is_stab()
+        && sym.n_type & N_TYPE == N_SECT
+        && sym.n_sect == n_sect
+        && sym.n_value <= addr
+    })
+    .max_by_key(|sym| sym.n_value)
+    .map(|sym| (sym.name.clone(), sym.n_value))
+    .unwrap_or_else(|| {
+      (String::new(), object.sections[n_sect as usize - 1].addr)
+    })
+}
+
+// Every call to a probe in `object`'s code, at the object's addresses.
+pub fn find_sites(arch: Arch, object: &ObjectFile) -> Vec<ProbeSite> {
+  let code = S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS;
+  let mut sites: Vec<ProbeSite> = Vec::new();
+  for (i, sect) in object.sections.iter().enumerate() {
+    if sect.flags & code == 0 {
+      continue;
+    }
+    for reloc in sect.relocs.iter() {
+      if !reloc.external || reloc.scattered.is_some() {
+        continue;
+      }
+      let symbol = match object
+        .symbols
+        .get(reloc.symbolnum as usize)
+        .and_then(|sym| parse_symbol(&sym.name))
+      {
+        Some(symbol) => symbol,
+        None => continue,
+      };
+      // The relocation is on the call's displacement, which follows the
+      // opcode on Intel.
+      let offset = if arch.is_arm64() { 0 } else { 1 };
+      let address = (sect.addr + reloc.address as u64).saturating_sub(offset);
+      let (function, function_address) =
+        function_at(object, i as u8 + 1, address);
+      sites.push(ProbeSite {
+        symbol: symbol,
+        function: function,
+        function_address: function_address,
+        address: address,
+      });
+    }
+  }
+  sites
+}
+
+// The providers' attributes, from the ___dtrace_stability references.
+pub fn find_stabilities(object: &ObjectFile) -> Vec<(String, [u32; 5])> {
+  object
+    .symbols
+    .iter()
+    .filter_map(|sym| parse_stability(&sym.name))
+    .collect()
+}
+
+// What replaces a call to a probe: a NOP, or for an is-enabled check,
+// zeroing the result.
+fn site_code(arch: Arch, kind: SiteKind) -> Result<Vec<u8>> {
+  let code: &[u8] = match (arch, kind) {
+    (Arch::X86_64, SiteKind::Probe) | (Arch::I386, SiteKind::Probe) => {
+      &[0x90, 0x0f, 0x1f, 0x40, 0x00]
+    }
+    (Arch::X86_64, _) | (Arch::I386, _) => &[0x33, 0xc0, 0x90, 0x90, 0x90],
+    (arch, SiteKind::Probe) if arch.is_arm64() => &[0x1f, 0x20, 0x03, 0xd5],
+    (arch, SiteKind::IsEnabled) if arch.is_arm64() => {
+      &[0x00, 0x00, 0x80, 0xd2]
+    }
+    (arch, _) => return Err(DtraceError::UnsupportedArch(arch)),
+  };
+  Ok(code.to_vec())
+}
+
+// Replace the call at `offset` in `contents` for a site of this kind.
+pub fn patch_site(
+  arch: Arch,
+  contents: &mut [u8],
+  offset: usize,
+  kind: SiteKind,
+) -> Result<()> {
+  let code = site_code(arch, kind)?;
+  let is_call = if arch.is_arm64() {
+    // BL.
+    contents.get(offset + 3).map(|b| b & 0xfc) == Some(0x94)
+  } else {
+    contents.get(offset) == Some(&0xe8)
+  };
+  if !is_call || offset + code.len() > contents.len() {
+    return Err(DtraceError::BadSite(offset as u64));
+  }
+  contents[offset..offset + code.len()].copy_from_slice(&code);
+  Ok(())
+}
+
+fn put_str(strtab: &mut Vec<u8>, s: &str) -> u32 {
+  let offset = strtab.len() as u32;
+  strtab.extend_from_slice(s.as_bytes());
+  strtab.push(0);
+  offset
+}
+
+// The argument types of a probe, as consecutive C strings.
+fn put_types(strtab: &mut Vec<u8>, types: &[String]) -> u32 {
+  let offset = strtab.len() as u32;
+  for ty in types.iter() {
+    put_str(strtab, ty);
+  }
+  if types.is_empty() {
+    0
+  } else {
+    offset
+  }
+}
+
+// The DOF describing one provider's sites, whose addresses are relative to
+// `image_base`: the kernel adds the address the image was loaded at.
+pub fn build_dof(
+  arch: Arch,
+  image_base: u64,
+  provider: &str,
+  sites: &[ProbeSite],
+  attrs: [u32; 5],
+) -> Vec<u8> {
+  // Each probe is a name within a function, with every site in the function
+  // calling it.
+  let mut probes: BTreeMap<(&str, &str), Vec<&ProbeSite>> = BTreeMap::new();
+  for site in sites.iter().filter(|s| s.symbol.provider == provider) {
+    probes
+      .entry((site.function.as_str(), site.symbol.probe.as_str()))
+      .or_insert_with(Vec::new)
+      .push(site);
+  }
+
+  let mut strtab: Vec<u8> = vec![0];
+  let provider_name = put_str(&mut strtab, provider);
+  let mut probe_table: Vec<u8> = Vec::new();
+  let mut prargs: Vec<u8> = Vec::new();
+  let mut proffs: Vec<u8> = Vec::new();
+  let mut prenoffs: Vec<u8> = Vec::new();
+  let mut reltab: Vec<u8> = Vec::new();
+  for (i, (&(function, name), sites)) in probes.iter().enumerate() {
+    let types = sites
+      .iter()
+      .find(|site| site.symbol.kind == SiteKind::Probe)
+      .map(|site| site.symbol.arg_types.clone())
+      .unwrap_or_default();
+    let func = put_str(&mut strtab, function);
+    let probe_name = put_str(&mut strtab, name);
+    // No translators, so the translated arguments are the native ones.
+    let argv = put_types(&mut strtab, &types);
+    let argidx = prargs.len() as u32;
+    prargs.extend((0..types.len()).map(|arg| arg as u8));
+    let offidx = (proffs.len() / 4) as u32;
+    let enoffidx = (prenoffs.len() / 4) as u32;
+    let (mut noffs, mut nenoffs) = (0u16, 0u16);
+    for site in sites.iter() {
+      let offset = (site.address - site.function_address) as u32;
+      match site.symbol.kind {
+        SiteKind::Probe => {
+          put_u32(&mut proffs, offset);
+          noffs += 1;
+        }
+        SiteKind::IsEnabled => {
+          put_u32(&mut prenoffs, offset);
+          nenoffs += 1;
+        }
+      }
+    }
+    let function_address = sites[0].function_address;
+    put_u64(&mut probe_table, function_address.wrapping_sub(image_base));
+    put_u32(&mut probe_table, func);
+    put_u32(&mut probe_table, probe_name);
+    put_u32(&mut probe_table, argv);
+    put_u32(&mut probe_table, argv);
+    put_u32(&mut probe_table, argidx);
+    put_u32(&mut probe_table, offidx);
+    put_u8(&mut probe_table, types.len() as u8);
+    put_u8(&mut probe_table, types.len() as u8);
+    put_u16(&mut probe_table, noffs);
+    put_u32(&mut probe_table, enoffidx);
+    put_u16(&mut probe_table, nenoffs);
+    put_u16(&mut probe_table, 0);
+    put_u32(&mut probe_table, 0);
+    // Relocate the probe's address by the image's.
+    put_u32(&mut reltab, func);
+    put_u32(&mut reltab, DOF_RELO_SETX);
+    put_u64(&mut reltab, i as u64 * DOF_PROBE_SIZE as u64);
+    put_u64(&mut reltab, 0);
+  }
+
+  // The sections, in the order their indices are referred to below.
+  let mut provider_sect: Vec<u8> = Vec::new();
+  for &field in [0, 1, 2, 3, provider_name].iter() {
+    put_u32(&mut provider_sect, field);
+  }
+  for &attr in attrs.iter() {
+    put_u32(&mut provider_sect, attr);
+  }
+  put_u32(&mut provider_sect, 4);
+  let mut relohdr: Vec<u8> = Vec::new();
+  for &field in [0, 6, 1].iter() {
+    put_u32(&mut relohdr, field);
+  }
+  let sections: [(u32, u32, u32, &[u8]); 8] = [
+    (DOF_SECT_STRTAB, 1, 0, &strtab),
+    (DOF_SECT_PROBES, 8, DOF_PROBE_SIZE, &probe_table),
+    (DOF_SECT_PRARGS, 1, 1, &prargs),
+    (DOF_SECT_PROFFS, 4, 4, &proffs),
+    (DOF_SECT_PRENOFFS, 4, 4, &prenoffs),
+    (DOF_SECT_PROVIDER, 4, 0, &provider_sect),
+    (DOF_SECT_RELTAB, 8, DOF_RELODESC_SIZE, &reltab),
+    (DOF_SECT_URELHDR, 4, 0, &relohdr),
+  ];
+
+  let secoff = DOF_HDR_SIZE;
+  let body_start = secoff + DOF_SEC_SIZE * sections.len() as u64;
+  let mut offset = body_start;
+  let mut headers: Vec<u8> = Vec::new();
+  let mut body: Vec<u8> = Vec::new();
+  for &(kind, align, entsize, data) in sections.iter() {
+    offset = round_up(offset, align as u64);
+    put_u32(&mut headers, kind);
+    put_u32(&mut headers, align);
+    put_u32(&mut headers, DOF_SECF_LOAD);
+    put_u32(&mut headers, entsize);
+    put_u64(&mut headers, offset);
+    put_u64(&mut headers, data.len() as u64);
+    body.resize((offset - body_start) as usize, 0);
+    body.extend_from_slice(data);
+    offset += data.len() as u64;
+  }
+
+  let version = if prenoffs.is_empty() {
+    DOF_VERSION_1
+  } else {
+    DOF_VERSION_2
+  };
+  let model = if arch.is_64_bit() {
+    DOF_MODEL_LP64
+  } else {
+    DOF_MODEL_ILP32
+  };
+  let mut dof: Vec<u8> = vec![0x7f, b'D', b'O', b'F'];
+  dof.extend_from_slice(&[model, DOF_ENCODE_LSB, version, DIF_VERSION_2]);
+  dof.extend_from_slice(&[DIF_DIR_NREGS, DIF_DTR_NREGS, 0, 0, 0, 0, 0, 0]);
+  put_u32(&mut dof, 0);
+  put_u32(&mut dof, DOF_HDR_SIZE as u32);
+  put_u32(&mut dof, DOF_SEC_SIZE as u32);
+  put_u32(&mut dof, sections.len() as u32);
+  put_u64(&mut dof, secoff);
+  put_u64(&mut dof, offset);
+  put_u64(&mut dof, offset);
+  put_u64(&mut dof, 0);
+  dof.extend_from_slice(&headers);
+  dof.extend_from_slice(&body);
+  dof
+}
+
+// The __dof_<provider> section name and DOF of each provider with sites,
+// at the sites' addresses in an image at `image_base`.
+pub fn dof_sections(
+  arch: Arch,
+  image_base: u64,
+  sites: &[ProbeSite],
+  stabilities: &[(String, [u32; 5])],
+) -> Vec<(String, Vec<u8>)> {
+  let mut providers: Vec<&str> =
+    sites.iter().map(|site| site.symbol.provider.as_str()).collect();
+  providers.sort();
+  providers.dedup();
+  providers
+    .into_iter()
+    .map(|provider| {
+      let attrs = stabilities
+        .iter()
+        .find(|&&(ref name, _)| name == provider)
+        .map(|&(_, attrs)| attrs)
+        .unwrap_or([DEFAULT_ATTR; 5]);
+      let dof = build_dof(arch, image_base, provider, sites, attrs);
+      let mut sectname = format!("__dof_{}", provider);
+      sectname.truncate(16);
+      (sectname, dof)
+    })
+    .collect()
+}
+
+// Add a __TEXT,__dof_<provider> section for each provider with sites, with
+// the sites at their addresses in `image`.
+pub fn add_to_image(
+  image: &mut Image,
+  sites: &[ProbeSite],
+  stabilities: &[(String, [u32; 5])],
+) -> image::Result<()> {
+  let sections =
+    dof_sections(image.arch, image.image_base, sites, stabilities);
+  for (sectname, dof) in sections {
+    let sect = OutputSection::new(&sectname, dof, 3, S_DTRACE_DOF);
+    image.add_section("__TEXT", sect)?;
+  }
+  Ok(())
+}
@@ -0,0 +1,546 @@
+// Just enough DWARF (versions 2 to 4) to combine the debug info of several
+// objects: each object's __DWARF sections are appended to the combined
+// ones, and references from one debug section into another are offsets,
+// which have to be moved along with what they refer to.
+//
+// Objects have one compile unit each and Mach-O objects don't relocate
+// these offsets, so they all start at zero in the input.
+//
+// Line tables can be read too, to point diagnostics at source lines.
+
+use std::collections::{BTreeMap, HashMap};
+
+use macho::{get_cstr, get_le, get_sleb128, get_u16, get_u32, get_uleb128,
+            set_u32, MachOError};
+
+pub const DEBUG_ABBREV: &str = "__debug_abbrev";
+pub const DEBUG_INFO: &str = "__debug_info";
+pub const DEBUG_STR: &str = "__debug_str";
+pub const DEBUG_LINE: &str = "__debug_line";
+pub const DEBUG_RANGES: &str = "__debug_ranges";
+pub const DEBUG_LOC: &str = "__debug_loc";
+pub const DEBUG_ARANGES: &str = "__debug_aranges";
+
+// The sections we combine. The Apple accelerator tables (__apple_names and
+// friends) index the whole of __debug_info, and are left out rather than
+// rebuilt; debuggers fall back to reading the DIEs.
+pub const DEBUG_SECTIONS: &[&str] = &[
+  DEBUG_ABBREV,
+  DEBUG_INFO,
+  DEBUG_STR,
+  DEBUG_LINE,
+  DEBUG_RANGES,
+  DEBUG_LOC,
+  DEBUG_ARANGES,
+];
+
+// Attributes whose values are offsets into another section when their form
+// is DW_FORM_sec_offset (or, before DWARF 4, DW_FORM_data4).
+const DW_AT_LOCATION: u64 = 0x02;
+const DW_AT_STMT_LIST: u64 = 0x10;
+const DW_AT_STRING_LENGTH: u64 = 0x19;
+const DW_AT_RETURN_ADDR: u64 = 0x2a;
+const DW_AT_SEGMENT: u64 = 0x2e;
+const DW_AT_DATA_MEMBER_LOCATION: u64 = 0x38;
+const DW_AT_FRAME_BASE: u64 = 0x40;
+const DW_AT_STATIC_LINK: u64 = 0x48;
+const DW_AT_USE_LOCATION: u64 = 0x4a;
+const DW_AT_VTABLE_ELEM_LOCATION: u64 = 0x4d;
+const DW_AT_RANGES: u64 = 0x55;
+
+const DW_FORM_ADDR: u64 = 0x01;
+const DW_FORM_BLOCK2: u64 = 0x03;
+const DW_FORM_BLOCK4: u64 = 0x04;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_BLOCK1: u64 = 0x0a;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_FLAG: u64 = 0x0c;
+const DW_FORM_SDATA: u64 = 0x0d;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_REF_ADDR: u64 = 0x10;
+const DW_FORM_REF1: u64 = 0x11;
+const DW_FORM_REF2: u64 = 0x12;
+const DW_FORM_REF4: u64 = 0x13;
+const DW_FORM_REF8: u64 = 0x14;
+const DW_FORM_REF_UDATA: u64 = 0x15;
+const DW_FORM_INDIRECT: u64 = 0x16;
+const DW_FORM_SEC_OFFSET: u64 = 0x17;
+const DW_FORM_EXPRLOC: u64 = 0x18;
+const DW_FORM_FLAG_PRESENT: u64 = 0x19;
+const DW_FORM_REF_SIG8: u64 = 0x20;
+
+#[derive(Debug)]
+pub enum DwarfError {
+  MachO(MachOError),
+  UnsupportedVersion(u16),
+  UnsupportedForm(u64),
+  // 64-bit DWARF, which nothing targeting Mach-O emits.
+  Dwarf64,
+  // A DIE using an abbreviation code its table doesn't have.
+  UnknownAbbrev(u64),
+}
+
+impl From<MachOError> for DwarfError {
+  fn from(err: MachOError) -> Self {
+    DwarfError::MachO(err)
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, DwarfError>;
+
+// Attribute specifications, (name, form), by abbreviation code.
+type AbbrevTable = HashMap<u64, Vec<(u64, u64)>>;
+
+fn parse_abbrevs(data: &[u8], offset: usize) -> Result<AbbrevTable> {
+  let what = "abbreviation";
+  let mut table: AbbrevTable = HashMap::new();
+  let mut cur = offset;
+  loop {
+    let code = get_uleb128(data, &mut cur, what)?;
+    if code == 0 {
+      return Ok(table);
+    }
+    // The tag, and whether it has children.
+    get_uleb128(data, &mut cur, what)?;
+    cur += 1;
+    let mut specs: Vec<(u64, u64)> = Vec::new();
+    loop {
+      let name = get_uleb128(data, &mut cur, what)?;
+      let form = get_uleb128(data, &mut cur, what)?;
+      if name == 0 && form == 0 {
+        break;
+      }
+      specs.push((name, form));
+    }
+    table.insert(code, specs);
+  }
+}
+
+// How far each section's contents moved when appended.
+#[derive(Debug, Clone, Copy, Default)]
+struct Bases {
+  info: u32,
+  abbrev: u32,
+  str: u32,
+  line: u32,
+  ranges: u32,
+  loc: u32,
+}
+
+impl Bases {
+  // The base for an offset-valued attribute, if `name` is one.
+  fn for_attribute(&self, name: u64) -> Option<u32> {
+    match name {
+      DW_AT_STMT_LIST => Some(self.line),
+      DW_AT_RANGES => Some(self.ranges),
+      DW_AT_LOCATION
+      | DW_AT_STRING_LENGTH
+      | DW_AT_RETURN_ADDR
+      | DW_AT_SEGMENT
+      | DW_AT_DATA_MEMBER_LOCATION
+      | DW_AT_FRAME_BASE
+      | DW_AT_STATIC_LINK
+      | DW_AT_USE_LOCATION
+      | DW_AT_VTABLE_ELEM_LOCATION => Some(self.loc),
+      _ => None,
+    }
+  }
+}
+
+fn add_u32(data: &mut [u8], offset: usize, base: u32) -> Result<()> {
+  let val = get_u32(data, offset, "DWARF offset")?;
+  set_u32(data, offset, val.wrapping_add(base));
+  Ok(())
+}
+
+fn skip_block(data: &[u8], cur: &mut usize, len: u64) -> Result<()> {
+  *cur += len as usize;
+  if *cur > data.len() {
+    return Err(MachOError::Truncated("DWARF block").into());
+  }
+  Ok(())
+}
+
+struct Unit {
+  version: u16,
+  addr_size: usize,
+}
+
+// Step over one attribute value at `*cur`, moving any offset it holds into
+// another section by that section's base.
+fn patch_attribute(
+  data: &mut [u8],
+  cur: &mut usize,
+  unit: &Unit,
+  name: u64,
+  form: u64,
+  bases: &Bases,
+) -> Result<()> {
+  let what = "attribute value";
+  match form {
+    DW_FORM_ADDR => *cur += unit.addr_size,
+    DW_FORM_BLOCK2 => {
+      let len = get_u16(data, *cur, what)? as u64;
+      *cur += 2;
+      skip_block(data, cur, len)?;
+    }
+    DW_FORM_BLOCK4 => {
+      let len = get_u32(data, *cur, what)? as u64;
+      *cur += 4;
+      skip_block(data, cur, len)?;
+    }
+    DW_FORM_BLOCK | DW_FORM_EXPRLOC => {
+      let len = get_uleb128(data, cur, what)?;
+      skip_block(data, cur, len)?;
+    }
+    DW_FORM_BLOCK1 => {
+      let len = *data.get(*cur).ok_or(MachOError::Truncated(what))? as u64;
+      *cur += 1;
+      skip_block(data, cur, len)?;
+    }
+    DW_FORM_DATA1 | DW_FORM_FLAG | DW_FORM_REF1 => *cur += 1,
+    DW_FORM_DATA2 | DW_FORM_REF2 => *cur += 2,
+    DW_FORM_DATA4 => {
+      if unit.version < 4 {
+        if let Some(base) = bases.for_attribute(name) {
+          add_u32(data, *cur, base)?;
+        }
+      }
+      *cur += 4;
+    }
+    DW_FORM_REF4 => *cur += 4,
+    DW_FORM_DATA8 | DW_FORM_REF8 | DW_FORM_REF_SIG8 => *cur += 8,
+    DW_FORM_STRING => {
+      get_cstr(data, cur, what)?;
+    }
+    DW_FORM_SDATA | DW_FORM_UDATA | DW_FORM_REF_UDATA => {
+      get_uleb128(data, cur, what)?;
+    }
+    DW_FORM_STRP => {
+      add_u32(data, *cur, bases.str)?;
+      *cur += 4;
+    }
+    DW_FORM_REF_ADDR => {
+      // An offset into __debug_info: address-sized in DWARF 2.
+      let size = if unit.version == 2 { unit.addr_size } else { 4 };
+      if size != 4 {
+        return Err(DwarfError::UnsupportedForm(form));
+      }
+      add_u32(data, *cur, bases.info)?;
+      *cur += 4;
+    }
+    DW_FORM_SEC_OFFSET => {
+      if let Some(base) = bases.for_attribute(name) {
+        add_u32(data, *cur, base)?;
+      }
+      *cur += 4;
+    }
+    DW_FORM_INDIRECT => {
+      let form = get_uleb128(data, cur, what)?;
+      patch_attribute(data, cur, unit, name, form, bases)?;
+    }
+    DW_FORM_FLAG_PRESENT => (),
+    _ => return Err(DwarfError::UnsupportedForm(form)),
+  }
+  Ok(())
+}
+
+// Move the offsets in every unit in `info` (using abbreviations from
+// `abbrev`, as it was before being appended).
+fn patch_info(info: &mut [u8], abbrev: &[u8], bases: &Bases) -> Result<()> {
+  let what = "compile unit header";
+  let mut offset = 0;
+  while offset < info.len() {
+    let length = get_u32(info, offset, what)? as usize;
+    if length == 0xffff_ffff {
+      return Err(DwarfError::Dwarf64);
+    }
+    let end = offset + 4 + length;
+    let version = get_u16(info, offset + 4, what)?;
+    if version < 2 || version > 4 {
+      return Err(DwarfError::UnsupportedVersion(version));
+    }
+    let abbrev_offset = get_u32(info, offset + 6, what)? as usize;
+    let abbrevs = parse_abbrevs(abbrev, abbrev_offset)?;
+    add_u32(info, offset + 6, bases.abbrev)?;
+    let unit = Unit {
+      version: version,
+      addr_size: *info.get(offset + 10).ok_or(MachOError::Truncated(what))?
+        as usize,
+    };
+    let mut cur = offset + 11;
+    while cur < end.min(info.len()) {
+      let code = get_uleb128(info, &mut cur, "DIE")?;
+      if code == 0 {
+        continue;
+      }
+      let specs = abbrevs
+        .get(&code)
+        .ok_or(DwarfError::UnknownAbbrev(code))?;
+      for &(name, form) in specs.iter() {
+        patch_attribute(info, &mut cur, &unit, name, form, bases)?;
+      }
+    }
+    offset = end;
+  }
+  Ok(())
+}
+
+// Each address range set names the unit it describes.
+fn patch_aranges(aranges: &mut [u8], info_base: u32) -> Result<()> {
+  let mut offset = 0;
+  while offset < aranges.len() {
+    let length = get_u32(aranges, offset, "address range set")? as usize;
+    if length == 0xffff_ffff {
+      return Err(DwarfError::Dwarf64);
+    }
+    add_u32(aranges, offset + 6, info_base)?;
+    offset += 4 + length;
+  }
+  Ok(())
+}
+
+// The debug sections of one or more objects, by section name.
+#[derive(Debug, Clone, Default)]
+pub struct DebugSections {
+  pub sections: BTreeMap<String, Vec<u8>>,
+}
+
+impl DebugSections {
+  pub fn get(&self, name: &str) -> &[u8] {
+    self.sections.get(name).map_or(&[], |data| &data[..])
+  }
+
+  fn len(&self, name: &str) -> u32 {
+    self.get(name).len() as u32
+  }
+
+  // Append another object's debug sections.
+  pub fn append(&mut self, other: &DebugSections) -> Result<()> {
+    let bases = Bases {
+      info: self.len(DEBUG_INFO),
+      abbrev: self.len(DEBUG_ABBREV),
+      str: self.len(DEBUG_STR),
+      line: self.len(DEBUG_LINE),
+      ranges: self.len(DEBUG_RANGES),
+      loc: self.len(DEBUG_LOC),
+    };
+    let mut info = other.get(DEBUG_INFO).to_vec();
+    patch_info(&mut info, other.get(DEBUG_ABBREV), &bases)?;
+    let mut aranges = other.get(DEBUG_ARANGES).to_vec();
+    patch_aranges(&mut aranges, bases.info)?;
+    for name in DEBUG_SECTIONS.iter() {
+      let data = match *name {
+        DEBUG_INFO => &info[..],
+        DEBUG_ARANGES => &aranges[..],
+        _ => other.get(name),
+      };
+      if !data.is_empty() {
+        self
+          .sections
+          .entry(name.to_string())
+          .or_insert_with(Vec::new)
+          .extend_from_slice(data);
+      }
+    }
+    Ok(())
+  }
+}
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+// One row of a line table: the instructions from `address` up to the next
+// row's come from this line of this file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineRow {
+  pub address: u64,
+  pub file: String,
+  pub line: u64,
+  // The last row of a sequence, which only marks where it ends.
+  pub end_sequence: bool,
+}
+
+// Every row of every line program in `debug_line`, in order.
+pub fn line_table(debug_line: &[u8]) -> Result<Vec<LineRow>> {
+  let mut rows: Vec<LineRow> = Vec::new();
+  let mut offset: usize = 0;
+  while offset < debug_line.len() {
+    let length = get_u32(debug_line, offset, "line table length")?;
+    if length == 0xffff_ffff {
+      return Err(DwarfError::Dwarf64);
+    }
+    let end = offset + 4 + length as usize;
+    let unit = debug_line
+      .get(..end)
+      .ok_or(MachOError::Truncated("line table"))?;
+    line_program(unit, offset + 4, &mut rows)?;
+    offset = end;
+  }
+  Ok(rows)
+}
+
+// Run the line program for one unit, which ends at the end of `unit` and
+// whose header starts at `offset`.
+fn line_program(
+  unit: &[u8],
+  offset: usize,
+  rows: &mut Vec<LineRow>,
+) -> Result<()> {
+  let what = "line table header";
+  let version = get_u16(unit, offset, what)?;
+  if !(2..=4).contains(&version) {
+    return Err(DwarfError::UnsupportedVersion(version));
+  }
+  let header_length = get_u32(unit, offset + 2, what)? as usize;
+  let program = offset + 6 + header_length;
+  let mut cur = offset + 6;
+  let byte = |cur: &mut usize| -> Result<u8> {
+    let val = *unit.get(*cur).ok_or(MachOError::Truncated(what))?;
+    *cur += 1;
+    Ok(val)
+  };
+  let min_inst_length = byte(&mut cur)? as u64;
+  if version >= 4 {
+    // maximum_operations_per_instruction, which is 1 except for VLIW.
+    byte(&mut cur)?;
+  }
+  byte(&mut cur)?;
+  let line_base = byte(&mut cur)? as i8 as i64;
+  let line_range = byte(&mut cur)?;
+  let opcode_base = byte(&mut cur)?;
+  if line_range == 0 || opcode_base == 0 {
+    return Err(MachOError::Truncated(what).into());
+  }
+  let opcode_lengths = unit
+    .get(cur..(cur + opcode_base as usize - 1))
+    .ok_or(MachOError::Truncated(what))?
+    .to_vec();
+  cur += opcode_base as usize - 1;
+
+  let mut directories: Vec<String> = Vec::new();
+  loop {
+    let dir = get_cstr(unit, &mut cur, what)?;
+    if dir.is_empty() {
+      break;
+    }
+    directories.push(dir);
+  }
+  let mut files: Vec<String> = Vec::new();
+  let file_entry = |cur: &mut usize, name: String| -> Result<String> {
+    let dir = get_uleb128(unit, cur, what)? as usize;
+    get_uleb128(unit, cur, what)?;
+    get_uleb128(unit, cur, what)?;
+    Ok(match directories.get(dir.wrapping_sub(1)) {
+      Some(dir) if !name.starts_with('/') => {
+        format!("{}/{}", dir, name)
+      }
+      _ => name,
+    })
+  };
+  loop {
+    let name = get_cstr(unit, &mut cur, what)?;
+    if name.is_empty() {
+      break;
+    }
+    files.push(file_entry(&mut cur, name)?);
+  }
+
+  let what = "line program";
+  cur = program;
+  let mut address: u64 = 0;
+  let mut file: u64 = 1;
+  let mut line: i64 = 1;
+  while cur < unit.len() {
+    let opcode = byte(&mut cur)?;
+    let mut emit = false;
+    let mut end_sequence = false;
+    if opcode >= opcode_base {
+      let adjusted = opcode - opcode_base;
+      address += (adjusted / line_range) as u64 * min_inst_length;
+      line += line_base + (adjusted % line_range) as i64;
+      emit = true;
+    } else if opcode == 0 {
+      let len = get_uleb128(unit, &mut cur, what)? as usize;
+      let next = cur + len;
+      match byte(&mut cur)? {
+        DW_LNE_END_SEQUENCE => {
+          emit = true;
+          end_sequence = true;
+        }
+        DW_LNE_SET_ADDRESS => address = get_le(unit, cur, len - 1, what)?,
+        DW_LNE_DEFINE_FILE => {
+          let name = get_cstr(unit, &mut cur, what)?;
+          files.push(file_entry(&mut cur, name)?);
+        }
+        _ => (),
+      }
+      cur = next;
+    } else {
+      match opcode {
+        DW_LNS_COPY => emit = true,
+        DW_LNS_ADVANCE_PC => {
+          address += get_uleb128(unit, &mut cur, what)? * min_inst_length;
+        }
+        DW_LNS_ADVANCE_LINE => line += get_sleb128(unit, &mut cur, what)?,
+        DW_LNS_SET_FILE => file = get_uleb128(unit, &mut cur, what)?,
+        DW_LNS_CONST_ADD_PC => {
+          let adjusted = 255 - opcode_base;
+          address += (adjusted / line_range) as u64 * min_inst_length;
+        }
+        DW_LNS_FIXED_ADVANCE_PC => {
+          address += get_u16(unit, cur, what)? as u64;
+          cur += 2;
+        }
+        _ => {
+          // Everything else only changes state we don't track.
+          for _ in 0..opcode_lengths[opcode as usize - 1] {
+            get_uleb128(unit, &mut cur, what)?;
+          }
+        }
+      }
+    }
+    if emit {
+      rows.push(LineRow {
+        address: address,
+        file: files
+          .get((file as usize).wrapping_sub(1))
+          .cloned()
+          .unwrap_or_default(),
+        line: line as u64,
+        end_sequence: end_sequence,
+      });
+    }
+    if end_sequence {
+      address = 0;
+      file = 1;
+      line = 1;
+    }
+  }
+  Ok(())
+}
+
+// The source line of the instruction at `address`, if a line table covers
+// it.
+pub fn line_for_address(rows: &[LineRow], address: u64) -> Option<&LineRow> {
+  rows
+    .windows(2)
+    .find(|pair| {
+      !pair[0].end_sequence
+        && pair[0].address <= address
+        && address < pair[1].address
+    })
+    .map(|pair| &pair[0])
+}
@@ -0,0 +1,820 @@
+// The checks dyld makes before it maps an image, made when the image is
+// written instead, so that an output dyld would refuse to load ("malformed
+// mach-o image: ...") fails the link rather than the program's launch.
+// --verify-output runs them on the output, and `mold dyld-check [-arch
+// <name>] <file>...` on any image.
+//
+// As in dyld: the load commands add up, segments stay in the file, don't
+// overlap and have protections dyld accepts, every blob of __LINKEDIT data
+// is inside __LINKEDIT, the chained fixups are well-formed and each chain
+// stays on its page and binds imports which exist, the export trie parses
+// and points into the image, and the code signature (which arm64 needs)
+// matches and comes last.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use codesign;
+use diagnostics::{Diagnostic, Severity};
+use macho::chained_fixups::{DYLD_CHAINED_PTR_32, DYLD_CHAINED_PTR_64,
+                            DYLD_CHAINED_PTR_64_OFFSET,
+                            DYLD_CHAINED_PTR_ARM64E,
+                            DYLD_CHAINED_PTR_START_NONE};
+use macho::dyld_info::{EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE,
+                       EXPORT_SYMBOL_FLAGS_KIND_MASK};
+use macho::export_trie::{self, ExportKind};
+use macho::fat;
+use macho::header::{header_size, parse_magic};
+use macho::load_command::{load_commands, LoadCommandHeader, Segment64,
+                          LC_CODE_SIGNATURE, LC_DATA_IN_CODE,
+                          LC_DYLD_CHAINED_FIXUPS, LC_DYLD_EXPORTS_TRIE,
+                          LC_DYLD_INFO, LC_DYLD_INFO_ONLY, LC_DYSYMTAB,
+                          LC_FUNCTION_STARTS, LC_LINKER_OPTIMIZATION_HINT,
+                          LC_LOAD_DYLIB, LC_LOAD_UPWARD_DYLIB,
+                          LC_LOAD_WEAK_DYLIB, LC_REEXPORT_DYLIB, LC_SEGMENT,
+                          LC_SEGMENT_64, LC_SEGMENT_SPLIT_INFO, LC_SYMTAB};
+use macho::{get_u16, get_u32, get_u64, Arch, MachOError, SECTION_TYPE,
+            S_GB_ZEROFILL, S_THREAD_LOCAL_ZEROFILL, S_ZEROFILL, VM_PROT_EXECUTE,
+            VM_PROT_READ, VM_PROT_WRITE};
+
+#[derive(Debug)]
+pub enum DyldCheckError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  Usage(String),
+  // What dyld would refuse to load the file over, one problem each.
+  Rejected(PathBuf, Vec<String>),
+  // The same, from `mold dyld-check`, which has printed the problems.
+  Invalid(PathBuf),
+}
+
+pub type Result<T> = ::std::result::Result<T, DyldCheckError>;
+
+impl DyldCheckError {
+  pub fn diagnostic(&self) -> Diagnostic {
+    let (code, file) = match *self {
+      DyldCheckError::IoError(ref path, _) => ("io-error", Some(path)),
+      DyldCheckError::Malformed(ref path, _)
+      | DyldCheckError::Rejected(ref path, _)
+      | DyldCheckError::Invalid(ref path) => ("dyld-would-reject", Some(path)),
+      DyldCheckError::Usage(_) => ("invalid-arguments", None),
+    };
+    let message = match *self {
+      DyldCheckError::Rejected(ref path, ref problems) => {
+        let mut message =
+          format!("dyld would refuse to load {}:", path.display());
+        for problem in problems.iter() {
+          message.push_str(&format!("\n  {}", problem));
+        }
+        message
+      }
+      _ => format!("{:?}", self),
+    };
+    let mut diagnostic = Diagnostic::new(Severity::Error, message);
+    diagnostic.code = Some(code);
+    diagnostic.file = file.cloned();
+    diagnostic
+  }
+}
+
+// A blob of data in __LINKEDIT: what it is and where it is in the file.
+struct Blob {
+  what: &'static str,
+  offset: u64,
+  size: u64,
+}
+
+impl Blob {
+  fn end(&self) -> u64 {
+    self.offset.saturating_add(self.size)
+  }
+
+  fn contents<'a>(&self, data: &'a [u8]) -> Option<&'a [u8]> {
+    data.get((self.offset as usize)..(self.end() as usize))
+  }
+}
+
+// "r-x" and so on.
+fn protection(prot: u32) -> String {
+  let flag = |bit: u32, c: char| if prot & bit != 0 { c } else { '-' };
+  let mut text = String::new();
+  text.push(flag(VM_PROT_READ, 'r'));
+  text.push(flag(VM_PROT_WRITE, 'w'));
+  text.push(flag(VM_PROT_EXECUTE, 'x'));
+  text
+}
+
+fn is_zerofill(flags: u32) -> bool {
+  match flags & SECTION_TYPE {
+    S_ZEROFILL | S_GB_ZEROFILL | S_THREAD_LOCAL_ZEROFILL => true,
+    _ => false,
+  }
+}
+
+fn check_commands(
+  data: &[u8],
+  is_64_bit: bool,
+  sizeofcmds: u64,
+  commands: &[LoadCommandHeader],
+  problems: &mut Vec<String>,
+) {
+  let align = if is_64_bit { 8 } else { 4 };
+  let mut total: u64 = 0;
+  for command in commands.iter() {
+    if command.cmdsize % align != 0 {
+      problems.push(format!(
+        "load command {:#x} at offset {:#x} is {} bytes, which isn't a \
+         multiple of {}",
+        command.cmd, command.offset, command.cmdsize, align
+      ));
+    }
+    total += command.cmdsize as u64;
+  }
+  if total != sizeofcmds {
+    problems.push(format!(
+      "the load commands are {} bytes, but the header's sizeofcmds is {}",
+      total, sizeofcmds
+    ));
+  }
+  if header_size(is_64_bit) + sizeofcmds > data.len() as u64 {
+    problems.push("the load commands run past the end of the file".to_string());
+  }
+}
+
+fn check_segments(
+  data: &[u8],
+  header_end: u64,
+  segments: &[Segment64],
+  problems: &mut Vec<String>,
+) {
+  for (i, seg) in segments.iter().enumerate() {
+    let name = &seg.segname;
+    let file_end = seg.fileoff.saturating_add(seg.filesize);
+    let vm_end = seg.vmaddr.saturating_add(seg.vmsize);
+    if file_end > data.len() as u64 {
+      problems.push(format!("{} runs past the end of the file", name));
+    }
+    if seg.filesize > seg.vmsize {
+      problems.push(format!(
+        "{} has {:#x} bytes in the file but a vmsize of {:#x}",
+        name, seg.filesize, seg.vmsize
+      ));
+    }
+    if seg.initprot & !seg.maxprot != 0 {
+      problems.push(format!(
+        "{} has initprot {} beyond its maxprot {}",
+        name,
+        protection(seg.initprot),
+        protection(seg.maxprot)
+      ));
+    }
+    if seg.initprot & (VM_PROT_WRITE | VM_PROT_EXECUTE)
+      == VM_PROT_WRITE | VM_PROT_EXECUTE
+    {
+      problems.push(format!("{} is both writable and executable", name));
+    }
+    match name.as_str() {
+      "__TEXT" => {
+        if seg.initprot != VM_PROT_READ | VM_PROT_EXECUTE {
+          problems.push(format!(
+            "__TEXT must be r-x, not {}",
+            protection(seg.initprot)
+          ));
+        }
+        if seg.fileoff != 0 || seg.filesize < header_end {
+          problems.push(
+            "__TEXT doesn't map the mach header and load commands".to_string(),
+          );
+        }
+      }
+      "__LINKEDIT" => {
+        if seg.initprot != VM_PROT_READ {
+          problems.push(format!(
+            "__LINKEDIT must be r--, not {}",
+            protection(seg.initprot)
+          ));
+        }
+        if segments.iter().any(|other| other.fileoff > seg.fileoff) {
+          problems
+            .push("__LINKEDIT isn't the last segment in the file".to_string());
+        }
+      }
+      _ => (),
+    }
+    for other in segments[(i + 1)..].iter() {
+      let other_vm_end = other.vmaddr.saturating_add(other.vmsize);
+      if seg.vmsize > 0 && other.vmsize > 0 && seg.vmaddr < other_vm_end
+        && other.vmaddr < vm_end
+      {
+        problems.push(format!(
+          "{} and {} overlap in memory",
+          name, other.segname
+        ));
+      }
+      let other_file_end = other.fileoff.saturating_add(other.filesize);
+      if seg.filesize > 0 && other.filesize > 0 && seg.fileoff < other_file_end
+        && other.fileoff < file_end
+      {
+        problems.push(format!(
+          "{} and {} overlap in the file",
+          name, other.segname
+        ));
+      }
+    }
+    for sect in seg.sections.iter() {
+      let offset = sect.offset as u64;
+      if sect.addr < seg.vmaddr || sect.addr.saturating_add(sect.size) > vm_end
+      {
+        problems.push(format!(
+          "{},{} is outside its segment",
+          sect.segname, sect.sectname
+        ));
+      } else if !is_zerofill(sect.flags) && sect.size > 0
+        && (offset < seg.fileoff || offset.saturating_add(sect.size) > file_end)
+      {
+        problems.push(format!(
+          "{},{} is outside its segment's file contents",
+          sect.segname, sect.sectname
+        ));
+      }
+    }
+  }
+}
+
+// What a linkedit_data_command's data is, for the commands which have one.
+fn linkedit_data_name(cmd: u32) -> Option<&'static str> {
+  match cmd {
+    LC_CODE_SIGNATURE => Some("code signature"),
+    LC_SEGMENT_SPLIT_INFO => Some("split segment info"),
+    LC_FUNCTION_STARTS => Some("function starts"),
+    LC_DATA_IN_CODE => Some("data in code"),
+    LC_LINKER_OPTIMIZATION_HINT => Some("linker optimization hints"),
+    LC_DYLD_EXPORTS_TRIE => Some("export trie"),
+    LC_DYLD_CHAINED_FIXUPS => Some("chained fixups"),
+    _ => None,
+  }
+}
+
+// Every non-empty blob of __LINKEDIT data the load commands point to.
+fn linkedit_blobs(
+  data: &[u8],
+  is_64_bit: bool,
+  commands: &[LoadCommandHeader],
+) -> ::macho::Result<Vec<Blob>> {
+  let mut blobs: Vec<Blob> = Vec::new();
+  for command in commands.iter() {
+    let at = |field: usize| -> ::macho::Result<u64> {
+      Ok(get_u32(data, command.offset + field, "load command")? as u64)
+    };
+    let mut add = |what: &'static str, offset: u64, size: u64| {
+      blobs.push(Blob {
+        what: what,
+        offset: offset,
+        size: size,
+      })
+    };
+    match command.cmd {
+      LC_SYMTAB => {
+        let nlist_size = if is_64_bit { 16 } else { 12 };
+        add("symbol table", at(8)?, at(12)? * nlist_size);
+        add("string table", at(16)?, at(20)?);
+      }
+      LC_DYSYMTAB => {
+        add("indirect symbol table", at(56)?, at(60)? * 4);
+        add("external relocations", at(64)?, at(68)? * 8);
+        add("local relocations", at(72)?, at(76)? * 8);
+      }
+      LC_DYLD_INFO | LC_DYLD_INFO_ONLY => {
+        let names = [
+          "rebase info",
+          "bind info",
+          "weak bind info",
+          "lazy bind info",
+          "export info",
+        ];
+        for (i, what) in names.iter().enumerate() {
+          add(what, at(8 + 8 * i)?, at(12 + 8 * i)?);
+        }
+      }
+      cmd => {
+        if let Some(what) = linkedit_data_name(cmd) {
+          add(what, at(8)?, at(12)?);
+        }
+      }
+    }
+  }
+  blobs.retain(|blob| blob.size > 0);
+  Ok(blobs)
+}
+
+fn check_linkedit(
+  segments: &[Segment64],
+  blobs: &[Blob],
+  problems: &mut Vec<String>,
+) {
+  let linkedit = segments.iter().find(|seg| seg.segname == "__LINKEDIT");
+  for blob in blobs.iter() {
+    match linkedit {
+      None => problems.push(format!(
+        "the {} isn't in __LINKEDIT, as there is no __LINKEDIT",
+        blob.what
+      )),
+      Some(seg) => {
+        let end = seg.fileoff.saturating_add(seg.filesize);
+        if blob.offset < seg.fileoff || blob.end() > end {
+          problems.push(format!(
+            "the {} ({:#x}..{:#x}) is outside __LINKEDIT ({:#x}..{:#x})",
+            blob.what,
+            blob.offset,
+            blob.end(),
+            seg.fileoff,
+            end
+          ));
+        }
+      }
+    }
+  }
+  let mut sorted: Vec<&Blob> = blobs.iter().collect();
+  sorted.sort_by_key(|blob| blob.offset);
+  for pair in sorted.windows(2) {
+    if pair[0].end() > pair[1].offset {
+      problems.push(format!(
+        "the {} and the {} overlap",
+        pair[0].what, pair[1].what
+      ));
+    }
+  }
+  // The signature hashes everything before it, so nothing may follow it.
+  for (i, blob) in sorted.iter().enumerate() {
+    if blob.what != "code signature" {
+      continue;
+    }
+    if i + 1 != sorted.len() {
+      let problem = "the code signature isn't the last thing in the file";
+      problems.push(problem.to_string());
+    }
+    if blob.offset % 16 != 0 {
+      problems.push("the code signature isn't 16-byte aligned".to_string());
+    }
+  }
+}
+
+// A chained pointer's import ordinal if it's a bind, its target's offset
+// from the mach header if it's a rebase whose target can be checked, and
+// the distance to the next pointer in strides.
+fn decode_pointer(
+  format: u16,
+  value: u64,
+  base: u64,
+) -> (Option<u64>, Option<u64>, u64) {
+  match format {
+    DYLD_CHAINED_PTR_ARM64E => {
+      let next = (value >> 51) & 0x7ff;
+      let auth = value >> 63 != 0;
+      if (value >> 62) & 1 != 0 {
+        (Some(value & 0xffff), None, next)
+      } else if auth {
+        (None, Some(value & 0xffff_ffff), next)
+      } else {
+        (None, Some((value & 0x7ff_ffff_ffff).wrapping_sub(base)), next)
+      }
+    }
+    // Rebase targets above max_valid_pointer aren't pointers at all.
+    DYLD_CHAINED_PTR_32 => {
+      let next = (value >> 26) & 0x1f;
+      if (value >> 31) & 1 != 0 {
+        (Some(value & 0xf_ffff), None, next)
+      } else {
+        (None, None, next)
+      }
+    }
+    _ => {
+      let next = (value >> 51) & 0xfff;
+      let target = value & 0xf_ffff_ffff;
+      if value >> 63 != 0 {
+        (Some(value & 0xff_ffff), None, next)
+      } else if format == DYLD_CHAINED_PTR_64 {
+        (None, Some(target.wrapping_sub(base)), next)
+      } else {
+        (None, Some(target), next)
+      }
+    }
+  }
+}
+
+// The shape of the image the chains are checked against.
+struct ChainContext {
+  // The mach header's vmaddr, and the size of the image from it.
+  base: u64,
+  image_size: u64,
+  imports_count: u64,
+}
+
+// Follow the chain starting `offset` bytes into `seg`, to its end.
+fn walk_chain(
+  data: &[u8],
+  seg: &Segment64,
+  format: u16,
+  page_size: u64,
+  mut offset: u64,
+  context: &ChainContext,
+) -> ::std::result::Result<(), String> {
+  let (stride, pointer_size) = match format {
+    DYLD_CHAINED_PTR_ARM64E => (8, 8),
+    DYLD_CHAINED_PTR_32 => (4, 4),
+    _ => (4, 8),
+  };
+  let page_end = ((offset / page_size + 1) * page_size).min(seg.filesize);
+  loop {
+    if offset + pointer_size > page_end {
+      return Err(format!(
+        "the chain at offset {:#x} runs off its page",
+        offset
+      ));
+    }
+    let at = (seg.fileoff + offset) as usize;
+    let value = if pointer_size == 4 {
+      get_u32(data, at, "chained pointer").map(|value| value as u64)
+    } else {
+      get_u64(data, at, "chained pointer")
+    };
+    let value = value.map_err(|_| {
+      format!("the pointer at offset {:#x} is past the end of the file", offset)
+    })?;
+    let (ordinal, target, next) = decode_pointer(format, value, context.base);
+    if let Some(ordinal) = ordinal {
+      if ordinal >= context.imports_count {
+        return Err(format!(
+          "the pointer at offset {:#x} binds import {}, but there are {}",
+          offset, ordinal, context.imports_count
+        ));
+      }
+    }
+    if let Some(target) = target {
+      if target >= context.image_size {
+        return Err(format!(
+          "the pointer at offset {:#x} is rebased to {:#x}, outside the image",
+          offset, target
+        ));
+      }
+    }
+    if next == 0 {
+      return Ok(());
+    }
+    offset += next * stride;
+  }
+}
+
+fn check_chained_fixups(
+  data: &[u8],
+  fixups: &[u8],
+  segments: &[Segment64],
+  base: u64,
+  image_size: u64,
+  problems: &mut Vec<String>,
+) -> ::macho::Result<()> {
+  let what = "dyld_chained_fixups_header";
+  let version = get_u32(fixups, 0, what)?;
+  let starts_offset = get_u32(fixups, 4, what)? as usize;
+  let imports_offset = get_u32(fixups, 8, what)? as u64;
+  let symbols_offset = get_u32(fixups, 12, what)? as u64;
+  let imports_count = get_u32(fixups, 16, what)? as u64;
+  let imports_format = get_u32(fixups, 20, what)?;
+  if version != 0 {
+    problems.push(format!("chained fixups version {} isn't 0", version));
+    return Ok(());
+  }
+  // DYLD_CHAINED_IMPORT, DYLD_CHAINED_IMPORT_ADDEND and
+  // DYLD_CHAINED_IMPORT_ADDEND64.
+  let import_size = match imports_format {
+    1 => 4,
+    2 => 8,
+    3 => 16,
+    format => {
+      problems
+        .push(format!("chained fixups import format {} is unknown", format));
+      return Ok(());
+    }
+  };
+  if imports_offset + imports_count * import_size > symbols_offset
+    || symbols_offset > fixups.len() as u64
+  {
+    let problem = "the chained fixups' imports run into their names";
+    problems.push(problem.to_string());
+    return Ok(());
+  }
+  for i in 0..imports_count {
+    let at = (imports_offset + i * import_size) as usize;
+    let name_offset = if imports_format == 3 {
+      get_u32(fixups, at + 4, "dyld_chained_import")? as u64
+    } else {
+      (get_u32(fixups, at, "dyld_chained_import")? >> 9) as u64
+    };
+    if symbols_offset + name_offset >= fixups.len() as u64 {
+      problems.push(format!(
+        "chained fixups import {} has its name outside the chained fixups",
+        i
+      ));
+      break;
+    }
+  }
+
+  let what = "dyld_chained_starts_in_image";
+  let seg_count = get_u32(fixups, starts_offset, what)? as usize;
+  if seg_count != segments.len() {
+    problems.push(format!(
+      "the chained fixups describe {} segments, but there are {}",
+      seg_count,
+      segments.len()
+    ));
+  }
+  let context = ChainContext {
+    base: base,
+    image_size: image_size,
+    imports_count: imports_count,
+  };
+  for (i, seg) in segments.iter().enumerate().take(seg_count) {
+    let info = get_u32(fixups, starts_offset + 4 + 4 * i, what)? as usize;
+    if info == 0 {
+      continue;
+    }
+    let at = starts_offset + info;
+    let what = "dyld_chained_starts_in_segment";
+    let page_size = get_u16(fixups, at + 4, what)? as u64;
+    let format = get_u16(fixups, at + 6, what)?;
+    let segment_offset = get_u64(fixups, at + 8, what)?;
+    let page_count = get_u16(fixups, at + 20, what)? as u64;
+    let name = &seg.segname;
+    if page_size != 0x1000 && page_size != 0x4000 {
+      problems.push(format!(
+        "{}'s chained fixups have a page size of {:#x}",
+        name, page_size
+      ));
+      continue;
+    }
+    match format {
+      DYLD_CHAINED_PTR_ARM64E
+      | DYLD_CHAINED_PTR_64
+      | DYLD_CHAINED_PTR_32
+      | DYLD_CHAINED_PTR_64_OFFSET => (),
+      _ => {
+        problems.push(format!(
+          "{}'s chained fixups have pointer format {}, which isn't checked",
+          name, format
+        ));
+        continue;
+      }
+    }
+    if segment_offset != seg.vmaddr.wrapping_sub(base) {
+      problems.push(format!(
+        "the chained fixups put {} at offset {:#x}, but it's at {:#x}",
+        name,
+        segment_offset,
+        seg.vmaddr.wrapping_sub(base)
+      ));
+    }
+    if page_count > (seg.vmsize + page_size - 1) / page_size {
+      problems.push(format!(
+        "{} has chained fixups for {} pages, more than it has",
+        name, page_count
+      ));
+      continue;
+    }
+    for page in 0..page_count {
+      let start = get_u16(fixups, at + 22 + 2 * page as usize, what)?;
+      // DYLD_CHAINED_PTR_START_MULTI pages, for 32-bit images, aren't
+      // followed.
+      if start == DYLD_CHAINED_PTR_START_NONE || start & 0x8000 != 0 {
+        continue;
+      }
+      let offset = page * page_size + start as u64;
+      if let Err(problem) =
+        walk_chain(data, seg, format, page_size, offset, &context)
+      {
+        problems.push(format!("{}: {}", name, problem));
+        break;
+      }
+    }
+  }
+  Ok(())
+}
+
+fn check_exports(
+  trie: &[u8],
+  image_size: u64,
+  dylib_count: u64,
+  problems: &mut Vec<String>,
+) {
+  let exports = match export_trie::parse(trie) {
+    Ok(exports) => exports,
+    Err(e) => {
+      problems.push(format!("the export trie is malformed ({:?})", e));
+      return;
+    }
+  };
+  for export in exports.iter() {
+    match export.kind {
+      ExportKind::Regular { address } => {
+        let kind = export.flags & EXPORT_SYMBOL_FLAGS_KIND_MASK;
+        if kind != EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE && address >= image_size {
+          problems.push(format!(
+            "the export trie puts {} at {:#x}, outside the image",
+            export.name, address
+          ));
+        }
+      }
+      ExportKind::Reexport { ordinal, .. } => {
+        if ordinal == 0 || ordinal > dylib_count {
+          problems.push(format!(
+            "{} is re-exported from dylib {}, but {} are loaded",
+            export.name, ordinal, dylib_count
+          ));
+        }
+      }
+    }
+  }
+}
+
+// Everything about the thin image in `data` which dyld would refuse to load
+// it over. Nothing if it would load it.
+pub fn check(data: &[u8]) -> ::macho::Result<Vec<String>> {
+  let is_64_bit = parse_magic(data)?;
+  let cputype = get_u32(data, 4, "mach header")?;
+  let cpusubtype = get_u32(data, 8, "mach header")?;
+  let sizeofcmds = get_u32(data, 20, "mach header")? as u64;
+  let commands = load_commands(data)?;
+  let mut problems: Vec<String> = Vec::new();
+  check_commands(data, is_64_bit, sizeofcmds, &commands, &mut problems);
+
+  let mut segments: Vec<Segment64> = Vec::new();
+  for command in commands.iter() {
+    match command.cmd {
+      LC_SEGMENT_64 => segments.push(Segment64::parse(data, command.offset)?),
+      LC_SEGMENT => segments.push(Segment64::parse_32(data, command.offset)?),
+      _ => (),
+    }
+  }
+  let header_end = header_size(is_64_bit) + sizeofcmds;
+  check_segments(data, header_end, &segments, &mut problems);
+  let blobs = linkedit_blobs(data, is_64_bit, &commands)?;
+  check_linkedit(&segments, &blobs, &mut problems);
+
+  let base = segments
+    .iter()
+    .find(|seg| seg.segname == "__TEXT")
+    .map_or(0, |seg| seg.vmaddr);
+  let image_size = segments
+    .iter()
+    .filter(|seg| seg.segname != "__PAGEZERO")
+    .map(|seg| seg.vmaddr.saturating_add(seg.vmsize).saturating_sub(base))
+    .max()
+    .unwrap_or(0);
+  let dylib_count = commands
+    .iter()
+    .filter(|command| match command.cmd {
+      LC_LOAD_DYLIB
+      | LC_LOAD_WEAK_DYLIB
+      | LC_REEXPORT_DYLIB
+      | LC_LOAD_UPWARD_DYLIB => true,
+      _ => false,
+    })
+    .count() as u64;
+  for blob in blobs.iter() {
+    let contents = match blob.contents(data) {
+      Some(contents) => contents,
+      None => continue,
+    };
+    match blob.what {
+      "chained fixups" => {
+        let checked = check_chained_fixups(
+          data,
+          contents,
+          &segments,
+          base,
+          image_size,
+          &mut problems,
+        );
+        if let Err(e) = checked {
+          problems.push(format!("the chained fixups are malformed ({:?})", e));
+        }
+      }
+      "export trie" | "export info" => {
+        check_exports(contents, image_size, dylib_count, &mut problems)
+      }
+      _ => (),
+    }
+  }
+
+  // codesign only reads 64-bit images.
+  if is_64_bit {
+    match codesign::verify(data) {
+      Ok(Some(verification)) => {
+        for mismatch in verification.mismatches.iter() {
+          problems.push(format!(
+            "the code signature doesn't match: {}",
+            mismatch.describe()
+          ));
+        }
+      }
+      Ok(None) => {
+        let arch = Arch::from_cputype(cputype, cpusubtype);
+        if arch.map_or(false, |arch| arch.is_arm64()) {
+          problems.push("arm64 images must be signed".to_string());
+        }
+      }
+      Err(e) => {
+        problems.push(format!("the code signature is malformed ({:?})", e))
+      }
+    }
+  }
+  Ok(problems)
+}
+
+// The problems with each image in `data`, fat or thin, each headed by its
+// architecture if it's fat. Only the slice for `arch`, if given.
+fn check_slices(
+  data: &[u8],
+  arch: Option<Arch>,
+) -> ::macho::Result<Vec<String>> {
+  let is_fat = fat::is_fat(data);
+  let mut problems: Vec<String> = Vec::new();
+  for (slice_arch, thin) in fat::slices(data)? {
+    if is_fat && arch.is_some() && slice_arch != arch {
+      continue;
+    }
+    let slice_problems = check(thin)?;
+    if is_fat {
+      let name = slice_arch.map_or("unknown", |arch| arch.name());
+      for problem in slice_problems.into_iter() {
+        problems.push(format!("({}) {}", name, problem));
+      }
+    } else {
+      problems.extend(slice_problems);
+    }
+  }
+  Ok(problems)
+}
+
+// Check a linked output, for --verify-output.
+pub fn check_file(path: &Path) -> Result<()> {
+  let data =
+    fs::read(path).map_err(|e| DyldCheckError::IoError(path.to_path_buf(), e))?;
+  let problems = check_slices(&data, None)
+    .map_err(|e| DyldCheckError::Malformed(path.to_path_buf(), e))?;
+  if problems.is_empty() {
+    Ok(())
+  } else {
+    Err(DyldCheckError::Rejected(path.to_path_buf(), problems))
+  }
+}
+
+// `mold dyld-check`, printing each file's problems to stdout.
+pub fn run_command(args: &[String]) -> Result<()> {
+  let mut arch: Option<Arch> = None;
+  let mut inputs: Vec<PathBuf> = Vec::new();
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-arch" => {
+        let name = args.next().ok_or_else(|| {
+          DyldCheckError::Usage("-arch needs an architecture".to_string())
+        })?;
+        arch = Some(Arch::from_name(name).ok_or_else(|| {
+          DyldCheckError::Usage(format!("unknown architecture {}", name))
+        })?);
+      }
+      _ if arg.starts_with('-') => {
+        return Err(DyldCheckError::Usage(format!("unknown option {}", arg)));
+      }
+      _ => inputs.push(PathBuf::from(arg)),
+    }
+  }
+  if inputs.is_empty() {
+    return Err(DyldCheckError::Usage("no input files".to_string()));
+  }
+
+  // Check everything before giving up on the first bad file.
+  let mut invalid: Option<PathBuf> = None;
+  for input in inputs.iter() {
+    let data =
+      fs::read(input).map_err(|e| DyldCheckError::IoError(input.clone(), e))?;
+    let problems = check_slices(&data, arch)
+      .map_err(|e| DyldCheckError::Malformed(input.clone(), e))?;
+    if problems.is_empty() {
+      println!("{}: ok", input.display());
+      continue;
+    }
+    println!("{}:", input.display());
+    for problem in problems.iter() {
+      println!("  {}", problem);
+    }
+    if invalid.is_none() {
+      invalid = Some(input.clone());
+    }
+  }
+  match invalid {
+    Some(input) => Err(DyldCheckError::Invalid(input)),
+    None => Ok(()),
+  }
+}
@@ -0,0 +1,839 @@
+// Dylibs on both sides of a link: the load commands identifying a dylib
+// output (LC_ID_DYLIB, LC_SUB_FRAMEWORK, LC_SUB_CLIENT) and the runtime
+// search paths it adds (LC_RPATH), and the set of dylibs the output links
+// against, including the ones they re-export, which undefined symbols are
+// resolved against.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use args::{LinkOptions, OutputKind};
+use cache;
+use dependency_info;
+use diagnostics;
+use image::Image;
+use ld_env;
+use macho::dyld_info::BIND_SPECIAL_DYLIB_MAIN_EXECUTABLE;
+use macho::dylib_file::DylibFile;
+use macho::load_command::{LoadCommand, LC_ID_DYLIB, LC_LOAD_DYLIB,
+                          LC_LOAD_UPWARD_DYLIB, LC_LOAD_WEAK_DYLIB,
+                          LC_REEXPORT_DYLIB, LC_SUB_CLIENT,
+                          LC_SUB_FRAMEWORK};
+use macho::{MachOError, ParseLimits, Version, MH_APP_EXTENSION_SAFE,
+            MH_DEAD_STRIPPABLE_DYLIB, MH_DYLDLINK, MH_NO_REEXPORTED_DYLIBS,
+            MH_TWOLEVEL};
+use search;
+use statistics;
+use target::{Target, TargetError};
+
+// ld64 writes fixed timestamps instead of real ones, so builds are
+// reproducible. dyld ignores them.
+pub const ID_DYLIB_TIMESTAMP: u32 = 1;
+pub const LOAD_DYLIB_TIMESTAMP: u32 = 2;
+
+#[derive(Debug)]
+pub enum DylibError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  LibraryNotFound(String),
+  FrameworkNotFound(String),
+  // -sub_library naming none of the linked dylibs.
+  SubLibraryNotFound(String),
+  // (install name, umbrella) of a dylib which is part of an umbrella
+  // framework the output is not allowed to bypass.
+  NotAllowedClient(String, String),
+  // -application_extension: the install name of a linked dylib which isn't
+  // marked MH_APP_EXTENSION_SAFE.
+  NotExtensionSafe(String),
+  Target(TargetError),
+}
+
+impl From<TargetError> for DylibError {
+  fn from(error: TargetError) -> Self {
+    DylibError::Target(error)
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, DylibError>;
+
+pub fn id_dylib_command(
+  install_name: &str,
+  current_version: Version,
+  compatibility_version: Version,
+) -> LoadCommand {
+  LoadCommand::Dylib {
+    cmd: LC_ID_DYLIB,
+    name: install_name.to_string(),
+    timestamp: ID_DYLIB_TIMESTAMP,
+    current_version: current_version,
+    compatibility_version: compatibility_version,
+  }
+}
+
+// One LC_RPATH per distinct -rpath, in command-line order.
+pub fn rpath_commands(rpaths: &[String]) -> Vec<LoadCommand> {
+  let mut seen: HashSet<&str> = HashSet::new();
+  rpaths
+    .iter()
+    .filter(|path| seen.insert(path.as_str()))
+    .map(|path| LoadCommand::Rpath { path: path.clone() })
+    .collect()
+}
+
+fn is_relative(path: &str) -> bool {
+  !path.starts_with('/') && !path.starts_with('@')
+}
+
+pub fn rpath_warnings(rpaths: &[String]) -> Vec<String> {
+  let mut warnings: Vec<String> = Vec::new();
+  let mut seen: HashSet<&str> = HashSet::new();
+  for path in rpaths.iter() {
+    if !seen.insert(path.as_str()) {
+      warnings.push(format!("duplicate -rpath '{}' ignored", path));
+    } else if path.starts_with("@rpath") {
+      warnings.push(format!(
+        "-rpath '{}' starts with @rpath, which dyld does not expand in rpaths",
+        path
+      ));
+    } else if is_relative(path) {
+      warnings.push(format!(
+        "-rpath '{}' is relative to the working directory at runtime; \
+         did you mean '@loader_path/{}'?",
+        path, path
+      ));
+    }
+  }
+  warnings
+}
+
+pub fn install_name_warnings(install_name: &str) -> Vec<String> {
+  let mut warnings: Vec<String> = Vec::new();
+  if is_relative(install_name) {
+    warnings.push(format!(
+      "install name '{}' is neither an absolute path nor relative to \
+       @rpath, @loader_path or @executable_path",
+      install_name
+    ));
+  }
+  warnings
+}
+
+// Where a path in the output starting with @loader_path or
+// @executable_path leads, given where the output is, or None if that
+// depends on the executable the output gets loaded into.
+fn expand_runtime_path(
+  path: &str,
+  output: &Path,
+  is_executable: bool,
+) -> Option<PathBuf> {
+  let dir = output.parent().unwrap_or_else(|| Path::new(""));
+  if path.starts_with("@loader_path/") {
+    Some(dir.join(&path["@loader_path/".len()..]))
+  } else if path == "@loader_path" {
+    Some(dir.to_path_buf())
+  } else if path.starts_with("@executable_path/") && is_executable {
+    Some(dir.join(&path["@executable_path/".len()..]))
+  } else if path.starts_with('@') {
+    None
+  } else {
+    Some(PathBuf::from(path))
+  }
+}
+
+// Every path dyld would try for a dylib with `install_name`, loaded by the
+// output at `output` with these rpaths, or None if they can't be known
+// before the output is loaded. Absolute install names aren't checked:
+// system dylibs are only in the shared cache.
+pub fn runtime_candidates(
+  install_name: &str,
+  output: &Path,
+  is_executable: bool,
+  rpaths: &[String],
+) -> Option<Vec<PathBuf>> {
+  if !install_name.starts_with('@') {
+    return None;
+  }
+  if !install_name.starts_with("@rpath/") {
+    return expand_runtime_path(install_name, output, is_executable)
+      .map(|path| vec![path]);
+  }
+  // A dylib or bundle may be loaded by an executable with rpaths of its own.
+  if rpaths.is_empty() && !is_executable {
+    return None;
+  }
+  let rest = &install_name["@rpath/".len()..];
+  rpaths
+    .iter()
+    .map(|rpath| {
+      expand_runtime_path(rpath, output, is_executable)
+        .map(|dir| dir.join(rest))
+    })
+    .collect()
+}
+
+// The install name of the output, which defaults to the output path.
+pub fn output_install_name(opts: &LinkOptions) -> String {
+  match opts.install_name {
+    Some(ref name) => name.clone(),
+    None => opts.output_path.to_string_lossy().into_owned(),
+  }
+}
+
+// The name the output links umbrella parts as: -client_name, or the short
+// name of its install name.
+pub fn client_name(opts: &LinkOptions) -> String {
+  match opts.client_name {
+    Some(ref name) => name.clone(),
+    None => leaf_name(&output_install_name(opts)).to_string(),
+  }
+}
+
+// The short name umbrella and client checks use: "Foo" for
+// /S/L/F/Foo.framework/Versions/A/Foo, "libfoo" for /usr/lib/libfoo.B.dylib.
+pub fn leaf_name(install_name: &str) -> &str {
+  let base = install_name.rsplit('/').next().unwrap_or(install_name);
+  match base.find('.') {
+    Some(idx) => &base[..idx],
+    None => base,
+  }
+}
+
+// The LC_ID_DYLIB, LC_SUB_FRAMEWORK and LC_SUB_CLIENT (for dylibs) and
+// LC_RPATH commands for the output, warning about suspicious paths along the
+// way. Like ld64, both versions default to 0.
+pub fn load_commands(opts: &LinkOptions, is_dylib: bool) -> Vec<LoadCommand> {
+  let mut cmds: Vec<LoadCommand> = Vec::new();
+  if is_dylib {
+    let install_name = output_install_name(opts);
+    for warning in install_name_warnings(&install_name) {
+      diagnostics::warning(&warning);
+    }
+    cmds.push(id_dylib_command(
+      &install_name,
+      opts.current_version.unwrap_or(Version(0)),
+      opts.compatibility_version.unwrap_or(Version(0)),
+    ));
+    if let Some(ref umbrella) = opts.umbrella {
+      cmds.push(LoadCommand::SubName {
+        cmd: LC_SUB_FRAMEWORK,
+        name: umbrella.clone(),
+      });
+    }
+    cmds.extend(opts.allowable_clients.iter().map(|client| {
+      LoadCommand::SubName {
+        cmd: LC_SUB_CLIENT,
+        name: client.clone(),
+      }
+    }));
+  }
+  for warning in rpath_warnings(&opts.rpaths) {
+    diagnostics::warning(&warning);
+  }
+  cmds.extend(rpath_commands(&opts.rpaths));
+  cmds
+}
+
+pub fn read_dylib(path: &Path, limits: &ParseLimits) -> Result<DylibFile> {
+  let mut data: Vec<u8> = Vec::new();
+  File::open(path)
+    .and_then(|mut f| f.read_to_end(&mut data))
+    .map_err(|e| DylibError::IoError(path.to_path_buf(), e))?;
+  statistics::bytes_read(data.len());
+  DylibFile::parse_with_limits(&data, limits)
+    .map_err(|e| DylibError::Malformed(path.to_path_buf(), e))
+}
+
+// A dylib as read from disk, with its exports as a set for lookups. The
+// daemon keeps these between links.
+#[derive(Debug)]
+pub struct ReadDylib {
+  pub file: DylibFile,
+  pub exports: HashSet<String>,
+}
+
+impl ReadDylib {
+  fn new(file: DylibFile) -> Self {
+    ReadDylib {
+      exports: file.exports.iter().map(|e| e.name.clone()).collect(),
+      file: file,
+    }
+  }
+}
+
+#[derive(Debug)]
+struct LoadedDylib {
+  read: Arc<ReadDylib>,
+  path: PathBuf,
+}
+
+impl Deref for LoadedDylib {
+  type Target = ReadDylib;
+
+  fn deref(&self) -> &ReadDylib {
+    &self.read
+  }
+}
+
+// How the output links a dylib, which decides its load command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Linkage {
+  Normal,
+  // -weak_library and friends: the dylib may be missing at runtime, so
+  // symbols from it are weak imports, which dyld sets to 0 if missing.
+  Weak,
+  // -upward_library and friends: the dylib may depend on the output in
+  // turn, so dyld doesn't order its initializers first.
+  Upward,
+  Reexport,
+}
+
+impl Linkage {
+  // The linkage of a dylib linked both ways: a re-export or a normal link
+  // wins, and it's only weak or upward if every link was.
+  fn join(self, other: Linkage) -> Linkage {
+    match (self, other) {
+      (Linkage::Reexport, _) | (_, Linkage::Reexport) => Linkage::Reexport,
+      (a, b) if a == b => a,
+      _ => Linkage::Normal,
+    }
+  }
+
+  fn load_command(self) -> u32 {
+    match self {
+      Linkage::Normal => LC_LOAD_DYLIB,
+      Linkage::Weak => LC_LOAD_WEAK_DYLIB,
+      Linkage::Upward => LC_LOAD_UPWARD_DYLIB,
+      Linkage::Reexport => LC_REEXPORT_DYLIB,
+    }
+  }
+}
+
+// Where an undefined symbol was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DylibSymbol {
+  // Ordinal of the directly linked dylib to bind to, or
+  // BIND_SPECIAL_DYLIB_MAIN_EXECUTABLE for the -bundle_loader. dyld follows
+  // re-exports itself, so this is never the ordinal of a re-exported dylib
+  // the output doesn't link directly.
+  pub ordinal: i64,
+  // Install name of the dylib which actually exports the symbol, or the
+  // path of the -bundle_loader.
+  pub defined_in: String,
+  // Found in a weakly linked dylib, so it must be bound as a weak import.
+  pub weak_import: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct DylibSet {
+  loaded: Vec<LoadedDylib>,
+  by_install_name: HashMap<String, usize>,
+  // The dylibs the output links against, as indices into `loaded` in
+  // ordinal order, and how the output links each one.
+  direct: Vec<(usize, Linkage)>,
+  // -bundle_loader: the executable a bundle will be loaded into.
+  bundle_loader: Option<LoadedDylib>,
+  // -dylib_file: where to read dylibs with these install names from.
+  dylib_files: HashMap<String, PathBuf>,
+  // -syslibroot: where to look for re-exported dylibs by install name.
+  syslibroots: Vec<PathBuf>,
+  // --untrusted-inputs: how much dylibs may ask of the parser.
+  limits: ParseLimits,
+}
+
+// Where to look for a re-exported dylib named by `install_name`, given the
+// path of the dylib which re-exports it.
+fn reexport_path(install_name: &str, parent: &Path) -> Option<PathBuf> {
+  if install_name.starts_with("@loader_path/") {
+    let rest = &install_name["@loader_path/".len()..];
+    parent.parent().map(|dir| dir.join(rest))
+  } else if install_name.starts_with('@') {
+    // @rpath and @executable_path depend on the eventual client.
+    None
+  } else {
+    Some(PathBuf::from(install_name))
+  }
+}
+
+impl DylibSet {
+  pub fn new() -> Self {
+    DylibSet {
+      ..Default::default()
+    }
+  }
+
+  // Read the dylib at `path`, and the dylibs it re-exports, unless they have
+  // already been read. Re-exports which can't be found are skipped with a
+  // warning: their symbols just won't be found through the re-exporter.
+  fn load(&mut self, path: &Path) -> Result<usize> {
+    // Even a copy the daemon kept is this file's contents.
+    dependency_info::record_input(path);
+    let limits = self.limits;
+    let read_with_limits = || read_dylib(path, &limits).map(ReadDylib::new);
+    // Dylibs parsed without limits aren't reused by links with them.
+    let read = if limits == ParseLimits::default() {
+      cache::DYLIBS.get(path, read_with_limits)?
+    } else {
+      Arc::new(read_with_limits()?)
+    };
+    if let Some(idx) = self.by_install_name.get(&read.file.install_name) {
+      return Ok(*idx);
+    }
+    let idx = self.loaded.len();
+    self.by_install_name.insert(read.file.install_name.clone(), idx);
+    let reexports = read.file.reexports.clone();
+    self.loaded.push(LoadedDylib {
+      read: read,
+      path: path.to_path_buf(),
+    });
+    for install_name in reexports.iter() {
+      if self.by_install_name.contains_key(install_name) {
+        continue;
+      }
+      let found = match self.dylib_files.get(install_name) {
+        Some(file) => Some(file.clone()),
+        None => reexport_path(install_name, path)
+          .map(|found| search::in_syslibroots(&found, &self.syslibroots)),
+      };
+      match found {
+        Some(ref reexport) if reexport.is_file() => {
+          self.load(reexport)?;
+        }
+        _ => {
+          if let Some(ref reexport) = found {
+            dependency_info::record_missing(reexport);
+          }
+          diagnostics::warning(&format!(
+            "unable to locate '{}', re-exported by '{}'",
+            install_name,
+            path.display()
+          ))
+        }
+      }
+    }
+    Ok(idx)
+  }
+
+  // Link the output against the dylib at `path`, returning its ordinal.
+  // Linking the same dylib twice just joins the linkages.
+  pub fn link(&mut self, path: &Path, linkage: Linkage) -> Result<u64> {
+    let idx = self.load(path)?;
+    if let Some(pos) = self.direct.iter().position(|&(i, _)| i == idx) {
+      self.direct[pos].1 = self.direct[pos].1.join(linkage);
+      return Ok(pos as u64 + 1);
+    }
+    self.direct.push((idx, linkage));
+    Ok(self.direct.len() as u64)
+  }
+
+  // -sub_library: re-export the linked dylib with the given leaf name.
+  pub fn reexport_by_leaf_name(&mut self, name: &str) -> Result<()> {
+    let loaded = &self.loaded;
+    match self
+      .direct
+      .iter_mut()
+      .find(|&&mut (idx, _)| leaf_name(&loaded[idx].file.install_name) == name)
+    {
+      Some(entry) => {
+        entry.1 = Linkage::Reexport;
+        Ok(())
+      }
+      None => Err(DylibError::SubLibraryNotFound(name.to_string())),
+    }
+  }
+
+  // Check every dylib read, including indirect re-exports, was built for
+  // the target platform, or both platforms of a zippered output.
+  pub fn check_platforms(&self, target: &Target) -> Result<()> {
+    for dylib in self.loaded.iter().chain(self.bundle_loader.iter()) {
+      target.check_input(&dylib.path, dylib.file.platform)?;
+      target.check_zippered(
+        &dylib.path,
+        dylib.file.platform,
+        dylib.file.variant,
+      )?;
+    }
+    Ok(())
+  }
+
+  pub fn has_reexports(&self) -> bool {
+    self
+      .direct
+      .iter()
+      .any(|&(_, linkage)| linkage == Linkage::Reexport)
+  }
+
+  // The dylib exporting `name` reachable from `idx` through re-exports,
+  // depth first in re-export order.
+  fn find_export(
+    &self,
+    idx: usize,
+    name: &str,
+    visited: &mut HashSet<usize>,
+  ) -> Option<usize> {
+    if !visited.insert(idx) {
+      return None;
+    }
+    let dylib = &self.loaded[idx];
+    if dylib.exports.contains(name) {
+      return Some(idx);
+    }
+    for install_name in dylib.file.reexports.iter() {
+      if let Some(reexport) = self.by_install_name.get(install_name) {
+        if let Some(found) = self.find_export(*reexport, name, visited) {
+          return Some(found);
+        }
+      }
+    }
+    None
+  }
+
+  // -bundle_loader: resolve symbols against the exports of the executable
+  // at `path` as well, binding them to whatever image loaded the bundle.
+  pub fn set_bundle_loader(&mut self, path: &Path) -> Result<()> {
+    dependency_info::record_input(path);
+    let mut data: Vec<u8> = Vec::new();
+    File::open(path)
+      .and_then(|mut f| f.read_to_end(&mut data))
+      .map_err(|e| DylibError::IoError(path.to_path_buf(), e))?;
+    let file = DylibFile::parse_executable(&data)
+      .map_err(|e| DylibError::Malformed(path.to_path_buf(), e))?;
+    self.bundle_loader = Some(LoadedDylib {
+      read: Arc::new(ReadDylib::new(file)),
+      path: path.to_path_buf(),
+    });
+    Ok(())
+  }
+
+  // Two-level namespace lookup: the -bundle_loader, then the first directly
+  // linked dylib which exports `name`, itself or through its re-exports.
+  pub fn lookup(&self, name: &str) -> Option<DylibSymbol> {
+    if let Some(ref loader) = self.bundle_loader {
+      if loader.exports.contains(name) {
+        return Some(DylibSymbol {
+          ordinal: BIND_SPECIAL_DYLIB_MAIN_EXECUTABLE,
+          defined_in: loader.path.to_string_lossy().into_owned(),
+          weak_import: false,
+        });
+      }
+    }
+    for (pos, &(idx, linkage)) in self.direct.iter().enumerate() {
+      let mut visited: HashSet<usize> = HashSet::new();
+      if let Some(found) = self.find_export(idx, name, &mut visited) {
+        return Some(DylibSymbol {
+          ordinal: pos as i64 + 1,
+          defined_in: self.loaded[found].file.install_name.clone(),
+          weak_import: linkage == Linkage::Weak,
+        });
+      }
+    }
+    None
+  }
+
+  // A dylib which is part of an umbrella framework (LC_SUB_FRAMEWORK) may
+  // only be linked directly by the umbrella itself, by other parts of the
+  // same umbrella, or by the clients it lists (LC_SUB_CLIENT).
+  pub fn check_clients(
+    &self,
+    client_name: &str,
+    umbrella: Option<&str>,
+  ) -> Result<()> {
+    for &(idx, _) in self.direct.iter() {
+      let dylib = &self.loaded[idx].file;
+      let dylib_umbrella = match dylib.umbrella {
+        Some(ref name) => name,
+        None => continue,
+      };
+      let allowed = client_name == dylib_umbrella
+        || umbrella == Some(dylib_umbrella.as_str())
+        || dylib.allowable_clients.iter().any(|c| c == client_name);
+      if !allowed {
+        return Err(DylibError::NotAllowedClient(
+          dylib.install_name.clone(),
+          dylib_umbrella.clone(),
+        ));
+      }
+    }
+    Ok(())
+  }
+
+  // Stop linking dylibs marked MH_DEAD_STRIPPABLE_DYLIB which none of
+  // `imports` bind to, so the output doesn't load them for nothing.
+  // Re-exports stay, as the output's clients may use them. Ordinals change,
+  // so symbols have to be looked up again afterwards.
+  pub fn remove_unused(&mut self, imports: &[String]) {
+    let used: HashSet<i64> = imports
+      .iter()
+      .filter_map(|name| self.lookup(name))
+      .map(|symbol| symbol.ordinal)
+      .collect();
+    let loaded = &self.loaded;
+    let direct = self.direct.drain(..).enumerate();
+    self.direct = direct
+      .filter(|&(pos, (idx, linkage))| {
+        linkage == Linkage::Reexport
+          || loaded[idx].file.flags & MH_DEAD_STRIPPABLE_DYLIB == 0
+          || used.contains(&(pos as i64 + 1))
+      })
+      .map(|(_, direct)| direct)
+      .collect();
+  }
+
+  // An app extension may only link dylibs built with
+  // -application_extension, which only use the APIs extensions may.
+  pub fn check_extension_safe(&self) -> Result<()> {
+    for &(idx, _) in self.direct.iter() {
+      let dylib = &self.loaded[idx].file;
+      if dylib.flags & MH_APP_EXTENSION_SAFE == 0 {
+        return Err(DylibError::NotExtensionSafe(dylib.install_name.clone()));
+      }
+    }
+    Ok(())
+  }
+
+  // Warnings for the linked dylibs which dyld would fail to find at runtime
+  // ("image not found"), by resolving their install names against where
+  // the output is and the rpaths it has. Only what's decided by the output
+  // itself is checked; weakly linked dylibs may be missing.
+  pub fn runtime_path_warnings(&self, opts: &LinkOptions) -> Vec<String> {
+    let is_executable = opts.output_kind == OutputKind::Executable;
+    let rpaths: Vec<String> = rpath_commands(&opts.rpaths)
+      .into_iter()
+      .filter_map(|cmd| match cmd {
+        LoadCommand::Rpath { path } => Some(path),
+        _ => None,
+      })
+      .collect();
+    let mut warnings: Vec<String> = Vec::new();
+    for &(idx, linkage) in self.direct.iter() {
+      if linkage == Linkage::Weak {
+        continue;
+      }
+      let dylib = &self.loaded[idx];
+      let install_name = &dylib.file.install_name;
+      let candidates = match runtime_candidates(
+        install_name,
+        &opts.output_path,
+        is_executable,
+        &rpaths,
+      ) {
+        Some(candidates) => candidates,
+        None => continue,
+      };
+      // The dylib is where it will be at runtime, or something else is.
+      if candidates
+        .iter()
+        .any(|path| *path == dylib.path || path.is_file())
+      {
+        continue;
+      }
+      if candidates.is_empty() {
+        warnings.push(format!(
+          "'{}' (linked from '{}') is found through @rpath, but the output \
+           has no LC_RPATH; it won't load at runtime without an -rpath",
+          install_name,
+          dylib.path.display()
+        ));
+      } else {
+        let tried: Vec<String> = candidates
+          .iter()
+          .map(|path| format!("'{}'", path.display()))
+          .collect();
+        warnings.push(format!(
+          "'{}' (linked from '{}') won't be found at runtime: dyld would \
+           look for it at {}",
+          install_name,
+          dylib.path.display(),
+          tried.join(", ")
+        ));
+      }
+    }
+    warnings
+  }
+
+  // LC_LOAD_DYLIB, or the weak, upward or re-export variant, for each
+  // linked dylib, in ordinal order.
+  pub fn load_commands(&self) -> Vec<LoadCommand> {
+    self
+      .direct
+      .iter()
+      .map(|&(idx, linkage)| {
+        let dylib = &self.loaded[idx].file;
+        LoadCommand::Dylib {
+          cmd: linkage.load_command(),
+          name: dylib.install_name.clone(),
+          timestamp: LOAD_DYLIB_TIMESTAMP,
+          current_version: dylib.current_version,
+          compatibility_version: dylib.compatibility_version,
+        }
+      })
+      .collect()
+  }
+
+  // How many dylibs the output links directly.
+  pub fn direct_count(&self) -> usize {
+    self.direct.len()
+  }
+
+  pub fn path(&self, ordinal: u64) -> &Path {
+    let (idx, _) = self.direct[ordinal as usize - 1];
+    &self.loaded[idx].path
+  }
+
+  // Link the dylib -l<name> (or -weak-l<name>, etc.) finds. Archives are
+  // linked as inputs instead.
+  fn link_library(
+    &mut self,
+    opts: &LinkOptions,
+    name: &str,
+    linkage: Linkage,
+  ) -> Result<()> {
+    let (found, tried) =
+      search::probe_library(name, &opts.library_search_paths());
+    // Creating any of the paths tried first would change what's linked.
+    for path in tried.iter() {
+      dependency_info::record_missing(path);
+    }
+    match found {
+      Some(ref path) if search::is_dylib_path(path) => {
+        self.link(path, linkage)?;
+        Ok(())
+      }
+      Some(ref path) => {
+        dependency_info::record_input(path);
+        Ok(())
+      }
+      None => Err(DylibError::LibraryNotFound(name.to_string())),
+    }
+  }
+
+  fn link_framework(
+    &mut self,
+    opts: &LinkOptions,
+    name: &str,
+    linkage: Linkage,
+  ) -> Result<()> {
+    match search::find_framework(name, &opts.framework_search_paths()) {
+      Some(ref path) => {
+        self.link(path, linkage)?;
+        Ok(())
+      }
+      None => Err(DylibError::FrameworkNotFound(name.to_string())),
+    }
+  }
+
+  // Link every dylib the options name: dylib inputs, then -l libraries
+  // which resolve to dylibs and -framework frameworks, then the weak and
+  // upward ones, then -reexport_library and -reexport-l. Then apply
+  // -sub_library and check the umbrella rules, and that an app extension
+  // only links extension-safe dylibs.
+  pub fn from_options(opts: &LinkOptions) -> Result<Self> {
+    let mut set = DylibSet::new();
+    set.dylib_files = opts.dylib_files.iter().cloned().collect();
+    set.syslibroots = opts.effective_syslibroots().to_vec();
+    set.limits = opts.parse_limits();
+    for path in opts.input_paths.iter() {
+      if search::is_dylib_path(path) {
+        set.link(path, Linkage::Normal)?;
+      }
+    }
+    for path in opts.dylibs.iter() {
+      set.link(path, Linkage::Normal)?;
+    }
+    for name in opts.libraries.iter() {
+      set.link_library(opts, name, Linkage::Normal)?;
+    }
+    for name in opts.frameworks.iter() {
+      set.link_framework(opts, name, Linkage::Normal)?;
+    }
+    let linkages = [
+      (&opts.weak_libraries, Linkage::Weak),
+      (&opts.upward_libraries, Linkage::Upward),
+      (&opts.reexport_libraries, Linkage::Reexport),
+    ];
+    for &(paths, linkage) in linkages.iter() {
+      for path in paths.iter() {
+        set.link(path, linkage)?;
+      }
+    }
+    let library_linkages = [
+      (&opts.weak_library_names, Linkage::Weak),
+      (&opts.upward_library_names, Linkage::Upward),
+      (&opts.reexported_library_names, Linkage::Reexport),
+    ];
+    for &(names, linkage) in library_linkages.iter() {
+      for name in names.iter() {
+        set.link_library(opts, name, linkage)?;
+      }
+    }
+    for name in opts.weak_frameworks.iter() {
+      set.link_framework(opts, name, Linkage::Weak)?;
+    }
+    for name in opts.upward_frameworks.iter() {
+      set.link_framework(opts, name, Linkage::Upward)?;
+    }
+    for name in opts.sub_libraries.iter() {
+      set.reexport_by_leaf_name(name)?;
+    }
+    if let Some(ref loader) = opts.bundle_loader {
+      set.set_bundle_loader(loader)?;
+    }
+    set.check_platforms(&opts.target())?;
+    if opts.trace_files {
+      for dylib in set.loaded.iter() {
+        println!("{}", dylib.path.display());
+      }
+    }
+    if opts.trace_dylibs {
+      for dylib in set.loaded.iter() {
+        ld_env::trace(opts, "dynamic library", &dylib.path);
+      }
+    }
+    set.check_clients(
+      &client_name(opts),
+      opts.umbrella.as_ref().map(|s| s.as_str()),
+    )?;
+    if opts.application_extension {
+      set.check_extension_safe()?;
+    }
+    for warning in set.runtime_path_warnings(opts) {
+      diagnostics::warning(&warning);
+    }
+    Ok(set)
+  }
+}
+
+// Header flags for a dynamically linked output of the given kind. dyld
+// skips re-export processing for dylibs marked MH_NO_REEXPORTED_DYLIBS, and
+// clients drop unused dylibs marked MH_DEAD_STRIPPABLE_DYLIB.
+pub fn header_flags(opts: &LinkOptions, set: &DylibSet) -> u32 {
+  let mut flags = MH_DYLDLINK | MH_TWOLEVEL;
+  if opts.output_kind == OutputKind::Dylib && !set.has_reexports() {
+    flags |= MH_NO_REEXPORTED_DYLIBS;
+  }
+  if opts.output_kind == OutputKind::Dylib && opts.mark_dead_strippable_dylib
+  {
+    flags |= MH_DEAD_STRIPPABLE_DYLIB;
+  }
+  if opts.application_extension {
+    flags |= MH_APP_EXTENSION_SAFE;
+  }
+  flags
+}
+
+// Add the output's own dylib commands and those for its dependencies to
+// `image`, along with the matching header flags, the space to leave for
+// changing their install names later, whether the shared cache builder
+// needs LC_SEGMENT_SPLIT_INFO and whether to leave room for encryption.
+pub fn add_to_image(image: &mut Image, opts: &LinkOptions, set: &DylibSet) {
+  let is_dylib = opts.output_kind == OutputKind::Dylib;
+  image.flags |= header_flags(opts, set);
+  image.headerpad = opts.headerpad_size(set.direct_count() + is_dylib as usize);
+  image.load_commands.extend(load_commands(opts, is_dylib));
+  image.load_commands.extend(set.load_commands());
+  image.split_seg_info = is_dylib && opts.shared_cache_eligible;
+  image.encryption_info = opts.use_encryption_info();
+}
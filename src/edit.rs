@@ -0,0 +1,345 @@
+// `mold edit`: install_name_tool's changes to a linked image, made in
+// place.
+//
+//   -id <name>              the install name of a dylib
+//   -change <old> <new>     a dylib it links against
+//   -add_rpath <path>
+//   -delete_rpath <path>
+//   -rpath <old> <new>
+//   -add_load_command <cmd> <file>
+//                           a command numbered <cmd> (in hex), with the
+//                           contents of <file> after its cmd and cmdsize
+//   -o <output>             write the result here rather than in place
+//
+// Commands which aren't changed are kept as they are, including ones this
+// doesn't know. Changed commands are written again, and the load commands
+// after them move
+// to make room; nothing refers to a load command by its offset, and the
+// strings in dylib and rpath commands are at offsets from the start of
+// their own command. The load commands can grow into the padding before
+// the first section (see -headerpad), but no further. An ad-hoc signature
+// is redone afterwards.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use codesign;
+use diagnostics;
+use macho::dylib_file::lc_str;
+use macho::header::{header_size, parse_magic};
+use macho::image_file::ImageFile;
+use macho::load_command::{load_commands, parse_platform, LoadCommand,
+                          LC_BUILD_VERSION, LC_CODE_SIGNATURE, LC_ID_DYLIB,
+                          LC_LOAD_DYLIB, LC_LOAD_UPWARD_DYLIB,
+                          LC_LOAD_WEAK_DYLIB, LC_REEXPORT_DYLIB, LC_RPATH,
+                          LC_VERSION_MIN_IPHONEOS, LC_VERSION_MIN_MACOSX,
+                          LC_VERSION_MIN_TVOS, LC_VERSION_MIN_WATCHOS};
+use macho::{get_u32, set_u32, MachOError, Platform, Version};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+  Id(String),
+  Change(String, String),
+  AddRpath(String),
+  DeleteRpath(String),
+  Rpath(String, String),
+  // An LC_BUILD_VERSION or LC_VERSION_MIN_* command to replace all of the
+  // image's existing ones with; several of these make a zippered image.
+  SetVersion(LoadCommand),
+  RemoveVersion(Platform),
+  // A command to add after the existing ones.
+  AddCommand(LoadCommand),
+}
+
+#[derive(Debug)]
+pub enum EditError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  Usage(String),
+  // -id on something without an install name.
+  NotADylib(PathBuf),
+  NoSuchRpath(PathBuf, String),
+  DuplicateRpath(PathBuf, String),
+  NoSuchPlatform(PathBuf, Platform),
+  // (path, bytes of load commands needed, bytes there's room for)
+  NoRoom(PathBuf, usize, usize),
+}
+
+pub type Result<T> = ::std::result::Result<T, EditError>;
+
+fn is_dylib_reference(cmd: u32) -> bool {
+  match cmd {
+    LC_LOAD_DYLIB | LC_LOAD_WEAK_DYLIB | LC_REEXPORT_DYLIB
+    | LC_LOAD_UPWARD_DYLIB => true,
+    _ => false,
+  }
+}
+
+fn is_version_command(cmd: u32) -> bool {
+  match cmd {
+    LC_BUILD_VERSION | LC_VERSION_MIN_MACOSX | LC_VERSION_MIN_IPHONEOS
+    | LC_VERSION_MIN_TVOS | LC_VERSION_MIN_WATCHOS => true,
+    _ => false,
+  }
+}
+
+// Where the load commands have to end: the file offset of the first section
+// with contents, or of the first segment after the header if no section
+// has any.
+fn load_command_limit(data: &[u8]) -> ::macho::Result<usize> {
+  let image = ImageFile::parse(data)?;
+  let sections = image
+    .segments
+    .iter()
+    .flat_map(|seg| seg.sections.iter())
+    .filter(|sect| sect.offset != 0)
+    .map(|sect| sect.offset as usize);
+  let segments = image
+    .segments
+    .iter()
+    .filter(|seg| seg.fileoff != 0 && seg.filesize != 0)
+    .map(|seg| seg.fileoff as usize);
+  Ok(sections.chain(segments).min().unwrap_or(data.len()))
+}
+
+fn rpath_command(path: &str) -> Vec<u8> {
+  let mut buf: Vec<u8> = Vec::new();
+  LoadCommand::Rpath {
+    path: path.to_string(),
+  }.write(&mut buf);
+  buf
+}
+
+// The dylib command at `offset` again, with `name` for its name.
+fn renamed_dylib(
+  data: &[u8],
+  offset: usize,
+  cmd: u32,
+  name: &str,
+) -> ::macho::Result<Vec<u8>> {
+  let what = "dylib_command";
+  let mut buf: Vec<u8> = Vec::new();
+  LoadCommand::Dylib {
+    cmd: cmd,
+    name: name.to_string(),
+    timestamp: get_u32(data, offset + 12, what)?,
+    current_version: Version(get_u32(data, offset + 16, what)?),
+    compatibility_version: Version(get_u32(data, offset + 20, what)?),
+  }.write(&mut buf);
+  Ok(buf)
+}
+
+// Make `edits` to the image at `path`, whose contents are `data`.
+pub fn edit_image(path: &Path, data: &mut [u8], edits: &[Edit]) -> Result<()> {
+  let malformed = |e| EditError::Malformed(path.to_path_buf(), e);
+  let is_64_bit = parse_magic(data).map_err(malformed)?;
+  let start = header_size(is_64_bit) as usize;
+  let limit = load_command_limit(data).map_err(malformed)?;
+  let commands = load_commands(data).map_err(malformed)?;
+  let old_end = commands
+    .last()
+    .map_or(start, |last| last.offset + last.cmdsize as usize);
+
+  let mut rpaths: Vec<String> = Vec::new();
+  let mut platforms: Vec<Platform> = Vec::new();
+  let mut has_id = false;
+  let mut signed = false;
+  let mut rewritten: Vec<u8> = Vec::new();
+  let mut ncmds: u32 = 0;
+  for header in commands.iter() {
+    let offset = header.offset;
+    let cmdsize = header.cmdsize as usize;
+    let original = &data[offset..(offset + cmdsize)];
+    let name = match header.cmd {
+      LC_ID_DYLIB | LC_RPATH => true,
+      cmd => is_dylib_reference(cmd),
+    };
+    let name = if name {
+      let name_offset =
+        get_u32(data, offset + 8, "load command").map_err(malformed)?;
+      Some(lc_str(data, offset, cmdsize, name_offset as usize)
+        .map_err(malformed)?)
+    } else {
+      None
+    };
+    let platform = parse_platform(data, offset)
+      .map_err(malformed)?
+      .map(|(platform, _)| platform);
+    signed |= header.cmd == LC_CODE_SIGNATURE;
+
+    let mut replacement: Option<Vec<u8>> = None;
+    let mut deleted = false;
+    for edit in edits.iter() {
+      match (header.cmd, edit, name.as_ref()) {
+        (LC_ID_DYLIB, &Edit::Id(ref new), _) => {
+          let buf = renamed_dylib(data, offset, header.cmd, new)
+            .map_err(malformed)?;
+          replacement = Some(buf);
+        }
+        (cmd, &Edit::Change(ref old, ref new), Some(name))
+          if is_dylib_reference(cmd) && name == old =>
+        {
+          let buf =
+            renamed_dylib(data, offset, cmd, new).map_err(malformed)?;
+          replacement = Some(buf);
+        }
+        (LC_RPATH, &Edit::DeleteRpath(ref old), Some(name)) if name == old => {
+          deleted = true;
+        }
+        (LC_RPATH, &Edit::Rpath(ref old, ref new), Some(name))
+          if name == old =>
+        {
+          replacement = Some(rpath_command(new));
+        }
+        (cmd, &Edit::SetVersion(_), _) if is_version_command(cmd) => {
+          deleted = true;
+        }
+        (_, &Edit::RemoveVersion(removed), _) if platform == Some(removed) => {
+          deleted = true;
+        }
+        _ => (),
+      }
+    }
+    platforms.extend(platform);
+    if header.cmd == LC_ID_DYLIB {
+      has_id = true;
+    }
+    if header.cmd == LC_RPATH {
+      if let Some(name) = name {
+        rpaths.push(name);
+      }
+    }
+    if deleted {
+      continue;
+    }
+    match replacement {
+      Some(buf) => rewritten.extend_from_slice(&buf),
+      None => rewritten.extend_from_slice(original),
+    }
+    ncmds += 1;
+  }
+
+  for edit in edits.iter() {
+    match *edit {
+      Edit::Id(_) if !has_id => {
+        return Err(EditError::NotADylib(path.to_path_buf()));
+      }
+      Edit::DeleteRpath(ref old) | Edit::Rpath(ref old, _)
+        if !rpaths.contains(old) =>
+      {
+        return Err(EditError::NoSuchRpath(path.to_path_buf(), old.clone()));
+      }
+      Edit::AddRpath(ref new) => {
+        if rpaths.contains(new) {
+          let new = new.clone();
+          return Err(EditError::DuplicateRpath(path.to_path_buf(), new));
+        }
+        rpaths.push(new.clone());
+        rewritten.extend_from_slice(&rpath_command(new));
+        ncmds += 1;
+      }
+      Edit::SetVersion(ref command) | Edit::AddCommand(ref command) => {
+        command.write(&mut rewritten);
+        ncmds += 1;
+      }
+      Edit::RemoveVersion(platform) if !platforms.contains(&platform) => {
+        return Err(EditError::NoSuchPlatform(path.to_path_buf(), platform));
+      }
+      _ => (),
+    }
+  }
+
+  if start + rewritten.len() > limit {
+    return Err(EditError::NoRoom(
+      path.to_path_buf(),
+      rewritten.len(),
+      limit.saturating_sub(start),
+    ));
+  }
+  let new_end = start + rewritten.len();
+  data[start..new_end].copy_from_slice(&rewritten);
+  for b in data[new_end..old_end.max(new_end)].iter_mut() {
+    *b = 0;
+  }
+  set_u32(data, 16, ncmds);
+  set_u32(data, 20, rewritten.len() as u32);
+
+  // Only ad-hoc signatures can be redone here.
+  if signed && !codesign::resign(data).map_err(malformed)? {
+    diagnostics::warning(&format!(
+      "{}: editing invalidated the code signature; sign it again",
+      path.display()
+    ));
+  }
+  Ok(())
+}
+
+// `mold edit [edits] [-o <output>] <input>`.
+pub fn run_command(args: &[String]) -> Result<()> {
+  let mut edits: Vec<Edit> = Vec::new();
+  let mut output: Option<PathBuf> = None;
+  let mut input: Option<PathBuf> = None;
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    let mut value = || {
+      args
+        .next()
+        .cloned()
+        .ok_or_else(|| EditError::Usage(format!("{} needs a value", arg)))
+    };
+    match arg.as_str() {
+      "-id" => edits.push(Edit::Id(value()?)),
+      "-change" => {
+        let old = value()?;
+        edits.push(Edit::Change(old, value()?));
+      }
+      "-add_rpath" => edits.push(Edit::AddRpath(value()?)),
+      "-delete_rpath" => edits.push(Edit::DeleteRpath(value()?)),
+      "-rpath" => {
+        let old = value()?;
+        edits.push(Edit::Rpath(old, value()?));
+      }
+      "-add_load_command" => {
+        let val = value()?;
+        let digits = val.trim_start_matches("0x").trim_start_matches("0X");
+        let cmd = u32::from_str_radix(digits, 16).map_err(|_| {
+          EditError::Usage(format!("invalid load command {}", val))
+        })?;
+        let path = PathBuf::from(value()?);
+        let body =
+          fs::read(&path).map_err(|e| EditError::IoError(path.clone(), e))?;
+        edits.push(Edit::AddCommand(LoadCommand::Raw {
+          cmd: cmd,
+          body: body,
+        }));
+      }
+      "-o" => output = Some(PathBuf::from(value()?)),
+      _ if arg.starts_with('-') => {
+        return Err(EditError::Usage(format!("unknown option {}", arg)));
+      }
+      _ if input.is_some() => {
+        return Err(EditError::Usage("only one input is allowed".to_string()));
+      }
+      _ => input = Some(PathBuf::from(arg)),
+    }
+  }
+  let input =
+    input.ok_or_else(|| EditError::Usage("no input file".to_string()))?;
+  if edits.is_empty() {
+    return Err(EditError::Usage("nothing to change".to_string()));
+  }
+
+  let output = output.unwrap_or_else(|| input.clone());
+  edit_file(&input, &output, &edits)
+}
+
+// Make `edits` to `input`, writing the result to `output`, which may be the
+// same file.
+pub fn edit_file(input: &Path, output: &Path, edits: &[Edit]) -> Result<()> {
+  let mut data =
+    fs::read(input).map_err(|e| EditError::IoError(input.to_path_buf(), e))?;
+  edit_image(input, &mut data, edits)?;
+  fs::write(output, &data)
+    .map_err(|e| EditError::IoError(output.to_path_buf(), e))
+}
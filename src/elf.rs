@@ -0,0 +1,410 @@
+// ELF relocatable objects as inputs to -r links: BFD reads them (when it is
+// built with the ELF targets), and their sections, symbols and relocations
+// are translated into a Mach-O object wherever the two formats mean the
+// same thing, for x86_64 and arm64. C names gain the leading underscore
+// Mach-O gives them.
+//
+// What Mach-O has no equivalent for is an error: ELF TLS, .init_array
+// priorities, and relocations other than the ones compilers emit for plain
+// code and data. Unwind tables (.eh_frame) and debug info are dropped.
+
+use std::path::Path;
+
+use bfd::{self, BFDError, ForeignObject, ForeignReloc, ForeignSection,
+          ForeignSymbolKind, RelocTarget, BSF_DEBUGGING, BSF_FILE,
+          BSF_GLOBAL, BSF_SECTION_SYM, BSF_WEAK, SEC_ALLOC, SEC_CODE,
+          SEC_HAS_CONTENTS, SEC_MERGE, SEC_READONLY, SEC_STRINGS,
+          SEC_THREAD_LOCAL};
+use macho::object_file::{ObjectFile, ObjectSection};
+use macho::reloc::{RelocationInfo, ARM64_RELOC_ADDEND, ARM64_RELOC_BRANCH26,
+                   ARM64_RELOC_GOT_LOAD_PAGE21,
+                   ARM64_RELOC_GOT_LOAD_PAGEOFF12, ARM64_RELOC_PAGE21,
+                   ARM64_RELOC_PAGEOFF12, ARM64_RELOC_UNSIGNED,
+                   X86_64_RELOC_BRANCH, X86_64_RELOC_GOT,
+                   X86_64_RELOC_GOT_LOAD, X86_64_RELOC_SIGNED,
+                   X86_64_RELOC_UNSIGNED};
+use macho::symtab::{Nlist, N_ABS, N_EXT, N_SECT, N_UNDF, N_WEAK_DEF,
+                    N_WEAK_REF};
+use macho::{round_up, set_u32, set_u64, Arch, S_ATTR_PURE_INSTRUCTIONS,
+            S_ATTR_SOME_INSTRUCTIONS, S_CSTRING_LITERALS,
+            S_MOD_INIT_FUNC_POINTERS, S_MOD_TERM_FUNC_POINTERS, S_REGULAR,
+            S_ZEROFILL};
+
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+
+const R_X86_64_64: u32 = 1;
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+const R_X86_64_GOTPCREL: u32 = 9;
+const R_X86_64_GOTPCRELX: u32 = 41;
+const R_X86_64_REX_GOTPCRELX: u32 = 42;
+
+const R_AARCH64_ABS64: u32 = 257;
+const R_AARCH64_ADR_PREL_PG_HI21: u32 = 275;
+const R_AARCH64_ADD_ABS_LO12_NC: u32 = 277;
+const R_AARCH64_LDST8_ABS_LO12_NC: u32 = 278;
+const R_AARCH64_JUMP26: u32 = 282;
+const R_AARCH64_CALL26: u32 = 283;
+const R_AARCH64_LDST16_ABS_LO12_NC: u32 = 284;
+const R_AARCH64_LDST32_ABS_LO12_NC: u32 = 285;
+const R_AARCH64_LDST64_ABS_LO12_NC: u32 = 286;
+const R_AARCH64_LDST128_ABS_LO12_NC: u32 = 299;
+const R_AARCH64_ADR_GOT_PAGE: u32 = 311;
+const R_AARCH64_LD64_GOT_LO12_NC: u32 = 312;
+
+#[derive(Debug)]
+pub enum ElfError {
+  Bfd(BFDError),
+  // The e_machine of an object which isn't for -arch (or isn't a 64-bit
+  // little-endian object at all).
+  WrongMachine(u16),
+  // BFD reads ELF inputs from their files, so they can't be given as
+  // buffers.
+  InMemory,
+  // (section name) of a section with no Mach-O equivalent.
+  UnsupportedSection(String),
+  // (section name, r_type) of a relocation with no Mach-O equivalent.
+  UnsupportedRelocation(String, u32),
+  // (name) of a symbol in a section which isn't translated.
+  UnsupportedSymbol(String),
+  // Mach-O section ordinals are 8 bits.
+  TooManySections,
+}
+
+pub type Result<T> = ::std::result::Result<T, ElfError>;
+
+pub fn is_elf(data: &[u8]) -> bool {
+  data.starts_with(b"\x7fELF")
+}
+
+// The e_machine of a 64-bit little-endian object.
+fn machine(data: &[u8]) -> Option<u16> {
+  // EI_CLASS is ELFCLASS64 and EI_DATA is ELFDATA2LSB.
+  if data.len() < 20 || data[4] != 2 || data[5] != 1 {
+    return None;
+  }
+  Some(data[18] as u16 | (data[19] as u16) << 8)
+}
+
+// Read the ELF object at `path`, whose contents are `data`, as a Mach-O
+// object for `arch`.
+pub fn read_object(path: &Path, data: &[u8], arch: Arch) -> Result<ObjectFile> {
+  let machine = machine(data).unwrap_or(0);
+  let expected = match arch {
+    Arch::X86_64 => EM_X86_64,
+    Arch::Arm64 => EM_AARCH64,
+    _ => return Err(ElfError::WrongMachine(machine)),
+  };
+  if machine != expected {
+    return Err(ElfError::WrongMachine(machine));
+  }
+  let object = bfd::read_foreign_object(path).map_err(ElfError::Bfd)?;
+  translate(&object, arch)
+}
+
+// Where an ELF section goes: its Mach-O segment, section and flags, or None
+// for a section which is dropped.
+fn macho_section(
+  sect: &ForeignSection,
+) -> Result<Option<(&'static str, &'static str, u32)>> {
+  let name = sect.name.as_str();
+  let flags = sect.flags;
+  // Notes, comments and debug info aren't loaded; Mach-O unwind info comes
+  // from the compiler's __compact_unwind, which ELF objects don't have.
+  if flags & SEC_ALLOC == 0 || name == ".eh_frame" {
+    return Ok(None);
+  }
+  let unsupported = || ElfError::UnsupportedSection(name.to_string());
+  if flags & SEC_THREAD_LOCAL != 0 {
+    return Err(unsupported());
+  }
+  let strings = SEC_MERGE | SEC_STRINGS;
+  let mapped = match name {
+    ".init_array" => ("__DATA", "__mod_init_func", S_MOD_INIT_FUNC_POINTERS),
+    ".fini_array" => ("__DATA", "__mod_term_func", S_MOD_TERM_FUNC_POINTERS),
+    // Constructors with priorities, which Mach-O doesn't order by.
+    _ if name.starts_with(".init_array.")
+      || name.starts_with(".fini_array.")
+      || name.starts_with(".ctors")
+      || name.starts_with(".dtors") =>
+    {
+      return Err(unsupported())
+    }
+    _ if flags & SEC_CODE != 0 => (
+      "__TEXT",
+      "__text",
+      S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS,
+    ),
+    _ if flags & SEC_HAS_CONTENTS == 0 => ("__DATA", "__bss", S_ZEROFILL),
+    _ if flags & strings == strings => {
+      ("__TEXT", "__cstring", S_CSTRING_LITERALS)
+    }
+    _ if flags & SEC_READONLY != 0 => ("__TEXT", "__const", S_REGULAR),
+    // Data which is only written by relocations.
+    _ if name.starts_with(".data.rel.ro") => ("__DATA", "__const", S_REGULAR),
+    _ => ("__DATA", "__data", S_REGULAR),
+  };
+  Ok(Some(mapped))
+}
+
+// The Mach-O relocations (more than one on arm64, for an addend) for an
+// ELF one against symbol index `symbolnum`, writing x86_64's addends into
+// the section's contents.
+fn relocation(
+  arch: Arch,
+  sect: &mut ObjectSection,
+  name: &str,
+  reloc: &ForeignReloc,
+  symbolnum: u32,
+) -> Result<Vec<RelocationInfo>> {
+  let unsupported =
+    || ElfError::UnsupportedRelocation(name.to_string(), reloc.kind);
+  let address = reloc.address as usize;
+  let (kind, pcrel, length) = match (arch, reloc.kind) {
+    (Arch::X86_64, R_X86_64_64) => (X86_64_RELOC_UNSIGNED, false, 3),
+    // The fixup is S + A - P, where Mach-O's is S + addend - (P + 4).
+    (Arch::X86_64, R_X86_64_PLT32) if reloc.addend == -4 => {
+      (X86_64_RELOC_BRANCH, true, 2)
+    }
+    (Arch::X86_64, R_X86_64_PC32) | (Arch::X86_64, R_X86_64_PLT32) => {
+      (X86_64_RELOC_SIGNED, true, 2)
+    }
+    (Arch::X86_64, R_X86_64_REX_GOTPCRELX) if reloc.addend == -4 => {
+      (X86_64_RELOC_GOT_LOAD, true, 2)
+    }
+    (Arch::X86_64, R_X86_64_GOTPCREL)
+    | (Arch::X86_64, R_X86_64_GOTPCRELX)
+    | (Arch::X86_64, R_X86_64_REX_GOTPCRELX) => (X86_64_RELOC_GOT, true, 2),
+    (Arch::Arm64, R_AARCH64_ABS64) => (ARM64_RELOC_UNSIGNED, false, 3),
+    (Arch::Arm64, R_AARCH64_CALL26) | (Arch::Arm64, R_AARCH64_JUMP26) => {
+      (ARM64_RELOC_BRANCH26, true, 2)
+    }
+    (Arch::Arm64, R_AARCH64_ADR_PREL_PG_HI21) => (ARM64_RELOC_PAGE21, true, 2),
+    (Arch::Arm64, R_AARCH64_ADD_ABS_LO12_NC)
+    | (Arch::Arm64, R_AARCH64_LDST8_ABS_LO12_NC)
+    | (Arch::Arm64, R_AARCH64_LDST16_ABS_LO12_NC)
+    | (Arch::Arm64, R_AARCH64_LDST32_ABS_LO12_NC)
+    | (Arch::Arm64, R_AARCH64_LDST64_ABS_LO12_NC)
+    | (Arch::Arm64, R_AARCH64_LDST128_ABS_LO12_NC) => {
+      (ARM64_RELOC_PAGEOFF12, false, 2)
+    }
+    (Arch::Arm64, R_AARCH64_ADR_GOT_PAGE) if reloc.addend == 0 => {
+      (ARM64_RELOC_GOT_LOAD_PAGE21, true, 2)
+    }
+    (Arch::Arm64, R_AARCH64_LD64_GOT_LO12_NC) if reloc.addend == 0 => {
+      (ARM64_RELOC_GOT_LOAD_PAGEOFF12, false, 2)
+    }
+    _ => return Err(unsupported()),
+  };
+  if address + (1 << length) > sect.contents.len() {
+    return Err(unsupported());
+  }
+  let macho_reloc = RelocationInfo {
+    address: address as u32,
+    symbolnum: symbolnum,
+    pcrel: pcrel,
+    length: length,
+    external: true,
+    kind: kind,
+    scattered: None,
+  };
+  let mut relocs: Vec<RelocationInfo> = Vec::new();
+  let addend = reloc.addend;
+  match arch {
+    // x86_64's addends are in the contents.
+    Arch::X86_64 if length == 3 => {
+      set_u64(&mut sect.contents, address, addend as u64);
+    }
+    Arch::X86_64 => {
+      set_u32(&mut sect.contents, address, (addend + 4) as u32);
+    }
+    Arch::Arm64 if kind == ARM64_RELOC_UNSIGNED => {
+      set_u64(&mut sect.contents, address, addend as u64);
+    }
+    // arm64 instructions take theirs from an ARM64_RELOC_ADDEND before
+    // the relocation, with 24 bits.
+    Arch::Arm64 if addend != 0 => {
+      if !(-(1 << 23)..1 << 23).contains(&addend) {
+        return Err(unsupported());
+      }
+      relocs.push(RelocationInfo {
+        symbolnum: addend as u32 & 0x00ff_ffff,
+        pcrel: false,
+        external: false,
+        kind: ARM64_RELOC_ADDEND,
+        ..macho_reloc
+      });
+    }
+    _ => (),
+  }
+  relocs.push(macho_reloc);
+  Ok(relocs)
+}
+
+// The Mach-O object meaning what `object` does, for `arch`.
+pub fn translate(object: &ForeignObject, arch: Arch) -> Result<ObjectFile> {
+  // Each ELF section's Mach-O ordinal, if it's kept.
+  let mut ordinals: Vec<Option<u8>> = Vec::new();
+  let mut sections: Vec<ObjectSection> = Vec::new();
+  let mut addr: u64 = 0;
+  for sect in object.sections.iter() {
+    let (segname, sectname, flags) = match macho_section(sect)? {
+      Some(mapped) => mapped,
+      None => {
+        ordinals.push(None);
+        continue;
+      }
+    };
+    if sections.len() == 255 {
+      return Err(ElfError::TooManySections);
+    }
+    addr = round_up(addr, 1 << sect.alignment_power);
+    let zerofill = flags == S_ZEROFILL;
+    sections.push(ObjectSection {
+      segname: segname.to_string(),
+      sectname: sectname.to_string(),
+      addr: addr,
+      size: sect.size,
+      align: sect.alignment_power,
+      flags: flags,
+      contents: if zerofill {
+        Vec::new()
+      } else {
+        sect.contents.clone()
+      },
+      ..Default::default()
+    });
+    addr += sect.size;
+    ordinals.push(Some(sections.len() as u8));
+  }
+
+  // Mach-O lists local symbols, then defined external ones, then undefined
+  // ones. The locals start with one at each section's start (as clang's
+  // ltmp<n> are on arm64), for relocations against ELF section symbols.
+  let mut groups: [Vec<(Option<usize>, Nlist)>; 3] =
+    [Vec::new(), Vec::new(), Vec::new()];
+  for (i, sect) in sections.iter().enumerate() {
+    groups[0].push((
+      None,
+      Nlist {
+        name: format!("ltmp{}", i),
+        n_type: N_SECT,
+        n_sect: i as u8 + 1,
+        n_desc: 0,
+        n_value: sect.addr,
+      },
+    ));
+  }
+  for (i, sym) in object.symbols.iter().enumerate() {
+    if sym.flags & (BSF_SECTION_SYM | BSF_FILE | BSF_DEBUGGING) != 0 {
+      continue;
+    }
+    let external = sym.flags & (BSF_GLOBAL | BSF_WEAK) != 0;
+    let weak = sym.flags & BSF_WEAK != 0;
+    let ext = if external { N_EXT } else { 0 };
+    let mut nlist = Nlist {
+      name: format!("_{}", sym.name),
+      n_type: 0,
+      n_sect: 0,
+      n_desc: 0,
+      n_value: sym.value,
+    };
+    let group = match sym.kind {
+      ForeignSymbolKind::Defined(idx) => match ordinals[idx] {
+        Some(ordinal) => {
+          nlist.n_type = N_SECT | ext;
+          nlist.n_sect = ordinal;
+          nlist.n_value += sections[ordinal as usize - 1].addr;
+          if weak {
+            nlist.n_desc = N_WEAK_DEF;
+          }
+          if external {
+            1
+          } else {
+            0
+          }
+        }
+        // Labels in dropped sections (.eh_frame's, say) go with them.
+        None if !external => continue,
+        None => return Err(ElfError::UnsupportedSymbol(sym.name.clone())),
+      },
+      ForeignSymbolKind::Absolute => {
+        nlist.n_type = N_ABS | ext;
+        if external {
+          1
+        } else {
+          0
+        }
+      }
+      ForeignSymbolKind::Undefined => {
+        nlist.n_type = N_UNDF | N_EXT;
+        if weak {
+          nlist.n_desc = N_WEAK_REF;
+        }
+        2
+      }
+      // A common symbol's value is its size, as in Mach-O.
+      ForeignSymbolKind::Common => {
+        nlist.n_type = N_UNDF | N_EXT;
+        2
+      }
+    };
+    groups[group].push((Some(i), nlist));
+  }
+  // The Mach-O index of each ELF symbol, if it's kept.
+  let mut indices: Vec<Option<u32>> = vec![None; object.symbols.len()];
+  let mut symbols: Vec<Nlist> = Vec::new();
+  for group in groups.iter() {
+    for &(elf_idx, ref nlist) in group.iter() {
+      if let Some(elf_idx) = elf_idx {
+        indices[elf_idx] = Some(symbols.len() as u32);
+      }
+      symbols.push(nlist.clone());
+    }
+  }
+
+  for (sect, ordinal) in object.sections.iter().zip(ordinals.iter()) {
+    let out = match *ordinal {
+      Some(ordinal) => &mut sections[ordinal as usize - 1],
+      None => continue,
+    };
+    for reloc in sect.relocs.iter() {
+      let unsupported =
+        || ElfError::UnsupportedRelocation(sect.name.clone(), reloc.kind);
+      // Section symbols are the ltmp<n> at the start of their section,
+      // which are the first symbols.
+      let section_start = |idx: usize| {
+        ordinals[idx].map(|ordinal| ordinal as u32 - 1)
+      };
+      let symbolnum = match reloc.target {
+        RelocTarget::Section(idx) => section_start(idx),
+        RelocTarget::Symbol(idx) => {
+          let sym = &object.symbols[idx];
+          match sym.kind {
+            ForeignSymbolKind::Defined(sect_idx)
+              if sym.flags & BSF_SECTION_SYM != 0 =>
+            {
+              section_start(sect_idx)
+            }
+            _ => indices[idx],
+          }
+        }
+      };
+      let symbolnum = symbolnum.ok_or_else(unsupported)?;
+      let relocs = relocation(arch, out, &sect.name, reloc, symbolnum)?;
+      out.relocs.extend(relocs);
+    }
+  }
+
+  Ok(ObjectFile {
+    cputype: arch.cputype(),
+    cpusubtype: arch.cpusubtype(),
+    flags: 0,
+    sections: sections,
+    symbols: symbols,
+    platform: None,
+    loh: Vec::new(),
+    data_in_code: Vec::new(),
+  })
+}
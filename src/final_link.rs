@@ -0,0 +1,4911 @@
+// Final links: executables, dylibs and bundles, which dyld loads. The inputs
+// are merged as for -r and the merged sections laid out in an Image. Calls
+// to functions in dylibs go through stubs, and other references to them
+// through the GOT. dyld binds those pointers, and any others to dylib
+// symbols, and slides the ones into the image itself, as the chained fixups
+// or the dyld info opcodes tell it to.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use args::{LinkOptions, OutputKind};
+use codesign;
+use dtrace::{self, DtraceError, ProbeSite};
+use dylib::{self, DylibError, DylibSet};
+use image::{Image, ImageError, LinkeditBlob, OutputSection,
+            SectionReference};
+use initializers::{InitError, InitFormat, Initializers, InputInitializers};
+use kext::{self, KextFixup};
+use literals::{self, LiteralError};
+use macho::chained_fixups::{self, apply_chains, ChainedFixupsBuilder, Fixup,
+                            PointerFormat, SegmentFixups};
+use macho::data_in_code::DataInCodeEntry;
+use macho::dyld_info::{encode_bind, encode_rebase, encode_weak_bind, Binding,
+                       WeakBinding, BIND_SPECIAL_DYLIB_FLAT_LOOKUP,
+                       BIND_SPECIAL_DYLIB_SELF,
+                       EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE,
+                       EXPORT_SYMBOL_FLAGS_KIND_REGULAR,
+                       EXPORT_SYMBOL_FLAGS_WEAK_DEFINITION};
+use macho::export_trie::{Export, ExportKind, ExportTrie};
+use macho::load_command::{LoadCommand, LC_DYLD_CHAINED_FIXUPS,
+                          LC_DYLD_EXPORTS_TRIE, LC_DYLD_INFO_ONLY,
+                          LC_DYSYMTAB, LC_SEGMENT_SPLIT_INFO, LC_SYMTAB};
+use macho::loh::Loh;
+use macho::object_file::{ObjectFile, ObjectSection};
+use macho::reloc::{encode_adrp, encode_branch26, encode_pageoff12, read_u32,
+                   write_rel32, write_u32, RelocError,
+                   ARM64_RELOC_ADDEND, ARM64_RELOC_AUTHENTICATED_POINTER,
+                   ARM64_RELOC_BRANCH26,
+                   ARM64_RELOC_GOT_LOAD_PAGE21,
+                   ARM64_RELOC_GOT_LOAD_PAGEOFF12, ARM64_RELOC_PAGE21,
+                   ARM64_RELOC_PAGEOFF12, ARM64_RELOC_POINTER_TO_GOT,
+                   ARM64_RELOC_SUBTRACTOR, ARM64_RELOC_TLVP_LOAD_PAGE21,
+                   ARM64_RELOC_TLVP_LOAD_PAGEOFF12, ARM64_RELOC_UNSIGNED,
+                   X86_64_RELOC_BRANCH, X86_64_RELOC_GOT,
+                   X86_64_RELOC_GOT_LOAD, X86_64_RELOC_SIGNED,
+                   X86_64_RELOC_SIGNED_1, X86_64_RELOC_SIGNED_2,
+                   X86_64_RELOC_SIGNED_4, X86_64_RELOC_SUBTRACTOR,
+                   X86_64_RELOC_TLV, X86_64_RELOC_UNSIGNED};
+use macho::split_seg::{DYLD_CACHE_ADJ_V2_ARM64_ADRP,
+                       DYLD_CACHE_ADJ_V2_ARM64_BR26,
+                       DYLD_CACHE_ADJ_V2_ARM64_OFF12,
+                       DYLD_CACHE_ADJ_V2_DELTA_32,
+                       DYLD_CACHE_ADJ_V2_POINTER_64};
+use macho::symtab::{Nlist, StringTable, INDIRECT_SYMBOL_LOCAL, N_ABS, N_EXT,
+                    N_FUN, N_GSYM, N_OSO, N_PEXT, N_SECT, N_SO, N_STSYM,
+                    N_TYPE, N_WEAK_DEF, N_WEAK_REF,
+                    REFERENCED_DYNAMICALLY};
+use macho::unwind_info::{self, CompactUnwindEntry};
+use macho::{round_up, set_u64, Arch, MachOError, MH_BINDS_TO_WEAK,
+            MH_NOUNDEFS, MH_WEAK_DEFINES,
+            S_ATTR_PURE_INSTRUCTIONS, S_ATTR_SOME_INSTRUCTIONS,
+            S_INIT_FUNC_OFFSETS, S_MOD_INIT_FUNC_POINTERS,
+            S_NON_LAZY_SYMBOL_POINTERS, S_SYMBOL_STUBS,
+            S_THREAD_LOCAL_REGULAR, S_THREAD_LOCAL_VARIABLES,
+            S_THREAD_LOCAL_ZEROFILL, S_ZEROFILL, SECTION_TYPE,
+            VM_PROT_WRITE};
+use output;
+use relocatable::{self, DebugInput, RelocatableError};
+use resolve::ResolveError;
+use symbol_list::SymbolList;
+use target;
+use timing;
+use tlv::{self, TLV_DESCRIPTOR_SIZE};
+use unwind::{self, UnwindError, UnwindSections};
+use uuid;
+
+#[derive(Debug)]
+pub enum FinalLinkError {
+  Relocatable(RelocatableError),
+  Dylib(DylibError),
+  Image(ImageError),
+  // Encoding the chained fixups.
+  Malformed(MachOError),
+  // What final links can't produce yet.
+  Unsupported(String),
+  // (relocation type, where it is) of one which can't be applied.
+  UnsupportedRelocation(u8, String),
+  Reloc(String, RelocError),
+  // Where a pointer dyld would have to fix up is in a segment it can't
+  // write to.
+  TextRelocation(String),
+  // The executable's entry point, which isn't defined.
+  NoEntryPoint(String),
+  // The -init symbol, which isn't defined.
+  NoInitSymbol(String),
+  Init(InitError),
+  Literal(LiteralError),
+  Resolve(ResolveError),
+  Unwind(UnwindError),
+  Dtrace(DtraceError),
+  // An -alias whose name an input already defines.
+  AliasDefined(String),
+  // Reading the export control or -alias_list files.
+  SymbolList(io::Error),
+  IoError(PathBuf, io::Error),
+}
+
+impl From<RelocatableError> for FinalLinkError {
+  fn from(error: RelocatableError) -> Self {
+    FinalLinkError::Relocatable(error)
+  }
+}
+
+impl From<DylibError> for FinalLinkError {
+  fn from(error: DylibError) -> Self {
+    FinalLinkError::Dylib(error)
+  }
+}
+
+impl From<ImageError> for FinalLinkError {
+  fn from(error: ImageError) -> Self {
+    FinalLinkError::Image(error)
+  }
+}
+
+impl From<InitError> for FinalLinkError {
+  fn from(error: InitError) -> Self {
+    FinalLinkError::Init(error)
+  }
+}
+
+impl From<LiteralError> for FinalLinkError {
+  fn from(error: LiteralError) -> Self {
+    FinalLinkError::Literal(error)
+  }
+}
+
+impl From<UnwindError> for FinalLinkError {
+  fn from(error: UnwindError) -> Self {
+    FinalLinkError::Unwind(error)
+  }
+}
+
+impl From<DtraceError> for FinalLinkError {
+  fn from(error: DtraceError) -> Self {
+    FinalLinkError::Dtrace(error)
+  }
+}
+
+impl From<MachOError> for FinalLinkError {
+  fn from(error: MachOError) -> Self {
+    FinalLinkError::Malformed(error)
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, FinalLinkError>;
+
+const DYLD_PATH: &'static str = "/usr/lib/dyld";
+const ENTRY_SYMBOL: &'static str = "_main";
+const START_SYMBOL: &'static str = "start";
+const EXECUTE_HEADER: &'static str = "__mh_execute_header";
+
+// Where an executable starts: main(), which dyld calls, or with -stack_addr
+// (or without dyld) `start`, which the kernel or loader jumps to.
+pub fn entry_symbol(opts: &LinkOptions) -> &'static str {
+  if opts.stack_addr.is_some() || !opts.uses_dyld() {
+    START_SYMBOL
+  } else {
+    ENTRY_SYMBOL
+  }
+}
+
+// Whether an output of `kind` starts running at an entry point: executables
+// and -preload images.
+pub fn has_entry_point(kind: OutputKind) -> bool {
+  kind == OutputKind::Executable || kind == OutputKind::Preload
+}
+
+// The symbol the linker defines at the mach header of an output of `kind`.
+fn header_symbol(kind: OutputKind) -> Option<&'static str> {
+  match kind {
+    OutputKind::Executable => Some(EXECUTE_HEADER),
+    OutputKind::Dylib => Some("__mh_dylib_header"),
+    OutputKind::Bundle => Some("__mh_bundle_header"),
+    _ => None,
+  }
+}
+
+// Whether the linker defines `name` itself in the output `opts` describe, so
+// references to it aren't undefined.
+pub fn is_linker_defined(opts: &LinkOptions, name: &str) -> bool {
+  opts.output_kind != OutputKind::Relocatable
+    && (name == "___dso_handle"
+      || header_symbol(opts.output_kind) == Some(name))
+}
+
+// The architectures whose relocations are applied below, which are the
+// only ones final links are made for. The rest are -r only.
+pub const ARCHS: [Arch; 3] = [Arch::X86_64, Arch::Arm64, Arch::Arm64e];
+
+// arm64e's signed pointers also need chained fixups.
+fn check_supported(opts: &LinkOptions) -> Result<()> {
+  if !ARCHS.contains(&opts.arch) {
+    Err(FinalLinkError::Unsupported(format!(
+      "final links for {}",
+      opts.arch.name()
+    )))
+  } else if opts.arch == Arch::Arm64e && !opts.use_chained_fixups() {
+    Err(FinalLinkError::Unsupported(
+      "arm64e outputs without chained fixups".to_string(),
+    ))
+  } else {
+    Ok(())
+  }
+}
+
+// Whether a relocation in __compact_unwind is for an entry's personality
+// routine. Entries are 32 bytes: the function, its length and encoding, and
+// then the personality and LSDA pointers.
+fn is_personality(address: u32) -> bool {
+  address % 32 == 16
+}
+
+// Whether a section is part of the template each thread's thread-local
+// variables are copied from: __thread_data or __thread_bss.
+fn is_tlv_template(flags: u32) -> bool {
+  match flags & SECTION_TYPE {
+    S_THREAD_LOCAL_REGULAR | S_THREAD_LOCAL_ZEROFILL => true,
+    _ => false,
+  }
+}
+
+// What a relocation asks for, whichever architecture it's for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+  Unsigned,
+  Subtractor,
+  // A 32-bit displacement from the end of the fixup.
+  Rel32,
+  // A call, through a stub if the callee is in a dylib.
+  Branch,
+  // A 32-bit displacement to the target's GOT entry.
+  GotRel32,
+  Branch26,
+  Page21,
+  PageOff12,
+  GotPage21,
+  GotPageOff12,
+  PointerToGot,
+  // A load of a thread-local variable's descriptor, through the GOT if
+  // it's in a dylib.
+  Tlv,
+  TlvPage21,
+  TlvPageOff12,
+  // The addend of the relocation which follows.
+  Addend,
+  // An arm64e pointer which dyld signs as it fixes it up.
+  AuthPointer,
+}
+
+impl Kind {
+  fn of(arch: Arch, kind: u8) -> Option<Kind> {
+    // arm64e is arm64 with signed pointers.
+    let arch = match (arch, kind) {
+      (Arch::Arm64e, ARM64_RELOC_AUTHENTICATED_POINTER) => {
+        return Some(Kind::AuthPointer)
+      }
+      (Arch::Arm64e, _) => Arch::Arm64,
+      _ => arch,
+    };
+    match (arch, kind) {
+      (Arch::X86_64, X86_64_RELOC_UNSIGNED) => Some(Kind::Unsigned),
+      (Arch::X86_64, X86_64_RELOC_SUBTRACTOR) => Some(Kind::Subtractor),
+      (Arch::X86_64, X86_64_RELOC_SIGNED)
+      | (Arch::X86_64, X86_64_RELOC_SIGNED_1)
+      | (Arch::X86_64, X86_64_RELOC_SIGNED_2)
+      | (Arch::X86_64, X86_64_RELOC_SIGNED_4) => Some(Kind::Rel32),
+      (Arch::X86_64, X86_64_RELOC_BRANCH) => Some(Kind::Branch),
+      (Arch::X86_64, X86_64_RELOC_GOT)
+      | (Arch::X86_64, X86_64_RELOC_GOT_LOAD) => Some(Kind::GotRel32),
+      (Arch::X86_64, X86_64_RELOC_TLV) => Some(Kind::Tlv),
+      (Arch::Arm64, ARM64_RELOC_UNSIGNED) => Some(Kind::Unsigned),
+      (Arch::Arm64, ARM64_RELOC_SUBTRACTOR) => Some(Kind::Subtractor),
+      (Arch::Arm64, ARM64_RELOC_BRANCH26) => Some(Kind::Branch26),
+      (Arch::Arm64, ARM64_RELOC_PAGE21) => Some(Kind::Page21),
+      (Arch::Arm64, ARM64_RELOC_PAGEOFF12) => Some(Kind::PageOff12),
+      (Arch::Arm64, ARM64_RELOC_GOT_LOAD_PAGE21) => Some(Kind::GotPage21),
+      (Arch::Arm64, ARM64_RELOC_GOT_LOAD_PAGEOFF12) => {
+        Some(Kind::GotPageOff12)
+      }
+      (Arch::Arm64, ARM64_RELOC_POINTER_TO_GOT) => Some(Kind::PointerToGot),
+      (Arch::Arm64, ARM64_RELOC_TLVP_LOAD_PAGE21) => Some(Kind::TlvPage21),
+      (Arch::Arm64, ARM64_RELOC_TLVP_LOAD_PAGEOFF12) => {
+        Some(Kind::TlvPageOff12)
+      }
+      (Arch::Arm64, ARM64_RELOC_ADDEND) => Some(Kind::Addend),
+      _ => None,
+    }
+  }
+
+  fn is_branch(&self) -> bool {
+    *self == Kind::Branch || *self == Kind::Branch26
+  }
+
+  fn is_tlv(&self) -> bool {
+    match *self {
+      Kind::Tlv | Kind::TlvPage21 | Kind::TlvPageOff12 => true,
+      _ => false,
+    }
+  }
+
+  fn uses_got(&self) -> bool {
+    match *self {
+      Kind::GotRel32
+      | Kind::GotPage21
+      | Kind::GotPageOff12
+      | Kind::PointerToGot => true,
+      _ => false,
+    }
+  }
+
+  // How the shared cache builder has to adjust a fixup of this kind when
+  // its target's segment moves relative to it.
+  fn split_seg_kind(&self) -> Option<u64> {
+    match *self {
+      Kind::Rel32 | Kind::Branch | Kind::GotRel32 => {
+        Some(DYLD_CACHE_ADJ_V2_DELTA_32)
+      }
+      Kind::Branch26 => Some(DYLD_CACHE_ADJ_V2_ARM64_BR26),
+      Kind::Page21 | Kind::GotPage21 => Some(DYLD_CACHE_ADJ_V2_ARM64_ADRP),
+      Kind::PageOff12 | Kind::GotPageOff12 => {
+        Some(DYLD_CACHE_ADJ_V2_ARM64_OFF12)
+      }
+      _ => None,
+    }
+  }
+}
+
+fn stub_size(arch: Arch) -> u64 {
+  if arch.is_arm64() {
+    12
+  } else {
+    6
+  }
+}
+
+// adrp x16, GOT entry@PAGE; ldr x16, [x16, GOT entry@PAGEOFF]; br x16
+const ARM64_STUB: [u32; 3] = [0x9000_0010, 0xf940_0210, 0xd61f_0200];
+
+// Write the stub at `stub`, which jumps to wherever the GOT entry at `slot`
+// points.
+fn write_stub(
+  arch: Arch,
+  code: &mut [u8],
+  stub: u64,
+  slot: u64,
+) -> ::macho::reloc::Result<()> {
+  if arch.is_arm64() {
+    write_u32(code, 0, encode_adrp(ARM64_STUB[0], stub, slot)?);
+    write_u32(code, 4, encode_pageoff12(ARM64_STUB[1], slot)?);
+    write_u32(code, 8, ARM64_STUB[2]);
+    Ok(())
+  } else {
+    // jmpq *slot(%rip)
+    code[0] = 0xff;
+    code[1] = 0x25;
+    write_rel32(code, 2, stub + 6, slot)
+  }
+}
+
+fn read_u64(contents: &[u8], offset: usize) -> u64 {
+  (0..8).fold(0, |acc, i| acc | ((contents[offset + i] as u64) << (i * 8)))
+}
+
+// The value stored at a fixup of 2^length bytes, which is signed when it's
+// 32 bits.
+fn read_addend(contents: &[u8], offset: usize, length: u8) -> i64 {
+  if length == 3 {
+    read_u64(contents, offset) as i64
+  } else {
+    read_u32(contents, offset) as i32 as i64
+  }
+}
+
+// A symbol defined in a dylib, or left for dyld to find.
+#[derive(Debug, Clone)]
+struct DylibImport {
+  name: String,
+  // The dylib's ordinal, BIND_SPECIAL_DYLIB_FLAT_LOOKUP, or
+  // BIND_SPECIAL_DYLIB_SELF for an interposable definition.
+  ordinal: i64,
+  weak_import: bool,
+}
+
+// What a symbol in the merged object refers to once the image is laid out.
+#[derive(Debug, Clone, Copy)]
+enum Target {
+  Address(u64),
+  // An index into the imports.
+  Import(usize),
+}
+
+// A pointer in the image which dyld has to fix up.
+#[derive(Debug, Clone, Copy)]
+enum Pointer {
+  // To this address in the image, which moves with it.
+  Rebase(u64),
+  // To (an import, plus an addend).
+  Bind(usize, i64),
+  // arm64e pointers dyld signs: to an address in the image, or to an
+  // import.
+  AuthRebase(u64, Auth),
+  AuthBind(usize, Auth),
+}
+
+// How an arm64e pointer is signed: with `key`, and a discriminator of
+// `diversity`, blended with the pointer's address if `addr_div`.
+#[derive(Debug, Clone, Copy)]
+struct Auth {
+  key: u8,
+  diversity: u16,
+  addr_div: bool,
+}
+
+impl Auth {
+  // The addend and signing at an ARM64_RELOC_AUTHENTICATED_POINTER fixup:
+  // the addend in the low 32 bits, then the diversity, the address
+  // diversity bit and the key, with the top bit set.
+  fn parse(value: u64) -> Option<(i64, Auth)> {
+    if value >> 63 == 0 {
+      return None;
+    }
+    let auth = Auth {
+      key: ((value >> 49) & 3) as u8,
+      diversity: (value >> 32) as u16,
+      addr_div: (value >> 48) & 1 != 0,
+    };
+    Some((value as u32 as i32 as i64, auth))
+  }
+}
+
+// Hide the globals the -exported_symbols_list (or -unexported_symbols_list)
+// leaves out, as if they had been private extern, and with
+// -no_weak_exports the weak definitions, which then aren't coalesced with
+// other images' copies.
+fn apply_export_control(
+  opts: &LinkOptions,
+  object: &mut ObjectFile,
+) -> Result<()> {
+  let control = opts.export_control().map_err(FinalLinkError::SymbolList)?;
+  for name in control.required_names().iter() {
+    let global = object.symbols.iter().find(|sym| {
+      sym.name == *name
+        && !sym.is_undefined()
+        && (sym.is_external() || sym.is_private_extern())
+    });
+    match global {
+      None => {
+        let missing = ResolveError::ExportedSymbolNotDefined(name.clone());
+        return Err(FinalLinkError::Resolve(missing));
+      }
+      Some(sym) if !sym.is_external() => {
+        let hidden = ResolveError::CannotExportHidden(name.clone());
+        return Err(FinalLinkError::Resolve(hidden));
+      }
+      Some(_) => (),
+    }
+  }
+  for sym in object.symbols.iter_mut() {
+    let hidden = !control.is_exported(&sym.name)
+      || (opts.no_weak_exports && sym.is_weak_def());
+    if sym.is_external() && !sym.is_undefined() && hidden {
+      sym.n_type = (sym.n_type & !N_EXT) | N_PEXT;
+    }
+  }
+  Ok(())
+}
+
+// Define each -alias as a strong, exported global at its target's address.
+// References to the alias are to the new definition.
+fn add_aliases(opts: &LinkOptions, object: &mut ObjectFile) -> Result<()> {
+  let aliases = opts.all_aliases().map_err(FinalLinkError::SymbolList)?;
+  for (target, alias) in aliases {
+    let global = |sym: &Nlist, name: &str| {
+      sym.name == name
+        && !sym.is_undefined()
+        && (sym.is_external() || sym.is_private_extern())
+    };
+    if object.symbols.iter().any(|sym| global(sym, &alias)) {
+      return Err(FinalLinkError::AliasDefined(alias));
+    }
+    let defined = match object.symbols.iter().find(|sym| global(sym, &target))
+    {
+      Some(sym) => Nlist {
+        name: alias.clone(),
+        n_type: (sym.n_type & !N_PEXT) | N_EXT,
+        n_desc: sym.n_desc & !N_WEAK_DEF,
+        ..sym.clone()
+      },
+      None => {
+        let missing = ResolveError::AliasTargetNotDefined(alias, target);
+        return Err(FinalLinkError::Resolve(missing));
+      }
+    };
+    let referenced = object.symbols.iter().position(|sym| {
+      sym.name == alias && sym.is_undefined() && !sym.is_common()
+    });
+    match referenced {
+      Some(i) => object.symbols[i] = defined,
+      None => object.symbols.push(defined),
+    }
+  }
+  Ok(())
+}
+
+// The segment and section the initializers are written to in `format`,
+// with the size of each.
+fn init_section_of(format: InitFormat) -> (&'static str, &'static str, u64) {
+  match format {
+    InitFormat::Pointers => ("__DATA", "__mod_init_func", 8),
+    InitFormat::Offsets => ("__TEXT", "__init_offsets", 4),
+  }
+}
+
+// Where everything is once the image is laid out.
+#[derive(Clone)]
+struct Addresses {
+  // For each merged section the image holds, (its address in the image,
+  // its address in the merged object).
+  sections: Vec<Option<(u64, u64)>>,
+  symbols: Vec<Option<Target>>,
+  stubs: u64,
+  got: u64,
+  // Where the thread-local template starts, which descriptors' offsets
+  // are from.
+  tlv_template: u64,
+}
+
+// Where a section added as (segname, sectname) ended up: the indices of
+// its segment and of it in the segment. Sections of __DATA may since have
+// moved to __DATA_CONST, and a kext's code to __TEXT_EXEC.
+fn locate(
+  image: &Image,
+  segname: &str,
+  sectname: &str,
+) -> Option<(usize, usize)> {
+  let find = |wanted: &str| {
+    image
+      .segments
+      .iter()
+      .enumerate()
+      .filter(|&(_, seg)| seg.segname == wanted)
+      .filter_map(|(i, seg)| {
+        let found = seg.sections.iter().position(|s| s.sectname == sectname);
+        found.map(|j| (i, j))
+      })
+      .next()
+  };
+  find(segname).or_else(|| {
+    if segname == "__DATA" {
+      find("__DATA_CONST")
+    } else if segname == "__TEXT" {
+      find("__TEXT_EXEC")
+    } else {
+      None
+    }
+  })
+}
+
+// The 1-based ordinal of a section across all the segments, as n_sect
+// counts them.
+fn section_ordinal(image: &Image, (seg, sect): (usize, usize)) -> u8 {
+  let before: usize =
+    image.segments[..seg].iter().map(|s| s.sections.len()).sum();
+  (before + sect + 1) as u8
+}
+
+// (segment index, section index) of the section containing `addr`.
+fn find_section(image: &Image, addr: u64) -> Option<(usize, usize)> {
+  for (i, seg) in image.segments.iter().enumerate() {
+    for (j, sect) in seg.sections.iter().enumerate() {
+      if addr >= sect.addr && addr < sect.addr + sect.size() {
+        return Some((i, j));
+      }
+    }
+  }
+  None
+}
+
+// Give the image's sections the references from them which the shared
+// cache builder has to adjust: the pointers it rebases, and code reaching
+// other sections, as relocating noted them.
+fn add_split_seg_references(
+  image: &mut Image,
+  pointers: &[(u64, Pointer)],
+  references: &[(u64, u64, u64)],
+) {
+  let rebases = pointers.iter().filter_map(|&(addr, pointer)| match pointer {
+    Pointer::Rebase(to) | Pointer::AuthRebase(to, _) => {
+      Some((DYLD_CACHE_ADJ_V2_POINTER_64, addr, to))
+    }
+    Pointer::Bind(..) | Pointer::AuthBind(..) => None,
+  });
+  let all: Vec<(u64, u64, u64)> =
+    references.iter().cloned().chain(rebases).collect();
+  for (kind, from, to) in all {
+    let (seg, sect) = match find_section(image, from) {
+      Some(found) => found,
+      None => continue,
+    };
+    let pointer = kind == DYLD_CACHE_ADJ_V2_POINTER_64;
+    let (target, target_offset) = match find_section(image, to) {
+      // Code doesn't move apart from what's in the same section.
+      Some(found) if found == (seg, sect) && !pointer => continue,
+      Some((i, j)) => {
+        let segment = &image.segments[i];
+        let section = &segment.sections[j];
+        let names = (segment.segname.clone(), section.sectname.clone());
+        (Some(names), to - section.addr)
+      }
+      None => (None, to.wrapping_sub(image.segments[0].vmaddr)),
+    };
+    let out = &mut image.segments[seg].sections[sect];
+    let offset = from - out.addr;
+    out.references.push(SectionReference {
+      kind: kind,
+      offset: offset,
+      target: target,
+      target_offset: target_offset,
+    });
+  }
+}
+
+// (segment index, offset within segment) of `addr`, as the dyld info
+// opcodes give locations. Segment indices count __PAGEZERO.
+fn segment_offset(image: &Image, addr: u64) -> Option<(u8, u64)> {
+  let first = image.has_pagezero() as usize;
+  image
+    .segments
+    .iter()
+    .enumerate()
+    .find(|&(_, seg)| addr >= seg.vmaddr && addr < seg.vmaddr + seg.vmsize)
+    .map(|(index, seg)| ((first + index) as u8, addr - seg.vmaddr))
+}
+
+// The section containing `addr`, and the offset into it, to say where a
+// pointer is.
+fn describe(image: &Image, addr: u64) -> String {
+  for seg in image.segments.iter() {
+    for sect in seg.sections.iter() {
+      if addr >= sect.addr && addr < sect.addr + sect.size() {
+        return format!(
+          "{},{}+{:#x}",
+          seg.segname,
+          sect.sectname,
+          addr - sect.addr
+        );
+      }
+    }
+  }
+  format!("{:#x}", addr)
+}
+
+// The merged object, and what the image adds to it, before layout.
+struct FinalLink<'a> {
+  opts: &'a LinkOptions,
+  object: ObjectFile,
+  // The (segname, sectname) of each merged section the image holds. Debug
+  // info is left in the objects, for dsymutil.
+  placed: Vec<Option<(String, String)>>,
+  imports: Vec<DylibImport>,
+  import_of: HashMap<usize, usize>,
+  // The exported definitions -interposable and -interposable_list name, by
+  // symbol index, with their imports. The output's calls and pointers to
+  // them bind to the output itself, so dyld can send them elsewhere.
+  interposed: HashMap<usize, usize>,
+  // Tentative definitions, by symbol index, with their offsets into
+  // __DATA,__common.
+  commons: HashMap<usize, u64>,
+  common_size: u64,
+  common_align: u32,
+  // The symbols called through a stub, and those with a GOT entry, in
+  // order, with their indices.
+  stubs: Vec<usize>,
+  got: Vec<usize>,
+  got_of: HashMap<usize, usize>,
+  // The merged __mod_init_func, and the -init symbol. The initializers are
+  // written out afresh, in the output's format, with -init's first.
+  init_funcs: Option<usize>,
+  init_symbol: Option<usize>,
+  // The merged __compact_unwind and __eh_frame, which __unwind_info and
+  // the pruned __eh_frame are built from.
+  compact_unwind: Option<usize>,
+  eh_frame: Option<usize>,
+  // The inputs with debug info, which the debug map names for dsymutil.
+  debug_inputs: Vec<DebugInput>,
+  // The calls to DTrace probes, where they were in the merged object, and
+  // the providers' attributes, for the __dof sections.
+  probe_sites: Vec<ProbeSite>,
+  stabilities: Vec<(String, [u32; 5])>,
+}
+
+impl<'a> FinalLink<'a> {
+  fn new(
+    opts: &'a LinkOptions,
+    object: ObjectFile,
+    debug_inputs: Vec<DebugInput>,
+    dylibs: &DylibSet,
+  ) -> Result<Self> {
+    let mut placed: Vec<Option<(String, String)>> = object
+      .sections
+      .iter()
+      .map(|sect| match sect.segname.as_str() {
+        "__DWARF" | "__LD" => None,
+        _ => Some((sect.segname.clone(), sect.sectname.clone())),
+      })
+      .collect();
+    let init_funcs = object.sections.iter().position(|sect| {
+      sect.flags & SECTION_TYPE == S_MOD_INIT_FUNC_POINTERS
+    });
+    let position = |segname: &str, sectname: &str| {
+      object.sections.iter().position(|sect| {
+        sect.segname == segname && sect.sectname == sectname
+      })
+    };
+    let compact_unwind = position("__LD", "__compact_unwind");
+    let eh_frame = position("__TEXT", "__eh_frame");
+    for &i in init_funcs.iter().chain(eh_frame.iter()) {
+      placed[i] = None;
+    }
+    let init_symbol = match opts.init_symbol {
+      Some(ref name) => {
+        let found = object.symbols.iter().position(|sym| {
+          sym.name == *name && !sym.is_stab() && !sym.is_undefined()
+        });
+        let missing = || FinalLinkError::NoInitSymbol(name.clone());
+        Some(found.ok_or_else(missing)?)
+      }
+      None => None,
+    };
+    let probe_sites = dtrace::find_sites(opts.arch, &object);
+    let stabilities = dtrace::find_stabilities(&object);
+    let mut link = FinalLink {
+      opts: opts,
+      object: object,
+      placed: placed,
+      imports: Vec::new(),
+      import_of: HashMap::new(),
+      interposed: HashMap::new(),
+      commons: HashMap::new(),
+      common_size: 0,
+      common_align: 0,
+      stubs: Vec::new(),
+      got: Vec::new(),
+      got_of: HashMap::new(),
+      init_funcs: init_funcs,
+      init_symbol: init_symbol,
+      compact_unwind: compact_unwind,
+      eh_frame: eh_frame,
+      debug_inputs: debug_inputs,
+      probe_sites: probe_sites,
+      stabilities: stabilities,
+    };
+    link.classify_symbols(dylibs);
+    if opts.uses_dyld() {
+      let interposable =
+        opts.interposable_symbols().map_err(FinalLinkError::SymbolList)?;
+      link.classify_interposable(&interposable);
+    }
+    link.plan_stubs();
+    if !opts.uses_dyld() && opts.output_kind != OutputKind::KextBundle {
+      if let Some(import) = link.imports.first() {
+        return Err(FinalLinkError::Unsupported(format!(
+          "{}, which is undefined, in an output dyld doesn't load",
+          import.name
+        )));
+      }
+    }
+    let entry = entry_symbol(opts);
+    if has_entry_point(opts.output_kind)
+      && !link.object.symbols.iter().any(|sym| {
+        sym.name == entry && !sym.is_stab() && !sym.is_undefined()
+      }) {
+      return Err(FinalLinkError::NoEntryPoint(entry.to_string()));
+    }
+    Ok(link)
+  }
+
+  // Sort the undefined symbols into imports, commons and what the linker
+  // defines.
+  fn classify_symbols(&mut self, dylibs: &DylibSet) {
+    for (i, sym) in self.object.symbols.iter().enumerate() {
+      if !sym.is_undefined() || !sym.is_external() {
+        continue;
+      }
+      if sym.is_common() {
+        // The alignment is in n_desc, or else the natural one for the size.
+        let align = match (sym.n_desc >> 8) & 0xf {
+          0 => (63 - sym.n_value.leading_zeros()).min(4),
+          align => align as u32,
+        };
+        let offset = round_up(self.common_size, 1 << align);
+        self.commons.insert(i, offset);
+        self.common_size = offset + sym.n_value;
+        self.common_align = self.common_align.max(align);
+        continue;
+      }
+      // The linker takes care of DTrace probes itself.
+      if is_linker_defined(self.opts, &sym.name)
+        || dtrace::is_dtrace_symbol(&sym.name)
+      {
+        continue;
+      }
+      let found = dylibs.lookup(&sym.name);
+      self.import_of.insert(i, self.imports.len());
+      self.imports.push(DylibImport {
+        name: sym.name.clone(),
+        ordinal: found
+          .as_ref()
+          .map_or(BIND_SPECIAL_DYLIB_FLAT_LOOKUP, |found| found.ordinal),
+        weak_import: found.map_or(false, |found| found.weak_import)
+          || sym.n_desc & N_WEAK_REF != 0,
+      });
+    }
+  }
+
+  // Make imports of the exported definitions in `interposable` which the
+  // output refers to itself.
+  fn classify_interposable(&mut self, interposable: &SymbolList) {
+    let referenced: HashSet<usize> = self
+      .object
+      .sections
+      .iter()
+      .flat_map(|sect| sect.relocs.iter())
+      .filter(|reloc| reloc.external)
+      .map(|reloc| reloc.symbolnum as usize)
+      .collect();
+    for (i, sym) in self.object.symbols.iter().enumerate() {
+      let exported = sym.is_external()
+        && !sym.is_private_extern()
+        && !sym.is_undefined()
+        && !sym.is_stab();
+      if !exported
+        || !referenced.contains(&i)
+        || !interposable.contains(&sym.name)
+      {
+        continue;
+      }
+      self.interposed.insert(i, self.imports.len());
+      self.imports.push(DylibImport {
+        name: sym.name.clone(),
+        ordinal: BIND_SPECIAL_DYLIB_SELF,
+        weak_import: false,
+      });
+    }
+  }
+
+  // What the output's own references to symbol `sym` reach: its import for
+  // an interposable definition, or else where it is.
+  fn referenced_target(&self, at: &Addresses, sym: usize) -> Option<Target> {
+    match self.interposed.get(&sym) {
+      Some(&import) => Some(Target::Import(import)),
+      None => at.symbols.get(sym).and_then(|target| *target),
+    }
+  }
+
+  // Give each function called in a dylib a stub, and each symbol referred
+  // to through the GOT an entry in it.
+  fn plan_stubs(&mut self) {
+    let arch = self.opts.arch;
+    let mut stubbed: HashSet<usize> = HashSet::new();
+    for (i, sect) in self.object.sections.iter().enumerate() {
+      let unwind = Some(i) == self.compact_unwind;
+      if self.placed[i].is_none() && !unwind {
+        continue;
+      }
+      for reloc in sect.relocs.iter().filter(|reloc| reloc.external) {
+        let kind = match Kind::of(arch, reloc.kind) {
+          Some(kind) => kind,
+          None => continue,
+        };
+        let sym = reloc.symbolnum as usize;
+        let import = self.import_of.contains_key(&sym);
+        let interposed = !unwind && self.interposed.contains_key(&sym);
+        let stub = kind.is_branch() && (import || interposed);
+        if stub && stubbed.insert(sym) {
+          self.stubs.push(sym);
+        }
+        // __unwind_info refers to personality routines through the GOT.
+        let personality = unwind && is_personality(reloc.address);
+        let got = stub
+          || kind.uses_got()
+          || kind.is_tlv() && import
+          || personality;
+        if got && !self.got_of.contains_key(&sym) {
+          self.got_of.insert(sym, self.got.len());
+          self.got.push(sym);
+        }
+      }
+    }
+  }
+
+  // Add the sections, the placeholders for the __LINKEDIT blobs and the
+  // load commands to `image`.
+  fn add_to_image(&self, image: &mut Image, dylibs: &DylibSet) -> Result<()> {
+    let arch = self.opts.arch;
+    let placed = self.object.sections.iter().zip(self.placed.iter());
+    for (i, (sect, placed)) in placed.enumerate() {
+      let (segname, sectname) = match *placed {
+        Some((ref segname, ref sectname)) => (segname, sectname),
+        None => continue,
+      };
+      let section = if sect.is_zerofill() {
+        OutputSection::zerofill(sectname, sect.size, sect.align, sect.flags)
+      } else {
+        OutputSection::new(sectname, Vec::new(), sect.align, sect.flags)
+      };
+      image.add_section(
+        segname,
+        OutputSection {
+          contents: sect.contents.clone(),
+          reserved1: sect.reserved1,
+          reserved2: sect.reserved2,
+          function_starts: self.object.function_starts(i),
+          data_in_code: self.data_in_code(sect),
+          ..section
+        },
+      )?;
+    }
+    if !self.commons.is_empty() {
+      image.add_section(
+        "__DATA",
+        OutputSection::zerofill(
+          "__common",
+          self.common_size,
+          self.common_align,
+          S_ZEROFILL,
+        ),
+      )?;
+    }
+    let inits = self
+      .init_funcs
+      .map_or(0, |i| self.object.sections[i].size as usize / 8)
+      + self.init_symbol.iter().count();
+    if inits > 0 {
+      let format = InitFormat::for_output(self.opts.use_chained_fixups());
+      let (segname, sectname, size) = init_section_of(format);
+      let flags = match format {
+        InitFormat::Pointers => S_MOD_INIT_FUNC_POINTERS,
+        InitFormat::Offsets => S_INIT_FUNC_OFFSETS,
+      };
+      let contents = vec![0; inits * size as usize];
+      let align = size.trailing_zeros();
+      image.add_section(
+        segname,
+        OutputSection::new(sectname, contents, align, flags),
+      )?;
+    }
+    if !self.stubs.is_empty() {
+      let size = stub_size(arch);
+      let code = vec![0; (size * self.stubs.len() as u64) as usize];
+      let align = if arch.is_arm64() { 2 } else { 1 };
+      let flags =
+        S_SYMBOL_STUBS | S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS;
+      image.add_section(
+        "__TEXT",
+        OutputSection {
+          reserved2: size as u32,
+          ..OutputSection::new("__stubs", code, align, flags)
+        },
+      )?;
+    }
+    if !self.got.is_empty() {
+      let slots = vec![0; 8 * self.got.len()];
+      image.add_section(
+        "__DATA",
+        OutputSection {
+          reserved1: self.stubs.len() as u32,
+          ..OutputSection::new("__got", slots, 3, S_NON_LAZY_SYMBOL_POINTERS)
+        },
+      )?;
+    }
+
+    // Placeholders of the right size: finish builds them again.
+    let eh_frame_addr = self.eh_frame.map_or(0, |i| {
+      self.object.sections[i].addr
+    });
+    let merged = self.merged_addresses();
+    let unwind = self.unwind_sections(&merged, 0, eh_frame_addr)?;
+    unwind::add_to_image(image, unwind)?;
+    dtrace::add_to_image(image, &self.probe_sites, &self.stabilities)?;
+
+    // The blobs are filled in once everything has an address.
+    let mut blobs: Vec<u32> = if self.opts.use_chained_fixups() {
+      vec![LC_DYLD_CHAINED_FIXUPS, LC_DYLD_EXPORTS_TRIE]
+    } else if self.opts.uses_dyld() {
+      vec![LC_DYLD_INFO_ONLY; 5]
+    } else {
+      Vec::new()
+    };
+    blobs.extend_from_slice(&[LC_SYMTAB, LC_SYMTAB, LC_DYSYMTAB]);
+    if self.opts.output_kind == OutputKind::KextBundle {
+      // The kext loader's external and local relocations.
+      blobs.extend_from_slice(&[LC_DYSYMTAB, LC_DYSYMTAB]);
+    }
+    for cmd in blobs {
+      image.linkedit.push(LinkeditBlob {
+        cmd: cmd,
+        data: Vec::new(),
+      });
+    }
+
+    image.data_const = self.opts.use_data_const();
+    image.segment_protections = self.opts.segment_protections.clone();
+    image.section_alignments = self.opts.section_alignments.clone();
+    image.stack_size = self.opts.stack_size.unwrap_or(0);
+    image.stack_addr = self.opts.stack_addr;
+    image.uses_dyld = self.opts.uses_dyld();
+    if let Some(image_base) = self.opts.image_base {
+      image.image_base = image_base;
+    }
+    image.segment_addresses = self.opts.segment_addresses.clone();
+    if self.opts.output_kind == OutputKind::Executable && image.uses_dyld {
+      image.load_commands.push(LoadCommand::Dylinker {
+        name: DYLD_PATH.to_string(),
+      });
+    }
+    if has_entry_point(self.opts.output_kind) {
+      // For LC_MAIN's (or LC_UNIXTHREAD's) space: the real address is known
+      // after layout.
+      image.entry_point = Some(image.image_base);
+    }
+    target::add_to_image(image, &self.opts.target());
+    image.load_commands.extend(self.opts.source_version_command());
+    dylib::add_to_image(image, self.opts, dylibs);
+    codesign::add_to_image(image, self.opts)?;
+    let uuid = uuid::load_command(self.opts.uuid).map_err(|e| {
+      FinalLinkError::IoError(PathBuf::from("/dev/urandom"), e)
+    })?;
+    image.load_commands.extend(uuid);
+    for &(ref owner, ref path) in self.opts.notes.iter() {
+      image.add_note(owner, path)?;
+    }
+    for &(cmd, ref path) in self.opts.extra_load_commands.iter() {
+      image.add_load_command(cmd, path)?;
+    }
+    image.flags |= self.opts.executable_header_flags();
+    image.flags |= tlv::header_flags(self.object.sections.iter().any(|sect| {
+      sect.flags & SECTION_TYPE == S_THREAD_LOCAL_VARIABLES
+    }));
+    if self
+      .imports
+      .iter()
+      .all(|import| import.ordinal != BIND_SPECIAL_DYLIB_FLAT_LOOKUP)
+    {
+      image.flags |= MH_NOUNDEFS;
+    }
+    Ok(())
+  }
+
+  fn addresses(&self, image: &Image) -> Addresses {
+    let address = |segname: &str, sectname: &str| {
+      locate(image, segname, sectname)
+        .map(|(i, j)| image.segments[i].sections[j].addr)
+    };
+    let sections: Vec<Option<(u64, u64)>> = self
+      .object
+      .sections
+      .iter()
+      .zip(self.placed.iter())
+      .map(|(sect, placed)| match *placed {
+        Some((ref segname, ref sectname)) => {
+          address(segname, sectname).map(|addr| (addr, sect.addr))
+        }
+        None => None,
+      })
+      .collect();
+    let common = address("__DATA", "__common").unwrap_or(0);
+    let symbols =
+      self.symbol_targets(&sections, common, image.segments[0].vmaddr);
+    let tlv_template = image
+      .segments
+      .iter()
+      .flat_map(|seg| seg.sections.iter())
+      .filter(|sect| is_tlv_template(sect.flags))
+      .map(|sect| sect.addr)
+      .min()
+      .unwrap_or(0);
+    Addresses {
+      sections: sections,
+      symbols: symbols,
+      stubs: address("__TEXT", "__stubs").unwrap_or(0),
+      got: address("__DATA", "__got").unwrap_or(0),
+      tlv_template: tlv_template,
+    }
+  }
+
+  // Where each symbol is, given where its section is, where __common is
+  // and where the mach header is.
+  fn symbol_targets(
+    &self,
+    sections: &[Option<(u64, u64)>],
+    common: u64,
+    header: u64,
+  ) -> Vec<Option<Target>> {
+    self
+      .object
+      .symbols
+      .iter()
+      .enumerate()
+      .map(|(i, sym)| {
+        if sym.is_stab() {
+          return None;
+        }
+        if let Some(&import) = self.import_of.get(&i) {
+          return Some(Target::Import(import));
+        }
+        if let Some(&offset) = self.commons.get(&i) {
+          return Some(Target::Address(common + offset));
+        }
+        match sym.n_type & N_TYPE {
+          N_SECT => sections
+            .get((sym.n_sect as usize).wrapping_sub(1))
+            .and_then(|sect| *sect)
+            .map(|(new, old)| Target::Address(new + sym.n_value - old)),
+          N_ABS => Some(Target::Address(sym.n_value)),
+          _ if is_linker_defined(self.opts, &sym.name) => {
+            Some(Target::Address(header))
+          }
+          _ => None,
+        }
+      })
+      .collect()
+  }
+
+  // Where everything was in the merged object, before layout. There's no
+  // GOT or stubs yet, nor a mach header.
+  fn merged_addresses(&self) -> Addresses {
+    let sections: Vec<Option<(u64, u64)>> = self
+      .object
+      .sections
+      .iter()
+      .map(|sect| Some((sect.addr, sect.addr)))
+      .collect();
+    let common = self.object.sections.iter().fold(0, |end, sect| {
+      end.max(sect.addr + sect.size)
+    });
+    Addresses {
+      symbols: self.symbol_targets(&sections, common, 0),
+      sections: sections,
+      stubs: 0,
+      got: 0,
+      tlv_template: 0,
+    }
+  }
+
+  // Apply the relocations of merged section `index` to `contents`, noting
+  // the pointers dyld has to fix up and, as (split seg kind, address,
+  // target address), the code referring to other addresses in the image.
+  fn relocate(
+    &self,
+    at: &Addresses,
+    index: usize,
+    contents: &mut [u8],
+    pointers: &mut Vec<(u64, Pointer)>,
+    references: &mut Vec<(u64, u64, u64)>,
+  ) -> Result<()> {
+    let arch = self.opts.arch;
+    let sect = &self.object.sections[index];
+    let (base, merged_base) = match at.sections[index] {
+      Some(addresses) => addresses,
+      None => return Ok(()),
+    };
+    let site_delta = base.wrapping_sub(merged_base);
+    let rebases = self.opts.needs_rebases();
+    let descriptors = sect.flags & SECTION_TYPE == S_THREAD_LOCAL_VARIABLES;
+    let mut addend: i64 = 0;
+    let mut subtracted: Option<u64> = None;
+    for reloc in sect.relocs.iter() {
+      let place = || {
+        format!("{},{}+{:#x}", sect.segname, sect.sectname, reloc.address)
+      };
+      let unsupported =
+        || FinalLinkError::UnsupportedRelocation(reloc.kind, place());
+      let offset = reloc.address as usize;
+      let kind = match Kind::of(arch, reloc.kind) {
+        Some(kind) => kind,
+        None => return Err(unsupported()),
+      };
+      if reloc.scattered.is_some()
+        || offset + (1 << reloc.length) > contents.len()
+      {
+        return Err(unsupported());
+      }
+      if kind == Kind::Addend {
+        // A 24-bit signed value in place of the symbol number.
+        addend = ((reloc.symbolnum << 8) as i32 >> 8) as i64;
+        continue;
+      }
+      let extra = addend;
+      addend = 0;
+      if subtracted.is_some() && kind != Kind::Unsigned {
+        return Err(unsupported());
+      }
+      let pc = base + reloc.address as u64;
+      let reloc_error = |e| FinalLinkError::Reloc(place(), e);
+      // A thread-local variable descriptor's last field, the offset of the
+      // variable's initial value in the template.
+      let template_offset = descriptors
+        && kind == Kind::Unsigned
+        && reloc.address as u64 % TLV_DESCRIPTOR_SIZE == 16;
+
+      if !reloc.external {
+        let target_delta = (reloc.symbolnum as usize)
+          .checked_sub(1)
+          .and_then(|j| at.sections.get(j))
+          .and_then(|sect| *sect)
+          .map(|(new, old)| new.wrapping_sub(old))
+          .ok_or_else(unsupported)?;
+        if let Some(from) = subtracted.take() {
+          // The target's merged address, less what was subtracted there,
+          // is in the fixup.
+          let stored = read_addend(contents, offset, reloc.length) as u64;
+          let value = stored.wrapping_add(target_delta).wrapping_sub(from);
+          write_value(contents, offset, reloc.length, value);
+          continue;
+        }
+        let moved = relocatable::adjust_fixup(
+          arch,
+          reloc,
+          contents,
+          offset,
+          target_delta,
+          site_delta,
+        ).map_err(reloc_error)?;
+        if !moved {
+          return Err(unsupported());
+        }
+        if arch == Arch::X86_64 && reloc.pcrel && reloc.length == 2 {
+          let disp = read_u32(contents, offset) as i32 as i64;
+          let to = (pc + 4).wrapping_add(disp as u64);
+          references.push((DYLD_CACHE_ADJ_V2_DELTA_32, pc, to));
+        }
+        if template_offset {
+          let value = read_addend(contents, offset, reloc.length) as u64;
+          let value = value.wrapping_sub(at.tlv_template);
+          write_value(contents, offset, reloc.length, value);
+        } else if kind == Kind::Unsigned && rebases {
+          if reloc.length != 3 {
+            return Err(unsupported());
+          }
+          pointers.push((pc, Pointer::Rebase(read_u64(contents, offset))));
+        }
+        continue;
+      }
+
+      let symbol = reloc.symbolnum as usize;
+      let probe = self
+        .object
+        .symbols
+        .get(symbol)
+        .and_then(|sym| dtrace::parse_symbol(&sym.name));
+      if let Some(probe) = probe {
+        // The call's opcode comes before its displacement on Intel.
+        let call = if arch.is_arm64() {
+          offset
+        } else {
+          offset.checked_sub(1).ok_or_else(unsupported)?
+        };
+        dtrace::patch_site(arch, contents, call, probe.kind)?;
+        continue;
+      }
+      // Calls and pointers to an interposable definition go through dyld,
+      // while differences and the unwind info keep to its address.
+      let through_dyld = (kind.is_branch()
+        || kind == Kind::Unsigned && reloc.length == 3
+        || kind == Kind::AuthPointer)
+        && subtracted.is_none()
+        && !template_offset
+        && Some(index) != self.compact_unwind
+        && Some(index) != self.eh_frame;
+      let target = if through_dyld {
+        self.referenced_target(at, symbol)
+      } else {
+        at.symbols.get(symbol).and_then(|t| *t)
+      };
+      let target = match target {
+        Some(target) => target,
+        None => return Err(unsupported()),
+      };
+      let slot = self.got_of.get(&symbol).map(|&k| at.got + 8 * k as u64);
+      let stub = self
+        .stubs
+        .iter()
+        .position(|&s| s == symbol)
+        .map(|k| at.stubs + stub_size(arch) * k as u64);
+      let stored = read_addend(contents, offset, reloc.length);
+      // Where a branch goes: the callee, or the stub for one in a dylib.
+      let callee = || match (target, stub) {
+        (_, Some(stub)) => Ok(stub),
+        (Target::Address(addr), None) => Ok(addr),
+        (Target::Import(_), None) => Err(unsupported()),
+      };
+      match kind {
+        Kind::Subtractor => match target {
+          Target::Address(addr) => subtracted = Some(addr),
+          Target::Import(_) => return Err(unsupported()),
+        },
+        Kind::Unsigned => match (subtracted.take(), target) {
+          (Some(from), Target::Address(addr)) => {
+            let value = addr.wrapping_add(stored as u64).wrapping_sub(from);
+            write_value(contents, offset, reloc.length, value);
+          }
+          (None, Target::Address(addr)) if template_offset => {
+            let value = addr.wrapping_add(stored as u64);
+            let value = value.wrapping_sub(at.tlv_template);
+            write_value(contents, offset, reloc.length, value);
+          }
+          (None, Target::Address(addr)) => {
+            let value = addr.wrapping_add(stored as u64);
+            if reloc.length != 3 && rebases {
+              return Err(unsupported());
+            }
+            write_value(contents, offset, reloc.length, value);
+            if rebases {
+              pointers.push((pc, Pointer::Rebase(value)));
+            }
+          }
+          (None, Target::Import(import)) if reloc.length == 3 => {
+            set_u64(contents, offset, 0);
+            pointers.push((pc, Pointer::Bind(import, stored)));
+          }
+          _ => return Err(unsupported()),
+        },
+        Kind::Rel32 | Kind::Branch => {
+          let to = if kind == Kind::Branch {
+            callee()?
+          } else {
+            match target {
+              Target::Address(addr) => addr,
+              Target::Import(_) => return Err(unsupported()),
+            }
+          };
+          let to = to.wrapping_add(stored as u64);
+          write_rel32(contents, offset, pc + 4, to).map_err(reloc_error)?;
+          references.push((DYLD_CACHE_ADJ_V2_DELTA_32, pc, to));
+        }
+        Kind::GotRel32 => {
+          let to = slot.ok_or_else(unsupported)?.wrapping_add(stored as u64);
+          write_rel32(contents, offset, pc + 4, to).map_err(reloc_error)?;
+          references.push((DYLD_CACHE_ADJ_V2_DELTA_32, pc, to));
+        }
+        Kind::Branch26 => {
+          let insn = read_u32(contents, offset);
+          let to = callee()?.wrapping_add(extra as u64);
+          let insn = encode_branch26(insn, pc, to).map_err(reloc_error)?;
+          write_u32(contents, offset, insn);
+          references.push((DYLD_CACHE_ADJ_V2_ARM64_BR26, pc, to));
+        }
+        Kind::Page21
+        | Kind::PageOff12
+        | Kind::GotPage21
+        | Kind::GotPageOff12 => {
+          let to = match (kind, target) {
+            (Kind::GotPage21, _) | (Kind::GotPageOff12, _) => {
+              slot.ok_or_else(unsupported)?
+            }
+            (_, Target::Address(addr)) => addr.wrapping_add(extra as u64),
+            (_, Target::Import(_)) => return Err(unsupported()),
+          };
+          let insn = read_u32(contents, offset);
+          let insn = if kind == Kind::Page21 || kind == Kind::GotPage21 {
+            encode_adrp(insn, pc, to)
+          } else {
+            encode_pageoff12(insn, to)
+          }.map_err(reloc_error)?;
+          write_u32(contents, offset, insn);
+          if let Some(adjust) = kind.split_seg_kind() {
+            references.push((adjust, pc, to));
+          }
+        }
+        Kind::PointerToGot => {
+          let slot = slot.ok_or_else(unsupported)?;
+          if reloc.pcrel && reloc.length == 2 {
+            let disp = slot.wrapping_sub(pc) as i64;
+            if disp != disp as i32 as i64 {
+              return Err(reloc_error(RelocError::Overflow("got32", disp)));
+            }
+            write_u32(contents, offset, disp as u32);
+            references.push((DYLD_CACHE_ADJ_V2_DELTA_32, pc, slot));
+          } else if !reloc.pcrel && reloc.length == 3 {
+            set_u64(contents, offset, slot);
+            if rebases {
+              pointers.push((pc, Pointer::Rebase(slot)));
+            }
+          } else {
+            return Err(unsupported());
+          }
+        }
+        Kind::Tlv | Kind::TlvPage21 | Kind::TlvPageOff12 => {
+          // A descriptor in the image is used where it is, and the load of
+          // its address becomes an add.
+          let (to, local) = match target {
+            Target::Address(addr) => (addr, true),
+            Target::Import(_) => (slot.ok_or_else(unsupported)?, false),
+          };
+          match kind {
+            Kind::Tlv => {
+              tlv::relocate_x86_64_tlv(contents, offset, pc + 4, to, local)
+            }
+            Kind::TlvPage21 => {
+              tlv::relocate_arm64_tlvp_page21(contents, offset, pc, to)
+            }
+            _ => {
+              tlv::relocate_arm64_tlvp_pageoff12(contents, offset, to, local)
+            }
+          }.map_err(reloc_error)?;
+        }
+        Kind::AuthPointer => {
+          let signed = if reloc.length == 3 && !reloc.pcrel {
+            Auth::parse(read_u64(contents, offset))
+          } else {
+            None
+          };
+          let (stored, auth) = signed.ok_or_else(unsupported)?;
+          set_u64(contents, offset, 0);
+          match target {
+            Target::Address(addr) => {
+              let to = addr.wrapping_add(stored as u64);
+              pointers.push((pc, Pointer::AuthRebase(to, auth)));
+            }
+            Target::Import(import) if stored == 0 => {
+              pointers.push((pc, Pointer::AuthBind(import, auth)));
+            }
+            Target::Import(_) => return Err(unsupported()),
+          }
+        }
+        Kind::Addend => unreachable!(),
+      }
+    }
+    if subtracted.is_some() {
+      return Err(FinalLinkError::UnsupportedRelocation(
+        0,
+        format!("{},{}", sect.segname, sect.sectname),
+      ));
+    }
+    Ok(())
+  }
+
+  // The functions the merged __mod_init_func points to, resolved where it
+  // was in the merged object.
+  fn init_funcs(&self, at: &Addresses) -> Result<Vec<u64>> {
+    let i = match self.init_funcs {
+      Some(i) => i,
+      None => return Ok(Vec::new()),
+    };
+    let sect = &self.object.sections[i];
+    let mut at = at.clone();
+    at.sections[i] = Some((sect.addr, sect.addr));
+    let mut contents = sect.contents.clone();
+    self.relocate(
+      &at,
+      i,
+      &mut contents,
+      &mut Vec::new(),
+      &mut Vec::new(),
+    )?;
+    Ok(
+      (0..contents.len() / 8)
+        .map(|k| read_u64(&contents, 8 * k))
+        .collect(),
+    )
+  }
+
+  // __unwind_info and what's left of __eh_frame, for the image at `header`
+  // with the pruned __eh_frame going to `eh_frame_addr`. Built before
+  // layout with the merged object's addresses, they have the size they'll
+  // have after it.
+  fn unwind_sections(
+    &self,
+    at: &Addresses,
+    header: u64,
+    eh_frame_addr: u64,
+  ) -> Result<UnwindSections> {
+    // A section's contents, resolved where it was in the merged object.
+    let resolve = |i: usize| -> Result<Vec<u8>> {
+      let sect = &self.object.sections[i];
+      let mut at = at.clone();
+      at.sections[i] = Some((sect.addr, sect.addr));
+      // Its symbols too, such as the labels __eh_frame's pc-relative
+      // pointers are taken from.
+      for (k, sym) in self.object.symbols.iter().enumerate() {
+        let inside =
+          sym.n_type & N_TYPE == N_SECT && sym.n_sect as usize == i + 1;
+        if inside && !sym.is_stab() {
+          at.symbols[k] = Some(Target::Address(sym.n_value));
+        }
+      }
+      let mut contents = sect.contents.clone();
+      self.relocate(
+        &at,
+        i,
+        &mut contents,
+        &mut Vec::new(),
+        &mut Vec::new(),
+      )?;
+      Ok(contents)
+    };
+    let mut entries: Vec<CompactUnwindEntry> = Vec::new();
+    if let Some(i) = self.compact_unwind {
+      entries = unwind_info::parse_compact_unwind(&resolve(i)?)?;
+      let sect = &self.object.sections[i];
+      for reloc in sect.relocs.iter() {
+        if !is_personality(reloc.address) {
+          continue;
+        }
+        let slot = Some(reloc.symbolnum as usize)
+          .filter(|_| reloc.external)
+          .and_then(|sym| self.got_of.get(&sym))
+          .ok_or_else(|| {
+            FinalLinkError::Unsupported(format!(
+              "personality routine at {},{}+{:#x} with no symbol",
+              sect.segname, sect.sectname, reloc.address
+            ))
+          })?;
+        let entry = &mut entries[reloc.address as usize / 32];
+        entry.personality = Some(at.got + 8 * *slot as u64);
+      }
+    }
+    let eh_frame = match self.eh_frame {
+      Some(i) => Some((resolve(i)?, self.object.sections[i].addr)),
+      None => None,
+    };
+    let eh_frame = eh_frame
+      .as_ref()
+      .map(|&(ref contents, addr)| (&contents[..], addr, eh_frame_addr));
+    Ok(unwind::synthesize(self.opts.arch, header, &entries, eh_frame)?)
+  }
+
+  // Write the initializers, noting the pointers to rebase.
+  fn write_initializers(
+    &self,
+    image: &mut Image,
+    at: &Addresses,
+    pointers: &mut Vec<(u64, Pointer)>,
+  ) -> Result<()> {
+    let init = self.init_symbol.and_then(|sym| match at.symbols[sym] {
+      Some(Target::Address(addr)) => Some(addr),
+      _ => None,
+    });
+    let input = InputInitializers {
+      inits: self.init_funcs(at)?,
+      terms: Vec::new(),
+    };
+    let format = InitFormat::for_output(self.opts.use_chained_fixups());
+    let section = match Initializers::collect(&[input], init)
+      .init_section(format, image.image_base)?
+    {
+      Some(section) => section,
+      None => return Ok(()),
+    };
+    let (segname, _, _) = init_section_of(format);
+    let (seg, sect) = match locate(image, segname, section.sectname) {
+      Some(found) => found,
+      None => return Ok(()),
+    };
+    let out = &mut image.segments[seg].sections[sect];
+    if self.opts.needs_rebases() {
+      for &offset in section.rebases.iter() {
+        let addr = read_u64(&section.contents, offset as usize);
+        pointers.push((out.addr + offset, Pointer::Rebase(addr)));
+      }
+    }
+    out.contents = section.contents;
+    Ok(())
+  }
+
+  // Fill in what depends on the layout: the sections' contents, the stubs
+  // and GOT, the fixups, the export trie, the symbol table and the entry
+  // point.
+  fn finish(&self, image: &mut Image) -> Result<()> {
+    let arch = self.opts.arch;
+    let at = self.addresses(image);
+    let mut pointers: Vec<(u64, Pointer)> = Vec::new();
+    let mut references: Vec<(u64, u64, u64)> = Vec::new();
+    for (i, placed) in self.placed.iter().enumerate() {
+      let (segname, sectname) = match *placed {
+        Some((ref segname, ref sectname)) => (segname, sectname),
+        None => continue,
+      };
+      let mut contents = self.object.sections[i].contents.clone();
+      self.relocate(&at, i, &mut contents, &mut pointers, &mut references)?;
+      if let Some((seg, sect)) = locate(image, segname, sectname) {
+        image.segments[seg].sections[sect].contents = contents;
+      }
+    }
+
+    self.write_initializers(image, &at, &mut pointers)?;
+    image.optimization_hints = self.optimization_hints(&at);
+
+    let eh_frame_addr = locate(image, "__TEXT", "__eh_frame")
+      .map_or(0, |(seg, sect)| image.segments[seg].sections[sect].addr);
+    let unwind =
+      self.unwind_sections(&at, image.image_base, eh_frame_addr)?;
+    let built = vec![
+      ("__unwind_info", unwind.unwind_info),
+      ("__eh_frame", unwind.eh_frame),
+    ];
+    for (sectname, contents) in built {
+      let contents = match contents {
+        Some(contents) => contents,
+        None => continue,
+      };
+      let out = match locate(image, "__TEXT", sectname) {
+        Some((seg, sect)) => &mut image.segments[seg].sections[sect],
+        None => continue,
+      };
+      // Only as much room as they took before layout was left for them.
+      if contents.len() > out.contents.len() {
+        return Err(FinalLinkError::Unsupported(format!(
+          "__TEXT,{} larger than before layout",
+          sectname
+        )));
+      }
+      out.contents[..contents.len()].copy_from_slice(&contents);
+    }
+
+    // The DOF again, with the probe sites where they ended up.
+    let sites: Vec<ProbeSite> = self
+      .probe_sites
+      .iter()
+      .map(|site| ProbeSite {
+        function_address: self
+          .moved(&at, site.function_address)
+          .unwrap_or(site.function_address),
+        address: self.moved(&at, site.address).unwrap_or(site.address),
+        ..site.clone()
+      })
+      .collect();
+    let dof = dtrace::dof_sections(
+      arch,
+      image.image_base,
+      &sites,
+      &self.stabilities,
+    );
+    for (sectname, contents) in dof {
+      if let Some((seg, sect)) = locate(image, "__TEXT", &sectname) {
+        image.segments[seg].sections[sect].contents = contents;
+      }
+    }
+
+    if let Some((seg, sect)) = locate(image, "__TEXT", "__stubs") {
+      let size = stub_size(arch);
+      let code = &mut image.segments[seg].sections[sect].contents;
+      for (k, sym) in self.stubs.iter().enumerate() {
+        let stub = at.stubs + size * k as u64;
+        let slot = at.got + 8 * self.got_of[sym] as u64;
+        let start = (size * k as u64) as usize;
+        write_stub(arch, &mut code[start..], stub, slot).map_err(|e| {
+          FinalLinkError::Reloc(self.object.symbols[*sym].name.clone(), e)
+        })?;
+        if arch.is_arm64() {
+          references.push((DYLD_CACHE_ADJ_V2_ARM64_ADRP, stub, slot));
+          references.push((DYLD_CACHE_ADJ_V2_ARM64_OFF12, stub + 4, slot));
+        } else {
+          references.push((DYLD_CACHE_ADJ_V2_DELTA_32, stub + 2, slot));
+        }
+      }
+    }
+    if let Some((seg, sect)) = locate(image, "__DATA", "__got") {
+      let slots = &mut image.segments[seg].sections[sect].contents;
+      for (k, &sym) in self.got.iter().enumerate() {
+        let slot = at.got + 8 * k as u64;
+        match self.referenced_target(&at, sym) {
+          Some(Target::Address(addr)) => {
+            set_u64(slots, 8 * k, addr);
+            if self.opts.needs_rebases() {
+              pointers.push((slot, Pointer::Rebase(addr)));
+            }
+          }
+          Some(Target::Import(import)) => {
+            pointers.push((slot, Pointer::Bind(import, 0)));
+          }
+          None => {
+            return Err(FinalLinkError::UnsupportedRelocation(
+              0,
+              self.object.symbols[sym].name.clone(),
+            ))
+          }
+        }
+      }
+    }
+
+    for &(addr, _) in pointers.iter() {
+      let writable = image.segments.iter().any(|seg| {
+        addr >= seg.vmaddr
+          && addr < seg.vmaddr + seg.vmsize
+          && seg.initprot & VM_PROT_WRITE != 0
+      });
+      if !writable {
+        return Err(FinalLinkError::TextRelocation(describe(image, addr)));
+      }
+    }
+
+    if image.split_seg_info {
+      add_split_seg_references(image, &pointers, &references);
+      let data = image.split_seg_data();
+      set_blobs(image, LC_SEGMENT_SPLIT_INFO, vec![data]);
+    }
+
+    let exports = self.export_trie(&at, image.segments[0].vmaddr);
+    let weak = self.weak_bindings(image, &at, &pointers);
+    if !weak.is_empty() {
+      image.flags |= MH_WEAK_DEFINES;
+    }
+    if weak.iter().any(|binding| !binding.locations.is_empty()) {
+      image.flags |= MH_BINDS_TO_WEAK;
+    }
+    if self.opts.use_chained_fixups() {
+      let fixups = self.chain(image, &pointers)?;
+      set_blobs(image, LC_DYLD_CHAINED_FIXUPS, vec![fixups]);
+      set_blobs(image, LC_DYLD_EXPORTS_TRIE, vec![exports]);
+    } else if self.opts.uses_dyld() {
+      let (rebase, bind, weak_bind) = self.dyld_info(image, &pointers, &weak);
+      let info = vec![rebase, bind, weak_bind, Vec::new(), exports];
+      set_blobs(image, LC_DYLD_INFO_ONLY, info);
+    }
+    let index_of = self.symbol_table(image, &at);
+    let mut dysymtab = vec![self.indirect_symbols(&index_of)];
+    if self.opts.output_kind == OutputKind::KextBundle {
+      let fixups = self.kext_fixups(image, &pointers, &index_of);
+      let (external, local) = kext::relocations(arch, &fixups);
+      dysymtab.extend(vec![external, local]);
+    }
+    set_blobs(image, LC_DYSYMTAB, dysymtab);
+
+    if has_entry_point(self.opts.output_kind) {
+      let entry = entry_symbol(self.opts);
+      let main = self
+        .object
+        .symbols
+        .iter()
+        .position(|sym| sym.name == entry && !sym.is_stab());
+      match main.and_then(|i| at.symbols[i]) {
+        Some(Target::Address(addr)) => image.entry_point = Some(addr),
+        _ => return Err(FinalLinkError::NoEntryPoint(entry.to_string())),
+      }
+    }
+    Ok(())
+  }
+
+  // Where `addr` in the merged object ended up, if its section was placed.
+  fn moved(&self, at: &Addresses, addr: u64) -> Option<u64> {
+    let i = self
+      .object
+      .sections
+      .iter()
+      .position(|sect| addr >= sect.addr && addr < sect.addr + sect.size)?;
+    at.sections[i].map(|(new, old)| new + addr - old)
+  }
+
+  // The merged object's optimization hints, moved to where their sections
+  // were laid out, for the Image to relax. Hints in sections which weren't
+  // placed as they were are dropped.
+  fn optimization_hints(&self, at: &Addresses) -> Vec<Loh> {
+    self
+      .object
+      .loh
+      .iter()
+      .filter_map(|hint| {
+        let addresses: Option<Vec<u64>> =
+          hint.addresses.iter().map(|&addr| self.moved(at, addr)).collect();
+        addresses.map(|addresses| Loh {
+          kind: hint.kind,
+          addresses: addresses,
+        })
+      })
+      .collect()
+  }
+
+  // The debug map: for each input with debug info, an N_OSO stab naming
+  // it and stabs giving where its symbols ended up, which is what dsymutil
+  // (and -dsym) read. Functions get an N_FUN pair, the second with the
+  // size; data an N_STSYM, or N_GSYM for globals, whose address the symbol
+  // table has.
+  fn debug_map<F>(&self, image: &Image, moved: &F) -> Vec<Nlist>
+  where
+    F: Fn(&Nlist) -> Option<Nlist>,
+  {
+    let stab = |n_type: u8, name: &str, n_sect: u8, n_value: u64| Nlist {
+      name: name.to_string(),
+      n_type: n_type,
+      n_sect: n_sect,
+      n_desc: 0,
+      n_value: n_value,
+    };
+    let ends: HashMap<u8, u64> = image
+      .segments
+      .iter()
+      .flat_map(|seg| seg.sections.iter())
+      .enumerate()
+      .map(|(k, sect)| ((k + 1) as u8, sect.addr + sect.size()))
+      .collect();
+    let mut stabs: Vec<Nlist> = Vec::new();
+    for input in self.debug_inputs.iter() {
+      let path = input.path.to_string_lossy();
+      stabs.push(Nlist {
+        n_desc: 1,
+        ..stab(N_OSO, &path, 0, input.mtime)
+      });
+      let mut symbols: Vec<(&Nlist, Nlist)> = input
+        .symbols
+        .iter()
+        .map(|&i| &self.object.symbols[i])
+        .filter_map(|sym| moved(sym).map(|out| (sym, out)))
+        .collect();
+      symbols.sort_by_key(|&(_, ref out)| (out.n_sect, out.n_value));
+      for (k, &(sym, ref out)) in symbols.iter().enumerate() {
+        let flags = self.object.sections[sym.n_sect as usize - 1].flags;
+        if flags & S_ATTR_SOME_INSTRUCTIONS != 0 {
+          let end = match symbols.get(k + 1) {
+            Some(&(_, ref next)) if next.n_sect == out.n_sect => next.n_value,
+            _ => ends.get(&out.n_sect).cloned().unwrap_or(out.n_value),
+          };
+          stabs.push(stab(N_FUN, &out.name, out.n_sect, out.n_value));
+          stabs.push(stab(N_FUN, "", 0, end - out.n_value));
+        } else if out.is_external() {
+          stabs.push(stab(N_GSYM, &out.name, 0, 0));
+        } else {
+          stabs.push(stab(N_STSYM, &out.name, out.n_sect, out.n_value));
+        }
+      }
+      stabs.push(stab(N_SO, "", 1, 0));
+    }
+    stabs
+  }
+
+  // LC_DYLD_CHAINED_FIXUPS: the pointers threaded into chains through each
+  // segment, and the imports they bind to.
+  fn chain(
+    &self,
+    image: &mut Image,
+    pointers: &[(u64, Pointer)],
+  ) -> Result<Vec<u8>> {
+    let format = PointerFormat::for_arch(self.opts.arch);
+    let header = image.segments[0].vmaddr;
+    let mut builder = ChainedFixupsBuilder::new();
+    for import in self.imports.iter() {
+      builder.add_import(chained_fixups::Import {
+        lib_ordinal: import.ordinal as u8,
+        weak_import: import.weak_import,
+        name: import.name.clone(),
+      });
+    }
+    if image.has_pagezero() {
+      builder.add_segment(None);
+    }
+    let page_size = image.page_size;
+    for seg in image.segments.iter_mut() {
+      let fixups: Vec<(u64, Fixup)> = pointers
+        .iter()
+        .filter(|&&(addr, _)| {
+          addr >= seg.vmaddr && addr < seg.vmaddr + seg.vmsize
+        })
+        .map(|&(addr, pointer)| {
+          let fixup = match pointer {
+            // arm64e's plain rebases are to the unslid address.
+            Pointer::Rebase(target) if format == PointerFormat::Arm64e => {
+              Fixup::Rebase {
+                target: target,
+                high8: 0,
+              }
+            }
+            Pointer::Rebase(target) => Fixup::Rebase {
+              target: target.wrapping_sub(header),
+              high8: 0,
+            },
+            Pointer::Bind(import, addend) => Fixup::Bind {
+              ordinal: import as u32,
+              addend: addend as u64,
+            },
+            Pointer::AuthRebase(target, auth) => Fixup::AuthRebase {
+              target: target.wrapping_sub(header),
+              diversity: auth.diversity,
+              addr_div: auth.addr_div,
+              key: auth.key,
+            },
+            Pointer::AuthBind(import, auth) => Fixup::AuthBind {
+              ordinal: import as u32,
+              diversity: auth.diversity,
+              addr_div: auth.addr_div,
+              key: auth.key,
+            },
+          };
+          (addr - seg.vmaddr, fixup)
+        })
+        .collect();
+      if fixups.is_empty() {
+        builder.add_segment(None);
+        continue;
+      }
+      // The chains run through the segment's file contents.
+      let mut data = vec![0; seg.filesize as usize];
+      for sect in seg.sections.iter().filter(|s| !s.is_zerofill()) {
+        let start = (sect.offset - seg.fileoff) as usize;
+        data[start..(start + sect.contents.len())]
+          .copy_from_slice(&sect.contents);
+      }
+      let starts = apply_chains(
+        format,
+        &SegmentFixups {
+          segment_offset: seg.vmaddr - header,
+          page_size: page_size,
+          fixups: fixups,
+          max_valid_pointer: 0,
+        },
+        &mut data,
+      )?;
+      for sect in seg.sections.iter_mut().filter(|s| !s.is_zerofill()) {
+        let start = (sect.offset - seg.fileoff) as usize;
+        let len = sect.contents.len();
+        sect.contents.copy_from_slice(&data[start..(start + len)]);
+      }
+      builder.add_segment(Some(starts));
+    }
+    // __LINKEDIT
+    builder.add_segment(None);
+    Ok(builder.write()?)
+  }
+
+  // The rebase, bind and weak bind opcode streams of LC_DYLD_INFO_ONLY.
+  fn dyld_info(
+    &self,
+    image: &Image,
+    pointers: &[(u64, Pointer)],
+    weak: &[WeakBinding],
+  ) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let mut rebases: Vec<(u8, u64)> = Vec::new();
+    let mut binds: Vec<Binding> = Vec::new();
+    for &(addr, pointer) in pointers.iter() {
+      let (segment, offset) = match segment_offset(image, addr) {
+        Some(found) => found,
+        None => continue,
+      };
+      match pointer {
+        Pointer::Rebase(_) => rebases.push((segment, offset)),
+        Pointer::Bind(import, addend) => {
+          let import = &self.imports[import];
+          binds.push(Binding {
+            ordinal: import.ordinal,
+            name: import.name.clone(),
+            weak_import: import.weak_import,
+            addend: addend,
+            segment: segment,
+            offset: offset,
+          });
+        }
+        // arm64e links, the only ones with signed pointers, are chained.
+        Pointer::AuthRebase(..) | Pointer::AuthBind(..) => unreachable!(),
+      }
+    }
+    (
+      encode_rebase(&rebases),
+      encode_bind(&binds),
+      encode_weak_bind(weak),
+    )
+  }
+
+  // The exported weak definitions, which dyld coalesces with other images'
+  // copies, and the pointers here it has to rebind to whichever copy wins.
+  fn weak_bindings(
+    &self,
+    image: &Image,
+    at: &Addresses,
+    pointers: &[(u64, Pointer)],
+  ) -> Vec<WeakBinding> {
+    let mut bindings: Vec<WeakBinding> = Vec::new();
+    for (i, sym) in self.object.symbols.iter().enumerate() {
+      if !sym.is_weak_def() || !sym.is_external() || sym.is_private_extern() {
+        continue;
+      }
+      let addr = match at.symbols[i] {
+        Some(Target::Address(addr)) => addr,
+        _ => continue,
+      };
+      let locations = pointers
+        .iter()
+        .filter(|&&(_, pointer)| match pointer {
+          Pointer::Rebase(target) | Pointer::AuthRebase(target, _) => {
+            target == addr
+          }
+          Pointer::Bind(..) | Pointer::AuthBind(..) => false,
+        })
+        .filter_map(|&(pc, _)| segment_offset(image, pc))
+        .collect();
+      bindings.push(WeakBinding {
+        name: sym.name.clone(),
+        strong_definition: false,
+        locations: locations,
+      });
+    }
+    bindings
+  }
+
+  // The symbols the image exports, at their offsets from the mach header.
+  fn export_trie(&self, at: &Addresses, header: u64) -> Vec<u8> {
+    let mut exports: Vec<Export> = Vec::new();
+    if self.opts.output_kind == OutputKind::Executable {
+      exports.push(Export {
+        name: EXECUTE_HEADER.to_string(),
+        flags: EXPORT_SYMBOL_FLAGS_KIND_REGULAR,
+        kind: ExportKind::Regular { address: 0 },
+      });
+    }
+    for (i, sym) in self.object.symbols.iter().enumerate() {
+      if !sym.is_external() || sym.is_private_extern() {
+        continue;
+      }
+      let addr = match at.symbols[i] {
+        Some(Target::Address(addr)) => addr,
+        _ => continue,
+      };
+      if is_linker_defined(self.opts, &sym.name) {
+        continue;
+      }
+      let (flags, address) = if sym.n_type & N_TYPE == N_ABS {
+        (EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE, addr)
+      } else if sym.is_weak_def() {
+        (EXPORT_SYMBOL_FLAGS_WEAK_DEFINITION, addr - header)
+      } else {
+        (EXPORT_SYMBOL_FLAGS_KIND_REGULAR, addr - header)
+      };
+      exports.push(Export {
+        name: sym.name.clone(),
+        flags: flags,
+        kind: ExportKind::Regular { address: address },
+      });
+    }
+    ExportTrie::build(&exports).encode()
+  }
+
+  // The merged object's data in code in `sect`, as offsets into it.
+  fn data_in_code(&self, sect: &ObjectSection) -> Vec<DataInCodeEntry> {
+    self
+      .object
+      .data_in_code
+      .iter()
+      .filter(|entry| {
+        let addr = entry.offset as u64;
+        addr >= sect.addr && addr < sect.addr + sect.size
+      })
+      .map(|entry| DataInCodeEntry {
+        offset: (entry.offset as u64 - sect.addr) as u32,
+        ..*entry
+      })
+      .collect()
+  }
+
+  // The pointers for the kext loader to fix up, as it sees them: binds to
+  // the imports' indices in the symbol table, with the addend left in the
+  // pointer, and rebases by the section they point into.
+  fn kext_fixups(
+    &self,
+    image: &mut Image,
+    pointers: &[(u64, Pointer)],
+    index_of: &HashMap<usize, u32>,
+  ) -> Vec<KextFixup> {
+    let base = image.segments[0].vmaddr;
+    let mut fixups: Vec<KextFixup> = Vec::new();
+    for &(addr, pointer) in pointers.iter() {
+      match pointer {
+        Pointer::Bind(import, addend) => {
+          if let Some(found) = find_section(image, addr) {
+            let sect = &mut image.segments[found.0].sections[found.1];
+            let offset = (addr - sect.addr) as usize;
+            set_u64(&mut sect.contents, offset, addend as u64);
+          }
+          fixups.push(KextFixup::Bind {
+            address: addr - base,
+            symbol: index_of[&import],
+          });
+        }
+        Pointer::Rebase(target) => {
+          let section = find_section(image, target)
+            .map_or(0, |found| section_ordinal(image, found));
+          fixups.push(KextFixup::Rebase {
+            address: addr - base,
+            section: section,
+          });
+        }
+        // Kexts aren't linked for arm64e.
+        Pointer::AuthRebase(..) | Pointer::AuthBind(..) => unreachable!(),
+      }
+    }
+    fixups
+  }
+
+  // The symbol table, with the locals first, then the external definitions
+  // and then the imports, each sorted by name. Returns the index of each
+  // import's symbol.
+  fn symbol_table(
+    &self,
+    image: &mut Image,
+    at: &Addresses,
+  ) -> HashMap<usize, u32> {
+    let ordinals: Vec<Option<u8>> = self
+      .placed
+      .iter()
+      .map(|placed| match *placed {
+        Some((ref segname, ref sectname)) => locate(image, segname, sectname)
+          .map(|found| section_ordinal(image, found)),
+        None => None,
+      })
+      .collect();
+    let common = locate(image, "__DATA", "__common")
+      .map_or(0, |found| section_ordinal(image, found));
+    let header = image.segments[0].vmaddr;
+    // Section-relative symbols, moved to their sections in the image.
+    let moved = |sym: &Nlist| -> Option<Nlist> {
+      if sym.n_sect == 0 {
+        return Some(sym.clone());
+      }
+      let j = sym.n_sect as usize - 1;
+      match (at.sections.get(j).and_then(|s| *s), ordinals.get(j)) {
+        (Some((new, old)), Some(&Some(ordinal))) => Some(Nlist {
+          n_sect: ordinal,
+          n_value: new + sym.n_value - old,
+          ..sym.clone()
+        }),
+        _ => None,
+      }
+    };
+
+    let mut locals: Vec<Nlist> = self.debug_map(image, &moved);
+    let mut extdefs: Vec<Nlist> = Vec::new();
+    let mut undefs: Vec<(usize, Nlist)> = Vec::new();
+    for (i, sym) in self.object.symbols.iter().enumerate() {
+      if sym.is_stab() {
+        locals.extend(moved(sym));
+      } else if let Some(&import) = self.import_of.get(&i) {
+        // Kexts are linked with a flat namespace.
+        let ordinal = if self.opts.uses_dyld() {
+          self.imports[import].ordinal as u8 as u16
+        } else {
+          0
+        };
+        let weak = if self.imports[import].weak_import {
+          N_WEAK_REF
+        } else {
+          0
+        };
+        undefs.push((
+          import,
+          Nlist {
+            n_desc: (sym.n_desc & 0xff) | weak | (ordinal << 8),
+            ..sym.clone()
+          },
+        ));
+      } else if let Some(Target::Address(addr)) =
+        self.commons.get(&i).and(at.symbols[i])
+      {
+        extdefs.push(Nlist {
+          n_type: N_SECT | N_EXT,
+          n_sect: common,
+          n_desc: 0,
+          n_value: addr,
+          ..sym.clone()
+        });
+      } else if sym.is_undefined() {
+        if is_linker_defined(self.opts, &sym.name)
+          && sym.name != EXECUTE_HEADER
+        {
+          locals.push(Nlist {
+            n_type: N_SECT | N_PEXT,
+            n_sect: 1,
+            n_desc: 0,
+            n_value: header,
+            ..sym.clone()
+          });
+        }
+      } else if sym.n_type & N_TYPE == N_SECT || sym.n_type & N_TYPE == N_ABS {
+        let sym = match moved(sym) {
+          Some(sym) => sym,
+          None => continue,
+        };
+        if sym.is_external() && !sym.is_private_extern() {
+          extdefs.push(sym);
+        } else {
+          locals.push(sym);
+        }
+      }
+    }
+    if self.opts.output_kind == OutputKind::Executable {
+      extdefs.push(Nlist {
+        name: EXECUTE_HEADER.to_string(),
+        n_type: N_SECT | N_EXT,
+        n_sect: 1,
+        n_desc: REFERENCED_DYNAMICALLY,
+        n_value: header,
+      });
+    }
+    extdefs.sort_by(|a, b| a.name.cmp(&b.name));
+    undefs.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+
+    let first_undef = (locals.len() + extdefs.len()) as u32;
+    let mut index_of: HashMap<usize, u32> = HashMap::new();
+    for (k, &(import, _)) in undefs.iter().enumerate() {
+      index_of.insert(import, first_undef + k as u32);
+    }
+    // An interposable definition's stub and GOT entry name the definition.
+    for (&sym, &import) in self.interposed.iter() {
+      let name = &self.object.symbols[sym].name;
+      if let Ok(k) = extdefs.binary_search_by(|def| def.name.cmp(name)) {
+        index_of.insert(import, (locals.len() + k) as u32);
+      }
+    }
+
+    image.dysymtab.ilocalsym = 0;
+    image.dysymtab.nlocalsym = locals.len() as u32;
+    image.dysymtab.iextdefsym = locals.len() as u32;
+    image.dysymtab.nextdefsym = extdefs.len() as u32;
+    image.dysymtab.iundefsym = first_undef;
+    image.dysymtab.nundefsym = undefs.len() as u32;
+
+    let mut nlists: Vec<u8> = Vec::new();
+    let mut strtab = StringTable::new();
+    let all = locals
+      .iter()
+      .chain(extdefs.iter())
+      .chain(undefs.iter().map(|&(_, ref sym)| sym));
+    for sym in all {
+      sym.write(&mut nlists, &mut strtab);
+    }
+    set_blobs(image, LC_SYMTAB, vec![nlists, strtab.finish()]);
+    index_of
+  }
+
+  // The indirect symbol table, for the stubs and GOT.
+  fn indirect_symbols(&self, index_of: &HashMap<usize, u32>) -> Vec<u8> {
+    let mut indirect: Vec<u8> = Vec::new();
+    for &sym in self.stubs.iter().chain(self.got.iter()) {
+      let index = self
+        .import_of
+        .get(&sym)
+        .or_else(|| self.interposed.get(&sym))
+        .and_then(|import| index_of.get(import))
+        .cloned()
+        .unwrap_or(INDIRECT_SYMBOL_LOCAL);
+      indirect.extend_from_slice(&[
+        index as u8,
+        (index >> 8) as u8,
+        (index >> 16) as u8,
+        (index >> 24) as u8,
+      ]);
+    }
+    indirect
+  }
+}
+
+// Replace the data of the placeholder blobs for `cmd`, in order.
+fn set_blobs(image: &mut Image, cmd: u32, parts: Vec<Vec<u8>>) {
+  let blobs = image.linkedit.iter_mut().filter(|blob| blob.cmd == cmd);
+  for (blob, data) in blobs.zip(parts.into_iter()) {
+    blob.data = data;
+  }
+}
+
+// Store `value` in a fixup of 2^length bytes.
+fn write_value(contents: &mut [u8], offset: usize, length: u8, value: u64) {
+  if length == 3 {
+    set_u64(contents, offset, value);
+  } else {
+    write_u32(contents, offset, value as u32);
+  }
+}
+
+// Link the image, laid out and ready to be written.
+fn lay_out(opts: &LinkOptions) -> Result<Image> {
+  check_supported(opts)?;
+  let mut dylibs = DylibSet::from_options(opts)?;
+  let (mut object, debug_inputs) =
+    relocatable::merge_for_final_link(opts, &dylibs)?;
+  literals::coalesce(opts.arch, &mut object)?;
+  add_aliases(opts, &mut object)?;
+  apply_export_control(opts, &mut object)?;
+  // Dead-strippable dylibs are only loaded if something binds to them.
+  let undefined: Vec<String> = object
+    .symbols
+    .iter()
+    .filter(|sym| sym.is_undefined() && sym.is_external() && !sym.is_common())
+    .map(|sym| sym.name.clone())
+    .collect();
+  dylibs.remove_unused(&undefined);
+  let link = FinalLink::new(opts, object, debug_inputs, &dylibs)?;
+  let mut image = Image::new(opts.arch, opts.output_kind.filetype());
+  link.add_to_image(&mut image, &dylibs)?;
+  let mut finished = Ok(());
+  timing::time("layout", || {
+    image.lay_out_with(|image| finished = link.finish(image))
+  });
+  finished?;
+  Ok(image)
+}
+
+// Link an executable, dylib or bundle, returning its contents.
+pub fn build(opts: &LinkOptions) -> Result<Vec<u8>> {
+  let image = lay_out(opts)?;
+  let mut data = vec![0; image.file_size()];
+  timing::time("write", || image.write_into(&mut data));
+  Ok(data)
+}
+
+// Link an executable, dylib or bundle to the output path, writing the image
+// straight into the output file's mapping.
+pub fn run(opts: &LinkOptions) -> Result<()> {
+  let image = lay_out(opts)?;
+  let path = &opts.output_path;
+  let io_error = |e| FinalLinkError::IoError(path.clone(), e);
+  let len = image.file_size() as u64;
+  timing::time("write", || {
+    output::write(path, len, |buf| image.write_into(buf))
+  }).map_err(io_error)?;
+  fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+    .map_err(io_error)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::env;
+  use std::path::Path;
+  use std::process;
+  use std::sync::Arc;
+
+  use dsym;
+  use dyld_check;
+  use image::{SectionAlignment, SegmentProtection};
+  use kext::KextError;
+  use macho::chained_fixups::DYLD_CHAINED_PTR_ARM64E;
+  use macho::data_in_code::{self, DICE_KIND_JUMP_TABLE16};
+  use macho::dylib_file::DylibFile;
+  use macho::dyld_info::{BIND_OPCODE_SET_DYLIB_ORDINAL_IMM,
+                         BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM,
+                         BIND_SYMBOL_FLAGS_WEAK_IMPORT};
+  use macho::export_trie;
+  use macho::function_starts;
+  use macho::image_file::ImageFile;
+  use macho::load_command::{load_commands, Section64, LC_ID_DYLIB,
+                            LC_BUILD_VERSION, LC_CODE_SIGNATURE,
+                            LC_DATA_IN_CODE, LC_DYLD_INFO_ONLY, LC_DYSYMTAB,
+                            LC_ENCRYPTION_INFO_64,
+                            LC_FUNCTION_STARTS, LC_LOAD_DYLIB,
+                            LC_LOAD_UPWARD_DYLIB, LC_LOAD_WEAK_DYLIB,
+                            LC_LOAD_DYLINKER, LC_MAIN, LC_NOTE, LC_RPATH,
+                            LC_SOURCE_VERSION, LC_SUB_CLIENT,
+                            LC_SUB_FRAMEWORK, LC_UNIXTHREAD,
+                            LC_VERSION_MIN_MACOSX};
+  use macho::loh::LOH_ARM64_ADRP_ADD;
+  use macho::reloc::RelocationInfo;
+  use macho::split_seg::{self, Reference};
+  use macho::{get_u32, get_u64, put_u32, put_u64, Platform, SourceVersion,
+              Version,
+              CPU_SUBTYPE_ARM64E, CPU_TYPE_X86_64, MH_APP_EXTENSION_SAFE,
+              MH_BUNDLE, MH_DEAD_STRIPPABLE_DYLIB, MH_DYLIB,
+              MH_EXECUTE, MH_HAS_TLV_DESCRIPTORS, MH_KEXT_BUNDLE, MH_PIE,
+              MH_PRELOAD,
+              MH_NO_REEXPORTED_DYLIBS, MH_SUBSECTIONS_VIA_SYMBOLS,
+              SG_READ_ONLY, S_CSTRING_LITERALS, S_DTRACE_DOF, VM_PROT_READ,
+              VM_PROT_WRITE};
+  use args::CreatedSection;
+  use parallel;
+  use relocatable::RelocatableInput;
+  use strip::{self, StripOptions};
+  use target::TargetError;
+  use undefined::UndefinedTreatment;
+  use uuid::UuidMode;
+
+  fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(name)
+  }
+
+  fn scratch_dir(name: &str) -> PathBuf {
+    let dir =
+      env::temp_dir().join(format!("mold-final-{}-{}", name, process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn nlist(name: &str, n_type: u8, n_sect: u8, n_value: u64) -> Nlist {
+    Nlist {
+      name: name.to_string(),
+      n_type: n_type,
+      n_sect: n_sect,
+      n_desc: 0,
+      n_value: n_value,
+    }
+  }
+
+  // An object of `sections` and `symbols`, as relocatable::link writes it.
+  fn object_of(
+    arch: Arch,
+    sections: Vec<ObjectSection>,
+    symbols: Vec<Nlist>,
+  ) -> Vec<u8> {
+    object_for(arch, Platform::MacOS, sections, symbols)
+  }
+
+  fn object_for(
+    arch: Arch,
+    platform: Platform,
+    sections: Vec<ObjectSection>,
+    symbols: Vec<Nlist>,
+  ) -> Vec<u8> {
+    let version = Version::new(11, 0, 0);
+    write_object(
+      arch,
+      ObjectFile {
+        cputype: arch.cputype(),
+        cpusubtype: arch.cpusubtype(),
+        flags: MH_SUBSECTIONS_VIA_SYMBOLS,
+        sections: sections,
+        symbols: symbols,
+        platform: Some((platform, version)),
+        loh: Vec::new(),
+        data_in_code: Vec::new(),
+      },
+    )
+  }
+
+  fn write_object(arch: Arch, object: ObjectFile) -> Vec<u8> {
+    let (platform, version) = object.platform.unwrap();
+    let target = target::Target {
+      platform: platform,
+      min_version: version,
+      sdk_version: version,
+      variant: None,
+    };
+    let input = RelocatableInput {
+      path: PathBuf::from("input.o"),
+      object: object,
+    };
+    relocatable::link(arch, &target, &[input], false).unwrap()
+  }
+
+  fn text_section(code: Vec<u8>, relocs: Vec<RelocationInfo>) -> ObjectSection {
+    ObjectSection {
+      segname: "__TEXT".to_string(),
+      sectname: "__text".to_string(),
+      size: code.len() as u64,
+      align: 2,
+      flags: S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS,
+      contents: code,
+      relocs: relocs,
+      ..Default::default()
+    }
+  }
+
+  // An object whose __text is `code`, defining `defined` at its start and
+  // with `relocs` against the undefined `undefined`.
+  fn object(
+    arch: Arch,
+    code: Vec<u8>,
+    relocs: Vec<RelocationInfo>,
+    defined: &str,
+    undefined: &str,
+  ) -> Vec<u8> {
+    let mut symbols = vec![nlist(defined, N_SECT | N_EXT, 1, 0)];
+    if !undefined.is_empty() {
+      symbols.push(nlist(undefined, N_EXT, 0, 0));
+    }
+    object_of(arch, vec![text_section(code, relocs)], symbols)
+  }
+
+  fn options(kind: OutputKind, inputs: Vec<(&str, Vec<u8>)>) -> LinkOptions {
+    let mut opts = LinkOptions {
+      output_kind: kind,
+      min_os_version: Version::new(11, 0, 0),
+      ..Default::default()
+    };
+    for (name, data) in inputs {
+      opts.input_paths.push(PathBuf::from(name));
+      opts.input_buffers.push((PathBuf::from(name), Arc::new(data)));
+    }
+    opts
+  }
+
+  // libputs.dylib, whose _puts just returns.
+  fn libputs(dir: &Path) -> PathBuf {
+    let code = object(Arch::X86_64, vec![0xc3], Vec::new(), "_puts", "");
+    let mut opts = options(OutputKind::Dylib, vec![("puts.o", code)]);
+    opts.install_name = Some("/usr/lib/libputs.dylib".to_string());
+    let path = dir.join("libputs.dylib");
+    fs::write(&path, build(&opts).unwrap()).unwrap();
+    path
+  }
+
+  fn section<'a>(image: &'a ImageFile, sectname: &str) -> &'a Section64 {
+    image
+      .segments
+      .iter()
+      .flat_map(|seg| seg.sections.iter())
+      .find(|sect| sect.sectname == sectname)
+      .unwrap()
+  }
+
+  fn read_rel32(data: &[u8], offset: u64) -> u64 {
+    read_u32(data, offset as usize) as i32 as i64 as u64
+  }
+
+  // test.o's main calls puts: where the call goes, and where the stub it
+  // goes to jumps.
+  fn call_to_puts(data: &[u8]) -> (u64, u64) {
+    let image = ImageFile::parse(data).unwrap();
+    let text = section(&image, "__text");
+    let stubs = section(&image, "__stubs");
+    let call = text.addr + 0x10 + read_rel32(data, text.offset as u64 + 0xc);
+    assert_eq!(call, stubs.addr);
+    let jump = stubs.addr + 6 + read_rel32(data, stubs.offset as u64 + 2);
+    (call, jump)
+  }
+
+  #[test]
+  fn calls_dylib_functions_through_stubs() {
+    let dir = scratch_dir("stubs");
+    let mut opts = LinkOptions {
+      fixup_chains: Some(false),
+      ..Default::default()
+    };
+    opts.input_paths = vec![fixture("test.o"), libputs(&dir)];
+    let data = build(&opts).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(dyld_check::check(&data).unwrap(), Vec::<String>::new());
+
+    let image = ImageFile::parse(&data).unwrap();
+    let (_, jump) = call_to_puts(&data);
+    assert_eq!(jump, section(&image, "__got").addr);
+    let puts = image.symbols.iter().find(|s| s.name == "_puts").unwrap();
+    assert!(puts.is_undefined());
+    assert_eq!(puts.n_desc >> 8, 1);
+    let main = load_commands(&data)
+      .unwrap()
+      .into_iter()
+      .find(|command| command.cmd == LC_MAIN)
+      .unwrap();
+    let entryoff = get_u64(&data, main.offset + 8, "").unwrap();
+    let text = section(&image, "__text");
+    let base = image.segment("__TEXT").unwrap().vmaddr;
+    assert_eq!(entryoff, text.addr - base);
+    assert!(get_u32(&data, 24, "").unwrap() & MH_NOUNDEFS != 0);
+  }
+
+  #[test]
+  fn binds_through_chained_fixups() {
+    let mut opts = LinkOptions {
+      fixup_chains: Some(true),
+      undefined: Some(UndefinedTreatment::DynamicLookup),
+      ..Default::default()
+    };
+    opts.input_paths.push(fixture("test.o"));
+    let data = build(&opts).unwrap();
+    assert_eq!(dyld_check::check(&data).unwrap(), Vec::<String>::new());
+    // Left for dyld to find anywhere, which the header has to allow.
+    assert!(get_u32(&data, 24, "").unwrap() & MH_NOUNDEFS == 0);
+
+    let image = ImageFile::parse(&data).unwrap();
+    let got = section(&image, "__got");
+    let (_, jump) = call_to_puts(&data);
+    assert_eq!(jump, got.addr);
+    // The GOT entry binds to the first import, ending its chain.
+    let slot = get_u64(&data, got.offset as usize, "").unwrap();
+    assert_eq!(slot, 1 << 63);
+  }
+
+  // An arm64 executable whose main calls puts, left to dyld to find.
+  fn arm64_main() -> LinkOptions {
+    // bl _puts; ret
+    let code = vec![0x00, 0x00, 0x00, 0x94, 0xc0, 0x03, 0x5f, 0xd6];
+    let call = RelocationInfo {
+      address: 0,
+      symbolnum: 1,
+      pcrel: true,
+      length: 2,
+      external: true,
+      kind: ARM64_RELOC_BRANCH26,
+      scattered: None,
+    };
+    let main = object(Arch::Arm64, code, vec![call], "_main", "_puts");
+    let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    opts.arch = Arch::Arm64;
+    opts.undefined = Some(UndefinedTreatment::DynamicLookup);
+    opts
+  }
+
+  #[test]
+  fn links_arm64_calls_through_stubs() {
+    let opts = arm64_main();
+    let data = build(&opts).unwrap();
+
+    let image = ImageFile::parse(&data).unwrap();
+    let text = section(&image, "__text");
+    let stubs = section(&image, "__stubs");
+    let got = section(&image, "__got");
+    let bl = read_u32(&data, text.offset as usize);
+    let disp = ((bl << 6) as i32 >> 4) as i64 as u64;
+    assert_eq!(text.addr.wrapping_add(disp), stubs.addr);
+    // adrp x16 and ldr x16 reach the GOT entry.
+    let adrp = read_u32(&data, stubs.offset as usize);
+    let page = (((adrp >> 29) & 3) | ((adrp >> 3) & 0x1f_fffc)) as u64;
+    assert_eq!((stubs.addr >> 12) + page, got.addr >> 12);
+    let ldr = read_u32(&data, stubs.offset as usize + 4);
+    assert_eq!(((ldr >> 10) & 0xfff) as u64 * 8, got.addr & 0xfff);
+  }
+
+  #[test]
+  fn signs_arm64e_pointers_through_chained_fixups() {
+    // A plain pointer to _main, then signed ones to _main and _puts, with
+    // the IA key and a discriminator of 0x1234 blended with the address.
+    let signed = (1 << 63) | (1 << 48) | (0x1234 << 32);
+    let mut contents = vec![0; 24];
+    set_u64(&mut contents, 8, signed);
+    set_u64(&mut contents, 16, signed);
+    let pointer = |address: u32, symbolnum: u32, kind: u8| RelocationInfo {
+      address: address,
+      symbolnum: symbolnum,
+      pcrel: false,
+      length: 3,
+      external: true,
+      kind: kind,
+      scattered: None,
+    };
+    let data = ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__data".to_string(),
+      addr: 8,
+      size: 24,
+      align: 3,
+      contents: contents,
+      relocs: vec![
+        pointer(0, 0, ARM64_RELOC_UNSIGNED),
+        pointer(8, 0, ARM64_RELOC_AUTHENTICATED_POINTER),
+        pointer(16, 1, ARM64_RELOC_AUTHENTICATED_POINTER),
+      ],
+      ..Default::default()
+    };
+    // bl _puts; ret
+    let code = vec![0x00, 0x00, 0x00, 0x94, 0xc0, 0x03, 0x5f, 0xd6];
+    let call = RelocationInfo {
+      address: 0,
+      symbolnum: 1,
+      pcrel: true,
+      length: 2,
+      external: true,
+      kind: ARM64_RELOC_BRANCH26,
+      scattered: None,
+    };
+    let main = object_of(
+      Arch::Arm64e,
+      vec![text_section(code, vec![call]), data],
+      vec![nlist("_main", N_SECT | N_EXT, 1, 0), nlist("_puts", N_EXT, 0, 0)],
+    );
+    let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    opts.arch = Arch::Arm64e;
+    opts.undefined = Some(UndefinedTreatment::DynamicLookup);
+    let out = build(&opts).unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+    assert_eq!(get_u32(&out, 8, "").unwrap(), CPU_SUBTYPE_ARM64E);
+
+    let image = ImageFile::parse(&out).unwrap();
+    let header = image.segment("__TEXT").unwrap().vmaddr;
+    let main = section(&image, "__text").addr;
+    let data = section(&image, "__data").offset as usize;
+    // DYLD_CHAINED_PTR_ARM64E: a plain rebase to the unslid address, and
+    // an authenticated one to the offset from the header.
+    let plain = get_u64(&out, data, "").unwrap();
+    assert_eq!(plain >> 62, 0);
+    assert_eq!(plain & 0x7ff_ffff_ffff, main);
+    let rebase = get_u64(&out, data + 8, "").unwrap();
+    assert_eq!(rebase >> 62, 0b10);
+    assert_eq!(rebase & 0xffff_ffff, main - header);
+    assert_eq!((rebase >> 32) & 0xffff, 0x1234);
+    assert_eq!((rebase >> 48) & 7, 1);
+    let bind = get_u64(&out, data + 16, "").unwrap();
+    assert_eq!(bind >> 62, 0b11);
+    assert_eq!(bind & 0xffff, 0);
+    assert_eq!((bind >> 32) & 0xffff, 0x1234);
+    // The stub's GOT entry is a plain bind.
+    let got = section(&image, "__got").offset as usize;
+    assert_eq!(get_u64(&out, got, "").unwrap() >> 62, 0b01);
+    let fixups = linkedit_data(&out, LC_DYLD_CHAINED_FIXUPS);
+    let starts = get_u32(fixups, 4, "").unwrap() as usize;
+    let formats: Vec<u16> = (0..get_u32(fixups, starts, "").unwrap() as usize)
+      .map(|k| get_u32(fixups, starts + 4 + 4 * k, "").unwrap() as usize)
+      .filter(|&offset| offset != 0)
+      .map(|offset| {
+        let at = starts + offset + 6;
+        fixups[at] as u16 | (fixups[at + 1] as u16) << 8
+      })
+      .collect();
+    // The GOT's segment and __DATA.
+    assert_eq!(formats, vec![DYLD_CHAINED_PTR_ARM64E; 2]);
+
+    opts.fixup_chains = Some(false);
+    match build(&opts) {
+      Err(FinalLinkError::Unsupported(ref what)) => {
+        assert!(what.contains("arm64e"))
+      }
+      other => panic!("{:?}", other.map(|data| data.len())),
+    }
+  }
+
+  #[test]
+  fn signs_arm64_outputs() {
+    let mut opts = arm64_main();
+    opts.output_path = PathBuf::from("out/hello");
+    let data = build(&opts).unwrap();
+    assert_eq!(dyld_check::check(&data).unwrap(), Vec::<String>::new());
+    let verification = codesign::verify(&data).unwrap().unwrap();
+    assert!(verification.is_valid());
+    assert_eq!(verification.identifier, "hello");
+
+    opts.adhoc_codesign = Some(false);
+    let data = build(&opts).unwrap();
+    assert!(codesign::verify(&data).unwrap().is_none());
+  }
+
+  #[test]
+  fn writes_outputs_in_place() {
+    // The image is written, and signed, straight into the output's mapping.
+    let dir = scratch_dir("in-place");
+    let mut opts = arm64_main();
+    opts.output_path = dir.join("hello");
+    run(&opts).unwrap();
+    let data = fs::read(&opts.output_path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(codesign::verify(&data).unwrap().unwrap().is_valid());
+    assert_eq!(data, build(&opts).unwrap());
+  }
+
+  #[test]
+  fn signs_every_page_ad_hoc() {
+    let be32 = |data: &[u8], offset: usize| {
+      get_u32(data, offset, "").unwrap().swap_bytes()
+    };
+    let mut opts = arm64_main();
+    opts.output_path = PathBuf::from("out/hello");
+    let data = build(&opts).unwrap();
+    let signature = load_commands(&data)
+      .unwrap()
+      .into_iter()
+      .find(|command| command.cmd == LC_CODE_SIGNATURE)
+      .unwrap();
+    let dataoff = get_u32(&data, signature.offset + 8, "").unwrap() as usize;
+    let datasize = get_u32(&data, signature.offset + 12, "").unwrap();
+    assert_eq!(dataoff + datasize as usize, data.len());
+
+    // A SuperBlob holding one CodeDirectory.
+    assert_eq!(be32(&data, dataoff), 0xfade_0cc0);
+    assert_eq!(be32(&data, dataoff + 8), 1);
+    let cd = dataoff + be32(&data, dataoff + 16) as usize;
+    assert_eq!(be32(&data, cd), 0xfade_0c02);
+    // Ad hoc and linker-signed.
+    assert_eq!(be32(&data, cd + 12), 0x2_0002);
+    let ident = cd + be32(&data, cd + 20) as usize;
+    assert_eq!(&data[ident..ident + 6], b"hello\0");
+    let pages = be32(&data, cd + 28) as usize;
+    assert_eq!(be32(&data, cd + 32) as usize, dataoff);
+    assert_eq!(data[cd + 39], 12);
+    assert_eq!(pages, (dataoff + 4095) / 4096);
+    let hashes = cd + be32(&data, cd + 16) as usize;
+    for page in 0..pages {
+      let end = dataoff.min((page + 1) * 4096);
+      let hash = codesign::sha256(&data[page * 4096..end]);
+      assert_eq!(&data[hashes + 32 * page..][..32], &hash[..]);
+    }
+
+    // x86_64 outputs are only signed when asked.
+    let main = object(Arch::X86_64, vec![0xc3], Vec::new(), "_main", "");
+    let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    assert!(codesign::verify(&build(&opts).unwrap()).unwrap().is_none());
+    opts.adhoc_codesign = Some(true);
+    let data = build(&opts).unwrap();
+    assert!(codesign::verify(&data).unwrap().unwrap().is_valid());
+  }
+
+  #[test]
+  fn embeds_entitlements_and_runtime_flags() {
+    let be32 = |data: &[u8], offset: usize| {
+      get_u32(data, offset, "").unwrap().swap_bytes()
+    };
+    let dir = scratch_dir("entitlements");
+    let plist = dir.join("app.entitlements");
+    let entitlements = b"<plist><dict/></plist>\n".to_vec();
+    fs::write(&plist, &entitlements).unwrap();
+    let mut opts = arm64_main();
+    opts.entitlements = Some(plist.clone());
+    opts.hardened_runtime = true;
+    opts.library_validation = true;
+    let data = build(&opts).unwrap();
+    assert!(codesign::verify(&data).unwrap().unwrap().is_valid());
+
+    let signature = load_commands(&data)
+      .unwrap()
+      .into_iter()
+      .find(|command| command.cmd == LC_CODE_SIGNATURE)
+      .unwrap();
+    let dataoff = get_u32(&data, signature.offset + 8, "").unwrap() as usize;
+    // The CodeDirectory, the requirements and the entitlements.
+    assert_eq!(be32(&data, dataoff + 8), 3);
+    let blob = |k: usize| {
+      let slot = be32(&data, dataoff + 12 + 8 * k);
+      (slot, dataoff + be32(&data, dataoff + 16 + 8 * k) as usize)
+    };
+    let (_, cd) = blob(0);
+    // Ad hoc, linker-signed, the hardened runtime and library validation.
+    assert_eq!(be32(&data, cd + 12), 0x2_0002 | 0x1_0000 | 0x2000);
+    let (slot, at) = blob(2);
+    assert_eq!(slot, 5);
+    assert_eq!(be32(&data, at), 0xfade_7171);
+    let size = be32(&data, at + 4) as usize;
+    assert_eq!(&data[at + 8..at + size], &entitlements[..]);
+    // Special slot 5 is the fifth hash before the page hashes.
+    let hashes = cd + be32(&data, cd + 16) as usize;
+    let hash = codesign::sha256(&data[at..at + size]);
+    assert_eq!(&data[hashes - 5 * 32..][..32], &hash[..]);
+
+    // The simulator reads them from a section instead.
+    opts.platform = Platform::IOSSimulator;
+    let symbols = vec![nlist("_main", N_SECT | N_EXT, 1, 0)];
+    let text = vec![text_section(vec![0xc3], Vec::new())];
+    let main = object_for(Arch::X86_64, opts.platform, text, symbols);
+    opts.input_paths = vec![PathBuf::from("main.o")];
+    opts.input_buffers = vec![(PathBuf::from("main.o"), Arc::new(main))];
+    opts.arch = Arch::X86_64;
+    opts.adhoc_codesign = Some(true);
+    let result = build(&opts);
+    fs::remove_dir_all(&dir).unwrap();
+    let data = result.unwrap();
+    let image = ImageFile::parse(&data).unwrap();
+    let sect = section(&image, "__entitlements");
+    let start = sect.offset as usize;
+    assert_eq!(&data[start..start + entitlements.len()], &entitlements[..]);
+  }
+
+  #[test]
+  fn requires_main_in_executables() {
+    let code = object(Arch::X86_64, vec![0xc3], Vec::new(), "_start", "");
+    let opts = options(OutputKind::Executable, vec![("start.o", code)]);
+    match build(&opts) {
+      Err(FinalLinkError::NoEntryPoint(ref name)) if name == "_main" => (),
+      other => panic!("{:?}", other.map(|data| data.len())),
+    }
+  }
+
+  // start.o: `start` returns, and __data holds a pointer to it.
+  fn start_object() -> Vec<u8> {
+    let data = ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__data".to_string(),
+      addr: 8,
+      size: 8,
+      align: 3,
+      contents: vec![0; 8],
+      relocs: vec![RelocationInfo {
+        address: 0,
+        symbolnum: 0,
+        pcrel: false,
+        length: 3,
+        external: true,
+        kind: X86_64_RELOC_UNSIGNED,
+        scattered: None,
+      }],
+      ..Default::default()
+    };
+    let text = text_section(vec![0xc3], Vec::new());
+    let symbols = vec![nlist("start", N_SECT | N_EXT, 1, 0)];
+    object_of(Arch::X86_64, vec![text, data], symbols)
+  }
+
+  #[test]
+  fn links_outputs_dyld_does_not_load() {
+    let has = |out: &[u8], cmd: u32| {
+      load_commands(out).unwrap().iter().any(|command| command.cmd == cmd)
+    };
+    // The loader starts the thread at `start`, at its link address.
+    let check = |out: &[u8]| {
+      assert!(!has(out, LC_LOAD_DYLINKER) && !has(out, LC_MAIN));
+      assert!(!has(out, LC_DYLD_INFO_ONLY));
+      let image = ImageFile::parse(out).unwrap();
+      let text = section(&image, "__text");
+      let data = section(&image, "__data");
+      assert_eq!(get_u64(out, data.offset as usize, "").unwrap(), text.addr);
+      let thread = load_commands(out)
+        .unwrap()
+        .into_iter()
+        .find(|command| command.cmd == LC_UNIXTHREAD)
+        .unwrap()
+        .offset;
+      assert_eq!(get_u64(out, thread + 16 + 8 * 16, "").unwrap(), text.addr);
+      image
+    };
+
+    let mut opts =
+      options(OutputKind::Executable, vec![("start.o", start_object())]);
+    opts.static_link = true;
+    opts.image_base = Some(0x20_0000);
+    opts.segment_addresses = vec![("__DATA".to_string(), 0x40_0000)];
+    let out = build(&opts).unwrap();
+    let image = check(&out);
+    assert_eq!(image.filetype, MH_EXECUTE);
+    assert_eq!(get_u32(&out, 24, "").unwrap() & MH_PIE, 0);
+    assert_eq!(image.segments[0].segname, "__PAGEZERO");
+    assert_eq!(image.segment("__TEXT").unwrap().vmaddr, 0x20_0000);
+    assert_eq!(image.segment("__DATA").unwrap().vmaddr, 0x40_0000);
+
+    let opts = options(OutputKind::Preload, vec![("start.o", start_object())]);
+    let out = build(&opts).unwrap();
+    let image = check(&out);
+    assert_eq!(image.filetype, MH_PRELOAD);
+    assert_eq!(image.segments[0].segname, "__TEXT");
+
+    // Nothing is left for dyld to bind.
+    let code = object(Arch::X86_64, vec![0xc3], Vec::new(), "start", "_f");
+    let mut opts = options(OutputKind::Preload, vec![("start.o", code)]);
+    opts.undefined = Some(UndefinedTreatment::DynamicLookup);
+    match build(&opts) {
+      Err(FinalLinkError::Unsupported(_)) => (),
+      other => panic!("{:?}", other.map(|data| data.len())),
+    }
+  }
+
+  #[test]
+  fn lays_out_thread_local_variables() {
+    // movq _v@TLVP(%rip), %rdi; callq *(%rdi); retq
+    let code = vec![0x48, 0x8b, 0x3d, 0, 0, 0, 0, 0xff, 0x17, 0xc3];
+    let reloc = |address: u32, symbolnum: u32, kind: u8, pcrel: bool| {
+      RelocationInfo {
+        address: address,
+        symbolnum: symbolnum,
+        pcrel: pcrel,
+        length: if pcrel { 2 } else { 3 },
+        external: true,
+        kind: kind,
+        scattered: None,
+      }
+    };
+    let vars = ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__thread_vars".to_string(),
+      addr: 0x10,
+      size: 24,
+      align: 3,
+      flags: S_THREAD_LOCAL_VARIABLES,
+      contents: vec![0; 24],
+      relocs: vec![
+        reloc(0, 3, X86_64_RELOC_UNSIGNED, false),
+        reloc(16, 2, X86_64_RELOC_UNSIGNED, false),
+      ],
+      ..Default::default()
+    };
+    let data = ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__thread_data".to_string(),
+      addr: 0x28,
+      size: 8,
+      align: 3,
+      flags: S_THREAD_LOCAL_REGULAR,
+      contents: vec![7, 0, 0, 0, 0, 0, 0, 0],
+      ..Default::default()
+    };
+    let text = text_section(code, vec![reloc(3, 1, X86_64_RELOC_TLV, true)]);
+    let main = object_of(
+      Arch::X86_64,
+      vec![text, vars, data],
+      vec![
+        nlist("_main", N_SECT | N_EXT, 1, 0),
+        nlist("_v", N_SECT | N_EXT, 2, 0x10),
+        nlist("_v$tlv$init", N_SECT, 3, 0x28),
+        nlist(tlv::TLV_BOOTSTRAP_SYMBOL, N_EXT, 0, 0),
+      ],
+    );
+    let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    opts.undefined = Some(UndefinedTreatment::DynamicLookup);
+    opts.fixup_chains = Some(true);
+    let out = build(&opts).unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+    let flags = get_u32(&out, 24, "").unwrap();
+    assert!(flags & MH_HAS_TLV_DESCRIPTORS != 0);
+
+    let image = ImageFile::parse(&out).unwrap();
+    let text = section(&image, "__text");
+    let vars = section(&image, "__thread_vars");
+    // The load of the descriptor's address became a leaq of it.
+    assert_eq!(out[text.offset as usize + 1], 0x8d);
+    let disp = read_rel32(&out, text.offset as u64 + 3);
+    assert_eq!(text.addr + 7 + disp, vars.addr);
+    // The thunk binds to __tlv_bootstrap and the variable is at the start
+    // of the template.
+    let thunk = get_u64(&out, vars.offset as usize, "").unwrap();
+    assert_eq!(thunk >> 63, 1);
+    let init = get_u64(&out, vars.offset as usize + 16, "").unwrap();
+    assert_eq!(init, 0);
+  }
+
+  // main.o's __mod_init_func runs _second, and -init names _first.
+  fn initializers(fixup_chains: bool) -> (Vec<u8>, u64) {
+    let reloc = RelocationInfo {
+      address: 0,
+      symbolnum: 2,
+      pcrel: false,
+      length: 3,
+      external: true,
+      kind: X86_64_RELOC_UNSIGNED,
+      scattered: None,
+    };
+    let init_funcs = ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__mod_init_func".to_string(),
+      addr: 8,
+      size: 8,
+      align: 3,
+      flags: S_MOD_INIT_FUNC_POINTERS,
+      contents: vec![0; 8],
+      relocs: vec![reloc],
+      ..Default::default()
+    };
+    let text = text_section(vec![0xc3, 0xc3, 0xc3], Vec::new());
+    let main = object_of(
+      Arch::X86_64,
+      vec![text, init_funcs],
+      vec![
+        nlist("_main", N_SECT | N_EXT, 1, 0),
+        nlist("_first", N_SECT | N_EXT, 1, 1),
+        nlist("_second", N_SECT | N_EXT, 1, 2),
+      ],
+    );
+    let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    opts.fixup_chains = Some(fixup_chains);
+    opts.init_symbol = Some("_first".to_string());
+    let out = build(&opts).unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+    let text = section(&ImageFile::parse(&out).unwrap(), "__text").addr;
+    (out, text)
+  }
+
+  #[test]
+  fn runs_the_init_symbol_first() {
+    let (out, text) = initializers(false);
+    let image = ImageFile::parse(&out).unwrap();
+    let init_funcs = section(&image, "__mod_init_func");
+    assert_eq!(init_funcs.flags, S_MOD_INIT_FUNC_POINTERS);
+    let at = |k: usize| {
+      get_u64(&out, init_funcs.offset as usize + 8 * k, "").unwrap()
+    };
+    assert_eq!((at(0), at(1)), (text + 1, text + 2));
+
+    // Offsets from the mach header where the fixups are chained.
+    let (out, text) = initializers(true);
+    let image = ImageFile::parse(&out).unwrap();
+    let offsets = section(&image, "__init_offsets");
+    let base = image.segment("__TEXT").unwrap().vmaddr;
+    let at = |k: usize| {
+      get_u32(&out, offsets.offset as usize + 4 * k, "").unwrap() as u64
+    };
+    assert_eq!((at(0), at(1)), (text + 1 - base, text + 2 - base));
+  }
+
+  #[test]
+  fn requires_the_init_symbol() {
+    let code = object(Arch::X86_64, vec![0xc3], Vec::new(), "_main", "");
+    let mut opts = options(OutputKind::Executable, vec![("main.o", code)]);
+    opts.init_symbol = Some("_setup".to_string());
+    match build(&opts) {
+      Err(FinalLinkError::NoInitSymbol(ref name)) if name == "_setup" => (),
+      other => panic!("{:?}", other.map(|data| data.len())),
+    }
+  }
+
+  #[test]
+  fn stores_each_literal_once() {
+    // leaq L_hi(%rip), %rdi; leaq L_hi.1(%rip), %rsi; retq
+    let code = vec![
+      0x48, 0x8d, 0x3d, 9, 0, 0, 0, 0x48, 0x8d, 0x35, 5, 0, 0, 0, 0xc3,
+    ];
+    let reloc = |address: u32| RelocationInfo {
+      address: address,
+      symbolnum: 2,
+      pcrel: true,
+      length: 2,
+      external: false,
+      kind: X86_64_RELOC_SIGNED,
+      scattered: None,
+    };
+    let strings = ObjectSection {
+      segname: "__TEXT".to_string(),
+      sectname: "__cstring".to_string(),
+      addr: 0x10,
+      size: 6,
+      align: 0,
+      flags: S_CSTRING_LITERALS,
+      contents: b"hi\0hi\0".to_vec(),
+      ..Default::default()
+    };
+    let text = text_section(code, vec![reloc(3), reloc(10)]);
+    let main = object_of(
+      Arch::X86_64,
+      vec![text, strings],
+      vec![nlist("_main", N_SECT | N_EXT, 1, 0)],
+    );
+    let opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    let out = build(&opts).unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+
+    let image = ImageFile::parse(&out).unwrap();
+    let text = section(&image, "__text");
+    let strings = section(&image, "__cstring");
+    assert_eq!(strings.size, 3);
+    let offset = strings.offset as usize;
+    assert_eq!(&out[offset..offset + 3], b"hi\0");
+    let first = text.addr + 7 + read_rel32(&out, text.offset as u64 + 3);
+    let second = text.addr + 14 + read_rel32(&out, text.offset as u64 + 10);
+    assert_eq!((first, second), (strings.addr, strings.addr));
+  }
+
+  // The names in the export trie of `out`, which has chained fixups, sorted.
+  fn exported(out: &[u8]) -> Vec<String> {
+    let trie = load_commands(out)
+      .unwrap()
+      .into_iter()
+      .find(|command| command.cmd == LC_DYLD_EXPORTS_TRIE)
+      .unwrap();
+    let offset = get_u32(out, trie.offset + 8, "").unwrap() as usize;
+    let size = get_u32(out, trie.offset + 12, "").unwrap() as usize;
+    let exports = export_trie::parse(&out[offset..offset + size]).unwrap();
+    let mut names: Vec<String> =
+      exports.into_iter().map(|export| export.name).collect();
+    names.sort();
+    names
+  }
+
+  // libboth.dylib, defining _kept and _hidden, linked with `configure`.
+  fn libboth<F: FnOnce(&mut LinkOptions)>(configure: F) -> Result<Vec<u8>> {
+    let text = text_section(vec![0xc3, 0xc3], Vec::new());
+    let code = object_of(
+      Arch::X86_64,
+      vec![text],
+      vec![
+        nlist("_kept", N_SECT | N_EXT, 1, 0),
+        nlist("_hidden", N_SECT | N_EXT, 1, 1),
+      ],
+    );
+    let mut opts = options(OutputKind::Dylib, vec![("both.o", code)]);
+    opts.fixup_chains = Some(true);
+    configure(&mut opts);
+    build(&opts)
+  }
+
+  #[test]
+  fn exports_only_the_listed_symbols() {
+    let out = libboth(|_| ()).unwrap();
+    assert_eq!(exported(&out), vec!["_hidden", "_kept"]);
+
+    let out = libboth(|opts| opts.exported_symbols.push("_k*".to_string()));
+    let out = out.unwrap();
+    assert_eq!(exported(&out), vec!["_kept"]);
+    let image = ImageFile::parse(&out).unwrap();
+    let hidden = image.symbols.iter().find(|s| s.name == "_hidden").unwrap();
+    assert!(!hidden.is_external() && hidden.is_private_extern());
+
+    let out = libboth(|opts| opts.unexported_symbols.push("_kept".to_string()));
+    assert_eq!(exported(&out.unwrap()), vec!["_hidden"]);
+
+    let missing = libboth(|opts| opts.exported_symbols.push("_gone".into()));
+    match missing {
+      Err(FinalLinkError::Resolve(ResolveError::ExportedSymbolNotDefined(
+        ref name,
+      ))) if name == "_gone" => (),
+      other => panic!("{:?}", other.map(|data| data.len())),
+    }
+  }
+
+  #[test]
+  fn defines_aliases_at_their_targets() {
+    let alias = ("_kept".to_string(), "_also".to_string());
+    let out = libboth(|opts| opts.aliases.push(alias)).unwrap();
+    assert_eq!(exported(&out), vec!["_also", "_hidden", "_kept"]);
+    let image = ImageFile::parse(&out).unwrap();
+    let value = |name: &str| {
+      let sym = image.symbols.iter().find(|s| s.name == name).unwrap();
+      assert!(sym.is_external());
+      sym.n_value
+    };
+    assert_eq!(value("_also"), value("_kept"));
+
+    let taken = ("_kept".to_string(), "_hidden".to_string());
+    match libboth(|opts| opts.aliases.push(taken)) {
+      Err(FinalLinkError::AliasDefined(ref name)) if name == "_hidden" => (),
+      other => panic!("{:?}", other.map(|data| data.len())),
+    }
+
+    // -alias_list files, in ld64's format.
+    let dir = scratch_dir("alias-list");
+    let list = dir.join("aliases");
+    fs::write(&list, "# target alias\n_hidden _other\n").unwrap();
+    let listed = libboth(|opts| opts.alias_lists.push(list.clone()));
+    assert_eq!(exported(&listed.unwrap()), vec!["_hidden", "_kept", "_other"]);
+    fs::write(&list, "_gone _other\n").unwrap();
+    let missing = libboth(|opts| opts.alias_lists.push(list.clone()));
+    fs::remove_dir_all(&dir).unwrap();
+    match missing {
+      Err(FinalLinkError::Resolve(ResolveError::AliasTargetNotDefined(
+        ref alias,
+        ref target,
+      ))) => assert_eq!((alias.as_str(), target.as_str()), ("_other", "_gone")),
+      other => panic!("{:?}", other.map(|data| data.len())),
+    }
+  }
+
+  // The lib ordinal and name of each of `out`'s chained fixups imports.
+  fn chained_imports(out: &[u8]) -> Vec<(u8, String)> {
+    let fixups = linkedit_data(out, LC_DYLD_CHAINED_FIXUPS);
+    let imports = get_u32(fixups, 8, "").unwrap() as usize;
+    let symbols = get_u32(fixups, 12, "").unwrap() as usize;
+    let count = get_u32(fixups, 16, "").unwrap() as usize;
+    (0..count)
+      .map(|k| {
+        let import = get_u32(fixups, imports + 4 * k, "").unwrap();
+        let start = symbols + (import >> 9) as usize;
+        let name = fixups[start..].split(|&b| b == 0).next().unwrap();
+        (import as u8, String::from_utf8(name.to_vec()).unwrap())
+      })
+      .collect()
+  }
+
+  #[test]
+  fn binds_interposable_symbols_to_the_output() {
+    // _caller: callq _open; callq _close; retq. _open: retq. _close: retq.
+    let call = |address: u32, symbolnum: u32| RelocationInfo {
+      address: address,
+      symbolnum: symbolnum,
+      pcrel: true,
+      length: 2,
+      external: true,
+      kind: X86_64_RELOC_BRANCH,
+      scattered: None,
+    };
+    let code = vec![0xe8, 0, 0, 0, 0, 0xe8, 0, 0, 0, 0, 0xc3, 0xc3, 0xc3];
+    let text = text_section(code, vec![call(1, 1), call(6, 2)]);
+    let code = object_of(
+      Arch::X86_64,
+      vec![text],
+      vec![
+        nlist("_caller", N_SECT | N_EXT, 1, 0),
+        nlist("_open", N_SECT | N_EXT, 1, 11),
+        nlist("_close", N_SECT | N_EXT, 1, 12),
+      ],
+    );
+    let dir = scratch_dir("interposable");
+    let list = dir.join("interposable");
+    fs::write(&list, "_open\n").unwrap();
+    let mut opts = options(OutputKind::Dylib, vec![("open.o", code)]);
+    opts.fixup_chains = Some(true);
+    opts.interposable_lists.push(list);
+    let out = build(&opts);
+    fs::remove_dir_all(&dir).unwrap();
+    let out = out.unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+    // _open stays exported, and binds to the dylib itself by name.
+    assert_eq!(exported(&out), vec!["_caller", "_close", "_open"]);
+    let self_ordinal = BIND_SPECIAL_DYLIB_SELF as u8;
+    assert_eq!(chained_imports(&out), vec![(self_ordinal, "_open".into())]);
+    let image = ImageFile::parse(&out).unwrap();
+    let text = section(&image, "__text");
+    let stubs = section(&image, "__stubs");
+    let got = section(&image, "__got");
+    let callee = |at: u64| {
+      text.addr + at + 4 + read_rel32(&out, text.offset as u64 + at)
+    };
+    assert_eq!(callee(1), stubs.addr);
+    assert_eq!(callee(6), text.addr + 12);
+    let jump = stubs.addr + 6 + read_rel32(&out, stubs.offset as u64 + 2);
+    assert_eq!(jump, got.addr);
+    let slot = get_u64(&out, got.offset as usize, "").unwrap();
+    assert_eq!(slot, 1 << 63);
+
+    // -interposable: every exported definition the dylib calls.
+    opts.interposable_lists.clear();
+    opts.interposable = true;
+    let out = build(&opts).unwrap();
+    let mut names: Vec<String> =
+      chained_imports(&out).into_iter().map(|(_, name)| name).collect();
+    names.sort();
+    assert_eq!(names, vec!["_close", "_open"]);
+  }
+
+  #[test]
+  fn creates_sections_from_files() {
+    let code = object(Arch::X86_64, vec![0xc3], Vec::new(), "_main", "");
+    let mut opts = options(OutputKind::Executable, vec![("main.o", code)]);
+    let plist = PathBuf::from("Info.plist");
+    opts.input_buffers.push((plist.clone(), Arc::new(b"<plist/>".to_vec())));
+    opts.created_sections.push(CreatedSection {
+      segname: "__TEXT".to_string(),
+      sectname: "__info_plist".to_string(),
+      path: Some(plist),
+    });
+    opts.created_sections.push(CreatedSection {
+      segname: "__DATA".to_string(),
+      sectname: "__empty".to_string(),
+      path: None,
+    });
+    let out = build(&opts).unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+    let image = ImageFile::parse(&out).unwrap();
+    let plist = section(&image, "__info_plist");
+    let offset = plist.offset as usize;
+    assert_eq!(&out[offset..offset + plist.size as usize], b"<plist/>");
+    assert_eq!(section(&image, "__empty").size, 0);
+
+    // -r keeps them as sections of the object.
+    opts.output_kind = OutputKind::Relocatable;
+    let object = ObjectFile::parse(&relocatable::build(&opts).unwrap());
+    let object = object.unwrap();
+    let plist = object
+      .sections
+      .iter()
+      .find(|sect| sect.sectname == "__info_plist")
+      .unwrap();
+    assert_eq!(plist.contents, b"<plist/>".to_vec());
+  }
+
+  // The lc_str at the start of each of `out`'s load commands of type `cmd`.
+  fn lc_strings(out: &[u8], cmd: u32) -> Vec<String> {
+    load_commands(out)
+      .unwrap()
+      .into_iter()
+      .filter(|command| command.cmd == cmd)
+      .map(|command| {
+        let start = command.offset
+          + get_u32(out, command.offset + 8, "").unwrap() as usize;
+        let end = command.offset + command.cmdsize as usize;
+        let name = out[start..end].split(|&b| b == 0).next().unwrap();
+        String::from_utf8(name.to_vec()).unwrap()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn names_dylibs_and_their_run_paths() {
+    let out = libboth(|opts| {
+      opts.install_name = Some("@rpath/libboth.dylib".to_string());
+      opts.current_version = Some(Version::new(2, 1, 0));
+      opts.rpaths.push("@loader_path/../lib".to_string());
+    }).unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+    let dylib = DylibFile::parse(&out).unwrap();
+    assert_eq!(dylib.install_name, "@rpath/libboth.dylib");
+    assert_eq!(dylib.current_version, Version::new(2, 1, 0));
+    assert_eq!(lc_strings(&out, LC_RPATH), vec!["@loader_path/../lib"]);
+  }
+
+  #[test]
+  fn reexports_dylibs_to_clients() {
+    let dir = scratch_dir("reexport");
+    let libputs = libputs(&dir);
+    let reexporting = |umbrella: Option<&str>| {
+      libboth(|opts| {
+        opts.install_name = Some("/usr/lib/libboth.dylib".to_string());
+        opts.reexport_libraries.push(libputs.clone());
+        opts.umbrella = umbrella.map(|name| name.to_string());
+      }).unwrap()
+    };
+    let dylib = DylibFile::parse(&reexporting(Some("Both"))).unwrap();
+    assert_eq!(dylib.reexports, vec!["/usr/lib/libputs.dylib"]);
+    assert_eq!(dylib.umbrella, Some("Both".to_string()));
+    assert!(dylib.flags & MH_NO_REEXPORTED_DYLIBS == 0);
+
+    // test.o finds _puts through libboth, which is all it links.
+    let out = reexporting(None);
+    let libboth = dir.join("libboth.dylib");
+    fs::write(&libboth, out).unwrap();
+    let mut opts = LinkOptions {
+      fixup_chains: Some(false),
+      ..Default::default()
+    };
+    opts.input_paths = vec![fixture("test.o"), libboth];
+    let installed = "/usr/lib/libputs.dylib".to_string();
+    opts.dylib_files.push((installed, libputs));
+    let out = build(&opts).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+    assert_eq!(lc_strings(&out, LC_LOAD_DYLIB), vec!["/usr/lib/libboth.dylib"]);
+    let image = ImageFile::parse(&out).unwrap();
+    let puts = image.symbols.iter().find(|s| s.name == "_puts").unwrap();
+    assert_eq!(puts.n_desc >> 8, 1);
+  }
+
+  #[test]
+  fn links_dylibs() {
+    let out = libboth(|opts| {
+      opts.compatibility_version = Some(Version::new(1, 0, 0));
+    }).unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+    assert_eq!(get_u32(&out, 12, "").unwrap(), MH_DYLIB);
+    let cmds: Vec<u32> = load_commands(&out)
+      .unwrap()
+      .into_iter()
+      .map(|command| command.cmd)
+      .collect();
+    assert!(cmds.contains(&LC_ID_DYLIB));
+    assert!(!cmds.contains(&LC_MAIN) && !cmds.contains(&LC_LOAD_DYLINKER));
+    let dylib = DylibFile::parse(&out).unwrap();
+    assert_eq!(dylib.compatibility_version, Version::new(1, 0, 0));
+    assert!(dylib.flags & MH_NO_REEXPORTED_DYLIBS != 0);
+    // Dylibs have no __PAGEZERO, so the exports are from the header at 0.
+    let image = ImageFile::parse(&out).unwrap();
+    assert_eq!(image.segments[0].segname, "__TEXT");
+    assert_eq!(image.segments[0].vmaddr, 0);
+  }
+
+  #[test]
+  fn binds_bundles_to_their_loader() {
+    let dir = scratch_dir("bundle");
+    let text = text_section(vec![0xc3, 0xc3], Vec::new());
+    let host = object_of(
+      Arch::X86_64,
+      vec![text],
+      vec![
+        nlist("_main", N_SECT | N_EXT, 1, 0),
+        nlist("_puts", N_SECT | N_EXT, 1, 1),
+      ],
+    );
+    let host = build(&options(OutputKind::Executable, vec![("host.o", host)]));
+    let loader = dir.join("host");
+    fs::write(&loader, host.unwrap()).unwrap();
+
+    // callq _puts; retq
+    let call = RelocationInfo {
+      address: 1,
+      symbolnum: 1,
+      pcrel: true,
+      length: 2,
+      external: true,
+      kind: X86_64_RELOC_BRANCH,
+      scattered: None,
+    };
+    let code = vec![0xe8, 0, 0, 0, 0, 0xc3];
+    let plugin = object(Arch::X86_64, code, vec![call], "_plugin", "_puts");
+    let mut opts = options(OutputKind::Bundle, vec![("plugin.o", plugin)]);
+    opts.bundle_loader = Some(loader);
+    opts.fixup_chains = Some(false);
+    let out = build(&opts).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+    assert_eq!(get_u32(&out, 12, "").unwrap(), MH_BUNDLE);
+    let image = ImageFile::parse(&out).unwrap();
+    let puts = image.symbols.iter().find(|s| s.name == "_puts").unwrap();
+    // EXECUTABLE_ORDINAL: found in whatever loads the bundle.
+    assert_eq!(puts.n_desc >> 8, 0xff);
+  }
+
+  // main.o with a pointer in __data to _main, and its rebase info size.
+  fn main_pointer(pie: Option<bool>) -> (Vec<u8>, u32) {
+    let reloc = RelocationInfo {
+      address: 0,
+      symbolnum: 0,
+      pcrel: false,
+      length: 3,
+      external: true,
+      kind: X86_64_RELOC_UNSIGNED,
+      scattered: None,
+    };
+    let data = ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__data".to_string(),
+      addr: 8,
+      size: 8,
+      align: 3,
+      contents: vec![0; 8],
+      relocs: vec![reloc],
+      ..Default::default()
+    };
+    let text = text_section(vec![0xc3], Vec::new());
+    let main = object_of(
+      Arch::X86_64,
+      vec![text, data],
+      vec![nlist("_main", N_SECT | N_EXT, 1, 0)],
+    );
+    let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    opts.pie = pie;
+    opts.fixup_chains = Some(false);
+    let out = build(&opts).unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+    let info = load_commands(&out)
+      .unwrap()
+      .into_iter()
+      .find(|command| command.cmd == LC_DYLD_INFO_ONLY)
+      .unwrap();
+    let rebase_size = get_u32(&out, info.offset + 12, "").unwrap();
+    (out, rebase_size)
+  }
+
+  #[test]
+  fn rebases_only_position_independent_executables() {
+    let (out, rebase_size) = main_pointer(None);
+    assert!(get_u32(&out, 24, "").unwrap() & MH_PIE != 0);
+    assert!(rebase_size > 0);
+
+    let (out, rebase_size) = main_pointer(Some(false));
+    assert!(get_u32(&out, 24, "").unwrap() & MH_PIE == 0);
+    assert_eq!(rebase_size, 0);
+    // Loaded where it was linked, so the pointer is already right.
+    let image = ImageFile::parse(&out).unwrap();
+    let data = section(&image, "__data");
+    let pointer = get_u64(&out, data.offset as usize, "").unwrap();
+    assert_eq!(pointer, section(&image, "__text").addr);
+  }
+
+  // An object defining `_shared` in its __text, weakly if `weak`, with a
+  // pointer to it in __data.
+  fn shared(weak: bool) -> Vec<u8> {
+    let reloc = RelocationInfo {
+      address: 0,
+      symbolnum: 0,
+      pcrel: false,
+      length: 3,
+      external: true,
+      kind: X86_64_RELOC_UNSIGNED,
+      scattered: None,
+    };
+    let data = ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__data".to_string(),
+      addr: 8,
+      size: 8,
+      align: 3,
+      contents: vec![0; 8],
+      relocs: vec![reloc],
+      ..Default::default()
+    };
+    let mut symbol = nlist("_shared", N_SECT | N_EXT, 1, 0);
+    if weak {
+      symbol.n_desc = N_WEAK_DEF;
+    }
+    let text = text_section(vec![0xc3], Vec::new());
+    object_of(Arch::X86_64, vec![text, data], vec![symbol])
+  }
+
+  #[test]
+  fn coalesces_weak_definitions() {
+    let inputs = vec![("a.o", shared(true)), ("b.o", shared(true))];
+    let mut opts = options(OutputKind::Dylib, inputs);
+    opts.fixup_chains = Some(false);
+    let out = build(&opts).unwrap();
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+    let image = ImageFile::parse(&out).unwrap();
+    let defined: Vec<&Nlist> = image
+      .symbols
+      .iter()
+      .filter(|sym| sym.name == "_shared")
+      .collect();
+    assert_eq!(defined.len(), 1);
+    let flags = get_u32(&out, 24, "").unwrap();
+    assert_eq!(
+      flags & (MH_WEAK_DEFINES | MH_BINDS_TO_WEAK),
+      MH_WEAK_DEFINES | MH_BINDS_TO_WEAK
+    );
+    // Both pointers go to the one copy, and dyld may rebind them to
+    // another image's.
+    let data = section(&image, "__data");
+    let (seg, dataseg) = image
+      .segments
+      .iter()
+      .enumerate()
+      .find(|&(_, seg)| seg.segname == "__DATA")
+      .unwrap();
+    for pointer in 0..2 {
+      let at = data.offset as usize + 8 * pointer;
+      assert_eq!(get_u64(&out, at, "").unwrap(), defined[0].n_value);
+    }
+    let offset = data.addr - dataseg.vmaddr;
+    let expected = encode_weak_bind(&[WeakBinding {
+      name: "_shared".to_string(),
+      strong_definition: false,
+      locations: vec![(seg as u8, offset), (seg as u8, offset + 8)],
+    }]);
+    let info = load_commands(&out)
+      .unwrap()
+      .into_iter()
+      .find(|command| command.cmd == LC_DYLD_INFO_ONLY)
+      .unwrap();
+    let weak_bind = get_u32(&out, info.offset + 24, "").unwrap() as usize;
+    let size = get_u32(&out, info.offset + 28, "").unwrap() as usize;
+    assert_eq!(out[weak_bind..weak_bind + size], expected[..]);
+
+    // With -no_weak_exports the copy stays inside the dylib.
+    opts.no_weak_exports = true;
+    let out = build(&opts).unwrap();
+    assert_eq!(get_u32(&out, 24, "").unwrap() & MH_WEAK_DEFINES, 0);
+    let image = ImageFile::parse(&out).unwrap();
+    let hidden = image.symbols.iter().find(|sym| sym.name == "_shared");
+    assert!(!hidden.unwrap().is_external());
+
+    // A strong definition wins, and then there's nothing to coalesce.
+    let inputs = vec![("a.o", shared(true)), ("b.o", shared(false))];
+    let mut opts = options(OutputKind::Dylib, inputs);
+    opts.fixup_chains = Some(false);
+    let out = build(&opts).unwrap();
+    assert_eq!(get_u32(&out, 24, "").unwrap() & MH_WEAK_DEFINES, 0);
+    let image = ImageFile::parse(&out).unwrap();
+    let shared = image.symbols.iter().find(|sym| sym.name == "_shared");
+    assert!(!shared.unwrap().is_weak_def());
+  }
+
+  #[test]
+  fn stamps_a_content_uuid() {
+    let out = libboth(|_| ()).unwrap();
+    let uuid = ImageFile::parse(&out).unwrap().uuid.unwrap();
+    assert!(uuid != [0; 16]);
+    // Version 3, a hash of the output.
+    assert_eq!(uuid[6] >> 4, 3);
+    assert_eq!(libboth(|_| ()).unwrap(), out);
+
+    let out = libboth(|opts| opts.uuid = UuidMode::None).unwrap();
+    assert_eq!(ImageFile::parse(&out).unwrap().uuid, None);
+
+    let out = libboth(|opts| opts.uuid = UuidMode::Random).unwrap();
+    let uuid = ImageFile::parse(&out).unwrap().uuid.unwrap();
+    assert_eq!(uuid[6] >> 4, 4);
+  }
+
+  // (cmd, the words after cmdsize) of the platform's load command: the
+  // platform, minimum OS and SDK versions, and then its tools.
+  fn build_version(out: &[u8]) -> (u32, Vec<u32>) {
+    let command = load_commands(out)
+      .unwrap()
+      .into_iter()
+      .find(|command| {
+        command.cmd == LC_BUILD_VERSION || command.cmd == LC_VERSION_MIN_MACOSX
+      })
+      .unwrap();
+    let words = (8..command.cmdsize as usize)
+      .step_by(4)
+      .map(|at| get_u32(out, command.offset + at, "").unwrap())
+      .collect();
+    (command.cmd, words)
+  }
+
+  #[test]
+  fn builds_for_the_platform_version() {
+    let out = libboth(|opts| {
+      opts.min_os_version = Version::new(12, 0, 0);
+      opts.sdk_version = Some(Version::new(13, 1, 0));
+    }).unwrap();
+    let (cmd, words) = build_version(&out);
+    assert_eq!(cmd, LC_BUILD_VERSION);
+    assert_eq!(words[0], Platform::MacOS.number());
+    assert_eq!(words[1], Version::new(12, 0, 0).0);
+    assert_eq!(words[2], Version::new(13, 1, 0).0);
+    // One tool, this linker.
+    assert_eq!(words[3], 1);
+    assert_eq!(words[4], target::TOOL_LD);
+
+    // Deployment targets before LC_BUILD_VERSION get LC_VERSION_MIN_*.
+    let out = libboth(|opts| {
+      opts.min_os_version = Version::new(10, 13, 0);
+    }).unwrap();
+    let (cmd, words) = build_version(&out);
+    assert_eq!(cmd, LC_VERSION_MIN_MACOSX);
+    let version = Version::new(10, 13, 0).0;
+    assert_eq!(words, vec![version, version]);
+
+    // The inputs were built for macOS.
+    match libboth(|opts| opts.platform = Platform::IOS) {
+      Err(FinalLinkError::Relocatable(RelocatableError::Target(
+        TargetError::PlatformMismatch(_, Platform::MacOS, Platform::IOS),
+      ))) => (),
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+  }
+
+  #[test]
+  fn builds_zippered_outputs_for_both_platforms() {
+    let catalyst = (
+      Platform::MacCatalyst,
+      Version::new(14, 0, 0),
+      Version::new(14, 2, 0),
+    );
+    let lib = libboth(|opts| {
+      opts.install_name = Some("/usr/lib/libboth.dylib".to_string());
+      opts.platform_variant = Some(catalyst);
+    }).unwrap();
+    let versions: Vec<Vec<u32>> = load_commands(&lib)
+      .unwrap()
+      .into_iter()
+      .filter(|command| command.cmd == LC_BUILD_VERSION)
+      .map(|command| {
+        (8..20)
+          .step_by(4)
+          .map(|at| get_u32(&lib, command.offset + at, "").unwrap())
+          .collect()
+      })
+      .collect();
+    let macos = Version::new(11, 0, 0).0;
+    assert_eq!(
+      versions,
+      vec![
+        vec![Platform::MacOS.number(), macos, macos],
+        vec![
+          Platform::MacCatalyst.number(),
+          Version::new(14, 0, 0).0,
+          Version::new(14, 2, 0).0,
+        ],
+      ]
+    );
+
+    // Zippered outputs can only link zippered dylibs.
+    let dir = scratch_dir("zippered");
+    let both = dir.join("libboth.dylib");
+    fs::write(&both, &lib).unwrap();
+    let code = object(Arch::X86_64, vec![0xc3], Vec::new(), "_main", "_kept");
+    let mut opts = options(OutputKind::Executable, vec![("main.o", code)]);
+    opts.platform_variant = Some(catalyst);
+    opts.input_paths.push(both);
+    let out = build(&opts);
+    opts.input_paths.push(libputs(&dir));
+    let mixed = build(&opts);
+    fs::remove_dir_all(&dir).unwrap();
+    let out = out.unwrap();
+    assert_eq!(lc_strings(&out, LC_LOAD_DYLIB), vec!["/usr/lib/libboth.dylib"]);
+    match mixed {
+      Err(FinalLinkError::Dylib(DylibError::Target(
+        TargetError::NotZippered(ref path),
+      ))) if path.ends_with("libputs.dylib") => (),
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+  }
+
+  // An __eh_frame with a CIE, and FDEs for 4-byte functions whose
+  // pc-relative starts are symbol `k` less label `labels + k`.
+  fn eh_frame(fdes: u32, labels: u32) -> (Vec<u8>, Vec<RelocationInfo>) {
+    // Version 1, "zR", alignment factors, return address register, and
+    // pc-relative FDE pointers, padded with DW_CFA_nop.
+    let mut data = vec![20, 0, 0, 0, 0, 0, 0, 0, 1, b'z', b'R', 0];
+    data.extend_from_slice(&[1, 0x78, 0x10, 1, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+    let mut relocs: Vec<RelocationInfo> = Vec::new();
+    for k in 0..fdes {
+      let offset = data.len() as u32;
+      put_u32(&mut data, 28);
+      put_u32(&mut data, offset + 4);
+      put_u64(&mut data, 0);
+      put_u64(&mut data, 4);
+      data.extend_from_slice(&[0; 8]);
+      let pair = [(X86_64_RELOC_SUBTRACTOR, labels + k),
+                  (X86_64_RELOC_UNSIGNED, k)];
+      for &(kind, symbolnum) in pair.iter() {
+        relocs.push(RelocationInfo {
+          address: offset + 8,
+          symbolnum: symbolnum,
+          pcrel: false,
+          length: 3,
+          external: true,
+          kind: kind,
+          scattered: None,
+        });
+      }
+    }
+    data.extend_from_slice(&[0; 4]);
+    (data, relocs)
+  }
+
+  #[test]
+  fn builds_unwind_info() {
+    // _main has a compact encoding and a personality routine; _g's
+    // encoding is DWARF mode. Both have FDEs.
+    let rbp_frame = 0x0100_0000;
+    let dwarf = unwind_info::dwarf_mode(Arch::X86_64);
+    let mut entries: Vec<u8> = Vec::new();
+    for &(function, encoding) in [(0, rbp_frame), (4, dwarf)].iter() {
+      put_u64(&mut entries, function);
+      put_u32(&mut entries, 4);
+      put_u32(&mut entries, encoding);
+      put_u64(&mut entries, 0);
+      put_u64(&mut entries, 0);
+    }
+    let pointer = |address: u32, symbolnum: u32, external: bool| {
+      RelocationInfo {
+        address: address,
+        symbolnum: symbolnum,
+        pcrel: false,
+        length: 3,
+        external: external,
+        kind: X86_64_RELOC_UNSIGNED,
+        scattered: None,
+      }
+    };
+    let (eh_frame, eh_frame_relocs) = eh_frame(2, 3);
+    let sections = vec![
+      text_section(vec![0xc3; 12], Vec::new()),
+      ObjectSection {
+        segname: "__TEXT".to_string(),
+        sectname: "__eh_frame".to_string(),
+        addr: 16,
+        align: 3,
+        contents: eh_frame,
+        relocs: eh_frame_relocs,
+        ..Default::default()
+      },
+      ObjectSection {
+        segname: "__LD".to_string(),
+        sectname: "__compact_unwind".to_string(),
+        addr: 112,
+        align: 3,
+        contents: entries,
+        relocs: vec![pointer(0, 1, false), pointer(16, 2, true),
+                     pointer(32, 1, false)],
+        ..Default::default()
+      },
+    ];
+    let sections = sections
+      .into_iter()
+      .map(|sect| ObjectSection {
+        size: sect.contents.len() as u64,
+        ..sect
+      })
+      .collect();
+    let symbols = vec![
+      nlist("_main", N_SECT | N_EXT, 1, 0),
+      nlist("_g", N_SECT | N_EXT, 1, 4),
+      nlist("___gxx_personality_v0", N_SECT | N_EXT, 1, 8),
+      nlist("_fde0", N_SECT, 2, 16 + 24 + 8),
+      nlist("_fde1", N_SECT, 2, 16 + 56 + 8),
+    ];
+    let main = object_of(Arch::X86_64, sections, symbols);
+    let opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    let out = build(&opts).unwrap();
+    let image = ImageFile::parse(&out).unwrap();
+    assert!(image.segment("__LD").is_none());
+    let text = section(&image, "__text").addr;
+    let got = section(&image, "__got");
+    assert_eq!(got.size, 8);
+
+    // Only _g's FDE is left, after the CIE.
+    let eh_frame = section(&image, "__eh_frame");
+    assert_eq!(eh_frame.size, 24 + 32 + 4);
+    let fde = eh_frame.offset as usize + 24;
+    let pc_begin = get_u64(&out, fde + 8, "").unwrap();
+    assert_eq!(pc_begin.wrapping_add(eh_frame.addr + 32), text + 4);
+
+    let expected = [
+      CompactUnwindEntry {
+        function: text,
+        length: 4,
+        encoding: rbp_frame,
+        personality: Some(got.addr),
+        lsda: None,
+      },
+      CompactUnwindEntry {
+        function: text + 4,
+        length: 4,
+        encoding: unwind_info::set_dwarf_offset(dwarf, 24).unwrap(),
+        personality: None,
+        lsda: None,
+      },
+    ];
+    let base = image.segment("__TEXT").unwrap().vmaddr;
+    let expected = unwind_info::build(&expected, base, &[got.addr])
+      .unwrap()
+      .unwrap();
+    let unwind_info = section(&image, "__unwind_info");
+    let start = unwind_info.offset as usize;
+    assert_eq!(&out[start..start + expected.len()], &expected[..]);
+  }
+
+  // An object defining _main and the local _g, with a compile unit whose
+  // low_pc is _g in a DWARF v4 __debug_info.
+  fn debug_object() -> Vec<u8> {
+    debug_object_defining("_main")
+  }
+
+  // An object with DWARF for its one function, `name`.
+  fn debug_object_defining(name: &str) -> Vec<u8> {
+    let abbrev = vec![1, 0x11, 0, 0x11, 0x01, 0, 0, 0];
+    let mut info: Vec<u8> = Vec::new();
+    put_u32(&mut info, 16);
+    info.extend_from_slice(&[4, 0, 0, 0, 0, 0, 8, 1]);
+    put_u64(&mut info, 4);
+    let debug = |sectname: &str, contents: Vec<u8>, relocs| ObjectSection {
+      segname: "__DWARF".to_string(),
+      sectname: sectname.to_string(),
+      size: contents.len() as u64,
+      contents: contents,
+      relocs: relocs,
+      ..Default::default()
+    };
+    let low_pc = RelocationInfo {
+      address: 12,
+      symbolnum: 1,
+      pcrel: false,
+      length: 3,
+      external: false,
+      kind: X86_64_RELOC_UNSIGNED,
+      scattered: None,
+    };
+    let sections = vec![
+      text_section(vec![0xc3; 8], Vec::new()),
+      debug("__debug_abbrev", abbrev, Vec::new()),
+      debug("__debug_info", info, vec![low_pc]),
+    ];
+    let symbols = vec![
+      nlist(name, N_SECT | N_EXT, 1, 0),
+      nlist("_g", N_SECT, 1, 4),
+    ];
+    object_of(Arch::X86_64, sections, symbols)
+  }
+
+  #[test]
+  fn writes_a_debug_map_for_dsym() {
+    let dir = scratch_dir("dsym");
+    let input = dir.join("main.o");
+    fs::write(&input, debug_object()).unwrap();
+    let mut opts = options(OutputKind::Executable, Vec::new());
+    opts.input_paths.push(input.clone());
+    opts.output_path = dir.join("main");
+    opts.reproducible = true;
+    opts.oso_prefix = Some(dir.clone());
+    run(&opts).unwrap();
+
+    let out = fs::read(&opts.output_path).unwrap();
+    let image = ImageFile::parse(&out).unwrap();
+    let text = section(&image, "__text").addr;
+    let stabs: Vec<(u8, &str, u64)> = image
+      .symbols
+      .iter()
+      .filter(|sym| sym.is_stab())
+      .map(|sym| (sym.n_type, sym.name.as_str(), sym.n_value))
+      .collect();
+    assert_eq!(
+      stabs,
+      vec![
+        (N_OSO, "main.o", 0),
+        (N_FUN, "_main", text),
+        (N_FUN, "", 4),
+        (N_FUN, "_g", text + 4),
+        (N_FUN, "", 4),
+        (N_SO, "", 0),
+      ]
+    );
+
+    let bundle = dsym::write_bundle(&opts).unwrap();
+    let dwarf = bundle.join("Contents/Resources/DWARF/main");
+    let data = fs::read(&dwarf).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let dsym = ImageFile::parse(&data).unwrap();
+    assert_eq!(dsym.uuid, image.uuid);
+    let info = dsym
+      .segment("__DWARF")
+      .unwrap()
+      .sections
+      .iter()
+      .find(|sect| sect.sectname == "__debug_info")
+      .unwrap();
+    let low_pc = get_u64(&data, info.offset as usize + 12, "").unwrap();
+    assert_eq!(low_pc, text + 4);
+  }
+
+  #[test]
+  fn links_the_same_wherever_and_on_however_many_threads() {
+    let link = |name: &str, threads: usize| {
+      let dir = scratch_dir(name);
+      let mut opts = options(OutputKind::Executable, Vec::new());
+      for (k, input) in ["main.o", "other.o"].iter().enumerate() {
+        let path = dir.join(input);
+        let name = if k == 0 { "_main" } else { "_other" };
+        fs::write(&path, debug_object_defining(name)).unwrap();
+        opts.input_paths.push(path);
+      }
+      opts.output_path = dir.join("main");
+      opts.reproducible = true;
+      opts.oso_prefix = Some(dir.clone());
+      parallel::set_num_threads(threads);
+      run(&opts).unwrap();
+      parallel::set_num_threads(0);
+      let out = fs::read(&opts.output_path).unwrap();
+      fs::remove_dir_all(&dir).unwrap();
+      out
+    };
+    let one = link("anywhere-1", 1);
+    assert_eq!(one, link("anywhere-2", 4));
+    assert!(!one.windows(8).any(|w| w == b"anywhere"));
+  }
+
+  #[test]
+  fn strips_symbols_while_linking_and_after() {
+    let link = |strip: StripOptions| {
+      let mut opts =
+        options(OutputKind::Executable, vec![("main.o", debug_object())]);
+      opts.strip = strip;
+      build(&opts).unwrap()
+    };
+    let symbols = |out: &[u8]| -> Vec<(u8, String)> {
+      ImageFile::parse(out)
+        .unwrap()
+        .symbols
+        .into_iter()
+        .map(|sym| (sym.n_type, sym.name))
+        .collect()
+    };
+    let stab = |n_type: u8, name: &str| (n_type, name.to_string());
+    let main = stab(N_SECT | N_EXT, "_main");
+    let header = stab(N_SECT | N_EXT, EXECUTE_HEADER);
+    let g = stab(N_SECT, "_g");
+    let debug_map = vec![
+      stab(N_OSO, "main.o"),
+      stab(N_FUN, "_main"),
+      stab(N_FUN, ""),
+      stab(N_FUN, "_g"),
+      stab(N_FUN, ""),
+      stab(N_SO, ""),
+    ];
+    let unstripped = link(StripOptions::default());
+    let mut all = debug_map.clone();
+    all.extend(vec![g.clone(), header.clone(), main.clone()]);
+    assert_eq!(symbols(&unstripped), all);
+
+    // -S drops the debug info, and so the debug map.
+    let debug = StripOptions {
+      debug: true,
+      ..Default::default()
+    };
+    let expected = vec![g.clone(), header.clone(), main.clone()];
+    assert_eq!(symbols(&link(debug)), expected);
+
+    // -x drops _g, which the debug map then doesn't mention.
+    let locals = StripOptions {
+      locals: true,
+      ..Default::default()
+    };
+    let mut expected = debug_map[..3].to_vec();
+    expected.extend(vec![stab(N_SO, ""), header.clone(), main.clone()]);
+    assert_eq!(symbols(&link(locals)), expected);
+
+    let exported = StripOptions {
+      non_exported: true,
+      ..Default::default()
+    };
+    let expected = vec![header.clone(), main.clone()];
+    assert_eq!(symbols(&link(exported)), expected);
+
+    // `mold strip` does the same to a linked image, in place.
+    let mut data = unstripped.clone();
+    let path = PathBuf::from("main");
+    strip::strip_image(&path, &mut data, exported).unwrap();
+    assert_eq!(symbols(&data), expected);
+    assert!(data.len() < unstripped.len());
+    let text = |out: &[u8]| {
+      let image = ImageFile::parse(out).unwrap();
+      let text = section(&image, "__text");
+      out[text.offset as usize..][..text.size as usize].to_vec()
+    };
+    assert_eq!(text(&data), text(&unstripped));
+  }
+
+  #[test]
+  fn applies_segment_protections_and_section_alignments() {
+    let main = object(Arch::X86_64, vec![0xc3], Vec::new(), "_main", "");
+    let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    opts.segment_protections = vec![SegmentProtection {
+      segname: "__TEXT".to_string(),
+      maxprot: 7,
+      initprot: 5,
+    }];
+    opts.section_alignments = vec![SectionAlignment {
+      segname: "__TEXT".to_string(),
+      sectname: "__text".to_string(),
+      align: 12,
+    }];
+    let out = build(&opts).unwrap();
+    let image = ImageFile::parse(&out).unwrap();
+    let text = image.segment("__TEXT").unwrap();
+    assert_eq!((text.maxprot, text.initprot), (7, 5));
+    let sect = section(&image, "__text");
+    assert_eq!(sect.align, 12);
+    assert_eq!(sect.addr % 0x1000, 0);
+  }
+
+  #[test]
+  fn moves_const_data_to_data_const() {
+    let data_section = |sectname: &str, byte: u8| ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: sectname.to_string(),
+      addr: 8,
+      size: 8,
+      align: 3,
+      contents: vec![byte; 8],
+      ..Default::default()
+    };
+    let link = |data_const: Option<bool>| {
+      let sections = vec![
+        text_section(vec![0xc3], Vec::new()),
+        data_section("__const", 1),
+        data_section("__data", 2),
+      ];
+      let symbols = vec![
+        nlist("_main", N_SECT | N_EXT, 1, 0),
+        nlist("_k", N_SECT | N_EXT, 2, 8),
+        nlist("_v", N_SECT | N_EXT, 3, 16),
+      ];
+      let main = object_of(Arch::X86_64, sections, symbols);
+      let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+      opts.data_const = data_const;
+      let out = build(&opts).unwrap();
+      assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+      ImageFile::parse(&out).unwrap()
+    };
+    let segments = |image: &ImageFile| -> Vec<(String, Vec<String>)> {
+      image
+        .segments
+        .iter()
+        .map(|seg| {
+          let sections = seg.sections.iter().map(|s| s.sectname.clone());
+          (seg.segname.clone(), sections.collect())
+        })
+        .collect()
+    };
+    let named = |segname: &str, sections: &[&str]| {
+      let sections = sections.iter().map(|s| s.to_string()).collect();
+      (segname.to_string(), sections)
+    };
+
+    // On by default from macOS 10.15.
+    let image = link(None);
+    assert_eq!(
+      segments(&image)[2..4].to_vec(),
+      vec![named("__DATA_CONST", &["__const"]), named("__DATA", &["__data"])]
+    );
+    let data_const = image.segment("__DATA_CONST").unwrap();
+    assert_eq!(data_const.flags, SG_READ_ONLY);
+    assert_eq!(data_const.initprot, VM_PROT_READ | VM_PROT_WRITE);
+
+    let image = link(Some(false));
+    assert!(image.segment("__DATA_CONST").is_none());
+    assert_eq!(segments(&image)[2], named("__DATA", &["__const", "__data"]));
+  }
+
+  #[test]
+  fn relaxes_adrp_sequences_the_hints_name() {
+    // adrp x0, _s@PAGE; add x0, x0, _s@PAGEOFF; ret
+    let code = vec![
+      0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x91, 0xc0, 0x03, 0x5f, 0xd6,
+    ];
+    let reloc = |address: u32, kind: u8| RelocationInfo {
+      address: address,
+      symbolnum: 1,
+      pcrel: kind == ARM64_RELOC_PAGE21,
+      length: 2,
+      external: true,
+      kind: kind,
+      scattered: None,
+    };
+    let relocs =
+      vec![reloc(0, ARM64_RELOC_PAGE21), reloc(4, ARM64_RELOC_PAGEOFF12)];
+    let cstring = ObjectSection {
+      segname: "__TEXT".to_string(),
+      sectname: "__cstring".to_string(),
+      addr: 12,
+      size: 3,
+      flags: S_CSTRING_LITERALS,
+      contents: b"hi\0".to_vec(),
+      ..Default::default()
+    };
+    let link = |hinted: bool| {
+      let sections =
+        vec![text_section(code.clone(), relocs.clone()), cstring.clone()];
+      let symbols =
+        vec![nlist("_main", N_SECT | N_EXT, 1, 0), nlist("_s", N_SECT, 2, 12)];
+      let main = object_of(Arch::Arm64, sections, symbols);
+      let mut main = ObjectFile::parse(&main).unwrap();
+      if hinted {
+        main.loh = vec![Loh {
+          kind: LOH_ARM64_ADRP_ADD,
+          addresses: vec![0, 4],
+        }];
+      }
+      let main = write_object(Arch::Arm64, main);
+      let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+      opts.arch = Arch::Arm64;
+      let out = build(&opts).unwrap();
+      let image = ImageFile::parse(&out).unwrap();
+      let text = section(&image, "__text").clone();
+      let s = section(&image, "__cstring").addr;
+      let insns: Vec<u32> = (0..3)
+        .map(|k| read_u32(&out, text.offset as usize + 4 * k))
+        .collect();
+      (insns, text.addr, s)
+    };
+
+    let (insns, _, _) = link(false);
+    assert_eq!(insns[0] & 0x9f00_0000, 0x9000_0000);
+    assert_eq!(insns[1] & 0xff00_0000, 0x9100_0000);
+
+    // The string is close enough for an adr, which leaves the add a nop.
+    let (insns, text, s) = link(true);
+    assert_eq!(insns[0] & 0x9f00_001f, 0x1000_0000);
+    let imm = ((insns[0] >> 29) & 3) | ((insns[0] >> 3) & 0x1f_fffc);
+    assert_eq!(text + imm as u64, s);
+    assert_eq!(insns[1], 0xd503_201f);
+    assert_eq!(insns[2], 0xd65f_03c0);
+  }
+
+  #[test]
+  fn sets_the_main_thread_stack() {
+    let command = |out: &[u8], cmd: u32| {
+      load_commands(out)
+        .unwrap()
+        .into_iter()
+        .find(|command| command.cmd == cmd)
+        .map(|command| command.offset)
+    };
+    let text = text_section(vec![0xc3, 0xc3], Vec::new());
+    let symbols = vec![
+      nlist("_main", N_SECT | N_EXT, 1, 0),
+      nlist("start", N_SECT | N_EXT, 1, 1),
+    ];
+    let main = object_of(Arch::X86_64, vec![text], symbols);
+    let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    opts.stack_size = Some(0x80_0000);
+    let out = build(&opts).unwrap();
+    let main = command(&out, LC_MAIN).unwrap();
+    assert_eq!(get_u64(&out, main + 16, "").unwrap(), 0x80_0000);
+
+    // With a stack address, the kernel starts the thread at `start`.
+    opts.stack_addr = Some(0x7fff_0000_0000);
+    let out = build(&opts).unwrap();
+    assert!(command(&out, LC_MAIN).is_none());
+    let thread = command(&out, LC_UNIXTHREAD).unwrap();
+    let reg = |index: usize| {
+      get_u64(&out, thread + 16 + 8 * index, "").unwrap()
+    };
+    let text = section(&ImageFile::parse(&out).unwrap(), "__text").addr;
+    assert_eq!(reg(7), 0x7fff_0000_0000);
+    assert_eq!(reg(16), text + 1);
+  }
+
+  // The LC_SOURCE_VERSION of an executable linked with `configure`.
+  fn source_version<F: FnOnce(&mut LinkOptions)>(configure: F) -> Option<u64> {
+    let main = object(Arch::X86_64, vec![0xc3], Vec::new(), "_main", "");
+    let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    configure(&mut opts);
+    let out = build(&opts).unwrap();
+    load_commands(&out)
+      .unwrap()
+      .into_iter()
+      .find(|command| command.cmd == LC_SOURCE_VERSION)
+      .map(|command| get_u64(&out, command.offset + 8, "").unwrap())
+  }
+
+  #[test]
+  fn records_the_source_version() {
+    assert_eq!(source_version(|_| ()), Some(0));
+    let version = SourceVersion::parse("1.2.3").unwrap();
+    let given = source_version(|opts| opts.source_version = Some(version));
+    assert_eq!(given, Some(1 << 40 | 2 << 30 | 3 << 20));
+    let off = source_version(|opts| opts.add_source_version = Some(false));
+    assert_eq!(off, None);
+  }
+
+  #[test]
+  fn links_weak_and_upward_dylibs() {
+    let dir = scratch_dir("linkage");
+    let link = |weak: bool| {
+      let mut opts = LinkOptions {
+        fixup_chains: Some(false),
+        ..Default::default()
+      };
+      opts.input_paths.push(fixture("test.o"));
+      if weak {
+        opts.weak_libraries.push(libputs(&dir));
+      } else {
+        opts.upward_libraries.push(libputs(&dir));
+      }
+      build(&opts).unwrap()
+    };
+    let puts = |out: &[u8]| {
+      let image = ImageFile::parse(out).unwrap();
+      image.symbols.into_iter().find(|s| s.name == "_puts").unwrap()
+    };
+    let weak = link(true);
+    let upward = link(false);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let name = vec!["/usr/lib/libputs.dylib".to_string()];
+    assert_eq!(lc_strings(&weak, LC_LOAD_WEAK_DYLIB), name);
+    assert!(lc_strings(&weak, LC_LOAD_DYLIB).is_empty());
+    // dyld leaves _puts null if libputs is missing, rather than failing.
+    let sym = puts(&weak);
+    assert!(sym.is_undefined());
+    assert_eq!(sym.n_desc, N_WEAK_REF | 1 << 8);
+    let image = ImageFile::parse(&weak).unwrap();
+    let linkedit = image.segment("__LINKEDIT").unwrap();
+    let bind = load_commands(&weak)
+      .unwrap()
+      .into_iter()
+      .find(|command| command.cmd == LC_DYLD_INFO_ONLY)
+      .unwrap();
+    let bind_off = get_u32(&weak, bind.offset + 16, "").unwrap() as usize;
+    assert!(bind_off as u64 >= linkedit.fileoff);
+    assert_eq!(weak[bind_off], BIND_OPCODE_SET_DYLIB_ORDINAL_IMM | 1);
+    assert_eq!(
+      weak[bind_off + 1],
+      BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM | BIND_SYMBOL_FLAGS_WEAK_IMPORT
+    );
+
+    assert_eq!(lc_strings(&upward, LC_LOAD_UPWARD_DYLIB), name);
+    assert_eq!(puts(&upward).n_desc, 1 << 8);
+  }
+
+  #[test]
+  fn reads_dependent_dylibs_from_dylib_file_paths() {
+    let dir = scratch_dir("dylib-file");
+    let libputs = libputs(&dir);
+    let libboth = libboth(|opts| {
+      opts.install_name = Some("/usr/lib/libboth.dylib".to_string());
+      opts.reexport_libraries.push(libputs.clone());
+    }).unwrap();
+    let libboth_path = dir.join("libboth.dylib");
+    fs::write(&libboth_path, libboth).unwrap();
+    let mut opts = LinkOptions {
+      fixup_chains: Some(false),
+      ..Default::default()
+    };
+    opts.input_paths = vec![fixture("test.o"), libboth_path];
+    // libputs isn't installed where libboth says, so _puts isn't found.
+    let missing = build(&opts);
+    opts.dylib_files.push(("/usr/lib/libputs.dylib".to_string(), libputs));
+    let found = build(&opts);
+    fs::remove_dir_all(&dir).unwrap();
+    match missing {
+      Err(FinalLinkError::Relocatable(
+        RelocatableError::UnresolvedSymbols { .. },
+      )) => (),
+      other => panic!("{:?}", other.map(|data| data.len())),
+    }
+    let out = found.unwrap();
+    let image = ImageFile::parse(&out).unwrap();
+    let puts = image.symbols.iter().find(|s| s.name == "_puts").unwrap();
+    assert!(puts.is_undefined());
+    assert_eq!(puts.n_desc >> 8, 1);
+  }
+
+  #[test]
+  fn leaves_room_after_the_load_commands() {
+    // The space between the load commands and the first section.
+    let padding = |out: &[u8]| {
+      let sizeofcmds = get_u32(out, 20, "").unwrap() as u64;
+      let image = ImageFile::parse(out).unwrap();
+      let first = image
+        .segments
+        .iter()
+        .flat_map(|seg| seg.sections.iter())
+        .map(|sect| sect.offset as u64)
+        .min()
+        .unwrap();
+      first - 32 - sizeofcmds
+    };
+    let main = object(Arch::X86_64, vec![0xc3], Vec::new(), "_main", "");
+    let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    let default = padding(&build(&opts).unwrap());
+    assert!(default >= 32 && default < 0x2000);
+    opts.headerpad = Some(0x2000);
+    assert!(padding(&build(&opts).unwrap()) >= 0x2000);
+
+    // Room to rename the dylib itself and each dylib it links to 1024
+    // bytes.
+    let dir = scratch_dir("headerpad");
+    let mut opts = LinkOptions {
+      fixup_chains: Some(false),
+      output_kind: OutputKind::Dylib,
+      headerpad_max_install_names: true,
+      ..Default::default()
+    };
+    opts.input_paths = vec![fixture("test.o"), libputs(&dir)];
+    let out = build(&opts);
+    opts.headerpad_max_install_names = false;
+    let default = build(&opts);
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(padding(&out.unwrap()) >= 2 * 1024);
+    assert!(padding(&default.unwrap()) < 2 * 1024);
+  }
+
+  #[test]
+  fn links_kexts_with_relocations_for_the_kext_loader() {
+    // _start: callq _IOLog; retq
+    let code = vec![0xe8, 0, 0, 0, 0, 0xc3];
+    let call = RelocationInfo {
+      address: 1,
+      symbolnum: 2,
+      pcrel: true,
+      length: 2,
+      external: true,
+      kind: X86_64_RELOC_BRANCH,
+      scattered: None,
+    };
+    // _kmod_info, named com.example.kext, and then a pointer to _start.
+    let mut contents = vec![0; 208];
+    contents[8] = 1;
+    contents[16..32].copy_from_slice(b"com.example.kext");
+    let data = ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__data".to_string(),
+      addr: 8,
+      size: 208,
+      align: 3,
+      contents: contents,
+      relocs: vec![RelocationInfo {
+        address: 200,
+        symbolnum: 0,
+        pcrel: false,
+        length: 3,
+        external: true,
+        kind: X86_64_RELOC_UNSIGNED,
+        scattered: None,
+      }],
+      ..Default::default()
+    };
+    let text = text_section(code.clone(), vec![call]);
+    let kext = object_of(
+      Arch::X86_64,
+      vec![text, data],
+      vec![
+        nlist("_start", N_SECT | N_EXT, 1, 0),
+        nlist("_kmod_info", N_SECT | N_EXT, 2, 8),
+        nlist("_IOLog", N_EXT, 0, 0),
+      ],
+    );
+    let opts = options(OutputKind::KextBundle, vec![("kext.o", kext)]);
+    let out = build(&opts).unwrap();
+    assert_eq!(get_u32(&out, 12, "").unwrap(), MH_KEXT_BUNDLE);
+
+    let image = ImageFile::parse(&out).unwrap();
+    assert_eq!(image.segments[0].segname, "__TEXT");
+    let exec = image.segment("__TEXT_EXEC").unwrap();
+    assert!(exec.sections.iter().any(|sect| sect.sectname == "__text"));
+    let commands = load_commands(&out).unwrap();
+    assert!(!commands.iter().any(|command| {
+      command.cmd == LC_DYLD_INFO_ONLY || command.cmd == LC_MAIN
+    }));
+    let dysymtab = commands
+      .iter()
+      .find(|command| command.cmd == LC_DYSYMTAB)
+      .unwrap()
+      .offset;
+    let field = |offset: usize| get_u32(&out, dysymtab + offset, "").unwrap();
+    let reloc_at = |table: u32, k: usize| {
+      RelocationInfo::parse(&out, table as usize + 8 * k)
+    };
+    let base = image.segments[0].vmaddr;
+
+    // The stub's pointer to _IOLog is bound by the loader.
+    assert_eq!(field(68), 1);
+    let external = reloc_at(field(64), 0);
+    assert!(external.external && !external.pcrel);
+    assert_eq!(external.length, 3);
+    let symbol = &image.symbols[external.symbolnum as usize];
+    assert_eq!(symbol.name, "_IOLog");
+    assert_eq!(symbol.n_desc >> 8, 0);
+
+    // The pointer to _start slides with the kext.
+    assert_eq!(field(76), 1);
+    let local = reloc_at(field(72), 0);
+    assert!(!local.external);
+    let data = section(&image, "__data");
+    assert_eq!(base + local.address as u64, data.addr + 200);
+    let sections: Vec<&Section64> =
+      image.segments.iter().flat_map(|seg| seg.sections.iter()).collect();
+    let text = sections
+      .iter()
+      .position(|sect| sect.sectname == "__text")
+      .unwrap();
+    assert_eq!(local.symbolnum as usize, text + 1);
+    let pointer = get_u64(&out, data.offset as usize + 200, "").unwrap();
+    assert_eq!(pointer, sections[text].addr);
+
+    // Every kext needs a kmod_info.
+    let start = object(Arch::X86_64, code, Vec::new(), "_start", "");
+    let opts = options(OutputKind::KextBundle, vec![("start.o", start)]);
+    match build(&opts) {
+      Err(FinalLinkError::Relocatable(RelocatableError::Kext(
+        KextError::MissingKmodInfo,
+      ))) => {}
+      other => panic!("linked a kext without kmod_info: {:?}", other),
+    }
+  }
+
+  #[test]
+  fn finds_libraries_inside_the_syslibroot() {
+    let root = scratch_dir("syslibroot");
+    let lib = root.join("usr/lib");
+    fs::create_dir_all(&lib).unwrap();
+    let libputs = libputs(&lib);
+    let mut opts = LinkOptions {
+      fixup_chains: Some(false),
+      libraries: vec!["puts".to_string()],
+      library_paths: vec![PathBuf::from("/usr/lib")],
+      syslibroots: vec![root.clone()],
+      ..Default::default()
+    };
+    opts.input_paths = vec![fixture("test.o")];
+    let found = build(&opts);
+
+    // Dylibs re-exported by install name are read from the root too.
+    let libboth = libboth(|opts| {
+      opts.install_name = Some("/usr/lib/libboth.dylib".to_string());
+      opts.reexport_libraries.push(libputs);
+    }).unwrap();
+    fs::write(lib.join("libboth.dylib"), libboth).unwrap();
+    opts.libraries = vec!["both".to_string()];
+    let reexported = build(&opts);
+
+    // A last root of / turns them off.
+    opts.syslibroots.push(PathBuf::from("/"));
+    let missing = build(&opts);
+    fs::remove_dir_all(&root).unwrap();
+    let out = found.unwrap();
+    assert_eq!(lc_strings(&out, LC_LOAD_DYLIB), vec!["/usr/lib/libputs.dylib"]);
+    let out = reexported.unwrap();
+    assert_eq!(lc_strings(&out, LC_LOAD_DYLIB), vec!["/usr/lib/libboth.dylib"]);
+    match missing {
+      Err(FinalLinkError::Dylib(DylibError::LibraryNotFound(ref name)))
+        if name == "both" => (),
+      other => panic!("{:?}", other.map(|data| data.len())),
+    }
+  }
+
+  // The data of the linkedit_data_command `cmd` in `out`.
+  fn linkedit_data(out: &[u8], cmd: u32) -> &[u8] {
+    let command = load_commands(out)
+      .unwrap()
+      .into_iter()
+      .find(|command| command.cmd == cmd)
+      .unwrap();
+    let dataoff = get_u32(out, command.offset + 8, "").unwrap() as usize;
+    let datasize = get_u32(out, command.offset + 12, "").unwrap() as usize;
+    &out[dataoff..(dataoff + datasize)]
+  }
+
+  #[test]
+  fn lists_function_starts_and_data_in_code() {
+    // _main: retq; a jump table entry; _g: retq
+    let text = text_section(vec![0xc3, 0, 0, 0, 0xc3], Vec::new());
+    let dice = DataInCodeEntry {
+      offset: 1,
+      length: 2,
+      kind: DICE_KIND_JUMP_TABLE16,
+    };
+    let main = write_object(
+      Arch::X86_64,
+      ObjectFile {
+        cputype: CPU_TYPE_X86_64,
+        cpusubtype: 3,
+        flags: MH_SUBSECTIONS_VIA_SYMBOLS,
+        sections: vec![text],
+        symbols: vec![
+          nlist("_main", N_SECT | N_EXT, 1, 0),
+          nlist("_g", N_SECT | N_EXT, 1, 4),
+        ],
+        platform: Some((Platform::MacOS, Version::new(11, 0, 0))),
+        loh: Vec::new(),
+        data_in_code: vec![dice],
+      },
+    );
+    let opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    let out = build(&opts).unwrap();
+    let image = ImageFile::parse(&out).unwrap();
+    let base = image.segment("__TEXT").unwrap().vmaddr;
+    let text = section(&image, "__text").addr;
+    let starts = linkedit_data(&out, LC_FUNCTION_STARTS);
+    let starts = function_starts::parse(starts, base).unwrap();
+    assert_eq!(starts, vec![text, text + 4]);
+    let entries = linkedit_data(&out, LC_DATA_IN_CODE);
+    let moved = DataInCodeEntry {
+      offset: (text + 1 - base) as u32,
+      ..dice
+    };
+    assert_eq!(data_in_code::parse(entries).unwrap(), vec![moved]);
+  }
+
+  #[test]
+  fn lists_references_for_the_shared_cache() {
+    // _f: leaq _d(%rip), %rax; retq
+    let lea = RelocationInfo {
+      address: 3,
+      symbolnum: 1,
+      pcrel: true,
+      length: 2,
+      external: true,
+      kind: X86_64_RELOC_SIGNED,
+      scattered: None,
+    };
+    let code = vec![0x48, 0x8d, 0x05, 0, 0, 0, 0, 0xc3];
+    let text = text_section(code, vec![lea]);
+    // _d: a pointer to _f.
+    let pointer = RelocationInfo {
+      address: 0,
+      symbolnum: 0,
+      pcrel: false,
+      length: 3,
+      external: true,
+      kind: X86_64_RELOC_UNSIGNED,
+      scattered: None,
+    };
+    let data = ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__data".to_string(),
+      addr: 8,
+      size: 8,
+      align: 3,
+      contents: vec![0; 8],
+      relocs: vec![pointer],
+      ..Default::default()
+    };
+    let lib = object_of(
+      Arch::X86_64,
+      vec![text, data],
+      vec![
+        nlist("_f", N_SECT | N_EXT, 1, 0),
+        nlist("_d", N_SECT | N_EXT, 2, 8),
+      ],
+    );
+    let mut opts = options(OutputKind::Dylib, vec![("lib.o", lib)]);
+    opts.install_name = Some("/usr/lib/libf.dylib".to_string());
+    let out = build(&opts).unwrap();
+    assert!(load_commands(&out)
+      .unwrap()
+      .iter()
+      .all(|command| command.cmd != LC_SEGMENT_SPLIT_INFO));
+
+    opts.shared_cache_eligible = true;
+    let out = build(&opts).unwrap();
+    let info = linkedit_data(&out, LC_SEGMENT_SPLIT_INFO);
+    let image = ImageFile::parse(&out).unwrap();
+    let names: Vec<&str> = image
+      .segments
+      .iter()
+      .flat_map(|seg| seg.sections.iter())
+      .map(|sect| sect.sectname.as_str())
+      .collect();
+    assert_eq!(names, vec!["__text", "__data"]);
+    assert_eq!(
+      split_seg::parse(info).unwrap(),
+      vec![
+        Reference {
+          from_section: 1,
+          to_section: 2,
+          to_offset: 0,
+          kind: DYLD_CACHE_ADJ_V2_DELTA_32,
+          from_offset: 3,
+        },
+        Reference {
+          from_section: 2,
+          to_section: 1,
+          to_offset: 0,
+          kind: DYLD_CACHE_ADJ_V2_POINTER_64,
+          from_offset: 0,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn links_app_extensions_against_extension_safe_dylibs() {
+    let dir = scratch_dir("extension");
+    let safe = libboth(|opts| {
+      opts.install_name = Some("/usr/lib/libboth.dylib".to_string());
+      opts.application_extension = true;
+    }).unwrap();
+    let both = dir.join("libboth.dylib");
+    fs::write(&both, &safe).unwrap();
+    let code = object(Arch::X86_64, vec![0xc3], Vec::new(), "_main", "_kept");
+    let mut opts = options(OutputKind::Executable, vec![("main.o", code)]);
+    opts.application_extension = true;
+    opts.input_paths.push(both);
+    let out = build(&opts);
+    opts.input_paths.push(libputs(&dir));
+    let unsafe_lib = build(&opts);
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(get_u32(&safe, 24, "").unwrap() & MH_APP_EXTENSION_SAFE != 0);
+    let out = out.unwrap();
+    assert!(get_u32(&out, 24, "").unwrap() & MH_APP_EXTENSION_SAFE != 0);
+    assert_eq!(lc_strings(&out, LC_LOAD_DYLIB), vec!["/usr/lib/libboth.dylib"]);
+    match unsafe_lib {
+      Err(FinalLinkError::Dylib(DylibError::NotExtensionSafe(ref name)))
+        if name == "/usr/lib/libputs.dylib" => (),
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+  }
+
+  #[test]
+  fn drops_dead_strippable_dylibs_nothing_binds_to() {
+    let dir = scratch_dir("dead-strippable");
+    let code = object(Arch::X86_64, vec![0xc3], Vec::new(), "_puts", "");
+    let mut opts = options(OutputKind::Dylib, vec![("puts.o", code)]);
+    opts.install_name = Some("/usr/lib/libputs.dylib".to_string());
+    opts.mark_dead_strippable_dylib = true;
+    let lib = build(&opts).unwrap();
+    let path = dir.join("libputs.dylib");
+    fs::write(&path, &lib).unwrap();
+    let link = |undefined: &str| {
+      let code = vec![0xc3];
+      let code = object(Arch::X86_64, code, Vec::new(), "_main", undefined);
+      let mut opts = options(OutputKind::Executable, vec![("main.o", code)]);
+      opts.input_paths.push(path.clone());
+      build(&opts)
+    };
+    let unused = link("");
+    let used = link("_puts");
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(get_u32(&lib, 24, "").unwrap() & MH_DEAD_STRIPPABLE_DYLIB != 0);
+    assert!(lc_strings(&unused.unwrap(), LC_LOAD_DYLIB).is_empty());
+    let used = used.unwrap();
+    let loaded = lc_strings(&used, LC_LOAD_DYLIB);
+    assert_eq!(loaded, vec!["/usr/lib/libputs.dylib"]);
+    let image = ImageFile::parse(&used).unwrap();
+    let puts = image.symbols.iter().find(|s| s.name == "_puts").unwrap();
+    assert_eq!(puts.n_desc >> 8, 1);
+  }
+
+  #[test]
+  fn links_umbrella_parts_only_from_allowed_clients() {
+    let dir = scratch_dir("umbrella");
+    let lib = libboth(|opts| {
+      opts.install_name = Some("/usr/lib/libboth.dylib".to_string());
+      opts.umbrella = Some("System".to_string());
+      opts.allowable_clients.push("Client".to_string());
+    }).unwrap();
+    let path = dir.join("libboth.dylib");
+    fs::write(&path, &lib).unwrap();
+    let code = object(Arch::X86_64, vec![0xc3], Vec::new(), "_main", "_kept");
+    let mut opts = options(OutputKind::Executable, vec![("main.o", code)]);
+    opts.output_path = PathBuf::from("Other");
+    opts.input_paths.push(path);
+    let other = build(&opts);
+    opts.client_name = Some("Client".to_string());
+    let client = build(&opts);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(lc_strings(&lib, LC_SUB_FRAMEWORK), vec!["System"]);
+    assert_eq!(lc_strings(&lib, LC_SUB_CLIENT), vec!["Client"]);
+    match other {
+      Err(FinalLinkError::Dylib(DylibError::NotAllowedClient(
+        ref name,
+        ref umbrella,
+      ))) if name == "/usr/lib/libboth.dylib" && umbrella == "System" => (),
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+    let client = client.unwrap();
+    let loaded = lc_strings(&client, LC_LOAD_DYLIB);
+    assert_eq!(loaded, vec!["/usr/lib/libboth.dylib"]);
+  }
+
+  #[test]
+  fn links_dtrace_probes() {
+    let call = |address: u32, symbolnum: u32| RelocationInfo {
+      address: address,
+      symbolnum: symbolnum,
+      pcrel: true,
+      length: 2,
+      external: true,
+      kind: X86_64_RELOC_BRANCH,
+      scattered: None,
+    };
+    // _main: callq the probe; callq its is-enabled check; retq
+    let code = vec![0xe8, 0, 0, 0, 0, 0xe8, 0, 0, 0, 0, 0xc3];
+    let text = text_section(code, vec![call(1, 1), call(6, 2)]);
+    let main = object_of(
+      Arch::X86_64,
+      vec![text],
+      vec![
+        nlist("_main", N_SECT | N_EXT, 1, 0),
+        nlist("___dtrace_probe$prov$fire$v1$696e74", N_EXT, 0, 0),
+        nlist("___dtrace_isenabled$prov$fire$v1", N_EXT, 0, 0),
+      ],
+    );
+    let opts = options(OutputKind::Executable, vec![("main.o", main)]);
+    let out = build(&opts).unwrap();
+    assert!(lc_strings(&out, LC_LOAD_DYLIB).is_empty());
+    let image = ImageFile::parse(&out).unwrap();
+    let text = section(&image, "__text");
+    let start = text.offset as usize;
+    assert_eq!(
+      &out[start..start + 11],
+      &[0x90, 0x0f, 0x1f, 0x40, 0x00, 0x33, 0xc0, 0x90, 0x90, 0x90, 0xc3]
+    );
+    let dof = section(&image, "__dof_prov");
+    assert_eq!(dof.flags & SECTION_TYPE, S_DTRACE_DOF);
+    let start = dof.offset as usize;
+    let data = &out[start..(start + dof.size as usize)];
+    assert_eq!(&data[..4], &[0x7f, b'D', b'O', b'F']);
+    // The offset of DOF section `k`'s data.
+    let body = |k: usize| get_u64(data, 64 + 32 * k + 16, "").unwrap() as usize;
+    // The probe is in _main, relative to the mach header, with the probe
+    // and the is-enabled check at their offsets into it.
+    let header = image.segment("__TEXT").unwrap().vmaddr;
+    assert_eq!(get_u64(data, body(1), "").unwrap(), text.addr - header);
+    assert_eq!(get_u32(data, body(3), "").unwrap(), 0);
+    assert_eq!(get_u32(data, body(4), "").unwrap(), 5);
+  }
+
+  #[test]
+  fn leaves_encryption_info_in_device_executables() {
+    let link = |encryptable: Option<bool>| {
+      let symbols = vec![nlist("_main", N_SECT | N_EXT, 1, 0)];
+      let text = vec![text_section(vec![0xc0, 0x03, 0x5f, 0xd6], Vec::new())];
+      let main = object_for(Arch::Arm64, Platform::IOS, text, symbols);
+      let mut opts = options(OutputKind::Executable, vec![("main.o", main)]);
+      opts.arch = Arch::Arm64;
+      opts.platform = Platform::IOS;
+      opts.encryptable = encryptable;
+      build(&opts).unwrap()
+    };
+    let encryption_info = |out: &[u8]| {
+      load_commands(out)
+        .unwrap()
+        .into_iter()
+        .find(|command| command.cmd == LC_ENCRYPTION_INFO_64)
+        .map(|command| {
+          let field = |k: usize| get_u32(out, command.offset + k, "").unwrap();
+          (field(8), field(12), field(16))
+        })
+    };
+
+    let out = link(None);
+    let image = ImageFile::parse(&out).unwrap();
+    let text = image.segment("__TEXT").unwrap();
+    let (cryptoff, cryptsize, cryptid) = encryption_info(&out).unwrap();
+    // From the page after the load commands to the end of __TEXT, with
+    // nothing encrypted yet.
+    assert_eq!(cryptoff, 0x4000);
+    assert!(section(&image, "__text").offset >= cryptoff);
+    assert_eq!((cryptoff + cryptsize) as u64, text.fileoff + text.filesize);
+    assert_eq!(cryptid, 0);
+    assert_eq!(encryption_info(&link(Some(false))), None);
+  }
+
+  #[test]
+  fn adds_notes_and_load_commands() {
+    let dir = scratch_dir("notes");
+    let (note, body) = (dir.join("note"), dir.join("body"));
+    fs::write(&note, b"build 42").unwrap();
+    fs::write(&body, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+    let code = object(Arch::X86_64, vec![0xc3], Vec::new(), "_main", "");
+    let mut opts = options(OutputKind::Executable, vec![("main.o", code)]);
+    opts.notes.push(("firmware".to_string(), note));
+    opts.extra_load_commands.push((0x7f, body));
+    let out = build(&opts);
+    fs::remove_dir_all(&dir).unwrap();
+    let out = out.unwrap();
+
+    let commands = load_commands(&out).unwrap();
+    let note = commands.iter().find(|c| c.cmd == LC_NOTE).unwrap();
+    assert_eq!(&out[note.offset + 8..note.offset + 16], b"firmware");
+    let offset = get_u64(&out, note.offset + 24, "").unwrap();
+    assert_eq!(get_u64(&out, note.offset + 32, "").unwrap(), 8);
+    let start = offset as usize;
+    assert_eq!(&out[start..start + 8], b"build 42");
+    let image = ImageFile::parse(&out).unwrap();
+    assert!(offset >= image.segment("__LINKEDIT").unwrap().fileoff);
+    let raw = commands.iter().find(|c| c.cmd == 0x7f).unwrap();
+    assert_eq!(raw.cmdsize, 16);
+    let body = &out[raw.offset + 8..raw.offset + 16];
+    assert_eq!(body, &[1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+  }
+
+  #[test]
+  fn orders_text_by_order_file_and_profile() {
+    let dir = scratch_dir("order");
+    let (order, profile) = (dir.join("order"), dir.join("profile"));
+    fs::write(&order, "_second\n_first\n").unwrap();
+    fs::write(&profile, "_main;_second 30\n").unwrap();
+    let first = object(Arch::X86_64, vec![0xc3], Vec::new(), "_first", "");
+    let second = object(Arch::X86_64, vec![0xc3], Vec::new(), "_second", "");
+    let mut opts = options(
+      OutputKind::Dylib,
+      vec![("first.o", first), ("second.o", second)],
+    );
+    opts.install_name = Some("/usr/lib/libordered.dylib".to_string());
+    let addresses = |opts: &LinkOptions| {
+      let out = build(opts).unwrap();
+      let image = ImageFile::parse(&out).unwrap();
+      let text = section(&image, "__text").addr;
+      let addr = |name: &str| {
+        let sym = image.symbols.iter().find(|s| s.name == name).unwrap();
+        sym.n_value - text
+      };
+      (addr("_first"), addr("_second"))
+    };
+
+    assert_eq!(addresses(&opts), (0, 4));
+    opts.profile_order = Some(profile);
+    let profiled = addresses(&opts);
+    opts.profile_order = None;
+    opts.order_file = Some(order);
+    let ordered = addresses(&opts);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(profiled, (4, 0));
+    assert_eq!(ordered, (4, 0));
+  }
+}
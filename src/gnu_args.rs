@@ -0,0 +1,212 @@
+// GNU ld and lld spellings of options, so that Makefiles written for ELF
+// targets need fewer changes to link Mach-O. Each is rewritten into the
+// ld64 option with the same meaning before the arguments are parsed:
+//
+//   -rpath=<dir>, --rpath[=]<dir>          -rpath <dir>
+//   -soname[=]<name>, --soname[=]<name>    -install_name <name>
+//   --whole-archive ... --no-whole-archive -force_load <archive>, for each
+//                                          archive (and -l<name> found as a
+//                                          static library in the -L
+//                                          directories) between them
+//   --version-script[=]<file>,             -exported_symbol (or, for a
+//   --dynamic-list[=]<file>                script with only local: names,
+//                                          -unexported_symbol) per name
+//   --export-dynamic-symbol[=]<name>       -exported_symbol _<name>
+//
+// ELF names have no leading underscore, so one is added to each C name.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use search;
+
+#[derive(Debug)]
+pub enum GnuArgsError {
+  MissingValue(String),
+  IoError(PathBuf, io::Error),
+  // A version script or dynamic list which isn't just lists of C names,
+  // and what in it couldn't be translated.
+  UnsupportedScript(PathBuf, String),
+}
+
+pub type Result<T> = ::std::result::Result<T, GnuArgsError>;
+
+// The value of `opt` in `arg`, as `opt=value`, or from the next argument.
+fn value<I: Iterator<Item = String>>(
+  arg: &str,
+  opt: &str,
+  args: &mut I,
+) -> Option<Result<String>> {
+  if arg == opt {
+    Some(
+      args
+        .next()
+        .ok_or_else(|| GnuArgsError::MissingValue(opt.to_string())),
+    )
+  } else if arg.starts_with(opt) && arg[opt.len()..].starts_with('=') {
+    Some(Ok(arg[opt.len() + 1..].to_string()))
+  } else {
+    None
+  }
+}
+
+fn value_of<I: Iterator<Item = String>>(
+  arg: &str,
+  opts: &[&str],
+  args: &mut I,
+) -> Option<Result<String>> {
+  opts.iter().filter_map(|opt| value(arg, opt, args)).next()
+}
+
+// `args`, with GNU spellings replaced by ld64 ones.
+pub fn translate(args: Vec<String>) -> Result<Vec<String>> {
+  // -l under --whole-archive is looked up in every -L directory, wherever
+  // it is on the command line.
+  let library_paths: Vec<PathBuf> = args
+    .iter()
+    .filter(|arg| arg.starts_with("-L") && arg.len() > 2)
+    .map(|arg| PathBuf::from(&arg[2..]))
+    .collect();
+  let mut translated: Vec<String> = Vec::new();
+  let mut whole_archive = false;
+  let mut args = args.into_iter();
+  while let Some(arg) = args.next() {
+    if let Some(dir) = value_of(&arg, &["-rpath", "--rpath"], &mut args) {
+      translated.push("-rpath".to_string());
+      translated.push(dir?);
+    } else if let Some(name) =
+      value_of(&arg, &["-soname", "--soname"], &mut args)
+    {
+      translated.push("-install_name".to_string());
+      translated.push(name?);
+    } else if let Some(path) =
+      value_of(&arg, &["--version-script", "--dynamic-list"], &mut args)
+    {
+      let path = PathBuf::from(path?);
+      let (global, local) = read_script(&path)?;
+      let (opt, names) = if global.is_empty() {
+        ("-unexported_symbol", local)
+      } else {
+        ("-exported_symbol", global)
+      };
+      for name in names {
+        translated.push(opt.to_string());
+        translated.push(format!("_{}", name));
+      }
+    } else if let Some(name) =
+      value_of(&arg, &["--export-dynamic-symbol"], &mut args)
+    {
+      translated.push("-exported_symbol".to_string());
+      translated.push(format!("_{}", name?));
+    } else if arg == "--whole-archive" {
+      whole_archive = true;
+    } else if arg == "--no-whole-archive" {
+      whole_archive = false;
+    } else if whole_archive && !arg.starts_with('-') && arg.ends_with(".a") {
+      translated.push("-force_load".to_string());
+      translated.push(arg);
+    } else if whole_archive && arg.starts_with("-l") && arg.len() > 2 {
+      // Shared libraries are linked whole anyway, as GNU ld does.
+      match search::find_library(&arg[2..], &library_paths) {
+        Some(ref path) if !search::is_dylib_path(path) => {
+          translated.push("-force_load".to_string());
+          translated.push(path.to_string_lossy().into_owned());
+        }
+        _ => translated.push(arg),
+      }
+    } else {
+      translated.push(arg);
+    }
+  }
+  Ok(translated)
+}
+
+// The global: and local: names in a version script or dynamic list
+// (where every name is global). Version tags and dependencies are ignored;
+// Mach-O has no symbol versioning.
+fn read_script(path: &Path) -> Result<(Vec<String>, Vec<String>)> {
+  let mut text = String::new();
+  File::open(path)
+    .and_then(|mut f| f.read_to_string(&mut text))
+    .map_err(|e| GnuArgsError::IoError(path.to_path_buf(), e))?;
+  let unsupported = |what: &str| {
+    GnuArgsError::UnsupportedScript(path.to_path_buf(), what.to_string())
+  };
+  let mut global: Vec<String> = Vec::new();
+  let mut local: Vec<String> = Vec::new();
+  let mut depth = 0;
+  let mut in_local = false;
+  let tokens =
+    tokenize(&text).ok_or_else(|| unsupported("unclosed comment"))?;
+  let mut tokens = tokens.iter().peekable();
+  while let Some(token) = tokens.next() {
+    match token.as_str() {
+      "{" => {
+        depth += 1;
+        in_local = false;
+      }
+      "}" => depth -= 1,
+      ";" | ":" => (),
+      "extern" => return Err(unsupported("extern \"C++\" blocks")),
+      _ if depth == 0 => (),
+      "global" | "local"
+        if tokens.peek().map(|t| t.as_str()) == Some(":") =>
+      {
+        in_local = token == "local";
+      }
+      name if in_local => local.push(name.to_string()),
+      name => global.push(name.to_string()),
+    }
+  }
+  if depth != 0 {
+    return Err(unsupported("unbalanced braces"));
+  }
+  // `local: *;` is what the exported list already means.
+  local.retain(|name| name != "*");
+  Ok((global, local))
+}
+
+// The words and punctuation of a script, without comments, or None if a
+// comment isn't closed.
+fn tokenize(text: &str) -> Option<Vec<String>> {
+  let mut tokens: Vec<String> = Vec::new();
+  let mut word = String::new();
+  let mut chars = text.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '#' => {
+        while chars.peek().map_or(false, |&c| c != '\n') {
+          chars.next();
+        }
+      }
+      '/' if chars.peek() == Some(&'*') => {
+        chars.next();
+        let mut last = ' ';
+        loop {
+          let c = chars.next()?;
+          if last == '*' && c == '/' {
+            break;
+          }
+          last = c;
+        }
+      }
+      '{' | '}' | ';' | ':' => {
+        if !word.is_empty() {
+          tokens.push(word.split_off(0));
+        }
+        tokens.push(c.to_string());
+      }
+      c if c.is_whitespace() => {
+        if !word.is_empty() {
+          tokens.push(word.split_off(0));
+        }
+      }
+      c => word.push(c),
+    }
+  }
+  if !word.is_empty() {
+    tokens.push(word);
+  }
+  Some(tokens)
+}
@@ -0,0 +1,1099 @@
+// The output image being assembled: segments of sections with their
+// contents, the other load commands, and the blobs which go in __LINKEDIT.
+// `write()` lays everything out and serializes the whole file.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use macho::data_in_code::{self, DataInCodeEntry};
+use macho::function_starts;
+use macho::header::{header_size, MachHeader64};
+use macho::load_command::{DyldInfo, Dysymtab, LoadCommand, Section64,
+                          Segment64, LC_CODE_SIGNATURE, LC_DATA_IN_CODE,
+                          LC_DYLD_INFO_ONLY, LC_DYSYMTAB, LC_ENCRYPTION_INFO,
+                          LC_ENCRYPTION_INFO_64, LC_FUNCTION_STARTS, LC_NOTE,
+                          LC_SEGMENT_SPLIT_INFO, LC_SYMTAB};
+use macho::loh::{self, Loh};
+use macho::split_seg::{self, Reference};
+use macho::symtab::nlist_size;
+use macho::{round_up, Arch, Platform, Version, MH_EXECUTE, MH_KEXT_BUNDLE,
+            MH_PRELOAD, SECTION_TYPE, SG_READ_ONLY, S_ATTR_PURE_INSTRUCTIONS,
+            S_ATTR_SOME_INSTRUCTIONS, S_GB_ZEROFILL, S_THREAD_LOCAL_ZEROFILL,
+            S_ZEROFILL, VM_PROT_EXECUTE, VM_PROT_NONE, VM_PROT_READ,
+            VM_PROT_WRITE};
+use codesign::{self, ExecSegment, Signer};
+use dependency_info;
+use timing;
+use uuid;
+
+// Where __TEXT starts in 64-bit executables, leaving the low 4GB unmapped.
+pub const DEFAULT_EXECUTABLE_BASE: u64 = 0x1_0000_0000;
+
+// Where __TEXT starts in executables for `arch`. 32-bit address spaces
+// can't spare 4GB, so __PAGEZERO is just the first page.
+pub fn default_executable_base(arch: Arch) -> u64 {
+  if arch.is_64_bit() {
+    DEFAULT_EXECUTABLE_BASE
+  } else {
+    arch.page_size()
+  }
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+  // (segname, sectname) of a section added twice.
+  DuplicateSection(String, String),
+  IoError(io::Error),
+}
+
+impl From<io::Error> for ImageError {
+  fn from(error: io::Error) -> Self {
+    ImageError::IoError(error)
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, ImageError>;
+
+// A reference from a section to another part of the image, which the
+// shared cache builder adjusts if it moves the two apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionReference {
+  // DYLD_CACHE_ADJ_V2_*.
+  pub kind: u64,
+  // Where the reference is, as an offset into its section.
+  pub offset: u64,
+  // The (segname, sectname) it refers to, or None for the mach header, and
+  // the offset into that.
+  pub target: Option<(String, String)>,
+  pub target_offset: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OutputSection {
+  pub sectname: String,
+  // log2 of the alignment.
+  pub align: u32,
+  pub flags: u32,
+  pub contents: Vec<u8>,
+  // Only for zero-fill sections, which have no contents in the file.
+  pub zerofill_size: u64,
+  pub reserved1: u32,
+  pub reserved2: u32,
+  // Where the functions in the section start (each atom, in a code
+  // section), and the data in it, as offsets into the section. The image
+  // lists them at their final addresses in LC_FUNCTION_STARTS and
+  // LC_DATA_IN_CODE.
+  pub function_starts: Vec<u64>,
+  pub data_in_code: Vec<DataInCodeEntry>,
+  // The references in the section, for LC_SEGMENT_SPLIT_INFO.
+  pub references: Vec<SectionReference>,
+  // Assigned by layout.
+  pub addr: u64,
+  pub offset: u64,
+}
+
+impl OutputSection {
+  pub fn new(
+    sectname: &str,
+    contents: Vec<u8>,
+    align: u32,
+    flags: u32,
+  ) -> Self {
+    OutputSection {
+      sectname: sectname.to_string(),
+      align: align,
+      flags: flags,
+      contents: contents,
+      ..Default::default()
+    }
+  }
+
+  pub fn zerofill(sectname: &str, size: u64, align: u32, flags: u32) -> Self {
+    OutputSection {
+      sectname: sectname.to_string(),
+      align: align,
+      flags: flags,
+      zerofill_size: size,
+      ..Default::default()
+    }
+  }
+
+  pub fn is_zerofill(&self) -> bool {
+    match self.flags & SECTION_TYPE {
+      S_ZEROFILL | S_GB_ZEROFILL | S_THREAD_LOCAL_ZEROFILL => true,
+      _ => false,
+    }
+  }
+
+  pub fn size(&self) -> u64 {
+    if self.is_zerofill() {
+      self.zerofill_size
+    } else {
+      self.contents.len() as u64
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OutputSegment {
+  pub segname: String,
+  pub maxprot: u32,
+  pub initprot: u32,
+  pub flags: u32,
+  pub sections: Vec<OutputSection>,
+  // Assigned by layout.
+  pub vmaddr: u64,
+  pub vmsize: u64,
+  pub fileoff: u64,
+  pub filesize: u64,
+}
+
+// Sections of __DATA which dyld only writes while applying fixups, and so
+// go in __DATA_CONST, which it makes read-only afterwards.
+const DATA_CONST_SECTIONS: [&'static str; 11] = [
+  "__got",
+  "__const",
+  "__cfstring",
+  "__mod_init_func",
+  "__mod_term_func",
+  "__objc_classlist",
+  "__objc_nlclslist",
+  "__objc_catlist",
+  "__objc_nlcatlist",
+  "__objc_protolist",
+  "__objc_imageinfo",
+];
+
+// Whether to use __DATA_CONST when neither -data_const nor -no_data_const
+// was given: as with ld64, from the OS versions whose dyld supports it.
+pub fn data_const_default(platform: Platform, min_version: Version) -> bool {
+  let introduced = match platform {
+    Platform::MacOS => Version::new(10, 15, 0),
+    Platform::IOS
+    | Platform::IOSSimulator
+    | Platform::TvOS
+    | Platform::TvOSSimulator
+    | Platform::MacCatalyst => Version::new(13, 0, 0),
+    Platform::WatchOS | Platform::WatchOSSimulator => Version::new(6, 0, 0),
+    Platform::BridgeOS | Platform::DriverKit => Version::new(0, 0, 0),
+  };
+  min_version >= introduced
+}
+
+// Whether executables for `platform` get an LC_ENCRYPTION_INFO by default.
+// Only the App Store for devices encrypts them.
+pub fn encryption_default(platform: Platform) -> bool {
+  match platform {
+    Platform::IOS | Platform::TvOS | Platform::WatchOS => true,
+    _ => false,
+  }
+}
+
+// -segprot: protections for a segment instead of the default ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentProtection {
+  pub segname: String,
+  pub maxprot: u32,
+  pub initprot: u32,
+}
+
+// -sectalign: a minimum alignment (log2) for an output section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionAlignment {
+  pub segname: String,
+  pub sectname: String,
+  pub align: u32,
+}
+
+pub fn default_protection(segname: &str) -> u32 {
+  match segname {
+    "__PAGEZERO" => VM_PROT_NONE,
+    "__TEXT" | "__TEXT_EXEC" => VM_PROT_READ | VM_PROT_EXECUTE,
+    "__LINKEDIT" => VM_PROT_READ,
+    _ => VM_PROT_READ | VM_PROT_WRITE,
+  }
+}
+
+fn protect(protections: &[SegmentProtection], seg: &mut OutputSegment) {
+  for prot in protections.iter() {
+    if prot.segname == seg.segname {
+      seg.maxprot = prot.maxprot;
+      seg.initprot = prot.initprot;
+    }
+  }
+}
+
+impl OutputSegment {
+  pub fn new(segname: &str) -> Self {
+    let prot = default_protection(segname);
+    OutputSegment {
+      segname: segname.to_string(),
+      maxprot: prot,
+      initprot: prot,
+      ..Default::default()
+    }
+  }
+
+  fn to_command(&self, arch: Arch) -> LoadCommand {
+    let seg = Segment64 {
+      segname: self.segname.clone(),
+      vmaddr: self.vmaddr,
+      vmsize: self.vmsize,
+      fileoff: self.fileoff,
+      filesize: self.filesize,
+      maxprot: self.maxprot,
+      initprot: self.initprot,
+      flags: self.flags,
+      sections: self
+        .sections
+        .iter()
+        .map(|sect| Section64 {
+          sectname: sect.sectname.clone(),
+          segname: self.segname.clone(),
+          addr: sect.addr,
+          size: sect.size(),
+          offset: sect.offset as u32,
+          align: sect.align,
+          flags: sect.flags,
+          reserved1: sect.reserved1,
+          reserved2: sect.reserved2,
+          ..Default::default()
+        })
+        .collect(),
+    };
+    LoadCommand::segment(arch, seg)
+  }
+}
+
+// How many blobs the linkedit command `cmd` points at.
+fn blob_parts(cmd: u32) -> usize {
+  match cmd {
+    LC_DYLD_INFO_ONLY => 5,
+    LC_SYMTAB => 2,
+    LC_DYSYMTAB => 3,
+    _ => 1,
+  }
+}
+
+const X86_THREAD_STATE32: u32 = 1;
+const X86_THREAD_STATE64: u32 = 4;
+const ARM_THREAD_STATE: u32 = 1;
+const ARM_THREAD_STATE64: u32 = 6;
+
+// LC_UNIXTHREAD starting the main thread at `pc` with its stack at `sp`,
+// and every other register zero.
+fn unix_thread(arch: Arch, pc: u64, sp: u64) -> LoadCommand {
+  // Register indices, counting registers of the thread state's width.
+  let (flavor, count, sp_index, pc_index) = match arch {
+    // rax..r15, rip, rflags, cs, fs, gs
+    Arch::X86_64 => (X86_THREAD_STATE64, 21, 7, 16),
+    // eax, ebx, ecx, edx, edi, esi, ebp, esp, ss, eflags, eip, then the
+    // segment registers
+    Arch::I386 => (X86_THREAD_STATE32, 16, 7, 10),
+    // r0..r12, sp, lr, pc, cpsr
+    Arch::Armv7 | Arch::Armv7s => (ARM_THREAD_STATE, 17, 13, 15),
+    // x0..x28, fp, lr, sp, pc, then cpsr and padding. arm64_32 uses the
+    // same 64-bit state.
+    _ => (ARM_THREAD_STATE64, 34, 31, 32),
+  };
+  let mut regs: Vec<u64> = vec![0; count];
+  regs[sp_index] = sp;
+  regs[pc_index] = pc;
+  let wide = flavor == X86_THREAD_STATE64 || flavor == ARM_THREAD_STATE64;
+  LoadCommand::UnixThread {
+    flavor: flavor,
+    state: regs
+      .iter()
+      .flat_map(|&reg| {
+        if wide {
+          vec![reg as u32, (reg >> 32) as u32]
+        } else {
+          vec![reg as u32]
+        }
+      })
+      .collect(),
+  }
+}
+
+// A blob in __LINKEDIT, referenced by a linkedit_data_command with `cmd`.
+// Commands which point at several blobs have one per part, in a row:
+// LC_DYLD_INFO_ONLY's rebase, bind, weak bind, lazy bind and export
+// streams, LC_SYMTAB's symbols and strings, and LC_DYSYMTAB's indirect
+// symbol table and (for kexts) external and local relocations.
+#[derive(Debug, Clone)]
+pub struct LinkeditBlob {
+  pub cmd: u32,
+  pub data: Vec<u8>,
+}
+
+// Data for some other tool, in __LINKEDIT, which an LC_NOTE points to.
+#[derive(Debug, Clone)]
+pub struct Note {
+  // Up to 16 bytes.
+  pub owner: String,
+  pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Image {
+  pub arch: Arch,
+  pub filetype: u32,
+  pub flags: u32,
+  pub page_size: u64,
+  // Where __TEXT is mapped. Anything below is covered by __PAGEZERO.
+  pub image_base: u64,
+  // Segments which have to start at a particular address (-segaddr).
+  pub segment_addresses: Vec<(String, u64)>,
+  // Whether dyld loads the image. If not, the entry point goes in an
+  // LC_UNIXTHREAD, since LC_MAIN is for dyld.
+  pub uses_dyld: bool,
+  // Extra space reserved after the load commands.
+  pub headerpad: u64,
+  // Address of main() in executables, for LC_MAIN. Other outputs have no
+  // entry point.
+  pub entry_point: Option<u64>,
+  // Stack size for LC_MAIN, or 0 for the default.
+  pub stack_size: u64,
+  // -stack_addr: where the main thread's stack starts (it grows down).
+  // LC_MAIN can't say, so the entry point goes in an LC_UNIXTHREAD instead,
+  // and must be `start` rather than main(), as with ld64.
+  pub stack_addr: Option<u64>,
+  // Whether to move const data out of __DATA into __DATA_CONST.
+  pub data_const: bool,
+  // Overrides of the default segment protections and section alignments.
+  pub segment_protections: Vec<SegmentProtection>,
+  pub section_alignments: Vec<SectionAlignment>,
+  // Segments other than __PAGEZERO and __LINKEDIT, which are synthesized.
+  pub segments: Vec<OutputSegment>,
+  // Load commands other than segments and those for __LINKEDIT blobs.
+  pub load_commands: Vec<LoadCommand>,
+  pub linkedit: Vec<LinkeditBlob>,
+  // LC_DYSYMTAB's partition of the symbol table, if there is one. Where the
+  // indirect symbol table is comes from its blob.
+  pub dysymtab: Dysymtab,
+  pub notes: Vec<Note>,
+  // arm64 optimization hints, at output addresses, to relax once the
+  // sections are laid out.
+  pub optimization_hints: Vec<Loh>,
+  // How to sign the output ad-hoc, if it should be.
+  pub code_signature: Option<Signer>,
+  // Whether to list the sections' references in LC_SEGMENT_SPLIT_INFO, for
+  // dylibs which may go in the dyld shared cache.
+  pub split_seg_info: bool,
+  // Whether to leave an LC_ENCRYPTION_INFO for App Store processing to
+  // encrypt __TEXT and fill in. The first page, with the load commands, is
+  // never encrypted, so the sections start on the next one.
+  pub encryption_info: bool,
+  // Assigned by layout.
+  pub linkedit_segment: OutputSegment,
+}
+
+impl Image {
+  pub fn new(arch: Arch, filetype: u32) -> Self {
+    Image {
+      arch: arch,
+      filetype: filetype,
+      flags: 0,
+      page_size: arch.page_size(),
+      image_base: if filetype == MH_EXECUTE {
+        default_executable_base(arch)
+      } else {
+        0
+      },
+      segment_addresses: Vec::new(),
+      uses_dyld: filetype != MH_PRELOAD && filetype != MH_KEXT_BUNDLE,
+      headerpad: 0,
+      entry_point: None,
+      stack_size: 0,
+      stack_addr: None,
+      data_const: false,
+      segment_protections: Vec::new(),
+      section_alignments: Vec::new(),
+      segments: vec![OutputSegment::new("__TEXT")],
+      load_commands: Vec::new(),
+      linkedit: Vec::new(),
+      dysymtab: Dysymtab::default(),
+      notes: Vec::new(),
+      optimization_hints: Vec::new(),
+      code_signature: None,
+      split_seg_info: false,
+      encryption_info: false,
+      linkedit_segment: OutputSegment::new("__LINKEDIT"),
+    }
+  }
+
+  pub fn segment_mut(&mut self, segname: &str) -> &mut OutputSegment {
+    let idx = match self.segments.iter().position(|s| s.segname == segname) {
+      Some(idx) => idx,
+      None => {
+        self.segments.push(OutputSegment::new(segname));
+        self.segments.len() - 1
+      }
+    };
+    &mut self.segments[idx]
+  }
+
+  pub fn find_section(
+    &self,
+    segname: &str,
+    sectname: &str,
+  ) -> Option<&OutputSection> {
+    self
+      .segments
+      .iter()
+      .find(|seg| seg.segname == segname)
+      .and_then(|seg| seg.sections.iter().find(|s| s.sectname == sectname))
+  }
+
+  pub fn add_section(
+    &mut self,
+    segname: &str,
+    section: OutputSection,
+  ) -> Result<()> {
+    if self.find_section(segname, &section.sectname).is_some() {
+      return Err(ImageError::DuplicateSection(
+        segname.to_string(),
+        section.sectname,
+      ));
+    }
+    self.segment_mut(segname).sections.push(section);
+    Ok(())
+  }
+
+  // Add a section holding the contents of `path` (-sectcreate), or an empty
+  // one if no path is given (-add_empty_section).
+  pub fn create_section(
+    &mut self,
+    segname: &str,
+    sectname: &str,
+    path: Option<&Path>,
+  ) -> Result<()> {
+    let mut contents: Vec<u8> = Vec::new();
+    if let Some(path) = path {
+      dependency_info::record_input(path);
+      File::open(path)?.read_to_end(&mut contents)?;
+    }
+    self.add_section(segname, OutputSection::new(sectname, contents, 0, 0))
+  }
+
+  // Add an LC_NOTE for `owner` with the contents of `path` (-add_note).
+  pub fn add_note(&mut self, owner: &str, path: &Path) -> Result<()> {
+    dependency_info::record_input(path);
+    let mut data: Vec<u8> = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    self.notes.push(Note {
+      owner: owner.to_string(),
+      data: data,
+    });
+    Ok(())
+  }
+
+  // Add a load command the linker knows nothing about, whose body (what
+  // follows cmd and cmdsize) is the contents of `path` (-add_load_command).
+  pub fn add_load_command(&mut self, cmd: u32, path: &Path) -> Result<()> {
+    dependency_info::record_input(path);
+    let mut body: Vec<u8> = Vec::new();
+    File::open(path)?.read_to_end(&mut body)?;
+    self.load_commands.push(LoadCommand::Raw {
+      cmd: cmd,
+      body: body,
+    });
+    Ok(())
+  }
+
+  // Whether the segment commands start with __PAGEZERO, which dyld counts
+  // when numbering segments.
+  pub fn has_pagezero(&self) -> bool {
+    self.filetype == MH_EXECUTE && self.image_base > 0
+  }
+
+  fn pagezero(&self) -> OutputSegment {
+    let mut seg = OutputSegment {
+      vmsize: self.image_base,
+      ..OutputSegment::new("__PAGEZERO")
+    };
+    protect(&self.segment_protections, &mut seg);
+    seg
+  }
+
+  // Move the sections in DATA_CONST_SECTIONS from __DATA to __DATA_CONST,
+  // which goes right after __TEXT.
+  fn split_data_const(&mut self) {
+    let data = match self.segments.iter().position(|s| s.segname == "__DATA")
+    {
+      Some(data) => data,
+      None => return,
+    };
+    let (moved, kept): (Vec<OutputSection>, Vec<OutputSection>) = self
+      .segments[data]
+      .sections
+      .drain(..)
+      .partition(|sect| DATA_CONST_SECTIONS.contains(&sect.sectname.as_str()));
+    self.segments[data].sections = kept;
+    if moved.is_empty() {
+      return;
+    }
+    if self.segments[data].sections.is_empty() {
+      self.segments.remove(data);
+    }
+    let data_const = match self
+      .segments
+      .iter()
+      .position(|s| s.segname == "__DATA_CONST")
+    {
+      Some(data_const) => data_const,
+      None => {
+        let mut seg = OutputSegment::new("__DATA_CONST");
+        seg.flags = SG_READ_ONLY;
+        self.segments.insert(1, seg);
+        1
+      }
+    };
+    self.segments[data_const].sections.extend(moved);
+  }
+
+  // Kexts keep their code in __TEXT_EXEC, right after a read-only __TEXT
+  // holding the headers and constants.
+  fn split_text_exec(&mut self) {
+    let (code, rest): (Vec<OutputSection>, Vec<OutputSection>) = self
+      .segments[0]
+      .sections
+      .drain(..)
+      .partition(|sect| {
+        sect.flags & (S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS) != 0
+      });
+    self.segments[0].sections = rest;
+    self.segments[0].maxprot = VM_PROT_READ;
+    self.segments[0].initprot = VM_PROT_READ;
+    if !code.is_empty() {
+      let mut text_exec = OutputSegment::new("__TEXT_EXEC");
+      text_exec.sections = code;
+      self.segments.insert(1, text_exec);
+    }
+  }
+
+  // Apply -segprot and -sectalign to the segments as they are now.
+  fn apply_overrides(&mut self) {
+    let linkedit = Some(&mut self.linkedit_segment);
+    for seg in self.segments.iter_mut().chain(linkedit) {
+      protect(&self.segment_protections, seg);
+      for sect in seg.sections.iter_mut() {
+        for forced in self.section_alignments.iter() {
+          if forced.segname == seg.segname && forced.sectname == sect.sectname
+          {
+            sect.align = sect.align.max(forced.align);
+          }
+        }
+      }
+    }
+  }
+
+  // LC_ENCRYPTION_INFO(_64) with cryptid 0, covering __TEXT from its first
+  // section on.
+  fn encryption_command(&self) -> LoadCommand {
+    let text = &self.segments[0];
+    let end = text.fileoff + text.filesize;
+    let start = text
+      .sections
+      .iter()
+      .filter(|sect| !sect.is_zerofill())
+      .map(|sect| round_up(sect.offset, self.page_size))
+      .min()
+      .unwrap_or(end)
+      .min(end);
+    let cmd = if self.arch.is_64_bit() {
+      LC_ENCRYPTION_INFO_64
+    } else {
+      LC_ENCRYPTION_INFO
+    };
+    LoadCommand::EncryptionInfo {
+      cmd: cmd,
+      cryptoff: start as u32,
+      cryptsize: (end - start) as u32,
+      cryptid: 0,
+    }
+  }
+
+  // Every load command, in the order they're written.
+  pub fn all_load_commands(&self) -> Vec<LoadCommand> {
+    let mut cmds: Vec<LoadCommand> = Vec::new();
+    if self.has_pagezero() {
+      cmds.push(self.pagezero().to_command(self.arch));
+    }
+    for seg in self.segments.iter() {
+      cmds.push(seg.to_command(self.arch));
+    }
+    cmds.push(self.linkedit_segment.to_command(self.arch));
+    let (offsets, _) = self.blob_offsets();
+    let mut owners = self.notes.iter().map(|note| note.owner.clone());
+    let mut i = 0;
+    while i < self.linkedit.len() {
+      let cmd = self.linkedit[i].cmd;
+      let parts = self.linkedit[i..]
+        .iter()
+        .take(blob_parts(cmd))
+        .take_while(|blob| blob.cmd == cmd)
+        .count();
+      // (file offset, size) of each part. Empty parts of a command with
+      // several are at offset 0, as ld64 leaves them.
+      let place = |part: usize| -> (u32, u32) {
+        if part >= parts {
+          return (0, 0);
+        }
+        let blob = &self.linkedit[i + part];
+        let dataoff = self.linkedit_segment.fileoff + offsets[i + part];
+        if blob.data.is_empty() && blob_parts(cmd) > 1 {
+          (0, 0)
+        } else {
+          (dataoff as u32, blob.data.len() as u32)
+        }
+      };
+      cmds.push(match cmd {
+        LC_NOTE => LoadCommand::Note {
+          data_owner: owners.next().unwrap_or_default(),
+          offset: place(0).0 as u64,
+          size: place(0).1 as u64,
+        },
+        LC_DYLD_INFO_ONLY => LoadCommand::DyldInfo(DyldInfo {
+          rebase_off: place(0).0,
+          rebase_size: place(0).1,
+          bind_off: place(1).0,
+          bind_size: place(1).1,
+          weak_bind_off: place(2).0,
+          weak_bind_size: place(2).1,
+          lazy_bind_off: place(3).0,
+          lazy_bind_size: place(3).1,
+          export_off: place(4).0,
+          export_size: place(4).1,
+        }),
+        LC_SYMTAB => LoadCommand::Symtab {
+          symoff: place(0).0,
+          nsyms: place(0).1 / nlist_size(self.arch.is_64_bit()) as u32,
+          stroff: place(1).0,
+          strsize: place(1).1,
+        },
+        LC_DYSYMTAB => LoadCommand::Dysymtab(Dysymtab {
+          indirectsymoff: place(0).0,
+          nindirectsyms: place(0).1 / 4,
+          extreloff: place(1).0,
+          nextrel: place(1).1 / 8,
+          locreloff: place(2).0,
+          nlocrel: place(2).1 / 8,
+          ..self.dysymtab
+        }),
+        cmd => LoadCommand::LinkeditData {
+          cmd: cmd,
+          dataoff: place(0).0,
+          datasize: place(0).1,
+        },
+      });
+      i += parts;
+    }
+    match (self.filetype, self.entry_point) {
+      (MH_EXECUTE, Some(entry)) | (MH_PRELOAD, Some(entry)) => {
+        if self.stack_addr.is_some() || !self.uses_dyld {
+          // A zero stack pointer leaves the stack to the loader.
+          let sp = self.stack_addr.unwrap_or(0);
+          cmds.push(unix_thread(self.arch, entry, sp));
+        } else {
+          // __TEXT starts at file offset 0.
+          cmds.push(LoadCommand::Main {
+            entryoff: entry - self.image_base,
+            stacksize: self.stack_size,
+          });
+        }
+      }
+      _ => (),
+    }
+    if self.encryption_info {
+      cmds.push(self.encryption_command());
+    }
+    cmds.extend(self.load_commands.iter().cloned());
+    cmds
+  }
+
+  pub fn sizeofcmds(&self) -> u64 {
+    self
+      .all_load_commands()
+      .iter()
+      .map(|cmd| cmd.cmdsize() as u64)
+      .sum()
+  }
+
+  // Where each __LINKEDIT blob starts relative to the segment, and the
+  // segment's size. ld64 aligns the code signature to 16 bytes.
+  fn blob_offsets(&self) -> (Vec<u64>, u64) {
+    let mut offsets: Vec<u64> = Vec::new();
+    let mut offset: u64 = 0;
+    for blob in self.linkedit.iter() {
+      let align = if blob.cmd == LC_CODE_SIGNATURE { 16 } else { 8 };
+      offset = round_up(offset, align);
+      offsets.push(offset);
+      offset += blob.data.len() as u64;
+    }
+    (offsets, round_up(offset, 8))
+  }
+
+  // Assign addresses and file offsets to every segment and section.
+  pub fn layout(&mut self) {
+    if self.data_const {
+      self.split_data_const();
+    }
+    if self.filetype == MH_KEXT_BUNDLE {
+      self.split_text_exec();
+    }
+    self.apply_overrides();
+    let header_end =
+      header_size(self.arch.is_64_bit()) + self.sizeofcmds() + self.headerpad;
+    let page_size = self.page_size;
+    let encrypted = self.encryption_info;
+    let mut vmaddr = self.image_base;
+    let mut fileoff: u64 = 0;
+
+    for (i, seg) in self.segments.iter_mut().enumerate() {
+      if let Some(&(_, addr)) = self
+        .segment_addresses
+        .iter()
+        .find(|&&(ref segname, _)| *segname == seg.segname)
+      {
+        vmaddr = addr;
+      }
+      seg.vmaddr = vmaddr;
+      seg.fileoff = fileoff;
+      // The mach header and load commands are mapped at the start of the
+      // first segment (__TEXT).
+      let mut cur: u64 = match i {
+        0 if encrypted => round_up(header_end, page_size),
+        0 => header_end,
+        _ => 0,
+      };
+      for sect in seg.sections.iter_mut().filter(|s| !s.is_zerofill()) {
+        cur = round_up(cur, 1 << sect.align);
+        sect.addr = vmaddr + cur;
+        sect.offset = fileoff + cur;
+        cur += sect.size();
+      }
+      seg.filesize = round_up(cur, page_size);
+      for sect in seg.sections.iter_mut().filter(|s| s.is_zerofill()) {
+        cur = round_up(cur, 1 << sect.align);
+        sect.addr = vmaddr + cur;
+        sect.offset = 0;
+        cur += sect.size();
+      }
+      seg.vmsize = round_up(cur, page_size);
+      vmaddr += seg.vmsize;
+      fileoff += seg.filesize;
+    }
+
+    let (_, linkedit_size) = self.blob_offsets();
+    self.linkedit_segment.vmaddr = vmaddr;
+    self.linkedit_segment.fileoff = fileoff;
+    self.linkedit_segment.filesize = linkedit_size;
+    self.linkedit_segment.vmsize = round_up(linkedit_size, page_size);
+  }
+
+  // The notes' data, at the start of __LINKEDIT, in the order they were
+  // added.
+  fn add_notes(&mut self) {
+    let mut blobs: Vec<LinkeditBlob> = self
+      .notes
+      .iter()
+      .map(|note| LinkeditBlob {
+        cmd: LC_NOTE,
+        data: note.data.clone(),
+      })
+      .collect();
+    blobs.extend(self.linkedit.drain(..).filter(|blob| blob.cmd != LC_NOTE));
+    self.linkedit = blobs;
+  }
+
+  // LC_FUNCTION_STARTS and LC_DATA_IN_CODE, from the sections' functions
+  // and data at their final addresses. The blobs' load commands move the
+  // sections, so they're added before the layout the addresses come from.
+  fn add_function_info(&mut self) {
+    self.linkedit.retain(|blob| {
+      blob.cmd != LC_FUNCTION_STARTS && blob.cmd != LC_DATA_IN_CODE
+    });
+    let (has_starts, has_dice) = self
+      .segments
+      .iter()
+      .flat_map(|seg| seg.sections.iter())
+      .fold((false, false), |(starts, dice), sect| {
+        (
+          starts || !sect.function_starts.is_empty(),
+          dice || !sect.data_in_code.is_empty(),
+        )
+      });
+    // Before the symbol table, as ld64 puts them, so the string table can
+    // still be last and strip can shrink it.
+    let mut at = self
+      .linkedit
+      .iter()
+      .position(|blob| blob.cmd == LC_SYMTAB)
+      .unwrap_or(self.linkedit.len());
+    for &(cmd, present) in [
+      (LC_FUNCTION_STARTS, has_starts),
+      (LC_DATA_IN_CODE, has_dice),
+    ].iter()
+    {
+      if present {
+        let blob = LinkeditBlob {
+          cmd: cmd,
+          data: Vec::new(),
+        };
+        self.linkedit.insert(at, blob);
+        at += 1;
+      }
+    }
+    if !has_starts && !has_dice {
+      return;
+    }
+    self.layout();
+
+    // Offsets in LC_DATA_IN_CODE are from the mach header, at the start
+    // of __TEXT.
+    let base = self.segments[0].vmaddr;
+    let mut starts: Vec<u64> = Vec::new();
+    let mut dice: Vec<DataInCodeEntry> = Vec::new();
+    for sect in self.segments.iter().flat_map(|seg| seg.sections.iter()) {
+      starts.extend(sect.function_starts.iter().map(|&off| sect.addr + off));
+      dice.extend(sect.data_in_code.iter().map(|entry| DataInCodeEntry {
+        offset: (sect.addr + entry.offset as u64 - base) as u32,
+        ..*entry
+      }));
+    }
+    for blob in self.linkedit.iter_mut() {
+      if blob.cmd == LC_FUNCTION_STARTS {
+        blob.data = function_starts::write(&starts, base);
+      } else if blob.cmd == LC_DATA_IN_CODE {
+        blob.data = data_in_code::write(&dice);
+      }
+    }
+  }
+
+  // The 1-based index of a section in load command order, as
+  // LC_SEGMENT_SPLIT_INFO numbers them. Sections named in __DATA may have
+  // been moved to __DATA_CONST.
+  fn section_index(&self, segname: &str, sectname: &str) -> Option<u64> {
+    self
+      .segments
+      .iter()
+      .flat_map(|seg| seg.sections.iter().map(move |sect| (seg, sect)))
+      .position(|(seg, sect)| {
+        sect.sectname == sectname
+          && (seg.segname == segname
+            || segname == "__DATA" && seg.segname == "__DATA_CONST")
+      })
+      .map(|i| i as u64 + 1)
+  }
+
+  // LC_SEGMENT_SPLIT_INFO, from the sections' references, if asked for.
+  fn add_split_seg_info(&mut self) {
+    self.linkedit.retain(|blob| blob.cmd != LC_SEGMENT_SPLIT_INFO);
+    if !self.split_seg_info {
+      return;
+    }
+    // Section indices are only known once __DATA_CONST and __TEXT_EXEC have
+    // been split out.
+    self.layout();
+    let data = self.split_seg_data();
+    self.linkedit.push(LinkeditBlob {
+      cmd: LC_SEGMENT_SPLIT_INFO,
+      data: data,
+    });
+  }
+
+  // The data of LC_SEGMENT_SPLIT_INFO for the sections' references as they
+  // are now, for references only known once the image is laid out.
+  pub fn split_seg_data(&self) -> Vec<u8> {
+    let mut refs: Vec<Reference> = Vec::new();
+    for seg in self.segments.iter() {
+      for sect in seg.sections.iter() {
+        let from = match self.section_index(&seg.segname, &sect.sectname) {
+          Some(from) => from,
+          None => continue,
+        };
+        for reference in sect.references.iter() {
+          let to = match reference.target {
+            Some((ref segname, ref sectname)) => {
+              match self.section_index(segname, sectname) {
+                Some(to) => to,
+                None => continue,
+              }
+            }
+            None => 0,
+          };
+          refs.push(Reference {
+            from_section: from,
+            to_section: to,
+            to_offset: reference.target_offset,
+            kind: reference.kind,
+            from_offset: reference.offset,
+          });
+        }
+      }
+    }
+    let pointer_size = self.arch.pointer_size() as usize;
+    split_seg::write(&refs, pointer_size)
+  }
+
+  // Reserve space for the code signature at the end of __LINKEDIT. Its
+  // size depends on where it starts, so this needs a layout first.
+  fn reserve_code_signature(&mut self) {
+    self.linkedit.retain(|blob| blob.cmd != LC_CODE_SIGNATURE);
+    let signer = match self.code_signature {
+      Some(ref signer) => signer.clone(),
+      None => return,
+    };
+    self.linkedit.push(LinkeditBlob {
+      cmd: LC_CODE_SIGNATURE,
+      data: Vec::new(),
+    });
+    self.layout();
+    let (offsets, _) = self.blob_offsets();
+    let offset = self.linkedit_segment.fileoff + offsets[offsets.len() - 1];
+    let size = signer.signature_size(offset);
+    if let Some(blob) = self.linkedit.last_mut() {
+      blob.data = vec![0; size as usize];
+    }
+  }
+
+  // Apply the optimization hints to the sections they're in.
+  fn relax(&self, buf: &mut [u8]) {
+    if !self.arch.is_arm64() {
+      return;
+    }
+    let sections: Vec<&OutputSection> = self
+      .segments
+      .iter()
+      .flat_map(|seg| seg.sections.iter())
+      .filter(|sect| !sect.is_zerofill())
+      .collect();
+    for hint in self.optimization_hints.iter() {
+      let first = match hint.addresses.first() {
+        Some(&first) => first,
+        None => continue,
+      };
+      let sect = sections.iter().find(|sect| {
+        first >= sect.addr && first < sect.addr + sect.contents.len() as u64
+      });
+      if let Some(sect) = sect {
+        let start = sect.offset as usize;
+        let code = &mut buf[start..(start + sect.contents.len())];
+        loh::apply(code, sect.addr, hint);
+      }
+    }
+  }
+
+  pub fn write(&mut self) -> Vec<u8> {
+    self.write_with(|_| ())
+  }
+
+  // Lay the image out and serialize it, calling `finish` once every section
+  // has its final address: see `lay_out_with`.
+  pub fn write_with<F: FnOnce(&mut Image)>(&mut self, finish: F) -> Vec<u8> {
+    self.lay_out_with(finish);
+    let mut buf = vec![0; self.file_size()];
+    self.write_into(&mut buf);
+    buf
+  }
+
+  // Lay the image out, calling `finish` once every section has its final
+  // address to fill in what depends on them: section contents (which
+  // mustn't change size) and the __LINKEDIT blobs. The blobs' load commands
+  // have to be there already, if empty, since they take up space before the
+  // sections. The image can then be written to a buffer of `file_size`.
+  pub fn lay_out_with<F: FnOnce(&mut Image)>(&mut self, finish: F) {
+    self.add_notes();
+    // Make room for LC_CODE_SIGNATURE's command before anything is laid
+    // out; the signature itself is sized last.
+    if self.code_signature.is_some()
+      && !self.linkedit.iter().any(|blob| blob.cmd == LC_CODE_SIGNATURE)
+    {
+      self.linkedit.push(LinkeditBlob {
+        cmd: LC_CODE_SIGNATURE,
+        data: Vec::new(),
+      });
+    }
+    self.add_split_seg_info();
+    self.add_function_info();
+    self.layout();
+    finish(self);
+    self.reserve_code_signature();
+    self.layout();
+  }
+
+  // Where __LINKEDIT starts in the file once the image is laid out: after
+  // the last segment, or the load commands if there are none.
+  fn linkedit_start(&self) -> usize {
+    match self.segments.last() {
+      Some(seg) => (seg.fileoff + seg.filesize) as usize,
+      None => {
+        let header = header_size(self.arch.is_64_bit());
+        (header + self.sizeofcmds()) as usize
+      }
+    }
+  }
+
+  // How big the laid out image's file is.
+  pub fn file_size(&self) -> usize {
+    let (_, linkedit_size) = self.blob_offsets();
+    self.linkedit_start() + linkedit_size as usize
+  }
+
+  // Serialize the laid out image into `buf`, which is `file_size` bytes and
+  // zeroed: the output file's mapping, say, so it's written in place.
+  pub fn write_into(&self, buf: &mut [u8]) {
+    let cmds = self.all_load_commands();
+    let mut head: Vec<u8> = Vec::new();
+    let mut header = MachHeader64::new(self.arch, self.filetype);
+    header.ncmds = cmds.len() as u32;
+    header.sizeofcmds = self.sizeofcmds() as u32;
+    header.flags = self.flags;
+    header.write(&mut head);
+    // Where a zeroed LC_UUID's uuid field is, to be filled in last.
+    let mut content_uuid: Option<usize> = None;
+    for cmd in cmds.iter() {
+      if let LoadCommand::Uuid(uuid) = *cmd {
+        if uuid == [0; 16] {
+          content_uuid = Some(head.len() + 8);
+        }
+      }
+      cmd.write(&mut head);
+    }
+    buf[..head.len()].copy_from_slice(&head);
+
+    for seg in self.segments.iter() {
+      for sect in seg.sections.iter().filter(|s| !s.is_zerofill()) {
+        let start = sect.offset as usize;
+        buf[start..(start + sect.contents.len())]
+          .copy_from_slice(&sect.contents);
+      }
+    }
+    self.relax(buf);
+
+    let (offsets, _) = self.blob_offsets();
+    let linkedit_start = self.linkedit_start();
+    for (blob, offset) in self.linkedit.iter().zip(offsets.iter()) {
+      let start = linkedit_start + *offset as usize;
+      buf[start..(start + blob.data.len())].copy_from_slice(&blob.data);
+    }
+    if let Some(offset) = content_uuid {
+      uuid::fill_content_uuid(buf, offset);
+    }
+    // The signature covers the UUID, so it goes last. It's the last blob.
+    if let Some(ref signer) = self.code_signature {
+      let offset = self.linkedit_segment.fileoff + offsets[offsets.len() - 1];
+      let text = &self.segments[0];
+      let exec_seg = ExecSegment {
+        fileoff: text.fileoff,
+        filesize: text.filesize,
+        main_binary: self.filetype == MH_EXECUTE,
+      };
+      timing::time("signing", || {
+        codesign::sign(buf, offset as usize, signer, exec_seg)
+      });
+    }
+  }
+}
@@ -0,0 +1,205 @@
+// Incremental relinking (-incremental, currently for -r outputs).
+//
+// Each input's sections are given a slot in their output section with room
+// to grow, and the slots are saved next to the output along with a digest
+// of each input. On the next link, if the options are the same and every
+// changed input still fits in its slots, the previous layout is kept, so
+// nothing outside the changed inputs' slots moves, and only the parts of the
+// output file which differ are rewritten.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use args::LinkOptions;
+use macho::object_file::ObjectFile;
+use macho::round_up;
+use uuid::md5;
+
+const STATE_HEADER: &str = "mold-incremental 1";
+
+// Output is compared and rewritten in chunks of this size.
+const PATCH_CHUNK_SIZE: usize = 0x1000;
+
+// Where one input section lives in its output section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+  pub offset: u64,
+  pub reserved: u64,
+}
+
+impl Slot {
+  pub fn fits(&self, size: u64, align: u32) -> bool {
+    size <= self.reserved && self.offset % (1 << align) == 0
+  }
+}
+
+// How much room a section of `size` bytes gets: a quarter again, so that
+// small edits don't move everything after it.
+pub fn reserve(size: u64, align: u32) -> u64 {
+  round_up(size + size / 4, 1 << align)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputRecord {
+  pub path: PathBuf,
+  pub digest: [u8; 16],
+  // One per section, in the input's section order.
+  pub slots: Vec<Slot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+  // Digest of the options, so that changing any of them relinks from
+  // scratch.
+  pub options: [u8; 16],
+  pub inputs: Vec<InputRecord>,
+}
+
+pub fn options_digest(opts: &LinkOptions) -> [u8; 16] {
+  md5(format!("{:?}", opts).as_bytes())
+}
+
+pub fn state_path(output: &Path) -> PathBuf {
+  let mut name = output.as_os_str().to_os_string();
+  name.push(".incremental");
+  PathBuf::from(name)
+}
+
+pub fn to_hex(digest: &[u8; 16]) -> String {
+  digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(s: &str) -> Option<[u8; 16]> {
+  if s.len() != 32 {
+    return None;
+  }
+  let mut digest: [u8; 16] = [0; 16];
+  for (i, byte) in digest.iter_mut().enumerate() {
+    *byte = u8::from_str_radix(s.get((i * 2)..(i * 2 + 2))?, 16).ok()?;
+  }
+  Some(digest)
+}
+
+impl State {
+  // The saved state for `output`, if there is any we can use.
+  pub fn load(output: &Path) -> Option<State> {
+    let file = File::open(state_path(output)).ok()?;
+    let mut lines = BufReader::new(file).lines();
+    if lines.next()?.ok()? != STATE_HEADER {
+      return None;
+    }
+    let options = lines.next()?.ok()?;
+    let mut state = State {
+      options: from_hex(options.trim_start_matches("options "))?,
+      inputs: Vec::new(),
+    };
+    while let Some(line) = lines.next() {
+      // input <digest> <number of slots> <path>
+      let line = line.ok()?;
+      let mut fields = line.splitn(4, ' ');
+      if fields.next()? != "input" {
+        return None;
+      }
+      let digest = from_hex(fields.next()?)?;
+      let nslots: usize = fields.next()?.parse().ok()?;
+      let mut record = InputRecord {
+        path: PathBuf::from(fields.next()?),
+        digest: digest,
+        slots: Vec::new(),
+      };
+      for _ in 0..nslots {
+        // slot <offset> <reserved>
+        let line = lines.next()?.ok()?;
+        let fields: Vec<&str> = line.split(' ').collect();
+        if fields.len() != 3 || fields[0] != "slot" {
+          return None;
+        }
+        record.slots.push(Slot {
+          offset: fields[1].parse().ok()?,
+          reserved: fields[2].parse().ok()?,
+        });
+      }
+      state.inputs.push(record);
+    }
+    Some(state)
+  }
+
+  pub fn save(&self, output: &Path) -> io::Result<()> {
+    let mut text =
+      format!("{}\noptions {}\n", STATE_HEADER, to_hex(&self.options));
+    for input in self.inputs.iter() {
+      text.push_str(&format!(
+        "input {} {} {}\n",
+        to_hex(&input.digest),
+        input.slots.len(),
+        input.path.display()
+      ));
+      for slot in input.slots.iter() {
+        text.push_str(&format!("slot {} {}\n", slot.offset, slot.reserved));
+      }
+    }
+    File::create(state_path(output))?.write_all(text.as_bytes())
+  }
+
+  // Whether the output was linked from exactly these inputs, with these
+  // options, and so needn't be linked again.
+  pub fn is_current(&self, options: &[u8; 16], digests: &[[u8; 16]]) -> bool {
+    self.options == *options
+      && self.inputs.len() == digests.len()
+      && self
+        .inputs
+        .iter()
+        .zip(digests.iter())
+        .all(|(record, digest)| record.digest == *digest)
+  }
+
+  // The slots to reuse for these inputs, if the previous layout still
+  // works: same options, same inputs in the same order, and every section
+  // still fitting where it was.
+  pub fn reusable_slots(
+    &self,
+    options: &[u8; 16],
+    inputs: &[(&Path, &ObjectFile)],
+  ) -> Option<Vec<Vec<Slot>>> {
+    if self.options != *options || self.inputs.len() != inputs.len() {
+      return None;
+    }
+    let mut slots: Vec<Vec<Slot>> = Vec::new();
+    for (record, &(path, object)) in self.inputs.iter().zip(inputs.iter()) {
+      if record.path != path || record.slots.len() != object.sections.len() {
+        return None;
+      }
+      let fits = record
+        .slots
+        .iter()
+        .zip(object.sections.iter())
+        .all(|(slot, sect)| slot.fits(sect.size, sect.align));
+      if !fits {
+        return None;
+      }
+      slots.push(record.slots.clone());
+    }
+    Some(slots)
+  }
+}
+
+// Bring the file at `path` up to date with `output`, writing only the
+// chunks which changed. Returns how many bytes were written.
+pub fn patch_output(path: &Path, output: &[u8]) -> io::Result<usize> {
+  let mut old: Vec<u8> = Vec::new();
+  File::open(path)?.read_to_end(&mut old)?;
+  let mut file = OpenOptions::new().write(true).open(path)?;
+  let mut written: usize = 0;
+  for (i, chunk) in output.chunks(PATCH_CHUNK_SIZE).enumerate() {
+    let start = i * PATCH_CHUNK_SIZE;
+    if old.get(start..(start + chunk.len())) == Some(chunk) {
+      continue;
+    }
+    file.seek(SeekFrom::Start(start as u64))?;
+    file.write_all(chunk)?;
+    written += chunk.len();
+  }
+  file.set_len(output.len() as u64)?;
+  Ok(written)
+}
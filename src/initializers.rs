@@ -0,0 +1,146 @@
+// Static initializers and terminators (__mod_init_func, __mod_term_func).
+//
+// Constructors run in command-line order, so they are collected from each
+// input file in turn. With -init, the named symbol runs before all of them.
+//
+// Newer deployment targets (the same ones which use chained fixups) store
+// initializers as 32-bit offsets from the mach header in __init_offsets, so
+// they don't each need a rebase.
+
+use macho::{put_u32, put_u64, S_INIT_FUNC_OFFSETS, S_MOD_INIT_FUNC_POINTERS,
+            S_MOD_TERM_FUNC_POINTERS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitFormat {
+  Pointers,
+  Offsets,
+}
+
+impl InitFormat {
+  pub fn for_output(use_chained_fixups: bool) -> Self {
+    if use_chained_fixups {
+      InitFormat::Offsets
+    } else {
+      InitFormat::Pointers
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum InitError {
+  // An initializer was too far from the mach header to be stored in
+  // __init_offsets.
+  OffsetOverflow(u64),
+}
+
+// The resolved addresses of the functions in one input's __mod_init_func and
+// __mod_term_func sections.
+#[derive(Debug, Clone, Default)]
+pub struct InputInitializers {
+  pub inits: Vec<u64>,
+  pub terms: Vec<u64>,
+}
+
+// A synthesized output section.
+#[derive(Debug, Clone)]
+pub struct InitSection {
+  pub sectname: &'static str,
+  pub flags: u32,
+  pub contents: Vec<u8>,
+  // Offsets within `contents` of pointers which need rebasing.
+  pub rebases: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Initializers {
+  pub inits: Vec<u64>,
+  pub terms: Vec<u64>,
+}
+
+impl Initializers {
+  // `inputs` must be in command-line order. `init_symbol` is the address of
+  // the -init symbol, if one was given.
+  pub fn collect(
+    inputs: &[InputInitializers],
+    init_symbol: Option<u64>,
+  ) -> Self {
+    let mut inits: Vec<u64> = Vec::new();
+    let mut terms: Vec<u64> = Vec::new();
+    if let Some(addr) = init_symbol {
+      inits.push(addr);
+    }
+    for input in inputs.iter() {
+      inits.extend(input.inits.iter());
+      terms.extend(input.terms.iter());
+    }
+    Initializers {
+      inits: inits,
+      terms: terms,
+    }
+  }
+
+  pub fn init_section(
+    &self,
+    format: InitFormat,
+    image_base: u64,
+  ) -> Result<Option<InitSection>, InitError> {
+    if self.inits.is_empty() {
+      return Ok(None);
+    }
+    match format {
+      InitFormat::Pointers => Ok(Some(pointer_section(
+        "__mod_init_func",
+        S_MOD_INIT_FUNC_POINTERS,
+        &self.inits,
+      ))),
+      InitFormat::Offsets => {
+        let mut contents: Vec<u8> = Vec::new();
+        for addr in self.inits.iter() {
+          let offset = addr.wrapping_sub(image_base);
+          if *addr < image_base || offset > u32::max_value() as u64 {
+            return Err(InitError::OffsetOverflow(*addr));
+          }
+          put_u32(&mut contents, offset as u32);
+        }
+        Ok(Some(InitSection {
+          sectname: "__init_offsets",
+          flags: S_INIT_FUNC_OFFSETS,
+          contents: contents,
+          rebases: Vec::new(),
+        }))
+      }
+    }
+  }
+
+  // Terminators are always stored as pointers; there is no offset format.
+  pub fn term_section(&self) -> Option<InitSection> {
+    if self.terms.is_empty() {
+      None
+    } else {
+      Some(pointer_section(
+        "__mod_term_func",
+        S_MOD_TERM_FUNC_POINTERS,
+        &self.terms,
+      ))
+    }
+  }
+}
+
+fn pointer_section(
+  sectname: &'static str,
+  flags: u32,
+  addrs: &[u64],
+) -> InitSection {
+  let mut contents: Vec<u8> = Vec::new();
+  let mut rebases: Vec<u64> = Vec::new();
+  for addr in addrs.iter() {
+    rebases.push(contents.len() as u64);
+    put_u64(&mut contents, *addr);
+  }
+  InitSection {
+    sectname: sectname,
+    flags: flags,
+    contents: contents,
+    rebases: rebases,
+  }
+}
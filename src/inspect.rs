@@ -0,0 +1,492 @@
+// `mold inspect [--json] <file>`: what's in a Mach-O file, as otool -hlL
+// would show it: the header, every load command, the segments and their
+// sections, the dylibs it refers to, and the platform it was built for. Fat
+// files are shown a slice at a time.
+
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use diagnostics::json_string;
+use macho::dylib_file::lc_str;
+use macho::fat;
+use macho::image_file::ImageFile;
+use macho::load_command::{load_commands, parse_platform, LoadCommandHeader,
+                          LC_BUILD_VERSION, LC_CODE_SIGNATURE, LC_DATA_IN_CODE,
+                          LC_DYLD_CHAINED_FIXUPS, LC_DYLD_ENVIRONMENT,
+                          LC_DYLD_EXPORTS_TRIE, LC_DYLD_INFO, LC_DYLD_INFO_ONLY,
+                          LC_DYSYMTAB, LC_ENCRYPTION_INFO,
+                          LC_ENCRYPTION_INFO_64, LC_FUNCTION_STARTS,
+                          LC_ID_DYLIB, LC_LINKER_OPTIMIZATION_HINT,
+                          LC_LINKER_OPTION, LC_LOAD_DYLIB, LC_LOAD_UPWARD_DYLIB,
+                          LC_LOAD_WEAK_DYLIB, LC_MAIN, LC_NOTE,
+                          LC_REEXPORT_DYLIB, LC_RPATH, LC_SEGMENT,
+                          LC_SEGMENT_64, LC_SEGMENT_SPLIT_INFO,
+                          LC_SOURCE_VERSION, LC_SUB_CLIENT, LC_SUB_FRAMEWORK,
+                          LC_SUB_LIBRARY, LC_SUB_UMBRELLA, LC_SYMTAB,
+                          LC_UNIXTHREAD, LC_UUID, LC_VERSION_MIN_IPHONEOS,
+                          LC_VERSION_MIN_MACOSX, LC_VERSION_MIN_TVOS,
+                          LC_VERSION_MIN_WATCHOS};
+use macho::{get_u32, Arch, MachOError, Platform, Version, MH_BUNDLE,
+            MH_DSYM, MH_DYLIB, MH_EXECUTE, MH_KEXT_BUNDLE, MH_OBJECT,
+            MH_PRELOAD};
+
+#[derive(Debug)]
+pub enum InspectError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  Usage(String),
+}
+
+pub type Result<T> = ::std::result::Result<T, InspectError>;
+
+const COMMAND_NAMES: &[(u32, &str)] = &[
+  (LC_SEGMENT, "LC_SEGMENT"),
+  (LC_SYMTAB, "LC_SYMTAB"),
+  (LC_UNIXTHREAD, "LC_UNIXTHREAD"),
+  (LC_DYSYMTAB, "LC_DYSYMTAB"),
+  (LC_LOAD_DYLIB, "LC_LOAD_DYLIB"),
+  (LC_ID_DYLIB, "LC_ID_DYLIB"),
+  (LC_SUB_FRAMEWORK, "LC_SUB_FRAMEWORK"),
+  (LC_SUB_UMBRELLA, "LC_SUB_UMBRELLA"),
+  (LC_SUB_CLIENT, "LC_SUB_CLIENT"),
+  (LC_SUB_LIBRARY, "LC_SUB_LIBRARY"),
+  (LC_LOAD_WEAK_DYLIB, "LC_LOAD_WEAK_DYLIB"),
+  (LC_SEGMENT_64, "LC_SEGMENT_64"),
+  (LC_UUID, "LC_UUID"),
+  (LC_RPATH, "LC_RPATH"),
+  (LC_CODE_SIGNATURE, "LC_CODE_SIGNATURE"),
+  (LC_SEGMENT_SPLIT_INFO, "LC_SEGMENT_SPLIT_INFO"),
+  (LC_REEXPORT_DYLIB, "LC_REEXPORT_DYLIB"),
+  (LC_ENCRYPTION_INFO, "LC_ENCRYPTION_INFO"),
+  (LC_DYLD_INFO, "LC_DYLD_INFO"),
+  (LC_DYLD_INFO_ONLY, "LC_DYLD_INFO_ONLY"),
+  (LC_LOAD_UPWARD_DYLIB, "LC_LOAD_UPWARD_DYLIB"),
+  (LC_VERSION_MIN_MACOSX, "LC_VERSION_MIN_MACOSX"),
+  (LC_VERSION_MIN_IPHONEOS, "LC_VERSION_MIN_IPHONEOS"),
+  (LC_FUNCTION_STARTS, "LC_FUNCTION_STARTS"),
+  (LC_DYLD_ENVIRONMENT, "LC_DYLD_ENVIRONMENT"),
+  (LC_MAIN, "LC_MAIN"),
+  (LC_DATA_IN_CODE, "LC_DATA_IN_CODE"),
+  (LC_SOURCE_VERSION, "LC_SOURCE_VERSION"),
+  (LC_ENCRYPTION_INFO_64, "LC_ENCRYPTION_INFO_64"),
+  (LC_LINKER_OPTION, "LC_LINKER_OPTION"),
+  (LC_LINKER_OPTIMIZATION_HINT, "LC_LINKER_OPTIMIZATION_HINT"),
+  (LC_VERSION_MIN_TVOS, "LC_VERSION_MIN_TVOS"),
+  (LC_VERSION_MIN_WATCHOS, "LC_VERSION_MIN_WATCHOS"),
+  (LC_NOTE, "LC_NOTE"),
+  (LC_BUILD_VERSION, "LC_BUILD_VERSION"),
+  (LC_DYLD_EXPORTS_TRIE, "LC_DYLD_EXPORTS_TRIE"),
+  (LC_DYLD_CHAINED_FIXUPS, "LC_DYLD_CHAINED_FIXUPS"),
+];
+
+const FILETYPE_NAMES: &[(u32, &str)] = &[
+  (MH_OBJECT, "MH_OBJECT"),
+  (MH_EXECUTE, "MH_EXECUTE"),
+  (MH_PRELOAD, "MH_PRELOAD"),
+  (MH_DYLIB, "MH_DYLIB"),
+  (MH_BUNDLE, "MH_BUNDLE"),
+  (MH_DSYM, "MH_DSYM"),
+  (MH_KEXT_BUNDLE, "MH_KEXT_BUNDLE"),
+];
+
+fn lookup(names: &[(u32, &'static str)], value: u32) -> Option<&'static str> {
+  names
+    .iter()
+    .find(|&&(v, _)| v == value)
+    .map(|&(_, name)| name)
+}
+
+pub fn command_name(cmd: u32) -> Option<&'static str> {
+  lookup(COMMAND_NAMES, cmd)
+}
+
+pub fn filetype_name(filetype: u32) -> Option<&'static str> {
+  lookup(FILETYPE_NAMES, filetype)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DylibReference {
+  // LC_ID_DYLIB, LC_LOAD_DYLIB and so on.
+  pub cmd: u32,
+  pub name: String,
+  pub current_version: Version,
+  pub compatibility_version: Version,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildVersion {
+  // LC_BUILD_VERSION or one of the LC_VERSION_MIN_* commands.
+  pub cmd: u32,
+  pub platform: Option<Platform>,
+  pub minos: Version,
+  pub sdk: Version,
+}
+
+// Everything shown for one thin file.
+#[derive(Debug, Clone)]
+pub struct Inspection {
+  pub image: ImageFile,
+  pub flags: u32,
+  pub sizeofcmds: u32,
+  pub load_commands: Vec<LoadCommandHeader>,
+  pub dylibs: Vec<DylibReference>,
+  pub rpaths: Vec<String>,
+  pub build_versions: Vec<BuildVersion>,
+}
+
+impl Inspection {
+  pub fn parse(data: &[u8]) -> ::macho::Result<Inspection> {
+    let mut inspection = Inspection {
+      image: ImageFile::parse(data)?,
+      flags: get_u32(data, 24, "mach header")?,
+      sizeofcmds: get_u32(data, 20, "mach header")?,
+      load_commands: load_commands(data)?,
+      dylibs: Vec::new(),
+      rpaths: Vec::new(),
+      build_versions: Vec::new(),
+    };
+    for header in inspection.load_commands.iter() {
+      let offset = header.offset;
+      let cmdsize = header.cmdsize as usize;
+      match header.cmd {
+        LC_ID_DYLIB | LC_LOAD_DYLIB | LC_LOAD_WEAK_DYLIB
+        | LC_REEXPORT_DYLIB | LC_LOAD_UPWARD_DYLIB => {
+          let name_offset = get_u32(data, offset + 8, "dylib_command")?;
+          inspection.dylibs.push(DylibReference {
+            cmd: header.cmd,
+            name: lc_str(data, offset, cmdsize, name_offset as usize)?,
+            current_version: Version(get_u32(
+              data,
+              offset + 16,
+              "dylib_command",
+            )?),
+            compatibility_version: Version(get_u32(
+              data,
+              offset + 20,
+              "dylib_command",
+            )?),
+          });
+        }
+        LC_RPATH => {
+          let path_offset = get_u32(data, offset + 8, "rpath_command")?;
+          inspection
+            .rpaths
+            .push(lc_str(data, offset, cmdsize, path_offset as usize)?);
+        }
+        LC_BUILD_VERSION => {
+          let what = "build_version_command";
+          inspection.build_versions.push(BuildVersion {
+            cmd: header.cmd,
+            platform: Platform::from_number(get_u32(data, offset + 8, what)?),
+            minos: Version(get_u32(data, offset + 12, what)?),
+            sdk: Version(get_u32(data, offset + 16, what)?),
+          });
+        }
+        LC_VERSION_MIN_MACOSX
+        | LC_VERSION_MIN_IPHONEOS
+        | LC_VERSION_MIN_TVOS
+        | LC_VERSION_MIN_WATCHOS => {
+          let what = "version_min_command";
+          inspection.build_versions.push(BuildVersion {
+            cmd: header.cmd,
+            platform: parse_platform(data, offset)?.map(|(p, _)| p),
+            minos: Version(get_u32(data, offset + 8, what)?),
+            sdk: Version(get_u32(data, offset + 12, what)?),
+          });
+        }
+        _ => (),
+      }
+    }
+    Ok(inspection)
+  }
+
+  pub fn arch(&self) -> Option<Arch> {
+    Arch::from_cputype(self.image.cputype, self.image.cpusubtype)
+  }
+
+  pub fn to_text(&self) -> String {
+    let image = &self.image;
+    let mut out = String::new();
+    let _ = writeln!(out, "Mach header");
+    let _ = writeln!(
+      out,
+      "  arch {}  cputype {:#x}  cpusubtype {:#x}  filetype {}",
+      self.arch().map_or("unknown", |arch| arch.name()),
+      image.cputype,
+      image.cpusubtype,
+      filetype_name(image.filetype)
+        .map_or(format!("{:#x}", image.filetype), |n| n.to_string())
+    );
+    let _ = writeln!(
+      out,
+      "  ncmds {}  sizeofcmds {}  flags {:#x}",
+      self.load_commands.len(),
+      self.sizeofcmds,
+      self.flags
+    );
+
+    let _ = writeln!(out, "Load commands");
+    for (i, header) in self.load_commands.iter().enumerate() {
+      let _ = writeln!(
+        out,
+        "  {:<3} {:<28} cmdsize {:<6} offset {}",
+        i,
+        command_name(header.cmd)
+          .map_or(format!("{:#x}", header.cmd), |n| n.to_string()),
+        header.cmdsize,
+        header.offset
+      );
+    }
+
+    let _ = writeln!(out, "Segments");
+    for seg in image.segments.iter() {
+      let _ = writeln!(
+        out,
+        "  {:<16} vmaddr {:#018x}  vmsize {:#x}  fileoff {}  filesize {}  \
+         prot {}/{}",
+        seg.segname,
+        seg.vmaddr,
+        seg.vmsize,
+        seg.fileoff,
+        seg.filesize,
+        protection(seg.initprot),
+        protection(seg.maxprot)
+      );
+      for sect in seg.sections.iter() {
+        let _ = writeln!(
+          out,
+          "    {},{:<16} addr {:#018x}  size {:#x}  offset {}  align 2^{}",
+          sect.segname, sect.sectname, sect.addr, sect.size, sect.offset,
+          sect.align
+        );
+      }
+    }
+
+    if !self.dylibs.is_empty() {
+      let _ = writeln!(out, "Dylibs");
+      for dylib in self.dylibs.iter() {
+        let _ = writeln!(
+          out,
+          "  {} {} (compatibility version {}, current version {})",
+          command_name(dylib.cmd).unwrap_or(""),
+          dylib.name,
+          dylib.compatibility_version,
+          dylib.current_version
+        );
+      }
+    }
+    if !self.rpaths.is_empty() {
+      let _ = writeln!(out, "Run paths");
+      for rpath in self.rpaths.iter() {
+        let _ = writeln!(out, "  {}", rpath);
+      }
+    }
+    for version in self.build_versions.iter() {
+      let _ = writeln!(
+        out,
+        "Build version\n  platform {}  minos {}  sdk {}",
+        version.platform.map_or("unknown", |p| p.name()),
+        version.minos,
+        version.sdk
+      );
+    }
+    if let Some(uuid) = image.uuid {
+      let _ = writeln!(out, "UUID\n  {}", uuid_string(&uuid));
+    }
+    out
+  }
+
+  pub fn to_json(&self) -> String {
+    let image = &self.image;
+    let optional =
+      |value: Option<&str>| value.map_or("null".to_string(), json_string);
+    let commands: Vec<String> = self
+      .load_commands
+      .iter()
+      .map(|header| {
+        format!(
+          "{{\"cmd\":{},\"name\":{},\"cmdsize\":{},\"offset\":{}}}",
+          header.cmd,
+          optional(command_name(header.cmd)),
+          header.cmdsize,
+          header.offset
+        )
+      })
+      .collect();
+    let segments: Vec<String> = image
+      .segments
+      .iter()
+      .map(|seg| {
+        let sections: Vec<String> = seg
+          .sections
+          .iter()
+          .map(|sect| {
+            format!(
+              "{{\"sectname\":{},\"addr\":{},\"size\":{},\"offset\":{},\
+               \"align\":{},\"flags\":{}}}",
+              json_string(&sect.sectname),
+              sect.addr,
+              sect.size,
+              sect.offset,
+              sect.align,
+              sect.flags
+            )
+          })
+          .collect();
+        format!(
+          "{{\"segname\":{},\"vmaddr\":{},\"vmsize\":{},\"fileoff\":{},\
+           \"filesize\":{},\"maxprot\":{},\"initprot\":{},\"flags\":{},\
+           \"sections\":[{}]}}",
+          json_string(&seg.segname),
+          seg.vmaddr,
+          seg.vmsize,
+          seg.fileoff,
+          seg.filesize,
+          seg.maxprot,
+          seg.initprot,
+          seg.flags,
+          sections.join(",")
+        )
+      })
+      .collect();
+    let dylibs: Vec<String> = self
+      .dylibs
+      .iter()
+      .map(|dylib| {
+        format!(
+          "{{\"cmd\":{},\"name\":{},\"current_version\":{},\
+           \"compatibility_version\":{}}}",
+          optional(command_name(dylib.cmd)),
+          json_string(&dylib.name),
+          json_string(&dylib.current_version.to_string()),
+          json_string(&dylib.compatibility_version.to_string())
+        )
+      })
+      .collect();
+    let rpaths: Vec<String> =
+      self.rpaths.iter().map(|rpath| json_string(rpath)).collect();
+    let build_versions: Vec<String> = self
+      .build_versions
+      .iter()
+      .map(|version| {
+        format!(
+          "{{\"cmd\":{},\"platform\":{},\"minos\":{},\"sdk\":{}}}",
+          optional(command_name(version.cmd)),
+          optional(version.platform.map(|p| p.name())),
+          json_string(&version.minos.to_string()),
+          json_string(&version.sdk.to_string())
+        )
+      })
+      .collect();
+    format!(
+      "{{\"arch\":{},\"cputype\":{},\"cpusubtype\":{},\"filetype\":{},\
+       \"flags\":{},\"sizeofcmds\":{},\"load_commands\":[{}],\
+       \"segments\":[{}],\"dylibs\":[{}],\"rpaths\":[{}],\
+       \"build_versions\":[{}],\"uuid\":{}}}",
+      optional(self.arch().map(|arch| arch.name())),
+      image.cputype,
+      image.cpusubtype,
+      optional(filetype_name(image.filetype)),
+      self.flags,
+      self.sizeofcmds,
+      commands.join(","),
+      segments.join(","),
+      dylibs.join(","),
+      rpaths.join(","),
+      build_versions.join(","),
+      image
+        .uuid
+        .map_or("null".to_string(), |uuid| json_string(&uuid_string(&uuid)))
+    )
+  }
+}
+
+fn protection(prot: u32) -> String {
+  let flag = |bit: u32, c: char| if prot & bit != 0 { c } else { '-' };
+  [flag(1, 'r'), flag(2, 'w'), flag(4, 'x')].iter().collect()
+}
+
+fn uuid_string(uuid: &[u8; 16]) -> String {
+  let hex: Vec<String> = uuid.iter().map(|b| format!("{:02X}", b)).collect();
+  format!(
+    "{}-{}-{}-{}-{}",
+    hex[0..4].concat(),
+    hex[4..6].concat(),
+    hex[6..8].concat(),
+    hex[8..10].concat(),
+    hex[10..16].concat()
+  )
+}
+
+// `data` (thin or fat) shown as text or JSON. A fat file's JSON is
+// {"fat": [...]}, with each slice's offset and size alongside its fields.
+pub fn inspect(data: &[u8], json: bool) -> ::macho::Result<String> {
+  if !fat::is_fat(data) {
+    let inspection = Inspection::parse(data)?;
+    return Ok(if json {
+      inspection.to_json()
+    } else {
+      inspection.to_text()
+    });
+  }
+  let mut slices: Vec<String> = Vec::new();
+  for slice in fat::parse(data)?.iter() {
+    let start = slice.offset as usize;
+    let inspection =
+      Inspection::parse(&data[start..(start + slice.size as usize)])?;
+    slices.push(if json {
+      format!(
+        "{{\"offset\":{},\"size\":{},\"image\":{}}}",
+        slice.offset,
+        slice.size,
+        inspection.to_json()
+      )
+    } else {
+      format!(
+        "Slice {} (offset {}, size {})\n{}",
+        inspection.arch().map_or("unknown", |arch| arch.name()),
+        slice.offset,
+        slice.size,
+        inspection.to_text()
+      )
+    });
+  }
+  Ok(if json {
+    format!("{{\"fat\":[{}]}}", slices.join(","))
+  } else {
+    slices.join("\n")
+  })
+}
+
+// `mold inspect [--json] <file>`, printing to stdout.
+pub fn run_command(args: &[String]) -> Result<()> {
+  let mut json = false;
+  let mut input: Option<PathBuf> = None;
+  for arg in args.iter() {
+    match arg.as_str() {
+      "--json" => json = true,
+      _ if arg.starts_with('-') => {
+        return Err(InspectError::Usage(format!("unknown option {}", arg)));
+      }
+      _ if input.is_some() => {
+        let message = "only one input is allowed".to_string();
+        return Err(InspectError::Usage(message));
+      }
+      _ => input = Some(PathBuf::from(arg)),
+    }
+  }
+  let input = input
+    .ok_or_else(|| InspectError::Usage("no input file".to_string()))?;
+  let data =
+    fs::read(&input).map_err(|e| InspectError::IoError(input.clone(), e))?;
+  let shown = inspect(&data, json)
+    .map_err(|e| InspectError::Malformed(input.clone(), e))?;
+  if json {
+    println!("{}", shown);
+  } else {
+    print!("{}:\n{}", input.display(), shown);
+  }
+  Ok(())
+}
@@ -0,0 +1,210 @@
+// Symbol names, each stored once. A big link has millions of symbols, most
+// of them named in several inputs, and copying each name into every table
+// that mentions it was a large share of the allocations and of peak memory.
+// Instead, names are interned: copied once into an arena, and referred to
+// by a Symbol, which is two integers.
+//
+// The interner is split into shards by the name's hash, the same way the
+// resolver splits its table, so threads interning different names mostly
+// touch different shards, and the resolver's lock on a shard covers its
+// names too. Lookups hash the name once; the hash picks the shard and is the
+// key within it.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+
+// Names are copied into chunks of at least this many bytes.
+const CHUNK_SIZE: usize = 64 * 1024;
+const NO_NEXT: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol {
+  shard: u32,
+  index: u32,
+}
+
+impl Symbol {
+  // The shard the name is in, which tables keyed by Symbol shard by too.
+  pub fn shard(self) -> usize {
+    self.shard as usize
+  }
+}
+
+// A hasher for keys which are hashes already.
+#[derive(Debug, Default)]
+struct Prehashed(u64);
+
+impl Hasher for Prehashed {
+  fn finish(&self) -> u64 {
+    self.0
+  }
+
+  fn write(&mut self, bytes: &[u8]) {
+    for &b in bytes.iter() {
+      self.0 = self.0.rotate_left(8) ^ b as u64;
+    }
+  }
+
+  fn write_u64(&mut self, n: u64) {
+    self.0 = n;
+  }
+}
+
+// A bump allocator for strings. Names are appended to the last chunk until
+// it's full, so a chunk never reallocates, and each name is an offset and
+// length rather than a pointer.
+#[derive(Debug, Default)]
+struct Arena {
+  chunks: Vec<String>,
+}
+
+impl Arena {
+  // (chunk, offset) of a copy of `s`.
+  fn alloc(&mut self, s: &str) -> (u32, u32) {
+    let fits = self
+      .chunks
+      .last()
+      .map_or(false, |chunk| chunk.capacity() - chunk.len() >= s.len());
+    if !fits {
+      self.chunks.push(String::with_capacity(CHUNK_SIZE.max(s.len())));
+    }
+    let chunk = self.chunks.len() - 1;
+    let offset = self.chunks[chunk].len();
+    self.chunks[chunk].push_str(s);
+    (chunk as u32, offset as u32)
+  }
+}
+
+// One shard's names.
+#[derive(Debug, Default)]
+pub struct Shard {
+  index: u32,
+  arena: Arena,
+  // (chunk, offset, length) of each name, by Symbol index.
+  spans: Vec<(u32, u32, u32)>,
+  // The first name with each hash, and after it the next name with the
+  // same hash, for the rare collision.
+  by_hash: HashMap<u64, u32, BuildHasherDefault<Prehashed>>,
+  next: Vec<u32>,
+}
+
+impl Shard {
+  pub fn name(&self, index: u32) -> &str {
+    let (chunk, offset, len) = self.spans[index as usize];
+    let start = offset as usize;
+    &self.arena.chunks[chunk as usize][start..(start + len as usize)]
+  }
+
+  fn symbol(&self, index: u32) -> Symbol {
+    Symbol {
+      shard: self.index,
+      index: index,
+    }
+  }
+
+  // `name`, whose hash is `hash`, if it has been interned.
+  pub fn get(&self, name: &str, hash: u64) -> Option<Symbol> {
+    let mut index = *self.by_hash.get(&hash)?;
+    while index != NO_NEXT {
+      if self.name(index) == name {
+        return Some(self.symbol(index));
+      }
+      index = self.next[index as usize];
+    }
+    None
+  }
+
+  pub fn intern(&mut self, name: &str, hash: u64) -> Symbol {
+    if let Some(sym) = self.get(name, hash) {
+      return sym;
+    }
+    let index = self.spans.len() as u32;
+    let (chunk, offset) = self.arena.alloc(name);
+    self.spans.push((chunk, offset, name.len() as u32));
+    // New names go at the head of their hash's chain.
+    let next = self.by_hash.insert(hash, index).unwrap_or(NO_NEXT);
+    self.next.push(next);
+    self.symbol(index)
+  }
+
+  pub fn len(&self) -> usize {
+    self.spans.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.spans.is_empty()
+  }
+}
+
+#[derive(Debug)]
+pub struct Interner {
+  shards: Vec<Shard>,
+}
+
+impl Default for Interner {
+  fn default() -> Self {
+    Interner::new(1)
+  }
+}
+
+impl Interner {
+  pub fn new(nshards: usize) -> Interner {
+    Interner {
+      shards: (0..nshards.max(1))
+        .map(|i| Shard {
+          index: i as u32,
+          ..Default::default()
+        })
+        .collect(),
+    }
+  }
+
+  pub fn hash(name: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish()
+  }
+
+  // The interner made of these shards, from into_shards.
+  pub fn from_shards(shards: Vec<Shard>) -> Interner {
+    Interner { shards: shards }
+  }
+
+  // Which of `nshards` shards a name with this hash belongs to.
+  pub fn shard_for(hash: u64, nshards: usize) -> usize {
+    (hash % nshards as u64) as usize
+  }
+
+  pub fn shard_of(&self, hash: u64) -> usize {
+    Interner::shard_for(hash, self.shards.len())
+  }
+
+  // The shards, in order, to put behind locks of their own.
+  pub fn into_shards(self) -> Vec<Shard> {
+    self.shards
+  }
+
+  pub fn intern(&mut self, name: &str) -> Symbol {
+    let hash = Interner::hash(name);
+    let shard = self.shard_of(hash);
+    self.shards[shard].intern(name, hash)
+  }
+
+  pub fn get(&self, name: &str) -> Option<Symbol> {
+    let hash = Interner::hash(name);
+    self.shards[self.shard_of(hash)].get(name, hash)
+  }
+
+  pub fn name(&self, sym: Symbol) -> &str {
+    self.shards[sym.shard as usize].name(sym.index)
+  }
+
+  pub fn len(&self) -> usize {
+    self.shards.iter().map(|shard| shard.len()).sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
@@ -0,0 +1,121 @@
+// Kernel extensions (-kext): MH_KEXT_BUNDLE outputs, which the kernel's kext
+// loader (or the kernel cache builder) links in rather than dyld. There is no
+// __PAGEZERO and no dyld info: code goes in its own __TEXT_EXEC segment, and
+// what has to be fixed up at load time is described by old-style external
+// and local relocations in LC_DYSYMTAB. Every kext also defines a
+// kmod_info_t, which is how the kernel finds its name, version and start
+// and stop routines.
+
+use std::path::PathBuf;
+
+use macho::reloc::{read_u32, RelocationInfo, ARM64_RELOC_UNSIGNED,
+                   X86_64_RELOC_UNSIGNED};
+use macho::Arch;
+use relocatable::RelocatableInput;
+
+pub const KMOD_INFO_SYMBOL: &str = "_kmod_info";
+
+// sizeof(kmod_info_t), which is packed to 4 bytes, and the only version.
+const KMOD_INFO_SIZE: usize = 196;
+const KMOD_INFO_VERSION: u32 = 1;
+// Offsets of info_version and name.
+const KMOD_INFO_VERSION_OFFSET: usize = 8;
+const KMOD_NAME_OFFSET: usize = 16;
+const KMOD_MAX_NAME: usize = 64;
+
+#[derive(Debug)]
+pub enum KextError {
+  MissingKmodInfo,
+  // The input defining _kmod_info, and what's wrong with it.
+  BadKmodInfo(PathBuf, &'static str),
+}
+
+pub type Result<T> = ::std::result::Result<T, KextError>;
+
+// Check some input defines a plausible _kmod_info: big enough, the version
+// the kernel expects, and a name.
+pub fn check_kmod_info(inputs: &[RelocatableInput]) -> Result<()> {
+  for input in inputs.iter() {
+    let object = &input.object;
+    let sym = object.symbols.iter().find(|sym| {
+      sym.name == KMOD_INFO_SYMBOL && sym.is_external() && !sym.is_undefined()
+    });
+    let sym = match sym {
+      Some(sym) => sym,
+      None => continue,
+    };
+    let bad = |what| Err(KextError::BadKmodInfo(input.path.clone(), what));
+    let sect = match object.section(sym.n_sect) {
+      Some(sect) if !sect.is_zerofill() => sect,
+      _ => return bad("not in a section with contents"),
+    };
+    let start = (sym.n_value - sect.addr) as usize;
+    let info = match sect.contents.get(start..(start + KMOD_INFO_SIZE)) {
+      Some(info) => info,
+      None => return bad("smaller than kmod_info_t"),
+    };
+    if read_u32(info, KMOD_INFO_VERSION_OFFSET) != KMOD_INFO_VERSION {
+      return bad("unknown info_version");
+    }
+    let name = &info[KMOD_NAME_OFFSET..(KMOD_NAME_OFFSET + KMOD_MAX_NAME)];
+    if name[0] == 0 || !name.contains(&0) {
+      return bad("name is empty or not terminated");
+    }
+    return Ok(());
+  }
+  Err(KextError::MissingKmodInfo)
+}
+
+// A pointer the kext loader has to fix up: `address` is relative to the
+// start of the kext.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KextFixup {
+  // Bind to the symbol at this index in the output's symbol table.
+  Bind { address: u64, symbol: u32 },
+  // Slide the pointer, which points into the section with this 1-based
+  // ordinal.
+  Rebase { address: u64, section: u8 },
+}
+
+fn encode(mut relocs: Vec<RelocationInfo>) -> Vec<u8> {
+  relocs.sort_by_key(|reloc| reloc.address);
+  let mut buf: Vec<u8> = Vec::new();
+  for reloc in relocs.iter() {
+    reloc.write(&mut buf);
+  }
+  buf
+}
+
+// The external and local relocation tables for `fixups`, which LC_DYSYMTAB
+// points to.
+pub fn relocations(arch: Arch, fixups: &[KextFixup]) -> (Vec<u8>, Vec<u8>) {
+  let kind = match arch {
+    Arch::X86_64 => X86_64_RELOC_UNSIGNED,
+    _ => ARM64_RELOC_UNSIGNED,
+  };
+  let mut external: Vec<RelocationInfo> = Vec::new();
+  let mut local: Vec<RelocationInfo> = Vec::new();
+  for fixup in fixups.iter() {
+    let (address, symbolnum, is_external) = match *fixup {
+      KextFixup::Bind { address, symbol } => (address, symbol, true),
+      KextFixup::Rebase { address, section } => {
+        (address, section as u32, false)
+      }
+    };
+    let reloc = RelocationInfo {
+      address: address as u32,
+      symbolnum: symbolnum,
+      pcrel: false,
+      length: if arch.is_64_bit() { 3 } else { 2 },
+      external: is_external,
+      kind: kind,
+      scattered: None,
+    };
+    if is_external {
+      external.push(reloc);
+    } else {
+      local.push(reloc);
+    }
+  }
+  (encode(external), encode(local))
+}
@@ -0,0 +1,57 @@
+// The environment variables ld64 reads, for build systems which set those
+// rather than options:
+//
+// - ZERO_AR_DATE: as -reproducible.
+// - RC_TRACE_DYLIBS and RC_TRACE_ARCHIVES (or LD_TRACE_DYLIBS and
+//   LD_TRACE_ARCHIVES): log each dylib and archive the link used, in
+//   ld64's format, for the tools which collect them.
+// - LD_TRACE_FILE: append that log to this file rather than stderr.
+// - LD_PRINT_OPTIONS: print the options before linking.
+
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use args::LinkOptions;
+use diagnostics;
+
+fn is_set(name: &str) -> bool {
+  env::var_os(name).is_some()
+}
+
+// The settings the environment asks for.
+pub fn apply(opts: &mut LinkOptions) {
+  opts.reproducible = is_set("ZERO_AR_DATE");
+  opts.trace_dylibs = is_set("RC_TRACE_DYLIBS") || is_set("LD_TRACE_DYLIBS");
+  opts.trace_archives =
+    is_set("RC_TRACE_ARCHIVES") || is_set("LD_TRACE_ARCHIVES");
+  opts.trace_file = env::var_os("LD_TRACE_FILE").map(PathBuf::from);
+}
+
+// LD_PRINT_OPTIONS
+pub fn print_options(args: &[String]) {
+  if !is_set("LD_PRINT_OPTIONS") {
+    return;
+  }
+  for arg in args.iter() {
+    eprintln!("[Logging ld64 options]\t{}", arg);
+  }
+}
+
+// One line of the log, e.g. "[Logging for XBS] Used dynamic library: <path>".
+// The file is opened for each line, as several links may share it.
+pub fn trace(opts: &LinkOptions, what: &str, path: &Path) {
+  let line = format!("[Logging for XBS] Used {}: {}\n", what, path.display());
+  let written = match opts.trace_file {
+    Some(ref file) => OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(file)
+      .and_then(|mut f| f.write_all(line.as_bytes())),
+    None => io::stderr().write_all(line.as_bytes()),
+  };
+  if let Err(e) = written {
+    diagnostics::warning(&format!("unable to write LD_TRACE_FILE ({})", e));
+  }
+}
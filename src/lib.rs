@@ -0,0 +1,81 @@
+// The linker as a library. LinkJob drives a whole link in-process; the
+// modules underneath are what the command line is built from.
+
+extern crate bfd_sys;
+// The Mach-O data model, which is its own crate so other tools can use it
+// without the linker.
+pub extern crate macho_types as macho;
+#[cfg(feature = "llvm")]
+extern crate llvm_sys;
+#[cfg(feature = "python")]
+extern crate pyo3;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+
+pub mod archive;
+pub mod build_version;
+pub mod cache;
+pub mod cc_flavor;
+pub mod capi;
+pub mod args;
+pub mod bfd;
+pub mod codesign;
+pub mod cold_split;
+pub mod daemon;
+pub mod dead_strip;
+pub mod demangle;
+pub mod dependency_info;
+pub mod diagnostics;
+pub mod dyld_check;
+pub mod dsym;
+pub mod dtrace;
+pub mod dwarf;
+pub mod dylib;
+pub mod edit;
+pub mod elf;
+pub mod final_link;
+pub mod gnu_args;
+pub mod image;
+pub mod incremental;
+pub mod initializers;
+pub mod inspect;
+pub mod intern;
+pub mod kext;
+pub mod ld_env;
+pub mod link_job;
+pub mod lipo;
+pub mod literals;
+#[cfg(feature = "llvm")]
+pub mod llvm_lto;
+pub mod lto;
+pub mod null_link;
+pub mod objc;
+pub mod order_file;
+pub mod output;
+pub mod overflow;
+pub mod parallel;
+pub mod profile_order;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod relocatable;
+pub mod resolve;
+pub mod response_file;
+pub mod search;
+pub mod size;
+pub mod statistics;
+pub mod strip;
+pub mod symbol_list;
+pub mod symbols;
+pub mod target;
+pub mod timing;
+pub mod tlv;
+pub mod undefined;
+pub mod universal;
+pub mod unwind;
+pub mod uuid;
+pub mod verbose;
+
+pub use args::OutputKind;
+pub use diagnostics::{Diagnostic, Severity};
+pub use link_job::{LinkError, LinkFailure, LinkJob};
+pub use macho::{Arch, Platform, Version};
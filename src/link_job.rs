@@ -0,0 +1,533 @@
+// Linking in-process: LinkJob builds up the same options as the command
+// line, for build tools and test harnesses which would otherwise have to run
+// the linker and scrape its stderr. Failures come back as values, with the
+// warnings reported along the way, rather than exiting.
+
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use args::{ArgsError, LinkOptions, OutputKind};
+use bfd::BFDError;
+use dependency_info::{self, DependencyInfo};
+use diagnostics::{self, Diagnostic, Severity};
+use dyld_check::{self, DyldCheckError};
+use dsym::{self, DsymError};
+use final_link::{self, FinalLinkError};
+use kext::KextError;
+use macho::{Arch, Platform, Version};
+use null_link::Stamp;
+use parallel;
+use relocatable::{self, RelocatableError};
+use resolve::ResolveError;
+use statistics;
+use target::TargetError;
+use timing;
+use universal::{self, UniversalError};
+use verbose;
+
+#[derive(Debug)]
+pub enum LinkError {
+  Args(ArgsError),
+  Relocatable(RelocatableError),
+  Universal(UniversalError<RelocatableError>),
+  FinalLink(FinalLinkError),
+  UniversalFinalLink(UniversalError<FinalLinkError>),
+  Bfd(BFDError),
+  Dsym(DsymError),
+  // --verify-output found an output dyld wouldn't load.
+  DyldCheck(DyldCheckError),
+  // The -dependency_info file which couldn't be written.
+  DependencyInfo(PathBuf, io::Error),
+  // Writing an in-memory output to the caller's writer.
+  Output(io::Error),
+}
+
+impl From<ArgsError> for LinkError {
+  fn from(err: ArgsError) -> Self {
+    LinkError::Args(err)
+  }
+}
+
+impl From<RelocatableError> for LinkError {
+  fn from(err: RelocatableError) -> Self {
+    LinkError::Relocatable(err)
+  }
+}
+
+impl From<UniversalError<RelocatableError>> for LinkError {
+  fn from(err: UniversalError<RelocatableError>) -> Self {
+    LinkError::Universal(err)
+  }
+}
+
+impl From<FinalLinkError> for LinkError {
+  fn from(err: FinalLinkError) -> Self {
+    LinkError::FinalLink(err)
+  }
+}
+
+impl From<UniversalError<FinalLinkError>> for LinkError {
+  fn from(err: UniversalError<FinalLinkError>) -> Self {
+    LinkError::UniversalFinalLink(err)
+  }
+}
+
+impl From<BFDError> for LinkError {
+  fn from(err: BFDError) -> Self {
+    LinkError::Bfd(err)
+  }
+}
+
+impl From<DsymError> for LinkError {
+  fn from(err: DsymError) -> Self {
+    LinkError::Dsym(err)
+  }
+}
+
+impl From<DyldCheckError> for LinkError {
+  fn from(err: DyldCheckError) -> Self {
+    LinkError::DyldCheck(err)
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, LinkError>;
+
+// (code, file, symbol) for an error resolving symbols.
+fn resolve_details(
+  err: &ResolveError,
+) -> (&'static str, Option<&PathBuf>, Option<&String>) {
+  match *err {
+    ResolveError::DuplicateSymbol(ref name, ..) => {
+      ("duplicate-symbol", None, Some(name))
+    }
+    ResolveError::DuplicateSymbols(_) => ("duplicate-symbols", None, None),
+    ResolveError::ExportedSymbolNotDefined(ref name) => {
+      ("exported-symbol-not-defined", None, Some(name))
+    }
+    ResolveError::CannotExportHidden(ref name) => {
+      ("cannot-export-hidden", None, Some(name))
+    }
+    ResolveError::AliasTargetNotDefined(_, ref target) => {
+      ("alias-target-not-defined", None, Some(target))
+    }
+  }
+}
+
+// (code, file, symbol) for an error from a relocatable link.
+fn relocatable_details(
+  err: &RelocatableError,
+) -> (&'static str, Option<&PathBuf>, Option<&String>) {
+  match *err {
+    RelocatableError::IoError(ref path, _) => ("io-error", Some(path), None),
+    RelocatableError::Malformed(ref path, _) => {
+      ("malformed-input", Some(path), None)
+    }
+    RelocatableError::Target(TargetError::PlatformMismatch(ref path, ..)) => {
+      ("platform-mismatch", Some(path), None)
+    }
+    RelocatableError::Target(TargetError::NotZippered(ref path)) => {
+      ("not-zippered", Some(path), None)
+    }
+    RelocatableError::Lto(_) => ("lto-failed", None, None),
+    RelocatableError::BadArchive(ref path, _) => {
+      ("malformed-archive", Some(path), None)
+    }
+    RelocatableError::Elf(ref path, _) => ("elf-input", Some(path), None),
+    RelocatableError::WrongArch(ref path, _) => {
+      ("wrong-architecture", Some(path), None)
+    }
+    RelocatableError::Resolve(ref err) => resolve_details(err),
+    RelocatableError::BadRelocationTarget(ref path, _) => {
+      ("bad-relocation-target", Some(path), None)
+    }
+    RelocatableError::UnsupportedRelocation(ref path, _) => {
+      ("unsupported-relocation", Some(path), None)
+    }
+    RelocatableError::Reloc(ref path, _) => {
+      ("relocation-out-of-range", Some(path), None)
+    }
+    RelocatableError::Overflow(ref overflow) => {
+      ("relocation-out-of-range", Some(&overflow.path), overflow.from.as_ref())
+    }
+    RelocatableError::TooManySections => ("too-many-sections", None, None),
+    RelocatableError::FormatLimit(..) => ("format-limit", None, None),
+    RelocatableError::Objc(_) => ("objc-mismatch", None, None),
+    RelocatableError::Profile(ref path, _) => ("bad-profile", Some(path), None),
+    RelocatableError::Kext(KextError::MissingKmodInfo) => {
+      ("missing-kmod-info", None, None)
+    }
+    RelocatableError::Kext(KextError::BadKmodInfo(ref path, _)) => {
+      ("bad-kmod-info", Some(path), None)
+    }
+    RelocatableError::UnresolvedSymbols {
+      ref undefined,
+      ref duplicates,
+    } => {
+      let code = match (undefined.is_empty(), duplicates.is_empty()) {
+        (false, true) => "undefined-symbols",
+        (true, false) => "duplicate-symbols",
+        _ => "unresolved-symbols",
+      };
+      (code, None, None)
+    }
+  }
+}
+
+// (code, file, symbol) for an error from a final link.
+fn final_link_details(
+  err: &FinalLinkError,
+) -> (&'static str, Option<&PathBuf>, Option<&String>) {
+  match *err {
+    FinalLinkError::Relocatable(ref err) => relocatable_details(err),
+    FinalLinkError::Resolve(ref err) => resolve_details(err),
+    FinalLinkError::IoError(ref path, _) => ("io-error", Some(path), None),
+    FinalLinkError::Unsupported(_) => ("unsupported", None, None),
+    FinalLinkError::NoEntryPoint(ref name) => {
+      ("no-entry-point", None, Some(name))
+    }
+    FinalLinkError::NoInitSymbol(ref name) => {
+      ("undefined-init", None, Some(name))
+    }
+    FinalLinkError::AliasDefined(ref name) => {
+      ("duplicate-symbol", None, Some(name))
+    }
+    _ => ("final-link-failed", None, None),
+  }
+}
+
+impl LinkError {
+  // The error as a diagnostic, with a code and whatever file or symbol it
+  // is about. The message is the same as the text output has always had.
+  pub fn diagnostic(&self) -> Diagnostic {
+    // Overflows explain themselves, with what to do about them, and so do
+    // outputs dyld wouldn't load.
+    match *self {
+      LinkError::Relocatable(RelocatableError::Overflow(ref overflow))
+      | LinkError::Universal(UniversalError::Slice(
+        _,
+        RelocatableError::Overflow(ref overflow),
+      )) => return overflow.diagnostic(),
+      LinkError::DyldCheck(ref err) => return err.diagnostic(),
+      _ => (),
+    }
+    let (code, file, symbol) = match *self {
+      LinkError::Args(ArgsError::Unsupported(_)) => {
+        ("unsupported", None, None)
+      }
+      LinkError::Args(_) => ("invalid-arguments", None, None),
+      LinkError::Relocatable(ref err)
+      | LinkError::Universal(UniversalError::Slice(_, ref err)) => {
+        relocatable_details(err)
+      }
+      LinkError::Universal(UniversalError::IoError(ref path, _))
+      | LinkError::UniversalFinalLink(UniversalError::IoError(ref path, _)) => {
+        ("io-error", Some(path), None)
+      }
+      LinkError::FinalLink(ref err)
+      | LinkError::UniversalFinalLink(UniversalError::Slice(_, ref err)) => {
+        final_link_details(err)
+      }
+      LinkError::Universal(_) | LinkError::UniversalFinalLink(_) => {
+        ("universal-failed", None, None)
+      }
+      LinkError::Bfd(_) => ("bfd-failed", None, None),
+      LinkError::Dsym(_) => ("dsym-failed", None, None),
+      LinkError::DyldCheck(_) => ("dyld-would-reject", None, None),
+      LinkError::DependencyInfo(ref path, _) => ("io-error", Some(path), None),
+      LinkError::Output(_) => ("io-error", None, None),
+    };
+    let message = format!("{:?}", self);
+    let mut diagnostic = Diagnostic::new(Severity::Error, message);
+    diagnostic.code = Some(code);
+    diagnostic.file = file.cloned();
+    diagnostic.symbol = symbol.cloned();
+    diagnostic
+  }
+}
+
+// A link which didn't succeed: why, and everything reported before it.
+#[derive(Debug)]
+pub struct LinkFailure {
+  pub error: LinkError,
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+// Link with options that have been parsed and validated, writing the output
+// and anything else they ask for.
+pub fn run(opts: &LinkOptions) -> Result<()> {
+  if opts.verbose {
+    verbose::print(opts);
+    if opts.input_paths.is_empty() {
+      return Ok(());
+    }
+  }
+  with_timing(opts, || run_passes(opts))
+}
+
+// Run `f` with the link's passes timed if --time-passes or
+// -print_statistics was given, and report them whether it succeeds or not.
+// The whole link is a span too, and starts a new count of errors against
+// --error-limit, on --threads threads.
+fn with_timing<T, F: FnOnce() -> Result<T>>(
+  opts: &LinkOptions,
+  f: F,
+) -> Result<T> {
+  let _span = timing::span("link");
+  diagnostics::set_error_limit(opts.error_limit);
+  parallel::set_num_threads(opts.threads);
+  if opts.time_passes.is_none() && !opts.print_statistics {
+    return f();
+  }
+  timing::enable();
+  statistics::take();
+  let result = f();
+  let passes = timing::take();
+  if let Some(format) = opts.time_passes {
+    timing::print(&passes, format);
+  }
+  if opts.print_statistics {
+    statistics::report(&passes);
+  }
+  result
+}
+
+fn run_passes(opts: &LinkOptions) -> Result<()> {
+  let skip_unchanged = opts.skip_unchanged && opts.input_buffers.is_empty();
+  if skip_unchanged {
+    let output = &opts.output_path;
+    if let Some(previous) = Stamp::load(output) {
+      if previous.is_current(opts, output) {
+        return write_dependency_info(opts, previous.dependency_info(output));
+      }
+    }
+  }
+
+  let started = SystemTime::now();
+  let (result, info) =
+    dependency_info::recording(&opts.output_path, || link_passes(opts));
+  result?;
+  if skip_unchanged {
+    // The link worked either way, so this only costs the next one.
+    let stamp = Stamp::compute(opts, &info, started);
+    if let Err(e) = stamp.finish(&opts.output_path) {
+      diagnostics::warning(&format!(
+        "unable to record the link for --skip-unchanged: {}",
+        e
+      ));
+    }
+  }
+  write_dependency_info(opts, info)
+}
+
+// The link itself, and what's written from its output.
+fn link_passes(opts: &LinkOptions) -> Result<()> {
+  if opts.output_kind == OutputKind::Relocatable {
+    if opts.is_universal() {
+      universal::run(opts, relocatable::build)?;
+    } else {
+      relocatable::run(opts)?;
+    }
+  } else {
+    if opts.is_universal() {
+      universal::run(opts, final_link::build)?;
+      let path = &opts.output_path;
+      fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .map_err(|e| FinalLinkError::IoError(path.clone(), e))?;
+    } else {
+      final_link::run(opts)?;
+    }
+    if opts.verify_output && opts.uses_dyld() {
+      dyld_check::check_file(&opts.output_path)?;
+    }
+  }
+
+  if opts.dsym && opts.output_kind != OutputKind::Relocatable {
+    dsym::write_bundle(opts)?;
+  }
+  Ok(())
+}
+
+// -dependency_info, if it was asked for.
+fn write_dependency_info(
+  opts: &LinkOptions,
+  info: DependencyInfo,
+) -> Result<()> {
+  if let Some(ref path) = opts.dependency_info {
+    info
+      .write(path)
+      .map_err(|e| LinkError::DependencyInfo(path.clone(), e))?;
+  }
+  Ok(())
+}
+
+// Link with options that have been parsed and validated, returning the
+// output instead of writing it.
+pub fn build(opts: &LinkOptions) -> Result<Vec<u8>> {
+  let relocatable = opts.output_kind == OutputKind::Relocatable;
+  if opts.dsym {
+    diagnostics::warning("-dsym is ignored for in-memory outputs");
+  }
+  if opts.incremental {
+    diagnostics::warning("-incremental is ignored for in-memory outputs");
+  }
+  let (output, info) = dependency_info::recording(&opts.output_path, || {
+    with_timing(opts, || {
+      if !relocatable && opts.is_universal() {
+        Ok(universal::build(opts, final_link::build)?)
+      } else if !relocatable {
+        Ok(final_link::build(opts)?)
+      } else if opts.is_universal() {
+        Ok(universal::build(opts, relocatable::build)?)
+      } else {
+        Ok(relocatable::build(opts)?)
+      }
+    })
+  });
+  let output = output?;
+  write_dependency_info(opts, info)?;
+  Ok(output)
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkJob {
+  opts: LinkOptions,
+}
+
+impl LinkJob {
+  pub fn new(arch: Arch) -> LinkJob {
+    LinkJob {
+      opts: LinkOptions {
+        arch: arch,
+        ..Default::default()
+      },
+    }
+  }
+
+  // Like -platform_version; the SDK version defaults to `min_version`.
+  pub fn platform(mut self, platform: Platform, min_version: Version) -> Self {
+    self.opts.platform = platform;
+    self.opts.min_os_version = min_version;
+    self
+  }
+
+  pub fn sdk_version(mut self, version: Version) -> Self {
+    self.opts.sdk_version = Some(version);
+    self
+  }
+
+  pub fn output_type(mut self, kind: OutputKind) -> Self {
+    self.opts.output_kind = kind;
+    self
+  }
+
+  // An object file, archive or bitcode file, as if named on the command
+  // line.
+  pub fn add_object<P: AsRef<Path>>(mut self, path: P) -> Self {
+    self.opts.input_paths.push(path.as_ref().to_path_buf());
+    self
+  }
+
+  // An input which is in memory rather than on disk, named `name` in
+  // diagnostics.
+  pub fn add_object_data<P, D>(mut self, name: P, data: D) -> Self
+  where
+    P: AsRef<Path>,
+    D: Into<Vec<u8>>,
+  {
+    let name = name.as_ref().to_path_buf();
+    self.opts.input_paths.push(name.clone());
+    self.opts.input_buffers.push((name, Arc::new(data.into())));
+    self
+  }
+
+  // A dylib to link against, whatever it's named.
+  pub fn add_dylib<P: AsRef<Path>>(mut self, path: P) -> Self {
+    self.opts.dylibs.push(path.as_ref().to_path_buf());
+    self
+  }
+
+  // -l<name> and -L<dir>.
+  pub fn add_library(mut self, name: &str) -> Self {
+    self.opts.libraries.push(name.to_string());
+    self
+  }
+
+  pub fn add_library_path<P: AsRef<Path>>(mut self, dir: P) -> Self {
+    self.opts.library_paths.push(dir.as_ref().to_path_buf());
+    self
+  }
+
+  // -framework <name> and -F<dir>.
+  pub fn add_framework(mut self, name: &str) -> Self {
+    self.opts.frameworks.push(name.to_string());
+    self
+  }
+
+  pub fn add_framework_path<P: AsRef<Path>>(mut self, dir: P) -> Self {
+    self.opts.framework_paths.push(dir.as_ref().to_path_buf());
+    self
+  }
+
+  // For everything else the command line can set.
+  pub fn options(&self) -> &LinkOptions {
+    &self.opts
+  }
+
+  pub fn options_mut(&mut self) -> &mut LinkOptions {
+    &mut self.opts
+  }
+
+  // Finish the options and run `link` with them, collecting diagnostics.
+  fn finish<T, F>(
+    self,
+    link: F,
+  ) -> ::std::result::Result<(T, Vec<Diagnostic>), LinkFailure>
+  where
+    F: FnOnce(&LinkOptions) -> Result<T>,
+  {
+    let mut opts = self.opts;
+    let (result, diagnostics) = diagnostics::capture(|| {
+      opts.finish()?;
+      link(&opts)
+    });
+    match result {
+      Ok(output) => Ok((output, diagnostics)),
+      Err(e) => Err(LinkFailure {
+        error: e,
+        diagnostics: diagnostics,
+      }),
+    }
+  }
+
+  // Link, writing the output to `path`. On success, the warnings reported
+  // along the way.
+  pub fn link_to<P: AsRef<Path>>(
+    mut self,
+    path: P,
+  ) -> ::std::result::Result<Vec<Diagnostic>, LinkFailure> {
+    self.opts.output_path = path.as_ref().to_path_buf();
+    self.finish(run).map(|((), diagnostics)| diagnostics)
+  }
+
+  // Link, returning the output and the warnings reported along the way.
+  pub fn link_to_buffer(
+    self,
+  ) -> ::std::result::Result<(Vec<u8>, Vec<Diagnostic>), LinkFailure> {
+    self.finish(build)
+  }
+
+  // Link, writing the output to `writer`.
+  pub fn link_to_writer<W: Write>(
+    self,
+    writer: &mut W,
+  ) -> ::std::result::Result<Vec<Diagnostic>, LinkFailure> {
+    self.finish(|opts| {
+      let output = build(opts)?;
+      writer.write_all(&output).map_err(LinkError::Output)
+    }).map(|((), diagnostics)| diagnostics)
+  }
+}
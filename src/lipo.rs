@@ -0,0 +1,237 @@
+// `mold fat`: lipo's operations on universal ("fat") files, so building
+// one on a non-Apple host doesn't need lipo.
+//
+//   mold fat info <file>...
+//   mold fat create -o <output> <thin file>...
+//   mold fat thin <arch> -o <output> <fat file>
+//   mold fat extract <arch>... -o <output> <fat file>
+//   mold fat remove <arch>... -o <output> <fat file>
+//
+// extract and remove write a fat file, even with one slice left.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use macho::fat;
+use macho::{get_u32, Arch, MachOError};
+
+#[derive(Debug)]
+pub enum LipoError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  Usage(String),
+  // (path, cputype, cpusubtype) of a slice or thin file we don't know the
+  // architecture of.
+  UnknownArch(PathBuf, u32, u32),
+  DuplicateArch(Arch),
+  NoSuchArch(PathBuf, Arch),
+}
+
+pub type Result<T> = ::std::result::Result<T, LipoError>;
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+  fs::read(path).map_err(|e| LipoError::IoError(path.to_path_buf(), e))
+}
+
+fn write(path: &Path, data: &[u8]) -> Result<()> {
+  fs::write(path, data).map_err(|e| LipoError::IoError(path.to_path_buf(), e))
+}
+
+// The slices of the fat file at `path`, with their architectures.
+fn read_slices(path: &Path, data: &[u8]) -> Result<Vec<(Arch, Vec<u8>)>> {
+  let malformed = |e| LipoError::Malformed(path.to_path_buf(), e);
+  if !fat::is_fat(data) {
+    let magic = get_u32(data, 0, "fat header").map_err(malformed)?;
+    return Err(malformed(MachOError::BadMagic(magic)));
+  }
+  let mut slices: Vec<(Arch, Vec<u8>)> = Vec::new();
+  for slice in fat::parse(data).map_err(malformed)?.iter() {
+    let arch = Arch::from_cputype(slice.cputype, slice.cpusubtype).ok_or(
+      LipoError::UnknownArch(
+        path.to_path_buf(),
+        slice.cputype,
+        slice.cpusubtype,
+      ),
+    )?;
+    let start = slice.offset as usize;
+    slices.push((arch, data[start..(start + slice.size as usize)].to_vec()));
+  }
+  Ok(slices)
+}
+
+fn write_fat(path: &Path, slices: &[(Arch, Vec<u8>)]) -> Result<()> {
+  let slices: Vec<(Arch, &[u8])> =
+    slices.iter().map(|&(arch, ref data)| (arch, &data[..])).collect();
+  let data = fat::write(&slices)
+    .map_err(|e| LipoError::Malformed(path.to_path_buf(), e))?;
+  write(path, &data)
+}
+
+// A line per file: its slices' architectures, or its own if it's thin.
+pub fn info(path: &Path, data: &[u8]) -> Result<String> {
+  let malformed = |e| LipoError::Malformed(path.to_path_buf(), e);
+  if !fat::is_fat(data) {
+    let cputype = get_u32(data, 4, "mach header").map_err(malformed)?;
+    let cpusubtype = get_u32(data, 8, "mach header").map_err(malformed)?;
+    let arch = Arch::from_cputype(cputype, cpusubtype).ok_or(
+      LipoError::UnknownArch(path.to_path_buf(), cputype, cpusubtype),
+    )?;
+    return Ok(format!(
+      "Non-fat file: {} is architecture: {}",
+      path.display(),
+      arch.name()
+    ));
+  }
+  let archs: Vec<&str> = read_slices(path, data)?
+    .iter()
+    .map(|&(arch, _)| arch.name())
+    .collect();
+  Ok(format!(
+    "Architectures in the fat file: {} are: {}",
+    path.display(),
+    archs.join(" ")
+  ))
+}
+
+// A fat file of `inputs`, which may themselves be fat.
+pub fn create(inputs: &[PathBuf], output: &Path) -> Result<()> {
+  let mut slices: Vec<(Arch, Vec<u8>)> = Vec::new();
+  for input in inputs.iter() {
+    let data = read(input)?;
+    let mut input_slices = if fat::is_fat(&data) {
+      read_slices(input, &data)?
+    } else {
+      let malformed = |e| LipoError::Malformed(input.clone(), e);
+      let cputype = get_u32(&data, 4, "mach header").map_err(malformed)?;
+      let cpusubtype = get_u32(&data, 8, "mach header").map_err(malformed)?;
+      let arch = Arch::from_cputype(cputype, cpusubtype)
+        .ok_or(LipoError::UnknownArch(input.clone(), cputype, cpusubtype))?;
+      vec![(arch, data)]
+    };
+    for (arch, data) in input_slices.drain(..) {
+      if slices.iter().any(|&(other, _)| other == arch) {
+        return Err(LipoError::DuplicateArch(arch));
+      }
+      slices.push((arch, data));
+    }
+  }
+  write_fat(output, &slices)
+}
+
+// `arch`'s slice of `input`, as a thin file.
+pub fn thin(input: &Path, arch: Arch, output: &Path) -> Result<()> {
+  let data = read(input)?;
+  let slices = read_slices(input, &data)?;
+  match slices.iter().find(|&&(other, _)| other == arch) {
+    Some(&(_, ref slice)) => write(output, slice),
+    None => Err(LipoError::NoSuchArch(input.to_path_buf(), arch)),
+  }
+}
+
+// `input` with only the slices for `archs` (or, with `remove`, every slice
+// but those).
+pub fn select(
+  input: &Path,
+  archs: &[Arch],
+  remove: bool,
+  output: &Path,
+) -> Result<()> {
+  let data = read(input)?;
+  let slices = read_slices(input, &data)?;
+  for &arch in archs.iter() {
+    if !slices.iter().any(|&(other, _)| other == arch) {
+      return Err(LipoError::NoSuchArch(input.to_path_buf(), arch));
+    }
+  }
+  let kept: Vec<(Arch, Vec<u8>)> = slices
+    .into_iter()
+    .filter(|&(arch, _)| archs.contains(&arch) != remove)
+    .collect();
+  if kept.is_empty() {
+    return Err(LipoError::Usage("no slices would be left".to_string()));
+  }
+  write_fat(output, &kept)
+}
+
+fn parse_arch(name: &str) -> Result<Arch> {
+  Arch::from_name(name)
+    .ok_or_else(|| LipoError::Usage(format!("unknown architecture {}", name)))
+}
+
+// `mold fat <operation> ...`.
+pub fn run_command(args: &[String]) -> Result<()> {
+  let operation = match args.first() {
+    Some(operation) => operation.as_str(),
+    None => return Err(LipoError::Usage("no operation".to_string())),
+  };
+  let mut output: Option<PathBuf> = None;
+  let mut words: Vec<&str> = Vec::new();
+  let mut rest = args[1..].iter();
+  while let Some(arg) = rest.next() {
+    match arg.as_str() {
+      "-o" | "-output" => match rest.next() {
+        Some(path) => output = Some(PathBuf::from(path)),
+        None => return Err(LipoError::Usage("-o needs a path".to_string())),
+      },
+      _ if arg.starts_with('-') => {
+        return Err(LipoError::Usage(format!("unknown option {}", arg)));
+      }
+      _ => words.push(arg),
+    }
+  }
+  let output = || {
+    output
+      .clone()
+      .ok_or_else(|| LipoError::Usage(format!("{} needs -o", operation)))
+  };
+  // The fat file is the last word, and the architectures come before it.
+  let split = |words: &[&str]| -> Result<(Vec<Arch>, PathBuf)> {
+    match words.split_last() {
+      Some((input, archs)) if !archs.is_empty() => {
+        let archs = archs
+          .iter()
+          .map(|name| parse_arch(name))
+          .collect::<Result<Vec<Arch>>>()?;
+        Ok((archs, PathBuf::from(input)))
+      }
+      _ => Err(LipoError::Usage(format!(
+        "{} needs an architecture and an input",
+        operation
+      ))),
+    }
+  };
+
+  match operation {
+    "info" => {
+      if words.is_empty() {
+        return Err(LipoError::Usage("no input files".to_string()));
+      }
+      for input in words.iter() {
+        let input = Path::new(input);
+        println!("{}", info(input, &read(input)?)?);
+      }
+      Ok(())
+    }
+    "create" => {
+      if words.is_empty() {
+        return Err(LipoError::Usage("no input files".to_string()));
+      }
+      let inputs: Vec<PathBuf> = words.iter().map(PathBuf::from).collect();
+      create(&inputs, &output()?)
+    }
+    "thin" => {
+      let (archs, input) = split(&words)?;
+      if archs.len() != 1 {
+        let message = "thin takes one architecture".to_string();
+        return Err(LipoError::Usage(message));
+      }
+      thin(&input, archs[0], &output()?)
+    }
+    "extract" | "remove" => {
+      let (archs, input) = split(&words)?;
+      select(&input, &archs, operation == "remove", &output()?)
+    }
+    _ => Err(LipoError::Usage(format!("unknown operation {}", operation))),
+  }
+}
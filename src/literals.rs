@@ -0,0 +1,312 @@
+// Merging of literal sections (__cstring, __literal4/8/16, __ustring) across
+// input files.
+//
+// Each literal is only stored once in the output, no matter how many inputs
+// contain it. Relocations which pointed into an input's copy of a literal are
+// redirected to the canonical copy with `LiteralMap::translate()`.
+
+use std::collections::HashMap;
+
+use macho::object_file::ObjectFile;
+use macho::reloc::{RelocError, RelocationInfo, ARM64_RELOC_SUBTRACTOR,
+                   ARM64_RELOC_UNSIGNED, X86_64_RELOC_SIGNED,
+                   X86_64_RELOC_SIGNED_1, X86_64_RELOC_SIGNED_2,
+                   X86_64_RELOC_SIGNED_4, X86_64_RELOC_SUBTRACTOR,
+                   X86_64_RELOC_UNSIGNED};
+use macho::{get_u32, get_u64, round_up, Arch, S_16BYTE_LITERALS,
+            S_4BYTE_LITERALS, S_8BYTE_LITERALS, S_CSTRING_LITERALS,
+            SECTION_TYPE};
+use relocatable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LiteralKind {
+  CString,
+  // UTF-16 strings terminated by a 16-bit zero, from __TEXT,__ustring.
+  UString,
+  Fixed(usize),
+}
+
+impl LiteralKind {
+  // Classify an input section, or return None if it isn't mergeable.
+  pub fn for_section(sectname: &str, flags: u32) -> Option<LiteralKind> {
+    match flags & SECTION_TYPE {
+      S_CSTRING_LITERALS => Some(LiteralKind::CString),
+      S_4BYTE_LITERALS => Some(LiteralKind::Fixed(4)),
+      S_8BYTE_LITERALS => Some(LiteralKind::Fixed(8)),
+      S_16BYTE_LITERALS => Some(LiteralKind::Fixed(16)),
+      _ if sectname == "__ustring" => Some(LiteralKind::UString),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum LiteralError {
+  // The last string in the section has no terminator: (offset of string).
+  Unterminated(usize),
+  // A fixed-size literal section whose size isn't a multiple of the literal
+  // size: (section size, literal size).
+  BadSize(usize, usize),
+  // Redirecting a relocation to a literal's canonical copy.
+  Reloc(RelocError),
+}
+
+impl From<RelocError> for LiteralError {
+  fn from(error: RelocError) -> Self {
+    LiteralError::Reloc(error)
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, LiteralError>;
+
+// Split a section into (offset, length) of each literal, including any
+// terminator.
+pub fn split_literals(
+  kind: LiteralKind,
+  contents: &[u8],
+) -> Result<Vec<(usize, usize)>> {
+  let mut literals: Vec<(usize, usize)> = Vec::new();
+  match kind {
+    LiteralKind::Fixed(size) => {
+      if contents.len() % size != 0 {
+        return Err(LiteralError::BadSize(contents.len(), size));
+      }
+      for i in 0..(contents.len() / size) {
+        literals.push((i * size, size));
+      }
+    }
+    LiteralKind::CString | LiteralKind::UString => {
+      let unit = if kind == LiteralKind::CString { 1 } else { 2 };
+      let mut start = 0;
+      let mut pos = 0;
+      while pos + unit <= contents.len() {
+        let at_terminator = contents[pos..(pos + unit)].iter().all(|b| *b == 0);
+        pos += unit;
+        if at_terminator {
+          literals.push((start, pos - start));
+          start = pos;
+        }
+      }
+      if start != contents.len() {
+        return Err(LiteralError::Unterminated(start));
+      }
+    }
+  }
+  Ok(literals)
+}
+
+// The deduplicated contents of one output literal section.
+#[derive(Debug, Default)]
+pub struct LiteralPool {
+  pub contents: Vec<u8>,
+  pub align: u64,
+  offsets: HashMap<Vec<u8>, u64>,
+}
+
+// Where each literal of one input section ended up in the output section,
+// as (input offset, length, output offset), sorted by input offset.
+#[derive(Debug, Clone, Default)]
+pub struct LiteralMap {
+  pub entries: Vec<(u64, u64, u64)>,
+}
+
+impl LiteralMap {
+  // Translate an offset into the input section, which may point into the
+  // middle of a literal, into an offset into the output section.
+  pub fn translate(&self, input_offset: u64) -> Option<u64> {
+    let idx = match self
+      .entries
+      .binary_search_by_key(&input_offset, |&(start, _, _)| start)
+    {
+      Ok(idx) => idx,
+      Err(0) => return None,
+      Err(idx) => idx - 1,
+    };
+    let (start, len, out) = self.entries[idx];
+    if input_offset < start + len {
+      Some(out + (input_offset - start))
+    } else {
+      None
+    }
+  }
+}
+
+impl LiteralPool {
+  pub fn new() -> Self {
+    LiteralPool {
+      align: 1,
+      ..Default::default()
+    }
+  }
+
+  // Add a literal, returning the offset of the canonical copy. Literals from
+  // sections with a larger alignment keep that alignment in the output.
+  pub fn add(&mut self, literal: &[u8], align: u64) -> u64 {
+    if let Some(off) = self.offsets.get(literal) {
+      if *off % align == 0 {
+        return *off;
+      }
+    }
+    if align > self.align {
+      self.align = align;
+    }
+    let off = round_up(self.contents.len() as u64, align);
+    self.contents.resize(off as usize, 0);
+    self.contents.extend_from_slice(literal);
+    self.offsets.insert(literal.to_vec(), off);
+    off
+  }
+
+  // Merge a whole input section into the pool.
+  pub fn merge_section(
+    &mut self,
+    kind: LiteralKind,
+    contents: &[u8],
+    align: u64,
+  ) -> Result<LiteralMap> {
+    let mut map = LiteralMap::default();
+    for (start, len) in split_literals(kind, contents)? {
+      let out = self.add(&contents[start..(start + len)], align);
+      map.entries.push((start as u64, len as u64, out));
+    }
+    Ok(map)
+  }
+
+  // Bytes saved by deduplication, for reporting.
+  pub fn saved_bytes(&self, total_input_size: u64) -> u64 {
+    total_input_size.saturating_sub(self.contents.len() as u64)
+  }
+}
+
+// The literal a section-relative relocation refers to, which is baked into
+// its fixup, for the kinds which say exactly where. `at` is the address of
+// the fixup, which is in `contents` at `offset`.
+fn fixup_target(
+  arch: Arch,
+  reloc: &RelocationInfo,
+  contents: &[u8],
+  offset: usize,
+  at: u64,
+) -> Option<u64> {
+  let unsigned = match arch {
+    Arch::X86_64 => X86_64_RELOC_UNSIGNED,
+    Arch::Arm64 | Arch::Arm64e => ARM64_RELOC_UNSIGNED,
+    _ => return None,
+  };
+  if reloc.kind == unsigned && !reloc.pcrel {
+    return match reloc.length {
+      3 => get_u64(contents, offset, "").ok(),
+      2 => get_u32(contents, offset, "").ok().map(|val| val as u64),
+      _ => None,
+    };
+  }
+  // The displacement is from the end of the instruction, which may have
+  // an immediate after it.
+  let immediate = match (arch, reloc.kind) {
+    (Arch::X86_64, X86_64_RELOC_SIGNED) => 0,
+    (Arch::X86_64, X86_64_RELOC_SIGNED_1) => 1,
+    (Arch::X86_64, X86_64_RELOC_SIGNED_2) => 2,
+    (Arch::X86_64, X86_64_RELOC_SIGNED_4) => 4,
+    _ => return None,
+  };
+  if !reloc.pcrel || reloc.length != 2 {
+    return None;
+  }
+  let disp = get_u32(contents, offset, "").ok()? as i32 as i64 as u64;
+  Some((at + 4 + immediate).wrapping_add(disp))
+}
+
+// (section, relocation, where it points) of each section-relative
+// relocation into merged section `index`, or None if some can't be
+// redirected.
+fn references(
+  arch: Arch,
+  object: &ObjectFile,
+  index: usize,
+) -> Option<Vec<(usize, usize, u64)>> {
+  let mut found: Vec<(usize, usize, u64)> = Vec::new();
+  for (i, sect) in object.sections.iter().enumerate() {
+    let mut paired = false;
+    for (k, reloc) in sect.relocs.iter().enumerate() {
+      if reloc.scattered.is_some() {
+        return None;
+      }
+      // The half of a subtraction after a SUBTRACTOR has the difference in
+      // its fixup, not the target.
+      let after_subtractor = paired;
+      paired = match arch {
+        Arch::X86_64 => reloc.kind == X86_64_RELOC_SUBTRACTOR,
+        Arch::Arm64 | Arch::Arm64e => reloc.kind == ARM64_RELOC_SUBTRACTOR,
+        _ => false,
+      };
+      if reloc.external || reloc.symbolnum as usize != index + 1 {
+        continue;
+      }
+      let offset = reloc.address as usize;
+      let at = sect.addr + reloc.address as u64;
+      match fixup_target(arch, reloc, &sect.contents, offset, at) {
+        Some(target) if !after_subtractor => found.push((i, k, target)),
+        _ => return None,
+      }
+    }
+  }
+  Some(found)
+}
+
+// Store each literal of a final link's merged object once. The literal
+// sections shrink in place: symbols in them and the relocations which
+// point into them are moved to the canonical copies.
+pub fn coalesce(arch: Arch, object: &mut ObjectFile) -> Result<()> {
+  for index in 0..object.sections.len() {
+    let (kind, align) = {
+      let sect = &object.sections[index];
+      match LiteralKind::for_section(&sect.sectname, sect.flags) {
+        Some(kind) if sect.relocs.is_empty() => (kind, 1 << sect.align),
+        _ => continue,
+      }
+    };
+    let refs = match references(arch, object, index) {
+      Some(refs) => refs,
+      None => continue,
+    };
+    let base = object.sections[index].addr;
+    let mut pool = LiteralPool::new();
+    let map =
+      pool.merge_section(kind, &object.sections[index].contents, align)?;
+    let moved = refs
+      .iter()
+      .map(|&(_, _, target)| {
+        let offset = target.wrapping_sub(base);
+        map.translate(offset).map(|to| to.wrapping_sub(offset))
+      })
+      .collect::<Option<Vec<u64>>>();
+    let moved = match moved {
+      Some(moved) => moved,
+      None => continue,
+    };
+    for (&(i, k, _), &delta) in refs.iter().zip(moved.iter()) {
+      let sect = &mut object.sections[i];
+      let reloc = sect.relocs[k].clone();
+      let offset = reloc.address as usize;
+      relocatable::adjust_fixup(
+        arch,
+        &reloc,
+        &mut sect.contents,
+        offset,
+        delta,
+        0,
+      )?;
+    }
+    let end = pool.contents.len() as u64;
+    for sym in object.symbols.iter_mut() {
+      if sym.n_sect as usize == index + 1 && !sym.is_undefined() {
+        let offset = sym.n_value.wrapping_sub(base);
+        sym.n_value = base + map.translate(offset).unwrap_or(end);
+      }
+    }
+    let sect = &mut object.sections[index];
+    sect.size = end;
+    sect.contents = pool.contents;
+  }
+  Ok(())
+}
@@ -0,0 +1,394 @@
+// Link-time optimization with the LLVM this linker was built against (the
+// `llvm` feature) rather than a libLTO found at run time, so bitcode inputs
+// link on hosts without Xcode and with the code generation options given
+// on the command line.
+//
+// Full LTO links every module into one, internalizes what nothing outside
+// it needs, and compiles that into a single object. ThinLTO (--thinlto)
+// optimizes and compiles each module on its own context and thread; each
+// keeps what the other modules refer to, as well as what native code does.
+
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
+use std::sync::Once;
+
+use llvm_sys::bit_reader::{LLVMGetBitcodeModuleInContext2,
+                           LLVMParseBitcodeInContext2};
+use llvm_sys::core::{LLVMContextCreate, LLVMContextDispose,
+                     LLVMCreateMemoryBufferWithMemoryRangeCopy,
+                     LLVMDisposeMemoryBuffer, LLVMDisposeMessage,
+                     LLVMGetBufferSize, LLVMGetBufferStart,
+                     LLVMGetFirstFunction, LLVMGetFirstGlobal,
+                     LLVMGetFirstGlobalAlias, LLVMGetLinkage,
+                     LLVMGetNextFunction, LLVMGetNextGlobal,
+                     LLVMGetNextGlobalAlias, LLVMGetTarget,
+                     LLVMGetValueName2, LLVMGetVisibility, LLVMIsDeclaration,
+                     LLVMSetLinkage};
+use llvm_sys::error::{LLVMDisposeErrorMessage, LLVMGetErrorMessage};
+use llvm_sys::linker::LLVMLinkModules2;
+use llvm_sys::prelude::{LLVMContextRef, LLVMMemoryBufferRef, LLVMModuleRef,
+                        LLVMValueRef};
+use llvm_sys::target::{LLVM_InitializeAllAsmPrinters,
+                       LLVM_InitializeAllTargetInfos,
+                       LLVM_InitializeAllTargetMCs,
+                       LLVM_InitializeAllTargets};
+use llvm_sys::target_machine::{LLVMCodeGenFileType, LLVMCodeGenOptLevel,
+                               LLVMCodeModel, LLVMCreateTargetMachine,
+                               LLVMDisposeTargetMachine,
+                               LLVMGetTargetFromTriple, LLVMRelocMode,
+                               LLVMTargetMachineEmitToMemoryBuffer};
+use llvm_sys::transforms::pass_builder::{LLVMCreatePassBuilderOptions,
+                                         LLVMDisposePassBuilderOptions,
+                                         LLVMRunPasses};
+use llvm_sys::{LLVMLinkage, LLVMVisibility};
+
+use args::{LinkOptions, OutputKind};
+use lto::{self, LtoError, Result};
+use parallel;
+use resolve::{InputSymbol, SymbolKind};
+
+static INITIALIZE: Once = Once::new();
+
+// A context, which owns the modules parsed into it.
+struct Context(LLVMContextRef);
+
+impl Context {
+  fn new() -> Context {
+    Context(unsafe { LLVMContextCreate() })
+  }
+}
+
+impl Drop for Context {
+  fn drop(&mut self) {
+    unsafe { LLVMContextDispose(self.0) }
+  }
+}
+
+// Takes ownership of a message LLVM allocated.
+unsafe fn take_message(message: *mut c_char) -> String {
+  if message.is_null() {
+    return String::new();
+  }
+  let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+  LLVMDisposeMessage(message);
+  text
+}
+
+fn memory_buffer(path: &Path, data: &[u8]) -> LLVMMemoryBufferRef {
+  let name = CString::new(path.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  unsafe {
+    LLVMCreateMemoryBufferWithMemoryRangeCopy(
+      data.as_ptr() as *const c_char,
+      data.len(),
+      name.as_ptr(),
+    )
+  }
+}
+
+fn bad_module(path: &Path) -> LtoError {
+  LtoError::BadModule(path.to_path_buf(), "invalid bitcode".to_string())
+}
+
+// Every function, variable and alias in the module.
+fn globals(module: LLVMModuleRef) -> Vec<LLVMValueRef> {
+  let mut values: Vec<LLVMValueRef> = Vec::new();
+  unsafe {
+    let mut value = LLVMGetFirstFunction(module);
+    while !value.is_null() {
+      values.push(value);
+      value = LLVMGetNextFunction(value);
+    }
+    let mut value = LLVMGetFirstGlobal(module);
+    while !value.is_null() {
+      values.push(value);
+      value = LLVMGetNextGlobal(value);
+    }
+    let mut value = LLVMGetFirstGlobalAlias(module);
+    while !value.is_null() {
+      values.push(value);
+      value = LLVMGetNextGlobalAlias(value);
+    }
+  }
+  values
+}
+
+// The Mach-O name of a global: its IR name with the leading underscore, or
+// as is if it starts with \1. None for LLVM's own globals.
+fn symbol_name(value: LLVMValueRef) -> Option<String> {
+  let mut len: usize = 0;
+  let name = unsafe {
+    let data = LLVMGetValueName2(value, &mut len);
+    String::from_utf8_lossy(slice::from_raw_parts(data as *const u8, len))
+  };
+  if name.is_empty() || name.starts_with("llvm.") {
+    None
+  } else if name.starts_with('\x01') {
+    Some(name[1..].to_string())
+  } else {
+    Some(format!("_{}", name))
+  }
+}
+
+// Parse a whole module into the context, for -arch.
+fn parse(
+  context: &Context,
+  path: &Path,
+  data: &[u8],
+  opts: &LinkOptions,
+) -> Result<LLVMModuleRef> {
+  let buffer = memory_buffer(path, data);
+  let mut module: LLVMModuleRef = ptr::null_mut();
+  let failed =
+    unsafe { LLVMParseBitcodeInContext2(context.0, buffer, &mut module) };
+  unsafe { LLVMDisposeMemoryBuffer(buffer) };
+  if failed != 0 {
+    return Err(bad_module(path));
+  }
+  let triple = unsafe { CStr::from_ptr(LLVMGetTarget(module)) };
+  lto::check_triple(path, triple.to_string_lossy().into_owned(), opts)?;
+  Ok(module)
+}
+
+// The names a module refers to but doesn't define. Only the module's
+// symbol table is read, not the function bodies.
+fn references(path: &Path, data: &[u8]) -> Result<Vec<String>> {
+  let context = Context::new();
+  let buffer = memory_buffer(path, data);
+  let mut module: LLVMModuleRef = ptr::null_mut();
+  // The module owns the buffer once it's been read.
+  if unsafe { LLVMGetBitcodeModuleInContext2(context.0, buffer, &mut module) }
+    != 0
+  {
+    unsafe { LLVMDisposeMemoryBuffer(buffer) };
+    return Err(bad_module(path));
+  }
+  Ok(
+    globals(module)
+      .into_iter()
+      .filter(|&value| unsafe { LLVMIsDeclaration(value) } != 0)
+      .filter_map(symbol_name)
+      .collect(),
+  )
+}
+
+// Give internal linkage to every definition which doesn't have to survive
+// optimization, so the optimizer can inline or drop it.
+fn internalize(
+  module: LLVMModuleRef,
+  opts: &LinkOptions,
+  references: &HashSet<String>,
+) {
+  for value in globals(module).into_iter() {
+    if unsafe { LLVMIsDeclaration(value) } != 0 {
+      continue;
+    }
+    let kind = match unsafe { LLVMGetLinkage(value) } {
+      LLVMLinkage::LLVMExternalLinkage => SymbolKind::Defined,
+      LLVMLinkage::LLVMWeakAnyLinkage
+      | LLVMLinkage::LLVMWeakODRLinkage
+      | LLVMLinkage::LLVMLinkOnceAnyLinkage
+      | LLVMLinkage::LLVMLinkOnceODRLinkage => SymbolKind::WeakDefined,
+      // Already local, or (like appending and common linkage) not ours to
+      // change.
+      _ => continue,
+    };
+    let name = match symbol_name(value) {
+      Some(name) => name,
+      None => continue,
+    };
+    let visibility = unsafe { LLVMGetVisibility(value) };
+    let sym = InputSymbol {
+      name: name,
+      kind: kind,
+      private_extern: visibility == LLVMVisibility::LLVMHiddenVisibility,
+      value: 0,
+    };
+    if !lto::must_preserve(&sym, opts, references) {
+      unsafe { LLVMSetLinkage(value, LLVMLinkage::LLVMInternalLinkage) };
+    }
+  }
+}
+
+// Optimize the module with `pipeline` (e.g. "lto<O2>") and compile it into
+// a Mach-O object.
+fn codegen(
+  module: LLVMModuleRef,
+  pipeline: &str,
+  opts: &LinkOptions,
+) -> Result<Vec<u8>> {
+  let triple = unsafe { LLVMGetTarget(module) };
+  let mut target = ptr::null_mut();
+  let mut message: *mut c_char = ptr::null_mut();
+  if unsafe { LLVMGetTargetFromTriple(triple, &mut target, &mut message) } != 0
+  {
+    return Err(LtoError::CodegenFailed(unsafe { take_message(message) }));
+  }
+  let cpu = CString::new(opts.lto_cpu.clone().unwrap_or_default())
+    .unwrap_or_default();
+  let features = CString::new(opts.lto_features.clone().unwrap_or_default())
+    .unwrap_or_default();
+  let level = match opts.lto_opt_level {
+    0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+    1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+    2 => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+    _ => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+  };
+  let reloc = if opts.output_kind == OutputKind::Executable && !opts.use_pie()
+  {
+    LLVMRelocMode::LLVMRelocDynamicNoPic
+  } else {
+    LLVMRelocMode::LLVMRelocPIC
+  };
+  let machine = unsafe {
+    LLVMCreateTargetMachine(
+      target,
+      triple,
+      cpu.as_ptr(),
+      features.as_ptr(),
+      level,
+      reloc,
+      LLVMCodeModel::LLVMCodeModelDefault,
+    )
+  };
+  let result = (|| {
+    let passes = CString::new(pipeline).unwrap();
+    unsafe {
+      let options = LLVMCreatePassBuilderOptions();
+      let error = LLVMRunPasses(module, passes.as_ptr(), machine, options);
+      LLVMDisposePassBuilderOptions(options);
+      if !error.is_null() {
+        let message = LLVMGetErrorMessage(error);
+        let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+        LLVMDisposeErrorMessage(message);
+        return Err(LtoError::CodegenFailed(text));
+      }
+    }
+    let mut message: *mut c_char = ptr::null_mut();
+    let mut buffer: LLVMMemoryBufferRef = ptr::null_mut();
+    let failed = unsafe {
+      LLVMTargetMachineEmitToMemoryBuffer(
+        machine,
+        module,
+        LLVMCodeGenFileType::LLVMObjectFile,
+        &mut message,
+        &mut buffer,
+      )
+    };
+    if failed != 0 {
+      return Err(LtoError::CodegenFailed(unsafe { take_message(message) }));
+    }
+    let object = unsafe {
+      let start = LLVMGetBufferStart(buffer) as *const u8;
+      let object = slice::from_raw_parts(start, LLVMGetBufferSize(buffer));
+      let object = object.to_vec();
+      LLVMDisposeMemoryBuffer(buffer);
+      object
+    };
+    Ok(object)
+  })();
+  unsafe { LLVMDisposeTargetMachine(machine) };
+  result
+}
+
+fn write_object(path: &Path, object: &[u8]) -> Result<()> {
+  File::create(path)
+    .and_then(|mut f| f.write_all(object))
+    .map_err(|e| LtoError::IoError(path.to_path_buf(), e))
+}
+
+// Compile the bitcode inputs into Mach-O objects, with where each appears
+// in diagnostics. With -object_path_lto, full LTO writes its object there,
+// and ThinLTO makes it a directory of <n>.o, one for each input.
+pub fn compile(
+  bitcode: &[(PathBuf, Vec<u8>)],
+  opts: &LinkOptions,
+  native_references: &HashSet<String>,
+) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+  INITIALIZE.call_once(|| unsafe {
+    LLVM_InitializeAllTargetInfos();
+    LLVM_InitializeAllTargets();
+    LLVM_InitializeAllTargetMCs();
+    LLVM_InitializeAllAsmPrinters();
+  });
+  if opts.thinlto {
+    compile_thin(bitcode, opts, native_references)
+  } else {
+    compile_full(bitcode, opts, native_references)
+  }
+}
+
+fn compile_full(
+  bitcode: &[(PathBuf, Vec<u8>)],
+  opts: &LinkOptions,
+  native_references: &HashSet<String>,
+) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+  let context = Context::new();
+  let mut merged: LLVMModuleRef = ptr::null_mut();
+  for &(ref path, ref data) in bitcode.iter() {
+    let module = parse(&context, path, data, opts)?;
+    if merged.is_null() {
+      merged = module;
+    } else if unsafe { LLVMLinkModules2(merged, module) } != 0 {
+      return Err(LtoError::BadModule(
+        path.clone(),
+        "unable to link it with the other bitcode".to_string(),
+      ));
+    }
+  }
+  internalize(merged, opts, native_references);
+  let pipeline = format!("lto<O{}>", opts.lto_opt_level);
+  let object = codegen(merged, &pipeline, opts)?;
+  if let Some(ref path) = opts.object_path_lto {
+    write_object(path, &object)?;
+  }
+  Ok(vec![(lto::object_path(opts), object)])
+}
+
+fn compile_thin(
+  bitcode: &[(PathBuf, Vec<u8>)],
+  opts: &LinkOptions,
+  native_references: &HashSet<String>,
+) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+  let threads = match opts.thinlto_jobs {
+    0 => parallel::num_threads(),
+    jobs => jobs,
+  };
+  let mut references = native_references.clone();
+  let declared =
+    parallel::map_with_threads(bitcode, threads, |&(ref path, ref data)| {
+      references(path, data)
+    });
+  for names in declared.into_iter() {
+    references.extend(names?);
+  }
+  let pipeline = format!("default<O{}>", opts.lto_opt_level);
+  let objects =
+    parallel::map_with_threads(bitcode, threads, |&(ref path, ref data)| {
+      let context = Context::new();
+      let module = parse(&context, path, data, opts)?;
+      internalize(module, opts, &references);
+      codegen(module, &pipeline, opts)
+    });
+
+  let dir = lto::object_path(opts);
+  if opts.object_path_lto.is_some() {
+    fs::create_dir_all(&dir).map_err(|e| LtoError::IoError(dir.clone(), e))?;
+  }
+  let mut outputs: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+  for (i, object) in objects.into_iter().enumerate() {
+    let path = dir.join(format!("{}.o", i));
+    let object = object?;
+    if opts.object_path_lto.is_some() {
+      write_object(&path, &object)?;
+    }
+    outputs.push((path, object));
+  }
+  Ok(outputs)
+}
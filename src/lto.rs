@@ -0,0 +1,364 @@
+// Link-time optimization of LLVM bitcode inputs through libLTO (or, with
+// the `llvm` feature, the LLVM in src/llvm_lto.rs).
+//
+// Bitcode files take part in symbol resolution like any other input, using
+// the symbols libLTO reports for them. Once every input has been read, the
+// bitcode modules are handed to libLTO together, along with the names which
+// must survive optimization (those referenced from native code, or visible
+// outside the output), and the machine-code object it produces replaces
+// them in the link.
+
+extern crate libc;
+
+use self::libc::{c_char, c_int, c_uint, c_void, size_t};
+
+use std::collections::HashSet;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::slice;
+
+use args::{LinkOptions, OutputKind};
+use resolve::{InputSymbol, SymbolKind};
+
+#[cfg(target_os = "macos")]
+const LIBLTO_NAME: &str = "libLTO.dylib";
+#[cfg(not(target_os = "macos"))]
+const LIBLTO_NAME: &str = "libLTO.so";
+
+// lto_symbol_attributes
+const LTO_SYMBOL_DEFINITION_MASK: c_uint = 0x700;
+const LTO_SYMBOL_DEFINITION_WEAK: c_uint = 0x300;
+const LTO_SYMBOL_DEFINITION_UNDEFINED: c_uint = 0x400;
+const LTO_SYMBOL_DEFINITION_WEAKUNDEF: c_uint = 0x500;
+const LTO_SYMBOL_SCOPE_MASK: c_uint = 0x3800;
+const LTO_SYMBOL_SCOPE_INTERNAL: c_uint = 0x800;
+const LTO_SYMBOL_SCOPE_HIDDEN: c_uint = 0x1000;
+
+// lto_codegen_model, lto_debug_model
+const LTO_CODEGEN_PIC_MODEL_DYNAMIC: c_int = 1;
+const LTO_CODEGEN_PIC_MODEL_DYNAMIC_NO_PIC: c_int = 2;
+const LTO_DEBUG_MODEL_DWARF: c_int = 1;
+
+#[derive(Debug)]
+pub enum LtoError {
+  // (library path, dlerror message)
+  LoadFailed(PathBuf, String),
+  // A libLTO entry point we need isn't exported by the library.
+  MissingSymbol(&'static str),
+  // (input path, libLTO error message)
+  BadModule(PathBuf, String),
+  // (input path, its target triple) for bitcode built for another -arch.
+  WrongArch(PathBuf, String),
+  CodegenFailed(String),
+  IoError(PathBuf, io::Error),
+}
+
+pub type Result<T> = ::std::result::Result<T, LtoError>;
+
+// Raw bitcode starts with 'BC' 0xC0DE; Darwin tools may also wrap it in a
+// header starting with 0x0B17C0DE.
+pub fn is_bitcode(data: &[u8]) -> bool {
+  data.starts_with(&[0x42, 0x43, 0xc0, 0xde])
+    || data.starts_with(&[0xde, 0xc0, 0x17, 0x0b])
+}
+
+// -lto_library, or libLTO from the toolchain this linker was installed
+// into (usr/bin/ld next to usr/lib/libLTO.dylib), or the dlopen search
+// path.
+pub fn library_path(opts: &LinkOptions) -> PathBuf {
+  if let Some(ref path) = opts.lto_library {
+    return path.clone();
+  }
+  env::current_exe()
+    .ok()
+    .and_then(|exe| {
+      exe
+        .parent()
+        .and_then(|bin| bin.parent())
+        .map(Path::to_path_buf)
+    })
+    .map(|usr| usr.join("lib").join(LIBLTO_NAME))
+    .filter(|path| path.exists())
+    .unwrap_or_else(|| PathBuf::from(LIBLTO_NAME))
+}
+
+type LtoModule = *mut c_void;
+type LtoCodeGen = *mut c_void;
+
+// The subset of the libLTO C API (llvm-c/lto.h) we use.
+struct Api {
+  get_error_message: unsafe extern "C" fn() -> *const c_char,
+  module_create_from_memory:
+    unsafe extern "C" fn(*const c_void, size_t) -> LtoModule,
+  module_dispose: unsafe extern "C" fn(LtoModule),
+  module_get_target_triple: unsafe extern "C" fn(LtoModule) -> *const c_char,
+  module_get_num_symbols: unsafe extern "C" fn(LtoModule) -> c_uint,
+  module_get_symbol_name:
+    unsafe extern "C" fn(LtoModule, c_uint) -> *const c_char,
+  module_get_symbol_attribute:
+    unsafe extern "C" fn(LtoModule, c_uint) -> c_uint,
+  codegen_create: unsafe extern "C" fn() -> LtoCodeGen,
+  codegen_dispose: unsafe extern "C" fn(LtoCodeGen),
+  codegen_add_module: unsafe extern "C" fn(LtoCodeGen, LtoModule) -> bool,
+  codegen_set_pic_model: unsafe extern "C" fn(LtoCodeGen, c_int) -> bool,
+  codegen_set_debug_model: unsafe extern "C" fn(LtoCodeGen, c_int) -> bool,
+  codegen_add_must_preserve_symbol:
+    unsafe extern "C" fn(LtoCodeGen, *const c_char),
+  codegen_compile:
+    unsafe extern "C" fn(LtoCodeGen, *mut size_t) -> *const c_void,
+}
+
+unsafe fn lookup<T: Copy>(
+  handle: *mut c_void,
+  name: &'static str,
+) -> Result<T> {
+  let c_name = CString::new(name).unwrap();
+  let sym = libc::dlsym(handle, c_name.as_ptr());
+  if sym.is_null() {
+    return Err(LtoError::MissingSymbol(name));
+  }
+  Ok(mem::transmute_copy(&sym))
+}
+
+fn c_string(ptr: *const c_char) -> String {
+  if ptr.is_null() {
+    return String::new();
+  }
+  unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned()
+}
+
+// A loaded libLTO. It's never unloaded, as LLVM doesn't support that.
+pub struct LibLto {
+  api: Api,
+}
+
+impl LibLto {
+  pub fn load(path: &Path) -> Result<LibLto> {
+    let c_path = CString::new(path.to_string_lossy().into_owned())
+      .map_err(|_| {
+        LtoError::LoadFailed(path.to_path_buf(), "NUL in path".to_string())
+      })?;
+    unsafe {
+      let handle = libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW);
+      if handle.is_null() {
+        return Err(LtoError::LoadFailed(
+          path.to_path_buf(),
+          c_string(libc::dlerror()),
+        ));
+      }
+      let api = Api {
+        get_error_message: lookup(handle, "lto_get_error_message")?,
+        module_create_from_memory: lookup(
+          handle,
+          "lto_module_create_from_memory",
+        )?,
+        module_dispose: lookup(handle, "lto_module_dispose")?,
+        module_get_target_triple: lookup(
+          handle,
+          "lto_module_get_target_triple",
+        )?,
+        module_get_num_symbols: lookup(handle, "lto_module_get_num_symbols")?,
+        module_get_symbol_name: lookup(handle, "lto_module_get_symbol_name")?,
+        module_get_symbol_attribute: lookup(
+          handle,
+          "lto_module_get_symbol_attribute",
+        )?,
+        codegen_create: lookup(handle, "lto_codegen_create")?,
+        codegen_dispose: lookup(handle, "lto_codegen_dispose")?,
+        codegen_add_module: lookup(handle, "lto_codegen_add_module")?,
+        codegen_set_pic_model: lookup(handle, "lto_codegen_set_pic_model")?,
+        codegen_set_debug_model: lookup(
+          handle,
+          "lto_codegen_set_debug_model",
+        )?,
+        codegen_add_must_preserve_symbol: lookup(
+          handle,
+          "lto_codegen_add_must_preserve_symbol",
+        )?,
+        codegen_compile: lookup(handle, "lto_codegen_compile")?,
+      };
+      Ok(LibLto { api: api })
+    }
+  }
+
+  fn error_message(&self) -> String {
+    c_string(unsafe { (self.api.get_error_message)() })
+  }
+}
+
+// One bitcode input, parsed by libLTO.
+pub struct BitcodeModule<'a> {
+  lib: &'a LibLto,
+  module: LtoModule,
+  pub path: PathBuf,
+}
+
+impl<'a> Drop for BitcodeModule<'a> {
+  fn drop(&mut self) {
+    unsafe { (self.lib.api.module_dispose)(self.module) }
+  }
+}
+
+impl<'a> BitcodeModule<'a> {
+  pub fn parse(
+    lib: &'a LibLto,
+    path: &Path,
+    data: &[u8],
+    opts: &LinkOptions,
+  ) -> Result<BitcodeModule<'a>> {
+    let module = unsafe {
+      (lib.api.module_create_from_memory)(
+        data.as_ptr() as *const c_void,
+        data.len() as size_t,
+      )
+    };
+    if module.is_null() {
+      return Err(LtoError::BadModule(path.to_path_buf(), lib.error_message()));
+    }
+    let module = BitcodeModule {
+      lib: lib,
+      module: module,
+      path: path.to_path_buf(),
+    };
+    let triple = c_string(unsafe {
+      (lib.api.module_get_target_triple)(module.module)
+    });
+    check_triple(path, triple, opts)?;
+    Ok(module)
+  }
+
+  // The module's external symbols, for resolution against the other inputs.
+  pub fn symbols(&self) -> Vec<InputSymbol> {
+    let api = &self.lib.api;
+    let count = unsafe { (api.module_get_num_symbols)(self.module) };
+    let mut symbols: Vec<InputSymbol> = Vec::new();
+    for i in 0..count {
+      let attrs = unsafe { (api.module_get_symbol_attribute)(self.module, i) };
+      let scope = attrs & LTO_SYMBOL_SCOPE_MASK;
+      if scope == LTO_SYMBOL_SCOPE_INTERNAL {
+        continue;
+      }
+      let kind = match attrs & LTO_SYMBOL_DEFINITION_MASK {
+        LTO_SYMBOL_DEFINITION_UNDEFINED | LTO_SYMBOL_DEFINITION_WEAKUNDEF => {
+          SymbolKind::Undefined
+        }
+        LTO_SYMBOL_DEFINITION_WEAK => SymbolKind::WeakDefined,
+        _ => SymbolKind::Defined,
+      };
+      symbols.push(InputSymbol {
+        name: c_string(unsafe { (api.module_get_symbol_name)(self.module, i) }),
+        kind: kind,
+        private_extern: scope == LTO_SYMBOL_SCOPE_HIDDEN,
+        value: 0,
+      });
+    }
+    symbols
+  }
+}
+
+// A module's target triple, e.g. "arm64-apple-macosx11.0.0", has to be for
+// -arch.
+pub fn check_triple(
+  path: &Path,
+  triple: String,
+  opts: &LinkOptions,
+) -> Result<()> {
+  if triple.split('-').next() != Some(opts.arch.name()) {
+    return Err(LtoError::WrongArch(path.to_path_buf(), triple));
+  }
+  Ok(())
+}
+
+// Whether a symbol defined in bitcode has to be kept by the optimizer:
+// native code refers to it, or it's visible outside the output. Relocatable
+// outputs keep hidden symbols too, as the objects they end up linked with
+// may refer to them.
+pub fn must_preserve(
+  sym: &InputSymbol,
+  opts: &LinkOptions,
+  native_references: &HashSet<String>,
+) -> bool {
+  if native_references.contains(&sym.name) {
+    return true;
+  }
+  match opts.output_kind {
+    OutputKind::Relocatable => true,
+    OutputKind::Dylib | OutputKind::Bundle | OutputKind::KextBundle => {
+      !sym.private_extern
+    }
+    OutputKind::Executable | OutputKind::Preload => sym.name == "_main",
+  }
+}
+
+// Optimize and compile the modules into one Mach-O object. It's also written
+// to -object_path_lto, where the debug map of the output can point to it.
+pub fn compile(
+  lib: &LibLto,
+  modules: &[BitcodeModule],
+  opts: &LinkOptions,
+  native_references: &HashSet<String>,
+) -> Result<Vec<u8>> {
+  let api = &lib.api;
+  let codegen = unsafe { (api.codegen_create)() };
+  if codegen.is_null() {
+    return Err(LtoError::CodegenFailed(lib.error_message()));
+  }
+  let result = (|| {
+    for module in modules.iter() {
+      if unsafe { (api.codegen_add_module)(codegen, module.module) } {
+        return Err(LtoError::BadModule(
+          module.path.clone(),
+          lib.error_message(),
+        ));
+      }
+      for sym in module.symbols().iter() {
+        let defined = sym.kind != SymbolKind::Undefined;
+        if defined && must_preserve(sym, opts, native_references) {
+          let name = CString::new(sym.name.clone()).unwrap();
+          unsafe {
+            (api.codegen_add_must_preserve_symbol)(codegen, name.as_ptr())
+          };
+        }
+      }
+    }
+    let pic_model =
+      if opts.output_kind == OutputKind::Executable && !opts.use_pie() {
+        LTO_CODEGEN_PIC_MODEL_DYNAMIC_NO_PIC
+      } else {
+        LTO_CODEGEN_PIC_MODEL_DYNAMIC
+      };
+    unsafe {
+      (api.codegen_set_pic_model)(codegen, pic_model);
+      (api.codegen_set_debug_model)(codegen, LTO_DEBUG_MODEL_DWARF);
+    }
+    let mut len: size_t = 0;
+    let data = unsafe { (api.codegen_compile)(codegen, &mut len) };
+    if data.is_null() {
+      return Err(LtoError::CodegenFailed(lib.error_message()));
+    }
+    // Owned by the code generator, so copy it before disposing of that.
+    Ok(unsafe { slice::from_raw_parts(data as *const u8, len) }.to_vec())
+  })();
+  unsafe { (api.codegen_dispose)(codegen) };
+  let object = result?;
+
+  if let Some(ref path) = opts.object_path_lto {
+    File::create(path)
+      .and_then(|mut f| f.write_all(&object))
+      .map_err(|e| LtoError::IoError(path.clone(), e))?;
+  }
+  Ok(object)
+}
+
+// Where the LTO object appears in diagnostics.
+pub fn object_path(opts: &LinkOptions) -> PathBuf {
+  opts
+    .object_path_lto
+    .clone()
+    .unwrap_or_else(|| PathBuf::from("<lto>"))
+}
+
@@ -1,52 +1,92 @@
-extern crate bfd_sys;
-
-mod bfd;
+extern crate mold;
 
 use std::env;
-use std::ffi::CString;
-use std::fs::File;
-use std::path::Path;
-use std::os::raw::c_char;
+use std::fmt::Debug;
+use std::process;
 
-fn main() {
-  // eprintln!("{:?}", bfd::get_all_targets());
-  // let argv: Vec<String> = env::args().collect();
-  // let in_path = Path::new(&argv[1]);
-  // eprintln!("in_path: {:?}", in_path);
-  // let out_path = Path::new(&argv[2]);
-  // eprintln!("out_path: {:?}", out_path);
-  // let res = bfd::make_executable(in_path, out_path).unwrap();
-  // eprintln!("res: {:?}", res);
+use mold::diagnostics::{Diagnostic, Format};
+use mold::{args, build_version, cc_flavor, codesign, daemon, diagnostics,
+           dyld_check, edit, inspect, link_job, lipo, size, strip, symbols,
+           LinkError};
 
-  let mut abfd: &mut bfd_sys::bfd;
-  unsafe {
-    abfd = &mut *bfd_sys::bfd_openw(
-      CString::new("foo").unwrap().as_ptr(),
-      CString::new("mach-o-x86-64").unwrap().as_ptr(),
-    );
-    bfd_sys::bfd_set_format(&mut *abfd, bfd_sys::bfd_format_bfd_object);
+fn main() {
+  // The subcommands for working with Mach-O files rather than linking.
+  let tool = env::args().nth(1).unwrap_or_default();
+  let args: Vec<String> = env::args().skip(2).collect();
+  match tool.as_str() {
+    "strip" => return exit_on_error(strip::run_command(&args)),
+    "inspect" => return exit_on_error(inspect::run_command(&args)),
+    "symbols" => return exit_on_error(symbols::run_command(&args)),
+    "size" => return exit_on_error(size::run_command(&args)),
+    "fat" => return exit_on_error(lipo::run_command(&args)),
+    "edit" => return exit_on_error(edit::run_command(&args)),
+    "codesign" => return exit_on_error(codesign::run_command(&args)),
+    "dyld-check" => return exit_on_error(dyld_check::run_command(&args)),
+    "build-version" => {
+      return exit_on_error(build_version::run_command(&args))
+    }
+    "--daemon" => return exit_on_error(daemon::run_command(&args)),
+    _ => (),
   }
+  let link_args: Vec<String> = if tool == "--flavor=cc" {
+    match cc_flavor::translate(args) {
+      Ok(args) => args,
+      Err(e) => {
+        diagnostics::report(LinkError::Args(e).diagnostic());
+        process::exit(1);
+      }
+    }
+  } else {
+    env::args().skip(1).collect()
+  };
 
-  let mut new: &mut bfd_sys::asymbol;
-  unsafe {
-    new = &mut *bfd_sys::_bfd_generic_make_empty_symbol(&mut *abfd);
+  // Hold on to warnings about the options until we know how to print them.
+  let (opts, warnings) = diagnostics::capture(|| {
+    args::parse_args(link_args.iter().cloned())
+  });
+  let opts = match opts {
+    Ok(opts) => opts,
+    Err(e) => {
+      let json = link_args.iter().any(|arg| arg == "--diagnostics-format=json");
+      if json {
+        diagnostics::set_format(Format::Json);
+      }
+      report_all(warnings);
+      let err = LinkError::Args(e);
+      diagnostics::report(err.diagnostic());
+      process::exit(1);
+    }
+  };
+  diagnostics::set_format(opts.diagnostics_format);
+  if let Some(ref socket) = opts.daemon_socket {
+    // The daemon parses the options again, and reports the same warnings.
+    match daemon::forward(socket, &link_args) {
+      Ok(true) => return,
+      Ok(false) => process::exit(1),
+      Err(e) => diagnostics::warning(&format!(
+        "unable to reach the daemon at {} ({}); linking without it",
+        socket.display(),
+        e
+      )),
+    }
   }
-  new.name = CString::new("dummy symbol").unwrap().as_ptr();
-  unsafe {
-    new.section = bfd_sys::bfd_make_section_old_way(
-      &mut *abfd,
-      CString::new(".text").unwrap().as_ptr(),
-    );
+  report_all(warnings);
+
+  if let Err(e) = link_job::run(&opts) {
+    diagnostics::report(e.diagnostic());
+    process::exit(1);
   }
-  new.flags = bfd_sys::BSF_GLOBAL;
-  new.value = 0x12345;
+}
 
-  let new_ptr: *mut bfd_sys::asymbol = &mut *new;
-  let nptr: *mut bfd_sys::asymbol = std::ptr::null_mut();
-  let mut ptrs: &mut [*mut bfd_sys::asymbol] = &mut [new_ptr, nptr];
+fn report_all(warnings: Vec<Diagnostic>) {
+  for diagnostic in warnings.into_iter() {
+    diagnostics::report(diagnostic);
+  }
+}
 
-  unsafe {
-    bfd_sys::bfd_set_symtab(&mut *abfd, ptrs.as_mut_ptr(), 1);
-    bfd_sys::bfd_close(&mut *abfd);
+fn exit_on_error<E: Debug>(result: Result<(), E>) {
+  if let Err(e) = result {
+    diagnostics::error(&format!("{:?}", e));
+    process::exit(1);
   }
 }
@@ -0,0 +1,153 @@
+// --skip-unchanged: don't relink when nothing has changed. After a link, a
+// digest of the options, of every file the link read (as it recorded them
+// for -dependency_info) and of the output is saved next to the output. The
+// next link is skipped if all of those are the same, and the paths it
+// looked for without finding still aren't there.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use args::LinkOptions;
+use dependency_info::DependencyInfo;
+use incremental::{from_hex, options_digest, to_hex};
+use uuid::md5;
+
+const STAMP_HEADER: &str = "mold-null-link 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stamp {
+  pub options: [u8; 16],
+  // Every input read, with its digest, or None if it couldn't be read or
+  // may have changed while the link was reading it.
+  pub inputs: Vec<(PathBuf, Option<[u8; 16]>)>,
+  // Paths searched for libraries without finding them.
+  pub missing: Vec<PathBuf>,
+  pub output: Option<[u8; 16]>,
+}
+
+pub fn stamp_path(output: &Path) -> PathBuf {
+  let mut name = output.as_os_str().to_os_string();
+  name.push(".linkstamp");
+  PathBuf::from(name)
+}
+
+fn digest(path: &Path) -> Option<[u8; 16]> {
+  fs::read(path).ok().map(|data| md5(&data))
+}
+
+// The digest of `path` as the link which started at `started` read it, as
+// far as can be told afterwards.
+fn digest_read_since(path: &Path, started: SystemTime) -> Option<[u8; 16]> {
+  let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+  if modified >= started {
+    return None;
+  }
+  digest(path)
+}
+
+impl Stamp {
+  // What the link with `opts`, which started at `started`, depended on:
+  // what it recorded reading in `info`. The output's digest is filled in
+  // by `finish`.
+  pub fn compute(
+    opts: &LinkOptions,
+    info: &DependencyInfo,
+    started: SystemTime,
+  ) -> Stamp {
+    Stamp {
+      options: options_digest(opts),
+      inputs: info
+        .inputs
+        .iter()
+        .map(|path| (path.clone(), digest_read_since(path, started)))
+        .collect(),
+      missing: info.missing.iter().cloned().collect(),
+      output: None,
+    }
+  }
+
+  // The stamp saved by the last successful link to `output`.
+  pub fn load(output: &Path) -> Option<Stamp> {
+    let file = File::open(stamp_path(output)).ok()?;
+    let mut lines = BufReader::new(file).lines();
+    if lines.next()?.ok()? != STAMP_HEADER {
+      return None;
+    }
+    let options = lines.next()?.ok()?;
+    let output_digest = lines.next()?.ok()?;
+    let mut stamp = Stamp {
+      options: from_hex(options.trim_start_matches("options "))?,
+      inputs: Vec::new(),
+      missing: Vec::new(),
+      output: Some(from_hex(output_digest.trim_start_matches("output "))?),
+    };
+    for line in lines {
+      // input <digest or -> <path>, or missing <path>
+      let line = line.ok()?;
+      let mut fields = line.splitn(2, ' ');
+      match fields.next()? {
+        "input" => {
+          let mut fields = fields.next()?.splitn(2, ' ');
+          let digest = match fields.next()? {
+            "-" => None,
+            hex => Some(from_hex(hex)?),
+          };
+          stamp.inputs.push((PathBuf::from(fields.next()?), digest));
+        }
+        "missing" => stamp.missing.push(PathBuf::from(fields.next()?)),
+        _ => return None,
+      }
+    }
+    Some(stamp)
+  }
+
+  pub fn save(&self, output: &Path) -> io::Result<()> {
+    let output_digest = self.output.as_ref().map_or(String::new(), to_hex);
+    let mut text = format!(
+      "{}\noptions {}\noutput {}\n",
+      STAMP_HEADER,
+      to_hex(&self.options),
+      output_digest
+    );
+    for &(ref path, ref digest) in self.inputs.iter() {
+      let digest = digest.as_ref().map_or("-".to_string(), to_hex);
+      text.push_str(&format!("input {} {}\n", digest, path.display()));
+    }
+    for path in self.missing.iter() {
+      text.push_str(&format!("missing {}\n", path.display()));
+    }
+    File::create(stamp_path(output))?.write_all(text.as_bytes())
+  }
+
+  // Whether relinking with `opts` would read the same files as the link
+  // which saved this stamp, and its output hasn't been changed or removed
+  // since.
+  pub fn is_current(&self, opts: &LinkOptions, output: &Path) -> bool {
+    self.options == options_digest(opts)
+      && self.inputs.iter().all(|&(ref path, ref previous)| {
+        previous.is_some() && digest(path) == *previous
+      })
+      && self.missing.iter().all(|path| !path.exists())
+      && self.output.is_some()
+      && digest(output) == self.output
+  }
+
+  // What the link which saved this stamp read, for -dependency_info when
+  // it's skipped.
+  pub fn dependency_info(&self, output: &Path) -> DependencyInfo {
+    DependencyInfo {
+      inputs: self.inputs.iter().map(|&(ref path, _)| path.clone()).collect(),
+      missing: self.missing.iter().cloned().collect(),
+      output: output.to_path_buf(),
+      skipped: true,
+    }
+  }
+
+  // Record the output the link wrote, and save the stamp for next time.
+  pub fn finish(mut self, output: &Path) -> io::Result<()> {
+    self.output = Some(md5(&fs::read(output)?));
+    self.save(output)
+  }
+}
@@ -0,0 +1,464 @@
+// Objective-C support: merging each input's __objc_imageinfo into one,
+// recognizing archive members with classes or categories for -ObjC, and
+// merging categories into their classes.
+//
+// Categories don't define any symbols, so a member which only adds
+// categories to a class from elsewhere is never loaded to resolve an
+// undefined symbol. -ObjC loads every member with ObjC code instead.
+//
+// A category on a class defined in the same input can have its method,
+// protocol and property lists joined onto the class's ahead of time, so the
+// runtime has nothing to attach at launch. Categories with +load or class
+// properties, and lists in the relative method list format, are left to
+// the runtime.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use dead_strip;
+use macho::object_file::{ObjectFile, ObjectSection};
+use macho::reloc::RelocationInfo;
+use macho::symtab::{N_SECT, N_TYPE};
+use macho::{get_u32, get_u64, put_u32, put_u64, round_up, set_u32, set_u64,
+            Arch, S_REGULAR};
+use relocatable::RelocatableInput;
+
+// struct objc_image_info { uint32_t version; uint32_t flags; }
+const IMAGE_INFO_SIZE: usize = 8;
+
+const OBJC_IMAGE_SUPPORTS_GC: u32 = 1 << 1;
+const OBJC_IMAGE_REQUIRES_GC: u32 = 1 << 2;
+// Only set in the output if every input has it.
+const OBJC_IMAGE_HAS_CATEGORY_CLASS_PROPERTIES: u32 = 1 << 6;
+const SWIFT_VERSION_SHIFT: u32 = 8;
+const SWIFT_VERSION_MASK: u32 = 0xff << SWIFT_VERSION_SHIFT;
+
+const CLASS_PREFIX: &'static str = "_OBJC_CLASS_$_";
+
+const POINTER_SIZE: usize = 8;
+// X86_64_RELOC_UNSIGNED and ARM64_RELOC_UNSIGNED.
+const RELOC_UNSIGNED: u8 = 0;
+// Fields of category_t.
+const CATEGORY_CLS: usize = 8;
+const CATEGORY_INSTANCE_METHODS: usize = 16;
+const CATEGORY_CLASS_METHODS: usize = 24;
+const CATEGORY_PROTOCOLS: usize = 32;
+const CATEGORY_INSTANCE_PROPERTIES: usize = 40;
+const CATEGORY_CLASS_PROPERTIES: usize = 48;
+// Fields of class_t and class_ro_t.
+const CLASS_ISA: usize = 0;
+const CLASS_DATA: usize = 32;
+const RO_BASE_METHODS: usize = 32;
+const RO_BASE_PROTOCOLS: usize = 40;
+const RO_BASE_PROPERTIES: usize = 64;
+// Every kind of list starts with 8 bytes of header.
+const LIST_HEADER_SIZE: usize = 8;
+
+#[derive(Debug)]
+pub enum ObjcError {
+  // An __objc_imageinfo section of the wrong size.
+  Malformed(PathBuf),
+  // An input whose garbage collection flags differ from the first input's.
+  GcMismatch(PathBuf),
+  // (path, its Swift version, the version of earlier inputs)
+  SwiftVersionMismatch(PathBuf, u32, u32),
+}
+
+pub type Result<T> = ::std::result::Result<T, ObjcError>;
+
+fn is_image_info(sect: &ObjectSection) -> bool {
+  match (sect.segname.as_str(), sect.sectname.as_str()) {
+    ("__DATA", "__objc_imageinfo")
+    | ("__DATA_CONST", "__objc_imageinfo")
+    | ("__OBJC", "__image_info") => true,
+    _ => false,
+  }
+}
+
+// Whether -ObjC should load an archive member.
+pub fn defines_objc(object: &ObjectFile) -> bool {
+  object.sections.iter().any(|sect| {
+    sect.sectname == "__objc_catlist" || sect.sectname == "__objc_classlist"
+  })
+    || object.symbols.iter().any(|sym| {
+      sym.is_external() && !sym.is_undefined()
+        && sym.name.starts_with(CLASS_PREFIX)
+    })
+}
+
+fn merge_flags(merged: u32, flags: u32) -> u32 {
+  let swift = if merged & SWIFT_VERSION_MASK != 0 {
+    merged & SWIFT_VERSION_MASK
+  } else {
+    flags & SWIFT_VERSION_MASK
+  };
+  let category_class_properties =
+    merged & flags & OBJC_IMAGE_HAS_CATEGORY_CLASS_PROPERTIES;
+  let rest = (merged | flags)
+    & !(SWIFT_VERSION_MASK | OBJC_IMAGE_HAS_CATEGORY_CLASS_PROPERTIES);
+  rest | swift | category_class_properties
+}
+
+// Leave a single image info, in the first input which had one, with the
+// flags of every input combined.
+pub fn merge_image_info(
+  arch: Arch,
+  inputs: &mut Vec<RelocatableInput>,
+) -> Result<()> {
+  let mut found: Vec<(usize, usize, u32)> = Vec::new();
+  for (i, input) in inputs.iter().enumerate() {
+    for (j, sect) in input.object.sections.iter().enumerate() {
+      if !is_image_info(sect) {
+        continue;
+      }
+      let flags = match get_u32(&sect.contents, 4, "objc_image_info") {
+        Ok(flags) if sect.contents.len() == IMAGE_INFO_SIZE => flags,
+        _ => return Err(ObjcError::Malformed(input.path.clone())),
+      };
+      found.push((i, j, flags));
+    }
+  }
+  let (first, first_sect, first_flags) = match found.first() {
+    Some(&first) => first,
+    None => return Ok(()),
+  };
+
+  let gc = first_flags & (OBJC_IMAGE_SUPPORTS_GC | OBJC_IMAGE_REQUIRES_GC);
+  let mut merged = first_flags;
+  for &(i, _, flags) in found.iter().skip(1) {
+    let path = &inputs[i].path;
+    if flags & (OBJC_IMAGE_SUPPORTS_GC | OBJC_IMAGE_REQUIRES_GC) != gc {
+      return Err(ObjcError::GcMismatch(path.clone()));
+    }
+    let swift = flags & SWIFT_VERSION_MASK;
+    let merged_swift = merged & SWIFT_VERSION_MASK;
+    if swift != 0 && merged_swift != 0 && swift != merged_swift {
+      return Err(ObjcError::SwiftVersionMismatch(
+        path.clone(),
+        swift >> SWIFT_VERSION_SHIFT,
+        merged_swift >> SWIFT_VERSION_SHIFT,
+      ));
+    }
+    merged = merge_flags(merged, flags);
+  }
+
+  set_u32(
+    &mut inputs[first].object.sections[first_sect].contents,
+    4,
+    merged,
+  );
+  for i in 0..inputs.len() {
+    let live: Vec<bool> = inputs[i]
+      .object
+      .sections
+      .iter()
+      .enumerate()
+      .map(|(j, sect)| !is_image_info(sect) || (i, j) == (first, first_sect))
+      .collect();
+    if live.iter().any(|&is_live| !is_live) {
+      inputs[i] = dead_strip::strip(arch, &inputs[i], &live);
+    }
+  }
+  Ok(())
+}
+
+// (section index, offset in the section) in an input.
+type Location = (usize, usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ListKind {
+  // method_list_t and property_list_t: a u32 entry size, then a u32 count.
+  Methods,
+  Properties,
+  // protocol_list_t: a u64 count of pointers.
+  Protocols,
+}
+
+impl ListKind {
+  fn entry_size(&self) -> usize {
+    match *self {
+      ListKind::Methods => 24,
+      ListKind::Properties => 16,
+      ListKind::Protocols => POINTER_SIZE,
+    }
+  }
+
+  // The number of entries in the list at `offset`, if we can join it.
+  fn count(&self, data: &[u8], offset: usize) -> Option<usize> {
+    let count = match *self {
+      ListKind::Protocols => get_u64(data, offset, "protocol_list_t").ok()?,
+      _ => {
+        // Anything else in the entry size word is a flag, such as the one
+        // for relative method lists.
+        let entsize = get_u32(data, offset, "list entsize").ok()?;
+        if entsize as usize != self.entry_size() {
+          return None;
+        }
+        get_u32(data, offset + 4, "list count").ok()? as u64
+      }
+    };
+    let end = offset + LIST_HEADER_SIZE + count as usize * self.entry_size();
+    if end <= data.len() {
+      Some(count as usize)
+    } else {
+      None
+    }
+  }
+
+  fn put_header(&self, buf: &mut Vec<u8>, count: usize) {
+    match *self {
+      ListKind::Protocols => put_u64(buf, count as u64),
+      _ => {
+        put_u32(buf, self.entry_size() as u32);
+        put_u32(buf, count as u32);
+      }
+    }
+  }
+}
+
+fn relocs_at(sect: &ObjectSection, offset: usize) -> Vec<&RelocationInfo> {
+  sect
+    .relocs
+    .iter()
+    .filter(|reloc| reloc.address as usize == offset)
+    .collect()
+}
+
+// Whether the pointer at `at` is null: zero, with nothing relocating it.
+fn is_null(object: &ObjectFile, at: Location) -> bool {
+  let sect = &object.sections[at.0];
+  relocs_at(sect, at.1).is_empty()
+    && get_u64(&sect.contents, at.1, "pointer").ok() == Some(0)
+}
+
+// Where the pointer at `at` points, if it's a plain pointer into one of
+// the object's sections.
+fn pointer_target(object: &ObjectFile, at: Location) -> Option<Location> {
+  let sect = object.sections.get(at.0)?;
+  let relocs = relocs_at(sect, at.1);
+  let reloc = match relocs.as_slice() {
+    [reloc] => *reloc,
+    _ => return None,
+  };
+  if reloc.kind != RELOC_UNSIGNED || reloc.length != 3 || reloc.pcrel {
+    return None;
+  }
+  let value = get_u64(&sect.contents, at.1, "pointer").ok()?;
+  // External relocations store an addend; the others, the address.
+  let (target, addr) = if reloc.external {
+    let sym = object.symbols.get(reloc.symbolnum as usize)?;
+    if sym.is_stab() || sym.n_type & N_TYPE != N_SECT || sym.n_sect == 0 {
+      return None;
+    }
+    (sym.n_sect as usize - 1, sym.n_value.wrapping_add(value))
+  } else if reloc.symbolnum > 0 {
+    (reloc.symbolnum as usize - 1, value)
+  } else {
+    return None;
+  };
+  let target_sect = object.sections.get(target)?;
+  let offset = addr.checked_sub(target_sect.addr)? as usize;
+  if offset < target_sect.contents.len() {
+    Some((target, offset))
+  } else {
+    None
+  }
+}
+
+// A list joining the entries of `lists`, in order, with the relocations
+// for them relative to the start of the new list.
+fn join_lists(
+  object: &ObjectFile,
+  kind: ListKind,
+  lists: &[(Location, usize)],
+) -> (Vec<u8>, Vec<RelocationInfo>) {
+  let mut contents: Vec<u8> = Vec::new();
+  let mut relocs: Vec<RelocationInfo> = Vec::new();
+  let total = lists.iter().map(|&(_, count)| count).sum();
+  kind.put_header(&mut contents, total);
+  for &((sect, offset), count) in lists.iter() {
+    let sect = &object.sections[sect];
+    let begin = offset + LIST_HEADER_SIZE;
+    let end = begin + count * kind.entry_size();
+    for reloc in sect.relocs.iter() {
+      let address = reloc.address as usize;
+      if address >= begin && address < end {
+        relocs.push(RelocationInfo {
+          address: (contents.len() + address - begin) as u32,
+          ..*reloc
+        });
+      }
+    }
+    contents.extend_from_slice(&sect.contents[begin..end]);
+  }
+  (contents, relocs)
+}
+
+// One list to replace: the class_ro_t field to point at the new one, and
+// the lists to join, category first.
+struct Join {
+  field: Location,
+  kind: ListKind,
+  lists: Vec<(Location, usize)>,
+}
+
+// What merging the category at `category` would take, or None if it can't
+// be merged.
+fn plan_merge(object: &ObjectFile, category: Location) -> Option<Vec<Join>> {
+  let field = |loc: Location, offset: usize| (loc.0, loc.1 + offset);
+  if !is_null(object, field(category, CATEGORY_CLASS_PROPERTIES)) {
+    return None;
+  }
+  let class = pointer_target(object, field(category, CATEGORY_CLS))?;
+  let ro = pointer_target(object, field(class, CLASS_DATA));
+  let meta_ro = pointer_target(object, field(class, CLASS_ISA))
+    .and_then(|meta| pointer_target(object, field(meta, CLASS_DATA)));
+  let fields = [
+    (CATEGORY_INSTANCE_METHODS, ro, RO_BASE_METHODS, ListKind::Methods),
+    (CATEGORY_CLASS_METHODS, meta_ro, RO_BASE_METHODS, ListKind::Methods),
+    (CATEGORY_PROTOCOLS, ro, RO_BASE_PROTOCOLS, ListKind::Protocols),
+    (
+      CATEGORY_INSTANCE_PROPERTIES,
+      ro,
+      RO_BASE_PROPERTIES,
+      ListKind::Properties,
+    ),
+  ];
+  let mut joins: Vec<Join> = Vec::new();
+  for &(category_field, ro, ro_field, kind) in fields.iter() {
+    let slot = field(category, category_field);
+    if is_null(object, slot) {
+      continue;
+    }
+    let ro_slot = field(ro?, ro_field);
+    let mut lists: Vec<Location> = vec![pointer_target(object, slot)?];
+    if !is_null(object, ro_slot) {
+      lists.push(pointer_target(object, ro_slot)?);
+    }
+    let mut counted: Vec<(Location, usize)> = Vec::new();
+    for &list in lists.iter() {
+      let count = kind.count(&object.sections[list.0].contents, list.1)?;
+      counted.push((list, count));
+    }
+    joins.push(Join {
+      field: ro_slot,
+      kind: kind,
+      lists: counted,
+    });
+  }
+  Some(joins)
+}
+
+fn list_entries(object: &ObjectFile, sectname: &str) -> Vec<Location> {
+  let sect = match object.sections.iter().position(|s| s.sectname == sectname)
+  {
+    Some(sect) => sect,
+    None => return Vec::new(),
+  };
+  let count = object.sections[sect].contents.len() / POINTER_SIZE;
+  (0..count)
+    .filter_map(|i| pointer_target(object, (sect, i * POINTER_SIZE)))
+    .collect()
+}
+
+// Merge the categories in `input` on classes it defines. The joined lists
+// go in a new __objc_const section; the old ones are left unused.
+pub fn merge_categories(input: &mut RelocatableInput) {
+  let object = &mut input.object;
+  let catlist =
+    match object.sections.iter().position(|s| s.sectname == "__objc_catlist")
+    {
+      Some(catlist) => catlist,
+      None => return,
+    };
+  // The runtime has to call +load on these.
+  let nonlazy: HashSet<Location> =
+    list_entries(object, "__objc_nlcatlist").into_iter().collect();
+
+  let end = object
+    .sections
+    .iter()
+    .map(|sect| sect.addr + sect.size)
+    .max()
+    .unwrap_or(0);
+  let new_sect = object.sections.len();
+  object.sections.push(ObjectSection {
+    segname: "__DATA".to_string(),
+    sectname: "__objc_const".to_string(),
+    addr: round_up(end, POINTER_SIZE as u64),
+    align: 3,
+    flags: S_REGULAR,
+    ..Default::default()
+  });
+
+  let mut merged: Vec<usize> = Vec::new();
+  let count = object.sections[catlist].contents.len() / POINTER_SIZE;
+  for i in 0..count {
+    let entry = i * POINTER_SIZE;
+    let joins = match pointer_target(object, (catlist, entry)) {
+      Some(category) if !nonlazy.contains(&category) => {
+        plan_merge(object, category)
+      }
+      _ => None,
+    };
+    let joins = match joins {
+      Some(joins) => joins,
+      None => continue,
+    };
+    for join in joins.iter() {
+      let (contents, relocs) = join_lists(object, join.kind, &join.lists);
+      let out = &mut object.sections[new_sect];
+      let start = round_up(out.contents.len() as u64, 8) as usize;
+      out.contents.resize(start, 0);
+      out.relocs.extend(relocs.into_iter().map(|reloc| RelocationInfo {
+        address: reloc.address + start as u32,
+        ..reloc
+      }));
+      out.contents.extend_from_slice(&contents);
+      out.size = out.contents.len() as u64;
+      let addr = out.addr + start as u64;
+
+      let (sect, offset) = join.field;
+      let sect = &mut object.sections[sect];
+      sect.relocs.retain(|reloc| reloc.address as usize != offset);
+      sect.relocs.push(RelocationInfo {
+        address: offset as u32,
+        symbolnum: new_sect as u32 + 1,
+        pcrel: false,
+        length: 3,
+        external: false,
+        kind: RELOC_UNSIGNED,
+        scattered: None,
+      });
+      set_u64(&mut sect.contents, offset, addr);
+    }
+    merged.push(entry);
+  }
+  if merged.is_empty() {
+    object.sections.pop();
+    return;
+  }
+
+  // Take the merged categories out of __objc_catlist.
+  let catlist_addr = object.sections[catlist].addr;
+  for &entry in merged.iter().rev() {
+    let sect = &mut object.sections[catlist];
+    sect.contents.drain(entry..(entry + POINTER_SIZE));
+    sect.size -= POINTER_SIZE as u64;
+    sect.relocs.retain(|reloc| reloc.address as usize != entry);
+    for reloc in sect.relocs.iter_mut() {
+      if reloc.address as usize > entry {
+        reloc.address -= POINTER_SIZE as u32;
+      }
+    }
+    let entry_addr = catlist_addr + entry as u64;
+    for sym in object.symbols.iter_mut() {
+      let in_catlist = !sym.is_stab()
+        && sym.n_type & N_TYPE == N_SECT
+        && sym.n_sect as usize == catlist + 1;
+      if in_catlist && sym.n_value > entry_addr {
+        sym.n_value -= POINTER_SIZE as u64;
+      }
+    }
+  }
+}
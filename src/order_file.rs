@@ -0,0 +1,199 @@
+// ld64-style order files (-order_file), listing symbols to place first in
+// their output sections, in the given order.
+//
+// Each line is a symbol name, optionally qualified with an architecture
+// and/or the object file it should come from:
+//
+//   _main
+//   x86_64:_only_on_intel
+//   foo.o:_static_function_in_foo
+//   libbar.a(baz.o):_from_an_archive_member
+//
+// Anything after a '#' is a comment.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use dependency_info;
+use diagnostics;
+use macho::Arch;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderEntry {
+  pub object: Option<String>,
+  pub symbol: String,
+  pub line: usize,
+}
+
+impl OrderEntry {
+  fn matches_object(&self, object_path: &str) -> bool {
+    match self.object {
+      None => true,
+      Some(ref qualifier) => {
+        object_path == qualifier
+          || object_path.ends_with(&format!("/{}", qualifier))
+      }
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct OrderFile {
+  pub entries: Vec<OrderEntry>,
+  by_symbol: HashMap<String, Vec<usize>>,
+}
+
+fn strip_arch_prefix(line: &str, arch: Arch) -> Option<&str> {
+  if let Some(idx) = line.find(':') {
+    if let Some(prefix_arch) = Arch::from_name(&line[..idx]) {
+      return if prefix_arch == arch {
+        Some(&line[(idx + 1)..])
+      } else {
+        None
+      };
+    }
+  }
+  Some(line)
+}
+
+// Split off an object file qualifier. Objective-C method names contain
+// colons too, so only a colon right after a ".o" (or ".o)" for archive
+// members) counts.
+fn split_object(line: &str) -> (Option<String>, String) {
+  for marker in [".o):", ".o:"].iter() {
+    if let Some(idx) = line.find(marker) {
+      let split = idx + marker.len() - 1;
+      return (
+        Some(line[..split].to_string()),
+        line[(split + 1)..].to_string(),
+      );
+    }
+  }
+  (None, line.to_string())
+}
+
+impl OrderFile {
+  // Parse an order file, dropping the entries for other architectures.
+  pub fn parse(contents: &str, arch: Arch) -> Self {
+    let mut order = OrderFile::default();
+    for (i, raw_line) in contents.lines().enumerate() {
+      let line = match raw_line.find('#') {
+        Some(idx) => &raw_line[..idx],
+        None => raw_line,
+      }.trim();
+      if line.is_empty() {
+        continue;
+      }
+      let line = match strip_arch_prefix(line, arch) {
+        Some(rest) => rest,
+        None => continue,
+      };
+      let (object, symbol) = split_object(line);
+      order.push(OrderEntry {
+        object: object,
+        symbol: symbol,
+        line: i + 1,
+      });
+    }
+    order
+  }
+
+  // An order of unqualified symbols, as derived from a profile.
+  pub fn from_symbols(symbols: &[String]) -> Self {
+    let mut order = OrderFile::default();
+    for (i, symbol) in symbols.iter().enumerate() {
+      order.push(OrderEntry {
+        object: None,
+        symbol: symbol.clone(),
+        line: i + 1,
+      });
+    }
+    order
+  }
+
+  fn push(&mut self, entry: OrderEntry) {
+    let idx = self.entries.len();
+    self
+      .by_symbol
+      .entry(entry.symbol.clone())
+      .or_insert_with(Vec::new)
+      .push(idx);
+    self.entries.push(entry);
+  }
+
+  // Place `other`'s symbols after this order's, leaving out the ones it
+  // already places.
+  pub fn append(&mut self, other: &OrderFile) {
+    for entry in other.entries.iter() {
+      if !self.by_symbol.contains_key(&entry.symbol) {
+        self.push(entry.clone());
+      }
+    }
+  }
+
+  pub fn read(path: &Path, arch: Arch) -> io::Result<Self> {
+    dependency_info::record_input(path);
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    Ok(OrderFile::parse(&contents, arch))
+  }
+
+  // The index of the entry matching `symbol` defined in `object_path`, if
+  // any. Lower indices are placed first.
+  pub fn position(&self, symbol: &str, object_path: &str) -> Option<usize> {
+    self.by_symbol.get(symbol).and_then(|idxs| {
+      idxs
+        .iter()
+        .find(|idx| self.entries[**idx].matches_object(object_path))
+        .cloned()
+    })
+  }
+
+  // Stable-sort the atoms of one output section so the ordered ones come
+  // first. `key` returns the symbol name and object path of an atom. Returns
+  // the indices of the entries which matched something.
+  pub fn sort_atoms<T, F>(&self, atoms: &mut Vec<T>, key: F) -> HashSet<usize>
+  where
+    F: Fn(&T) -> (String, String),
+  {
+    let mut matched: HashSet<usize> = HashSet::new();
+    let mut keyed: Vec<(usize, usize, T)> = Vec::new();
+    for (i, atom) in atoms.drain(..).enumerate() {
+      let (symbol, object) = key(&atom);
+      let pos = match self.position(&symbol, &object) {
+        Some(pos) => {
+          matched.insert(pos);
+          pos
+        }
+        None => self.entries.len(),
+      };
+      keyed.push((pos, i, atom));
+    }
+    keyed.sort_by_key(|&(pos, i, _)| (pos, i));
+    atoms.extend(keyed.into_iter().map(|(_, _, atom)| atom));
+    matched
+  }
+
+  pub fn unmatched(&self, matched: &HashSet<usize>) -> Vec<&OrderEntry> {
+    self
+      .entries
+      .iter()
+      .enumerate()
+      .filter(|&(i, _)| !matched.contains(&i))
+      .map(|(_, entry)| entry)
+      .collect()
+  }
+
+  pub fn report_unmatched(&self, path: &Path, matched: &HashSet<usize>) {
+    for entry in self.unmatched(matched) {
+      diagnostics::warning(&format!(
+        "{}:{}: symbol '{}' in order file not found",
+        path.display(),
+        entry.line,
+        entry.symbol
+      ));
+    }
+  }
+}
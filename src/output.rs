@@ -0,0 +1,114 @@
+// Writing the output file through a memory mapping. Once layout has fixed
+// the output's size, the file is created at that size and mapped, and the
+// writer stores everything straight into the mapping, so a big output
+// isn't also held in memory as one buffer while it's written.
+
+extern crate libc;
+
+use std::fs::OpenOptions;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use statistics;
+
+// A file mapped shared and writable, unmapped when dropped.
+pub struct MappedFile {
+  addr: *mut libc::c_void,
+  len: usize,
+}
+
+impl MappedFile {
+  // Create (or truncate) `path`, size it to `len` bytes, and map it.
+  pub fn create(path: &Path, len: u64) -> io::Result<MappedFile> {
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(true)
+      .open(path)?;
+    file.set_len(len)?;
+    if len == 0 {
+      // mmap refuses empty mappings.
+      return Ok(MappedFile {
+        addr: ptr::null_mut(),
+        len: 0,
+      });
+    }
+    let addr = unsafe {
+      libc::mmap(
+        ptr::null_mut(),
+        len as usize,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        file.as_raw_fd(),
+        0,
+      )
+    };
+    if addr == libc::MAP_FAILED {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(MappedFile {
+      addr: addr,
+      len: len as usize,
+    })
+  }
+
+  // Write the mapping back to the file.
+  pub fn flush(&self) -> io::Result<()> {
+    if self.len == 0 {
+      return Ok(());
+    }
+    if unsafe { libc::msync(self.addr, self.len, libc::MS_SYNC) } != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+}
+
+impl Deref for MappedFile {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    if self.len == 0 {
+      return &[];
+    }
+    unsafe { slice::from_raw_parts(self.addr as *const u8, self.len) }
+  }
+}
+
+impl DerefMut for MappedFile {
+  fn deref_mut(&mut self) -> &mut [u8] {
+    if self.len == 0 {
+      return &mut [];
+    }
+    unsafe { slice::from_raw_parts_mut(self.addr as *mut u8, self.len) }
+  }
+}
+
+impl Drop for MappedFile {
+  fn drop(&mut self) {
+    if self.len != 0 {
+      unsafe {
+        libc::munmap(self.addr, self.len);
+      }
+    }
+  }
+}
+
+// Write a `len` byte output to `path`, with `fill` storing the contents
+// into the mapped file, which starts out zeroed.
+pub fn write<F: FnOnce(&mut [u8])>(
+  path: &Path,
+  len: u64,
+  fill: F,
+) -> io::Result<()> {
+  let mut mapped = MappedFile::create(path, len)?;
+  fill(&mut mapped);
+  mapped.flush()?;
+  statistics::bytes_written(len);
+  Ok(())
+}
@@ -0,0 +1,158 @@
+// Fixups which no longer fit in their relocation once the sections have
+// been placed: where the fixup is, what it refers to, how far away that
+// ended up, and what could bring it back in range.
+
+use std::path::{Path, PathBuf};
+
+use diagnostics::{Diagnostic, Severity};
+use macho::object_file::{ObjectFile, ObjectSection};
+use macho::reloc::RelocationInfo;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelocationOverflow {
+  pub path: PathBuf,
+  // "segname,sectname" of the section with the fixup, and the fixup's
+  // offset in it.
+  pub section: String,
+  pub offset: u64,
+  // The symbol the fixup is in, if it's in one.
+  pub from: Option<String>,
+  // The symbol (or, failing that, the section) it refers to.
+  pub to: String,
+  // The kind of fixup, as in reloc.rs: "rel32", "br24", "thumb_br22" or
+  // "adrp".
+  pub kind: &'static str,
+  // The displacement that didn't fit, in bytes.
+  pub distance: i64,
+}
+
+fn section_name(sect: &ObjectSection) -> String {
+  format!("{},{}", sect.segname, sect.sectname)
+}
+
+// The symbol in section `ordinal` (1-based) which `addr` is in: the last
+// one starting at or before it.
+fn symbol_at(object: &ObjectFile, ordinal: usize, addr: u64) -> Option<&str> {
+  object
+    .symbols
+    .iter()
+    .filter(|sym| !sym.is_stab() && sym.n_sect as usize == ordinal)
+    .filter(|sym| sym.n_value <= addr)
+    .max_by_key(|sym| sym.n_value)
+    .map(|sym| sym.name.as_str())
+}
+
+impl RelocationOverflow {
+  // The overflow of `reloc`, in the input's section `sect`, by `value` (as
+  // RelocError::Overflow has it).
+  pub fn new(
+    path: &Path,
+    object: &ObjectFile,
+    sect: usize,
+    reloc: &RelocationInfo,
+    kind: &'static str,
+    value: i64,
+  ) -> RelocationOverflow {
+    let section = &object.sections[sect];
+    let site = section.addr + reloc.address as u64;
+    let to = reloc.scattered.and_then(|addr| {
+      let addr = addr as u64;
+      object
+        .sections
+        .iter()
+        .position(|s| addr >= s.addr && addr < s.addr + s.size)
+        .and_then(|idx| symbol_at(object, idx + 1, addr))
+    });
+    let to = to.map(|name| name.to_string()).or_else(|| {
+      let idx = (reloc.symbolnum as usize).checked_sub(1)?;
+      object.sections.get(idx).map(section_name)
+    });
+    RelocationOverflow {
+      path: path.to_path_buf(),
+      section: section_name(section),
+      offset: reloc.address as u64,
+      from: symbol_at(object, sect + 1, site).map(|name| name.to_string()),
+      to: to.unwrap_or_else(|| "?".to_string()),
+      kind: kind,
+      // adrp counts 4KB pages.
+      distance: if kind == "adrp" { value << 12 } else { value },
+    }
+  }
+
+  // How far the fixup reaches either way.
+  fn reach(&self) -> &'static str {
+    match self.kind {
+      "br24" => "32MB",
+      "thumb_br22" => "16MB",
+      "adrp" => "4GB",
+      _ => "2GB",
+    }
+  }
+
+  fn is_branch(&self) -> bool {
+    self.kind == "br24" || self.kind == "thumb_br22"
+  }
+
+  // What could bring the fixup back in range, one per line.
+  pub fn suggestions(&self) -> Vec<String> {
+    let mut hints: Vec<String> = Vec::new();
+    if self.is_branch() {
+      hints.push(
+        "link the objects into the final image directly rather than through \
+         -r: the final link reaches distant calls through branch islands"
+          .to_string(),
+      );
+      hints.push(
+        "or keep the caller and callee together with -order_file".to_string(),
+      );
+    } else {
+      hints.push(
+        "move the large section so it isn't between the fixup and its \
+         target (zerofill sections go last)"
+          .to_string(),
+      );
+      hints.push(
+        "or compile with -mcmodel=large, which reaches the target through \
+         a 64-bit address"
+          .to_string(),
+      );
+      if self.kind == "adrp" {
+        hints.push(
+          "or, if the output is linked at a fixed address, choose an \
+           -image_base which keeps it within 4GB of its data"
+            .to_string(),
+        );
+      }
+    }
+    hints
+  }
+
+  pub fn diagnostic(&self) -> Diagnostic {
+    let mut message = format!(
+      "relocation out of range: {} fixup in {}({}) at offset 0x{:x}",
+      self.kind,
+      self.path.display(),
+      self.section,
+      self.offset
+    );
+    if let Some(ref from) = self.from {
+      message.push_str(&format!("\n  in {}", from));
+    }
+    message.push_str(&format!(
+      "\n  refers to {}, {} bytes away; it reaches {} either way",
+      self.to,
+      self.distance,
+      self.reach()
+    ));
+    for hint in self.suggestions().iter() {
+      message.push_str(&format!("\n  {}", hint));
+    }
+    let mut diagnostic = Diagnostic::new(Severity::Error, message);
+    diagnostic.code = Some("relocation-out-of-range");
+    diagnostic.file = Some(self.path.clone());
+    diagnostic.symbol = self.from.clone();
+    let section = format!("{}({})", self.path.display(), self.section);
+    diagnostic.sections = vec![section];
+    diagnostic
+  }
+}
@@ -0,0 +1,129 @@
+// Running independent work on several threads, with rayon. Results come
+// back in input order, so parallel passes produce the same output as
+// sequential ones.
+//
+// --threads belongs to a link, not the process (the daemon runs many), and
+// rayon's global pool is sized once, so each thread count gets a pool of
+// its own, kept for the next link which asks for it. Diagnostics and
+// -dependency_info go wherever the calling thread sends them, so each item
+// carries those over to the worker which runs it.
+
+extern crate rayon;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use self::rayon::prelude::*;
+use self::rayon::{ThreadPool, ThreadPoolBuilder};
+
+use dependency_info::{self, Recorder};
+use diagnostics::{self, Sink};
+
+// --threads, or 0 for one thread per CPU.
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+static POOLS: Mutex<Vec<(usize, Arc<ThreadPool>)>> = Mutex::new(Vec::new());
+
+pub fn set_num_threads(threads: usize) {
+  THREADS.store(threads, Ordering::Relaxed);
+}
+
+pub fn num_threads() -> usize {
+  match THREADS.load(Ordering::Relaxed) {
+    0 => thread::available_parallelism()
+      .map(|n| n.get())
+      .unwrap_or(1),
+    threads => threads,
+  }
+}
+
+// The pool to run `len` items on `threads` threads with, or None to run
+// them on the calling thread: when there's only one thread's worth of
+// work, or a pool couldn't be started.
+fn pool(threads: usize, len: usize) -> Option<Arc<ThreadPool>> {
+  if threads.min(len) <= 1 {
+    return None;
+  }
+  let mut pools = match POOLS.lock() {
+    Ok(pools) => pools,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  if let Some(found) = pools.iter().find(|&&(n, _)| n == threads) {
+    return Some(found.1.clone());
+  }
+  let pool = ThreadPoolBuilder::new().num_threads(threads).build().ok()?;
+  let pool = Arc::new(pool);
+  pools.push((threads, pool.clone()));
+  Some(pool)
+}
+
+// Run `f` on this thread as the thread which called into this module would.
+fn carry<R, F: FnOnce() -> R>(
+  sink: &Option<Sink>,
+  recorder: &Option<Recorder>,
+  f: F,
+) -> R {
+  diagnostics::with_sink(sink.clone(), || {
+    dependency_info::with_recorder(recorder.clone(), f)
+  })
+}
+
+// `f` applied to every item.
+pub fn map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+  T: Sync,
+  R: Send,
+  F: Fn(&T) -> R + Sync,
+{
+  map_with_threads(items, num_threads(), f)
+}
+
+// `f` applied to every item, on at most `threads` threads.
+pub fn map_with_threads<T, R, F>(items: &[T], threads: usize, f: F) -> Vec<R>
+where
+  T: Sync,
+  R: Send,
+  F: Fn(&T) -> R + Sync,
+{
+  let pool = match pool(threads, items.len()) {
+    Some(pool) => pool,
+    None => return items.iter().map(f).collect(),
+  };
+  let sink = diagnostics::current_sink();
+  let recorder = dependency_info::current_recorder();
+  pool.install(|| {
+    items
+      .par_iter()
+      .map(|item| carry(&sink, &recorder, || f(item)))
+      .collect()
+  })
+}
+
+// `f` applied to every item (and its index), with exclusive access to it.
+pub fn map_mut<T, R, F>(items: &mut [T], f: F) -> Vec<R>
+where
+  T: Send,
+  R: Send,
+  F: Fn(usize, &mut T) -> R + Sync,
+{
+  let pool = match pool(num_threads(), items.len()) {
+    Some(pool) => pool,
+    None => {
+      return items
+        .iter_mut()
+        .enumerate()
+        .map(|(i, item)| f(i, item))
+        .collect()
+    }
+  };
+  let sink = diagnostics::current_sink();
+  let recorder = dependency_info::current_recorder();
+  pool.install(|| {
+    items
+      .par_iter_mut()
+      .enumerate()
+      .map(|(i, item)| carry(&sink, &recorder, || f(i, item)))
+      .collect()
+  })
+}
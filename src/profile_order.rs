@@ -0,0 +1,293 @@
+// Function ordering from profiles (--profile-order <file>): the functions a
+// sampled profile found hot are placed together, so running them touches as
+// few pages as possible. The result is an order file, which -order_file
+// entries go before.
+//
+// A sampled profile has a line per distinct call stack, outermost frame
+// first, and the number of samples which hit it, as the stackcollapse
+// scripts write them from `perf script` or an Instruments deep copy:
+//
+//   _main;_parse;_lex 120
+//   _main;_eval 45
+//
+// A line with a single frame just gives that function's samples. A file
+// without counts is a symbol list to use as it is: a name per line, or
+// Propeller's `!name` function lines (its `!!` basic block clusters are
+// skipped, as there are no basic block sections to order). Names are
+// matched as the symbol table spells them. Anything after a '#' is a
+// comment.
+//
+// Hot functions are clustered as in C3 (Ottoni and Maher, as lld's
+// --call-graph-profile-sort does): hottest first, each function's cluster
+// joins its hottest caller's while that still fits in a page, so the calls
+// between them stay on it. Clusters go in order of samples per byte.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use dependency_info;
+use order_file::OrderFile;
+
+#[derive(Debug)]
+pub enum ProfileError {
+  IoError(io::Error),
+  // (line, text) of a stack without a sample count, in a sampled profile.
+  MissingCount(usize, String),
+}
+
+impl From<io::Error> for ProfileError {
+  fn from(error: io::Error) -> Self {
+    ProfileError::IoError(error)
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, ProfileError>;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+  // How many samples each function was on the stack for.
+  pub samples: HashMap<String, u64>,
+  // Samples through each (caller, callee) call.
+  pub calls: HashMap<(String, String), u64>,
+  // The order a symbol list gives, if the file is one.
+  pub symbols: Vec<String>,
+}
+
+// A stack and its count, if the line ends with a count.
+fn split_count(line: &str) -> Option<(&str, u64)> {
+  let idx = line.rfind(char::is_whitespace)?;
+  let count = line[idx..].trim().parse().ok()?;
+  Some((line[..idx].trim(), count))
+}
+
+impl Profile {
+  pub fn parse(contents: &str) -> Result<Self> {
+    let mut profile = Profile::default();
+    let mut sampled: Option<bool> = None;
+    for (i, raw_line) in contents.lines().enumerate() {
+      let line = match raw_line.find('#') {
+        Some(idx) => &raw_line[..idx],
+        None => raw_line,
+      }.trim();
+      if line.is_empty() || line.starts_with("!!") {
+        continue;
+      }
+      let stack = split_count(line);
+      match (*sampled.get_or_insert(stack.is_some()), stack) {
+        (true, Some((stack, count))) => profile.add_stack(stack, count),
+        (true, None) => {
+          return Err(ProfileError::MissingCount(i + 1, line.to_string()));
+        }
+        (false, _) => {
+          let name = line.trim_start_matches('!');
+          profile.symbols.push(name.to_string());
+        }
+      }
+    }
+    Ok(profile)
+  }
+
+  pub fn read(path: &Path) -> Result<Self> {
+    dependency_info::record_input(path);
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    Profile::parse(&contents)
+  }
+
+  fn add_stack(&mut self, stack: &str, count: u64) {
+    let frames: Vec<&str> = stack.split(';').map(|f| f.trim()).collect();
+    // Recursion puts a function on the stack more than once, but it's one
+    // sample of it.
+    let mut seen: HashSet<&str> = HashSet::new();
+    for &frame in frames.iter().filter(|frame| !frame.is_empty()) {
+      if seen.insert(frame) {
+        *self.samples.entry(frame.to_string()).or_insert(0) += count;
+      }
+    }
+    for pair in frames.windows(2) {
+      if pair[0] != pair[1] && !pair[0].is_empty() && !pair[1].is_empty() {
+        let call = (pair[0].to_string(), pair[1].to_string());
+        *self.calls.entry(call).or_insert(0) += count;
+      }
+    }
+  }
+
+  // The functions the profile names: those with samples, or those listed.
+  pub fn functions(&self) -> HashSet<&str> {
+    if self.samples.is_empty() {
+      self.symbols.iter().map(|name| name.as_str()).collect()
+    } else {
+      self.samples.keys().map(|name| name.as_str()).collect()
+    }
+  }
+
+  fn samples_of(&self, name: &str) -> u64 {
+    self.samples.get(name).cloned().unwrap_or(0)
+  }
+
+  // The hot functions in the order to place them, out of `functions`: the
+  // (name, size) of each function in the link, in the order it would
+  // otherwise be laid out in.
+  pub fn order(
+    &self,
+    functions: &[(String, u64)],
+    page_size: u64,
+  ) -> Vec<String> {
+    if self.samples.is_empty() {
+      return self.symbols.clone();
+    }
+    let sizes: HashMap<&str, u64> = functions
+      .iter()
+      .map(|&(ref name, size)| (name.as_str(), size))
+      .collect();
+    let mut hot: Vec<&str> = self
+      .samples
+      .keys()
+      .map(|name| name.as_str())
+      .filter(|name| sizes.contains_key(name))
+      .collect();
+    hot.sort_by(|a, b| {
+      self.samples_of(b).cmp(&self.samples_of(a)).then(a.cmp(b))
+    });
+
+    // Each function's hottest caller among the hot functions.
+    let mut callers: HashMap<&str, (&str, u64)> = HashMap::new();
+    for (&(ref caller, ref callee), &count) in self.calls.iter() {
+      let (caller, callee) = (caller.as_str(), callee.as_str());
+      if !sizes.contains_key(caller) || !sizes.contains_key(callee) {
+        continue;
+      }
+      let best = callers.entry(callee).or_insert((caller, count));
+      // Ties go to the first caller by name.
+      if (count, Reverse(caller)) > (best.1, Reverse(best.0)) {
+        *best = (caller, count);
+      }
+    }
+
+    // Clusters of functions, and which cluster each function is in.
+    let mut clusters: Vec<Vec<&str>> = hot.iter().map(|&f| vec![f]).collect();
+    let mut cluster_of: HashMap<&str, usize> =
+      hot.iter().enumerate().map(|(i, &f)| (f, i)).collect();
+    let cluster_size = |cluster: &Vec<&str>| -> u64 {
+      cluster.iter().map(|f| sizes[f]).sum()
+    };
+    for &function in hot.iter() {
+      let caller = match callers.get(function) {
+        Some(&(caller, _)) => caller,
+        None => continue,
+      };
+      let (from, to) = (cluster_of[function], cluster_of[caller]);
+      if from == to
+        || cluster_size(&clusters[from]) + cluster_size(&clusters[to])
+          > page_size
+      {
+        continue;
+      }
+      let moved: Vec<&str> = clusters[from].drain(..).collect();
+      for &f in moved.iter() {
+        cluster_of.insert(f, to);
+      }
+      clusters[to].extend(moved);
+    }
+
+    // Densest first. An empty function still counts as a byte.
+    let mut clusters: Vec<(f64, Vec<&str>)> = clusters
+      .into_iter()
+      .filter(|cluster| !cluster.is_empty())
+      .map(|cluster| {
+        let samples: u64 = cluster.iter().map(|f| self.samples_of(f)).sum();
+        let size = cluster_size(&cluster).max(1);
+        (samples as f64 / size as f64, cluster)
+      })
+      .collect();
+    clusters.sort_by(|a, b| {
+      b.0
+        .partial_cmp(&a.0)
+        .unwrap_or(Ordering::Equal)
+        .then(a.1[0].cmp(&b.1[0]))
+    });
+    clusters
+      .into_iter()
+      .flat_map(|(_, cluster)| cluster.into_iter().map(|f| f.to_string()))
+      .collect()
+  }
+
+  // How many pages the functions with samples span when `functions` are
+  // laid out one after another in the given order.
+  pub fn pages_touched(
+    &self,
+    functions: &[(&str, u64)],
+    page_size: u64,
+  ) -> u64 {
+    let mut pages: HashSet<u64> = HashSet::new();
+    let mut offset: u64 = 0;
+    for &(name, size) in functions.iter() {
+      if self.samples_of(name) > 0 {
+        let last = (offset + size.max(1) - 1) / page_size;
+        pages.extend(offset / page_size..last + 1);
+      }
+      offset += size;
+    }
+    pages.len() as u64
+  }
+
+  // The order to place `functions` in, and how many fewer pages the hot
+  // ones are estimated to span: the ordered ones go first, and the rest
+  // stay as they were.
+  pub fn plan(&self, functions: &[(String, u64)], page_size: u64) -> Plan {
+    let order = self.order(functions, page_size);
+    let before: Vec<(&str, u64)> = functions
+      .iter()
+      .map(|&(ref name, size)| (name.as_str(), size))
+      .collect();
+    let placed: HashSet<&str> = order.iter().map(|f| f.as_str()).collect();
+    let sizes: HashMap<&str, u64> = before.iter().cloned().collect();
+    let after: Vec<(&str, u64)> = order
+      .iter()
+      .filter_map(|f| sizes.get(f.as_str()).map(|&size| (f.as_str(), size)))
+      .chain(before.iter().cloned().filter(|&(f, _)| !placed.contains(&f)))
+      .collect();
+    Plan {
+      pages_before: self.pages_touched(&before, page_size),
+      pages_after: self.pages_touched(&after, page_size),
+      order: order,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+  pub order: Vec<String>,
+  // Pages the sampled functions span in the original layout, and ordered.
+  pub pages_before: u64,
+  pub pages_after: u64,
+}
+
+impl Plan {
+  // The order as an order file, after -order_file's entries if there are
+  // any.
+  pub fn order_file(&self, explicit: Option<OrderFile>) -> OrderFile {
+    let derived = OrderFile::from_symbols(&self.order);
+    match explicit {
+      Some(mut order) => {
+        order.append(&derived);
+        order
+      }
+      None => derived,
+    }
+  }
+
+  pub fn report(&self) -> String {
+    format!(
+      "profile order: {} hot functions span {} pages ordered, {} as they \
+       were ({} fewer page faults to fault them in)\n",
+      self.order.len(),
+      self.pages_after,
+      self.pages_before,
+      self.pages_before.saturating_sub(self.pages_after)
+    )
+  }
+}
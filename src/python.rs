@@ -0,0 +1,259 @@
+// Python bindings (built with --features python): reading a Mach-O file's
+// header, load commands, segments and symbols, and linking with LinkJob,
+// for scripts which analyze or build binaries.
+//
+//   import mold
+//   image = mold.parse(open("test.o", "rb").read())
+//   job = mold.LinkJob("x86_64")
+//   job.platform("macos", "10.13")
+//   job.output_type("relocatable")
+//   job.add_object("test.o")
+//   warnings = job.link_to("out.o")
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList};
+
+use args::OutputKind;
+use diagnostics::Diagnostic;
+use link_job::{LinkFailure, LinkJob};
+use macho::image_file::ImageFile;
+use macho::load_command::load_commands;
+use macho::{Arch, MachOError, Platform, Version};
+
+fn parse_error(e: MachOError) -> PyErr {
+  PyValueError::new_err(format!("{:?}", e))
+}
+
+fn diagnostic_dict<'p>(py: Python<'p>, diagnostic: &Diagnostic) -> &'p PyDict {
+  let dict = PyDict::new(py);
+  // Setting str and Option values can't fail.
+  let _ = dict.set_item("severity", diagnostic.severity.name());
+  let _ = dict.set_item("code", diagnostic.code);
+  let _ = dict.set_item("message", &diagnostic.message);
+  let _ = dict.set_item(
+    "file",
+    diagnostic.file.as_ref().map(|f| f.display().to_string()),
+  );
+  let _ = dict.set_item("symbol", diagnostic.symbol.clone());
+  let _ = dict.set_item("sections", diagnostic.sections.clone());
+  dict
+}
+
+fn diagnostic_list<'p>(
+  py: Python<'p>,
+  diagnostics: &[Diagnostic],
+) -> &'p PyList {
+  PyList::new(py, diagnostics.iter().map(|d| diagnostic_dict(py, d)))
+}
+
+// A failed link raises RuntimeError, with the error's message and every
+// diagnostic reported before it as the arguments.
+fn link_error(py: Python, failure: LinkFailure) -> PyErr {
+  let mut diagnostics = failure.diagnostics;
+  diagnostics.push(failure.error.diagnostic());
+  let message = diagnostics[diagnostics.len() - 1].message.clone();
+  let list: PyObject = diagnostic_list(py, &diagnostics).into();
+  PyRuntimeError::new_err((message, list))
+}
+
+// A thin Mach-O file (object, executable, dylib or bundle) as a dict of its
+// header fields, load commands, segments with their sections, and symbols.
+#[pyfunction]
+fn parse<'p>(py: Python<'p>, data: &[u8]) -> PyResult<&'p PyDict> {
+  let image = ImageFile::parse(data).map_err(parse_error)?;
+  let commands = load_commands(data).map_err(parse_error)?;
+
+  let dict = PyDict::new(py);
+  dict.set_item("cputype", image.cputype)?;
+  dict.set_item("cpusubtype", image.cpusubtype)?;
+  dict.set_item("filetype", image.filetype)?;
+  dict.set_item("uuid", image.uuid.map(|uuid| PyBytes::new(py, &uuid)))?;
+  if let Some((platform, version)) = image.platform {
+    dict.set_item("platform", platform.name())?;
+    dict.set_item("min_version", version.to_string())?;
+  }
+
+  let load_commands = PyList::empty(py);
+  for header in commands.iter() {
+    let command = PyDict::new(py);
+    command.set_item("cmd", header.cmd)?;
+    command.set_item("cmdsize", header.cmdsize)?;
+    command.set_item("offset", header.offset)?;
+    load_commands.append(command)?;
+  }
+  dict.set_item("load_commands", load_commands)?;
+
+  let segments = PyList::empty(py);
+  for seg in image.segments.iter() {
+    let segment = PyDict::new(py);
+    segment.set_item("segname", &seg.segname)?;
+    segment.set_item("vmaddr", seg.vmaddr)?;
+    segment.set_item("vmsize", seg.vmsize)?;
+    segment.set_item("fileoff", seg.fileoff)?;
+    segment.set_item("filesize", seg.filesize)?;
+    segment.set_item("maxprot", seg.maxprot)?;
+    segment.set_item("initprot", seg.initprot)?;
+    segment.set_item("flags", seg.flags)?;
+    let sections = PyList::empty(py);
+    for sect in seg.sections.iter() {
+      let section = PyDict::new(py);
+      section.set_item("segname", &sect.segname)?;
+      section.set_item("sectname", &sect.sectname)?;
+      section.set_item("addr", sect.addr)?;
+      section.set_item("size", sect.size)?;
+      section.set_item("offset", sect.offset)?;
+      section.set_item("align", sect.align)?;
+      section.set_item("flags", sect.flags)?;
+      sections.append(section)?;
+    }
+    segment.set_item("sections", sections)?;
+    segments.append(segment)?;
+  }
+  dict.set_item("segments", segments)?;
+
+  let symbols = PyList::empty(py);
+  for sym in image.symbols.iter() {
+    let symbol = PyDict::new(py);
+    symbol.set_item("name", &sym.name)?;
+    symbol.set_item("n_type", sym.n_type)?;
+    symbol.set_item("n_sect", sym.n_sect)?;
+    symbol.set_item("n_desc", sym.n_desc)?;
+    symbol.set_item("n_value", sym.n_value)?;
+    symbol.set_item("external", sym.is_external())?;
+    symbol.set_item("undefined", sym.is_undefined())?;
+    symbols.append(symbol)?;
+  }
+  dict.set_item("symbols", symbols)?;
+  Ok(dict)
+}
+
+// LinkJob for Python: the same methods, but mutating in place, and a link
+// consumes it (a second link raises RuntimeError).
+#[pyclass(name = "LinkJob")]
+struct PyLinkJob {
+  job: Option<LinkJob>,
+}
+
+impl PyLinkJob {
+  fn update<F: FnOnce(LinkJob) -> LinkJob>(&mut self, f: F) -> PyResult<()> {
+    let job = self.take()?;
+    self.job = Some(f(job));
+    Ok(())
+  }
+
+  fn take(&mut self) -> PyResult<LinkJob> {
+    self
+      .job
+      .take()
+      .ok_or_else(|| PyRuntimeError::new_err("this LinkJob already ran"))
+  }
+}
+
+fn version(s: &str) -> PyResult<Version> {
+  Version::parse(s).map_err(parse_error)
+}
+
+#[pymethods]
+impl PyLinkJob {
+  #[new]
+  fn new(arch: &str) -> PyResult<Self> {
+    let arch = Arch::from_name(arch).ok_or_else(|| {
+      PyValueError::new_err(format!("unknown architecture: {}", arch))
+    })?;
+    Ok(PyLinkJob {
+      job: Some(LinkJob::new(arch)),
+    })
+  }
+
+  fn platform(&mut self, platform: &str, min_version: &str) -> PyResult<()> {
+    let platform = Platform::from_name(platform).ok_or_else(|| {
+      PyValueError::new_err(format!("unknown platform: {}", platform))
+    })?;
+    let min_version = version(min_version)?;
+    self.update(|job| job.platform(platform, min_version))
+  }
+
+  fn sdk_version(&mut self, sdk_version: &str) -> PyResult<()> {
+    let sdk_version = version(sdk_version)?;
+    self.update(|job| job.sdk_version(sdk_version))
+  }
+
+  // "execute", "dylib", "bundle" or "relocatable".
+  fn output_type(&mut self, kind: &str) -> PyResult<()> {
+    let kind = match kind {
+      "execute" => OutputKind::Executable,
+      "dylib" => OutputKind::Dylib,
+      "bundle" => OutputKind::Bundle,
+      "relocatable" => OutputKind::Relocatable,
+      _ => {
+        let message = format!("unknown output type: {}", kind);
+        return Err(PyValueError::new_err(message));
+      }
+    };
+    self.update(|job| job.output_type(kind))
+  }
+
+  fn add_object(&mut self, path: &str) -> PyResult<()> {
+    self.update(|job| job.add_object(path))
+  }
+
+  fn add_object_data(&mut self, name: &str, data: &[u8]) -> PyResult<()> {
+    self.update(|job| job.add_object_data(name, data))
+  }
+
+  fn add_dylib(&mut self, path: &str) -> PyResult<()> {
+    self.update(|job| job.add_dylib(path))
+  }
+
+  fn add_library(&mut self, name: &str) -> PyResult<()> {
+    self.update(|job| job.add_library(name))
+  }
+
+  fn add_library_path(&mut self, dir: &str) -> PyResult<()> {
+    self.update(|job| job.add_library_path(dir))
+  }
+
+  fn add_framework(&mut self, name: &str) -> PyResult<()> {
+    self.update(|job| job.add_framework(name))
+  }
+
+  fn add_framework_path(&mut self, dir: &str) -> PyResult<()> {
+    self.update(|job| job.add_framework_path(dir))
+  }
+
+  // Link to `path`, returning the warnings as dicts.
+  fn link_to<'p>(
+    &mut self,
+    py: Python<'p>,
+    path: &str,
+  ) -> PyResult<&'p PyList> {
+    let job = self.take()?;
+    match py.allow_threads(move || job.link_to(path)) {
+      Ok(diagnostics) => Ok(diagnostic_list(py, &diagnostics)),
+      Err(failure) => Err(link_error(py, failure)),
+    }
+  }
+
+  // Link in memory, returning (output bytes, warnings).
+  fn link_to_buffer<'p>(
+    &mut self,
+    py: Python<'p>,
+  ) -> PyResult<(&'p PyBytes, &'p PyList)> {
+    let job = self.take()?;
+    match py.allow_threads(move || job.link_to_buffer()) {
+      Ok((output, diagnostics)) => Ok((
+        PyBytes::new(py, &output),
+        diagnostic_list(py, &diagnostics),
+      )),
+      Err(failure) => Err(link_error(py, failure)),
+    }
+  }
+}
+
+#[pymodule]
+fn mold(_py: Python, m: &PyModule) -> PyResult<()> {
+  m.add_function(wrap_pyfunction!(self::parse, m)?)?;
+  m.add_class::<PyLinkJob>()?;
+  Ok(())
+}
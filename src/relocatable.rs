@@ -0,0 +1,3031 @@
+// Relocatable output (-r): merge the input objects into a single MH_OBJECT.
+//
+// Sections with the same name are concatenated, relocations are carried
+// over with their addresses, symbol indices and section ordinals rewritten,
+// and the symbol tables are merged with the same rules as a final link.
+// Nothing is bound or rebased, so there is no dyld info; that is left to the
+// link which eventually consumes the output.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use archive::{self, ArchiveError};
+use args::{LinkOptions, LtoBackend, OutputKind};
+use cache;
+use cold_split;
+use dead_strip::{self, Liveness};
+use dependency_info;
+use diagnostics::{self, Diagnostic, Severity};
+use dtrace;
+use dylib::DylibSet;
+use elf::{self, ElfError};
+use final_link;
+use image::Note;
+use incremental::{self, InputRecord, Slot, State};
+use intern::Symbol;
+use kext::{self, KextError};
+use ld_env;
+#[cfg(feature = "llvm")]
+use llvm_lto;
+use lto::{self, BitcodeModule, LibLto, LtoError};
+use macho::data_in_code::{self, DataInCodeEntry};
+use macho::fat;
+use macho::header::{header_size, MachHeader64};
+use macho::load_command::{Dysymtab, LoadCommand, Section64, Segment64,
+                          LC_DATA_IN_CODE, LC_LINKER_OPTIMIZATION_HINT};
+use macho::loh::{self, Loh};
+use macho::object_file::{ObjectFile, ObjectSection};
+use macho::reloc::{self, read_u32, write_u32, RelocError, RelocationInfo,
+                   ARM64_RELOC_ADDEND, ARM64_RELOC_UNSIGNED,
+                   ARM_RELOC_BR24, ARM_RELOC_VANILLA, ARM_THUMB_RELOC_BR22,
+                   GENERIC_RELOC_VANILLA, X86_64_RELOC_BRANCH,
+                   X86_64_RELOC_SIGNED, X86_64_RELOC_SIGNED_1,
+                   X86_64_RELOC_SIGNED_2, X86_64_RELOC_SIGNED_4,
+                   X86_64_RELOC_UNSIGNED};
+use macho::symtab::{nlist_size, Nlist, StringTable, N_EXT, N_PEXT,
+                    N_SECT, N_TYPE};
+use macho::{round_up, section_type, set_u32, set_u64, Arch, MachOError,
+            ParseLimits, MH_SUBSECTIONS_VIA_SYMBOLS, MH_OBJECT,
+            SECTION_TYPE, S_GB_ZEROFILL, S_THREAD_LOCAL_ZEROFILL,
+            S_ZEROFILL, VM_PROT_EXECUTE, VM_PROT_READ, VM_PROT_WRITE};
+use objc::{self, ObjcError};
+use order_file::OrderFile;
+use output;
+use overflow::RelocationOverflow;
+use parallel;
+use profile_order::{Profile, ProfileError};
+use resolve::{self, ConcurrentSymbolTable, InputSymbol, ResolveError,
+              SymbolKind, SymbolTable};
+use search;
+use size::SizeReport;
+use statistics;
+use strip;
+use symbol_list::SymbolList;
+use undefined::{self, UndefinedSymbol, UndefinedTreatment};
+use target::{Target, TargetError};
+use timing;
+use uuid::md5;
+
+#[derive(Debug)]
+pub enum RelocatableError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  Target(TargetError),
+  Lto(LtoError),
+  BadArchive(PathBuf, ArchiveError),
+  // An ELF input which couldn't be read, or translated into Mach-O.
+  Elf(PathBuf, ElfError),
+  // (path, cputype) of an input for another architecture.
+  WrongArch(PathBuf, u32),
+  Resolve(ResolveError),
+  // A relocation referring to a symbol index or section ordinal which
+  // doesn't exist: (path, the index).
+  BadRelocationTarget(PathBuf, u32),
+  // (path, relocation type) of a section-relative relocation whose fixup
+  // we don't know how to move.
+  UnsupportedRelocation(PathBuf, u8),
+  // A section-relative fixup which can't be moved: misaligned, or not the
+  // instruction the relocation says.
+  Reloc(PathBuf, RelocError),
+  // One which no longer fits once moved.
+  Overflow(Box<RelocationOverflow>),
+  // Section ordinals are 8 bits.
+  TooManySections,
+  // (field, value) for an output which the object format can't describe:
+  // a file offset past 4GB, say, or a relocation against a symbol index
+  // over 24 bits. Offsets and sizes are 64-bit everywhere else.
+  FormatLimit(&'static str, u64),
+  Objc(ObjcError),
+  // The --profile-order profile.
+  Profile(PathBuf, ProfileError),
+  // A kext without a usable kmod_info.
+  Kext(KextError),
+  // The symbols nothing defines (with -undefined error) and those defined
+  // more than once. Each has been reported on its own, up to
+  // --error-limit.
+  UnresolvedSymbols {
+    undefined: Vec<String>,
+    duplicates: Vec<String>,
+  },
+}
+
+impl From<ObjcError> for RelocatableError {
+  fn from(error: ObjcError) -> Self {
+    RelocatableError::Objc(error)
+  }
+}
+
+impl From<KextError> for RelocatableError {
+  fn from(error: KextError) -> Self {
+    RelocatableError::Kext(error)
+  }
+}
+
+impl From<ResolveError> for RelocatableError {
+  fn from(error: ResolveError) -> Self {
+    RelocatableError::Resolve(error)
+  }
+}
+
+impl From<LtoError> for RelocatableError {
+  fn from(error: LtoError) -> Self {
+    RelocatableError::Lto(error)
+  }
+}
+
+impl From<TargetError> for RelocatableError {
+  fn from(error: TargetError) -> Self {
+    RelocatableError::Target(error)
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, RelocatableError>;
+
+pub struct RelocatableInput {
+  pub path: PathBuf,
+  pub object: ObjectFile,
+}
+
+#[derive(Debug, Default)]
+struct MergedSection {
+  segname: String,
+  sectname: String,
+  align: u32,
+  flags: u32,
+  reserved1: u32,
+  reserved2: u32,
+  contents: Vec<u8>,
+  size: u64,
+  addr: u64,
+  relocs: Vec<RelocationInfo>,
+}
+
+impl MergedSection {
+  fn is_zerofill(&self) -> bool {
+    match self.flags & SECTION_TYPE {
+      S_ZEROFILL | S_GB_ZEROFILL | S_THREAD_LOCAL_ZEROFILL => true,
+      _ => false,
+    }
+  }
+}
+
+// Where one input section ended up.
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+  // Index into the merged sections (the output ordinal minus 1).
+  section: usize,
+  offset: u64,
+  // How far the section's contents moved: new address minus old.
+  delta: u64,
+}
+
+// How input sections are placed within their output sections.
+#[derive(Debug, Clone, Copy)]
+pub enum SectionLayout<'a> {
+  // One after the other, as tightly as alignment allows.
+  Packed,
+  // In slots with room to grow (-incremental): the given ones from the
+  // previous link, or new ones.
+  Slotted(Option<&'a [Vec<Slot>]>),
+}
+
+// The largest section alignment (log2) we lay out: 4GB, far more than
+// anything needs, and well short of overflowing the arithmetic.
+const MAX_ALIGN: u32 = 32;
+
+// `val` for a 32-bit field of the output.
+fn fit_u32(what: &'static str, val: u64) -> Result<u32> {
+  fit(what, val, u32::MAX as u64)
+}
+
+// `val` for a field of the output which holds at most `max`.
+fn fit(what: &'static str, val: u64, max: u64) -> Result<u32> {
+  if val > max {
+    return Err(RelocatableError::FormatLimit(what, val));
+  }
+  Ok(val as u32)
+}
+
+// a + b, for sizes and addresses in the output.
+fn add(what: &'static str, a: u64, b: u64) -> Result<u64> {
+  a.checked_add(b)
+    .ok_or(RelocatableError::FormatLimit(what, a))
+}
+
+// `val` rounded up to a multiple of 2^`align`.
+fn align_up(what: &'static str, val: u64, align: u32) -> Result<u64> {
+  let mask = (1u64 << align) - 1;
+  add(what, val, mask).map(|end| end & !mask)
+}
+
+// The symbols an input section defines.
+fn section_symbols(object: &ObjectFile, section: usize) -> Vec<&str> {
+  object
+    .symbols
+    .iter()
+    .filter(|sym| !sym.is_stab() && sym.n_type & N_TYPE == N_SECT)
+    .filter(|sym| sym.n_sect as usize == section + 1)
+    .map(|sym| sym.name.as_str())
+    .collect()
+}
+
+// The (input, section) pairs of __text in the order to place them: those
+// `order` names first, each by the first of its symbols `order` has.
+fn order_text(
+  inputs: &[RelocatableInput],
+  order: &OrderFile,
+  text: &mut Vec<(usize, usize)>,
+) {
+  order.sort_atoms(text, |&(i, j)| {
+    let path = inputs[i].path.display().to_string();
+    let first = section_symbols(&inputs[i].object, j)
+      .into_iter()
+      .min_by_key(|name| order.position(name, &path).unwrap_or(usize::MAX))
+      .unwrap_or("");
+    (first.to_string(), path)
+  });
+}
+
+fn merge_sections(
+  inputs: &[RelocatableInput],
+  layout: SectionLayout,
+  order: Option<&OrderFile>,
+) -> Result<(Vec<MergedSection>, Vec<Vec<Placement>>, Vec<Vec<Slot>>)> {
+  let mut merged: Vec<MergedSection> = Vec::new();
+  let mut by_name: HashMap<(String, String), usize> = HashMap::new();
+  // Output sections go in the order the inputs first have them, whatever
+  // order their contents are placed in.
+  let mut indices: Vec<Vec<usize>> = Vec::new();
+  let mut rest: Vec<(usize, usize)> = Vec::new();
+  let mut text: Vec<(usize, usize)> = Vec::new();
+  for (i, input) in inputs.iter().enumerate() {
+    let mut file_indices: Vec<usize> = Vec::new();
+    for (j, sect) in input.object.sections.iter().enumerate() {
+      if sect.align > MAX_ALIGN {
+        let align = MachOError::FieldOverflow("align", sect.align as u64);
+        return Err(RelocatableError::Malformed(input.path.clone(), align));
+      }
+      let key = (sect.segname.clone(), sect.sectname.clone());
+      let idx = *by_name.entry(key).or_insert_with(|| {
+        merged.push(MergedSection {
+          segname: sect.segname.clone(),
+          sectname: sect.sectname.clone(),
+          flags: (sect.flags & !SECTION_TYPE)
+            | section_type(&sect.segname, &sect.sectname, sect.flags),
+          reserved1: sect.reserved1,
+          reserved2: sect.reserved2,
+          ..Default::default()
+        });
+        merged.len() - 1
+      });
+      file_indices.push(idx);
+      if sect.segname == "__TEXT" && sect.sectname == "__text" {
+        text.push((i, j));
+      } else {
+        rest.push((i, j));
+      }
+    }
+    indices.push(file_indices);
+  }
+  if let Some(order) = order {
+    order_text(inputs, order, &mut text);
+  }
+
+  let mut placements: Vec<Vec<Placement>> = Vec::new();
+  let mut slots: Vec<Vec<Slot>> = Vec::new();
+  for input in inputs.iter() {
+    let count = input.object.sections.len();
+    let placement = Placement {
+      section: 0,
+      offset: 0,
+      delta: 0,
+    };
+    placements.push(vec![placement; count]);
+    slots.push(vec![Slot { offset: 0, reserved: 0 }; count]);
+  }
+  for (i, j) in text.into_iter().chain(rest) {
+    let sect = &inputs[i].object.sections[j];
+    let idx = indices[i][j];
+    let out = &mut merged[idx];
+    out.align = out.align.max(sect.align);
+    // Section attributes (the high bits) accumulate; the type is kept
+    // from the first input.
+    out.flags |= sect.flags & !SECTION_TYPE;
+    let packed = align_up("section size", out.size, sect.align)?;
+    let slot = match layout {
+      SectionLayout::Packed => Slot {
+        offset: packed,
+        reserved: sect.size,
+      },
+      SectionLayout::Slotted(Some(previous)) => previous[i][j],
+      SectionLayout::Slotted(None) => Slot {
+        offset: packed,
+        reserved: incremental::reserve(sect.size, sect.align),
+      },
+    };
+    if !out.is_zerofill() {
+      out.contents.resize(slot.offset as usize, 0);
+      out.contents.extend_from_slice(&sect.contents);
+    }
+    out.size = add("section size", slot.offset, slot.reserved)?;
+    placements[i][j] = Placement {
+      section: idx,
+      offset: slot.offset,
+      delta: 0,
+    };
+    slots[i][j] = slot;
+  }
+  move_cold_text(&mut merged, &mut placements);
+  if merged.len() > 255 {
+    return Err(RelocatableError::TooManySections);
+  }
+
+  // Zero-fill sections go after everything with contents, as they take no
+  // space in the file.
+  let mut addr: u64 = 0;
+  for zerofill in [false, true].iter() {
+    for sect in merged.iter_mut() {
+      if sect.is_zerofill() == *zerofill {
+        addr = align_up("section address", addr, sect.align)?;
+        sect.addr = addr;
+        addr = add("section address", addr, sect.size)?;
+      }
+    }
+  }
+  for (input, file_placements) in inputs.iter().zip(placements.iter_mut()) {
+    for (sect, placement) in
+      input.object.sections.iter().zip(file_placements.iter_mut())
+    {
+      let new_addr = merged[placement.section].addr + placement.offset;
+      placement.delta = new_addr.wrapping_sub(sect.addr);
+    }
+  }
+  Ok((merged, placements, slots))
+}
+
+// --split-cold's section goes after the rest of __TEXT, wherever its first
+// input was.
+fn move_cold_text(
+  merged: &mut Vec<MergedSection>,
+  placements: &mut [Vec<Placement>],
+) {
+  let cold = match merged.iter().position(|sect| {
+    sect.segname == "__TEXT" && sect.sectname == cold_split::COLD_SECTION
+  }) {
+    Some(cold) => cold,
+    None => return,
+  };
+  let sect = merged.remove(cold);
+  let to = merged
+    .iter()
+    .rposition(|sect| sect.segname == "__TEXT")
+    .map_or(0, |last_text| last_text + 1);
+  merged.insert(to, sect);
+  for placement in placements.iter_mut().flat_map(|file| file.iter_mut()) {
+    let section = placement.section;
+    placement.section = if section == cold {
+      to
+    } else if section > cold && section <= to {
+      section - 1
+    } else if section < cold && section >= to {
+      section + 1
+    } else {
+      section
+    };
+  }
+}
+
+// Each input's external symbols, added to the table by whichever thread
+// read them.
+fn resolve_inputs(
+  inputs: &[RelocatableInput],
+) -> resolve::Result<SymbolTable> {
+  let table = ConcurrentSymbolTable::new();
+  let indexed: Vec<(usize, &RelocatableInput)> =
+    inputs.iter().enumerate().collect();
+  parallel::map(&indexed, |&(i, input)| {
+    table.add_file(i, &to_input_symbols(&input.object))
+  });
+  table.finish()
+}
+
+fn to_input_symbols(object: &ObjectFile) -> Vec<InputSymbol> {
+  object
+    .symbols
+    .iter()
+    .filter(|sym| sym.is_external())
+    .map(|sym| InputSymbol {
+      name: sym.name.clone(),
+      kind: if sym.is_undefined() {
+        SymbolKind::Undefined
+      } else if sym.is_weak_def() {
+        SymbolKind::WeakDefined
+      } else {
+        SymbolKind::Defined
+      },
+      private_extern: sym.is_private_extern(),
+      value: sym.n_value,
+    })
+    .collect()
+}
+
+// Move a symbol from its input section to its output section.
+fn relocate_symbol(sym: &Nlist, placements: &[Placement]) -> Nlist {
+  let placement = if sym.n_sect == 0 {
+    None
+  } else {
+    placements.get(sym.n_sect as usize - 1)
+  };
+  let is_section_relative = sym.is_stab() || sym.n_type & N_TYPE == N_SECT;
+  match placement {
+    Some(placement) if is_section_relative => Nlist {
+      n_sect: placement.section as u8 + 1,
+      n_value: sym.n_value.wrapping_add(placement.delta),
+      ..sym.clone()
+    },
+    _ => sym.clone(),
+  }
+}
+
+struct MergedSymbols {
+  symbols: Vec<Nlist>,
+  dysymtab: Dysymtab,
+  // For each input, its symbol indices mapped to output indices.
+  maps: Vec<Vec<Option<u32>>>,
+}
+
+fn merge_symbols(
+  inputs: &[RelocatableInput],
+  placements: &[Vec<Placement>],
+  keep_private_externs: bool,
+) -> Result<MergedSymbols> {
+  let table = resolve_inputs(inputs).map_err(|e| match e {
+    ResolveError::DuplicateSymbols(duplicates) => {
+      RelocatableError::UnresolvedSymbols {
+        undefined: Vec::new(),
+        duplicates: report_duplicates(inputs, &duplicates),
+      }
+    }
+    e => RelocatableError::Resolve(e),
+  })?;
+  statistics::symbols_resolved(table.len());
+
+  let mut locals: Vec<Nlist> = Vec::new();
+  let mut hidden: Vec<Nlist> = Vec::new();
+  let mut extdefs: Vec<Nlist> = Vec::new();
+  let mut undefs: HashMap<Symbol, Nlist> = HashMap::new();
+  let mut local_maps: Vec<Vec<Option<u32>>> = Vec::new();
+  for (i, input) in inputs.iter().enumerate() {
+    let mut map: Vec<Option<u32>> = Vec::new();
+    for sym in input.object.symbols.iter() {
+      if !sym.is_external() {
+        map.push(Some(locals.len() as u32));
+        locals.push(relocate_symbol(sym, &placements[i]));
+        continue;
+      }
+      map.push(None);
+      // Every external symbol was interned when resolving.
+      let name = match table.symbol(&sym.name) {
+        Some(name) => name,
+        None => continue,
+      };
+      if sym.is_undefined() {
+        if table.definition(name).is_none() {
+          // Keep the largest size of a tentative definition.
+          let entry = undefs.entry(name).or_insert_with(|| sym.clone());
+          if sym.n_value > entry.n_value {
+            *entry = sym.clone();
+          }
+        }
+      } else if table.definition(name).map(|def| def.file) == Some(i) {
+        let mut out = relocate_symbol(sym, &placements[i]);
+        if sym.is_private_extern() && !keep_private_externs {
+          // Hidden symbols are only visible within the output, so they
+          // become locals, marked as having been private extern.
+          out.n_type = (out.n_type & !N_EXT) | N_PEXT;
+          hidden.push(out);
+        } else {
+          extdefs.push(out);
+        }
+      }
+    }
+    local_maps.push(map);
+  }
+  extdefs.sort_by(|a, b| a.name.cmp(&b.name));
+  let mut undefs: Vec<Nlist> = undefs.into_iter().map(|(_, sym)| sym).collect();
+  undefs.sort_by(|a, b| a.name.cmp(&b.name));
+
+  let dysymtab = Dysymtab {
+    ilocalsym: 0,
+    nlocalsym: (locals.len() + hidden.len()) as u32,
+    iextdefsym: (locals.len() + hidden.len()) as u32,
+    nextdefsym: extdefs.len() as u32,
+    iundefsym: (locals.len() + hidden.len() + extdefs.len()) as u32,
+    nundefsym: undefs.len() as u32,
+    ..Default::default()
+  };
+  let mut symbols = locals;
+  symbols.extend(hidden);
+  symbols.extend(extdefs);
+  symbols.extend(undefs);
+
+  let mut global_index: HashMap<Symbol, u32> = HashMap::new();
+  for (idx, sym) in symbols.iter().enumerate() {
+    if sym.is_external() || sym.is_private_extern() {
+      if let Some(name) = table.symbol(&sym.name) {
+        global_index.insert(name, idx as u32);
+      }
+    }
+  }
+  let maps = inputs
+    .iter()
+    .zip(local_maps.into_iter())
+    .map(|(input, map)| {
+      input
+        .object
+        .symbols
+        .iter()
+        .zip(map.into_iter())
+        .map(|(sym, idx)| {
+          idx.or_else(|| {
+            let name = table.symbol(&sym.name)?;
+            global_index.get(&name).cloned()
+          })
+        })
+        .collect()
+    })
+    .collect();
+  Ok(MergedSymbols {
+    symbols: symbols,
+    dysymtab: dysymtab,
+    maps: maps,
+  })
+}
+
+// Section-relative relocations have the target's address baked into the
+// fixup, so it has to move with the target (and, if pc-relative, with the
+// fixup itself). False if we don't know how to move this kind of fixup.
+pub fn adjust_fixup(
+  arch: Arch,
+  reloc: &RelocationInfo,
+  contents: &mut [u8],
+  offset: usize,
+  target_delta: u64,
+  site_delta: u64,
+) -> reloc::Result<bool> {
+  let is_unsigned = match arch {
+    Arch::X86_64 => reloc.kind == X86_64_RELOC_UNSIGNED,
+    Arch::Arm64 | Arch::Arm64e | Arch::Arm64_32 => {
+      reloc.kind == ARM64_RELOC_UNSIGNED
+    }
+    Arch::I386 => reloc.kind == GENERIC_RELOC_VANILLA && !reloc.pcrel,
+    Arch::Armv7 | Arch::Armv7s => reloc.kind == ARM_RELOC_VANILLA,
+  };
+  let is_rel32 = reloc.pcrel
+    && reloc.length == 2
+    && match arch {
+      Arch::X86_64 => [
+        X86_64_RELOC_SIGNED,
+        X86_64_RELOC_BRANCH,
+        X86_64_RELOC_SIGNED_1,
+        X86_64_RELOC_SIGNED_2,
+        X86_64_RELOC_SIGNED_4,
+      ].contains(&reloc.kind),
+      Arch::I386 => reloc.kind == GENERIC_RELOC_VANILLA,
+      _ => false,
+    };
+  let is_arm = arch == Arch::Armv7 || arch == Arch::Armv7s;
+  if offset + (1 << reloc.length) > contents.len() {
+    return Ok(false);
+  }
+  let moved = target_delta.wrapping_sub(site_delta);
+  if is_unsigned && !reloc.pcrel && reloc.length == 3 {
+    let mut val: u64 = 0;
+    for i in 0..8 {
+      val |= (contents[offset + i] as u64) << (i * 8);
+    }
+    set_u64(contents, offset, val.wrapping_add(target_delta));
+  } else if is_unsigned && !reloc.pcrel && reloc.length == 2 {
+    let val = read_u32(contents, offset);
+    set_u32(contents, offset, val.wrapping_add(target_delta as u32));
+  } else if is_rel32 {
+    let val = read_u32(contents, offset) as i32 as i64 + moved as i64;
+    if val != val as i32 as i64 {
+      return Err(RelocError::Overflow("rel32", val));
+    }
+    write_u32(contents, offset, val as u32);
+  } else if is_arm && reloc.kind == ARM_RELOC_BR24 {
+    reloc::adjust_br24(contents, offset, moved as i64)?;
+  } else if is_arm && reloc.kind == ARM_THUMB_RELOC_BR22 {
+    reloc::adjust_thumb_br22(contents, offset, moved as i64)?;
+  } else {
+    return Ok(false);
+  }
+  Ok(true)
+}
+
+// Where the input section containing `addr` was placed, for scattered
+// relocations, which name their target by address.
+fn placement_at<'a>(
+  object: &ObjectFile,
+  file_placements: &'a [Placement],
+  addr: u32,
+) -> Option<&'a Placement> {
+  let addr = addr as u64;
+  let sections = &object.sections;
+  // An address just past the end of a section (as a SECTDIFF's pair may
+  // be) belongs to it only if it doesn't start another.
+  sections
+    .iter()
+    .position(|sect| addr >= sect.addr && addr < sect.addr + sect.size)
+    .or_else(|| {
+      sections.iter().position(|sect| addr == sect.addr + sect.size)
+    })
+    .and_then(|idx| file_placements.get(idx))
+}
+
+// Rewrite one input section's relocations into its output section.
+fn relocate_section(
+  arch: Arch,
+  input: &RelocatableInput,
+  sect_index: usize,
+  placement: &Placement,
+  file_placements: &[Placement],
+  symbol_map: &[Option<u32>],
+  out_sect: &mut MergedSection,
+) -> Result<()> {
+  let sect = &input.object.sections[sect_index];
+  let bad_target =
+    |idx: u32| RelocatableError::BadRelocationTarget(input.path.clone(), idx);
+  let unsupported = |kind: u8| {
+    RelocatableError::UnsupportedRelocation(input.path.clone(), kind)
+  };
+  for (i, reloc) in sect.relocs.iter().enumerate() {
+    let overflow = |e| match e {
+      RelocError::Overflow(kind, value) => {
+        RelocatableError::Overflow(Box::new(RelocationOverflow::new(
+          &input.path,
+          &input.object,
+          sect_index,
+          reloc,
+          kind,
+          value,
+        )))
+      }
+      e => RelocatableError::Reloc(input.path.clone(), e),
+    };
+    // r_address's top bit marks scattered relocations, whose addresses only
+    // have 24 bits.
+    let max_address = match reloc.scattered {
+      Some(_) => 0x00ff_ffff,
+      None => 0x7fff_ffff,
+    };
+    let address = reloc.address as u64 + placement.offset;
+    let mut out = RelocationInfo {
+      address: fit("relocation address", address, max_address)?,
+      ..*reloc
+    };
+    let is_addend = arch.is_arm64() && reloc.kind == ARM64_RELOC_ADDEND;
+    let is_pair = reloc::is_pair(arch, reloc.kind);
+    if let Some(value) = reloc.scattered {
+      let target = placement_at(&input.object, file_placements, value)
+        .ok_or_else(|| bad_target(value))?;
+      out.scattered = Some(value.wrapping_add(target.delta as u32));
+      if is_pair {
+        // Moved along with the SECTDIFF before it.
+      } else if reloc::is_sectdiff(arch, reloc.kind) {
+        // The fixup is target minus pair, which only changes if the two
+        // moved by different amounts.
+        let pair = sect
+          .relocs
+          .get(i + 1)
+          .and_then(|pair| pair.scattered)
+          .and_then(|value| placement_at(&input.object, file_placements, value))
+          .ok_or_else(|| unsupported(reloc.kind))?;
+        let offset = out.address as usize;
+        if reloc.length != 2 || offset + 4 > out_sect.contents.len() {
+          return Err(unsupported(reloc.kind));
+        }
+        let val = read_u32(&out_sect.contents, offset);
+        let moved = target.delta.wrapping_sub(pair.delta) as u32;
+        write_u32(&mut out_sect.contents, offset, val.wrapping_add(moved));
+      } else {
+        let adjusted = adjust_fixup(
+          arch,
+          reloc,
+          &mut out_sect.contents,
+          out.address as usize,
+          target.delta,
+          placement.delta,
+        ).map_err(overflow)?;
+        if !adjusted {
+          return Err(unsupported(reloc.kind));
+        }
+      }
+    } else if is_addend || is_pair {
+      // symbolnum is the addend, or unused.
+    } else if reloc.external {
+      let symbolnum = symbol_map
+        .get(reloc.symbolnum as usize)
+        .and_then(|idx| *idx)
+        .ok_or_else(|| bad_target(reloc.symbolnum))?;
+      // r_symbolnum has 24 bits.
+      out.symbolnum =
+        fit("relocation symbol index", symbolnum as u64, 0x00ff_ffff)?;
+    } else {
+      let target = reloc
+        .symbolnum
+        .checked_sub(1)
+        .and_then(|idx| file_placements.get(idx as usize))
+        .ok_or_else(|| bad_target(reloc.symbolnum))?;
+      out.symbolnum = target.section as u32 + 1;
+      let adjusted = adjust_fixup(
+        arch,
+        reloc,
+        &mut out_sect.contents,
+        out.address as usize,
+        target.delta,
+        placement.delta,
+      ).map_err(overflow)?;
+      if !adjusted {
+        return Err(unsupported(reloc.kind));
+      }
+    }
+    out_sect.relocs.push(out);
+  }
+  Ok(())
+}
+
+// Each output section's fixups and relocations depend only on the input
+// sections placed in it, so output sections are processed in parallel.
+fn merge_relocations(
+  arch: Arch,
+  inputs: &[RelocatableInput],
+  placements: &[Vec<Placement>],
+  symbol_maps: &[Vec<Option<u32>>],
+  merged: &mut [MergedSection],
+) -> Result<()> {
+  // The (input, section index) pairs making up each output section, in
+  // input order.
+  let mut members: Vec<Vec<(usize, usize)>> = vec![Vec::new(); merged.len()];
+  for (i, file_placements) in placements.iter().enumerate() {
+    for (j, placement) in file_placements.iter().enumerate() {
+      members[placement.section].push((i, j));
+    }
+  }
+  // Errors carry the (input, section index) they came from.
+  type SectionResult =
+    ::std::result::Result<(), ((usize, usize), RelocatableError)>;
+  let results: Vec<SectionResult> = parallel::map_mut(merged, |idx, out_sect| {
+    for &(i, j) in members[idx].iter() {
+      relocate_section(
+        arch,
+        &inputs[i],
+        j,
+        &placements[i][j],
+        &placements[i],
+        &symbol_maps[i],
+        out_sect,
+      ).map_err(|e| ((i, j), e))?;
+    }
+    Ok(())
+  });
+  // Report the error a sequential pass over the inputs would have hit.
+  match results.into_iter().filter_map(|r| r.err()).min_by_key(|e| e.0) {
+    Some((_, e)) => Err(e),
+    None => Ok(()),
+  }
+}
+
+// Copy each section's contents to its address in `data` (which mirrors the
+// segment's file layout), from several threads. Sections don't overlap, so
+// each gets its own disjoint slice of the output.
+fn copy_contents(merged: &[MergedSection], data: &mut [u8]) {
+  let mut sections: Vec<&MergedSection> =
+    merged.iter().filter(|sect| !sect.is_zerofill()).collect();
+  sections.sort_by_key(|sect| sect.addr);
+  let mut copies: Vec<(&mut [u8], &[u8])> = Vec::new();
+  let mut rest = data;
+  let mut rest_addr: u64 = 0;
+  for sect in sections.into_iter() {
+    let (_, tail) = rest.split_at_mut((sect.addr - rest_addr) as usize);
+    let (dest, tail) = tail.split_at_mut(sect.contents.len());
+    copies.push((dest, &sect.contents));
+    rest = tail;
+    rest_addr = sect.addr + sect.contents.len() as u64;
+  }
+  parallel::map_mut(&mut copies, |_, copy| copy.0.copy_from_slice(copy.1));
+}
+
+// Move each input's optimization hints to where its sections were placed.
+// Hints which aren't entirely within sections are dropped.
+fn relocate_hints(
+  inputs: &[RelocatableInput],
+  placements: &[Vec<Placement>],
+) -> Vec<Loh> {
+  let mut hints: Vec<Loh> = Vec::new();
+  for (input, file_placements) in inputs.iter().zip(placements.iter()) {
+    let sections = &input.object.sections;
+    for hint in input.object.loh.iter() {
+      let addresses: Option<Vec<u64>> = hint
+        .addresses
+        .iter()
+        .map(|&addr| {
+          sections
+            .iter()
+            .zip(file_placements.iter())
+            .find(|&(sect, _)| {
+              addr >= sect.addr && addr < sect.addr + sect.size
+            })
+            .map(|(_, placement)| addr.wrapping_add(placement.delta))
+        })
+        .collect();
+      if let Some(addresses) = addresses {
+        hints.push(Loh {
+          kind: hint.kind,
+          addresses: addresses,
+        });
+      }
+    }
+  }
+  hints
+}
+
+// Move each input's data in code to where its sections were placed, and so
+// into the output's address order. Entries outside sections are dropped.
+fn relocate_data_in_code(
+  inputs: &[RelocatableInput],
+  placements: &[Vec<Placement>],
+) -> Vec<DataInCodeEntry> {
+  let mut entries: Vec<DataInCodeEntry> = Vec::new();
+  for (input, file_placements) in inputs.iter().zip(placements.iter()) {
+    let sections = &input.object.sections;
+    for entry in input.object.data_in_code.iter() {
+      let addr = entry.offset as u64;
+      let moved = sections
+        .iter()
+        .zip(file_placements.iter())
+        .find(|&(sect, _)| addr >= sect.addr && addr < sect.addr + sect.size)
+        .map(|(_, placement)| addr.wrapping_add(placement.delta));
+      if let Some(addr) = moved {
+        entries.push(DataInCodeEntry {
+          offset: addr as u32,
+          ..*entry
+        });
+      }
+    }
+  }
+  entries.sort_by_key(|entry| entry.offset);
+  entries
+}
+
+// What -add_note and -add_load_command add to a -r output: LC_NOTEs, with
+// their data before the symbol table, and commands written as they are.
+#[derive(Debug, Default)]
+pub struct ExtraCommands {
+  pub notes: Vec<Note>,
+  pub commands: Vec<LoadCommand>,
+}
+
+impl ExtraCommands {
+  pub fn read(opts: &LinkOptions) -> Result<Self> {
+    let read = |path: &PathBuf| {
+      dependency_info::record_input(path);
+      fs::read(path).map_err(|e| RelocatableError::IoError(path.clone(), e))
+    };
+    let mut extra = ExtraCommands::default();
+    for &(ref owner, ref path) in opts.notes.iter() {
+      extra.notes.push(Note {
+        owner: owner.clone(),
+        data: read(path)?,
+      });
+    }
+    for &(cmd, ref path) in opts.extra_load_commands.iter() {
+      extra.commands.push(LoadCommand::Raw {
+        cmd: cmd,
+        body: read(path)?,
+      });
+    }
+    Ok(extra)
+  }
+}
+
+// What an object's load commands point to besides its sections and
+// symbols, and the commands added as they are.
+struct Linkedit<'a> {
+  hints: &'a [Loh],
+  dice: &'a [DataInCodeEntry],
+  extra: &'a ExtraCommands,
+}
+
+// A merged object, laid out but not yet written. Everything but the section
+// contents is encoded already; those are copied straight from the merged
+// sections to wherever the object is written, so they aren't first copied
+// into one big buffer.
+pub struct ObjectOutput {
+  // The header and load commands.
+  commands: Vec<u8>,
+  data_start: u64,
+  merged: Vec<MergedSection>,
+  reloc_start: u64,
+  reloc_data: Vec<u8>,
+  loh_start: u64,
+  loh_data: Vec<u8>,
+  dice_start: u64,
+  dice_data: Vec<u8>,
+  note_start: u64,
+  note_data: Vec<u8>,
+  symoff: u64,
+  sym_data: Vec<u8>,
+  strings: Vec<u8>,
+}
+
+impl ObjectOutput {
+  pub fn size(&self) -> u64 {
+    self.symoff + (self.sym_data.len() + self.strings.len()) as u64
+  }
+
+  // Store the object into `buf`, which is size() bytes and zeroed.
+  pub fn write_to(&self, buf: &mut [u8]) {
+    let mut put = |offset: u64, data: &[u8]| {
+      let offset = offset as usize;
+      buf[offset..(offset + data.len())].copy_from_slice(data);
+    };
+    put(0, &self.commands);
+    put(self.reloc_start, &self.reloc_data);
+    put(self.loh_start, &self.loh_data);
+    put(self.dice_start, &self.dice_data);
+    put(self.note_start, &self.note_data);
+    put(self.symoff, &self.sym_data);
+    put(self.symoff + self.sym_data.len() as u64, &self.strings);
+    copy_contents(&self.merged, &mut buf[(self.data_start as usize)..]);
+  }
+
+  pub fn to_vec(&self) -> Vec<u8> {
+    let mut buf: Vec<u8> = vec![0; self.size() as usize];
+    self.write_to(&mut buf);
+    buf
+  }
+}
+
+fn write_object(
+  arch: Arch,
+  target: &Target,
+  flags: u32,
+  merged: Vec<MergedSection>,
+  symbols: MergedSymbols,
+  linkedit: Linkedit,
+) -> Result<ObjectOutput> {
+  let (hints, dice, extra) = (linkedit.hints, linkedit.dice, linkedit.extra);
+  let vmsize = merged.iter().map(|s| s.addr + s.size).max().unwrap_or(0);
+  if !arch.is_64_bit() {
+    // 32-bit objects' addresses and sizes are 32 bits.
+    fit_u32("32-bit segment size", vmsize)?;
+  }
+  let filesize = merged
+    .iter()
+    .filter(|s| !s.is_zerofill())
+    .map(|s| s.addr + s.size)
+    .max()
+    .unwrap_or(0);
+  let mut segment = Segment64 {
+    segname: String::new(),
+    vmaddr: 0,
+    vmsize: vmsize,
+    filesize: filesize,
+    maxprot: VM_PROT_READ | VM_PROT_WRITE | VM_PROT_EXECUTE,
+    initprot: VM_PROT_READ | VM_PROT_WRITE | VM_PROT_EXECUTE,
+    ..Default::default()
+  };
+  let mut symtab = LoadCommand::Symtab {
+    symoff: 0,
+    nsyms: 0,
+    stroff: 0,
+    strsize: 0,
+  };
+  let dysymtab = LoadCommand::Dysymtab(symbols.dysymtab);
+  let build_versions = target.load_commands();
+  let loh_data = if hints.is_empty() {
+    Vec::new()
+  } else {
+    loh::write(hints)
+  };
+  let mut loh_cmd = LoadCommand::LinkeditData {
+    cmd: LC_LINKER_OPTIMIZATION_HINT,
+    dataoff: 0,
+    datasize: fit_u32("optimization hints size", loh_data.len() as u64)?,
+  };
+  let dice_data = data_in_code::write(dice);
+  let mut dice_cmd = LoadCommand::LinkeditData {
+    cmd: LC_DATA_IN_CODE,
+    dataoff: 0,
+    datasize: fit_u32("data in code size", dice_data.len() as u64)?,
+  };
+  let is_64_bit = arch.is_64_bit();
+  let mut sizeofcmds = LoadCommand::segment(
+    arch,
+    Segment64 {
+      sections: vec![Section64::default(); merged.len()],
+      ..Default::default()
+    },
+  ).cmdsize()
+    + build_versions.iter().map(|cmd| cmd.cmdsize()).sum::<u32>()
+    + symtab.cmdsize()
+    + dysymtab.cmdsize();
+  if !loh_data.is_empty() {
+    sizeofcmds += loh_cmd.cmdsize();
+  }
+  if !dice_data.is_empty() {
+    sizeofcmds += dice_cmd.cmdsize();
+  }
+  let mut notes: Vec<LoadCommand> = extra
+    .notes
+    .iter()
+    .map(|note| LoadCommand::Note {
+      data_owner: note.owner.clone(),
+      offset: 0,
+      size: note.data.len() as u64,
+    })
+    .collect();
+  sizeofcmds += notes
+    .iter()
+    .chain(extra.commands.iter())
+    .map(|cmd| cmd.cmdsize())
+    .sum::<u32>();
+
+  // Section contents, with file offsets mirroring addresses, then the
+  // relocations, then the optimization hints, data in code and notes, then
+  // the symbol and string tables.
+  let data_start = round_up(header_size(is_64_bit) + sizeofcmds as u64, 8);
+  segment.fileoff = data_start;
+  let mut reloc_data: Vec<u8> = Vec::new();
+  let reloc_start = round_up(data_start + filesize, 8);
+  for sect in merged.iter() {
+    let mut header = Section64 {
+      sectname: sect.sectname.clone(),
+      segname: sect.segname.clone(),
+      addr: sect.addr,
+      size: sect.size,
+      align: sect.align,
+      flags: sect.flags,
+      reserved1: sect.reserved1,
+      reserved2: sect.reserved2,
+      ..Default::default()
+    };
+    if !sect.is_zerofill() {
+      header.offset = fit_u32("section offset", data_start + sect.addr)?;
+    }
+    if !sect.relocs.is_empty() {
+      let reloff = reloc_start + reloc_data.len() as u64;
+      header.reloff = fit_u32("relocations offset", reloff)?;
+      header.nreloc = fit_u32("relocation count", sect.relocs.len() as u64)?;
+      for reloc in sect.relocs.iter() {
+        reloc.write(&mut reloc_data);
+      }
+    }
+    segment.sections.push(header);
+  }
+
+  let loh_start = reloc_start + round_up(reloc_data.len() as u64, 8);
+  if let LoadCommand::LinkeditData {
+    ref mut dataoff, ..
+  } = loh_cmd
+  {
+    *dataoff = fit_u32("optimization hints offset", loh_start)?;
+  }
+  let dice_start = loh_start + loh_data.len() as u64;
+  if let LoadCommand::LinkeditData {
+    ref mut dataoff, ..
+  } = dice_cmd
+  {
+    *dataoff = fit_u32("data in code offset", dice_start)?;
+  }
+  let note_start = dice_start + dice_data.len() as u64;
+  let mut note_data: Vec<u8> = Vec::new();
+  for (cmd, note) in notes.iter_mut().zip(extra.notes.iter()) {
+    if let LoadCommand::Note { ref mut offset, .. } = *cmd {
+      *offset = note_start + note_data.len() as u64;
+    }
+    note_data.extend_from_slice(&note.data);
+  }
+  let symoff = note_start + note_data.len() as u64;
+  let mut strtab = StringTable::new();
+  let mut sym_data: Vec<u8> = Vec::new();
+  for sym in symbols.symbols.iter() {
+    sym.write_sized(&mut sym_data, &mut strtab, is_64_bit);
+  }
+  let strings = strtab.finish();
+  let stroff = symoff + sym_data.len() as u64;
+  let nsyms = (sym_data.len() / nlist_size(is_64_bit)) as u64;
+  symtab = LoadCommand::Symtab {
+    symoff: fit_u32("symbol table offset", symoff)?,
+    nsyms: fit_u32("symbol count", nsyms)?,
+    stroff: fit_u32("string table offset", stroff)?,
+    strsize: fit_u32("string table size", strings.len() as u64)?,
+  };
+
+  let mut cmds = vec![LoadCommand::segment(arch, segment)];
+  cmds.extend(build_versions);
+  cmds.push(symtab);
+  cmds.push(dysymtab);
+  if !loh_data.is_empty() {
+    cmds.push(loh_cmd);
+  }
+  if !dice_data.is_empty() {
+    cmds.push(dice_cmd);
+  }
+  cmds.extend(notes);
+  cmds.extend(extra.commands.iter().cloned());
+  let mut commands: Vec<u8> = Vec::new();
+  let mut header = MachHeader64::new(arch, MH_OBJECT);
+  header.ncmds = cmds.len() as u32;
+  header.sizeofcmds = sizeofcmds;
+  header.flags = flags;
+  header.write(&mut commands);
+  for cmd in cmds.iter() {
+    cmd.write(&mut commands);
+  }
+  Ok(ObjectOutput {
+    commands: commands,
+    data_start: data_start,
+    merged: merged,
+    reloc_start: reloc_start,
+    reloc_data: reloc_data,
+    loh_start: loh_start,
+    loh_data: loh_data,
+    dice_start: dice_start,
+    dice_data: dice_data,
+    note_start: note_start,
+    note_data: note_data,
+    symoff: symoff,
+    sym_data: sym_data,
+    strings: strings,
+  })
+}
+
+pub fn link(
+  arch: Arch,
+  target: &Target,
+  inputs: &[RelocatableInput],
+  keep_private_externs: bool,
+) -> Result<Vec<u8>> {
+  let layout = SectionLayout::Packed;
+  let extra = ExtraCommands::default();
+  link_with_layout(
+    arch,
+    target,
+    inputs,
+    keep_private_externs,
+    layout,
+    None,
+    &extra,
+  ).map(|(output, _)| output.to_vec())
+}
+
+// Link, also returning where each input section was put.
+pub fn link_with_layout(
+  arch: Arch,
+  target: &Target,
+  inputs: &[RelocatableInput],
+  keep_private_externs: bool,
+  layout: SectionLayout,
+  order: Option<&OrderFile>,
+  extra: &ExtraCommands,
+) -> Result<(ObjectOutput, Vec<Vec<Slot>>)> {
+  for input in inputs.iter() {
+    if input.object.cputype != arch.cputype() {
+      return Err(RelocatableError::WrongArch(
+        input.path.clone(),
+        input.object.cputype,
+      ));
+    }
+  }
+  let (mut merged, placements, slots) =
+    timing::time("layout", || merge_sections(inputs, layout, order))?;
+  let sections = inputs.iter().map(|input| input.object.sections.iter());
+  statistics::sections_merged(sections.clone().map(|s| s.len()).sum());
+  statistics::fixups_applied(
+    sections.flatten().map(|sect| sect.relocs.len()).sum(),
+  );
+  let symbols = timing::time("resolve", || {
+    merge_symbols(inputs, &placements, keep_private_externs)
+  })?;
+  timing::time("fixups", || {
+    merge_relocations(arch, inputs, &placements, &symbols.maps, &mut merged)
+  })?;
+  // Atoms can only be split at symbols if every input allowed it.
+  let flags = if inputs
+    .iter()
+    .all(|input| input.object.flags & MH_SUBSECTIONS_VIA_SYMBOLS != 0)
+  {
+    MH_SUBSECTIONS_VIA_SYMBOLS
+  } else {
+    0
+  };
+  let (hints, dice) = timing::time("fixups", || {
+    let hints = relocate_hints(inputs, &placements);
+    (hints, relocate_data_in_code(inputs, &placements))
+  });
+  let output = timing::time("linkedit", || {
+    let linkedit = Linkedit {
+      hints: &hints,
+      dice: &dice,
+      extra: extra,
+    };
+    write_object(arch, target, flags, merged, symbols, linkedit)
+  })?;
+  Ok((output, slots))
+}
+
+enum ParsedInput {
+  Object(ObjectFile),
+  Bitcode(Vec<u8>),
+  // Each member's name and contents.
+  Archive(Arc<Vec<(String, ObjectFile)>>),
+}
+
+fn read_archive(
+  path: &PathBuf,
+  data: &[u8],
+  limits: &ParseLimits,
+) -> Result<Vec<(String, ObjectFile)>> {
+  let members = archive::members(data)
+    .map_err(|e| RelocatableError::BadArchive(path.clone(), e))?;
+  let mut objects: Vec<(String, ObjectFile)> = Vec::new();
+  for member in members.iter().filter(|m| !m.is_symbol_table()) {
+    let member_path = member_path(path, member.name);
+    // Bitcode members aren't indexed, so they're never pulled in.
+    if lto::is_bitcode(member.data) {
+      continue;
+    }
+    let object = ObjectFile::parse_with_limits(member.data, limits)
+      .map_err(|e| RelocatableError::Malformed(member_path, e))?;
+    statistics::object_parsed();
+    objects.push((member.name.to_string(), object));
+  }
+  Ok(objects)
+}
+
+// How archive members are named in diagnostics: libfoo.a(bar.o).
+fn member_path(archive: &Path, member: &str) -> PathBuf {
+  PathBuf::from(format!("{}({})", archive.display(), member))
+}
+
+// The contents of an input, from memory if it was given that way.
+fn read_file<'a>(
+  opts: &'a LinkOptions,
+  path: &PathBuf,
+) -> Result<Cow<'a, [u8]>> {
+  if let Some(data) = opts.input_buffer(path) {
+    return Ok(Cow::Borrowed(data));
+  }
+  dependency_info::record_input(path);
+  let mut data: Vec<u8> = Vec::new();
+  File::open(path)
+    .and_then(|mut f| f.read_to_end(&mut data))
+    .map_err(|e| RelocatableError::IoError(path.clone(), e))?;
+  statistics::bytes_read(data.len());
+  Ok(Cow::Owned(data))
+}
+
+fn read_input(opts: &LinkOptions, path: &PathBuf) -> Result<ParsedInput> {
+  let _span = timing::input_span(path);
+  let limits = opts.parse_limits();
+  // Archives parsed without limits aren't reused by links with them.
+  let cacheable = limits == ParseLimits::default();
+  if opts.input_buffer(path).is_none()
+    && cacheable
+    && archive::is_archive_file(path)
+  {
+    // The daemon keeps archives parsed between links, so they're only read
+    // when there isn't an up to date copy. (Universal archives aren't kept.)
+    dependency_info::record_input(path);
+    let members = cache::ARCHIVES.get(path, || {
+      read_archive(path, &read_file(opts, path)?, &limits)
+    });
+    return members.map(ParsedInput::Archive);
+  }
+  let data = read_file(opts, path)?;
+  if elf::is_elf(&data) {
+    if opts.input_buffer(path).is_some() {
+      return Err(RelocatableError::Elf(path.clone(), ElfError::InMemory));
+    }
+    let object = elf::read_object(path, &data, opts.arch)
+      .map_err(|e| RelocatableError::Elf(path.clone(), e))?;
+    statistics::object_parsed();
+    return Ok(ParsedInput::Object(object));
+  }
+  let thin = fat::thin(&data, opts.arch)
+    .map_err(|e| RelocatableError::Malformed(path.clone(), e))?;
+  if lto::is_bitcode(thin) {
+    return Ok(ParsedInput::Bitcode(thin.to_vec()));
+  }
+  if archive::is_archive(thin) {
+    return read_archive(path, thin, &limits).map(|members| {
+      ParsedInput::Archive(Arc::new(members))
+    });
+  }
+  let object = ObjectFile::parse_with_limits(thin, &limits)
+    .map_err(|e| RelocatableError::Malformed(path.clone(), e))?;
+  statistics::object_parsed();
+  Ok(ParsedInput::Object(object))
+}
+
+// -t
+fn trace_load(opts: &LinkOptions, path: &Path) {
+  timing::loaded(path);
+  if opts.trace_files {
+    println!("{}", path.display());
+  }
+}
+
+fn add_external_symbols(
+  object: &ObjectFile,
+  defined: &mut HashSet<String>,
+  undefined: &mut HashSet<String>,
+) {
+  for sym in object.symbols.iter().filter(|sym| sym.is_external()) {
+    if sym.is_undefined() {
+      if !defined.contains(&sym.name) {
+        undefined.insert(sym.name.clone());
+      }
+    } else {
+      undefined.remove(&sym.name);
+      defined.insert(sym.name.clone());
+    }
+  }
+}
+
+// Load archive members which define symbols that are still undefined,
+// until nothing more is needed. A member can refer to symbols defined in an
+// earlier archive, so they're all searched again after anything is loaded.
+// Members of -force_load archives (or any, with -all_load) are loaded
+// regardless, so any symbols they define twice are reported as duplicates.
+fn load_archive_members(
+  opts: &LinkOptions,
+  inputs: &mut Vec<RelocatableInput>,
+  archives: Vec<(PathBuf, Arc<Vec<(String, ObjectFile)>>)>,
+) -> Result<()> {
+  let target = opts.target();
+  let mut defined: HashSet<String> = HashSet::new();
+  let mut undefined: HashSet<String> = HashSet::new();
+  for input in inputs.iter() {
+    add_external_symbols(&input.object, &mut defined, &mut undefined);
+  }
+  for name in opts.forced_undefined.iter() {
+    if !defined.contains(name) {
+      undefined.insert(name.clone());
+    }
+  }
+
+  // Each archive's members, and whether each has been loaded. Members are
+  // copied out as they're loaded, since the daemon may be keeping the
+  // archive for later links.
+  let mut pending: Vec<(PathBuf, Arc<Vec<(String, ObjectFile)>>, Vec<bool>)> =
+    archives
+      .into_iter()
+      .map(|(path, members)| {
+        let loaded = vec![false; members.len()];
+        (path, members, loaded)
+      })
+      .collect();
+  let mut loaded_any = true;
+  while loaded_any {
+    loaded_any = false;
+    for &mut (ref archive, ref members, ref mut loaded) in pending.iter_mut() {
+      let forced = if opts.all_load {
+        Some("-all_load")
+      } else if opts.force_load.contains(archive) {
+        Some("-force_load")
+      } else {
+        None
+      };
+      for (&(ref name, ref object), loaded) in
+        members.iter().zip(loaded.iter_mut())
+      {
+        let reason = match (*loaded, forced) {
+          (false, Some(forced)) => Some(forced.to_string()),
+          (false, None) => object
+            .symbols
+            .iter()
+            .find(|sym| {
+              sym.is_external()
+                && !sym.is_undefined()
+                && undefined.contains(&sym.name)
+            })
+            .map(|sym| sym.name.clone())
+            .or_else(|| {
+              if opts.objc && objc::defines_objc(object) {
+                Some("-ObjC".to_string())
+              } else {
+                None
+              }
+            }),
+          (true, _) => None,
+        };
+        let reason = match reason {
+          Some(reason) => reason,
+          None => continue,
+        };
+        *loaded = true;
+        let object = object.clone();
+        let path = member_path(archive, name);
+        target.check_input(&path, object.platform)?;
+        if opts.why_load {
+          println!("{} forced load of {}", reason, path.display());
+        }
+        trace_load(opts, &path);
+        add_external_symbols(&object, &mut defined, &mut undefined);
+        inputs.push(RelocatableInput {
+          path: path,
+          object: object,
+        });
+        loaded_any = true;
+      }
+    }
+  }
+  if opts.trace_archives {
+    for &(ref archive, _, ref loaded) in pending.iter() {
+      if loaded.contains(&true) {
+        ld_env::trace(opts, "static archive", archive);
+      }
+    }
+  }
+  Ok(())
+}
+
+// The objects the LTO backend compiles the bitcode inputs into, with where
+// each appears in diagnostics.
+fn compile_bitcode(
+  opts: &LinkOptions,
+  bitcode: &[(PathBuf, Vec<u8>)],
+  native_references: &HashSet<String>,
+) -> lto::Result<Vec<(PathBuf, Vec<u8>)>> {
+  match opts.lto_backend() {
+    #[cfg(feature = "llvm")]
+    LtoBackend::Llvm => llvm_lto::compile(bitcode, opts, native_references),
+    LtoBackend::LibLto => {
+      let path = lto::library_path(opts);
+      // A bare name is left to dlopen's search, which isn't recorded.
+      if path.exists() {
+        dependency_info::record_input(&path);
+      }
+      let lib = LibLto::load(&path)?;
+      let mut modules: Vec<BitcodeModule> = Vec::new();
+      for &(ref path, ref data) in bitcode.iter() {
+        modules.push(BitcodeModule::parse(&lib, path, data, opts)?);
+      }
+      let data = lto::compile(&lib, &modules, opts, native_references)?;
+      Ok(vec![(lto::object_path(opts), data)])
+    }
+  }
+}
+
+// Read every input, in parallel. Universal inputs contribute their slice
+// for -arch, and bitcode inputs are compiled into objects (one, unless
+// --thinlto) which take the place of the first of them. Dylibs are left to
+// `dylibs`, which undefined symbols may be found in.
+fn read_inputs(
+  opts: &LinkOptions,
+  dylibs: &DylibSet,
+) -> Result<Vec<RelocatableInput>> {
+  let target = opts.target();
+  let mut inputs: Vec<RelocatableInput> = Vec::new();
+  let mut bitcode: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+  let mut lto_position: usize = 0;
+  let mut archives: Vec<(PathBuf, Arc<Vec<(String, ObjectFile)>>)> =
+    Vec::new();
+  let paths: Vec<PathBuf> = opts
+    .input_paths
+    .iter()
+    .filter(|path| !search::is_dylib_path(path))
+    .cloned()
+    .collect();
+  let parsed = timing::time("parse", || {
+    parallel::map(&paths, |path| read_input(opts, path))
+  });
+  for (path, input) in paths.iter().zip(parsed.into_iter()) {
+    match input? {
+      ParsedInput::Object(object) => {
+        target.check_input(path, object.platform)?;
+        trace_load(opts, path);
+        inputs.push(RelocatableInput {
+          path: path.clone(),
+          object: object,
+        });
+      }
+      ParsedInput::Bitcode(data) => {
+        if bitcode.is_empty() {
+          lto_position = inputs.len();
+        }
+        trace_load(opts, path);
+        bitcode.push((path.clone(), data));
+      }
+      ParsedInput::Archive(members) => archives.push((path.clone(), members)),
+    }
+  }
+  for path in opts.force_load.iter() {
+    if !archives.iter().any(|&(ref archive, _)| archive == path) {
+      diagnostics::warning(&format!(
+        "-force_load {} is not an archive; it was linked as is",
+        path.display()
+      ));
+    }
+  }
+  timing::time("parse", || {
+    load_archive_members(opts, &mut inputs, archives)
+  })?;
+
+  if !bitcode.is_empty() {
+    let native_references: HashSet<String> = inputs
+      .iter()
+      .flat_map(|input| input.object.symbols.iter())
+      .filter(|sym| sym.is_external() && sym.is_undefined())
+      .map(|sym| sym.name.clone())
+      .collect();
+    let objects = timing::time("lto", || {
+      compile_bitcode(opts, &bitcode, &native_references)
+    })?;
+    for (i, (path, data)) in objects.into_iter().enumerate() {
+      let object = ObjectFile::parse(&data)
+        .map_err(|e| RelocatableError::Malformed(path.clone(), e))?;
+      inputs.insert(
+        lto_position + i,
+        RelocatableInput {
+          path: path,
+          object: object,
+        },
+      );
+    }
+  }
+  objc::merge_image_info(opts.arch, &mut inputs)?;
+  if opts.merge_objc_categories() {
+    parallel::map_mut(&mut inputs, |_, input| objc::merge_categories(input));
+  }
+  if opts.dead_strip {
+    inputs = timing::time("dead-strip", || dead_strip_inputs(opts, &inputs));
+  }
+  if opts.split_cold {
+    let profile = read_profile(opts)?;
+    let split = timing::time("split-cold", || {
+      cold_split::split(&mut inputs, profile.as_ref())
+    });
+    if opts.split_cold_report {
+      diagnostics::note(&split.report());
+    }
+  }
+  check_undefined(opts, &inputs, dylibs)?;
+  if !opts.strip.is_empty() {
+    inputs = parallel::map(&inputs, |input| {
+      strip::strip_input(opts.arch, input, opts.strip)
+    });
+  }
+  inputs.extend(created_sections(opts)?);
+  Ok(inputs)
+}
+
+fn read_profile(opts: &LinkOptions) -> Result<Option<Profile>> {
+  match opts.profile_order {
+    Some(ref path) => Profile::read(path)
+      .map(Some)
+      .map_err(|e| RelocatableError::Profile(path.clone(), e)),
+    None => Ok(None),
+  }
+}
+
+// The order to place __text's contents in: -order_file's, then the hot
+// functions of the --profile-order profile. None if neither was given.
+fn text_order(
+  opts: &LinkOptions,
+  inputs: &[RelocatableInput],
+) -> Result<Option<OrderFile>> {
+  let explicit = match opts.order_file {
+    Some(ref path) => Some(
+      OrderFile::read(path, opts.arch)
+        .map_err(|e| RelocatableError::IoError(path.clone(), e))?,
+    ),
+    None => None,
+  };
+  let profile = match read_profile(opts)? {
+    Some(profile) => profile,
+    None => return Ok(explicit),
+  };
+  // Each __text input section is a function, as far as the profile goes,
+  // named by its first symbol.
+  let functions: Vec<(String, u64)> = inputs
+    .iter()
+    .flat_map(|input| {
+      let object = &input.object;
+      object.sections.iter().enumerate().filter_map(move |(j, sect)| {
+        if sect.segname != "__TEXT" || sect.sectname != "__text" {
+          return None;
+        }
+        section_symbols(object, j)
+          .first()
+          .map(|name| (name.to_string(), sect.size))
+      })
+    })
+    .collect();
+  let plan = profile.plan(&functions, opts.arch.page_size());
+  Ok(Some(plan.order_file(explicit)))
+}
+
+// The sections of -sectcreate and -add_empty_section, as an input of their
+// own after all the others.
+fn created_sections(opts: &LinkOptions) -> Result<Option<RelocatableInput>> {
+  if opts.created_sections.is_empty() {
+    return Ok(None);
+  }
+  let mut sections: Vec<ObjectSection> = Vec::new();
+  for created in opts.created_sections.iter() {
+    let contents = match created.path {
+      Some(ref path) => read_file(opts, path)?.into_owned(),
+      None => Vec::new(),
+    };
+    sections.push(ObjectSection {
+      segname: created.segname.clone(),
+      sectname: created.sectname.clone(),
+      size: contents.len() as u64,
+      contents: contents,
+      ..Default::default()
+    });
+  }
+  Ok(Some(RelocatableInput {
+    path: PathBuf::from("-sectcreate"),
+    object: ObjectFile {
+      cputype: opts.arch.cputype(),
+      flags: MH_SUBSECTIONS_VIA_SYMBOLS,
+      sections: sections,
+      ..Default::default()
+    },
+  }))
+}
+
+// -undefined error or warning: report what nothing defines, neither the
+// inputs, nor the dylibs, nor the linker itself.
+fn check_undefined(
+  opts: &LinkOptions,
+  inputs: &[RelocatableInput],
+  dylibs: &DylibSet,
+) -> Result<()> {
+  let severity = match opts.undefined_treatment() {
+    UndefinedTreatment::Error => Severity::Error,
+    UndefinedTreatment::Warning => Severity::Warning,
+    _ => return Ok(()),
+  };
+  let allowed = dynamic_lookup_allowed(opts)?;
+  // The linker takes care of DTrace probes itself.
+  let undefined: Vec<UndefinedSymbol> = undefined::find(opts, inputs)
+    .into_iter()
+    .filter(|sym| {
+      !allowed.contains(&sym.name) && !dtrace::is_dtrace_symbol(&sym.name)
+        && !final_link::is_linker_defined(opts, &sym.name)
+        && dylibs.lookup(&sym.name).is_none()
+    })
+    .collect();
+  if severity == Severity::Warning {
+    for sym in undefined.iter() {
+      diagnostics::report(sym.diagnostic(severity));
+    }
+    return Ok(());
+  }
+  if undefined.is_empty() {
+    return Ok(());
+  }
+  for sym in undefined.iter() {
+    diagnostics::report_error(sym.diagnostic(severity));
+  }
+  // The link stops here, so find the duplicate definitions now too, rather
+  // than leaving them for the next attempt.
+  let duplicates = match resolve_inputs(inputs) {
+    Err(ResolveError::DuplicateSymbols(duplicates)) => {
+      report_duplicates(inputs, &duplicates)
+    }
+    _ => Vec::new(),
+  };
+  Err(RelocatableError::UnresolvedSymbols {
+    undefined: undefined.into_iter().map(|sym| sym.name).collect(),
+    duplicates: duplicates,
+  })
+}
+
+// -U and -dynamic_lookup_list: the undefined symbols left for dyld.
+fn dynamic_lookup_allowed(opts: &LinkOptions) -> Result<SymbolList> {
+  let mut allowed = SymbolList::new();
+  for path in opts.dynamic_lookup_lists.iter() {
+    allowed
+      .read(path)
+      .map_err(|e| RelocatableError::IoError(path.clone(), e))?;
+  }
+  for name in opts.dynamic_lookup_symbols.iter() {
+    allowed.add(name);
+  }
+  Ok(allowed)
+}
+
+// Report each symbol defined more than once, with every input defining it,
+// returning their names.
+fn report_duplicates(
+  inputs: &[RelocatableInput],
+  duplicates: &[(String, Vec<usize>)],
+) -> Vec<String> {
+  for &(ref name, ref files) in duplicates.iter() {
+    let mut message = format!("duplicate symbol: {}", name);
+    for &file in files.iter() {
+      let path = inputs[file].path.display();
+      message.push_str(&format!("\n  defined in {}", path));
+    }
+    let mut diagnostic = Diagnostic::new(Severity::Error, message);
+    diagnostic.code = Some("duplicate-symbol");
+    diagnostic.file = files.first().map(|&file| inputs[file].path.clone());
+    diagnostic.symbol = Some(name.clone());
+    diagnostics::report_error(diagnostic);
+  }
+  duplicates.iter().map(|&(ref name, _)| name.clone()).collect()
+}
+
+fn dead_strip_inputs(
+  opts: &LinkOptions,
+  inputs: &[RelocatableInput],
+) -> Vec<RelocatableInput> {
+  let liveness = Liveness::compute(opts, inputs);
+  for name in opts.why_live.iter() {
+    match liveness.why_live(inputs, name) {
+      Some(lines) => {
+        for line in lines.iter() {
+          println!("{}", line);
+        }
+      }
+      None => println!("{} is not live", name),
+    }
+  }
+  inputs
+    .iter()
+    .zip(liveness.live.iter())
+    .map(|(input, live)| dead_strip::strip(opts.arch, input, live))
+    .collect()
+}
+
+// --size-report, for the inputs as they'll be merged.
+fn report_sizes(opts: &LinkOptions, inputs: &[RelocatableInput]) {
+  if !opts.size_report {
+    return;
+  }
+  if opts.is_universal() {
+    println!("{}:", opts.arch.name());
+  }
+  print!("{}", SizeReport::compute(inputs).to_text());
+}
+
+// Read every input and merge them, up to writing the result.
+fn prepare(
+  opts: &LinkOptions,
+  dylibs: &DylibSet,
+) -> Result<(ObjectOutput, Vec<RelocatableInput>)> {
+  let inputs = read_inputs(opts, dylibs)?;
+  report_sizes(opts, &inputs);
+  let target = opts.target();
+  let keep_private_externs = opts.keep_private_externs;
+  let layout = SectionLayout::Packed;
+  let order = text_order(opts, &inputs)?;
+  // A final link's image has them instead of the merged object.
+  let extra = if opts.output_kind == OutputKind::Relocatable {
+    ExtraCommands::read(opts)?
+  } else {
+    ExtraCommands::default()
+  };
+  link_with_layout(
+    opts.arch,
+    &target,
+    &inputs,
+    keep_private_externs,
+    layout,
+    order.as_ref(),
+    &extra,
+  ).map(|(output, _)| (output, inputs))
+}
+
+// Read every input and merge them.
+pub fn build(opts: &LinkOptions) -> Result<Vec<u8>> {
+  Ok(prepare(opts, &DylibSet::new())?.0.to_vec())
+}
+
+// An input with debug info, as the output's debug map names it: where it
+// was read from, when it was last modified (or 0), and the index in the
+// merged object of each symbol it defines.
+#[derive(Debug, Clone)]
+pub struct DebugInput {
+  pub path: PathBuf,
+  pub mtime: u64,
+  pub symbols: Vec<usize>,
+}
+
+// The inputs of `merged` which have __DWARF sections. Each input's locals
+// are in the merged object in order, before everything else; the symbols
+// it defines for others are found by name.
+fn debug_inputs(
+  opts: &LinkOptions,
+  inputs: &[RelocatableInput],
+  merged: &ObjectFile,
+) -> Vec<DebugInput> {
+  let is_defined = |sym: &Nlist| {
+    !sym.is_stab() && sym.n_type & N_TYPE == N_SECT
+  };
+  let globals: HashMap<&str, usize> = merged
+    .symbols
+    .iter()
+    .enumerate()
+    .filter(|&(_, sym)| is_defined(sym))
+    .filter(|&(_, sym)| sym.n_type & (N_EXT | N_PEXT) != 0)
+    .map(|(i, sym)| (sym.name.as_str(), i))
+    .collect();
+  let mut debug: Vec<DebugInput> = Vec::new();
+  let mut next_local = 0;
+  for input in inputs.iter() {
+    let object = &input.object;
+    let has_dwarf =
+      object.sections.iter().any(|sect| sect.segname == "__DWARF");
+    let mut symbols: Vec<usize> = Vec::new();
+    for sym in object.symbols.iter() {
+      if !sym.is_external() {
+        if is_defined(sym) {
+          symbols.push(next_local);
+        }
+        next_local += 1;
+      } else if is_defined(sym) {
+        symbols.extend(globals.get(sym.name.as_str()));
+      }
+    }
+    if !has_dwarf {
+      continue;
+    }
+    let mtime = fs::metadata(&input.path)
+      .and_then(|meta| meta.modified())
+      .ok()
+      .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+      .map_or(0, |time| time.as_secs());
+    debug.push(DebugInput {
+      path: oso_path(opts, &input.path),
+      mtime: if opts.reproducible { 0 } else { mtime },
+      symbols: symbols,
+    });
+  }
+  debug
+}
+
+// The path the debug map names `path` by: without -oso_prefix, if it has
+// that prefix.
+fn oso_path(opts: &LinkOptions, path: &Path) -> PathBuf {
+  let prefix = match opts.oso_prefix {
+    Some(ref prefix) if prefix.as_path() == Path::new(".") => {
+      env::current_dir().ok()
+    }
+    ref prefix => prefix.clone(),
+  };
+  match prefix.as_ref().and_then(|prefix| path.strip_prefix(prefix).ok()) {
+    Some(relative) => relative.to_path_buf(),
+    None => path.to_path_buf(),
+  }
+}
+
+// Merge the inputs of a final link into one object, as -r would, for the
+// image to be built from, along with the inputs its debug map names.
+// Undefined symbols may be found in `dylibs`.
+pub fn merge_for_final_link(
+  opts: &LinkOptions,
+  dylibs: &DylibSet,
+) -> Result<(ObjectFile, Vec<DebugInput>)> {
+  let (output, inputs) = prepare(opts, dylibs)?;
+  if opts.output_kind == OutputKind::KextBundle {
+    kext::check_kmod_info(&inputs)?;
+  }
+  let object = ObjectFile::parse(&output.to_vec())
+    .map_err(|e| RelocatableError::Malformed(opts.output_path.clone(), e))?;
+  let debug = debug_inputs(opts, &inputs, &object);
+  Ok((object, debug))
+}
+
+// Relink reusing the previous layout when possible, and rewrite only what
+// changed in the output.
+fn run_incremental(opts: &LinkOptions) -> Result<()> {
+  let output_path = &opts.output_path;
+  let io_error = |e| RelocatableError::IoError(output_path.clone(), e);
+  let options = incremental::options_digest(opts);
+  let mut digests: Vec<[u8; 16]> = Vec::new();
+  for path in opts.input_paths.iter() {
+    digests.push(md5(&read_file(opts, path)?));
+  }
+  let state = State::load(output_path);
+  if let Some(ref state) = state {
+    if state.is_current(&options, &digests) && output_path.exists() {
+      return Ok(());
+    }
+  }
+
+  let inputs = read_inputs(opts, &DylibSet::new())?;
+  report_sizes(opts, &inputs);
+  let previous = state.and_then(|state| {
+    let objects: Vec<(&Path, &ObjectFile)> = inputs
+      .iter()
+      .map(|input| (input.path.as_path(), &input.object))
+      .collect();
+    state.reusable_slots(&options, &objects)
+  });
+  let layout = SectionLayout::Slotted(previous.as_ref().map(|s| &s[..]));
+  let (output, slots) = link_with_layout(
+    opts.arch,
+    &opts.target(),
+    &inputs,
+    opts.keep_private_externs,
+    layout,
+    text_order(opts, &inputs)?.as_ref(),
+    &ExtraCommands::read(opts)?,
+  )?;
+  timing::time("write", || {
+    if previous.is_some() && output_path.exists() {
+      incremental::patch_output(output_path, &output.to_vec()).map(|_| ())
+    } else {
+      output::write(output_path, output.size(), |buf| output.write_to(buf))
+    }
+  }).map_err(io_error)?;
+
+  // Bitcode inputs are replaced by the LTO object, so they aren't recorded
+  // one to one and the next link starts from scratch.
+  let inputs = if inputs.len() == digests.len() {
+    inputs
+      .iter()
+      .zip(digests.iter())
+      .zip(slots.into_iter())
+      .map(|((input, digest), input_slots)| InputRecord {
+        path: input.path.clone(),
+        digest: *digest,
+        slots: input_slots,
+      })
+      .collect()
+  } else {
+    Vec::new()
+  };
+  State {
+    options: options,
+    inputs: inputs,
+  }.save(output_path)
+    .map_err(io_error)
+}
+
+// Merge the inputs and write the result to the output path.
+pub fn run(opts: &LinkOptions) -> Result<()> {
+  if opts.incremental {
+    return run_incremental(opts);
+  }
+  let (output, _) = prepare(opts, &DylibSet::new())?;
+  timing::time("write", || {
+    output::write(&opts.output_path, output.size(), |buf| {
+      output.write_to(buf)
+    })
+  }).map_err(|e| RelocatableError::IoError(opts.output_path.clone(), e))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::env;
+  use std::fs;
+  use std::process;
+
+  use args::OutputKind;
+  use dead_strip::Liveness;
+  use macho::load_command::{load_commands, LC_NOTE, LC_SEGMENT};
+  use macho::object_file::ObjectSection;
+  use macho::reloc::ARM64_RELOC_BRANCH26;
+  use macho::symtab::N_COLD_FUNC;
+  use macho::{get_u32, get_u64, Platform, Version, CPU_TYPE_X86_64, MH_MAGIC,
+              S_ATTR_NO_DEAD_STRIP,
+              S_INTERPOSING};
+
+  fn target() -> Target {
+    Target {
+      platform: Platform::MacOS,
+      min_version: Version::new(11, 0, 0),
+      sdk_version: Version::new(11, 0, 0),
+      variant: None,
+    }
+  }
+
+  // An object defining _f<n> (which calls _f<n + 1>), a weak _shared, and a
+  // pointer in __data to its own code.
+  fn object(n: usize) -> ObjectFile {
+    let text = ObjectSection {
+      segname: "__TEXT".to_string(),
+      sectname: "__text".to_string(),
+      size: 6,
+      align: 4,
+      flags: 0x8000_0400,
+      contents: vec![0xe8, 0, 0, 0, 0, 0xc3],
+      relocs: vec![RelocationInfo {
+        address: 1,
+        symbolnum: 2,
+        pcrel: true,
+        length: 2,
+        external: true,
+        kind: X86_64_RELOC_BRANCH,
+        scattered: None,
+      }],
+      ..Default::default()
+    };
+    let data = ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__data".to_string(),
+      addr: 8,
+      size: 8,
+      align: 3,
+      contents: vec![0; 8],
+      relocs: vec![RelocationInfo {
+        address: 0,
+        symbolnum: 1,
+        pcrel: false,
+        length: 3,
+        external: false,
+        kind: X86_64_RELOC_UNSIGNED,
+        scattered: None,
+      }],
+      ..Default::default()
+    };
+    let sym = |name: String, n_type: u8, n_sect: u8, n_desc: u16, value| Nlist {
+      name: name,
+      n_type: n_type,
+      n_sect: n_sect,
+      n_desc: n_desc,
+      n_value: value,
+    };
+    ObjectFile {
+      cputype: CPU_TYPE_X86_64,
+      cpusubtype: 3,
+      flags: MH_SUBSECTIONS_VIA_SYMBOLS,
+      sections: vec![text, data],
+      symbols: vec![
+        sym(format!("_f{}", n), N_SECT | N_EXT, 1, 0, 0),
+        sym("_shared".to_string(), N_SECT | N_EXT, 2, 0x80, 8),
+        sym(format!("_f{}", n + 1), N_EXT, 0, 0, 0),
+      ],
+      platform: Some((Platform::MacOS, Version::new(11, 0, 0))),
+      loh: Vec::new(),
+      data_in_code: Vec::new(),
+    }
+  }
+
+  fn inputs(count: usize) -> Vec<RelocatableInput> {
+    (0..count)
+      .map(|n| RelocatableInput {
+        path: PathBuf::from(format!("f{}.o", n)),
+        object: object(n),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn merges_objects_into_one() {
+    let output = link(Arch::X86_64, &target(), &inputs(2), false).unwrap();
+    let object = ObjectFile::parse(&output).unwrap();
+    assert_eq!(object.cputype, CPU_TYPE_X86_64);
+    let names: Vec<&str> =
+      object.sections.iter().map(|s| s.sectname.as_str()).collect();
+    assert_eq!(names, vec!["__text", "__data"]);
+    // f1.o's __text is 16-byte aligned after f0.o's.
+    let text = &object.sections[0];
+    assert_eq!(text.size, 22);
+    let defined = |name: &str| {
+      object
+        .symbols
+        .iter()
+        .position(|sym| sym.name == name && !sym.is_undefined())
+    };
+    let f1 = defined("_f1").unwrap();
+    assert_eq!(object.symbols[f1].n_value, text.addr + 16);
+    assert!(defined("_f2").is_none());
+    // _f0's call is now to the _f1 defined here.
+    assert_eq!(text.relocs[0].symbolnum as usize, f1);
+    assert!(text.relocs[0].external);
+    // f1.o's pointer to its own code moved with it.
+    let data = &object.sections[1];
+    let pointer = data.relocs.iter().find(|r| r.address == 8).unwrap();
+    assert!(!pointer.external);
+    assert_eq!(data.contents[8..16], [16, 0, 0, 0, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn relocates_every_section() {
+    let output = link(Arch::X86_64, &target(), &inputs(8), false).unwrap();
+    let object = ObjectFile::parse(&output).unwrap();
+    let (text, data) = (&object.sections[0], &object.sections[1]);
+    // Each _f<n> calls the next one, and f<n>.o's pointer is to its code.
+    for n in 0..8 {
+      let call = &text.relocs[n];
+      assert_eq!(call.address, 16 * n as u32 + 1);
+      let callee = &object.symbols[call.symbolnum as usize];
+      assert_eq!(callee.name, format!("_f{}", n + 1));
+      let pointer = get_u64(&data.contents, 8 * n, "").unwrap();
+      assert_eq!(pointer, text.addr + 16 * n as u64);
+    }
+    let again = link(Arch::X86_64, &target(), &inputs(8), false).unwrap();
+    assert_eq!(again, output);
+
+    // Sections are relocated in parallel, but the error is still the one
+    // from the first input.
+    let mut inputs = inputs(8);
+    inputs[5].object.sections[0].relocs[0].symbolnum = 99;
+    inputs[2].object.sections[1].relocs[0].symbolnum = 99;
+    match link(Arch::X86_64, &target(), &inputs, false) {
+      Err(RelocatableError::BadRelocationTarget(path, 99)) => {
+        assert_eq!(path, PathBuf::from("f2.o"));
+      }
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+  }
+
+  #[test]
+  fn moves_data_in_code_with_its_section() {
+    let mut inputs = inputs(2);
+    for input in inputs.iter_mut() {
+      input.object.data_in_code = vec![DataInCodeEntry {
+        offset: 5,
+        length: 1,
+        kind: 1,
+      }];
+    }
+    let output = link(Arch::X86_64, &target(), &inputs, false).unwrap();
+    let object = ObjectFile::parse(&output).unwrap();
+    // The second __text is aligned to 16 bytes after the first.
+    let offsets: Vec<u32> =
+      object.data_in_code.iter().map(|entry| entry.offset).collect();
+    assert_eq!(offsets, vec![5, 21]);
+  }
+
+  #[test]
+  fn keeps_interposing_tables() {
+    let mut inputs = inputs(1);
+    // A replacement for _f1: pointers to _f0 and to _f1, in a section made
+    // with __attribute__((section("__DATA,__interpose"))).
+    let reloc = |address, symbolnum, external| RelocationInfo {
+      address: address,
+      symbolnum: symbolnum,
+      pcrel: false,
+      length: 3,
+      external: external,
+      kind: X86_64_RELOC_UNSIGNED,
+      scattered: None,
+    };
+    inputs[0].object.sections.push(ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__interpose".to_string(),
+      addr: 16,
+      size: 16,
+      align: 3,
+      contents: vec![0; 16],
+      relocs: vec![reloc(0, 1, false), reloc(8, 2, true)],
+      ..Default::default()
+    });
+    let opts = LinkOptions {
+      output_kind: OutputKind::Executable,
+      dead_strip: true,
+      ..Default::default()
+    };
+    let liveness = Liveness::compute(&opts, &inputs);
+    assert_eq!(liveness.live[0], vec![true, false, true]);
+
+    let output = link(Arch::X86_64, &target(), &inputs, false).unwrap();
+    let object = ObjectFile::parse(&output).unwrap();
+    let interpose = &object.sections[2];
+    assert_eq!(interpose.sectname, "__interpose");
+    assert_eq!(interpose.flags & SECTION_TYPE, S_INTERPOSING);
+  }
+
+  #[test]
+  fn moves_cold_functions_after_text() {
+    let mut marked = inputs(3);
+    marked[1].object.symbols[0].n_desc |= N_COLD_FUNC;
+    let split = cold_split::split(&mut marked, None);
+    assert_eq!(split.functions, vec!["_f1".to_string()]);
+    assert_eq!((split.sections, split.bytes), (1, 6));
+
+    let output = link(Arch::X86_64, &target(), &marked, false).unwrap();
+    let object = ObjectFile::parse(&output).unwrap();
+    let names: Vec<&str> =
+      object.sections.iter().map(|s| s.sectname.as_str()).collect();
+    assert_eq!(names, vec!["__text", "__text_cold", "__data"]);
+    let f1 = object
+      .symbols
+      .iter()
+      .find(|sym| sym.name == "_f1" && !sym.is_undefined())
+      .unwrap();
+    assert_eq!((f1.n_sect, f1.n_value), (2, object.sections[1].addr));
+    let shared = object.symbols.iter().find(|sym| sym.name == "_shared");
+    assert_eq!(shared.map(|sym| sym.n_sect), Some(3));
+
+    // With a profile, what it didn't sample is cold too.
+    let mut sampled = inputs(3);
+    let profile = Profile::parse("_f0;_f1 10\n_f0 5\n").unwrap();
+    let split = cold_split::split(&mut sampled, Some(&profile));
+    assert_eq!(split.functions, vec!["_f2".to_string()]);
+    assert!(cold_split::is_cold_part("_parse.cold.1"));
+    assert!(cold_split::is_cold_part("_parse.cold"));
+    assert!(!cold_split::is_cold_part("_parse.colder"));
+  }
+
+  #[test]
+  fn reports_what_split_cold_moved_only_when_asked() {
+    let mut marked = inputs(1);
+    marked[0].object.symbols[0].n_desc |= N_COLD_FUNC;
+    let data = link(Arch::X86_64, &target(), &marked, false).unwrap();
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      split_cold: true,
+      min_os_version: Version::new(11, 0, 0),
+      ..Default::default()
+    };
+    opts.input_paths.push(PathBuf::from("f0.o"));
+    opts.input_buffers.push((PathBuf::from("f0.o"), Arc::new(data)));
+
+    let (output, notes) = diagnostics::capture(|| build(&opts).unwrap());
+    assert!(notes.is_empty(), "{:?}", notes);
+    let object = ObjectFile::parse(&output).unwrap();
+    assert_eq!(object.sections[0].sectname, "__text_cold");
+
+    opts.split_cold_report = true;
+    let (_, notes) = diagnostics::capture(|| build(&opts).unwrap());
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].severity, Severity::Note);
+    assert!(notes[0].message.starts_with("split cold: moved 1 functions"));
+  }
+
+  #[test]
+  fn linking_twice_is_identical() {
+    let first = link(Arch::X86_64, &target(), &inputs(64), false).unwrap();
+    let second = link(Arch::X86_64, &target(), &inputs(64), false).unwrap();
+    assert!(first == second);
+  }
+
+  #[test]
+  fn linking_files_twice_is_identical() {
+    let dir =
+      env::temp_dir().join(format!("mold-reproducible-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      reproducible: true,
+      ..Default::default()
+    };
+    for (n, input) in inputs(64).into_iter().enumerate() {
+      let path = dir.join(format!("f{}.o", n));
+      let data = link(Arch::X86_64, &target(), &[input], false).unwrap();
+      fs::write(&path, data).unwrap();
+      opts.input_paths.push(path);
+    }
+    let first = build(&opts).unwrap();
+    let second = build(&opts).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(first == second);
+  }
+
+  #[test]
+  fn compiles_bitcode_with_the_lto_library() {
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      min_os_version: Version::new(11, 0, 0),
+      lto_library: Some(PathBuf::from("/nonexistent/libLTO.dylib")),
+      ..Default::default()
+    };
+    let bitcode = vec![0x42, 0x43, 0xc0, 0xde, 0x35, 0x14, 0, 0];
+    assert!(lto::is_bitcode(&bitcode));
+    opts.input_paths.push(PathBuf::from("f.bc"));
+    opts.input_buffers.push((PathBuf::from("f.bc"), Arc::new(bitcode)));
+    // -lto_library picks libLTO over the built-in backend, and loading it
+    // is where the link fails.
+    assert_eq!(opts.lto_backend(), LtoBackend::LibLto);
+    match build(&opts) {
+      Err(RelocatableError::Lto(LtoError::LoadFailed(path, _))) => {
+        assert_eq!(path, PathBuf::from("/nonexistent/libLTO.dylib"))
+      }
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+
+    // Without bitcode, libLTO isn't needed.
+    let data = link(Arch::X86_64, &target(), &inputs(1), false).unwrap();
+    opts.input_paths = vec![PathBuf::from("f0.o")];
+    opts.input_buffers = vec![(PathBuf::from("f0.o"), Arc::new(data))];
+    build(&opts).unwrap();
+  }
+
+  #[test]
+  fn relinks_incrementally_in_place() {
+    let dir =
+      env::temp_dir().join(format!("mold-incremental-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let write_input = |n: usize, code: Vec<u8>| {
+      let mut object = object(n);
+      object.sections[0].size = code.len() as u64;
+      object.sections[0].contents = code;
+      let input = RelocatableInput {
+        path: PathBuf::from(format!("f{}.o", n)),
+        object: object,
+      };
+      let data = link(Arch::X86_64, &target(), &[input], false).unwrap();
+      fs::write(dir.join(format!("f{}.o", n)), data).unwrap();
+    };
+    let call = vec![0xe8, 0, 0, 0, 0, 0xc3];
+    write_input(0, call.clone());
+    write_input(1, call.clone());
+    let output_path = dir.join("out.o");
+    let opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      incremental: true,
+      output_path: output_path.clone(),
+      input_paths: vec![dir.join("f0.o"), dir.join("f1.o")],
+      ..Default::default()
+    };
+    let f1 = || {
+      let object = ObjectFile::parse(&fs::read(&output_path).unwrap());
+      let object = object.unwrap();
+      let f1 = object.symbols.iter().find(|sym| sym.name == "_f1").unwrap();
+      f1.n_value - object.sections[0].addr
+    };
+
+    run(&opts).unwrap();
+    let state = State::load(&output_path).unwrap();
+    assert_eq!(state.inputs.len(), 2);
+    let slot = state.inputs[0].slots[0];
+    assert_eq!(slot, Slot { offset: 0, reserved: 16 });
+    assert_eq!(f1(), 16);
+
+    // Nothing changed, so the output is left alone.
+    fs::write(&output_path, b"stale").unwrap();
+    run(&opts).unwrap();
+    assert_eq!(fs::read(&output_path).unwrap(), b"stale");
+    fs::remove_file(&output_path).unwrap();
+    run(&opts).unwrap();
+
+    // f0.o grows, but still fits in its slot.
+    let mut grown = call.clone();
+    grown.extend_from_slice(&[0x90; 8]);
+    write_input(0, grown);
+    run(&opts).unwrap();
+    assert_eq!(f1(), 16);
+    let object = ObjectFile::parse(&fs::read(&output_path).unwrap()).unwrap();
+    assert_eq!(object.sections[0].contents[6..14], [0x90; 8]);
+
+    // Past its slot, everything after it moves.
+    let mut grown = call.clone();
+    grown.extend_from_slice(&[0x90; 16]);
+    write_input(0, grown);
+    run(&opts).unwrap();
+    assert_eq!(f1(), 32);
+    let state = State::load(&output_path).unwrap();
+    assert_eq!(state.inputs[1].slots[0].offset, 32);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn reads_and_resolves_inputs_in_parallel() {
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      min_os_version: Version::new(11, 0, 0),
+      ..Default::default()
+    };
+    for (n, input) in inputs(32).into_iter().enumerate() {
+      let data = link(Arch::X86_64, &target(), &[input], false).unwrap();
+      let path = PathBuf::from(format!("f{}.o", n));
+      opts.input_paths.push(path.clone());
+      opts.input_buffers.push((path, Arc::new(data)));
+    }
+    // In command-line order, whichever thread read them.
+    let read = read_inputs(&opts, &DylibSet::new()).unwrap();
+    let paths: Vec<&PathBuf> = read.iter().map(|input| &input.path).collect();
+    assert_eq!(paths, opts.input_paths.iter().collect::<Vec<_>>());
+    let table = resolve_inputs(&read).unwrap();
+    let shared = table.lookup("_shared").unwrap();
+    assert_eq!((shared.file, shared.weak), (0, true));
+    assert_eq!(shared.coalesced, (1..32).collect::<Vec<usize>>());
+    assert_eq!(table.undefined(), vec!["_f32"]);
+
+    let mut read = read;
+    read[7].object.symbols[0].name = "_f3".to_string();
+    match resolve_inputs(&read) {
+      Err(ResolveError::DuplicateSymbols(duplicates)) => {
+        assert_eq!(duplicates, vec![("_f3".to_string(), vec![3, 7])])
+      }
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+
+    // The error is the first input's, as reading in order would find.
+    for &n in [20, 5].iter() {
+      opts.input_buffers[n].1 = Arc::new(vec![0; 16]);
+    }
+    match read_inputs(&opts, &DylibSet::new()) {
+      Err(RelocatableError::Malformed(path, _)) => {
+        assert_eq!(path, PathBuf::from("f5.o"))
+      }
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+  }
+
+  // A BSD archive of object(n) for each of `members`.
+  fn archive(members: &[usize]) -> Vec<u8> {
+    archive_of(members.iter().map(|&n| object(n)).collect())
+  }
+
+  // A BSD archive of `members`, named f0.o, f1.o and so on by what they
+  // define.
+  fn archive_of(members: Vec<ObjectFile>) -> Vec<u8> {
+    let mut data: Vec<u8> = b"!<arch>\n".to_vec();
+    for member in members {
+      let name = format!("{}.o", &member.symbols[0].name[1..]);
+      let input = RelocatableInput {
+        path: PathBuf::from(name.clone()),
+        object: member,
+      };
+      let object = link(Arch::X86_64, &target(), &[input], false).unwrap();
+      let header = format!(
+        "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+        name,
+        0,
+        0,
+        0,
+        644,
+        object.len()
+      );
+      data.extend_from_slice(header.as_bytes());
+      data.extend_from_slice(&object);
+      if data.len() % 2 != 0 {
+        data.push(b'\n');
+      }
+    }
+    data
+  }
+
+  #[test]
+  fn loads_archive_members_on_demand() {
+    let main = link(Arch::X86_64, &target(), &inputs(1), false).unwrap();
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      min_os_version: Version::new(11, 0, 0),
+      ..Default::default()
+    };
+    let lib = PathBuf::from("libf.a");
+    for (path, data) in vec![("f0.o", main), ("libf.a", archive(&[1, 9, 2]))] {
+      opts.input_paths.push(PathBuf::from(path));
+      opts.input_buffers.push((PathBuf::from(path), Arc::new(data)));
+    }
+    let loaded = |opts: &LinkOptions| -> Vec<String> {
+      read_inputs(opts, &DylibSet::new())
+        .unwrap()
+        .iter()
+        .map(|input| input.path.display().to_string())
+        .collect()
+    };
+    // _f0 needs _f1, which needs _f2 from later in the archive. Nothing
+    // needs _f9.
+    assert_eq!(loaded(&opts), vec!["f0.o", "libf.a(f1.o)", "libf.a(f2.o)"]);
+    let output = build(&opts).unwrap();
+    let object = ObjectFile::parse(&output).unwrap();
+    let undefined: Vec<&str> = object
+      .symbols
+      .iter()
+      .filter(|sym| sym.is_undefined())
+      .map(|sym| sym.name.as_str())
+      .collect();
+    assert_eq!(undefined, vec!["_f3"]);
+
+    opts.force_load.push(lib);
+    assert_eq!(
+      loaded(&opts),
+      vec!["f0.o", "libf.a(f1.o)", "libf.a(f9.o)", "libf.a(f2.o)"]
+    );
+  }
+
+  #[test]
+  fn dead_strips_what_nothing_exports_or_calls() {
+    // Only _f1 is exported. It calls _f2, and nothing calls _f0 or points
+    // to any __data.
+    let mut inputs = inputs(3);
+    for input in inputs.iter_mut() {
+      for sym in input.object.symbols.iter_mut() {
+        if !sym.is_undefined() && sym.name != "_f1" {
+          sym.n_type |= N_PEXT;
+        }
+      }
+    }
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      dead_strip: true,
+      min_os_version: Version::new(11, 0, 0),
+      ..Default::default()
+    };
+    let liveness = Liveness::compute(&opts, &inputs);
+    let live = vec![vec![false, false], vec![true, false], vec![true, false]];
+    assert_eq!(liveness.live, live);
+    assert_eq!(
+      liveness.why_live(&inputs, "_f2").unwrap(),
+      vec!["_f2 from f2.o", "  _f1 from f1.o"]
+    );
+    assert_eq!(liveness.why_live(&inputs, "_f0"), None);
+
+    for input in inputs.into_iter() {
+      let data = link(Arch::X86_64, &target(), &[input], true).unwrap();
+      let path = PathBuf::from(format!("f{}.o", opts.input_paths.len()));
+      opts.input_paths.push(path.clone());
+      opts.input_buffers.push((path, Arc::new(data)));
+    }
+    let object = ObjectFile::parse(&build(&opts).unwrap()).unwrap();
+    let names: Vec<&str> =
+      object.sections.iter().map(|s| s.sectname.as_str()).collect();
+    assert_eq!(names, vec!["__text"]);
+    assert_eq!(object.sections[0].size, 22);
+    let defined = |name: &str| {
+      object
+        .symbols
+        .iter()
+        .any(|sym| sym.name == name && !sym.is_undefined())
+    };
+    assert!(defined("_f1") && defined("_f2") && !defined("_f0"));
+  }
+
+  // object(n), with an __objc_imageinfo of `flags`.
+  fn objc_object(n: usize, flags: u32) -> ObjectFile {
+    let mut object = object(n);
+    let mut contents = vec![0; 8];
+    set_u32(&mut contents, 4, flags);
+    object.sections.push(ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__objc_imageinfo".to_string(),
+      addr: 0x10,
+      size: 8,
+      align: 2,
+      flags: S_ATTR_NO_DEAD_STRIP,
+      contents: contents,
+      ..Default::default()
+    });
+    object
+  }
+
+  fn link_objects(objects: Vec<ObjectFile>) -> Result<Vec<u8>> {
+    link_objects_with(objects, |_| ())
+  }
+
+  fn link_objects_with<F: FnOnce(&mut LinkOptions)>(
+    objects: Vec<ObjectFile>,
+    configure: F,
+  ) -> Result<Vec<u8>> {
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      min_os_version: Version::new(11, 0, 0),
+      ..Default::default()
+    };
+    configure(&mut opts);
+    for (n, object) in objects.into_iter().enumerate() {
+      let path = PathBuf::from(format!("f{}.o", n));
+      let input = RelocatableInput {
+        path: path.clone(),
+        object: object,
+      };
+      let data = link(Arch::X86_64, &target(), &[input], false).unwrap();
+      opts.input_paths.push(path.clone());
+      opts.input_buffers.push((path, Arc::new(data)));
+    }
+    build(&opts)
+  }
+
+  #[test]
+  fn merges_objc_image_info() {
+    // Swift 5 with class properties in categories, then plain ObjC without.
+    let objects = vec![objc_object(0, 0x740), objc_object(1, 0)];
+    let output = link_objects(objects).unwrap();
+    let object = ObjectFile::parse(&output).unwrap();
+    let infos: Vec<&ObjectSection> = object
+      .sections
+      .iter()
+      .filter(|sect| sect.sectname == "__objc_imageinfo")
+      .collect();
+    assert_eq!(infos.len(), 1);
+    assert_eq!(infos[0].contents, vec![0, 0, 0, 0, 0, 7, 0, 0]);
+
+    let objects = vec![objc_object(0, 0x700), objc_object(1, 0x600)];
+    match link_objects(objects) {
+      Err(RelocatableError::Objc(ObjcError::SwiftVersionMismatch(
+        ref path,
+        6,
+        7,
+      ))) if path == Path::new("f1.o") => (),
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+  }
+
+  #[test]
+  fn loads_objc_members_for_objc_flag() {
+    let main = link(Arch::X86_64, &target(), &inputs(1), false).unwrap();
+    // f7.o only has a class list, which nothing refers to by name.
+    let mut classes = object(7);
+    classes.sections.push(ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: "__objc_classlist".to_string(),
+      addr: 0x10,
+      size: 8,
+      align: 3,
+      contents: vec![0; 8],
+      ..Default::default()
+    });
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      min_os_version: Version::new(11, 0, 0),
+      ..Default::default()
+    };
+    let lib = archive_of(vec![object(1), classes]);
+    for (path, data) in vec![("f0.o", main), ("libf.a", lib)] {
+      opts.input_paths.push(PathBuf::from(path));
+      opts.input_buffers.push((PathBuf::from(path), Arc::new(data)));
+    }
+    let loaded = |opts: &LinkOptions| -> Vec<String> {
+      read_inputs(opts, &DylibSet::new())
+        .unwrap()
+        .iter()
+        .map(|input| input.path.display().to_string())
+        .collect()
+    };
+    assert_eq!(loaded(&opts), vec!["f0.o", "libf.a(f1.o)"]);
+    opts.objc = true;
+    assert_eq!(loaded(&opts), vec!["f0.o", "libf.a(f1.o)", "libf.a(f7.o)"]);
+    let object = ObjectFile::parse(&build(&opts).unwrap()).unwrap();
+    assert!(object
+      .sections
+      .iter()
+      .any(|sect| sect.sectname == "__objc_classlist"));
+  }
+
+  // A class Foo whose instance methods are one 0xaa method, and a category
+  // on it adding one 0xbb method.
+  fn category_object() -> ObjectFile {
+    let pointer = |address: u32, sect: u32| RelocationInfo {
+      address: address,
+      symbolnum: sect,
+      pcrel: false,
+      length: 3,
+      external: false,
+      kind: X86_64_RELOC_UNSIGNED,
+      scattered: None,
+    };
+    let section = |sectname: &str, addr: u64, size: usize| ObjectSection {
+      segname: "__DATA".to_string(),
+      sectname: sectname.to_string(),
+      addr: addr,
+      size: size as u64,
+      align: 3,
+      contents: vec![0; size],
+      ..Default::default()
+    };
+    // class_t, whose data is the class_ro_t at the start of __objc_const.
+    let mut class = section("__objc_data", 0, 40);
+    set_u64(&mut class.contents, 32, 0x28);
+    class.relocs = vec![pointer(32, 2)];
+    // The class_ro_t, its method list at 72, the category_t at 104 and its
+    // method list at 160.
+    let mut consts = section("__objc_const", 0x28, 192);
+    let method_list = |contents: &mut Vec<u8>, at: usize, fill: u8| {
+      set_u32(contents, at, 24);
+      set_u32(contents, at + 4, 1);
+      for byte in contents[at + 8..at + 32].iter_mut() {
+        *byte = fill;
+      }
+    };
+    method_list(&mut consts.contents, 72, 0xaa);
+    method_list(&mut consts.contents, 160, 0xbb);
+    set_u64(&mut consts.contents, 32, 0x28 + 72);
+    set_u64(&mut consts.contents, 104 + 16, 0x28 + 160);
+    consts.relocs = vec![pointer(32, 2), pointer(112, 1), pointer(120, 2)];
+    let mut catlist = section("__objc_catlist", 0xe8, 8);
+    set_u64(&mut catlist.contents, 0, 0x28 + 104);
+    catlist.relocs = vec![pointer(0, 2)];
+    ObjectFile {
+      cputype: CPU_TYPE_X86_64,
+      cpusubtype: 3,
+      flags: MH_SUBSECTIONS_VIA_SYMBOLS,
+      sections: vec![class, consts, catlist],
+      symbols: vec![Nlist {
+        name: "_OBJC_CLASS_$_Foo".to_string(),
+        n_type: N_SECT | N_EXT,
+        n_sect: 1,
+        n_desc: 0,
+        n_value: 0,
+      }],
+      platform: Some((Platform::MacOS, Version::new(11, 0, 0))),
+      loh: Vec::new(),
+      data_in_code: Vec::new(),
+    }
+  }
+
+  // The method list the class_ro_t points at: its count and the first byte
+  // of each method.
+  fn class_methods(output: &[u8]) -> (u32, Vec<u8>) {
+    let object = ObjectFile::parse(output).unwrap();
+    let at = |addr: u64| {
+      let sect = object
+        .sections
+        .iter()
+        .find(|sect| sect.addr <= addr && addr < sect.addr + sect.size)
+        .unwrap();
+      (sect, (addr - sect.addr) as usize)
+    };
+    let class = object
+      .sections
+      .iter()
+      .find(|sect| sect.sectname == "__objc_data")
+      .unwrap();
+    let (ro, offset) = at(get_u64(&class.contents, 32, "").unwrap());
+    let list = get_u64(&ro.contents, offset + 32, "").unwrap();
+    let (sect, offset) = at(list);
+    let count = get_u32(&sect.contents, offset + 4, "").unwrap();
+    let firsts = (0..count as usize)
+      .map(|i| sect.contents[offset + 8 + 24 * i])
+      .collect();
+    (count, firsts)
+  }
+
+  #[test]
+  fn merges_categories_into_their_classes() {
+    let catlist = |output: &[u8]| {
+      ObjectFile::parse(output)
+        .unwrap()
+        .sections
+        .iter()
+        .find(|sect| sect.sectname == "__objc_catlist")
+        .map_or(0, |sect| sect.size)
+    };
+    let output = link_objects(vec![category_object()]).unwrap();
+    assert_eq!(class_methods(&output), (1, vec![0xaa]));
+    assert_eq!(catlist(&output), 8);
+
+    let merge = |opts: &mut LinkOptions| {
+      opts.objc_category_merging = Some(true);
+    };
+    let output = link_objects_with(vec![category_object()], merge).unwrap();
+    // The category's methods come first, so they override the class's.
+    assert_eq!(class_methods(&output), (2, vec![0xbb, 0xaa]));
+    assert_eq!(catlist(&output), 0);
+  }
+
+  #[test]
+  fn loads_whole_archives_for_all_load_and_force_load() {
+    let main = link(Arch::X86_64, &target(), &inputs(1), false).unwrap();
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      min_os_version: Version::new(11, 0, 0),
+      ..Default::default()
+    };
+    let archives =
+      vec![("libf.a", archive(&[1, 9])), ("libg.a", archive(&[5]))];
+    opts.input_paths.push(PathBuf::from("f0.o"));
+    opts.input_buffers.push((PathBuf::from("f0.o"), Arc::new(main)));
+    for (path, data) in archives {
+      opts.input_paths.push(PathBuf::from(path));
+      opts.input_buffers.push((PathBuf::from(path), Arc::new(data)));
+    }
+    let loaded = |opts: &LinkOptions| -> Vec<String> {
+      read_inputs(opts, &DylibSet::new())
+        .unwrap()
+        .iter()
+        .map(|input| input.path.display().to_string())
+        .collect()
+    };
+    assert_eq!(loaded(&opts), vec!["f0.o", "libf.a(f1.o)"]);
+
+    opts.force_load = vec![PathBuf::from("libg.a")];
+    assert_eq!(loaded(&opts), vec!["f0.o", "libf.a(f1.o)", "libg.a(f5.o)"]);
+
+    opts.force_load.clear();
+    opts.all_load = true;
+    assert_eq!(
+      loaded(&opts),
+      vec!["f0.o", "libf.a(f1.o)", "libf.a(f9.o)", "libg.a(f5.o)"]
+    );
+    let object = ObjectFile::parse(&build(&opts).unwrap()).unwrap();
+    let defined = |name: &str| {
+      object
+        .symbols
+        .iter()
+        .any(|sym| sym.name == name && !sym.is_undefined())
+    };
+    assert!(defined("_f9") && defined("_f5"));
+  }
+
+  #[test]
+  fn loads_and_keeps_forced_undefined_symbols() {
+    let main = link(Arch::X86_64, &target(), &inputs(1), false).unwrap();
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      min_os_version: Version::new(11, 0, 0),
+      ..Default::default()
+    };
+    for (path, data) in vec![("f0.o", main), ("libf.a", archive(&[1, 9, 2]))] {
+      opts.input_paths.push(PathBuf::from(path));
+      opts.input_buffers.push((PathBuf::from(path), Arc::new(data)));
+    }
+    opts.forced_undefined = vec!["_f9".to_string()];
+    let loaded: Vec<String> = read_inputs(&opts, &DylibSet::new())
+      .unwrap()
+      .iter()
+      .map(|input| input.path.display().to_string())
+      .collect();
+    assert_eq!(
+      loaded,
+      vec!["f0.o", "libf.a(f1.o)", "libf.a(f9.o)", "libf.a(f2.o)"]
+    );
+
+    // Executables only keep what main reaches, unless -u names it.
+    let opts = LinkOptions {
+      output_kind: OutputKind::Executable,
+      dead_strip: true,
+      forced_undefined: vec!["_f1".to_string()],
+      ..Default::default()
+    };
+    let inputs = inputs(3);
+    let liveness = Liveness::compute(&opts, &inputs);
+    let live = vec![vec![false, false], vec![true, false], vec![true, false]];
+    assert_eq!(liveness.live, live);
+    let opts = LinkOptions {
+      forced_undefined: Vec::new(),
+      ..opts
+    };
+    let liveness = Liveness::compute(&opts, &inputs);
+    assert_eq!(liveness.live, vec![vec![false, false]; 3]);
+  }
+
+  // object(n), for `arch`: _f<n> calls _f<n + 1> with `call`, at offset
+  // `at` in `code`, and __data points to the code.
+  fn object_for(arch: Arch, n: usize) -> ObjectFile {
+    let (code, at, call, pointer): (Vec<u8>, u32, u8, u8) = match arch {
+      // bl _f<n + 1>; ret
+      Arch::Arm64_32 => (
+        vec![0, 0, 0, 0x94, 0xc0, 0x03, 0x5f, 0xd6],
+        0,
+        ARM64_RELOC_BRANCH26,
+        ARM64_RELOC_UNSIGNED,
+      ),
+      // call _f<n + 1>; ret; nop; nop
+      Arch::I386 => (
+        vec![0xe8, 0, 0, 0, 0, 0xc3, 0x90, 0x90],
+        1,
+        GENERIC_RELOC_VANILLA,
+        GENERIC_RELOC_VANILLA,
+      ),
+      // bl _f<n + 1>; bx lr
+      Arch::Armv7 => (
+        vec![0, 0, 0, 0xeb, 0x1e, 0xff, 0x2f, 0xe1],
+        0,
+        ARM_RELOC_BR24,
+        ARM_RELOC_VANILLA,
+      ),
+      _ => return object(n),
+    };
+    let mut object = object(n);
+    object.cputype = arch.cputype();
+    object.cpusubtype = arch.cpusubtype();
+    let text = &mut object.sections[0];
+    text.size = code.len() as u64;
+    text.align = 2;
+    text.contents = code;
+    text.relocs[0].address = at;
+    text.relocs[0].kind = call;
+    let data = &mut object.sections[1];
+    data.size = 4;
+    data.align = 2;
+    data.contents = vec![0; 4];
+    data.relocs[0].length = 2;
+    data.relocs[0].kind = pointer;
+    object
+  }
+
+  // Link two of `arch`'s objects, and check the output is a 32-bit object
+  // for `arch` in which each call and pointer still goes where it did.
+  fn links_32_bit_objects(arch: Arch) {
+    let inputs: Vec<RelocatableInput> = (0..2)
+      .map(|n| RelocatableInput {
+        path: PathBuf::from(format!("f{}.o", n)),
+        object: object_for(arch, n),
+      })
+      .collect();
+    let output = link(arch, &target(), &inputs, false).unwrap();
+    assert_eq!(get_u32(&output, 0, "").unwrap(), MH_MAGIC);
+    assert_eq!(get_u32(&output, 4, "").unwrap(), arch.cputype());
+    assert_eq!(get_u32(&output, 28, "").unwrap(), LC_SEGMENT);
+
+    let object = ObjectFile::parse(&output).unwrap();
+    let (text, data) = (&object.sections[0], &object.sections[1]);
+    assert_eq!(text.size, 16);
+    let calls: Vec<(u32, &str)> = text
+      .relocs
+      .iter()
+      .map(|reloc| {
+        let callee = &object.symbols[reloc.symbolnum as usize];
+        (reloc.address, callee.name.as_str())
+      })
+      .collect();
+    let at = inputs[0].object.sections[0].relocs[0].address;
+    assert_eq!(calls, vec![(at, "_f1"), (at + 8, "_f2")]);
+    assert_eq!(data.size, 8);
+    assert_eq!(get_u32(&data.contents, 4, "").unwrap() as u64, text.addr + 8);
+    assert!(data.relocs.iter().all(|reloc| reloc.length == 2));
+  }
+
+  #[test]
+  fn links_arm64_32_objects() {
+    links_32_bit_objects(Arch::Arm64_32);
+  }
+
+  #[test]
+  fn links_i386_and_armv7_objects() {
+    links_32_bit_objects(Arch::I386);
+    links_32_bit_objects(Arch::Armv7);
+  }
+
+  #[test]
+  fn adds_notes_and_load_commands() {
+    let dir = env::temp_dir().join(format!("mold-notes-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (note, body) = (dir.join("note"), dir.join("body"));
+    fs::write(&note, b"build 42").unwrap();
+    fs::write(&body, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+    let data = link(Arch::X86_64, &target(), &inputs(1), false).unwrap();
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      min_os_version: Version::new(11, 0, 0),
+      ..Default::default()
+    };
+    opts.input_paths.push(PathBuf::from("f0.o"));
+    opts.input_buffers.push((PathBuf::from("f0.o"), Arc::new(data)));
+    opts.notes.push(("firmware".to_string(), note));
+    opts.extra_load_commands.push((0x7f, body));
+    let output = build(&opts);
+    fs::remove_dir_all(&dir).unwrap();
+    let output = output.unwrap();
+
+    let commands = load_commands(&output).unwrap();
+    let note = commands.iter().find(|c| c.cmd == LC_NOTE).unwrap();
+    assert_eq!(&output[note.offset + 8..note.offset + 16], b"firmware");
+    let offset = get_u64(&output, note.offset + 24, "").unwrap() as usize;
+    assert_eq!(get_u64(&output, note.offset + 32, "").unwrap(), 8);
+    assert_eq!(&output[offset..offset + 8], b"build 42");
+    let raw = commands.iter().find(|c| c.cmd == 0x7f).unwrap();
+    assert_eq!(raw.cmdsize, 16);
+    let body = &output[raw.offset + 8..raw.offset + 16];
+    assert_eq!(body, &[1, 2, 3, 4, 5, 6, 7, 8]);
+    // The symbols are still where the object says.
+    let object = ObjectFile::parse(&output).unwrap();
+    assert!(object.symbols.iter().any(|sym| sym.name == "_f0"));
+  }
+
+  #[test]
+  fn orders_text_by_order_file_and_profile() {
+    let dir = env::temp_dir().join(format!("mold-order-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (order, profile) = (dir.join("order"), dir.join("profile"));
+    fs::write(&order, "# hot first\n_f1\n").unwrap();
+    fs::write(&profile, "_main;_f1 10\n").unwrap();
+    let objects = inputs(2);
+    let mut opts = LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      min_os_version: Version::new(11, 0, 0),
+      ..Default::default()
+    };
+    for n in 0..2 {
+      let data =
+        link(Arch::X86_64, &target(), &objects[n..n + 1], false).unwrap();
+      let path = PathBuf::from(format!("f{}.o", n));
+      opts.input_paths.push(path.clone());
+      opts.input_buffers.push((path, Arc::new(data)));
+    }
+    let addresses = |opts: &LinkOptions| {
+      let object = ObjectFile::parse(&build(opts).unwrap()).unwrap();
+      let addr = |name: &str| {
+        object.symbols.iter().find(|sym| sym.name == name).unwrap().n_value
+      };
+      (addr("_f0"), addr("_f1"))
+    };
+
+    assert_eq!(addresses(&opts), (0, 16));
+    let ordered = LinkOptions {
+      order_file: Some(order),
+      ..opts.clone()
+    };
+    assert_eq!(addresses(&ordered), (16, 0));
+    let profiled = LinkOptions {
+      profile_order: Some(profile),
+      ..opts.clone()
+    };
+    assert_eq!(addresses(&profiled), (16, 0));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
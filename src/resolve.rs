@@ -0,0 +1,409 @@
+// Global symbol resolution across all the input files of a link.
+//
+// Files are added in command-line order. Weak definitions (C++ inline
+// functions, template instantiations, ...) are coalesced: the first one seen
+// wins and later copies are dropped, unless a strong definition comes along,
+// which always wins. Two strong definitions of the same name are an error.
+//
+// Files can also be added from any number of threads at once, in any order,
+// through a ConcurrentSymbolTable: a map split into shards by the name's
+// hash, each behind a lock of its own, as dashmap does. Conflicts are
+// settled by where each definition is in the command line rather than by
+// which thread got there first, so the table comes out the same as adding
+// the files one after the other.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::Mutex;
+
+use intern::{Interner, Shard, Symbol};
+use parallel;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+  Undefined,
+  Defined,
+  WeakDefined,
+}
+
+// An external symbol as read from one input file.
+#[derive(Debug, Clone)]
+pub struct InputSymbol {
+  pub name: String,
+  pub kind: SymbolKind,
+  // Visible to the other files in this link, but not exported from the
+  // output (.private_extern / visibility("hidden")).
+  pub private_extern: bool,
+  pub value: u64,
+}
+
+#[derive(Debug)]
+pub enum ResolveError {
+  // (symbol name, file index of the first definition, of the second)
+  DuplicateSymbol(String, usize, usize),
+  // Every symbol with more than one strong definition, in the order their
+  // second definitions were seen: (symbol name, the files defining it).
+  DuplicateSymbols(Vec<(String, Vec<usize>)>),
+  // Listed in -exported_symbols_list, but not defined by any input.
+  ExportedSymbolNotDefined(String),
+  // Listed in -exported_symbols_list, but private extern in its input.
+  CannotExportHidden(String),
+  // (alias, target) where the target of an -alias isn't defined.
+  AliasTargetNotDefined(String, String),
+}
+
+pub type Result<T> = ::std::result::Result<T, ResolveError>;
+
+#[derive(Debug, Clone)]
+pub struct Definition {
+  pub file: usize,
+  pub value: u64,
+  pub weak: bool,
+  pub private_extern: bool,
+  // Files whose copies of this symbol were dropped in favor of this one.
+  pub coalesced: Vec<usize>,
+}
+
+impl Definition {
+  fn new(file: usize, sym: &InputSymbol) -> Self {
+    Definition {
+      file: file,
+      value: sym.value,
+      weak: sym.kind == SymbolKind::WeakDefined,
+      private_extern: sym.private_extern,
+      coalesced: Vec::new(),
+    }
+  }
+}
+
+// The definitions and references of the names in one shard.
+#[derive(Debug, Default)]
+struct Resolution {
+  definitions: HashMap<Symbol, Definition>,
+  referenced: HashSet<Symbol>,
+}
+
+impl Resolution {
+  // True if `name` wasn't referenced before.
+  fn add_reference(&mut self, name: Symbol) -> bool {
+    self.referenced.insert(name)
+  }
+
+  fn add_definition(
+    &mut self,
+    file: usize,
+    name: Symbol,
+    sym: &InputSymbol,
+  ) -> Result<()> {
+    let new_def = Definition::new(file, sym);
+    let weak = new_def.weak;
+    match self.definitions.entry(name) {
+      Entry::Vacant(e) => {
+        e.insert(new_def);
+      }
+      Entry::Occupied(mut e) => {
+        let existing = e.get_mut();
+        match (existing.weak, weak) {
+          // Keep whatever we had: either the first weak copy, or a strong
+          // definition which beats any weak one.
+          (_, true) => existing.coalesced.push(file),
+          (true, false) => {
+            // In file order, as the concurrent table has them.
+            let mut coalesced = existing.coalesced.clone();
+            coalesced.push(existing.file);
+            coalesced.sort();
+            *existing = Definition {
+              coalesced: coalesced,
+              ..new_def
+            };
+          }
+          (false, false) => {
+            return Err(ResolveError::DuplicateSymbol(
+              sym.name.clone(),
+              existing.file,
+              file,
+            ));
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+// Where a symbol was seen: (file index, index among the file's symbols).
+type Position = (usize, usize);
+
+// The definition of a name which wins so far, where it is, and where every
+// strong definition of it is, to report duplicates.
+#[derive(Debug)]
+struct Candidate {
+  def: Definition,
+  at: Position,
+  strong: Vec<Position>,
+}
+
+impl Candidate {
+  fn new(def: Definition, at: Position) -> Self {
+    let strong = if def.weak { Vec::new() } else { vec![at] };
+    Candidate {
+      def: def,
+      at: at,
+      strong: strong,
+    }
+  }
+
+  // A strong definition beats a weak one, and otherwise the one earlier in
+  // the command line wins, whichever was added first. The weak copies
+  // which lose are coalesced into the winner.
+  fn add(&mut self, def: Definition, at: Position) {
+    if !def.weak {
+      self.strong.push(at);
+    }
+    let loser = if (def.weak, at) < (self.def.weak, self.at) {
+      let coalesced = mem::take(&mut self.def.coalesced);
+      self.at = at;
+      mem::replace(
+        &mut self.def,
+        Definition {
+          coalesced: coalesced,
+          ..def
+        },
+      )
+    } else {
+      def
+    };
+    if loser.weak {
+      self.def.coalesced.push(loser.file);
+    }
+  }
+}
+
+// One shard of a ConcurrentSymbolTable: its names, the definitions of each
+// so far, and where each was first referenced.
+#[derive(Debug, Default)]
+struct ConcurrentShard {
+  names: Shard,
+  candidates: HashMap<Symbol, Candidate>,
+  references: HashMap<Symbol, Position>,
+}
+
+impl ConcurrentShard {
+  fn add(&mut self, at: Position, sym: &InputSymbol, hash: u64) {
+    let name = self.names.intern(&sym.name, hash);
+    match sym.kind {
+      SymbolKind::Undefined => {
+        let first = self.references.entry(name).or_insert(at);
+        *first = (*first).min(at);
+      }
+      SymbolKind::Defined | SymbolKind::WeakDefined => {
+        let def = Definition::new(at.0, sym);
+        match self.candidates.entry(name) {
+          Entry::Vacant(e) => {
+            e.insert(Candidate::new(def, at));
+          }
+          Entry::Occupied(mut e) => e.get_mut().add(def, at),
+        }
+      }
+    }
+  }
+}
+
+// A symbol table which files can be added to from several threads at once,
+// as they're read. Each file's symbols are grouped by shard before any lock
+// is taken, so adding a file takes each shard's lock at most once, and
+// threads adding different files mostly hold different locks.
+#[derive(Debug)]
+pub struct ConcurrentSymbolTable {
+  shards: Vec<Mutex<ConcurrentShard>>,
+}
+
+impl Default for ConcurrentSymbolTable {
+  fn default() -> Self {
+    ConcurrentSymbolTable::new()
+  }
+}
+
+impl ConcurrentSymbolTable {
+  // Several shards per thread, so two threads rarely want the same one.
+  pub fn new() -> Self {
+    ConcurrentSymbolTable::with_shards(parallel::num_threads() * 4)
+  }
+
+  pub fn with_shards(nshards: usize) -> Self {
+    let shards = Interner::new(nshards).into_shards();
+    ConcurrentSymbolTable {
+      shards: shards
+        .into_iter()
+        .map(|names| {
+          Mutex::new(ConcurrentShard {
+            names: names,
+            ..Default::default()
+          })
+        })
+        .collect(),
+    }
+  }
+
+  // Add the external symbols of the file at index `file` of the link.
+  pub fn add_file(&self, file: usize, symbols: &[InputSymbol]) {
+    let nshards = self.shards.len();
+    let mut buckets: Vec<Vec<(usize, u64)>> = vec![Vec::new(); nshards];
+    for (i, sym) in symbols.iter().enumerate() {
+      let hash = Interner::hash(&sym.name);
+      buckets[Interner::shard_for(hash, nshards)].push((i, hash));
+    }
+    for (shard, bucket) in self.shards.iter().zip(buckets.iter()) {
+      if bucket.is_empty() {
+        continue;
+      }
+      // A thread which panicked holding the lock has already failed the
+      // link; the shard is still usable.
+      let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+      for &(i, hash) in bucket.iter() {
+        shard.add((file, i), &symbols[i], hash);
+      }
+    }
+  }
+
+  // The table once every file has been added. Every name with more than
+  // one strong definition is an error, in the order their second
+  // definitions are in the command line.
+  pub fn finish(self) -> Result<SymbolTable> {
+    let mut names: Vec<Shard> = Vec::new();
+    let mut resolved: Vec<Resolution> = Vec::new();
+    let mut references: Vec<(Position, Symbol)> = Vec::new();
+    let mut duplicates: Vec<(Position, Symbol, Vec<usize>)> = Vec::new();
+    for shard in self.shards.into_iter() {
+      let shard = shard.into_inner().unwrap_or_else(|e| e.into_inner());
+      let mut resolution = Resolution::default();
+      for (name, mut candidate) in shard.candidates.into_iter() {
+        if candidate.strong.len() > 1 {
+          candidate.strong.sort();
+          let files = candidate.strong.iter().map(|&(file, _)| file).collect();
+          duplicates.push((candidate.strong[1], name, files));
+        }
+        candidate.def.coalesced.sort();
+        resolution.definitions.insert(name, candidate.def);
+      }
+      for (name, at) in shard.references.into_iter() {
+        resolution.referenced.insert(name);
+        references.push((at, name));
+      }
+      names.push(shard.names);
+      resolved.push(resolution);
+    }
+    let names = Interner::from_shards(names);
+    if !duplicates.is_empty() {
+      duplicates.sort();
+      return Err(ResolveError::DuplicateSymbols(
+        duplicates
+          .into_iter()
+          .map(|(_, name, files)| (names.name(name).to_string(), files))
+          .collect(),
+      ));
+    }
+    references.sort();
+    Ok(SymbolTable {
+      names: names,
+      resolved: resolved,
+      reference_order: references.into_iter().map(|(_, name)| name).collect(),
+    })
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+  // Every name seen, stored once. The tables below are keyed by Symbol.
+  names: Interner,
+  // The resolution of each shard's names, indexed like the names' shards.
+  resolved: Vec<Resolution>,
+  // Referenced names in the order they were first seen, so diagnostics come
+  // out in a stable order.
+  reference_order: Vec<Symbol>,
+}
+
+impl SymbolTable {
+  pub fn new() -> Self {
+    SymbolTable {
+      ..Default::default()
+    }
+  }
+
+  pub fn add_file(
+    &mut self,
+    file: usize,
+    symbols: &[InputSymbol],
+  ) -> Result<()> {
+    for sym in symbols.iter() {
+      let name = self.names.intern(&sym.name);
+      match sym.kind {
+        SymbolKind::Undefined => {
+          if self.resolution_mut(name).add_reference(name) {
+            self.reference_order.push(name);
+          }
+        }
+        SymbolKind::Defined | SymbolKind::WeakDefined => {
+          self.resolution_mut(name).add_definition(file, name, sym)?
+        }
+      }
+    }
+    Ok(())
+  }
+
+  // Resolve every file's symbols, adding them to a ConcurrentSymbolTable
+  // from as many threads as there are. Resolution carries on past duplicate
+  // definitions, so the error lists all of them.
+  pub fn resolve_parallel(files: &[Vec<InputSymbol>]) -> Result<SymbolTable> {
+    let table = ConcurrentSymbolTable::new();
+    let indexed: Vec<(usize, &Vec<InputSymbol>)> =
+      files.iter().enumerate().collect();
+    parallel::map(&indexed, |&(file, symbols)| table.add_file(file, symbols));
+    table.finish()
+  }
+
+  // The resolution of `sym`'s shard, made if it's the first name there.
+  fn resolution_mut(&mut self, sym: Symbol) -> &mut Resolution {
+    if self.resolved.len() <= sym.shard() {
+      self.resolved.resize_with(sym.shard() + 1, Default::default);
+    }
+    &mut self.resolved[sym.shard()]
+  }
+
+  // The interned `name`, if any input mentions it.
+  pub fn symbol(&self, name: &str) -> Option<Symbol> {
+    self.names.get(name)
+  }
+
+  pub fn name(&self, sym: Symbol) -> &str {
+    self.names.name(sym)
+  }
+
+  // How many names the inputs mention.
+  pub fn len(&self) -> usize {
+    self.names.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.names.is_empty()
+  }
+
+  pub fn lookup(&self, name: &str) -> Option<&Definition> {
+    self.definition(self.symbol(name)?)
+  }
+
+  pub fn definition(&self, sym: Symbol) -> Option<&Definition> {
+    self.resolved.get(sym.shard())?.definitions.get(&sym)
+  }
+
+  // Referenced symbols which no input defines.
+  pub fn undefined(&self) -> Vec<&str> {
+    self
+      .reference_order
+      .iter()
+      .filter(|&&sym| self.definition(sym).is_none())
+      .map(|&sym| self.names.name(sym))
+      .collect()
+  }
+}
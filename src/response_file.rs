@@ -0,0 +1,206 @@
+// Response files: an `@path` argument is replaced by the arguments in the
+// file at `path`, which build systems use to get around command line length
+// limits.
+//
+// As with LLVM's ExpandResponseFiles, an `@path` is only expanded when it's
+// an argument of its own and the file exists, and never when it's the value
+// of the option before it, so `-rpath @loader_path/../lib` and
+// `-install_name @rpath/libfoo.dylib` are left alone.
+//
+// Arguments are separated by whitespace and quoted as in a POSIX shell:
+// single quotes take everything up to the next single quote literally,
+// double quotes allow `\` to escape `"`, `\`, `$` and '`', and a `\` outside
+// quotes escapes any character. Response files may name other response files.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+// Deeper nesting than this is taken to be a cycle.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug)]
+pub enum ResponseFileError {
+  IoError(PathBuf, io::Error),
+  UnterminatedQuote(PathBuf),
+  TooDeep(PathBuf),
+}
+
+pub type Result<T> = ::std::result::Result<T, ResponseFileError>;
+
+struct Expander<F> {
+  // How many values follow an option.
+  values: F,
+  // How many of the next arguments are values of an earlier option.
+  pending: usize,
+  out: Vec<String>,
+}
+
+impl<F: Fn(&str) -> usize> Expander<F> {
+  fn expand_arg(&mut self, arg: String, depth: usize) -> Result<()> {
+    if self.pending > 0 {
+      self.pending -= 1;
+      self.out.push(arg);
+      return Ok(());
+    }
+    let path = PathBuf::from(arg.get(1..).unwrap_or(""));
+    if !arg.starts_with('@') || arg.len() == 1 || !path.is_file() {
+      self.pending = (self.values)(&arg);
+      self.out.push(arg);
+      return Ok(());
+    }
+    if depth >= MAX_DEPTH {
+      return Err(ResponseFileError::TooDeep(path));
+    }
+    let mut text = String::new();
+    File::open(&path)
+      .and_then(|mut f| f.read_to_string(&mut text))
+      .map_err(|e| ResponseFileError::IoError(path.clone(), e))?;
+    let words =
+      split(&text).ok_or_else(|| ResponseFileError::UnterminatedQuote(path))?;
+    for word in words {
+      self.expand_arg(word, depth + 1)?;
+    }
+    Ok(())
+  }
+}
+
+// `args` with every response file replaced by its contents. `values` gives
+// the number of values which follow each option.
+pub fn expand<I, F>(args: I, values: F) -> Result<Vec<String>>
+where
+  I: Iterator<Item = String>,
+  F: Fn(&str) -> usize,
+{
+  let mut expander = Expander {
+    values: values,
+    pending: 0,
+    out: Vec::new(),
+  };
+  for arg in args {
+    expander.expand_arg(arg, 0)?;
+  }
+  Ok(expander.out)
+}
+
+// The words of `text`, or None if a quote isn't closed.
+pub fn split(text: &str) -> Option<Vec<String>> {
+  let mut words: Vec<String> = Vec::new();
+  // The word being read, if any. Tracked separately from its contents so
+  // that `''` is an empty argument rather than none.
+  let mut word: Option<String> = None;
+  let mut chars = text.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      c if c.is_whitespace() => {
+        words.extend(word.take());
+      }
+      '\'' => {
+        let word = word.get_or_insert_with(String::new);
+        loop {
+          match chars.next()? {
+            '\'' => break,
+            c => word.push(c),
+          }
+        }
+      }
+      '"' => {
+        let word = word.get_or_insert_with(String::new);
+        loop {
+          match chars.next()? {
+            '"' => break,
+            '\\' => match chars.next()? {
+              c @ '"' | c @ '\\' | c @ '$' | c @ '`' => word.push(c),
+              // A quoted line continuation.
+              '\n' => (),
+              c => {
+                word.push('\\');
+                word.push(c);
+              }
+            },
+            c => word.push(c),
+          }
+        }
+      }
+      '\\' => match chars.next() {
+        Some('\n') => (),
+        Some(c) => word.get_or_insert_with(String::new).push(c),
+        None => word.get_or_insert_with(String::new).push('\\'),
+      },
+      c => word.get_or_insert_with(String::new).push(c),
+    }
+  }
+  words.extend(word);
+  Some(words)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::env;
+  use std::fs;
+  use std::process;
+
+  use args::parse_args;
+
+  fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+  }
+
+  #[test]
+  fn leaves_dyld_paths_given_as_values_alone() {
+    let opts = parse_args(
+      strings(&[
+        "-dylib",
+        "-rpath",
+        "@loader_path/../lib",
+        "-install_name",
+        "@rpath/x",
+        "a.o",
+      ]).into_iter(),
+    ).unwrap();
+    assert_eq!(opts.rpaths, vec!["@loader_path/../lib".to_string()]);
+    assert_eq!(opts.install_name, Some("@rpath/x".to_string()));
+  }
+
+  #[test]
+  fn never_expands_values_even_when_the_file_exists() {
+    let path =
+      env::temp_dir().join(format!("mold-rsp-value-{}", process::id()));
+    fs::write(&path, "b.o").unwrap();
+    let value = format!("@{}", path.display());
+    let args = strings(&["-rpath", &value, "a.o"]);
+    let expanded = expand(args.clone().into_iter(), |opt| {
+      if opt == "-rpath" {
+        1
+      } else {
+        0
+      }
+    }).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(expanded, args);
+  }
+
+  #[test]
+  fn expands_standalone_response_files_which_exist() {
+    let path = env::temp_dir().join(format!("mold-rsp-{}", process::id()));
+    fs::write(&path, "-rpath @loader_path 'b c.o'").unwrap();
+    let args = vec![
+      format!("@{}", path.display()),
+      "@missing-response-file".to_string(),
+    ];
+    let expanded = expand(args.into_iter(), |opt| {
+      if opt == "-rpath" {
+        1
+      } else {
+        0
+      }
+    }).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(
+      expanded,
+      strings(&["-rpath", "@loader_path", "b c.o", "@missing-response-file"])
+    );
+  }
+}
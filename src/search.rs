@@ -0,0 +1,106 @@
+// Finding the files named by -l and -framework (and the options built on
+// them) in the library and framework search paths.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The entry `name` in `dir`. SDKs are laid out for macOS's case-insensitive
+// filesystems, where `-framework foundation` finds Foundation.framework, so
+// on case-sensitive ones (when cross-linking from Linux, say) names which
+// differ only in case match too.
+fn find_entry(dir: &Path, name: &str) -> Option<PathBuf> {
+  let exact = dir.join(name);
+  if exact.exists() {
+    return Some(exact);
+  }
+  fs::read_dir(dir)
+    .ok()?
+    .filter_map(|entry| entry.ok())
+    .map(|entry| entry.file_name())
+    .find(|entry| {
+      entry
+        .to_str()
+        .map_or(false, |entry| entry.eq_ignore_ascii_case(name))
+    })
+    .map(|entry| dir.join(entry))
+}
+
+// Look for lib<name>.dylib, then lib<name>.a, in each directory in turn.
+pub fn find_library(name: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+  probe_library(name, dirs).0
+}
+
+// The search for -l<name>: what was found, and every path tried before it.
+pub fn probe_library(
+  name: &str,
+  dirs: &[PathBuf],
+) -> (Option<PathBuf>, Vec<PathBuf>) {
+  let candidates = [format!("lib{}.dylib", name), format!("lib{}.a", name)];
+  let mut tried: Vec<PathBuf> = Vec::new();
+  for dir in dirs.iter() {
+    for candidate in candidates.iter() {
+      match find_entry(dir, candidate) {
+        Some(ref path) if path.is_file() => {
+          return (Some(path.clone()), tried);
+        }
+        _ => tried.push(dir.join(candidate)),
+      }
+    }
+  }
+  (None, tried)
+}
+
+// Look for <name>.framework/<name> in each directory in turn.
+pub fn find_framework(name: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+  dirs
+    .iter()
+    .filter_map(|dir| find_entry(dir, &format!("{}.framework", name)))
+    .filter_map(|framework| find_entry(&framework, name))
+    .find(|path| path.is_file())
+}
+
+// `path` (absolute) inside `root`.
+fn rooted(root: &Path, path: &Path) -> PathBuf {
+  root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+// -syslibroot: each absolute search directory is replaced by its copies
+// under the roots which have one. Directories no root has are searched as
+// given.
+pub fn apply_syslibroots(dirs: &[PathBuf], roots: &[PathBuf]) -> Vec<PathBuf> {
+  let mut result: Vec<PathBuf> = Vec::new();
+  for dir in dirs.iter() {
+    let found: Vec<PathBuf> = if dir.is_absolute() {
+      roots
+        .iter()
+        .map(|root| rooted(root, dir))
+        .filter(|path| path.is_dir())
+        .collect()
+    } else {
+      Vec::new()
+    };
+    if found.is_empty() {
+      result.push(dir.clone());
+    } else {
+      result.extend(found);
+    }
+  }
+  result
+}
+
+// Where to read the file with absolute path `path` (an install name, say)
+// from: the first root which has it, or else `path` itself.
+pub fn in_syslibroots(path: &Path, roots: &[PathBuf]) -> PathBuf {
+  if !path.is_absolute() {
+    return path.to_path_buf();
+  }
+  roots
+    .iter()
+    .map(|root| rooted(root, path))
+    .find(|candidate| candidate.is_file())
+    .unwrap_or_else(|| path.to_path_buf())
+}
+
+pub fn is_dylib_path(path: &Path) -> bool {
+  path.extension().map_or(false, |ext| ext == "dylib")
+}
@@ -0,0 +1,267 @@
+// Where the bytes go. `mold size [-arch <name>] <file>...` shows a file's
+// segment and section sizes, as size -m does, and --size-report shows
+// which inputs the output's sections came from: each input section is an
+// atom the output copies whole, so an input's share of an output section
+// is the size of its sections with the same name. Alignment padding isn't
+// anyone's.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use archive::{self, ArchiveError};
+use macho::fat;
+use macho::image_file::ImageFile;
+use macho::{Arch, MachOError};
+use relocatable::RelocatableInput;
+
+#[derive(Debug)]
+pub enum SizeError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  Archive(PathBuf, ArchiveError),
+  Usage(String),
+}
+
+pub type Result<T> = ::std::result::Result<T, SizeError>;
+
+// One thin file's segments, each with its sections' sizes and the total.
+pub fn segment_sizes(data: &[u8]) -> ::macho::Result<String> {
+  let image = ImageFile::parse(data)?;
+  let mut out = String::new();
+  let mut total: u64 = 0;
+  for seg in image.segments.iter() {
+    let _ = writeln!(
+      out,
+      "Segment {}: {} (vmaddr {:#x} fileoff {})",
+      seg.segname, seg.vmsize, seg.vmaddr, seg.fileoff
+    );
+    let mut sections_total: u64 = 0;
+    for sect in seg.sections.iter() {
+      let _ = writeln!(
+        out,
+        "\tSection {}: {} (addr {:#x} offset {})",
+        sect.sectname, sect.size, sect.addr, sect.offset
+      );
+      sections_total += sect.size;
+    }
+    if !seg.sections.is_empty() {
+      let _ = writeln!(out, "\ttotal {}", sections_total);
+    }
+    total += seg.vmsize;
+  }
+  let _ = writeln!(out, "total {}", total);
+  Ok(out)
+}
+
+// `mold size`, printing to stdout.
+pub fn run_command(args: &[String]) -> Result<()> {
+  let mut arch: Option<Arch> = None;
+  let mut inputs: Vec<PathBuf> = Vec::new();
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-arch" => {
+        let name = args.next().ok_or_else(|| {
+          SizeError::Usage("-arch needs an architecture".to_string())
+        })?;
+        arch = Some(Arch::from_name(name).ok_or_else(|| {
+          SizeError::Usage(format!("unknown architecture {}", name))
+        })?);
+      }
+      _ if arg.starts_with('-') => {
+        return Err(SizeError::Usage(format!("unknown option {}", arg)));
+      }
+      _ => inputs.push(PathBuf::from(arg)),
+    }
+  }
+  if inputs.is_empty() {
+    return Err(SizeError::Usage("no input files".to_string()));
+  }
+
+  let mut reports: Vec<(String, String)> = Vec::new();
+  for input in inputs.iter() {
+    let data =
+      fs::read(input).map_err(|e| SizeError::IoError(input.clone(), e))?;
+    let malformed = |e| SizeError::Malformed(input.clone(), e);
+    let mut files: Vec<(String, &[u8])> = Vec::new();
+    if archive::is_archive(&data) {
+      let members = archive::members(&data)
+        .map_err(|e| SizeError::Archive(input.clone(), e))?;
+      for member in members.iter().filter(|m| !m.is_symbol_table()) {
+        let name = format!("{}({})", input.display(), member.name);
+        files.push((name, member.data));
+      }
+    } else {
+      files.push((input.display().to_string(), &data[..]));
+    }
+    for (name, data) in files.into_iter() {
+      let is_fat = fat::is_fat(data);
+      for (slice_arch, thin) in fat::slices(data).map_err(malformed)? {
+        if is_fat && arch.is_some() && slice_arch != arch {
+          continue;
+        }
+        let heading = if is_fat {
+          let slice_arch = slice_arch.map_or("unknown", |a| a.name());
+          format!("{} (for architecture {})", name, slice_arch)
+        } else {
+          name.clone()
+        };
+        reports.push((heading, segment_sizes(thin).map_err(malformed)?));
+      }
+    }
+  }
+
+  let headings = reports.len() > 1;
+  for (i, (heading, report)) in reports.iter().enumerate() {
+    if headings {
+      if i > 0 {
+        println!();
+      }
+      println!("{}:", heading);
+    }
+    print!("{}", report);
+  }
+  Ok(())
+}
+
+// (segname, sectname, size)
+pub type SectionSize = (String, String, u64);
+
+// What each input contributes to each output section.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeReport {
+  // Each input's sections, in input order. Archive members are named as
+  // they are in diagnostics: libfoo.a(bar.o).
+  pub inputs: Vec<(PathBuf, Vec<SectionSize>)>,
+}
+
+// The archive an input came from, if it's a member of one.
+fn archive_of(input: &Path) -> Option<String> {
+  let name = input.display().to_string();
+  if !name.ends_with(')') {
+    return None;
+  }
+  name.rfind('(').map(|start| name[..start].to_string())
+}
+
+fn percent(size: u64, total: u64) -> f64 {
+  if total == 0 {
+    0.0
+  } else {
+    size as f64 * 100.0 / total as f64
+  }
+}
+
+impl SizeReport {
+  pub fn compute(inputs: &[RelocatableInput]) -> SizeReport {
+    SizeReport {
+      inputs: inputs
+        .iter()
+        .map(|input| {
+          let sections = input
+            .object
+            .sections
+            .iter()
+            .filter(|sect| sect.size > 0)
+            .map(|sect| {
+              (sect.segname.clone(), sect.sectname.clone(), sect.size)
+            })
+            .collect();
+          (input.path.clone(), sections)
+        })
+        .collect(),
+    }
+  }
+
+  pub fn input_size(&self, index: usize) -> u64 {
+    self.inputs[index].1.iter().map(|&(_, _, size)| size).sum()
+  }
+
+  pub fn total(&self) -> u64 {
+    (0..self.inputs.len()).map(|i| self.input_size(i)).sum()
+  }
+
+  // Inputs from largest to smallest, with their sections, then the same for
+  // whole archives and for output sections.
+  pub fn to_text(&self) -> String {
+    let total = self.total();
+    let mut out = String::new();
+    let _ = writeln!(out, "{:>12} {:>7}  input", "bytes", "%");
+    let mut order: Vec<usize> = (0..self.inputs.len()).collect();
+    order.sort_by_key(|&i| (::std::cmp::Reverse(self.input_size(i)), i));
+    for &i in order.iter() {
+      let (ref path, ref sections) = self.inputs[i];
+      let size = self.input_size(i);
+      let _ = writeln!(
+        out,
+        "{:>12} {:>6.2}%  {}",
+        size,
+        percent(size, total),
+        path.display()
+      );
+      for &(ref segname, ref sectname, size) in sections.iter() {
+        let _ = writeln!(
+          out,
+          "{:>12} {:>6.2}%    {},{}",
+          size,
+          percent(size, total),
+          segname,
+          sectname
+        );
+      }
+    }
+
+    let mut archives: Vec<(String, u64)> = Vec::new();
+    for (i, &(ref path, _)) in self.inputs.iter().enumerate() {
+      if let Some(archive) = archive_of(path) {
+        match archives.iter().position(|&(ref a, _)| *a == archive) {
+          Some(pos) => archives[pos].1 += self.input_size(i),
+          None => archives.push((archive, self.input_size(i))),
+        }
+      }
+    }
+    if !archives.is_empty() {
+      archives.sort_by_key(|&(_, size)| ::std::cmp::Reverse(size));
+      let _ = writeln!(out, "\n{:>12} {:>7}  archive", "bytes", "%");
+      for &(ref archive, size) in archives.iter() {
+        let _ = writeln!(
+          out,
+          "{:>12} {:>6.2}%  {}",
+          size,
+          percent(size, total),
+          archive
+        );
+      }
+    }
+
+    let mut sections: Vec<((String, String), u64)> = Vec::new();
+    let mut index: HashMap<(String, String), usize> = HashMap::new();
+    for &(_, ref input_sections) in self.inputs.iter() {
+      for &(ref segname, ref sectname, size) in input_sections.iter() {
+        let key = (segname.clone(), sectname.clone());
+        let next = sections.len();
+        let slot = *index.entry(key.clone()).or_insert(next);
+        if slot == next {
+          sections.push((key, 0));
+        }
+        sections[slot].1 += size;
+      }
+    }
+    let _ = writeln!(out, "\n{:>12} {:>7}  section", "bytes", "%");
+    for &((ref segname, ref sectname), size) in sections.iter() {
+      let _ = writeln!(
+        out,
+        "{:>12} {:>6.2}%  {},{}",
+        size,
+        percent(size, total),
+        segname,
+        sectname
+      );
+    }
+    let _ = writeln!(out, "{:>12} {:>6.2}%  total", total, 100.0);
+    out
+  }
+}
@@ -0,0 +1,112 @@
+// -print_statistics: what a link did, for keeping an eye on a build: how
+// many objects it parsed, sections (our atoms; inputs aren't split any
+// finer) and symbols it merged, fixups it applied and bytes it read and
+// wrote, with the time each pass took and the peak memory use. Like
+// --time-passes, only -r links are measured pass by pass.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use timing::{self, Pass};
+
+static OBJECTS: AtomicU64 = AtomicU64::new(0);
+static SECTIONS: AtomicU64 = AtomicU64::new(0);
+static SYMBOLS: AtomicU64 = AtomicU64::new(0);
+static FIXUPS: AtomicU64 = AtomicU64::new(0);
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+static BYTES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Statistics {
+  pub objects: u64,
+  pub sections: u64,
+  pub symbols: u64,
+  pub fixups: u64,
+  pub bytes_read: u64,
+  pub bytes_written: u64,
+}
+
+fn add(counter: &AtomicU64, n: u64) {
+  counter.fetch_add(n, Ordering::Relaxed);
+}
+
+// An object file (or archive member) was parsed.
+pub fn object_parsed() {
+  add(&OBJECTS, 1);
+}
+
+pub fn sections_merged(n: usize) {
+  add(&SECTIONS, n as u64);
+}
+
+pub fn symbols_resolved(n: usize) {
+  add(&SYMBOLS, n as u64);
+}
+
+pub fn fixups_applied(n: usize) {
+  add(&FIXUPS, n as u64);
+}
+
+pub fn bytes_read(n: usize) {
+  add(&BYTES_READ, n as u64);
+}
+
+pub fn bytes_written(n: u64) {
+  add(&BYTES_WRITTEN, n);
+}
+
+// The counts so far, which start again from zero.
+pub fn take() -> Statistics {
+  let take = |counter: &AtomicU64| counter.swap(0, Ordering::Relaxed);
+  Statistics {
+    objects: take(&OBJECTS),
+    sections: take(&SECTIONS),
+    symbols: take(&SYMBOLS),
+    fixups: take(&FIXUPS),
+    bytes_read: take(&BYTES_READ),
+    bytes_written: take(&BYTES_WRITTEN),
+  }
+}
+
+fn seconds(elapsed: Duration) -> f64 {
+  elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9
+}
+
+pub fn to_text(stats: &Statistics, passes: &[Pass]) -> String {
+  let mut out = String::new();
+  let counts = [
+    ("objects parsed", stats.objects),
+    ("sections merged", stats.sections),
+    ("symbols resolved", stats.symbols),
+    ("fixups applied", stats.fixups),
+    ("bytes read", stats.bytes_read),
+    ("bytes written", stats.bytes_written),
+  ];
+  for &(name, count) in counts.iter() {
+    let _ = writeln!(out, "{:<18} {:>12}", name, count);
+  }
+  // A pass which runs more than once (fixups, or each architecture of a
+  // universal link) is shown as its total.
+  let mut totals: Vec<(&'static str, Duration)> = Vec::new();
+  for pass in passes.iter() {
+    match totals.iter_mut().find(|&&mut (name, _)| name == pass.name) {
+      Some(&mut (_, ref mut elapsed)) => *elapsed += pass.elapsed,
+      None => totals.push((pass.name, pass.elapsed)),
+    }
+  }
+  for &(name, elapsed) in totals.iter() {
+    let _ = writeln!(out, "time in {:<10} {:>11.4}s", name, seconds(elapsed));
+  }
+  let total: Duration = passes.iter().map(|pass| pass.elapsed).sum();
+  let _ = writeln!(out, "{:<18} {:>11.4}s", "total time", seconds(total));
+  let rss = timing::max_rss()
+    .map_or("-".to_string(), |rss| format!("{:.1} MB", rss as f64 / 1e6));
+  let _ = writeln!(out, "{:<18} {:>12}", "peak RSS", rss);
+  out
+}
+
+// Print the statistics to stderr, and start counting again.
+pub fn report(passes: &[Pass]) {
+  eprint!("{}", to_text(&take(), passes));
+}
@@ -0,0 +1,343 @@
+// Stripping symbols, either while linking (-x, -S, -exported_only) or from
+// an already linked image (`mold strip`).
+//
+// -S drops debug info: stabs, and for -r outputs the __DWARF sections.
+// -x drops local symbols. -exported_only drops both, keeping only the
+// symbols other images can see or which this one imports. Symbols which
+// relocations or the indirect symbol table still refer to are always kept.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use codesign;
+use dead_strip;
+use diagnostics;
+use macho::header::MACH_HEADER_64_SIZE;
+use macho::load_command::{LC_CODE_SIGNATURE, LC_DYSYMTAB, LC_SEGMENT_64,
+                          LC_SYMTAB};
+use macho::object_file::ObjectFile;
+use macho::reloc::ARM64_RELOC_ADDEND;
+use macho::symtab::{parse_symtab, Nlist, StringTable, INDIRECT_SYMBOL_ABS,
+                    INDIRECT_SYMBOL_LOCAL, NLIST_64_SIZE};
+use macho::{get_name16, get_u32, get_u64, next_command, round_up, set_u32,
+            set_u64, Arch, MachOError, MH_MAGIC_64, MH_OBJECT};
+use relocatable::RelocatableInput;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StripOptions {
+  pub debug: bool,
+  pub locals: bool,
+  pub non_exported: bool,
+}
+
+impl StripOptions {
+  pub fn is_empty(&self) -> bool {
+    !self.debug && !self.locals && !self.non_exported
+  }
+
+  pub fn strips_debug(&self) -> bool {
+    self.debug || self.non_exported
+  }
+
+  pub fn keeps(&self, sym: &Nlist) -> bool {
+    if sym.is_stab() {
+      !self.strips_debug()
+    } else if !sym.is_external() {
+      !self.locals && !self.non_exported
+    } else {
+      true
+    }
+  }
+}
+
+// Which symbols to keep, and where each kept one ends up.
+fn symbol_map(
+  symbols: &[Nlist],
+  opts: StripOptions,
+  referenced: &HashSet<u32>,
+) -> Vec<Option<u32>> {
+  let mut next: u32 = 0;
+  symbols
+    .iter()
+    .enumerate()
+    .map(|(idx, sym)| {
+      if opts.keeps(sym) || referenced.contains(&(idx as u32)) {
+        next += 1;
+        Some(next - 1)
+      } else {
+        None
+      }
+    })
+    .collect()
+}
+
+// `input` stripped before going into a -r link.
+pub fn strip_input(
+  arch: Arch,
+  input: &RelocatableInput,
+  opts: StripOptions,
+) -> RelocatableInput {
+  let live: Vec<bool> = input
+    .object
+    .sections
+    .iter()
+    .map(|sect| !(opts.strips_debug() && sect.segname == "__DWARF"))
+    .collect();
+  let input = dead_strip::strip(arch, input, &live);
+  let object = &input.object;
+
+  let is_symbol_reference = |kind: u8| {
+    !arch.is_arm64() || kind != ARM64_RELOC_ADDEND
+  };
+  let referenced: HashSet<u32> = object
+    .sections
+    .iter()
+    .flat_map(|sect| sect.relocs.iter())
+    .filter(|reloc| reloc.external && is_symbol_reference(reloc.kind))
+    .map(|reloc| reloc.symbolnum)
+    .collect();
+  let map = symbol_map(&object.symbols, opts, &referenced);
+
+  let mut sections = object.sections.clone();
+  for reloc in sections.iter_mut().flat_map(|sect| sect.relocs.iter_mut()) {
+    if reloc.external && is_symbol_reference(reloc.kind) {
+      if let Some(&Some(idx)) = map.get(reloc.symbolnum as usize) {
+        reloc.symbolnum = idx;
+      }
+    }
+  }
+  RelocatableInput {
+    path: input.path.clone(),
+    object: ObjectFile {
+      cputype: object.cputype,
+      cpusubtype: object.cpusubtype,
+      flags: object.flags,
+      sections: sections,
+      symbols: object
+        .symbols
+        .iter()
+        .zip(map.iter())
+        .filter(|&(_, idx)| idx.is_some())
+        .map(|(sym, _)| sym.clone())
+        .collect(),
+      platform: object.platform,
+      loh: object.loh.clone(),
+      data_in_code: object.data_in_code.clone(),
+    },
+  }
+}
+
+#[derive(Debug)]
+pub enum StripError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  Usage(String),
+  // Objects are stripped by linking them with -r and the strip options.
+  NotLinked(PathBuf),
+  // Old-style external relocations refer to symbols by index, and we don't
+  // rewrite them.
+  HasExternalRelocations(PathBuf),
+}
+
+pub type Result<T> = ::std::result::Result<T, StripError>;
+
+// Where the parts of a linked image we rewrite are.
+#[derive(Debug, Default)]
+struct LinkeditLayout {
+  symtab_cmd: usize,
+  dysymtab_cmd: Option<usize>,
+  linkedit_cmd: Option<usize>,
+  signed: bool,
+}
+
+fn find_commands(data: &[u8]) -> ::macho::Result<Option<LinkeditLayout>> {
+  let ncmds = get_u32(data, 16, "mach header")?;
+  let mut layout = LinkeditLayout::default();
+  let mut symtab_cmd: Option<usize> = None;
+  let mut offset = MACH_HEADER_64_SIZE as usize;
+  for _ in 0..ncmds {
+    let cmd = get_u32(data, offset, "load command")?;
+    let cmdsize = get_u32(data, offset + 4, "load command")? as usize;
+    if cmdsize < 8 {
+      return Err(MachOError::Malformed("load command smaller than 8"));
+    }
+    match cmd {
+      LC_SYMTAB => symtab_cmd = Some(offset),
+      LC_DYSYMTAB => layout.dysymtab_cmd = Some(offset),
+      LC_CODE_SIGNATURE => layout.signed = true,
+      LC_SEGMENT_64 => {
+        if get_name16(data, offset + 8, "segment_command_64")? == "__LINKEDIT"
+        {
+          layout.linkedit_cmd = Some(offset);
+        }
+      }
+      _ => (),
+    }
+    offset = next_command(offset, cmdsize)?;
+  }
+  Ok(symtab_cmd.map(|cmd| LinkeditLayout {
+    symtab_cmd: cmd,
+    ..layout
+  }))
+}
+
+// Strip a linked image in place. The symbol and string tables shrink where
+// they are, and the file is truncated if the string table was at its end.
+// An ad-hoc signature is redone afterwards.
+pub fn strip_image(
+  path: &PathBuf,
+  data: &mut Vec<u8>,
+  opts: StripOptions,
+) -> Result<()> {
+  let malformed = |e| StripError::Malformed(path.clone(), e);
+  let magic = get_u32(data, 0, "mach header").map_err(malformed)?;
+  if magic != MH_MAGIC_64 {
+    return Err(malformed(MachOError::BadMagic(magic)));
+  }
+  if get_u32(data, 12, "mach header").map_err(malformed)? == MH_OBJECT {
+    return Err(StripError::NotLinked(path.clone()));
+  }
+  let layout = match find_commands(data).map_err(malformed)? {
+    Some(layout) => layout,
+    // Nothing to strip.
+    None => return Ok(()),
+  };
+  let symbols =
+    parse_symtab(data, layout.symtab_cmd, true).map_err(malformed)?;
+  let what = "symtab_command";
+  let symoff = get_u32(data, layout.symtab_cmd + 8, what).map_err(malformed)?
+    as usize;
+  let stroff = get_u32(data, layout.symtab_cmd + 16, what).map_err(malformed)?
+    as usize;
+  let strsize = get_u32(data, layout.symtab_cmd + 20, what)
+    .map_err(malformed)? as usize;
+
+  // Symbols the indirect symbol table refers to stay.
+  let mut indirect: Option<(usize, usize)> = None;
+  let mut referenced: HashSet<u32> = HashSet::new();
+  if let Some(cmd) = layout.dysymtab_cmd {
+    let what = "dysymtab_command";
+    if get_u32(data, cmd + 68, what).map_err(malformed)? != 0 {
+      return Err(StripError::HasExternalRelocations(path.clone()));
+    }
+    let off = get_u32(data, cmd + 56, what).map_err(malformed)? as usize;
+    let count = get_u32(data, cmd + 60, what).map_err(malformed)? as usize;
+    for i in 0..count {
+      let entry = get_u32(data, off + 4 * i, "indirect symbol table")
+        .map_err(malformed)?;
+      if entry & (INDIRECT_SYMBOL_LOCAL | INDIRECT_SYMBOL_ABS) == 0 {
+        referenced.insert(entry);
+      }
+    }
+    indirect = Some((off, count));
+  }
+  let map = symbol_map(&symbols, opts, &referenced);
+
+  let mut strtab = StringTable::new();
+  let mut sym_data: Vec<u8> = Vec::new();
+  for (sym, idx) in symbols.iter().zip(map.iter()) {
+    if idx.is_some() {
+      sym.write(&mut sym_data, &mut strtab);
+    }
+  }
+  let strings = strtab.finish();
+  if strings.len() > strsize {
+    return Err(malformed(MachOError::Malformed("string table grew")));
+  }
+  let old_symsize = symbols.len() * NLIST_64_SIZE;
+  data[symoff..(symoff + old_symsize)].iter_mut().for_each(|b| *b = 0);
+  data[symoff..(symoff + sym_data.len())].copy_from_slice(&sym_data);
+  data[stroff..(stroff + strsize)].iter_mut().for_each(|b| *b = 0);
+  data[stroff..(stroff + strings.len())].copy_from_slice(&strings);
+  let nsyms = sym_data.len() / NLIST_64_SIZE;
+  set_u32(data, layout.symtab_cmd + 12, nsyms as u32);
+  set_u32(data, layout.symtab_cmd + 20, strings.len() as u32);
+
+  if let Some(cmd) = layout.dysymtab_cmd {
+    // Each partition (locals, defined externals, undefined externals) is
+    // still contiguous, just smaller.
+    let mut start: u32 = 0;
+    for field in [8, 16, 24].iter() {
+      let first = get_u32(data, cmd + field, "dysymtab_command")
+        .map_err(malformed)? as usize;
+      let count = get_u32(data, cmd + field + 4, "dysymtab_command")
+        .map_err(malformed)? as usize;
+      let kept = map
+        .get(first..(first + count))
+        .map_or(0, |range| range.iter().filter(|idx| idx.is_some()).count());
+      set_u32(data, cmd + field, start);
+      set_u32(data, cmd + field + 4, kept as u32);
+      start += kept as u32;
+    }
+  }
+  if let Some((off, count)) = indirect {
+    for i in 0..count {
+      let entry = get_u32(data, off + 4 * i, "indirect symbol table")
+        .map_err(malformed)?;
+      if let Some(&Some(idx)) = map.get(entry as usize) {
+        set_u32(data, off + 4 * i, idx);
+      }
+    }
+  }
+
+  if stroff + strsize == data.len() {
+    let end = stroff + strings.len();
+    data.truncate(end);
+    if let Some(cmd) = layout.linkedit_cmd {
+      let what = "segment_command_64";
+      let fileoff = get_u64(data, cmd + 40, what).map_err(malformed)?;
+      let filesize = end as u64 - fileoff;
+      set_u64(data, cmd + 32, round_up(filesize, 0x1000));
+      set_u64(data, cmd + 48, filesize);
+    }
+  }
+  // Only ad-hoc signatures can be redone here.
+  if layout.signed && !codesign::resign(data).map_err(malformed)? {
+    diagnostics::warning(&format!(
+      "{}: stripping invalidated the code signature; sign it again",
+      path.display()
+    ));
+  }
+  Ok(())
+}
+
+// `mold strip [-x] [-S] [-o <output>] <input>`. With neither -x nor -S,
+// keeps only exported and imported symbols.
+pub fn run_command(args: &[String]) -> Result<()> {
+  let mut opts = StripOptions::default();
+  let mut output: Option<PathBuf> = None;
+  let mut input: Option<PathBuf> = None;
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-x" => opts.locals = true,
+      "-S" => opts.debug = true,
+      "-o" => match args.next() {
+        Some(path) => output = Some(PathBuf::from(path)),
+        None => return Err(StripError::Usage("-o needs a path".to_string())),
+      },
+      _ if arg.starts_with('-') => {
+        return Err(StripError::Usage(format!("unknown option {}", arg)));
+      }
+      _ if input.is_some() => {
+        return Err(StripError::Usage("only one input is allowed".to_string()));
+      }
+      _ => input = Some(PathBuf::from(arg)),
+    }
+  }
+  let input = input
+    .ok_or_else(|| StripError::Usage("no input file".to_string()))?;
+  if opts.is_empty() {
+    opts.non_exported = true;
+  }
+
+  let mut data: Vec<u8> = Vec::new();
+  File::open(&input)
+    .and_then(|mut f| f.read_to_end(&mut data))
+    .map_err(|e| StripError::IoError(input.clone(), e))?;
+  strip_image(&input, &mut data, opts)?;
+  let output = output.unwrap_or(input);
+  fs::write(&output, &data).map_err(|e| StripError::IoError(output, e))
+}
@@ -0,0 +1,211 @@
+// Files listing symbol names one per line, as taken by
+// -exported_symbols_list, -unexported_symbols_list and friends.
+//
+// Names may contain the wildcards `*`, `?` and `[...]` character classes.
+// Anything after a '#' is a comment.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use dependency_info;
+
+// Match `name` against a glob `pattern`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+  let pat: Vec<char> = pattern.chars().collect();
+  let txt: Vec<char> = name.chars().collect();
+  glob_match_chars(&pat, &txt)
+}
+
+fn glob_match_chars(pat: &[char], txt: &[char]) -> bool {
+  let (mut p, mut t) = (0, 0);
+  // Where to resume after the most recent `*` if the rest fails to match.
+  let mut backtrack: Option<(usize, usize)> = None;
+  while t < txt.len() {
+    let step = if p < pat.len() {
+      match pat[p] {
+        '*' => {
+          backtrack = Some((p, t));
+          p += 1;
+          continue;
+        }
+        '?' => Some(1),
+        '[' => match_class(&pat[p..], txt[t]),
+        c if c == txt[t] => Some(1),
+        _ => None,
+      }
+    } else {
+      None
+    };
+    match step {
+      // `len` is how much of the pattern the character consumed.
+      Some(len) => {
+        p += len;
+        t += 1;
+      }
+      None => match backtrack {
+        Some((star_p, star_t)) => {
+          backtrack = Some((star_p, star_t + 1));
+          p = star_p + 1;
+          t = star_t + 1;
+        }
+        None => return false,
+      },
+    }
+  }
+  pat[p..].iter().all(|c| *c == '*')
+}
+
+// Match `c` against the character class at the start of `pat`, returning the
+// length of the class in the pattern if it matched.
+fn match_class(pat: &[char], c: char) -> Option<usize> {
+  let close = match pat.iter().skip(1).position(|ch| *ch == ']') {
+    Some(idx) => idx + 1,
+    // An unterminated `[` is just a literal.
+    None => return if c == '[' { Some(1) } else { None },
+  };
+  let class = &pat[1..close];
+  let mut i = 0;
+  let mut matched = false;
+  while i < class.len() {
+    if i + 2 < class.len() && class[i + 1] == '-' {
+      if class[i] <= c && c <= class[i + 2] {
+        matched = true;
+      }
+      i += 3;
+    } else {
+      if class[i] == c {
+        matched = true;
+      }
+      i += 1;
+    }
+  }
+  if matched {
+    Some(close + 1)
+  } else {
+    None
+  }
+}
+
+fn is_pattern(s: &str) -> bool {
+  s.contains(|c| c == '*' || c == '?' || c == '[')
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolList {
+  // Plain names, in the order they were added.
+  pub names: Vec<String>,
+  name_set: HashSet<String>,
+  pub patterns: Vec<String>,
+}
+
+impl SymbolList {
+  pub fn new() -> Self {
+    SymbolList {
+      ..Default::default()
+    }
+  }
+
+  pub fn add(&mut self, entry: &str) {
+    if is_pattern(entry) {
+      self.patterns.push(entry.to_string());
+    } else if self.name_set.insert(entry.to_string()) {
+      self.names.push(entry.to_string());
+    }
+  }
+
+  pub fn parse(&mut self, contents: &str) {
+    for line in contents.lines() {
+      let line = match line.find('#') {
+        Some(idx) => &line[..idx],
+        None => line,
+      }.trim();
+      if !line.is_empty() {
+        self.add(line);
+      }
+    }
+  }
+
+  pub fn read(&mut self, path: &Path) -> io::Result<()> {
+    dependency_info::record_input(path);
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    self.parse(&contents);
+    Ok(())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.names.is_empty() && self.patterns.is_empty()
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.name_set.contains(name)
+      || self.patterns.iter().any(|pat| glob_match(pat, name))
+  }
+}
+
+// Which global symbols are exported from the output.
+#[derive(Debug, Clone)]
+pub enum ExportControl {
+  All,
+  // -exported_symbols_list: only these.
+  Only(SymbolList),
+  // -unexported_symbols_list: everything but these.
+  AllExcept(SymbolList),
+}
+
+impl Default for ExportControl {
+  fn default() -> Self {
+    ExportControl::All
+  }
+}
+
+impl ExportControl {
+  pub fn is_exported(&self, name: &str) -> bool {
+    match *self {
+      ExportControl::All => true,
+      ExportControl::Only(ref list) => list.contains(name),
+      ExportControl::AllExcept(ref list) => !list.contains(name),
+    }
+  }
+
+  // Non-wildcard names listed for export, which must all be defined.
+  pub fn required_names(&self) -> &[String] {
+    match *self {
+      ExportControl::Only(ref list) => &list.names,
+      _ => &[],
+    }
+  }
+}
+
+// Parse an -alias_list file: each line names an existing symbol and then the
+// alias to define for it, separated by whitespace.
+pub fn parse_alias_list(contents: &str) -> io::Result<Vec<(String, String)>> {
+  let mut aliases: Vec<(String, String)> = Vec::new();
+  for (i, line) in contents.lines().enumerate() {
+    let line = match line.find('#') {
+      Some(idx) => &line[..idx],
+      None => line,
+    };
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.len() {
+      0 => (),
+      2 => aliases.push((fields[0].to_string(), fields[1].to_string())),
+      _ => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("line {}: expected 'symbol alias'", i + 1),
+        ))
+      }
+    }
+  }
+  Ok(aliases)
+}
+
+pub fn read_alias_list(path: &Path) -> io::Result<Vec<(String, String)>> {
+  dependency_info::record_input(path);
+  let mut contents = String::new();
+  File::open(path)?.read_to_string(&mut contents)?;
+  parse_alias_list(&contents)
+}
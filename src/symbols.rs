@@ -0,0 +1,216 @@
+// `mold symbols [options] <file>...`: the symbol table, as nm prints it,
+// for objects, linked images, archives (member by member) and fat files
+// (slice by slice).
+//
+//   -g              only external symbols
+//   -u              only undefined symbols
+//   -U, --defined-only
+//                   only defined symbols
+//   -a              debugger symbols (stabs) too
+//   -C, --demangle  show C++ names demangled
+//   -j              just the names
+//   -arch <name>    only this slice of a fat file
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use archive::{self, ArchiveError};
+use demangle;
+use macho::fat;
+use macho::image_file::ImageFile;
+use macho::symtab::{Nlist, N_ABS, N_INDR, N_SECT, N_TYPE, N_UNDF};
+use macho::{Arch, MachOError};
+
+#[derive(Debug)]
+pub enum SymbolsError {
+  IoError(PathBuf, io::Error),
+  Malformed(PathBuf, MachOError),
+  Archive(PathBuf, ArchiveError),
+  Usage(String),
+}
+
+pub type Result<T> = ::std::result::Result<T, SymbolsError>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SymbolsOptions {
+  pub external_only: bool,
+  pub undefined_only: bool,
+  pub defined_only: bool,
+  pub debug: bool,
+  pub demangle: bool,
+  pub names_only: bool,
+  pub arch: Option<Arch>,
+}
+
+impl SymbolsOptions {
+  pub fn shows(&self, sym: &Nlist) -> bool {
+    if sym.is_stab() {
+      return self.debug && !self.external_only && !self.undefined_only;
+    }
+    let undefined = sym.is_undefined() && !sym.is_common();
+    !(self.external_only && !sym.is_external()
+      || self.undefined_only && !undefined
+      || self.defined_only && undefined)
+  }
+}
+
+// The letter nm shows for `sym`'s type: upper case if it's external.
+// `sections` are (segname, sectname) in section number order.
+fn type_letter(sym: &Nlist, sections: &[(&str, &str)]) -> char {
+  if sym.is_stab() {
+    return '-';
+  }
+  let letter = match sym.n_type & N_TYPE {
+    N_UNDF if sym.is_common() => 'c',
+    N_UNDF => 'u',
+    N_ABS => 'a',
+    N_INDR => 'i',
+    N_SECT => match sections.get((sym.n_sect as usize).wrapping_sub(1)) {
+      Some(&("__TEXT", "__text")) => 't',
+      Some(&("__DATA", "__data")) => 'd',
+      Some(&(_, "__bss")) | Some(&(_, "__common")) => 'b',
+      _ => 's',
+    },
+    _ => '?',
+  };
+  if sym.is_external() {
+    letter.to_ascii_uppercase()
+  } else {
+    letter
+  }
+}
+
+// The listing for one thin file.
+pub fn list(
+  data: &[u8],
+  opts: &SymbolsOptions,
+) -> ::macho::Result<Vec<String>> {
+  let image = ImageFile::parse(data)?;
+  let sections: Vec<(&str, &str)> = image
+    .segments
+    .iter()
+    .flat_map(|seg| seg.sections.iter())
+    .map(|sect| (sect.segname.as_str(), sect.sectname.as_str()))
+    .collect();
+  let width = if image.cputype & ::macho::CPU_ARCH_ABI64 != 0 {
+    16
+  } else {
+    8
+  };
+  let mut symbols: Vec<&Nlist> =
+    image.symbols.iter().filter(|sym| opts.shows(sym)).collect();
+  symbols.sort_by(|a, b| a.name.cmp(&b.name));
+  Ok(
+    symbols
+      .iter()
+      .map(|sym| {
+        let name = if opts.demangle {
+          demangle::demangle(&sym.name).unwrap_or_else(|| sym.name.clone())
+        } else {
+          sym.name.clone()
+        };
+        if opts.names_only {
+          return name;
+        }
+        let letter = type_letter(sym, &sections);
+        if letter == 'U' || letter == 'u' {
+          format!("{:width$} {} {}", "", letter, name, width = width)
+        } else {
+          format!(
+            "{:0width$x} {} {}",
+            sym.n_value,
+            letter,
+            name,
+            width = width
+          )
+        }
+      })
+      .collect(),
+  )
+}
+
+// `mold symbols`, printing to stdout.
+pub fn run_command(args: &[String]) -> Result<()> {
+  let mut opts = SymbolsOptions::default();
+  let mut inputs: Vec<PathBuf> = Vec::new();
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-g" => opts.external_only = true,
+      "-u" => opts.undefined_only = true,
+      "-U" | "--defined-only" => opts.defined_only = true,
+      "-a" => opts.debug = true,
+      "-C" | "--demangle" => opts.demangle = true,
+      "-j" => opts.names_only = true,
+      "-arch" => {
+        let name = args.next().ok_or_else(|| {
+          SymbolsError::Usage("-arch needs an architecture".to_string())
+        })?;
+        let arch = Arch::from_name(name).ok_or_else(|| {
+          SymbolsError::Usage(format!("unknown architecture {}", name))
+        })?;
+        opts.arch = Some(arch);
+      }
+      _ if arg.starts_with('-') => {
+        return Err(SymbolsError::Usage(format!("unknown option {}", arg)));
+      }
+      _ => inputs.push(PathBuf::from(arg)),
+    }
+  }
+  if inputs.is_empty() {
+    return Err(SymbolsError::Usage("no input files".to_string()));
+  }
+  if opts.undefined_only && opts.defined_only {
+    let message = "-u and -U can't be used together".to_string();
+    return Err(SymbolsError::Usage(message));
+  }
+
+  // Like nm, each listing gets a heading unless there's only one.
+  let mut listings: Vec<(String, Vec<String>)> = Vec::new();
+  for input in inputs.iter() {
+    let data = fs::read(input)
+      .map_err(|e| SymbolsError::IoError(input.clone(), e))?;
+    let malformed = |e| SymbolsError::Malformed(input.clone(), e);
+    let mut files: Vec<(String, &[u8])> = Vec::new();
+    if archive::is_archive(&data) {
+      let members = archive::members(&data)
+        .map_err(|e| SymbolsError::Archive(input.clone(), e))?;
+      for member in members.iter().filter(|m| !m.is_symbol_table()) {
+        let name = format!("{}({})", input.display(), member.name);
+        files.push((name, member.data));
+      }
+    } else {
+      files.push((input.display().to_string(), &data[..]));
+    }
+    for (name, data) in files.into_iter() {
+      let is_fat = fat::is_fat(data);
+      for (arch, thin) in fat::slices(data).map_err(malformed)? {
+        if is_fat && opts.arch.is_some() && arch != opts.arch {
+          continue;
+        }
+        let heading = if is_fat {
+          let arch = arch.map_or("unknown", |arch| arch.name());
+          format!("{} (for architecture {})", name, arch)
+        } else {
+          name.clone()
+        };
+        listings.push((heading, list(thin, &opts).map_err(malformed)?));
+      }
+    }
+  }
+
+  let headings = listings.len() > 1;
+  for (i, (heading, lines)) in listings.iter().enumerate() {
+    if headings {
+      if i > 0 {
+        println!();
+      }
+      println!("{}:", heading);
+    }
+    for line in lines.iter() {
+      println!("{}", line);
+    }
+  }
+  Ok(())
+}
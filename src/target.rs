@@ -0,0 +1,190 @@
+// The platform and OS versions an output is built for: the LC_BUILD_VERSION
+// it gets, and checking that the inputs were built for the same platform.
+
+use std::path::{Path, PathBuf};
+
+use diagnostics;
+use image::Image;
+use macho::load_command::{LoadCommand, LC_VERSION_MIN_IPHONEOS,
+                          LC_VERSION_MIN_MACOSX, LC_VERSION_MIN_TVOS,
+                          LC_VERSION_MIN_WATCHOS};
+use macho::{Platform, Version};
+
+// Tool numbers for build_tool_version entries.
+pub const TOOL_CLANG: u32 = 1;
+pub const TOOL_SWIFT: u32 = 2;
+pub const TOOL_LD: u32 = 3;
+
+#[derive(Debug)]
+pub enum TargetError {
+  // (input path, platform it was built for, platform being linked)
+  PlatformMismatch(PathBuf, Platform, Platform),
+  // A dylib linked into a zippered output which only supports one of its
+  // platforms.
+  NotZippered(PathBuf),
+}
+
+pub type Result<T> = ::std::result::Result<T, TargetError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+  pub platform: Platform,
+  pub min_version: Version,
+  pub sdk_version: Version,
+  // The Mac Catalyst side of a zippered macOS output, which can be loaded
+  // by both macOS and Mac Catalyst processes.
+  pub variant: Option<TargetVariant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetVariant {
+  pub platform: Platform,
+  pub min_version: Version,
+  pub sdk_version: Version,
+}
+
+fn linker_version() -> Version {
+  Version::parse(env!("CARGO_PKG_VERSION")).unwrap_or(Version(0))
+}
+
+impl Target {
+  // The LC_VERSION_MIN_* command and the OS version which introduced
+  // LC_BUILD_VERSION, for platforms which had the older commands.
+  fn legacy_command(&self) -> Option<(u32, Version)> {
+    match self.platform {
+      Platform::MacOS => {
+        Some((LC_VERSION_MIN_MACOSX, Version::new(10, 14, 0)))
+      }
+      Platform::IOS | Platform::IOSSimulator => {
+        Some((LC_VERSION_MIN_IPHONEOS, Version::new(12, 0, 0)))
+      }
+      Platform::TvOS | Platform::TvOSSimulator => {
+        Some((LC_VERSION_MIN_TVOS, Version::new(12, 0, 0)))
+      }
+      Platform::WatchOS | Platform::WatchOSSimulator => {
+        Some((LC_VERSION_MIN_WATCHOS, Version::new(5, 0, 0)))
+      }
+      _ => None,
+    }
+  }
+
+  // LC_BUILD_VERSION, or LC_VERSION_MIN_* for OS versions which predate it.
+  pub fn load_command(&self) -> LoadCommand {
+    match self.legacy_command() {
+      Some((cmd, introduced)) if self.min_version < introduced => {
+        LoadCommand::VersionMin {
+          cmd: cmd,
+          version: self.min_version,
+          sdk: self.sdk_version,
+        }
+      }
+      _ => LoadCommand::BuildVersion {
+        platform: self.platform.number(),
+        minos: self.min_version,
+        sdk: self.sdk_version,
+        tools: vec![(TOOL_LD, linker_version())],
+      },
+    }
+  }
+
+  // With a zippered output, an LC_BUILD_VERSION for each platform. Zippered
+  // outputs are new enough to never need LC_VERSION_MIN_*.
+  pub fn load_commands(&self) -> Vec<LoadCommand> {
+    let mut cmds = vec![self.load_command()];
+    if let Some(variant) = self.variant {
+      cmds.push(LoadCommand::BuildVersion {
+        platform: variant.platform.number(),
+        minos: variant.min_version,
+        sdk: variant.sdk_version,
+        tools: vec![(TOOL_LD, linker_version())],
+      });
+    }
+    cmds
+  }
+
+  fn accepts(&self, platform: Platform) -> bool {
+    // Mac Catalyst processes can load (zippered) macOS code, and zippered
+    // outputs can contain code for either.
+    platform == self.platform
+      || (self.platform == Platform::MacCatalyst && platform == Platform::MacOS)
+      || self.variant.map(|v| v.platform) == Some(platform)
+  }
+
+  // A zippered output can only link dylibs which are zippered too, since
+  // it has to load in both kinds of process. `variant` is the second
+  // platform the dylib was built for.
+  pub fn check_zippered(
+    &self,
+    path: &Path,
+    built_for: Option<(Platform, Version)>,
+    variant: Option<(Platform, Version)>,
+  ) -> Result<()> {
+    let wanted = match self.variant {
+      Some(wanted) => wanted,
+      None => return Ok(()),
+    };
+    // Dylibs which don't say are assumed to be fine anywhere.
+    if built_for.is_none() {
+      return Ok(());
+    }
+    let platforms: Vec<Platform> = built_for
+      .iter()
+      .chain(variant.iter())
+      .map(|&(platform, _)| platform)
+      .collect();
+    let zippered = platforms.contains(&self.platform)
+      && platforms.contains(&wanted.platform);
+    if zippered {
+      Ok(())
+    } else {
+      Err(TargetError::NotZippered(path.to_path_buf()))
+    }
+  }
+
+  // Inputs must be built for the platform being linked (a simulator is a
+  // platform of its own). Inputs built for a newer OS version than the
+  // deployment target, or than the zippered variant's, are allowed, but
+  // may use APIs which don't exist on the older OS.
+  pub fn check_input(
+    &self,
+    path: &Path,
+    built_for: Option<(Platform, Version)>,
+  ) -> Result<()> {
+    let (platform, version) = match built_for {
+      Some(built_for) => built_for,
+      None => return Ok(()),
+    };
+    if !self.accepts(platform) {
+      return Err(TargetError::PlatformMismatch(
+        path.to_path_buf(),
+        platform,
+        self.platform,
+      ));
+    }
+    let min_version = if platform == self.platform {
+      Some(self.min_version)
+    } else {
+      self
+        .variant
+        .filter(|variant| variant.platform == platform)
+        .map(|variant| variant.min_version)
+    };
+    match min_version {
+      Some(min_version) if version > min_version => {
+        diagnostics::warning(&format!(
+          "{} was built for newer {} version ({}) than being linked ({})",
+          path.display(),
+          platform.name(),
+          version,
+          min_version
+        ))
+      }
+      _ => (),
+    }
+    Ok(())
+  }
+}
+
+pub fn add_to_image(image: &mut Image, target: &Target) {
+  image.load_commands.extend(target.load_commands());
+}
@@ -0,0 +1,158 @@
+// --time-passes: how long each phase of a link took, and the process's
+// peak memory use when it finished (getrusage only keeps the high-water
+// mark, so a pass which used less than an earlier one shows the same peak).
+// Passes are recorded in the order they finish; a universal link records
+// each architecture's in turn.
+//
+// With the tracing feature, each pass and input file also gets a span, and
+// each input loaded an event, so a subscriber like tracing-chrome or
+// tracing-flame can show where the time goes in more detail.
+
+extern crate libc;
+
+use std::fmt::Write;
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use diagnostics::{json_string, Format};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pass {
+  pub name: &'static str,
+  pub elapsed: Duration,
+  // Peak resident set size in bytes, if the OS says.
+  pub max_rss: Option<u64>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PASSES: Mutex<Vec<Pass>> = Mutex::new(Vec::new());
+
+#[cfg(feature = "tracing")]
+pub type Span = ::tracing::span::EnteredSpan;
+// What the spans below return without the tracing feature.
+#[cfg(not(feature = "tracing"))]
+pub struct Span;
+
+// A span for the pass `name`, until it's dropped.
+#[cfg(feature = "tracing")]
+pub fn span(name: &'static str) -> Span {
+  ::tracing::info_span!("pass", name = name).entered()
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn span(_name: &'static str) -> Span {
+  Span
+}
+
+// A span for reading the input at `path`.
+#[cfg(feature = "tracing")]
+pub fn input_span(path: &Path) -> Span {
+  ::tracing::info_span!("input", path = %path.display()).entered()
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn input_span(_path: &Path) -> Span {
+  Span
+}
+
+// An input (or archive member) is part of the link.
+#[cfg(feature = "tracing")]
+pub fn loaded(path: &Path) {
+  ::tracing::debug!(path = %path.display(), "loaded");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn loaded(_path: &Path) {}
+
+pub fn enable() {
+  ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn max_rss() -> Option<u64> {
+  let mut usage: libc::rusage = unsafe { mem::zeroed() };
+  if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+    return None;
+  }
+  // Bytes on macOS, kilobytes everywhere else.
+  let scale = if cfg!(target_os = "macos") { 1 } else { 1024 };
+  Some(usage.ru_maxrss as u64 * scale)
+}
+
+// Run `f` as the pass `name`.
+pub fn time<T, F: FnOnce() -> T>(name: &'static str, f: F) -> T {
+  let _span = span(name);
+  if !ENABLED.load(Ordering::Relaxed) {
+    return f();
+  }
+  let start = Instant::now();
+  let result = f();
+  let pass = Pass {
+    name: name,
+    elapsed: start.elapsed(),
+    max_rss: max_rss(),
+  };
+  match PASSES.lock() {
+    Ok(mut passes) => passes.push(pass),
+    Err(poisoned) => poisoned.into_inner().push(pass),
+  }
+  result
+}
+
+// The passes recorded so far, which are forgotten.
+pub fn take() -> Vec<Pass> {
+  match PASSES.lock() {
+    Ok(mut passes) => passes.drain(..).collect(),
+    Err(poisoned) => poisoned.into_inner().drain(..).collect(),
+  }
+}
+
+fn seconds(elapsed: Duration) -> f64 {
+  elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9
+}
+
+pub fn to_text(passes: &[Pass]) -> String {
+  let mut out = String::new();
+  let _ = writeln!(out, "{:<12} {:>10} {:>12}", "pass", "seconds", "peak RSS");
+  for pass in passes.iter() {
+    let rss = pass
+      .max_rss
+      .map_or("-".to_string(), |rss| format!("{:.1} MB", rss as f64 / 1e6));
+    let _ = writeln!(
+      out,
+      "{:<12} {:>10.4} {:>12}",
+      pass.name,
+      seconds(pass.elapsed),
+      rss
+    );
+  }
+  let total: Duration = passes.iter().map(|pass| pass.elapsed).sum();
+  let _ = writeln!(out, "{:<12} {:>10.4}", "total", seconds(total));
+  out
+}
+
+pub fn to_json(passes: &[Pass]) -> String {
+  let passes: Vec<String> = passes
+    .iter()
+    .map(|pass| {
+      let rss = pass.max_rss.map_or("null".to_string(), |rss| rss.to_string());
+      format!(
+        "{{\"name\":{},\"seconds\":{},\"max_rss\":{}}}",
+        json_string(pass.name),
+        seconds(pass.elapsed),
+        rss
+      )
+    })
+    .collect();
+  format!("{{\"passes\":[{}]}}", passes.join(","))
+}
+
+// Print `passes` to stderr.
+pub fn print(passes: &[Pass], format: Format) {
+  match format {
+    Format::Text => eprint!("{}", to_text(passes)),
+    Format::Json => eprintln!("{}", to_json(passes)),
+  }
+}
@@ -0,0 +1,148 @@
+// Thread-local variable (`__thread` / `thread_local`) support.
+//
+// Each thread-local variable has a descriptor in __thread_vars:
+//
+//   struct tlv_descriptor {
+//     void *(*thunk)(struct tlv_descriptor *);
+//     unsigned long key;
+//     unsigned long offset;
+//   };
+//
+// The thunk is bound to dyld's `__tlv_bootstrap`, which swaps in the real
+// accessor at load time. The offset locates the variable's initial value in
+// the per-thread template, which is __thread_data followed by __thread_bss.
+
+use macho::reloc::{self, RelocError};
+use macho::{put_u64, round_up, MH_HAS_TLV_DESCRIPTORS};
+
+pub const TLV_BOOTSTRAP_SYMBOL: &str = "__tlv_bootstrap";
+
+pub const TLV_DESCRIPTOR_SIZE: u64 = 24;
+
+// An input section going into the thread-local template.
+#[derive(Debug, Clone, Copy)]
+pub struct TemplateSection {
+  pub size: u64,
+  pub align: u64,
+  // S_THREAD_LOCAL_ZEROFILL (__thread_bss) rather than
+  // S_THREAD_LOCAL_REGULAR (__thread_data).
+  pub zerofill: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct TemplateLayout {
+  // Offset of each input section within the template, in input order.
+  pub offsets: Vec<u64>,
+  // Size of the initialized part (__thread_data).
+  pub data_size: u64,
+  // Size of the zero-filled part (__thread_bss) which follows it.
+  pub bss_size: u64,
+}
+
+// Lay out the thread-local template. All the initialized sections go first so
+// that __thread_bss can be a single zero-fill range at the end.
+pub fn layout_template(sections: &[TemplateSection]) -> TemplateLayout {
+  let mut offsets: Vec<u64> = vec![0; sections.len()];
+  let mut cur: u64 = 0;
+
+  for (i, sect) in sections.iter().enumerate() {
+    if !sect.zerofill {
+      cur = round_up(cur, sect.align);
+      offsets[i] = cur;
+      cur += sect.size;
+    }
+  }
+  let data_size = cur;
+
+  for (i, sect) in sections.iter().enumerate() {
+    if sect.zerofill {
+      cur = round_up(cur, sect.align);
+      offsets[i] = cur;
+      cur += sect.size;
+    }
+  }
+
+  TemplateLayout {
+    offsets: offsets,
+    data_size: data_size,
+    bss_size: cur - data_size,
+  }
+}
+
+// The contents of a descriptor for the variable at `template_offset`. The
+// thunk is left zero, as it is filled in by a bind to TLV_BOOTSTRAP_SYMBOL.
+pub fn descriptor_contents(template_offset: u64) -> Vec<u8> {
+  let mut buf: Vec<u8> = Vec::new();
+  put_u64(&mut buf, 0);
+  put_u64(&mut buf, 0);
+  put_u64(&mut buf, template_offset);
+  buf
+}
+
+pub fn header_flags(has_descriptors: bool) -> u32 {
+  if has_descriptors {
+    MH_HAS_TLV_DESCRIPTORS
+  } else {
+    0
+  }
+}
+
+// Apply X86_64_RELOC_TLV. The compiler emits `movq _var@TLVP(%rip), %rdi`,
+// loading the descriptor's address out of a pointer. If the descriptor is
+// defined in this image (`local`), the load is rewritten to a `leaq` of the
+// descriptor itself and `target` is the descriptor; otherwise `target` is the
+// pointer in __thread_ptrs that dyld binds.
+pub fn relocate_x86_64_tlv(
+  code: &mut [u8],
+  offset: usize,
+  pc: u64,
+  target: u64,
+  local: bool,
+) -> reloc::Result<()> {
+  if offset < 2 || code[offset - 2] != 0x8b {
+    let opcode = if offset < 2 { 0 } else { code[offset - 2] as u32 };
+    return Err(RelocError::UnexpectedInstruction(offset, opcode));
+  }
+  if local {
+    code[offset - 2] = 0x8d;
+  }
+  reloc::write_rel32(code, offset, pc, target)
+}
+
+// Apply ARM64_RELOC_TLVP_LOAD_PAGE21 to the `adrp` at `offset`.
+pub fn relocate_arm64_tlvp_page21(
+  code: &mut [u8],
+  offset: usize,
+  pc: u64,
+  target: u64,
+) -> reloc::Result<()> {
+  let insn = reloc::read_u32(code, offset);
+  if !reloc::is_adrp(insn) {
+    return Err(RelocError::UnexpectedInstruction(offset, insn));
+  }
+  reloc::write_u32(code, offset, reloc::encode_adrp(insn, pc, target)?);
+  Ok(())
+}
+
+// Apply ARM64_RELOC_TLVP_LOAD_PAGEOFF12 to the `ldr` at `offset`. As on
+// x86_64, a load of a local descriptor's address becomes an `add`.
+pub fn relocate_arm64_tlvp_pageoff12(
+  code: &mut [u8],
+  offset: usize,
+  target: u64,
+  local: bool,
+) -> reloc::Result<()> {
+  let insn = reloc::read_u32(code, offset);
+  if !reloc::is_ldr_x_imm(insn) {
+    return Err(RelocError::UnexpectedInstruction(offset, insn));
+  }
+  let patched = if local {
+    let rd = insn & 0x1f;
+    let rn = (insn >> 5) & 0x1f;
+    reloc::encode_add_x_imm(rd, rn, (target & 0xfff) as u32)
+  } else {
+    reloc::encode_pageoff12(insn, target)?
+  };
+  reloc::write_u32(code, offset, patched);
+  Ok(())
+}
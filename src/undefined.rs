@@ -0,0 +1,359 @@
+// Undefined symbols: what's still referenced but not defined once every
+// input (and archive member) is loaded, and -undefined, which says what to
+// do about them. Each one is reported with everything referring to it: the
+// input and section, the symbol the reference is made from, and the source
+// line if the input has a line table. Where something defined looks like
+// what was meant, that's suggested too.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use args::LinkOptions;
+use demangle;
+use diagnostics::{Diagnostic, Severity};
+use dwarf::{self, LineRow};
+use macho::object_file::ObjectFile;
+use macho::symtab::{N_SECT, N_TYPE};
+use relocatable::RelocatableInput;
+
+const MAX_SUGGESTIONS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndefinedTreatment {
+  Error,
+  Warning,
+  Suppress,
+  // Leave them for dyld to find in whatever is loaded, with flat lookup.
+  DynamicLookup,
+}
+
+impl UndefinedTreatment {
+  pub fn parse(val: &str) -> Option<UndefinedTreatment> {
+    match val {
+      "error" => Some(UndefinedTreatment::Error),
+      "warning" => Some(UndefinedTreatment::Warning),
+      "suppress" => Some(UndefinedTreatment::Suppress),
+      "dynamic_lookup" => Some(UndefinedTreatment::DynamicLookup),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+  pub path: PathBuf,
+  pub segname: String,
+  pub sectname: String,
+  // The symbol whose contents make the reference, if any comes before it
+  // in the section.
+  pub from: Option<String>,
+  // (source file, line)
+  pub source: Option<(String, u64)>,
+}
+
+impl Reference {
+  // How sections are named in diagnostics: test.o(__TEXT,__text).
+  pub fn section_name(&self) -> String {
+    format!(
+      "{}({},{})",
+      self.path.display(),
+      self.segname,
+      self.sectname
+    )
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndefinedSymbol {
+  pub name: String,
+  pub references: Vec<Reference>,
+  // Named by -u, as well as or instead of being referred to by an input.
+  pub forced: bool,
+  // What might have been meant instead, one hint per line.
+  pub hints: Vec<String>,
+}
+
+impl UndefinedSymbol {
+  // The name as written in the source, with the symbol name after it if
+  // that's different.
+  pub fn display_name(&self) -> String {
+    display_name(&self.name)
+  }
+
+  pub fn diagnostic(&self, severity: Severity) -> Diagnostic {
+    let mut message = format!("undefined symbol: {}", self.display_name());
+    for reference in self.references.iter() {
+      message.push_str("\n  referenced ");
+      if let Some(ref from) = reference.from {
+        let from = demangle::demangle(from).unwrap_or_else(|| from.clone());
+        message.push_str(&format!("from {} ", from));
+      }
+      message.push_str(&format!("in {}", reference.section_name()));
+      if let Some((ref file, line)) = reference.source {
+        message.push_str(&format!(" at {}:{}", file, line));
+      }
+    }
+    if self.forced {
+      message.push_str("\n  referenced by -u");
+    }
+    for hint in self.hints.iter() {
+      message.push_str(&format!("\n  {}", hint));
+    }
+    let mut diagnostic = Diagnostic::new(severity, message);
+    diagnostic.code = Some("undefined-symbol");
+    diagnostic.file = self.references.first().map(|r| r.path.clone());
+    diagnostic.symbol = Some(self.name.clone());
+    diagnostic.sections = self
+      .references
+      .iter()
+      .map(|reference| reference.section_name())
+      .collect();
+    diagnostic
+  }
+}
+
+fn display_name(name: &str) -> String {
+  match demangle::demangle(name) {
+    Some(demangled) => format!("{} ({})", demangled, name),
+    None => name.to_string(),
+  }
+}
+
+// What names are compared by: as written in the source, so C names lose
+// their leading underscore and C++ names are demangled.
+fn source_name(name: &str) -> String {
+  match demangle::demangle(name) {
+    Some(demangled) => demangled,
+    None if name.starts_with('_') => name[1..].to_string(),
+    None => name.to_string(),
+  }
+}
+
+// A C++ function's name without its parameters: "ns::f" for
+// "ns::f(int) const".
+fn base_name(demangled: &str) -> &str {
+  match demangled.find('(') {
+    Some(end) => &demangled[..end],
+    None => demangled,
+  }
+}
+
+// The edit distance between `a` and `b`, counting swapping two adjacent
+// characters as one edit, or None if it's more than `limit`.
+fn edit_distance(a: &[u8], b: &[u8], limit: usize) -> Option<usize> {
+  let longer = a.len().max(b.len());
+  if longer - a.len().min(b.len()) > limit {
+    return None;
+  }
+  // Three rows of the usual table: two back, one back and this one.
+  let mut two_back: Vec<usize> = vec![0; b.len() + 1];
+  let mut one_back: Vec<usize> = (0..(b.len() + 1)).collect();
+  let mut row: Vec<usize> = vec![0; b.len() + 1];
+  for i in 1..(a.len() + 1) {
+    row[0] = i;
+    for j in 1..(b.len() + 1) {
+      let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+      row[j] = (one_back[j] + 1)
+        .min(row[j - 1] + 1)
+        .min(one_back[j - 1] + cost);
+      let swapped =
+        i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1];
+      if swapped {
+        row[j] = row[j].min(two_back[j - 2] + 1);
+      }
+    }
+    if row.iter().min().map_or(false, |&min| min > limit) {
+      return None;
+    }
+    two_back.clone_from(&one_back);
+    one_back.clone_from(&row);
+  }
+  let distance = one_back[b.len()];
+  if distance <= limit {
+    Some(distance)
+  } else {
+    None
+  }
+}
+
+// Hints for an undefined `name`, given the names which are defined: a
+// definition with the other language's linkage, which is what a missing
+// extern "C" looks like, and failing that the closest names.
+pub fn suggestions(name: &str, defined: &[&str]) -> Vec<String> {
+  let source = source_name(name);
+  let mut hints: Vec<String> = Vec::new();
+  for &candidate in defined.iter() {
+    let linkage_mismatch = match (
+      demangle::is_mangled(name),
+      demangle::demangle(candidate),
+    ) {
+      (false, Some(ref demangled)) => base_name(demangled) == source,
+      (true, None) => source_name(candidate) == base_name(&source),
+      _ => false,
+    };
+    if !linkage_mismatch {
+      continue;
+    }
+    hints.push(if demangle::is_mangled(name) {
+      format!(
+        "{} is defined with C linkage; declare it extern \"C\" where it's \
+         used",
+        candidate
+      )
+    } else {
+      format!(
+        "{} is defined with C++ linkage; declare it extern \"C\" where it's \
+         defined",
+        display_name(candidate)
+      )
+    });
+  }
+  if !hints.is_empty() {
+    return hints;
+  }
+
+  let limit = (source.len() / 3).max(1);
+  let mut close: Vec<(usize, &str)> = defined
+    .iter()
+    .filter_map(|&candidate| {
+      let candidate_source = source_name(candidate);
+      edit_distance(source.as_bytes(), candidate_source.as_bytes(), limit)
+        .map(|distance| (distance, candidate))
+    })
+    .collect();
+  close.sort();
+  close
+    .iter()
+    .take(MAX_SUGGESTIONS)
+    .map(|&(_, candidate)| format!("did you mean {}?", display_name(candidate)))
+    .collect()
+}
+
+fn line_table(object: &ObjectFile) -> Vec<LineRow> {
+  object
+    .sections
+    .iter()
+    .find(|sect| sect.sectname == dwarf::DEBUG_LINE)
+    .and_then(|sect| dwarf::line_table(&sect.contents).ok())
+    .unwrap_or_default()
+}
+
+// The last symbol defined at or before `addr` in section `n_sect`.
+fn symbol_at(object: &ObjectFile, n_sect: u8, addr: u64) -> Option<String> {
+  object
+    .symbols
+    .iter()
+    .filter(|sym| {
+      !sym.is_stab()
+        && sym.n_type & N_TYPE == N_SECT
+        && sym.n_sect == n_sect
+        && sym.n_value <= addr
+    })
+    .max_by_key(|sym| sym.n_value)
+    .map(|sym| sym.name.clone())
+}
+
+// Every symbol which is referred to (or forced with -u) but which no input
+// defines, in the order they were first referred to.
+pub fn find(
+  opts: &LinkOptions,
+  inputs: &[RelocatableInput],
+) -> Vec<UndefinedSymbol> {
+  let defined: HashSet<&str> = inputs
+    .iter()
+    .flat_map(|input| input.object.symbols.iter())
+    .filter(|sym| {
+      sym.is_external() && (!sym.is_undefined() || sym.is_common())
+    })
+    .map(|sym| sym.name.as_str())
+    .collect();
+
+  let mut undefined: Vec<UndefinedSymbol> = Vec::new();
+  let mut index: HashMap<String, usize> = HashMap::new();
+  for input in inputs.iter() {
+    let object = &input.object;
+    let mut lines: Option<Vec<LineRow>> = None;
+    for (i, sect) in object.sections.iter().enumerate() {
+      for reloc in sect.relocs.iter() {
+        if !reloc.external || reloc.scattered.is_some() {
+          continue;
+        }
+        let sym = match object.symbols.get(reloc.symbolnum as usize) {
+          Some(sym) => sym,
+          None => continue,
+        };
+        let is_undefined = sym.is_external()
+          && sym.is_undefined()
+          && !defined.contains(sym.name.as_str());
+        if !is_undefined {
+          continue;
+        }
+        let addr = sect.addr + reloc.address as u64;
+        let lines = lines.get_or_insert_with(|| line_table(object));
+        let reference = Reference {
+          path: input.path.clone(),
+          segname: sect.segname.clone(),
+          sectname: sect.sectname.clone(),
+          from: symbol_at(object, i as u8 + 1, addr),
+          source: dwarf::line_for_address(lines, addr)
+            .map(|row| (row.file.clone(), row.line)),
+        };
+        let next = undefined.len();
+        let slot = *index.entry(sym.name.clone()).or_insert(next);
+        if slot == next {
+          undefined.push(UndefinedSymbol {
+            name: sym.name.clone(),
+            references: Vec::new(),
+            forced: false,
+            hints: Vec::new(),
+          });
+        }
+        let references = &mut undefined[slot].references;
+        if !references.contains(&reference) {
+          references.push(reference);
+        }
+      }
+    }
+  }
+  // Undefined symbols nothing has a relocation for are still undefined.
+  for sym in inputs.iter().flat_map(|input| input.object.symbols.iter()) {
+    let is_undefined = sym.is_external()
+      && sym.is_undefined()
+      && !sym.is_common()
+      && !defined.contains(sym.name.as_str());
+    if is_undefined && !index.contains_key(&sym.name) {
+      index.insert(sym.name.clone(), undefined.len());
+      undefined.push(UndefinedSymbol {
+        name: sym.name.clone(),
+        references: Vec::new(),
+        forced: false,
+        hints: Vec::new(),
+      });
+    }
+  }
+  for name in opts.forced_undefined.iter() {
+    if defined.contains(name.as_str()) {
+      continue;
+    }
+    match index.get(name) {
+      Some(&slot) => undefined[slot].forced = true,
+      None => {
+        index.insert(name.clone(), undefined.len());
+        undefined.push(UndefinedSymbol {
+          name: name.clone(),
+          references: Vec::new(),
+          forced: true,
+          hints: Vec::new(),
+        });
+      }
+    }
+  }
+  if !undefined.is_empty() {
+    let mut candidates: Vec<&str> = defined.into_iter().collect();
+    candidates.sort();
+    for sym in undefined.iter_mut() {
+      sym.hints = suggestions(&sym.name, &candidates);
+    }
+  }
+  undefined
+}
@@ -0,0 +1,225 @@
+// Universal output: with several -arch options, each architecture is linked
+// on its own thread and the slices are combined into one fat file, instead
+// of leaving that to a separate lipo step.
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::thread;
+
+use args::LinkOptions;
+use dependency_info;
+use diagnostics;
+use macho::fat;
+use macho::{Arch, MachOError};
+use statistics;
+use timing;
+
+#[derive(Debug)]
+pub enum UniversalError<E> {
+  IoError(PathBuf, io::Error),
+  // Linking one of the slices failed.
+  Slice(Arch, E),
+  // The thread linking a slice panicked.
+  Panicked(Arch),
+  Malformed(MachOError),
+}
+
+pub type Result<T, E> = ::std::result::Result<T, UniversalError<E>>;
+
+// Link every -arch with `link_slice` in parallel, and combine the slices,
+// in -arch order.
+pub fn build<E>(
+  opts: &LinkOptions,
+  link_slice: fn(&LinkOptions) -> ::std::result::Result<Vec<u8>, E>,
+) -> Result<Vec<u8>, E>
+where
+  E: Debug + Send + 'static,
+{
+  let sink = diagnostics::current_sink();
+  let recorder = dependency_info::current_recorder();
+  let handles: Vec<_> = opts
+    .archs
+    .iter()
+    .map(|&arch| {
+      let slice_opts = opts.for_arch(arch);
+      let (sink, recorder) = (sink.clone(), recorder.clone());
+      let handle = thread::spawn(move || {
+        diagnostics::with_sink(sink, || {
+          dependency_info::with_recorder(recorder, || link_slice(&slice_opts))
+        })
+      });
+      (arch, handle)
+    })
+    .collect();
+
+  let mut slices: Vec<(Arch, Vec<u8>)> = Vec::new();
+  for (arch, handle) in handles {
+    match handle.join() {
+      Ok(Ok(data)) => slices.push((arch, data)),
+      Ok(Err(e)) => return Err(UniversalError::Slice(arch, e)),
+      Err(_) => return Err(UniversalError::Panicked(arch)),
+    }
+  }
+
+  let borrowed: Vec<(Arch, &[u8])> = slices
+    .iter()
+    .map(|&(arch, ref data)| (arch, data.as_slice()))
+    .collect();
+  fat::write(&borrowed).map_err(UniversalError::Malformed)
+}
+
+// Build the fat file and write it to the output path.
+pub fn run<E>(
+  opts: &LinkOptions,
+  link_slice: fn(&LinkOptions) -> ::std::result::Result<Vec<u8>, E>,
+) -> Result<(), E>
+where
+  E: Debug + Send + 'static,
+{
+  let output = build(opts, link_slice)?;
+  timing::time("write", || {
+    statistics::bytes_written(output.len() as u64);
+    File::create(&opts.output_path).and_then(|mut f| f.write_all(&output))
+  }).map_err(|e| UniversalError::IoError(opts.output_path.clone(), e))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::env;
+  use std::fs;
+  use std::os::unix::fs::PermissionsExt;
+  use std::process;
+  use std::sync::Arc;
+
+  use args::OutputKind;
+  use final_link;
+  use link_job;
+  use macho::object_file::{ObjectFile, ObjectSection};
+  use macho::symtab::{Nlist, N_EXT, N_SECT};
+  use macho::{get_u32, Platform, Version, CPU_TYPE_ARM64, CPU_TYPE_X86_64,
+              MH_EXECUTE, MH_SUBSECTIONS_VIA_SYMBOLS};
+  use relocatable::{self, RelocatableError, RelocatableInput};
+  use target::Target;
+
+  // An object for `arch` whose _main just returns.
+  fn object(arch: Arch) -> Vec<u8> {
+    let (cputype, cpusubtype, code) = match arch {
+      Arch::Arm64 => (CPU_TYPE_ARM64, 0, vec![0xc0, 0x03, 0x5f, 0xd6]),
+      _ => (CPU_TYPE_X86_64, 3, vec![0xc3]),
+    };
+    let text = ObjectSection {
+      segname: "__TEXT".to_string(),
+      sectname: "__text".to_string(),
+      size: code.len() as u64,
+      align: 2,
+      flags: 0x8000_0400,
+      contents: code,
+      ..Default::default()
+    };
+    let version = Version::new(11, 0, 0);
+    let object = ObjectFile {
+      cputype: cputype,
+      cpusubtype: cpusubtype,
+      flags: MH_SUBSECTIONS_VIA_SYMBOLS,
+      sections: vec![text],
+      symbols: vec![Nlist {
+        name: "_main".to_string(),
+        n_type: N_SECT | N_EXT,
+        n_sect: 1,
+        n_desc: 0,
+        n_value: 0,
+      }],
+      platform: Some((Platform::MacOS, version)),
+      loh: Vec::new(),
+      data_in_code: Vec::new(),
+    };
+    let target = Target {
+      platform: Platform::MacOS,
+      min_version: version,
+      sdk_version: version,
+      variant: None,
+    };
+    let input = RelocatableInput {
+      path: PathBuf::from("main.o"),
+      object: object,
+    };
+    relocatable::link(arch, &target, &[input], false).unwrap()
+  }
+
+  // -r of a fat main.o with a slice for each of `slices`, for `archs`.
+  fn options(slices: &[Arch], archs: Vec<Arch>) -> LinkOptions {
+    let objects: Vec<(Arch, Vec<u8>)> =
+      slices.iter().map(|&arch| (arch, object(arch))).collect();
+    let borrowed: Vec<(Arch, &[u8])> = objects
+      .iter()
+      .map(|&(arch, ref data)| (arch, data.as_slice()))
+      .collect();
+    let input = fat::write(&borrowed).unwrap();
+    LinkOptions {
+      output_kind: OutputKind::Relocatable,
+      arch: archs[0],
+      archs: archs,
+      min_os_version: Version::new(11, 0, 0),
+      input_paths: vec![PathBuf::from("main.o")],
+      input_buffers: vec![(PathBuf::from("main.o"), Arc::new(input))],
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn links_each_arch_into_its_slice() {
+    let archs = vec![Arch::Arm64, Arch::X86_64];
+    let opts = options(&[Arch::X86_64, Arch::Arm64], archs.clone());
+    let output = build(&opts, relocatable::build).unwrap();
+    // In -arch order, each as linking that arch alone would have made it.
+    let cputypes: Vec<u32> = fat::parse(&output)
+      .unwrap()
+      .iter()
+      .map(|slice| slice.cputype)
+      .collect();
+    assert_eq!(cputypes, vec![CPU_TYPE_ARM64, CPU_TYPE_X86_64]);
+    for &arch in archs.iter() {
+      let slice = fat::slice_for(&output, arch).unwrap().unwrap();
+      let alone = relocatable::build(&opts.for_arch(arch)).unwrap();
+      assert_eq!(slice, &alone[..]);
+    }
+  }
+
+  #[test]
+  fn links_universal_executables() {
+    let dir = env::temp_dir().join(format!("mold-universal-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let archs = vec![Arch::X86_64, Arch::Arm64];
+    let mut opts = options(&archs, archs.clone());
+    opts.output_kind = OutputKind::Executable;
+    opts.output_path = dir.join("main");
+    opts.verify_output = true;
+    link_job::run(&opts).unwrap();
+    let output = fs::read(&opts.output_path).unwrap();
+    let mode = fs::metadata(&opts.output_path).unwrap().permissions().mode();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(mode & 0o777, 0o755);
+    for &arch in archs.iter() {
+      let slice = fat::slice_for(&output, arch).unwrap().unwrap();
+      assert_eq!(get_u32(slice, 12, "").unwrap(), MH_EXECUTE);
+      let alone = final_link::build(&opts.for_arch(arch)).unwrap();
+      assert_eq!(slice, &alone[..]);
+    }
+  }
+
+  #[test]
+  fn reports_the_slice_which_failed() {
+    let opts = options(&[Arch::X86_64], vec![Arch::X86_64, Arch::Arm64]);
+    match build(&opts, relocatable::build) {
+      Err(UniversalError::Slice(
+        Arch::Arm64,
+        RelocatableError::Malformed(..),
+      )) => (),
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+  }
+}
@@ -0,0 +1,433 @@
+// Unwind info in the output: __TEXT,__unwind_info built from the inputs'
+// compact unwind entries, and what's left of __TEXT,__eh_frame.
+//
+// Most functions' unwind info fits a compact encoding, and those functions'
+// DWARF FDEs are dropped from __eh_frame. The rest (functions whose
+// encoding is DWARF mode, or which have no compact entry at all) keep their
+// FDEs, and their __unwind_info entries point at them.
+
+use std::collections::{BTreeMap, HashSet};
+
+use image::{self, Image, OutputSection};
+use macho::unwind_info::{self, CompactUnwindEntry, MAX_PERSONALITIES};
+use macho::{get_cstr, get_u16, get_u32, get_u64, get_uleb128, set_u32,
+            set_u64, Arch, MachOError, S_ATTR_LIVE_SUPPORT, S_COALESCED};
+
+// S_ATTR_NO_TOC | S_ATTR_STRIP_STATIC_SYMS, which ld64 also sets on
+// __eh_frame.
+const EH_FRAME_FLAGS: u32 =
+  S_COALESCED | 0x4000_0000 | 0x2000_0000 | S_ATTR_LIVE_SUPPORT;
+
+// DWARF pointer encodings (DW_EH_PE_*): the low bits give the format, the
+// high ones what the value is relative to.
+const DW_EH_PE_OMIT: u8 = 0xff;
+const DW_EH_PE_FORMAT_MASK: u8 = 0x0f;
+const DW_EH_PE_SIGNED: u8 = 0x08;
+const DW_EH_PE_APPLICATION_MASK: u8 = 0x70;
+const DW_EH_PE_ABSPTR: u8 = 0x00;
+const DW_EH_PE_PCREL: u8 = 0x10;
+
+#[derive(Debug)]
+pub enum UnwindError {
+  MachO(MachOError),
+  // The personality routines used, when there are more than the encoding
+  // has room for.
+  TooManyPersonalities(Vec<u64>),
+  // A function whose encoding is DWARF mode but which has no FDE.
+  MissingFde(u64),
+}
+
+impl From<MachOError> for UnwindError {
+  fn from(err: MachOError) -> Self {
+    UnwindError::MachO(err)
+  }
+}
+
+pub type Result<T> = ::std::result::Result<T, UnwindError>;
+
+// A pointer-sized field inside __eh_frame: (section offset, encoding).
+type PointerField = (usize, u8);
+
+#[derive(Debug, Clone)]
+enum RecordKind {
+  Cie {
+    personality: Option<PointerField>,
+  },
+  Fde {
+    // Section offset of the CIE.
+    cie: usize,
+    pc_begin: u64,
+    pc_range: u64,
+    pc_begin_field: PointerField,
+    lsda: Option<PointerField>,
+  },
+}
+
+#[derive(Debug, Clone)]
+struct Record {
+  offset: usize,
+  size: usize,
+  kind: RecordKind,
+}
+
+// Every CIE and FDE in `data`, which is mapped at `addr`.
+fn parse_eh_frame(data: &[u8], addr: u64) -> Result<Vec<Record>> {
+  let what = "eh_frame record";
+  // Each CIE's FDE pointer encoding and LSDA encoding.
+  let mut cies: BTreeMap<usize, (u8, Option<u8>)> = BTreeMap::new();
+  let mut records: Vec<Record> = Vec::new();
+  let mut offset = 0;
+  while offset < data.len() {
+    let length = get_u32(data, offset, what)? as usize;
+    if length == 0 {
+      // Terminator.
+      break;
+    }
+    if length == 0xffff_ffff {
+      return Err(MachOError::Malformed("64-bit eh_frame record").into());
+    }
+    let end = offset + 4 + length;
+    if end > data.len() {
+      return Err(MachOError::Truncated(what).into());
+    }
+    let id_field = offset + 4;
+    let id = get_u32(data, id_field, what)? as usize;
+    let mut cur = id_field + 4;
+    let kind = if id == 0 {
+      let (fde_encoding, lsda_encoding, personality) =
+        parse_cie_augmentation(data, &mut cur)?;
+      cies.insert(offset, (fde_encoding, lsda_encoding));
+      RecordKind::Cie {
+        personality: personality,
+      }
+    } else {
+      let cie = id_field
+        .checked_sub(id)
+        .ok_or(MachOError::Malformed("FDE's CIE pointer"))?;
+      let &(fde_encoding, lsda_encoding) = cies
+        .get(&cie)
+        .ok_or(MachOError::Malformed("FDE's CIE pointer"))?;
+      let pc_begin = read_pointer(data, cur, fde_encoding, addr)?;
+      let pc_begin_field = (cur, fde_encoding);
+      cur += pointer_size(fde_encoding)?;
+      // The range is just a size: only the format applies.
+      let range_encoding = fde_encoding & DW_EH_PE_FORMAT_MASK;
+      let pc_range = read_pointer(data, cur, range_encoding, addr)?;
+      cur += pointer_size(range_encoding)?;
+      let lsda = match lsda_encoding {
+        Some(encoding) => {
+          // The augmentation data length.
+          get_uleb128(data, &mut cur, what)?;
+          if read_raw(data, cur, encoding)? == 0 {
+            None
+          } else {
+            Some((cur, encoding))
+          }
+        }
+        None => None,
+      };
+      RecordKind::Fde {
+        cie: cie,
+        pc_begin: pc_begin,
+        pc_range: pc_range,
+        pc_begin_field: pc_begin_field,
+        lsda: lsda,
+      }
+    };
+    records.push(Record {
+      offset: offset,
+      size: end - offset,
+      kind: kind,
+    });
+    offset = end;
+  }
+  Ok(records)
+}
+
+// Read a CIE up to the end of its augmentation data, returning its FDE
+// pointer encoding, LSDA encoding, and personality pointer.
+fn parse_cie_augmentation(
+  data: &[u8],
+  cur: &mut usize,
+) -> Result<(u8, Option<u8>, Option<PointerField>)> {
+  let what = "CIE";
+  let version = *data.get(*cur).ok_or(MachOError::Truncated(what))?;
+  *cur += 1;
+  let augmentation = get_cstr(data, cur, what)?;
+  // Code and data alignment factors.
+  get_uleb128(data, cur, what)?;
+  get_uleb128(data, cur, what)?;
+  // Return address register.
+  if version == 1 {
+    *cur += 1;
+  } else {
+    get_uleb128(data, cur, what)?;
+  }
+  let mut fde_encoding = DW_EH_PE_ABSPTR;
+  let mut lsda_encoding: Option<u8> = None;
+  let mut personality: Option<PointerField> = None;
+  if !augmentation.starts_with('z') {
+    return Ok((fde_encoding, lsda_encoding, personality));
+  }
+  get_uleb128(data, cur, what)?;
+  for c in augmentation[1..].chars() {
+    let encoding = *data.get(*cur).ok_or(MachOError::Truncated(what))?;
+    match c {
+      'L' => {
+        *cur += 1;
+        lsda_encoding = Some(encoding);
+      }
+      'P' => {
+        *cur += 1;
+        personality = Some((*cur, encoding));
+        *cur += pointer_size(encoding)?;
+      }
+      'R' => {
+        *cur += 1;
+        fde_encoding = encoding;
+      }
+      'S' => (),
+      _ => return Err(MachOError::Malformed("CIE augmentation").into()),
+    }
+  }
+  Ok((fde_encoding, lsda_encoding.filter(|e| *e != DW_EH_PE_OMIT), personality))
+}
+
+fn pointer_size(encoding: u8) -> Result<usize> {
+  match encoding & DW_EH_PE_FORMAT_MASK {
+    0x00 | 0x04 | 0x0c => Ok(8),
+    0x03 | 0x0b => Ok(4),
+    0x02 | 0x0a => Ok(2),
+    _ => Err(MachOError::Malformed("eh_frame pointer encoding").into()),
+  }
+}
+
+// The stored value of a pointer field, sign-extended if it's signed.
+fn read_raw(data: &[u8], offset: usize, encoding: u8) -> Result<u64> {
+  let what = "eh_frame pointer";
+  let (raw, bits) = match pointer_size(encoding)? {
+    2 => (get_u16(data, offset, what)? as u64, 16),
+    4 => (get_u32(data, offset, what)? as u64, 32),
+    _ => (get_u64(data, offset, what)?, 64),
+  };
+  if encoding & DW_EH_PE_SIGNED != 0 && bits < 64 {
+    let shift = 64 - bits;
+    return Ok((((raw << shift) as i64) >> shift) as u64);
+  }
+  Ok(raw)
+}
+
+// The address a pointer field refers to, for a section mapped at `addr`.
+fn read_pointer(
+  data: &[u8],
+  offset: usize,
+  encoding: u8,
+  addr: u64,
+) -> Result<u64> {
+  let raw = read_raw(data, offset, encoding)?;
+  match encoding & DW_EH_PE_APPLICATION_MASK {
+    DW_EH_PE_ABSPTR => Ok(raw),
+    DW_EH_PE_PCREL => Ok(raw.wrapping_add(addr + offset as u64)),
+    _ => Err(MachOError::Malformed("eh_frame pointer encoding").into()),
+  }
+}
+
+// Keep a pc-relative field pointing at the same place after moving it by
+// `moved` bytes.
+fn adjust_pcrel(
+  data: &mut [u8],
+  field: PointerField,
+  moved: i64,
+) -> Result<()> {
+  let (offset, encoding) = field;
+  if encoding & DW_EH_PE_APPLICATION_MASK != DW_EH_PE_PCREL {
+    return Ok(());
+  }
+  let raw = read_raw(data, offset, encoding)?.wrapping_sub(moved as u64);
+  match pointer_size(encoding)? {
+    2 => {
+      data[offset] = raw as u8;
+      data[offset + 1] = (raw >> 8) as u8;
+    }
+    4 => set_u32(data, offset, raw as u32),
+    _ => set_u64(data, offset, raw),
+  }
+  Ok(())
+}
+
+// __eh_frame without the FDEs of functions whose compact encodings don't
+// need them, and CIEs no remaining FDE uses. `data` was laid out at
+// `old_addr` and the result goes at `new_addr`; its size doesn't depend on
+// either, so a caller can prune once to lay out the output and again to
+// write it. Also returns `entries` with DWARF-mode encodings pointing at the
+// FDEs' new offsets, plus DWARF-mode entries for functions which only have
+// an FDE.
+pub fn prune_eh_frame(
+  arch: Arch,
+  data: &[u8],
+  old_addr: u64,
+  new_addr: u64,
+  entries: &[CompactUnwindEntry],
+) -> Result<(Vec<u8>, Vec<CompactUnwindEntry>)> {
+  let records = parse_eh_frame(data, old_addr)?;
+  let compact: HashSet<u64> = entries
+    .iter()
+    .filter(|entry| !unwind_info::is_dwarf(arch, entry.encoding))
+    .map(|entry| entry.function)
+    .collect();
+  let keep_fde = |record: &Record| match record.kind {
+    RecordKind::Fde { pc_begin, .. } => !compact.contains(&pc_begin),
+    RecordKind::Cie { .. } => false,
+  };
+  let used_cies: HashSet<usize> = records
+    .iter()
+    .filter(|record| keep_fde(record))
+    .filter_map(|record| match record.kind {
+      RecordKind::Fde { cie, .. } => Some(cie),
+      RecordKind::Cie { .. } => None,
+    })
+    .collect();
+
+  let mut pruned: Vec<u8> = Vec::new();
+  // Old section offset of each kept record to its new one.
+  let mut moved_to: BTreeMap<usize, usize> = BTreeMap::new();
+  // Where each function's FDE ended up, and its range.
+  let mut fdes: BTreeMap<u64, (usize, u64)> = BTreeMap::new();
+  for record in records.iter() {
+    let keep = match record.kind {
+      RecordKind::Cie { .. } => used_cies.contains(&record.offset),
+      RecordKind::Fde { .. } => keep_fde(record),
+    };
+    if !keep {
+      continue;
+    }
+    let new_offset = pruned.len();
+    moved_to.insert(record.offset, new_offset);
+    let end = record.offset + record.size;
+    pruned.extend_from_slice(&data[record.offset..end]);
+    let moved = (new_addr + new_offset as u64) as i64
+      - (old_addr + record.offset as u64) as i64;
+    // Fields within the record, relative to its new start.
+    let rebase = |field: PointerField| {
+      (field.0 - record.offset + new_offset, field.1)
+    };
+    match record.kind {
+      RecordKind::Cie { personality } => {
+        if let Some(field) = personality {
+          adjust_pcrel(&mut pruned, rebase(field), moved)?;
+        }
+      }
+      RecordKind::Fde {
+        cie,
+        pc_begin,
+        pc_range,
+        pc_begin_field,
+        lsda,
+      } => {
+        // CIEs come before their FDEs, so this one has already moved.
+        let id_field = new_offset + 4;
+        set_u32(&mut pruned, id_field, (id_field - moved_to[&cie]) as u32);
+        adjust_pcrel(&mut pruned, rebase(pc_begin_field), moved)?;
+        if let Some(field) = lsda {
+          adjust_pcrel(&mut pruned, rebase(field), moved)?;
+        }
+        fdes.insert(pc_begin, (new_offset, pc_range));
+      }
+    }
+  }
+  if !pruned.is_empty() {
+    // Keep the terminator.
+    pruned.extend_from_slice(&[0; 4]);
+  }
+
+  let mut updated: Vec<CompactUnwindEntry> = Vec::new();
+  for entry in entries.iter() {
+    let mut entry = *entry;
+    if unwind_info::is_dwarf(arch, entry.encoding) {
+      let &(offset, _) = fdes
+        .get(&entry.function)
+        .ok_or(UnwindError::MissingFde(entry.function))?;
+      entry.encoding =
+        unwind_info::set_dwarf_offset(entry.encoding, offset as u32)?;
+    }
+    updated.push(entry);
+  }
+  let described: HashSet<u64> =
+    entries.iter().map(|entry| entry.function).collect();
+  for (&function, &(offset, range)) in fdes.iter() {
+    if !described.contains(&function) {
+      let mode = unwind_info::dwarf_mode(arch);
+      updated.push(CompactUnwindEntry {
+        function: function,
+        length: range as u32,
+        encoding: unwind_info::set_dwarf_offset(mode, offset as u32)?,
+        personality: None,
+        lsda: None,
+      });
+    }
+  }
+  Ok((pruned, updated))
+}
+
+// The distinct personality routines, in the order functions use them.
+fn personalities(entries: &[CompactUnwindEntry]) -> Result<Vec<u64>> {
+  let mut sorted: Vec<&CompactUnwindEntry> = entries.iter().collect();
+  sorted.sort_by_key(|entry| entry.function);
+  let mut personalities: Vec<u64> = Vec::new();
+  for entry in sorted.iter() {
+    if let Some(personality) = entry.personality {
+      if !personalities.contains(&personality) {
+        personalities.push(personality);
+      }
+    }
+  }
+  if personalities.len() > MAX_PERSONALITIES {
+    return Err(UnwindError::TooManyPersonalities(personalities));
+  }
+  Ok(personalities)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UnwindSections {
+  pub unwind_info: Option<Vec<u8>>,
+  pub eh_frame: Option<Vec<u8>>,
+}
+
+// The unwind sections for an image mapped at `image_base`, given every
+// input's compact unwind entries and the merged __eh_frame, if there is one,
+// as (contents, old address, new address).
+pub fn synthesize(
+  arch: Arch,
+  image_base: u64,
+  entries: &[CompactUnwindEntry],
+  eh_frame: Option<(&[u8], u64, u64)>,
+) -> Result<UnwindSections> {
+  let (eh_frame, entries) = match eh_frame {
+    Some((data, old_addr, new_addr)) => {
+      let (pruned, entries) =
+        prune_eh_frame(arch, data, old_addr, new_addr, entries)?;
+      (Some(pruned).filter(|data| !data.is_empty()), entries)
+    }
+    None => (None, entries.to_vec()),
+  };
+  let personalities = personalities(&entries)?;
+  Ok(UnwindSections {
+    unwind_info: unwind_info::build(&entries, image_base, &personalities)?,
+    eh_frame: eh_frame,
+  })
+}
+
+pub fn add_to_image(
+  image: &mut Image,
+  sections: UnwindSections,
+) -> image::Result<()> {
+  if let Some(contents) = sections.unwind_info {
+    let sect = OutputSection::new("__unwind_info", contents, 2, 0);
+    image.add_section("__TEXT", sect)?;
+  }
+  if let Some(contents) = sections.eh_frame {
+    let sect = OutputSection::new("__eh_frame", contents, 3, EH_FRAME_FLAGS);
+    image.add_section("__TEXT", sect)?;
+  }
+  Ok(())
+}
@@ -0,0 +1,110 @@
+// LC_UUID generation. Like ld64, the UUID is by default an MD5 hash of the
+// output with the UUID itself zeroed, so linking the same inputs twice gives
+// byte-identical results. -random_uuid and -no_uuid override this.
+
+use std::fs::File;
+use std::io::{self, Read};
+
+use macho::load_command::LoadCommand;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UuidMode {
+  Content,
+  Random,
+  None,
+}
+
+// The LC_UUID to emit. Content UUIDs start out zeroed and are filled in by
+// `fill_content_uuid` once the rest of the output is written.
+pub fn load_command(mode: UuidMode) -> io::Result<Option<LoadCommand>> {
+  match mode {
+    UuidMode::Content => Ok(Some(LoadCommand::Uuid([0; 16]))),
+    UuidMode::Random => Ok(Some(LoadCommand::Uuid(random_uuid()?))),
+    UuidMode::None => Ok(None),
+  }
+}
+
+// Mark `bytes` as an RFC 4122 UUID of the given version.
+fn set_version(mut bytes: [u8; 16], version: u8) -> [u8; 16] {
+  bytes[6] = (bytes[6] & 0x0f) | (version << 4);
+  bytes[8] = (bytes[8] & 0x3f) | 0x80;
+  bytes
+}
+
+pub fn random_uuid() -> io::Result<[u8; 16]> {
+  let mut bytes: [u8; 16] = [0; 16];
+  File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+  Ok(set_version(bytes, 4))
+}
+
+// Hash the whole output, whose UUID at `offset` is still zero, and store the
+// result there.
+pub fn fill_content_uuid(output: &mut [u8], offset: usize) {
+  let uuid = set_version(md5(output), 3);
+  output[offset..(offset + 16)].copy_from_slice(&uuid);
+}
+
+const MD5_SHIFTS: [u32; 64] = [
+  7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5,
+  9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11,
+  16, 23, 4, 11, 16, 23, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10,
+  15, 21,
+];
+
+// RFC 1321.
+pub fn md5(data: &[u8]) -> [u8; 16] {
+  // floor(abs(sin(i + 1)) * 2^32)
+  let k: Vec<u32> = (0..64)
+    .map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32)
+    .collect();
+  let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+  let mut message = data.to_vec();
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  let bit_len = (data.len() as u64).wrapping_mul(8);
+  for i in 0..8 {
+    message.push((bit_len >> (i * 8)) as u8);
+  }
+
+  for chunk in message.chunks(64) {
+    let mut m: [u32; 16] = [0; 16];
+    for (i, word) in m.iter_mut().enumerate() {
+      *word = (0..4).fold(0, |acc, j| {
+        acc | ((chunk[i * 4 + j] as u32) << (j * 8))
+      });
+    }
+    let (mut a, mut b, mut c, mut d) = (state[0], state[1], state[2], state[3]);
+    for i in 0..64 {
+      let (f, g) = match i / 16 {
+        0 => ((b & c) | (!b & d), i),
+        1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+        2 => (b ^ c ^ d, (3 * i + 5) % 16),
+        _ => (c ^ (b | !d), (7 * i) % 16),
+      };
+      let rotated = a
+        .wrapping_add(f)
+        .wrapping_add(k[i])
+        .wrapping_add(m[g])
+        .rotate_left(MD5_SHIFTS[i]);
+      a = d;
+      d = c;
+      c = b;
+      b = b.wrapping_add(rotated);
+    }
+    state[0] = state[0].wrapping_add(a);
+    state[1] = state[1].wrapping_add(b);
+    state[2] = state[2].wrapping_add(c);
+    state[3] = state[3].wrapping_add(d);
+  }
+
+  let mut digest: [u8; 16] = [0; 16];
+  for (i, word) in state.iter().enumerate() {
+    for j in 0..4 {
+      digest[i * 4 + j] = (word >> (j * 8)) as u8;
+    }
+  }
+  digest
+}
@@ -0,0 +1,44 @@
+// -v: what this linker is and how it's set up for the link, on stderr, for
+// bug reports and for working out why a library wasn't found. Given with no
+// inputs, as in `ld -v`, that's all the link does.
+
+use args::{LinkOptions, LtoBackend};
+use final_link;
+use lto;
+
+fn yes_no(enabled: bool) -> &'static str {
+  if enabled {
+    "yes"
+  } else {
+    "no"
+  }
+}
+
+pub fn print(opts: &LinkOptions) {
+  eprintln!("@(#)PROGRAM:ld  PROJECT:mold-{}", env!("CARGO_PKG_VERSION"));
+  // What final links can be made for, as ld64 lists them.
+  let archs: Vec<&str> =
+    final_link::ARCHS.iter().map(|arch| arch.name()).collect();
+  eprintln!("configured to support archs: {}", archs.join(" "));
+  match opts.lto_backend() {
+    LtoBackend::LibLto => eprintln!(
+      "LTO support using: libLTO ({})",
+      lto::library_path(opts).display()
+    ),
+    #[cfg(feature = "llvm")]
+    LtoBackend::Llvm => eprintln!("LTO support using: LLVM (built in)"),
+  }
+  eprintln!("ad-hoc code signing: {}", yes_no(opts.use_adhoc_codesign()));
+  eprintln!("chained fixups: {}", yes_no(opts.use_chained_fixups()));
+  for root in opts.effective_syslibroots().iter() {
+    eprintln!("SDK root: {}", root.display());
+  }
+  eprintln!("Library search paths:");
+  for dir in opts.library_search_paths().iter() {
+    eprintln!("\t{}", dir.display());
+  }
+  eprintln!("Framework search paths:");
+  for dir in opts.framework_search_paths().iter() {
+    eprintln!("\t{}", dir.display());
+  }
+}
@@ -0,0 +1,574 @@
+// Linking for Apple targets without an Apple host: these run the linker on
+// the checked-in hello-world object (test.o, built from test.c for x86_64
+// macOS), and need nothing from macOS or Xcode to pass.
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+const MH_MAGIC_64: u32 = 0xfeedfacf;
+const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+const MH_OBJECT: u32 = 0x1;
+
+fn fixture(name: &str) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join(name)
+}
+
+// A fresh directory for one test's outputs.
+fn scratch_dir(test: &str) -> PathBuf {
+  let name = format!("mold-{}-{}", test, process::id());
+  let dir = env::temp_dir().join(name);
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+// Run the linker with `args`, for x86_64 macOS, panicking if it fails.
+// Returns what it printed to stderr.
+fn link(args: &[&str]) -> String {
+  let output = Command::new(env!("CARGO_BIN_EXE_mold"))
+    .args(&["-arch", "x86_64", "-macosx_version_min", "10.13"])
+    .args(args)
+    .output()
+    .unwrap();
+  assert!(
+    output.status.success(),
+    "link failed: {}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  String::from_utf8(output.stderr).unwrap()
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+  (0..4).fold(0, |acc, i| acc | ((data[offset + i] as u32) << (i * 8)))
+}
+
+#[test]
+fn links_hello_world() {
+  let dir = scratch_dir("hello");
+  let out = dir.join("hello.o");
+  let input = fixture("test.o");
+  link(&["-r", "-o", out.to_str().unwrap(), input.to_str().unwrap()]);
+
+  let data = fs::read(&out).unwrap();
+  assert_eq!(read_u32(&data, 0), MH_MAGIC_64);
+  assert_eq!(read_u32(&data, 4), CPU_TYPE_X86_64);
+  assert_eq!(read_u32(&data, 12), MH_OBJECT);
+  let greeting = b"hello, world!";
+  assert!(data.windows(greeting.len()).any(|w| w == greeting));
+}
+
+#[test]
+fn output_does_not_depend_on_the_host() {
+  // Everything is written byte by byte in little-endian order, with no
+  // timestamps or host paths, so two links are identical.
+  let dir = scratch_dir("reproducible");
+  let input = fixture("test.o");
+  let outputs: Vec<Vec<u8>> = ["a.o", "b.o"]
+    .iter()
+    .map(|name| {
+      let out = dir.join(name);
+      link(&["-r", "-o", out.to_str().unwrap(), input.to_str().unwrap()]);
+      fs::read(&out).unwrap()
+    })
+    .collect();
+  assert_eq!(outputs[0], outputs[1]);
+}
+
+#[test]
+fn finds_frameworks_in_an_sdk_on_case_sensitive_filesystems() {
+  let dir = scratch_dir("sdk");
+  let sdk = dir.join("MacOSX.sdk");
+  let framework = sdk.join("System/Library/Frameworks/Foundation.framework");
+  fs::create_dir_all(&framework).unwrap();
+  let found = framework.join("Foundation");
+  let input = fixture("test.o");
+  link(&[
+    "-dylib",
+    "-install_name",
+    "/System/Library/Frameworks/Foundation.framework/Foundation",
+    "-undefined",
+    "dynamic_lookup",
+    "-o",
+    found.to_str().unwrap(),
+    input.to_str().unwrap(),
+  ]);
+  let out = dir.join("out");
+  let info = dir.join("info.dat");
+  link(&[
+    "-undefined",
+    "dynamic_lookup",
+    "-syslibroot",
+    sdk.to_str().unwrap(),
+    "-F/System/Library/Frameworks",
+    "-framework",
+    "foundation",
+    "-dependency_info",
+    info.to_str().unwrap(),
+    "-o",
+    out.to_str().unwrap(),
+    input.to_str().unwrap(),
+  ]);
+
+  let data = fs::read(&info).unwrap();
+  let found = found.to_str().unwrap().as_bytes();
+  assert!(data.windows(found.len()).any(|w| w == found));
+}
+
+#[test]
+fn times_passes() {
+  let dir = scratch_dir("time-passes");
+  let out = dir.join("hello.o");
+  let out = out.to_str().unwrap();
+  let input = fixture("test.o");
+  let input = input.to_str().unwrap();
+
+  let report = link(&["-r", "--time-passes", "-o", out, input]);
+  for pass in ["parse", "layout", "resolve", "fixups", "linkedit", "write"]
+    .iter()
+  {
+    assert!(
+      report.lines().any(|line| line.starts_with(pass)),
+      "no {} in {}",
+      pass,
+      report
+    );
+  }
+  assert!(report.lines().last().unwrap().starts_with("total"));
+
+  let json = link(&["-r", "--time-passes=json", "-o", out, input]);
+  assert!(json.starts_with("{\"passes\":[{\"name\":\"parse\",\"seconds\":"));
+  assert!(json.contains("\"name\":\"write\""));
+}
+
+// A BSD archive holding test.o.
+fn archive(path: &Path) {
+  let object = fs::read(fixture("test.o")).unwrap();
+  let mut data: Vec<u8> = b"!<arch>\n".to_vec();
+  let header = format!(
+    "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+    "test.o",
+    0,
+    0,
+    0,
+    644,
+    object.len()
+  );
+  data.extend_from_slice(header.as_bytes());
+  data.extend_from_slice(&object);
+  if data.len() % 2 != 0 {
+    data.push(b'\n');
+  }
+  fs::write(path, &data).unwrap();
+}
+
+#[test]
+fn honors_ld64_environment_variables() {
+  let dir = scratch_dir("ld-env");
+  let lib = dir.join("libhello.a");
+  archive(&lib);
+  let trace = dir.join("trace.log");
+  let out = dir.join("hello.o");
+  let output = Command::new(env!("CARGO_BIN_EXE_mold"))
+    .args(&["-arch", "x86_64", "-macosx_version_min", "10.13", "-r"])
+    .args(&["-all_load", "-o", out.to_str().unwrap(), lib.to_str().unwrap()])
+    .env("RC_TRACE_ARCHIVES", "1")
+    .env("LD_TRACE_FILE", &trace)
+    .env("LD_PRINT_OPTIONS", "1")
+    .output()
+    .unwrap();
+  assert!(output.status.success());
+
+  let stderr = String::from_utf8(output.stderr).unwrap();
+  assert!(stderr.contains("[Logging ld64 options]\t-all_load\n"));
+  let log = fs::read_to_string(&trace).unwrap();
+  assert_eq!(
+    log,
+    format!("[Logging for XBS] Used static archive: {}\n", lib.display())
+  );
+}
+
+#[test]
+fn prints_the_version_and_search_paths() {
+  let dir = scratch_dir("verbose");
+  let lib = dir.to_str().unwrap();
+  let report = link(&["-v", &format!("-L{}", lib)]);
+
+  assert!(report.starts_with("@(#)PROGRAM:ld  PROJECT:mold-"));
+  let archs = "configured to support archs: x86_64 arm64 arm64e\n";
+  assert!(report.contains(archs));
+  assert!(report.contains(&format!("Library search paths:\n\t{}\n", lib)));
+  assert!(report.contains("Framework search paths:\n"));
+}
+
+#[test]
+fn links_through_a_daemon() {
+  let dir = scratch_dir("daemon");
+  let socket = dir.join("mold.sock");
+  let lib = dir.join("libtest.a");
+  archive(&lib);
+  let mut daemon = Command::new(env!("CARGO_BIN_EXE_mold"))
+    .args(&["--daemon", socket.to_str().unwrap()])
+    .spawn()
+    .unwrap();
+  // The socket exists a moment before the daemon listens on it.
+  while UnixStream::connect(&socket).is_err() {
+    thread::sleep(Duration::from_millis(10));
+  }
+  // Only this user can connect.
+  let mode = fs::metadata(&socket).unwrap().permissions().mode();
+  assert_eq!(mode & 0o777, 0o600);
+
+  // A second daemon doesn't take the socket over, and only sockets are
+  // replaced.
+  let not_a_socket = dir.join("not-a-socket");
+  fs::write(&not_a_socket, b"keep").unwrap();
+  for path in [&socket, &not_a_socket].iter() {
+    let status = Command::new(env!("CARGO_BIN_EXE_mold"))
+      .args(&["--daemon", path.to_str().unwrap()])
+      .stderr(Stdio::null())
+      .status()
+      .unwrap();
+    assert!(!status.success());
+  }
+  assert_eq!(fs::read(&not_a_socket).unwrap(), b"keep");
+
+  let local = dir.join("local.o");
+  let lib = lib.to_str().unwrap();
+  link(&["-r", "-all_load", "-o", local.to_str().unwrap(), lib]);
+  let greeting = b"hello, world!";
+  let linked = fs::read(&local).unwrap();
+  assert!(linked.windows(greeting.len()).any(|w| w == greeting));
+  // Twice, the second time with the archive already parsed. Paths are
+  // relative to the client's directory, not the daemon's.
+  for name in ["a.o", "b.o"].iter() {
+    let out = dir.join(name);
+    let output = Command::new(env!("CARGO_BIN_EXE_mold"))
+      .current_dir(&dir)
+      .args(&["--daemon-socket", socket.to_str().unwrap()])
+      .args(&["-arch", "x86_64", "-macosx_version_min", "10.13"])
+      .args(&["-r", "-all_load", "-o", name, "libtest.a"])
+      .output()
+      .unwrap();
+    assert!(output.status.success());
+    assert_eq!(fs::read(&out).unwrap(), fs::read(&local).unwrap());
+  }
+
+  // Another client's libtest.a is another file, even if it looks the same.
+  let other = dir.join("other");
+  fs::create_dir(&other).unwrap();
+  let mut data = fs::read(dir.join("libtest.a")).unwrap();
+  let at = data.windows(greeting.len()).position(|w| w == greeting);
+  data[at.unwrap()] = b'H';
+  fs::write(other.join("libtest.a"), &data).unwrap();
+  let modified = fs::metadata(lib).unwrap().modified().unwrap();
+  fs::File::options()
+    .write(true)
+    .open(other.join("libtest.a"))
+    .and_then(|file| file.set_modified(modified))
+    .unwrap();
+  let output = Command::new(env!("CARGO_BIN_EXE_mold"))
+    .current_dir(&other)
+    .args(&["--daemon-socket", socket.to_str().unwrap()])
+    .args(&["-arch", "x86_64", "-macosx_version_min", "10.13"])
+    .args(&["-r", "-all_load", "-o", "a.o", "libtest.a"])
+    .output()
+    .unwrap();
+  assert!(output.status.success());
+  let linked = fs::read(other.join("a.o")).unwrap();
+  assert!(linked.windows(greeting.len()).any(|w| w == b"Hello, world!"));
+
+  // A failed link fails the client, with the daemon's diagnostics.
+  let output = Command::new(env!("CARGO_BIN_EXE_mold"))
+    .current_dir(&dir)
+    .args(&["--daemon-socket", socket.to_str().unwrap()])
+    .args(&["-arch", "x86_64", "-r", "-o", "c.o", "missing.o"])
+    .output()
+    .unwrap();
+  assert!(!output.status.success());
+  assert!(String::from_utf8(output.stderr)
+    .unwrap()
+    .starts_with("mold: error: "));
+  daemon.kill().unwrap();
+  let _ = daemon.wait();
+}
+
+#[test]
+fn skips_links_when_nothing_changed() {
+  let dir = scratch_dir("skip-unchanged");
+  let out = dir.join("hello.o");
+  let info = dir.join("info.dat");
+  let input = dir.join("test.o");
+  fs::copy(fixture("test.o"), &input).unwrap();
+  let args = [
+    "-r",
+    "--skip-unchanged",
+    "-dependency_info",
+    info.to_str().unwrap(),
+    "-o",
+    out.to_str().unwrap(),
+    input.to_str().unwrap(),
+  ];
+  let skipped = |info: &Path| {
+    let data = fs::read(info).unwrap();
+    let note = b"(skipped: output up to date)";
+    data.windows(note.len()).any(|w| w == note)
+  };
+
+  link(&args);
+  assert!(!skipped(&info));
+  let linked = fs::read(&out).unwrap();
+  link(&args);
+  assert!(skipped(&info));
+
+  // A changed output is relinked, even though the inputs are the same.
+  fs::write(&out, b"changed").unwrap();
+  link(&args);
+  assert!(!skipped(&info));
+  assert_eq!(fs::read(&out).unwrap(), linked);
+
+  // So is one whose input has changed: here, to the output of a link of it.
+  link(&args);
+  assert!(skipped(&info));
+  fs::copy(&out, &input).unwrap();
+  link(&args);
+  assert!(!skipped(&info));
+}
+
+#[test]
+fn reports_every_definition_of_a_duplicate_symbol() {
+  let dir = scratch_dir("duplicates");
+  let out = dir.join("dup.o");
+  let copies: Vec<PathBuf> = ["a.o", "b.o", "c.o"]
+    .iter()
+    .map(|name| {
+      let copy = dir.join(name);
+      fs::copy(fixture("test.o"), &copy).unwrap();
+      copy
+    })
+    .collect();
+
+  let output = Command::new(env!("CARGO_BIN_EXE_mold"))
+    .args(&["-arch", "x86_64", "-macosx_version_min", "10.13", "-r", "-o"])
+    .arg(&out)
+    .args(&copies)
+    .output()
+    .unwrap();
+  assert!(!output.status.success());
+  let stderr = String::from_utf8(output.stderr).unwrap();
+  assert!(stderr.contains("duplicate symbol: _main"), "{}", stderr);
+  for copy in copies.iter() {
+    let line = format!("defined in {}", copy.display());
+    assert!(stderr.contains(&line), "{} not in {}", line, stderr);
+  }
+}
+
+#[test]
+fn allows_listed_symbols_to_be_undefined() {
+  let dir = scratch_dir("dynamic-lookup");
+  let out = dir.join("hello.o");
+  let out = out.to_str().unwrap();
+  let input = fixture("test.o");
+  let input = input.to_str().unwrap();
+  let list = dir.join("expected.txt");
+  fs::write(&list, "# resolved by the host\n_puts\n").unwrap();
+
+  let strict = Command::new(env!("CARGO_BIN_EXE_mold"))
+    .args(&["-arch", "x86_64", "-macosx_version_min", "10.13"])
+    .args(&["-r", "-undefined", "error", "-o", out, input])
+    .output()
+    .unwrap();
+  assert!(!strict.status.success());
+  link(&["-r", "-undefined", "error", "-U", "_puts", "-o", out, input]);
+  let list = list.to_str().unwrap();
+  let warnings = link(&[
+    "-r",
+    "-undefined",
+    "warning",
+    "-dynamic_lookup_list",
+    list,
+    "-o",
+    out,
+    input,
+  ]);
+  assert!(!warnings.contains("_puts"), "{}", warnings);
+}
+
+#[test]
+fn prints_statistics() {
+  let dir = scratch_dir("statistics");
+  let out = dir.join("hello.o");
+  let input = fixture("test.o");
+  let size = fs::metadata(&input).unwrap().len();
+
+  let report = link(&[
+    "-r",
+    "-print_statistics",
+    "-o",
+    out.to_str().unwrap(),
+    input.to_str().unwrap(),
+  ]);
+  let count = |name: &str| -> u64 {
+    let line = report.lines().find(|line| line.starts_with(name));
+    let line = line.unwrap_or_else(|| panic!("no {} in {}", name, report));
+    line[name.len()..].trim().parse().unwrap()
+  };
+  assert_eq!(count("objects parsed"), 1);
+  assert!(count("symbols resolved") > 0);
+  assert_eq!(count("bytes read"), size);
+  let written = fs::metadata(&out).unwrap().len();
+  assert_eq!(count("bytes written"), written);
+  assert!(report.contains("time in resolve"));
+  assert!(report.lines().any(|line| line.starts_with("peak RSS")));
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+  read_u32(data, offset) as u64 | (read_u32(data, offset + 4) as u64) << 32
+}
+
+// test.o with its __cstring made a zerofill section of `size` bytes, which
+// takes no space in the file.
+fn zerofill_cstring(path: &Path, size: u64) {
+  const SECTION: usize = 184;
+  let mut data = fs::read(fixture("test.o")).unwrap();
+  assert_eq!(&data[SECTION..SECTION + 9], b"__cstring");
+  // size, offset and flags (S_ZEROFILL), little-endian.
+  let size: Vec<u8> = (0..8).map(|i| (size >> (i * 8)) as u8).collect();
+  data[SECTION + 40..SECTION + 48].copy_from_slice(&size);
+  data[SECTION + 48..SECTION + 52].copy_from_slice(&[0; 4]);
+  data[SECTION + 64..SECTION + 68].copy_from_slice(&[1, 0, 0, 0]);
+  fs::write(path, &data).unwrap();
+}
+
+#[test]
+fn links_zerofill_sections_larger_than_4gb() {
+  // A 5GB __cstring: its size and the segment's don't fit in 32 bits.
+  const SIZE: u64 = 5 << 30;
+  let dir = scratch_dir("huge-zerofill");
+  let input = dir.join("huge.o");
+  let out = dir.join("out.o");
+  zerofill_cstring(&input, SIZE);
+
+  link(&["-r", "-o", out.to_str().unwrap(), input.to_str().unwrap()]);
+  let data = fs::read(&out).unwrap();
+  assert!((data.len() as u64) < SIZE);
+  // The object's one segment is the first load command.
+  let segment = 32;
+  assert!(read_u64(&data, segment + 32) > SIZE);
+  let nsects = read_u32(&data, segment + 64) as usize;
+  let sizes: Vec<u64> = (0..nsects)
+    .map(|i| read_u64(&data, segment + 72 + 80 * i + 40))
+    .collect();
+  assert!(sizes.contains(&SIZE), "{:?}", sizes);
+}
+
+#[test]
+fn explains_relocations_which_overflow() {
+  // The second object's __cstring ends up after the first's 5GB one, out
+  // of reach of the 32-bit displacement in its __text.
+  let dir = scratch_dir("overflow");
+  let (huge, small) = (dir.join("huge.o"), dir.join("small.o"));
+  zerofill_cstring(&huge, 5 << 30);
+  zerofill_cstring(&small, 16);
+  // With its _main renamed, so the two don't clash, and the reference to
+  // the string made section-relative, as it would be to an L label.
+  let mut data = fs::read(&small).unwrap();
+  let at = data.windows(6).position(|w| w == b"_main\0").unwrap();
+  data[at..at + 5].copy_from_slice(b"_hook");
+  // __text's section header is just before __cstring's.
+  let reloff = read_u32(&data, 104 + 56) as usize;
+  let signed = (0..2).map(|i| reloff + 8 * i).find(|&r| data[r] == 7);
+  let signed = signed.unwrap();
+  // Section 2, pc-relative, 4 bytes, not extern, X86_64_RELOC_SIGNED.
+  let info: u32 = 2 | 1 << 24 | 2 << 25 | 1 << 28;
+  data[signed + 4..signed + 8].copy_from_slice(&info.to_le_bytes());
+  // The displacement from the end of the instruction to the string.
+  let text = read_u32(&data, 104 + 48) as usize;
+  data[text + 7] = 0x17 - 0xb;
+  fs::write(&small, &data).unwrap();
+  let out = dir.join("out.o");
+  let output = Command::new(env!("CARGO_BIN_EXE_mold"))
+    .args(&["-arch", "x86_64", "-macosx_version_min", "10.13", "-r", "-o"])
+    .args(&[&out, &huge, &small])
+    .output()
+    .unwrap();
+  assert!(!output.status.success());
+
+  let stderr = String::from_utf8(output.stderr).unwrap();
+  let expected = format!(
+    "relocation out of range: rel32 fixup in {}(__TEXT,__text)",
+    small.display()
+  );
+  assert!(stderr.contains(&expected), "{}", stderr);
+  assert!(stderr.contains("\n  in _hook\n"), "{}", stderr);
+  assert!(stderr.contains("refers to __TEXT,__cstring"), "{}", stderr);
+  assert!(stderr.contains("-mcmodel=large"), "{}", stderr);
+}
+
+#[test]
+fn links_with_the_arguments_rustc_gives_cc() {
+  let dir = scratch_dir("cc-flavor");
+  let out = dir.join("hello.o");
+  let output = Command::new(env!("CARGO_BIN_EXE_mach-o-linker"))
+    .args(&["-arch", "x86_64", "-m64"])
+    .arg(fixture("test.o"))
+    .args(&["-nodefaultlibs", "-fPIC", "-g", "-L", "/nonexistent"])
+    .args(&["-mmacosx-version-min=10.13", "-Wl,-r,-x", "-Xlinker", "-S"])
+    .arg("-o")
+    .arg(&out)
+    .output()
+    .unwrap();
+  assert!(
+    output.status.success(),
+    "link failed: {}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  let data = fs::read(&out).unwrap();
+  assert_eq!(read_u32(&data, 0), MH_MAGIC_64);
+  assert_eq!(read_u32(&data, 12), MH_OBJECT);
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_cargo_with_mach_o_linker_for_apple_targets() {
+  use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+
+  let dir = scratch_dir("cargo-mach-link");
+  // A stand-in for cargo which records its arguments and linker.
+  let cargo = dir.join("cargo");
+  let record = dir.join("record.txt");
+  let script = format!(
+    "#!/bin/sh\necho \"$@\" \"$CARGO_TARGET_AARCH64_APPLE_DARWIN_LINKER\" \
+     > {}\n",
+    record.display()
+  );
+  fs::write(&cargo, script).unwrap();
+  fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+
+  let status = Command::new(env!("CARGO_BIN_EXE_cargo-mach-link"))
+    .args(&["mach-link", "--target", "aarch64-apple-darwin", "--release"])
+    .env("CARGO", &cargo)
+    .status()
+    .unwrap();
+  assert!(status.success());
+  let record = fs::read_to_string(&record).unwrap();
+  assert!(
+    record.starts_with("build --target aarch64-apple-darwin --release "),
+    "{}",
+    record
+  );
+  assert!(record.trim_end().ends_with("mach-o-linker"), "{}", record);
+
+  let status = Command::new(env!("CARGO_BIN_EXE_cargo-mach-link"))
+    .args(&["mach-link", "build"])
+    .env("CARGO", &cargo)
+    .status()
+    .unwrap();
+  assert!(!status.success());
+}
@@ -0,0 +1,642 @@
+// Linking in-process through LinkJob.
+
+extern crate mold;
+
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use mold::args::{self, ArgsError, LtoBackend};
+use mold::capi::{mol_link, MolDiagnostic, MOL_SEVERITY_ERROR};
+use mold::bfd::{ForeignObject, ForeignReloc, ForeignSection, ForeignSymbol,
+                ForeignSymbolKind, RelocTarget, BSF_GLOBAL, BSF_SECTION_SYM,
+                SEC_ALLOC, SEC_CODE, SEC_HAS_CONTENTS, SEC_MERGE,
+                SEC_READONLY, SEC_STRINGS};
+use mold::elf;
+use mold::order_file::OrderFile;
+use mold::profile_order::{Profile, ProfileError};
+use mold::macho::reloc::{X86_64_RELOC_BRANCH, X86_64_RELOC_SIGNED};
+use mold::relocatable::RelocatableError;
+use mold::resolve::{ConcurrentSymbolTable, InputSymbol, ResolveError,
+                    SymbolKind, SymbolTable};
+use mold::target::TargetError;
+use mold::undefined::UndefinedTreatment;
+use mold::{Arch, LinkError, LinkJob, OutputKind, Platform, Severity, Version};
+
+fn fixture(name: &str) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join(name)
+}
+
+fn output_path(name: &str) -> PathBuf {
+  env::temp_dir().join(format!("mold-link-job-{}-{}", process::id(), name))
+}
+
+fn hello_world() -> LinkJob {
+  LinkJob::new(Arch::X86_64)
+    .platform(Platform::MacOS, Version::new(10, 13, 0))
+    .output_type(OutputKind::Relocatable)
+    .add_object(fixture("test.o"))
+}
+
+#[test]
+fn links_and_returns_warnings() {
+  let out = output_path("hello.o");
+  let mut job = hello_world();
+  job.options_mut().why_live.push("_main".to_string());
+  let diagnostics = job.link_to(&out).unwrap();
+
+  assert_eq!(diagnostics.len(), 1);
+  assert_eq!(diagnostics[0].severity, Severity::Warning);
+  assert!(diagnostics[0].message.contains("-why_live"));
+  let data = fs::read(&out).unwrap();
+  assert_eq!(&data[0..4], &[0xcf, 0xfa, 0xed, 0xfe]);
+  fs::remove_file(&out).unwrap();
+}
+
+#[test]
+fn reports_errors_instead_of_exiting() {
+  let job = LinkJob::new(Arch::X86_64).output_type(OutputKind::Relocatable);
+  let failure = job.link_to(output_path("empty.o")).unwrap_err();
+  match failure.error {
+    LinkError::Args(ArgsError::NoInputFiles) => (),
+    e => panic!("unexpected error: {:?}", e),
+  }
+}
+
+#[test]
+fn rejects_options_only_final_links_use() {
+  let mut job = hello_world();
+  job.options_mut().exported_symbols.push("_main".to_string());
+  let failure = job.link_to_buffer().unwrap_err();
+  match failure.error {
+    LinkError::Args(ArgsError::Unsupported(ref what)) => {
+      assert!(what.contains("-exported_symbol"))
+    }
+    e => panic!("unexpected error: {:?}", e),
+  }
+}
+
+#[test]
+fn links_in_memory() {
+  let data = fs::read(fixture("test.o")).unwrap();
+  let (output, diagnostics) = LinkJob::new(Arch::X86_64)
+    .platform(Platform::MacOS, Version::new(10, 13, 0))
+    .output_type(OutputKind::Relocatable)
+    .add_object_data("test.o", &data[..])
+    .link_to_buffer()
+    .unwrap();
+  assert!(diagnostics.is_empty());
+
+  let out = output_path("file.o");
+  hello_world().link_to(&out).unwrap();
+  assert_eq!(output, fs::read(&out).unwrap());
+  fs::remove_file(&out).unwrap();
+
+  let mut written: Vec<u8> = Vec::new();
+  hello_world().link_to_writer(&mut written).unwrap();
+  assert_eq!(output, written);
+}
+
+#[test]
+fn errors_have_codes_and_files() {
+  let missing = fixture("missing.o");
+  let failure = LinkJob::new(Arch::X86_64)
+    .output_type(OutputKind::Relocatable)
+    .add_object(&missing)
+    .link_to(output_path("missing.o"))
+    .unwrap_err();
+  let diagnostic = failure.error.diagnostic();
+  assert_eq!(diagnostic.severity, Severity::Error);
+  assert_eq!(diagnostic.code, Some("io-error"));
+  assert_eq!(diagnostic.file, Some(missing));
+  assert!(diagnostic.to_json().starts_with(
+    "{\"severity\":\"error\",\"code\":\"io-error\",\"message\":"
+  ));
+}
+
+#[test]
+fn treats_old_ios_objects_for_intel_as_simulator_builds() {
+  // test.o with LC_VERSION_MIN_IPHONEOS in place of LC_VERSION_MIN_MACOSX,
+  // as compilers wrote for the simulator before LC_BUILD_VERSION.
+  let mut data = fs::read(fixture("test.o")).unwrap();
+  let cmd = data
+    .windows(8)
+    .position(|w| w == [0x24, 0, 0, 0, 16, 0, 0, 0])
+    .unwrap();
+  data[cmd] = 0x25;
+  let link = |platform: Platform| {
+    LinkJob::new(Arch::X86_64)
+      .platform(platform, Version::new(11, 0, 0))
+      .output_type(OutputKind::Relocatable)
+      .add_object_data("sim.o", &data[..])
+      .link_to_buffer()
+  };
+
+  let (_, diagnostics) = link(Platform::IOSSimulator).unwrap();
+  assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+  let failure = link(Platform::IOS).unwrap_err();
+  assert_eq!(failure.error.diagnostic().code, Some("platform-mismatch"));
+  match failure.error {
+    LinkError::Relocatable(RelocatableError::Target(
+      TargetError::PlatformMismatch(_, Platform::IOSSimulator, Platform::IOS),
+    )) => (),
+    e => panic!("unexpected error: {:?}", e),
+  }
+}
+
+#[test]
+fn reports_where_undefined_symbols_are_referenced() {
+  let mut job = hello_world();
+  job.options_mut().undefined = Some(UndefinedTreatment::Error);
+  let failure = job.link_to_buffer().unwrap_err();
+
+  assert_eq!(failure.diagnostics.len(), 1);
+  let diagnostic = &failure.diagnostics[0];
+  assert_eq!(diagnostic.code, Some("undefined-symbol"));
+  assert_eq!(diagnostic.symbol, Some("_puts".to_string()));
+  let section = format!("{}(__TEXT,__text)", fixture("test.o").display());
+  assert_eq!(diagnostic.sections, vec![section.clone()]);
+  assert!(diagnostic
+    .message
+    .contains(&format!("referenced from _main in {}", section)));
+}
+
+#[test]
+fn suggests_what_undefined_symbols_might_be() {
+  let mut job = hello_world();
+  job.options_mut().undefined = Some(UndefinedTreatment::Warning);
+  job.options_mut().forced_undefined.push("_mian".to_string());
+  let (_, diagnostics) = job.link_to_buffer().unwrap();
+
+  let mian = diagnostics
+    .iter()
+    .find(|d| d.symbol == Some("_mian".to_string()))
+    .unwrap();
+  assert!(mian.message.ends_with("did you mean _main?"));
+}
+
+#[test]
+fn accepts_gnu_spellings() {
+  let dir = output_path("gnu");
+  fs::create_dir_all(&dir).unwrap();
+  fs::copy(fixture("test.o"), dir.join("libhello.a")).unwrap();
+  let script = dir.join("exports.map");
+  fs::write(&script, "HELLO_1.0 {\n  global: main; hello_*;\n  local: *;\n};\n")
+    .unwrap();
+  let object = fixture("test.o");
+  let args = vec![
+    "-arch".to_string(),
+    "x86_64".to_string(),
+    "-dylib".to_string(),
+    "-soname=libhello.so".to_string(),
+    "-rpath=$ORIGIN".to_string(),
+    format!("-L{}", dir.display()),
+    format!("--version-script={}", script.display()),
+    "--whole-archive".to_string(),
+    "-lhello".to_string(),
+    "--no-whole-archive".to_string(),
+    object.to_str().unwrap().to_string(),
+  ];
+  let opts = args::parse_args(args.into_iter()).unwrap();
+
+  assert_eq!(opts.install_name, Some("libhello.so".to_string()));
+  assert_eq!(opts.rpaths, vec!["$ORIGIN".to_string()]);
+  assert_eq!(opts.force_load, vec![dir.join("libhello.a")]);
+  assert_eq!(opts.exported_symbols, vec!["_main", "_hello_*"]);
+  assert!(opts.input_paths.contains(&object));
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn resolves_the_same_whatever_order_files_arrive_in() {
+  let symbol = |name: &str, kind| InputSymbol {
+    name: name.to_string(),
+    kind: kind,
+    private_extern: false,
+    value: 0,
+  };
+  let files = vec![
+    vec![
+      symbol("_inline", SymbolKind::WeakDefined),
+      symbol("_missing", SymbolKind::Undefined),
+    ],
+    vec![
+      symbol("_inline", SymbolKind::WeakDefined),
+      symbol("_gone", SymbolKind::Undefined),
+    ],
+    vec![
+      symbol("_inline", SymbolKind::Defined),
+      symbol("_main", SymbolKind::Defined),
+      symbol("_missing", SymbolKind::Undefined),
+    ],
+    vec![symbol("_inline", SymbolKind::WeakDefined)],
+  ];
+  let mut sequential = SymbolTable::new();
+  for (file, symbols) in files.iter().enumerate() {
+    sequential.add_file(file, symbols).unwrap();
+  }
+  for &nshards in [1, 3, 64].iter() {
+    // Last file first, as a slow first input would have it.
+    let table = ConcurrentSymbolTable::with_shards(nshards);
+    for (file, symbols) in files.iter().enumerate().rev() {
+      table.add_file(file, symbols);
+    }
+    let table = table.finish().unwrap();
+    let inline = table.lookup("_inline").unwrap();
+    assert_eq!((inline.file, inline.weak), (2, false));
+    assert_eq!(inline.coalesced, vec![0, 1, 3]);
+    let expected = sequential.lookup("_inline").unwrap();
+    assert_eq!(inline.coalesced, expected.coalesced);
+    assert_eq!(table.undefined(), sequential.undefined());
+    assert_eq!(table.undefined(), vec!["_missing", "_gone"]);
+    let main = table.lookup("_main").unwrap();
+    assert_eq!(main.file, sequential.lookup("_main").unwrap().file);
+  }
+
+  // Every duplicate, in order of its second definition.
+  let table = ConcurrentSymbolTable::with_shards(4);
+  let duplicated = [
+    vec![symbol("_b", SymbolKind::Defined)],
+    vec![symbol("_a", SymbolKind::Defined)],
+    vec![symbol("_a", SymbolKind::Defined)],
+    vec![
+      symbol("_b", SymbolKind::Defined),
+      symbol("_a", SymbolKind::Defined),
+    ],
+  ];
+  for (file, symbols) in duplicated.iter().enumerate().rev() {
+    table.add_file(file, symbols);
+  }
+  match table.finish() {
+    Err(ResolveError::DuplicateSymbols(duplicates)) => assert_eq!(
+      duplicates,
+      vec![("_a".to_string(), vec![1, 2, 3]), ("_b".to_string(), vec![0, 3])]
+    ),
+    other => panic!("expected duplicates, got {:?}", other),
+  }
+}
+
+#[test]
+fn parses_lto_codegen_options() {
+  let object = fixture("test.o");
+  let parse = |extra: &[&str]| {
+    let mut args: Vec<String> = extra.iter().map(|s| s.to_string()).collect();
+    args.push(object.to_str().unwrap().to_string());
+    args::parse_args(args.into_iter())
+  };
+  let opts = parse(&[
+    "--lto-O3",
+    "--lto-cpu=apple-m1",
+    "--lto-features=+crc",
+    "--thinlto-jobs=4",
+  ])
+  .unwrap();
+
+  assert_eq!(opts.lto_opt_level, 3);
+  assert_eq!(opts.lto_cpu, Some("apple-m1".to_string()));
+  assert_eq!(opts.lto_features, Some("+crc".to_string()));
+  assert!(opts.thinlto);
+  assert_eq!(opts.thinlto_jobs, 4);
+
+  let opts = parse(&["-lto_library", "/opt/llvm/lib/libLTO.dylib"]).unwrap();
+  assert_eq!(opts.lto_backend(), LtoBackend::LibLto);
+  match parse(&["--lto-O4"]) {
+    Err(ArgsError::InvalidValue(opt, val)) => {
+      assert_eq!((opt.as_str(), val.as_str()), ("--lto-O", "4"))
+    }
+    other => panic!("expected an invalid --lto-O, got {:?}", other.err()),
+  }
+}
+
+fn elf_section(name: &str, flags: u32, contents: &[u8]) -> ForeignSection {
+  ForeignSection {
+    name: name.to_string(),
+    flags: flags | SEC_HAS_CONTENTS,
+    size: contents.len() as u64,
+    alignment_power: 4,
+    contents: contents.to_vec(),
+    relocs: Vec::new(),
+  }
+}
+
+#[test]
+fn translates_elf_objects() {
+  // main() { puts("hello"); }, as gcc compiles it for x86_64 Linux:
+  // lea .LC0(%rip), %rdi; call puts@PLT.
+  let mut text = elf_section(
+    ".text",
+    SEC_ALLOC | SEC_CODE | SEC_READONLY,
+    &[0x48, 0x8d, 0x3d, 0, 0, 0, 0, 0xe8, 0, 0, 0, 0, 0xc3],
+  );
+  text.relocs = vec![
+    ForeignReloc {
+      address: 3,
+      target: RelocTarget::Section(1),
+      addend: -4,
+      kind: 2,
+    },
+    ForeignReloc {
+      address: 8,
+      target: RelocTarget::Symbol(2),
+      addend: -4,
+      kind: 4,
+    },
+  ];
+  let strings = SEC_ALLOC | SEC_READONLY | SEC_MERGE | SEC_STRINGS;
+  let object = ForeignObject {
+    sections: vec![
+      text,
+      elf_section(".rodata.str1.1", strings, b"hello\0"),
+      elf_section(".comment", 0, b"GCC\0"),
+    ],
+    symbols: vec![
+      ForeignSymbol {
+        name: ".text".to_string(),
+        flags: BSF_SECTION_SYM,
+        kind: ForeignSymbolKind::Defined(0),
+        value: 0,
+      },
+      ForeignSymbol {
+        name: "main".to_string(),
+        flags: BSF_GLOBAL,
+        kind: ForeignSymbolKind::Defined(0),
+        value: 0,
+      },
+      ForeignSymbol {
+        name: "puts".to_string(),
+        flags: 0,
+        kind: ForeignSymbolKind::Undefined,
+        value: 0,
+      },
+    ],
+  };
+  let translated = elf::translate(&object, Arch::X86_64).unwrap();
+
+  let names: Vec<(&str, &str)> = translated
+    .sections
+    .iter()
+    .map(|s| (s.segname.as_str(), s.sectname.as_str()))
+    .collect();
+  assert_eq!(names, vec![("__TEXT", "__text"), ("__TEXT", "__cstring")]);
+  assert_eq!(translated.sections[1].addr, 16);
+  let symbols: Vec<&str> =
+    translated.symbols.iter().map(|s| s.name.as_str()).collect();
+  assert_eq!(symbols, vec!["ltmp0", "ltmp1", "_main", "_puts"]);
+  assert!(translated.symbols[2].is_external());
+  assert!(translated.symbols[3].is_undefined());
+
+  let relocs = &translated.sections[0].relocs;
+  assert_eq!(relocs.len(), 2);
+  // The string, through the section's start symbol; the addend is in
+  // the instruction.
+  assert_eq!((relocs[0].kind, relocs[0].symbolnum), (X86_64_RELOC_SIGNED, 1));
+  assert_eq!((relocs[1].kind, relocs[1].symbolnum), (X86_64_RELOC_BRANCH, 3));
+  assert!(relocs.iter().all(|r| r.external && r.pcrel && r.length == 2));
+  assert_eq!(&translated.sections[0].contents[3..7], &[0, 0, 0, 0]);
+}
+
+#[test]
+fn orders_functions_by_profile() {
+  let profile = Profile::parse(
+    "# perf script | stackcollapse-perf.pl\n\
+     _main;_parse;_lex 120\n\
+     _main;_eval 45\n\
+     _main 5\n",
+  ).unwrap();
+  assert_eq!(profile.samples["_main"], 170);
+  let functions: Vec<(String, u64)> = [
+    ("_main", 100),
+    ("_cold1", 4000),
+    ("_parse", 200),
+    ("_cold2", 4000),
+    ("_lex", 50),
+    ("_eval", 300),
+  ].iter()
+    .map(|&(name, size)| (name.to_string(), size))
+    .collect();
+  let plan = profile.plan(&functions, 0x1000);
+  // _lex joins its caller _parse, which joins _main, as does _eval.
+  assert_eq!(plan.order, vec!["_main", "_parse", "_lex", "_eval"]);
+  assert_eq!((plan.pages_before, plan.pages_after), (3, 1));
+  assert!(plan.report().contains("(2 fewer page faults"));
+
+  // -order_file's entries come first.
+  let explicit = OrderFile::parse("_cold2\n_lex\n", Arch::X86_64);
+  let order = plan.order_file(Some(explicit));
+  assert_eq!(order.position("_cold2", "a.o"), Some(0));
+  assert_eq!(order.position("_lex", "a.o"), Some(1));
+  assert_eq!(order.position("_main", "a.o"), Some(2));
+  assert_eq!(order.position("_cold1", "a.o"), None);
+
+  let list = Profile::parse("!_foo\n!!1 2\n!_bar\n").unwrap();
+  assert_eq!(list.order(&functions, 0x1000), vec!["_foo", "_bar"]);
+  match Profile::parse("_a;_b 3\n_c\n") {
+    Err(ProfileError::MissingCount(2, ref line)) => assert_eq!(line, "_c"),
+    other => panic!("expected a missing count, got {:?}", other),
+  }
+
+  let opts = args::parse_args(
+    vec![
+      "--profile-order=perf.folded".to_string(),
+      fixture("test.o").to_str().unwrap().to_string(),
+    ].into_iter(),
+  ).unwrap();
+  assert_eq!(opts.profile_order, Some(PathBuf::from("perf.folded")));
+}
+
+#[test]
+fn records_what_the_link_read() {
+  let dir = output_path("dependency-info");
+  let (empty, libs) = (dir.join("empty"), dir.join("libs"));
+  fs::create_dir_all(&empty).unwrap();
+  fs::create_dir_all(&libs).unwrap();
+  // libfoo re-exports libbar, which the link has to read to find what
+  // libfoo exports.
+  let dylib = |name: &str, reexports: Vec<PathBuf>| {
+    let path = libs.join(name);
+    let mut job = hello_world().output_type(OutputKind::Dylib);
+    job.options_mut().install_name = Some(path.to_str().unwrap().to_string());
+    job.options_mut().undefined = Some(UndefinedTreatment::DynamicLookup);
+    job.options_mut().reexport_libraries = reexports;
+    job.link_to(&path).unwrap();
+    path
+  };
+  let bar = dylib("libbar.dylib", Vec::new());
+  let foo = dylib("libfoo.dylib", vec![bar.clone()]);
+  let (out, info) = (dir.join("hello"), dir.join("info.dat"));
+  let mut job = hello_world()
+    .output_type(OutputKind::Executable)
+    .add_library("foo")
+    .add_library_path(&empty)
+    .add_library_path(&libs);
+  job.options_mut().undefined = Some(UndefinedTreatment::DynamicLookup);
+  job.options_mut().dependency_info = Some(info.clone());
+  job.link_to(&out).unwrap();
+
+  // Each record is an opcode and a NUL-terminated string.
+  let data = fs::read(&info).unwrap();
+  let mut records: Vec<(u8, String)> = Vec::new();
+  let mut rest = &data[..];
+  while !rest.is_empty() {
+    let end = rest.iter().skip(1).position(|&b| b == 0).unwrap() + 1;
+    let value = String::from_utf8(rest[1..end].to_vec()).unwrap();
+    records.push((rest[0], value));
+    rest = &rest[end + 1..];
+  }
+  assert_eq!(records[0].0, 0x00);
+  assert!(records[0].1.starts_with("mold-"));
+  let path = |path: &Path| path.to_str().unwrap().to_string();
+  assert_eq!(
+    records[1..].to_vec(),
+    vec![
+      (0x10, path(&bar)),
+      (0x10, path(&foo)),
+      (0x10, path(&fixture("test.o"))),
+      // Where -lfoo was looked for first: creating either would change
+      // what gets linked.
+      (0x11, path(&empty.join("libfoo.a"))),
+      (0x11, path(&empty.join("libfoo.dylib"))),
+      (0x40, path(&out)),
+    ]
+  );
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reads_arguments_and_inputs_from_files() {
+  let dir = output_path("file-lists");
+  fs::create_dir_all(&dir).unwrap();
+  let (list, response, out) =
+    (dir.join("inputs"), dir.join("args"), dir.join("hello.o"));
+  // Relative to the directory after the comma.
+  fs::write(&list, "test.o\n\n").unwrap();
+  fs::write(
+    &response,
+    format!(
+      "-r -arch x86_64 -macosx_version_min 10.13\n-filelist '{},{}' -o \"{}\"",
+      list.display(),
+      fixture("").display(),
+      out.display()
+    ),
+  ).unwrap();
+  let opts =
+    args::parse_args(vec![format!("@{}", response.display())].into_iter())
+      .unwrap();
+  assert_eq!(opts.input_paths, vec![fixture("test.o")]);
+  assert_eq!(opts.output_path, out);
+  mold::link_job::run(&opts).unwrap();
+  let mut linked: Vec<u8> = Vec::new();
+  hello_world().link_to_writer(&mut linked).unwrap();
+  assert_eq!(fs::read(&out).unwrap(), linked);
+
+  let missing = dir.join("missing");
+  let args = vec!["-filelist".to_string(), missing.display().to_string()];
+  match args::parse_args(args.into_iter()) {
+    Err(ArgsError::FileList(ref path, _)) => assert_eq!(*path, missing),
+    other => panic!("expected a missing file list, got {:?}", other),
+  }
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn warns_when_linked_dylibs_will_not_load() {
+  let dir = output_path("rpath");
+  fs::create_dir_all(&dir).unwrap();
+  let lib = dir.join("libhello.dylib");
+  let mut job = hello_world().output_type(OutputKind::Dylib);
+  job.options_mut().install_name = Some("@rpath/libhello.dylib".to_string());
+  job.options_mut().undefined = Some(UndefinedTreatment::DynamicLookup);
+  job.link_to(&lib).unwrap();
+
+  let link = |rpaths: &[&str]| {
+    let mut job = hello_world()
+      .output_type(OutputKind::Executable)
+      .add_dylib(&lib);
+    job.options_mut().undefined = Some(UndefinedTreatment::DynamicLookup);
+    job.options_mut().rpaths = rpaths.iter().map(|s| s.to_string()).collect();
+    job.link_to(dir.join("app")).unwrap()
+  };
+  let diagnostics = link(&[]);
+  assert_eq!(diagnostics.len(), 1);
+  assert_eq!(diagnostics[0].severity, Severity::Warning);
+  assert!(diagnostics[0].message.contains("has no LC_RPATH"));
+  assert!(link(&["@executable_path"]).is_empty());
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn links_dylibs_whatever_they_are_named() {
+  let dir = output_path("framework");
+  fs::create_dir_all(&dir).unwrap();
+  let framework = dir.join("Hello");
+  let name = "/Library/Frameworks/Hello.framework/Hello";
+  let mut job = hello_world().output_type(OutputKind::Dylib);
+  job.options_mut().install_name = Some(name.to_string());
+  job.options_mut().undefined = Some(UndefinedTreatment::DynamicLookup);
+  job.link_to(&framework).unwrap();
+
+  let mut job = hello_world()
+    .output_type(OutputKind::Executable)
+    .add_dylib(&framework);
+  // It's linked against, not read as an object.
+  assert!(!job.options().input_paths.contains(&framework));
+  job.options_mut().undefined = Some(UndefinedTreatment::DynamicLookup);
+  let (output, _) = job.link_to_buffer().unwrap();
+  fs::remove_dir_all(&dir).unwrap();
+  assert!(output.windows(name.len()).any(|w| w == name.as_bytes()));
+}
+
+static C_API_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn count_errors(diagnostic: *const MolDiagnostic) {
+  if unsafe { (*diagnostic).severity } == MOL_SEVERITY_ERROR {
+    C_API_ERRORS.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+// mol_link with `args` after the program name.
+fn link_through_c_api(args: &[&str]) -> i32 {
+  let args: Vec<CString> = ["ld"]
+    .iter()
+    .chain(args.iter())
+    .map(|arg| CString::new(*arg).unwrap())
+    .collect();
+  let argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+  unsafe { mol_link(argv.len() as i32, argv.as_ptr(), Some(count_errors)) }
+}
+
+#[test]
+fn links_through_the_c_api_leaving_only_the_output() {
+  let dir = output_path("capi");
+  fs::create_dir_all(&dir).unwrap();
+  let files = || {
+    let mut names: Vec<String> = fs::read_dir(&dir)
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+      .collect();
+    names.sort();
+    names
+  };
+  let object = fixture("test.o");
+  let out = dir.join("hello.o");
+  let mut args = vec!["-arch", "x86_64", "-macosx_version_min", "10.13"];
+  args.extend(&["-r", "-o", out.to_str().unwrap()]);
+  let mut linked = args.clone();
+  linked.push(object.to_str().unwrap());
+  assert_eq!(link_through_c_api(&linked), 0);
+  assert_eq!(files(), vec!["hello.o"]);
+
+  // A failed link reports its errors to the callback, and writes nothing.
+  fs::remove_file(&out).unwrap();
+  let missing = dir.join("missing.o");
+  let mut failed = args.clone();
+  failed.push(missing.to_str().unwrap());
+  assert_eq!(link_through_c_api(&failed), 1);
+  assert!(C_API_ERRORS.load(Ordering::SeqCst) > 0);
+  assert_eq!(files(), Vec::<String>::new());
+  fs::remove_dir_all(&dir).unwrap();
+}
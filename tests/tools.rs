@@ -0,0 +1,857 @@
+// The subcommands for looking at and changing Mach-O files (`mold inspect`
+// and so on), run on the checked-in hello-world object.
+
+extern crate mold;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::Duration;
+
+use mold::args;
+use mold::cache;
+use mold::codesign::{self, ExecSegment, Signer};
+use mold::dyld_check;
+use mold::dtrace::{self, DtraceError, SiteKind};
+use mold::dylib::{self, DylibError, DylibSet, Linkage};
+use mold::edit::{self, Edit};
+use mold::image::{Image, Note, OutputSection, SectionReference};
+use mold::macho::data_in_code::{self, DataInCodeEntry, DICE_KIND_DATA};
+use mold::macho::function_starts;
+use mold::macho::header::MachHeader64;
+use mold::macho::load_command::{load_commands, LoadCommand, Section64,
+                                Segment64, LC_CODE_SIGNATURE,
+                                LC_DATA_IN_CODE, LC_ENCRYPTION_INFO_64,
+                                LC_FUNCTION_STARTS, LC_ID_DYLIB,
+                                LC_LOAD_DYLIB, LC_NOTE, LC_RPATH,
+                                LC_SEGMENT_SPLIT_INFO, LC_SUB_CLIENT,
+                                LC_SUB_FRAMEWORK};
+use mold::macho::object_file::{ObjectFile, ObjectSection};
+use mold::macho::reloc::{RelocationInfo, X86_64_RELOC_BRANCH};
+use mold::macho::split_seg::{self, Reference, DYLD_CACHE_ADJ_V2_DELTA_32,
+                             DYLD_CACHE_ADJ_V2_POINTER_64};
+use mold::macho::symtab::{Nlist, N_EXT, N_SECT};
+use mold::macho::{get_u32, Arch, Version, CPU_TYPE_X86_64,
+                  MH_APP_EXTENSION_SAFE,
+                  MH_DEAD_STRIPPABLE_DYLIB, MH_DYLIB, MH_EXECUTE,
+                  SECTION_TYPE, S_DTRACE_DOF};
+
+fn fixture(name: &str) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join(name)
+}
+
+// A dylib with an install name, a dependency and an rpath, and room for
+// its load commands to grow.
+fn dylib() -> Vec<u8> {
+  dylib_with(Vec::new())
+}
+
+fn dylib_with(extra: Vec<LoadCommand>) -> Vec<u8> {
+  dylib_named("/usr/local/lib/libfoo.dylib", 0, extra)
+}
+
+fn dylib_named(name: &str, flags: u32, extra: Vec<LoadCommand>) -> Vec<u8> {
+  let dylib = |cmd: u32, name: &str| LoadCommand::Dylib {
+    cmd: cmd,
+    name: name.to_string(),
+    timestamp: 2,
+    current_version: Version::new(1, 0, 0),
+    compatibility_version: Version::new(1, 0, 0),
+  };
+  let text = Segment64 {
+    segname: "__TEXT".to_string(),
+    vmsize: 0x1000,
+    filesize: 0x1000,
+    maxprot: 5,
+    initprot: 5,
+    sections: vec![Section64 {
+      sectname: "__text".to_string(),
+      segname: "__TEXT".to_string(),
+      addr: 0x800,
+      size: 0x10,
+      offset: 0x800,
+      ..Default::default()
+    }],
+    ..Default::default()
+  };
+  let mut commands = vec![
+    LoadCommand::Segment64(text),
+    dylib(LC_ID_DYLIB, name),
+    dylib(LC_LOAD_DYLIB, "/usr/lib/libSystem.B.dylib"),
+    LoadCommand::Rpath {
+      path: "@loader_path".to_string(),
+    },
+  ];
+  commands.extend(extra);
+  let mut load_commands: Vec<u8> = Vec::new();
+  for command in commands.iter() {
+    command.write(&mut load_commands);
+  }
+  let mut header = MachHeader64::new(Arch::X86_64, MH_DYLIB);
+  header.flags = flags;
+  header.ncmds = commands.len() as u32;
+  header.sizeofcmds = load_commands.len() as u32;
+  let mut data: Vec<u8> = Vec::new();
+  header.write(&mut data);
+  data.extend_from_slice(&load_commands);
+  data.resize(0x1000, 0);
+  data
+}
+
+// Run `mold <args>`, returning its stdout and panicking if it fails.
+fn run(args: &[&str]) -> String {
+  let output = Command::new(env!("CARGO_BIN_EXE_mold"))
+    .args(args)
+    .output()
+    .unwrap();
+  assert!(
+    output.status.success(),
+    "mold {} failed: {}",
+    args.join(" "),
+    String::from_utf8_lossy(&output.stderr)
+  );
+  String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn inspects_objects() {
+  let input = fixture("test.o");
+  let text = run(&["inspect", input.to_str().unwrap()]);
+  assert!(text.contains("filetype MH_OBJECT"));
+  assert!(text.contains("LC_VERSION_MIN_MACOSX"));
+  assert!(text.contains("__TEXT,__text"));
+  assert!(text.contains("platform macos  minos 10.13.0"));
+
+  let json = run(&["inspect", "--json", input.to_str().unwrap()]);
+  assert!(json.starts_with("{\"arch\":\"x86_64\","));
+  assert!(json.contains("\"name\":\"LC_SYMTAB\""));
+}
+
+#[test]
+fn lists_symbols() {
+  let input = fixture("test.o");
+  let input = input.to_str().unwrap();
+  assert_eq!(
+    run(&["symbols", input]),
+    "0000000000000028 s EH_frame1\n\
+     0000000000000000 T _main\n\
+     \x20                U _puts\n\
+     0000000000000017 s lC0\n"
+  );
+  assert_eq!(run(&["symbols", "-g", "-U", "-j", input]), "_main\n");
+}
+
+#[test]
+fn reports_sizes() {
+  let input = fixture("test.o");
+  let input = input.to_str().unwrap();
+  let sizes = run(&["size", input]);
+  assert!(sizes.contains("\tSection __text: 23 (addr 0x0 offset 464)\n"));
+  assert!(sizes.ends_with("\ttotal 117\ntotal 120\n"));
+
+  let out = env::temp_dir().join(format!("mold-size-{}.o", process::id()));
+  let report = run(&[
+    "-arch",
+    "x86_64",
+    "-macosx_version_min",
+    "10.13",
+    "-r",
+    "--size-report",
+    "-o",
+    out.to_str().unwrap(),
+    input,
+  ]);
+  assert!(report.contains(&format!("         117 100.00%  {}\n", input)));
+  assert!(report.contains("          80  68.38%  __TEXT,__eh_frame\n"));
+  fs::remove_file(&out).unwrap();
+}
+
+// test.o relabeled as arm64, which is enough for the fat file operations.
+fn arm64_copy(dir: &Path) -> PathBuf {
+  let mut data = fs::read(fixture("test.o")).unwrap();
+  data[4..8].copy_from_slice(&[0x0c, 0x00, 0x00, 0x01]);
+  data[8..12].copy_from_slice(&[0, 0, 0, 0]);
+  let path = dir.join("arm64.o");
+  fs::write(&path, &data).unwrap();
+  path
+}
+
+#[test]
+fn builds_and_takes_apart_fat_files() {
+  let dir = env::temp_dir().join(format!("mold-fat-{}", process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let x86_64 = fixture("test.o");
+  let arm64 = arm64_copy(&dir);
+  let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+  let universal = path("universal.o");
+  let x86_64_name = x86_64.to_str().unwrap();
+  let arm64_name = arm64.to_str().unwrap();
+  run(&["fat", "create", "-o", &universal, x86_64_name, arm64_name]);
+  assert_eq!(
+    run(&["fat", "info", &universal]),
+    format!(
+      "Architectures in the fat file: {} are: x86_64 arm64\n",
+      universal
+    )
+  );
+
+  let thin = path("thin.o");
+  run(&["fat", "thin", "arm64", "-o", &thin, &universal]);
+  assert_eq!(fs::read(&thin).unwrap(), fs::read(&arm64).unwrap());
+
+  let removed = path("removed.o");
+  run(&["fat", "remove", "arm64", "-o", &removed, &universal]);
+  assert!(run(&["fat", "info", &removed]).ends_with("are: x86_64\n"));
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn edits_install_names_and_rpaths() {
+  let path = env::temp_dir().join(format!("mold-edit-{}.dylib", process::id()));
+  fs::write(&path, dylib()).unwrap();
+  let name = path.to_str().unwrap();
+  run(&[
+    "edit",
+    "-id",
+    "@rpath/libfoo.dylib",
+    "-change",
+    "/usr/lib/libSystem.B.dylib",
+    "/usr/lib/libSystem.dylib",
+    "-rpath",
+    "@loader_path",
+    "@loader_path/../lib",
+    "-add_rpath",
+    "@executable_path/Frameworks",
+    name,
+  ]);
+
+  let text = run(&["inspect", name]);
+  assert!(text.contains(
+    "LC_ID_DYLIB @rpath/libfoo.dylib (compatibility version 1.0.0, \
+     current version 1.0.0)"
+  ));
+  assert!(text.contains("LC_LOAD_DYLIB /usr/lib/libSystem.dylib "));
+  assert!(text.contains(
+    "Run paths\n  @loader_path/../lib\n  @executable_path/Frameworks\n"
+  ));
+  assert_eq!(fs::read(&path).unwrap().len(), 0x1000);
+  fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn retargets_build_versions() {
+  let path =
+    env::temp_dir().join(format!("mold-build-version-{}.dylib", process::id()));
+  fs::write(&path, dylib()).unwrap();
+  let name = path.to_str().unwrap();
+  assert_eq!(run(&["build-version", name]), "no build version\n");
+
+  run(&[
+    "build-version",
+    "-set-build-version",
+    "ios",
+    "14.0",
+    "15.0",
+    "-tool",
+    "ld",
+    "609.8",
+    name,
+  ]);
+  assert_eq!(
+    run(&["build-version", name]),
+    "LC_BUILD_VERSION\n  platform ios  minos 14.0.0  sdk 15.0.0\n  \
+     tool ld 609.8.0\n"
+  );
+
+  // Retargeting replaces the device platform rather than adding to it.
+  run(&[
+    "build-version",
+    "-set-build-version",
+    "ios-simulator",
+    "14.0",
+    "15.0",
+    name,
+  ]);
+  let shown = run(&["build-version", name]);
+  assert!(shown.contains("platform ios-simulator  minos 14.0.0"));
+  assert!(!shown.contains("platform ios "));
+
+  run(&["build-version", "-remove-build-version", "ios-simulator", name]);
+  assert_eq!(run(&["build-version", name]), "no build version\n");
+  fs::remove_file(&path).unwrap();
+}
+
+// dylib(), ad-hoc signed.
+fn signed_dylib() -> Vec<u8> {
+  let signer = Signer {
+    identifier: "libfoo.dylib".to_string(),
+    ..Default::default()
+  };
+  let size = signer.signature_size(0x1000);
+  let mut data = dylib_with(vec![LoadCommand::LinkeditData {
+    cmd: LC_CODE_SIGNATURE,
+    dataoff: 0x1000,
+    datasize: size as u32,
+  }]);
+  data.resize(0x1000 + size as usize, 0);
+  codesign::sign(&mut data, 0x1000, &signer, ExecSegment::default());
+  data
+}
+
+#[test]
+fn verifies_code_signatures() {
+  let path =
+    env::temp_dir().join(format!("mold-codesign-{}.dylib", process::id()));
+  let name = path.to_str().unwrap();
+  let mut data = signed_dylib();
+  fs::write(&path, &data).unwrap();
+  assert!(run(&["codesign", "verify", name]).ends_with("\nvalid\n"));
+
+  // Change a byte of __text, in the first page.
+  data[0x800] ^= 0xff;
+  fs::write(&path, &data).unwrap();
+  let output = Command::new(env!("CARGO_BIN_EXE_mold"))
+    .args(&["codesign", "verify", name])
+    .output()
+    .unwrap();
+  assert!(!output.status.success());
+  let report = String::from_utf8(output.stdout).unwrap();
+  assert!(report.contains("  page 0 (offset 0x0) doesn't match its hash\n"));
+  assert!(report.ends_with("\ninvalid\n"));
+  fs::remove_file(&path).unwrap();
+}
+
+// signed_dylib(), with the signature in a __LINKEDIT segment as dyld needs.
+fn linked_dylib() -> Vec<u8> {
+  let signer = Signer {
+    identifier: "libfoo.dylib".to_string(),
+    ..Default::default()
+  };
+  let size = signer.signature_size(0x1000);
+  let linkedit = Segment64 {
+    segname: "__LINKEDIT".to_string(),
+    vmaddr: 0x1000,
+    vmsize: 0x1000,
+    fileoff: 0x1000,
+    filesize: size,
+    maxprot: 1,
+    initprot: 1,
+    ..Default::default()
+  };
+  let mut data = dylib_with(vec![
+    LoadCommand::Segment64(linkedit),
+    LoadCommand::LinkeditData {
+      cmd: LC_CODE_SIGNATURE,
+      dataoff: 0x1000,
+      datasize: size as u32,
+    },
+  ]);
+  data.resize(0x1000 + size as usize, 0);
+  codesign::sign(&mut data, 0x1000, &signer, ExecSegment::default());
+  data
+}
+
+#[test]
+fn checks_images_as_dyld_would() {
+  let path =
+    env::temp_dir().join(format!("mold-dyld-check-{}.dylib", process::id()));
+  let name = path.to_str().unwrap();
+  let mut data = linked_dylib();
+  assert_eq!(dyld_check::check(&data).unwrap(), Vec::<String>::new());
+  fs::write(&path, &data).unwrap();
+  assert_eq!(run(&["dyld-check", name]), format!("{}: ok\n", name));
+
+  assert_eq!(
+    dyld_check::check(&signed_dylib()).unwrap(),
+    vec![
+      "the code signature isn't in __LINKEDIT, as there is no __LINKEDIT"
+        .to_string(),
+    ]
+  );
+
+  // Make __LINKEDIT writable, which also changes the first page.
+  let segname = data
+    .windows(16)
+    .position(|name| name == &b"__LINKEDIT\0\0\0\0\0\0"[..])
+    .unwrap();
+  data[segname + 52] = 3;
+  fs::write(&path, &data).unwrap();
+  let output = Command::new(env!("CARGO_BIN_EXE_mold"))
+    .args(&["dyld-check", name])
+    .output()
+    .unwrap();
+  assert!(!output.status.success());
+  let report = String::from_utf8(output.stdout).unwrap();
+  assert!(report.starts_with(&format!("{}:\n", name)));
+  let beyond = "  __LINKEDIT has initprot rw- beyond its maxprot r--\n";
+  assert!(report.contains(beyond));
+  assert!(report.contains("  __LINKEDIT must be r--, not rw-\n"));
+  assert!(report.contains("  the code signature doesn't match: page 0"));
+  fs::remove_file(&path).unwrap();
+}
+
+// The data of the linkedit_data_command `cmd` in `data`.
+fn linkedit_data(data: &[u8], cmd: u32) -> &[u8] {
+  let command = load_commands(data)
+    .unwrap()
+    .into_iter()
+    .find(|command| command.cmd == cmd)
+    .unwrap();
+  let dataoff = get_u32(data, command.offset + 8, "").unwrap() as usize;
+  let datasize = get_u32(data, command.offset + 12, "").unwrap() as usize;
+  &data[dataoff..(dataoff + datasize)]
+}
+
+#[test]
+fn writes_function_starts_and_data_in_code() {
+  let data = fs::read(fixture("test.o")).unwrap();
+  let object = ObjectFile::parse(&data).unwrap();
+  assert_eq!(object.function_starts(0), vec![0]);
+  assert_eq!(object.function_starts(1), Vec::<u64>::new());
+
+  let text = &object.sections[0];
+  let mut sect =
+    OutputSection::new("__text", text.contents.clone(), text.align, text.flags);
+  sect.function_starts = object.function_starts(0);
+  let dice = DataInCodeEntry {
+    offset: 4,
+    length: 2,
+    kind: DICE_KIND_DATA,
+  };
+  sect.data_in_code = vec![dice];
+  let mut image = Image::new(Arch::X86_64, MH_DYLIB);
+  image.add_section("__TEXT", sect).unwrap();
+  let data = image.write();
+  let addr = image.segments[0].sections[0].addr;
+
+  let starts = linkedit_data(&data, LC_FUNCTION_STARTS);
+  assert_eq!(function_starts::parse(starts, 0).unwrap(), vec![addr]);
+  let entries = linkedit_data(&data, LC_DATA_IN_CODE);
+  let moved = DataInCodeEntry {
+    offset: addr as u32 + 4,
+    ..dice
+  };
+  assert_eq!(data_in_code::parse(entries).unwrap(), vec![moved]);
+  assert_eq!(dyld_check::check(&data).unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn writes_segment_split_info() {
+  let mut text = OutputSection::new("__text", vec![0x90; 16], 2, 0);
+  // A RIP-relative load of the pointer in __data.
+  text.references.push(SectionReference {
+    kind: DYLD_CACHE_ADJ_V2_DELTA_32,
+    offset: 4,
+    target: Some(("__DATA".to_string(), "__data".to_string())),
+    target_offset: 0,
+  });
+  let mut data = OutputSection::new("__data", vec![0; 16], 3, 0);
+  data.references.push(SectionReference {
+    kind: DYLD_CACHE_ADJ_V2_POINTER_64,
+    offset: 8,
+    target: Some(("__TEXT".to_string(), "__text".to_string())),
+    target_offset: 12,
+  });
+  let mut image = Image::new(Arch::X86_64, MH_DYLIB);
+  image.add_section("__TEXT", text).unwrap();
+  image.add_section("__DATA", data).unwrap();
+  image.split_seg_info = true;
+  let out = image.write();
+
+  let info = linkedit_data(&out, LC_SEGMENT_SPLIT_INFO);
+  assert_eq!(
+    split_seg::parse(info).unwrap(),
+    vec![
+      Reference {
+        from_section: 1,
+        to_section: 2,
+        to_offset: 0,
+        kind: DYLD_CACHE_ADJ_V2_DELTA_32,
+        from_offset: 4,
+      },
+      Reference {
+        from_section: 2,
+        to_section: 1,
+        to_offset: 12,
+        kind: DYLD_CACHE_ADJ_V2_POINTER_64,
+        from_offset: 8,
+      },
+    ]
+  );
+  assert_eq!(dyld_check::check(&out).unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn links_only_extension_safe_dylibs_into_extensions() {
+  let dir = env::temp_dir();
+  let id = process::id();
+  let unsafe_path = dir.join(format!("mold-unsafe-{}.dylib", id));
+  fs::write(&unsafe_path, dylib()).unwrap();
+  let mut set = DylibSet::new();
+  set.link(&unsafe_path, Linkage::Normal).unwrap();
+  match set.check_extension_safe() {
+    Err(DylibError::NotExtensionSafe(name)) => {
+      assert_eq!(name, "/usr/local/lib/libfoo.dylib")
+    }
+    other => panic!("unexpected {:?}", other),
+  }
+
+  // The same dylib, built with -application_extension.
+  let name = "/usr/local/lib/libfoo.dylib";
+  let data = dylib_named(name, MH_APP_EXTENSION_SAFE, Vec::new());
+  let safe_path = dir.join(format!("mold-safe-{}.dylib", id));
+  fs::write(&safe_path, data).unwrap();
+  let mut set = DylibSet::new();
+  set.link(&safe_path, Linkage::Normal).unwrap();
+  assert!(set.check_extension_safe().is_ok());
+  fs::remove_file(unsafe_path).unwrap();
+  fs::remove_file(safe_path).unwrap();
+}
+
+#[test]
+fn checks_umbrella_clients() {
+  let sub = |cmd: u32, name: &str| LoadCommand::SubName {
+    cmd: cmd,
+    name: name.to_string(),
+  };
+  let path = env::temp_dir().join(format!("mold-sub-{}.dylib", process::id()));
+  let data = dylib_with(vec![
+    sub(LC_SUB_FRAMEWORK, "Umbrella"),
+    sub(LC_SUB_CLIENT, "Allowed"),
+  ]);
+  fs::write(&path, data).unwrap();
+  let mut set = DylibSet::new();
+  set.link(&path, Linkage::Normal).unwrap();
+  fs::remove_file(&path).unwrap();
+  assert!(set.check_clients("Umbrella", None).is_ok());
+  assert!(set.check_clients("Other", Some("Umbrella")).is_ok());
+  assert!(set.check_clients("Allowed", None).is_ok());
+  match set.check_clients("Other", None) {
+    Err(DylibError::NotAllowedClient(name, umbrella)) => {
+      assert_eq!(name, "/usr/local/lib/libfoo.dylib");
+      assert_eq!(umbrella, "Umbrella");
+    }
+    other => panic!("unexpected {:?}", other),
+  }
+
+  let parse = |extra: &[&str]| {
+    let mut args: Vec<String> = extra.iter().map(|s| s.to_string()).collect();
+    args.push(fixture("test.o").to_str().unwrap().to_string());
+    args::parse_args(args.into_iter()).unwrap()
+  };
+  let opts = parse(&[
+    "-bundle",
+    "-o",
+    "Plugin.bundle/Contents/MacOS/Plugin",
+    "-client_name",
+    "Allowed",
+  ]);
+  assert_eq!(dylib::client_name(&opts), "Allowed");
+
+  let opts = parse(&[
+    "-dylib",
+    "-install_name",
+    "/usr/local/lib/libfoo.dylib",
+    "-umbrella",
+    "Umbrella",
+    "-allowable_client",
+    "Allowed",
+  ]);
+  assert_eq!(dylib::client_name(&opts), "libfoo");
+  let commands = dylib::load_commands(&opts, true);
+  assert_eq!(
+    commands[1..].to_vec(),
+    vec![sub(LC_SUB_FRAMEWORK, "Umbrella"), sub(LC_SUB_CLIENT, "Allowed")]
+  );
+}
+
+#[test]
+fn warns_about_dylibs_dyld_will_not_find() {
+  let dir = env::temp_dir().join(format!("mold-rpath-{}", process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let path = dir.join("libbar.dylib");
+  fs::write(&path, dylib_named("@rpath/libbar.dylib", 0, Vec::new())).unwrap();
+  let mut set = DylibSet::new();
+  set.link(&path, Linkage::Normal).unwrap();
+  let warnings = |extra: &[&str]| {
+    let output = dir.join("app");
+    let mut args = vec!["-o".to_string(), output.display().to_string()];
+    args.extend(extra.iter().map(|s| s.to_string()));
+    args.push(fixture("test.o").to_str().unwrap().to_string());
+    let opts = args::parse_args(args.into_iter()).unwrap();
+    set.runtime_path_warnings(&opts)
+  };
+
+  let missing = warnings(&[]);
+  assert_eq!(missing.len(), 1);
+  assert!(missing[0].contains("has no LC_RPATH"));
+  assert_eq!(warnings(&["-rpath", "@loader_path"]), Vec::<String>::new());
+  let elsewhere = warnings(&["-rpath", "@executable_path/../lib"]);
+  let expected = dir.join("../lib/libbar.dylib");
+  assert_eq!(elsewhere.len(), 1);
+  assert!(elsewhere[0].ends_with(&format!("'{}'", expected.display())));
+  // A dylib's loader may supply the rpaths.
+  assert_eq!(warnings(&["-dylib"]), Vec::<String>::new());
+  assert_eq!(
+    dylib::runtime_candidates(
+      "@executable_path/libbar.dylib",
+      &dir.join("libfoo.dylib"),
+      false,
+      &[],
+    ),
+    None
+  );
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keeps_parsed_dylibs_for_the_daemon() {
+  let name = format!("mold-cached-{}.dylib", process::id());
+  let path = env::temp_dir().join(name);
+  let data = dylib();
+  fs::write(&path, &data).unwrap();
+  cache::enable();
+  DylibSet::new().link(&path, Linkage::Normal).unwrap();
+  assert!(!cache::DYLIBS.is_empty());
+
+  // While the file looks the same, it isn't read again.
+  let modified = fs::metadata(&path).unwrap().modified().unwrap();
+  fs::write(&path, vec![0; data.len()]).unwrap();
+  let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+  file.set_modified(modified).unwrap();
+  DylibSet::new().link(&path, Linkage::Normal).unwrap();
+  file.set_modified(modified + Duration::from_secs(1)).unwrap();
+  let changed = DylibSet::new().link(&path, Linkage::Normal);
+  fs::remove_file(&path).unwrap();
+  assert!(changed.is_err());
+}
+
+#[test]
+fn drops_unused_dead_strippable_dylibs() {
+  let dir = env::temp_dir();
+  let id = process::id();
+  let kept = dir.join(format!("mold-kept-{}.dylib", id));
+  fs::write(&kept, dylib()).unwrap();
+  let name = "/usr/local/lib/libbar.dylib";
+  let strippable = dir.join(format!("mold-strippable-{}.dylib", id));
+  let data = dylib_named(name, MH_DEAD_STRIPPABLE_DYLIB, Vec::new());
+  fs::write(&strippable, data).unwrap();
+
+  let mut set = DylibSet::new();
+  set.link(&strippable, Linkage::Normal).unwrap();
+  set.link(&kept, Linkage::Normal).unwrap();
+  set.remove_unused(&["_missing".to_string()]);
+  let names: Vec<String> = set
+    .load_commands()
+    .into_iter()
+    .filter_map(|command| match command {
+      LoadCommand::Dylib { name, .. } => Some(name),
+      _ => None,
+    })
+    .collect();
+  assert_eq!(names, vec!["/usr/local/lib/libfoo.dylib".to_string()]);
+
+  // Unless clients see it through the output.
+  let mut set = DylibSet::new();
+  set.link(&strippable, Linkage::Reexport).unwrap();
+  set.remove_unused(&[]);
+  assert_eq!(set.direct_count(), 1);
+  fs::remove_file(kept).unwrap();
+  fs::remove_file(strippable).unwrap();
+}
+
+// An object whose _main fires probe my-probe of provider myprov, with an
+// int argument, and checks whether it's enabled.
+fn probing_object() -> ObjectFile {
+  let call = |address, symbolnum| RelocationInfo {
+    address: address,
+    symbolnum: symbolnum,
+    pcrel: true,
+    length: 2,
+    external: true,
+    kind: X86_64_RELOC_BRANCH,
+    scattered: None,
+  };
+  let text = ObjectSection {
+    segname: "__TEXT".to_string(),
+    sectname: "__text".to_string(),
+    size: 13,
+    flags: 0x8000_0400,
+    contents: vec![0x55, 0xe8, 0, 0, 0, 0, 0xe8, 0, 0, 0, 0, 0x5d, 0xc3],
+    relocs: vec![call(2, 1), call(7, 2)],
+    ..Default::default()
+  };
+  let sym = |name: &str, n_type: u8, n_sect: u8| Nlist {
+    name: name.to_string(),
+    n_type: n_type,
+    n_sect: n_sect,
+    n_desc: 0,
+    n_value: 0,
+  };
+  let stability = "___dtrace_stability$myprov$v1$1_1_0_1_1_0_1_1_0_1_1_0_1_1_0";
+  ObjectFile {
+    cputype: CPU_TYPE_X86_64,
+    cpusubtype: 3,
+    flags: 0,
+    sections: vec![text],
+    symbols: vec![
+      sym("_main", N_SECT | N_EXT, 1),
+      sym("___dtrace_probe$myprov$my__probe$v1$696e74", N_EXT, 0),
+      sym("___dtrace_isenabled$myprov$my__probe$v1", N_EXT, 0),
+      sym(stability, N_EXT, 0),
+    ],
+    platform: None,
+    loh: Vec::new(),
+    data_in_code: Vec::new(),
+  }
+}
+
+#[test]
+fn turns_dtrace_probes_into_dof() {
+  let object = probing_object();
+  let sites = dtrace::find_sites(Arch::X86_64, &object);
+  let found: Vec<(&str, &str, SiteKind, u64, &str)> = sites
+    .iter()
+    .map(|site| {
+      let symbol = &site.symbol;
+      let probe = (symbol.provider.as_str(), symbol.probe.as_str());
+      (probe.0, probe.1, symbol.kind, site.address, site.function.as_str())
+    })
+    .collect();
+  assert_eq!(
+    found,
+    vec![
+      ("myprov", "my-probe", SiteKind::Probe, 1, "_main"),
+      ("myprov", "my-probe", SiteKind::IsEnabled, 6, "_main"),
+    ]
+  );
+  assert_eq!(sites[0].symbol.arg_types, vec!["int".to_string()]);
+
+  let mut contents = object.sections[0].contents.clone();
+  for site in sites.iter() {
+    let offset = site.address as usize;
+    dtrace::patch_site(Arch::X86_64, &mut contents, offset, site.symbol.kind)
+      .unwrap();
+  }
+  assert_eq!(
+    contents,
+    vec![
+      0x55, 0x90, 0x0f, 0x1f, 0x40, 0x00, 0x33, 0xc0, 0x90, 0x90, 0x90,
+      0x5d, 0xc3,
+    ]
+  );
+  match dtrace::patch_site(Arch::X86_64, &mut contents, 0, SiteKind::Probe) {
+    Err(DtraceError::BadSite(0)) => (),
+    other => panic!("unexpected {:?}", other),
+  }
+
+  let mut image = Image::new(Arch::X86_64, MH_DYLIB);
+  let text = OutputSection::new("__text", contents, 4, 0x8000_0400);
+  image.add_section("__TEXT", text).unwrap();
+  let stabilities = dtrace::find_stabilities(&object);
+  dtrace::add_to_image(&mut image, &sites, &stabilities).unwrap();
+  let dof = &image.segments[0].sections[1];
+  assert_eq!(dof.sectname, "__dof_myprov");
+  assert_eq!(dof.flags & SECTION_TYPE, S_DTRACE_DOF);
+  let data = &dof.contents;
+  // DOF_VERSION_2, for the is-enabled site, and eight sections.
+  assert_eq!(&data[..7], &[0x7f, b'D', b'O', b'F', 2, 1, 2]);
+  assert_eq!(get_u32(data, 28, "").unwrap(), 8);
+  // The provider (section 5) is named in the string table (section 0), and
+  // has the attributes the stability reference gave it.
+  let section_offset = |index: usize| {
+    get_u32(data, 64 + index * 32 + 16, "").unwrap() as usize
+  };
+  let provider = section_offset(5);
+  let name = get_u32(data, provider + 16, "").unwrap() as usize;
+  let strtab = section_offset(0);
+  assert_eq!(&data[strtab + name..strtab + name + 7], b"myprov\0");
+  assert_eq!(get_u32(data, provider + 20, "").unwrap(), 0x0101_0000);
+}
+
+#[test]
+fn leaves_room_for_encryption_in_device_executables() {
+  let parse = |extra: &[&str]| {
+    let mut args: Vec<String> = extra.iter().map(|s| s.to_string()).collect();
+    args.push(fixture("test.o").to_str().unwrap().to_string());
+    args::parse_args(args.into_iter()).unwrap()
+  };
+  let ios = ["-arch", "arm64", "-platform_version", "ios", "14.0", "14.0"];
+  let with = |extra: &str| parse(&[&ios[..], &[extra]].concat());
+  assert!(parse(&ios).use_encryption_info());
+  assert!(!with("-no_encryption").use_encryption_info());
+  assert!(!with("-dylib").use_encryption_info());
+  assert!(!parse(&["-arch", "arm64"]).use_encryption_info());
+
+  let code = vec![0xc0, 0x03, 0x5f, 0xd6];
+  let text = OutputSection::new("__text", code, 2, 0x8000_0400);
+  let mut image = Image::new(Arch::Arm64, MH_EXECUTE);
+  image.add_section("__TEXT", text).unwrap();
+  image.encryption_info = true;
+  let out = image.write();
+  // The sections start on the page after the load commands, and everything
+  // from there to the end of __TEXT is left to be encrypted.
+  let sect = &image.segments[0].sections[0];
+  assert_eq!(sect.offset, 0x4000);
+  let command = load_commands(&out)
+    .unwrap()
+    .into_iter()
+    .find(|command| command.cmd == LC_ENCRYPTION_INFO_64)
+    .unwrap();
+  assert_eq!(command.cmdsize, 24);
+  let field = |offset| get_u32(&out, command.offset + offset, "").unwrap();
+  assert_eq!(field(8), 0x4000);
+  assert_eq!(field(12) as u64, image.segments[0].filesize - 0x4000);
+  assert_eq!(field(16), 0);
+}
+
+#[test]
+fn passes_notes_and_unknown_commands_through() {
+  let mut image = Image::new(Arch::X86_64, MH_DYLIB);
+  let text = OutputSection::new("__text", vec![0xc3], 4, 0x8000_0400);
+  image.add_section("__TEXT", text).unwrap();
+  // Room for the edit below.
+  image.headerpad = 0x100;
+  image.notes.push(Note {
+    owner: "firmware".to_string(),
+    data: b"build 42".to_vec(),
+  });
+  image.load_commands.push(LoadCommand::Raw {
+    cmd: 0x7f,
+    body: vec![1, 2, 3, 4, 5, 6, 7, 8],
+  });
+  let mut out = image.write();
+
+  let commands = load_commands(&out).unwrap();
+  let note = commands.iter().find(|c| c.cmd == LC_NOTE).unwrap();
+  assert_eq!(&out[note.offset + 8..note.offset + 16], b"firmware");
+  let offset = get_u32(&out, note.offset + 24, "").unwrap() as usize;
+  assert_eq!(get_u32(&out, note.offset + 32, "").unwrap(), 8);
+  assert_eq!(&out[offset..offset + 8], b"build 42");
+  assert!(offset as u64 >= image.linkedit_segment.fileoff);
+  let raw = commands.iter().find(|c| c.cmd == 0x7f).unwrap();
+  assert_eq!(raw.cmdsize, 16);
+  let body = &out[raw.offset + 8..raw.offset + 16];
+  assert_eq!(body, &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+  // Editing keeps both, and adds another.
+  let path = Path::new("notes.dylib");
+  let added = LoadCommand::Raw {
+    cmd: 0x7e,
+    body: Vec::new(),
+  };
+  let edits = [
+    Edit::AddRpath("@loader_path".to_string()),
+    Edit::AddCommand(added),
+  ];
+  edit::edit_image(path, &mut out, &edits).unwrap();
+  let cmds: Vec<u32> = load_commands(&out)
+    .unwrap()
+    .iter()
+    .map(|command| command.cmd)
+    .collect();
+  assert!(cmds.contains(&LC_NOTE) && cmds.contains(&0x7f));
+  assert_eq!(&cmds[cmds.len() - 2..], &[LC_RPATH, 0x7e]);
+}
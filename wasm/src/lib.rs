@@ -0,0 +1,163 @@
+// The linker's Mach-O parser compiled to WebAssembly, so a web page can
+// inspect an uploaded binary exactly as the linker would read it, using
+// the same macho-types crate. That crate depends on nothing (not even
+// libc), so it builds for wasm32 as is.
+//
+//   import init, { inspect } from "./pkg/mold_wasm.js";
+//   await init();
+//   const image = JSON.parse(inspect(new Uint8Array(buffer)));
+
+extern crate macho_types as macho;
+extern crate wasm_bindgen;
+
+use wasm_bindgen::prelude::*;
+
+use macho::fat;
+use macho::image_file::ImageFile;
+use macho::load_command::load_commands;
+use macho::{Arch, MachOError};
+
+fn json_string(s: &str) -> String {
+  let mut out = String::from("\"");
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      c if (c as u32) < 0x20 => {
+        out.push_str(&format!("\\u{:04x}", c as u32));
+      }
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+  out
+}
+
+fn arch_name(cputype: u32, cpusubtype: u32) -> String {
+  match Arch::from_cputype(cputype, cpusubtype) {
+    Some(arch) => json_string(arch.name()),
+    None => "null".to_string(),
+  }
+}
+
+// One thin file: its header, load commands, segments (with their sections)
+// and symbols.
+fn thin_json(data: &[u8]) -> Result<String, MachOError> {
+  let image = ImageFile::parse(data)?;
+  let commands: Vec<String> = load_commands(data)?
+    .iter()
+    .map(|header| {
+      format!(
+        "{{\"cmd\":{},\"cmdsize\":{},\"offset\":{}}}",
+        header.cmd, header.cmdsize, header.offset
+      )
+    })
+    .collect();
+  let segments: Vec<String> = image
+    .segments
+    .iter()
+    .map(|seg| {
+      let sections: Vec<String> = seg
+        .sections
+        .iter()
+        .map(|sect| {
+          format!(
+            "{{\"segname\":{},\"sectname\":{},\"addr\":{},\"size\":{},\
+             \"offset\":{},\"align\":{},\"flags\":{}}}",
+            json_string(&sect.segname),
+            json_string(&sect.sectname),
+            sect.addr,
+            sect.size,
+            sect.offset,
+            sect.align,
+            sect.flags
+          )
+        })
+        .collect();
+      format!(
+        "{{\"segname\":{},\"vmaddr\":{},\"vmsize\":{},\"fileoff\":{},\
+         \"filesize\":{},\"maxprot\":{},\"initprot\":{},\"flags\":{},\
+         \"sections\":[{}]}}",
+        json_string(&seg.segname),
+        seg.vmaddr,
+        seg.vmsize,
+        seg.fileoff,
+        seg.filesize,
+        seg.maxprot,
+        seg.initprot,
+        seg.flags,
+        sections.join(",")
+      )
+    })
+    .collect();
+  let symbols: Vec<String> = image
+    .symbols
+    .iter()
+    .map(|sym| {
+      format!(
+        "{{\"name\":{},\"n_type\":{},\"n_sect\":{},\"n_desc\":{},\
+         \"n_value\":{}}}",
+        json_string(&sym.name),
+        sym.n_type,
+        sym.n_sect,
+        sym.n_desc,
+        sym.n_value
+      )
+    })
+    .collect();
+  let uuid = match image.uuid {
+    Some(uuid) => {
+      let hex: Vec<String> =
+        uuid.iter().map(|b| format!("{:02x}", b)).collect();
+      json_string(&hex.concat())
+    }
+    None => "null".to_string(),
+  };
+  let (platform, min_version) = match image.platform {
+    Some((platform, version)) => (
+      json_string(platform.name()),
+      json_string(&version.to_string()),
+    ),
+    None => ("null".to_string(), "null".to_string()),
+  };
+  Ok(format!(
+    "{{\"arch\":{},\"cputype\":{},\"cpusubtype\":{},\"filetype\":{},\
+     \"uuid\":{},\"platform\":{},\"min_version\":{},\"load_commands\":[{}],\
+     \"segments\":[{}],\"symbols\":[{}]}}",
+    arch_name(image.cputype, image.cpusubtype),
+    image.cputype,
+    image.cpusubtype,
+    image.filetype,
+    uuid,
+    platform,
+    min_version,
+    commands.join(","),
+    segments.join(","),
+    symbols.join(",")
+  ))
+}
+
+fn inspect_json(data: &[u8]) -> Result<String, MachOError> {
+  if !fat::is_fat(data) {
+    return thin_json(data);
+  }
+  let mut slices: Vec<String> = Vec::new();
+  for slice in fat::parse(data)?.iter() {
+    let start = slice.offset as usize;
+    let thin = thin_json(&data[start..(start + slice.size as usize)])?;
+    slices.push(format!(
+      "{{\"offset\":{},\"size\":{},\"align\":{},\"image\":{}}}",
+      slice.offset, slice.size, slice.align, thin
+    ));
+  }
+  Ok(format!("{{\"fat\":[{}]}}", slices.join(",")))
+}
+
+// `data` (a thin or fat Mach-O file) as JSON. A thin file is an object with
+// its header fields and "load_commands", "segments" and "symbols"; a fat
+// file is {"fat": [...]}, with each slice's "image" in that form. Throws a
+// string describing the problem if the file is malformed.
+#[wasm_bindgen]
+pub fn inspect(data: &[u8]) -> Result<String, JsValue> {
+  inspect_json(data).map_err(|e| JsValue::from_str(&format!("{:?}", e)))
+}